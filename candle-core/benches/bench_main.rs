@@ -4,6 +4,7 @@ use criterion::criterion_main;
 criterion_main!(
     benchmarks::affine::benches,
     benchmarks::matmul::benches,
+    benchmarks::qmatmul::benches,
     benchmarks::random::benches,
     benchmarks::where_cond::benches
 );
@@ -1,5 +1,6 @@
 pub(crate) mod affine;
 pub(crate) mod matmul;
+pub(crate) mod qmatmul;
 pub(crate) mod random;
 pub(crate) mod where_cond;
 
@@ -14,7 +15,7 @@ pub(crate) trait BenchDevice {
 impl BenchDevice for Device {
     fn sync(&self) -> Result<()> {
         match self {
-            Device::Cpu => Ok(()),
+            Device::Cpu | Device::CpuPool(_) => Ok(()),
             Device::Cuda(device) => {
                 #[cfg(feature = "cuda")]
                 return Ok(device.synchronize()?);
@@ -32,7 +33,7 @@ impl BenchDevice for Device {
 
     fn bench_name<S: Into<String>>(&self, name: S) -> String {
         match self {
-            Device::Cpu => {
+            Device::Cpu | Device::CpuPool(_) => {
                 let cpu_type = if cfg!(feature = "accelerate") {
                     "accelerate"
                 } else if cfg!(feature = "mkl") {
@@ -1,4 +1,4 @@
-use candle_core::quantized::{gguf_file, GgmlDType, QTensor};
+use candle_core::quantized::{ggml_file, gguf_file, GgmlDType, QTensor};
 use candle_core::{Device, Result};
 use clap::{Parser, Subcommand, ValueEnum};
 use rayon::prelude::*;
@@ -51,6 +51,15 @@ enum Quantization {
     Q5k,
     Q6k,
     Q8k,
+    Iq1s,
+    Iq1m,
+    Iq2xxs,
+    Iq2xs,
+    Iq2s,
+    Iq3xxs,
+    Iq3s,
+    Iq4nl,
+    Iq4xs,
     F16,
     F32,
 }
@@ -70,6 +79,15 @@ impl Quantization {
             Quantization::Q5k => GgmlDType::Q5K,
             Quantization::Q6k => GgmlDType::Q6K,
             Quantization::Q8k => GgmlDType::Q8K,
+            Quantization::Iq1s => GgmlDType::IQ1S,
+            Quantization::Iq1m => GgmlDType::IQ1M,
+            Quantization::Iq2xxs => GgmlDType::IQ2XXS,
+            Quantization::Iq2xs => GgmlDType::IQ2XS,
+            Quantization::Iq2s => GgmlDType::IQ2S,
+            Quantization::Iq3xxs => GgmlDType::IQ3XXS,
+            Quantization::Iq3s => GgmlDType::IQ3S,
+            Quantization::Iq4nl => GgmlDType::IQ4NL,
+            Quantization::Iq4xs => GgmlDType::IQ4XS,
             Quantization::F16 => GgmlDType::F16,
             Quantization::F32 => GgmlDType::F32,
         }
@@ -142,6 +160,20 @@ enum Command {
         #[arg(long)]
         out_file: std::path::PathBuf,
     },
+
+    /// Upgrades a legacy ggml/ggmf/ggjt llama file to gguf, the tensors are not requantized.
+    GgmlToGguf {
+        /// The input file, in one of the legacy ggml formats.
+        in_file: std::path::PathBuf,
+
+        /// The output file, in gguf format.
+        #[arg(long)]
+        out_file: std::path::PathBuf,
+
+        /// The context length to store in the metadata, it is not part of the ggml files.
+        #[arg(long)]
+        context_length: Option<u32>,
+    },
 }
 
 #[derive(Parser, Debug, Clone)]
@@ -183,7 +215,7 @@ fn run_ls(
         }
         Format::Safetensors => {
             let tensors = unsafe { candle_core::safetensors::MmapedSafetensors::new(file)? };
-            let mut tensors = tensors.tensors();
+            let mut tensors = tensors.tensors()?;
             tensors.sort_by(|a, b| a.0.cmp(&b.0));
             for (name, view) in tensors.iter() {
                 let dtype = view.dtype();
@@ -255,11 +287,7 @@ fn run_quantize_safetensors(
     q: Quantization,
 ) -> Result<()> {
     let mut out_file = std::fs::File::create(out_file)?;
-    let mut tensors = std::collections::HashMap::new();
-    for in_file in in_files.iter() {
-        let in_tensors = candle_core::safetensors::load(in_file, &Device::Cpu)?;
-        tensors.extend(in_tensors)
-    }
+    let tensors = candle_core::quantized::convert::load(in_files)?;
     println!("tensors: {}", tensors.len());
 
     let dtype = q.dtype();
@@ -319,7 +347,10 @@ fn run_quantize(
         }
     }
     if let Some(extension) = in_files[0].extension() {
-        if extension == "safetensors" {
+        if matches!(
+            extension.to_str(),
+            Some("safetensors" | "npz" | "pth" | "pt")
+        ) {
             return run_quantize_safetensors(in_files, out_file, q);
         }
     }
@@ -360,6 +391,26 @@ fn run_quantize(
     Ok(())
 }
 
+fn run_ggml_to_gguf(
+    in_file: std::path::PathBuf,
+    out_file: std::path::PathBuf,
+    context_length: Option<u32>,
+    device: &Device,
+) -> Result<()> {
+    let mut in_file = std::fs::File::open(&in_file)?;
+    let content = ggml_file::Content::read(&mut in_file, device)?;
+    println!("params: {:?}", content.hparams);
+    println!("tensors: {}", content.tensors.len());
+    let context_length = context_length.map(gguf_file::Value::U32);
+    let metadata = match context_length.as_ref() {
+        None => vec![],
+        Some(v) => vec![("llama.context_length", v)],
+    };
+    let mut out_file = std::fs::File::create(out_file)?;
+    content.write_gguf(&mut out_file, &metadata)?;
+    Ok(())
+}
+
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
     let device = Device::Cpu;
@@ -384,6 +435,11 @@ fn main() -> anyhow::Result<()> {
             mode,
         } => run_quantize(&in_file, out_file, quantization, mode, &device)?,
         Command::Dequantize { in_file, out_file } => run_dequantize(in_file, out_file, &device)?,
+        Command::GgmlToGguf {
+            in_file,
+            out_file,
+            context_length,
+        } => run_ggml_to_gguf(in_file, out_file, context_length, &device)?,
     }
     Ok(())
 }
@@ -124,6 +124,7 @@ impl Tensor {
                     | Op::ToDevice(node)
                     | Op::Transpose(node, _, _)
                     | Op::Permute(node, _)
+                    | Op::AsStrided(node, _)
                     | Op::Narrow(node, _, _, _)
                     | Op::Unary(node, _)
                     | Op::Elu(node, _)
@@ -666,6 +667,19 @@ impl Tensor {
                         let sum_grad = grads.or_insert(arg)?;
                         *sum_grad = sum_grad.add(&arg_grad)?
                     }
+                    Op::AsStrided(arg, layout) => {
+                        // The elements of arg can appear multiple times in the view, e.g. with
+                        // overlapping windows, so their gradients are accumulated with index_add.
+                        let ids: Vec<u32> = layout.strided_index().map(|i| i as u32).collect();
+                        let ids = Tensor::from_vec(ids, layout.shape().elem_count(), arg.device())?;
+                        let arg_grad = arg.zeros_like()?.flatten_all()?.index_add(
+                            &ids,
+                            &grad.flatten_all()?,
+                            0,
+                        )?;
+                        let sum_grad = grads.or_insert(arg)?;
+                        *sum_grad = sum_grad.add(&arg_grad.reshape(arg.dims())?)?
+                    }
                 };
             }
         }
@@ -673,7 +687,7 @@ impl Tensor {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct GradStore(HashMap<TensorId, Tensor>);
 
 impl GradStore {
@@ -127,9 +127,10 @@ impl ParamsConvTranspose2D {
 
 impl Tensor {
     fn conv1d_single_group(&self, kernel: &Self, params: &ParamsConv1D) -> Result<Self> {
-        let storage =
+        let storage = self.device().enter(|| {
             self.storage()
-                .conv1d(self.layout(), &kernel.storage(), kernel.layout(), params)?;
+                .conv1d(self.layout(), &kernel.storage(), kernel.layout(), params)
+        })?;
         let op = BackpropOp::new2(self, kernel, |arg, kernel| Op::Conv1D {
             arg,
             kernel,
@@ -138,7 +139,13 @@ impl Tensor {
             dilation: params.dilation,
         });
         let out_dims = params.out_dims();
-        Ok(crate::tensor::from_storage(storage, out_dims, op, false))
+        Ok(crate::tensor::from_storage(
+            storage,
+            self.device(),
+            out_dims,
+            op,
+            false,
+        ))
     }
 
     /// Applies a 1D convolution over the input tensor.
@@ -212,12 +219,14 @@ impl Tensor {
             stride,
             dilation,
         };
-        let storage = self.storage().conv_transpose1d(
-            self.layout(),
-            &kernel.storage(),
-            kernel.layout(),
-            &params,
-        )?;
+        let storage = self.device().enter(|| {
+            self.storage().conv_transpose1d(
+                self.layout(),
+                &kernel.storage(),
+                kernel.layout(),
+                &params,
+            )
+        })?;
         let op = BackpropOp::new2(self, kernel, |arg, kernel| Op::ConvTranspose1D {
             arg,
             kernel,
@@ -227,13 +236,20 @@ impl Tensor {
             dilation: params.dilation,
         });
         let out_dims = params.out_dims();
-        Ok(crate::tensor::from_storage(storage, out_dims, op, false))
+        Ok(crate::tensor::from_storage(
+            storage,
+            self.device(),
+            out_dims,
+            op,
+            false,
+        ))
     }
 
     fn conv2d_single_group(&self, kernel: &Self, params: &ParamsConv2D) -> Result<Self> {
-        let storage =
+        let storage = self.device().enter(|| {
             self.storage()
-                .conv2d(self.layout(), &kernel.storage(), kernel.layout(), params)?;
+                .conv2d(self.layout(), &kernel.storage(), kernel.layout(), params)
+        })?;
         let op = BackpropOp::new2(self, kernel, |arg, kernel| Op::Conv2D {
             arg,
             kernel,
@@ -242,7 +258,13 @@ impl Tensor {
             dilation: params.dilation,
         });
         let out_dims = params.out_dims();
-        Ok(crate::tensor::from_storage(storage, out_dims, op, false))
+        Ok(crate::tensor::from_storage(
+            storage,
+            self.device(),
+            out_dims,
+            op,
+            false,
+        ))
     }
 
     /// Applies a 2D convolution over the input tensor.
@@ -315,12 +337,14 @@ impl Tensor {
             stride,
             dilation,
         };
-        let storage = self.storage().conv_transpose2d(
-            self.layout(),
-            &kernel.storage(),
-            kernel.layout(),
-            &params,
-        )?;
+        let storage = self.device().enter(|| {
+            self.storage().conv_transpose2d(
+                self.layout(),
+                &kernel.storage(),
+                kernel.layout(),
+                &params,
+            )
+        })?;
         let op = BackpropOp::new2(self, kernel, |arg, kernel| Op::ConvTranspose2D {
             arg,
             kernel,
@@ -330,6 +354,12 @@ impl Tensor {
             dilation: params.dilation,
         });
         let out_dims = params.out_dims();
-        Ok(crate::tensor::from_storage(storage, out_dims, op, false))
+        Ok(crate::tensor::from_storage(
+            storage,
+            self.device(),
+            out_dims,
+            op,
+            false,
+        ))
     }
 }
@@ -1,5 +1,8 @@
 pub mod erf;
 pub mod kernels;
+pub mod thread_pool;
+
+pub use thread_pool::ThreadPool;
 
 trait Cpu<const ARR: usize> {
     type Unit;
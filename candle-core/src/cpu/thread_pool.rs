@@ -0,0 +1,135 @@
+//! Thread pools for the cpu devices.
+//!
+//! The cpu kernels use the global rayon thread pool by default, its size being set with the
+//! `RAYON_NUM_THREADS` environment variable. A [`crate::Device::CpuPool`] device carries its own
+//! thread pool instead so that multiple models can run side by side in the same process without
+//! starving each other. The matmul, convolution and custom ops on tensors from such a device make
+//! the pool the current one for the calling thread, and the parallel kernels run through
+//! [`install`]. The other operations run on the calling thread as with [`crate::Device::Cpu`].
+use crate::Result;
+use std::cell::RefCell;
+use std::sync::Arc;
+
+thread_local! {
+    static CURRENT_POOL: RefCell<Option<Arc<rayon::ThreadPool>>> = const { RefCell::new(None) };
+}
+
+#[derive(Clone)]
+pub struct ThreadPool {
+    pool: Arc<rayon::ThreadPool>,
+    core_ids: Option<Arc<[usize]>>,
+}
+
+impl std::fmt::Debug for ThreadPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ThreadPool")
+            .field("num_threads", &self.num_threads())
+            .field("core_ids", &self.core_ids())
+            .finish()
+    }
+}
+
+impl ThreadPool {
+    /// Creates a pool with `num_threads` threads, using 0 selects the number of cpus.
+    pub fn new(num_threads: usize) -> Result<Self> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .thread_name(|i| format!("candle-cpu-{i}"))
+            .build()
+            .map_err(crate::Error::wrap)?;
+        Ok(Self {
+            pool: Arc::new(pool),
+            core_ids: None,
+        })
+    }
+
+    /// Creates a pool with one thread per element of `core_ids`, the i-th thread being pinned to
+    /// the cpu core `core_ids[i]`. Pinning threads is only supported on linux.
+    pub fn with_affinity(core_ids: &[usize]) -> Result<Self> {
+        if core_ids.is_empty() {
+            crate::bail!("no core ids provided for the thread pool")
+        }
+        let pool = Self::new(core_ids.len())?;
+        let pinned = pool
+            .pool
+            .broadcast(|ctx| set_affinity(core_ids[ctx.index()]));
+        pinned.into_iter().collect::<Result<Vec<()>>>()?;
+        Ok(Self {
+            core_ids: Some(core_ids.into()),
+            ..pool
+        })
+    }
+
+    pub fn num_threads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    /// The cpu cores the threads are pinned to, if any.
+    pub fn core_ids(&self) -> Option<&[usize]> {
+        self.core_ids.as_deref()
+    }
+
+    /// Returns true if both values refer to the same underlying pool.
+    pub fn same_pool(&self, rhs: &Self) -> bool {
+        Arc::ptr_eq(&self.pool, &rhs.pool)
+    }
+
+    /// Runs `f` on the current thread with this pool as the current pool, the parallel cpu
+    /// kernels called from `f` run on the threads of this pool.
+    pub fn enter<R>(&self, f: impl FnOnce() -> R) -> R {
+        struct Guard(Option<Arc<rayon::ThreadPool>>);
+        impl Drop for Guard {
+            fn drop(&mut self) {
+                let prev = self.0.take();
+                CURRENT_POOL.with(|p| *p.borrow_mut() = prev)
+            }
+        }
+        let prev = CURRENT_POOL.with(|p| p.borrow_mut().replace(self.pool.clone()));
+        let _guard = Guard(prev);
+        f()
+    }
+
+    /// Runs `f` on the threads of this pool.
+    pub fn install<R: Send>(&self, f: impl FnOnce() -> R + Send) -> R {
+        self.pool.install(f)
+    }
+}
+
+/// Runs `f` in the current pool as set by [`ThreadPool::enter`], or on the current thread if
+/// there is no such pool in which case the global rayon pool is used by the parallel iterators.
+pub fn install<R: Send>(f: impl FnOnce() -> R + Send) -> R {
+    match CURRENT_POOL.with(|p| p.borrow().clone()) {
+        Some(pool) => pool.install(f),
+        None => f(),
+    }
+}
+
+/// The number of threads of the current pool, if a pool was set with [`ThreadPool::enter`] or if
+/// the current thread belongs to a rayon pool.
+pub fn current_num_threads() -> Option<usize> {
+    match CURRENT_POOL.with(|p| p.borrow().as_ref().map(|p| p.current_num_threads())) {
+        Some(num_threads) => Some(num_threads),
+        None => rayon::current_thread_index().map(|_| rayon::current_num_threads()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_affinity(core_id: usize) -> Result<()> {
+    let num_cpus = 8 * std::mem::size_of::<libc::cpu_set_t>();
+    if core_id >= num_cpus {
+        crate::bail!("core id {core_id} is out of range")
+    }
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(core_id, &mut set);
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_affinity(_core_id: usize) -> Result<()> {
+    crate::bail!("setting the thread affinity is only supported on linux")
+}
@@ -3,6 +3,7 @@ use crate::op::{BinaryOpT, CmpOp, ReduceOp, UnaryOpT};
 use crate::{DType, Error, IntDType, Layout, Result, Shape, WithDType};
 use half::{bf16, f16};
 use rayon::prelude::*;
+use std::sync::Arc;
 
 const USE_IM2COL_CONV1D: bool = true;
 const USE_IM2COL_CONV2D: bool = true;
@@ -20,21 +21,131 @@ pub enum CpuStorage {
     F64(Vec<f64>),
 }
 
+/// A borrowed view on the data of a cpu storage, this is what the cpu kernels operate on so that
+/// they can be used both on owned storages and on memory mapped ones.
+#[derive(Debug, Clone, Copy)]
+pub enum CpuStorageRef<'a> {
+    U8(&'a [u8]),
+    U32(&'a [u32]),
+    I64(&'a [i64]),
+    BF16(&'a [bf16]),
+    F16(&'a [f16]),
+    F32(&'a [f32]),
+    F64(&'a [f64]),
+}
+
+impl<'a> CpuStorageRef<'a> {
+    pub fn dtype(&self) -> DType {
+        match self {
+            Self::U8(_) => DType::U8,
+            Self::U32(_) => DType::U32,
+            Self::I64(_) => DType::I64,
+            Self::BF16(_) => DType::BF16,
+            Self::F16(_) => DType::F16,
+            Self::F32(_) => DType::F32,
+            Self::F64(_) => DType::F64,
+        }
+    }
+
+    pub fn as_slice<D: WithDType>(&self) -> Result<&'a [D]> {
+        D::cpu_storage_ref_as_slice(*self)
+    }
+
+    /// Copies the data to a new owned storage.
+    pub fn to_cpu_storage(&self) -> CpuStorage {
+        match self {
+            Self::U8(vs) => CpuStorage::U8(vs.to_vec()),
+            Self::U32(vs) => CpuStorage::U32(vs.to_vec()),
+            Self::I64(vs) => CpuStorage::I64(vs.to_vec()),
+            Self::BF16(vs) => CpuStorage::BF16(vs.to_vec()),
+            Self::F16(vs) => CpuStorage::F16(vs.to_vec()),
+            Self::F32(vs) => CpuStorage::F32(vs.to_vec()),
+            Self::F64(vs) => CpuStorage::F64(vs.to_vec()),
+        }
+    }
+}
+
+/// A read-only cpu storage whose data is not copied but borrowed from a memory mapped file, e.g.
+/// the tensors of a safetensors file. The pages are loaded lazily by the OS and are shared with
+/// the other processes mapping the same file.
+///
+/// Operations read the data in place and produce owned storages. Mutating a tensor using such a
+/// storage, e.g. via [`crate::Var::set`] or [`crate::Tensor::slice_set`], first replaces it with
+/// an owned copy.
+#[derive(Debug, Clone)]
+pub struct CpuMmapStorage {
+    mmap: Arc<memmap2::Mmap>,
+    offset: usize,
+    len: usize,
+    dtype: DType,
+}
+
+impl CpuMmapStorage {
+    /// Creates a storage for the `len` elements of type `dtype` located at `offset` bytes in
+    /// `mmap`. This returns an error if the data does not fit in the mapping or is not aligned
+    /// for `dtype`.
+    pub fn new(mmap: Arc<memmap2::Mmap>, offset: usize, len: usize, dtype: DType) -> Result<Self> {
+        let size_in_bytes = len * dtype.size_in_bytes();
+        if offset + size_in_bytes > mmap.len() {
+            crate::bail!(
+                "mmap storage out of bounds: {offset} + {size_in_bytes} > {}",
+                mmap.len()
+            )
+        }
+        if mmap[offset..].as_ptr().align_offset(dtype.size_in_bytes()) != 0 {
+            crate::bail!("mmap storage at offset {offset} is not aligned for {dtype:?}")
+        }
+        Ok(Self {
+            mmap,
+            offset,
+            len,
+            dtype,
+        })
+    }
+
+    pub fn dtype(&self) -> DType {
+        self.dtype
+    }
+
+    pub fn storage_ref(&self) -> CpuStorageRef<'_> {
+        fn cast<T>(data: &[u8], len: usize) -> &[T] {
+            // Safety: the bounds and the alignment have been checked on creation, and all the bit
+            // patterns are valid for the supported dtypes.
+            unsafe { std::slice::from_raw_parts(data.as_ptr() as *const T, len) }
+        }
+        let data = &self.mmap[self.offset..];
+        match self.dtype {
+            DType::U8 => CpuStorageRef::U8(cast(data, self.len)),
+            DType::U32 => CpuStorageRef::U32(cast(data, self.len)),
+            DType::I64 => CpuStorageRef::I64(cast(data, self.len)),
+            DType::BF16 => CpuStorageRef::BF16(cast(data, self.len)),
+            DType::F16 => CpuStorageRef::F16(cast(data, self.len)),
+            DType::F32 => CpuStorageRef::F32(cast(data, self.len)),
+            DType::F64 => CpuStorageRef::F64(cast(data, self.len)),
+        }
+    }
+
+    /// Copies the data to a new owned storage.
+    pub fn to_cpu_storage(&self) -> CpuStorage {
+        self.storage_ref().to_cpu_storage()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CpuDevice;
 
 pub trait Map1 {
     fn f<T: WithDType>(&self, vs: &[T], layout: &Layout) -> Result<Vec<T>>;
 
-    fn map(&self, vs: &CpuStorage, layout: &Layout) -> Result<CpuStorage> {
+    fn map(&self, vs: CpuStorageRef<'_>, layout: &Layout) -> Result<CpuStorage> {
         match vs {
-            CpuStorage::U8(vs) => Ok(CpuStorage::U8(self.f(vs, layout)?)),
-            CpuStorage::U32(vs) => Ok(CpuStorage::U32(self.f(vs, layout)?)),
-            CpuStorage::I64(vs) => Ok(CpuStorage::I64(self.f(vs, layout)?)),
-            CpuStorage::BF16(vs) => Ok(CpuStorage::BF16(self.f(vs, layout)?)),
-            CpuStorage::F16(vs) => Ok(CpuStorage::F16(self.f(vs, layout)?)),
-            CpuStorage::F32(vs) => Ok(CpuStorage::F32(self.f(vs, layout)?)),
-            CpuStorage::F64(vs) => Ok(CpuStorage::F64(self.f(vs, layout)?)),
+            CpuStorageRef::U8(vs) => Ok(CpuStorage::U8(self.f(vs, layout)?)),
+            CpuStorageRef::U32(vs) => Ok(CpuStorage::U32(self.f(vs, layout)?)),
+            CpuStorageRef::I64(vs) => Ok(CpuStorage::I64(self.f(vs, layout)?)),
+            CpuStorageRef::BF16(vs) => Ok(CpuStorage::BF16(self.f(vs, layout)?)),
+            CpuStorageRef::F16(vs) => Ok(CpuStorage::F16(self.f(vs, layout)?)),
+            CpuStorageRef::F32(vs) => Ok(CpuStorage::F32(self.f(vs, layout)?)),
+            CpuStorageRef::F64(vs) => Ok(CpuStorage::F64(self.f(vs, layout)?)),
         }
     }
 }
@@ -47,39 +158,40 @@ pub trait Map1Any {
         wrap: W,
     ) -> Result<CpuStorage>;
 
-    fn map(&self, vs: &CpuStorage, layout: &Layout) -> Result<CpuStorage> {
+    fn map(&self, vs: CpuStorageRef<'_>, layout: &Layout) -> Result<CpuStorage> {
         match vs {
-            CpuStorage::U8(vs) => Ok(self.f(vs, layout, CpuStorage::U8)?),
-            CpuStorage::U32(vs) => Ok(self.f(vs, layout, CpuStorage::U32)?),
-            CpuStorage::I64(vs) => Ok(self.f(vs, layout, CpuStorage::I64)?),
-            CpuStorage::BF16(vs) => Ok(self.f(vs, layout, CpuStorage::BF16)?),
-            CpuStorage::F16(vs) => Ok(self.f(vs, layout, CpuStorage::F16)?),
-            CpuStorage::F32(vs) => Ok(self.f(vs, layout, CpuStorage::F32)?),
-            CpuStorage::F64(vs) => Ok(self.f(vs, layout, CpuStorage::F64)?),
+            CpuStorageRef::U8(vs) => Ok(self.f(vs, layout, CpuStorage::U8)?),
+            CpuStorageRef::U32(vs) => Ok(self.f(vs, layout, CpuStorage::U32)?),
+            CpuStorageRef::I64(vs) => Ok(self.f(vs, layout, CpuStorage::I64)?),
+            CpuStorageRef::BF16(vs) => Ok(self.f(vs, layout, CpuStorage::BF16)?),
+            CpuStorageRef::F16(vs) => Ok(self.f(vs, layout, CpuStorage::F16)?),
+            CpuStorageRef::F32(vs) => Ok(self.f(vs, layout, CpuStorage::F32)?),
+            CpuStorageRef::F64(vs) => Ok(self.f(vs, layout, CpuStorage::F64)?),
         }
     }
 }
 
 type C = CpuStorage;
+type R<'a> = CpuStorageRef<'a>;
 pub trait Map2 {
     const OP: &'static str;
     fn f<T: WithDType>(&self, v1: &[T], l1: &Layout, v2: &[T], l2: &Layout) -> Result<Vec<T>>;
 
     fn map(
         &self,
-        v1: &CpuStorage,
+        v1: CpuStorageRef<'_>,
         l1: &Layout,
-        v2: &CpuStorage,
+        v2: CpuStorageRef<'_>,
         l2: &Layout,
     ) -> Result<CpuStorage> {
         match (v1, v2) {
-            (C::U8(v1), C::U8(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?)),
-            (C::U32(v1), C::U32(v2)) => Ok(C::U32(self.f(v1, l1, v2, l2)?)),
-            (C::I64(v1), C::I64(v2)) => Ok(C::I64(self.f(v1, l1, v2, l2)?)),
-            (C::BF16(v1), C::BF16(v2)) => Ok(C::BF16(self.f(v1, l1, v2, l2)?)),
-            (C::F16(v1), C::F16(v2)) => Ok(C::F16(self.f(v1, l1, v2, l2)?)),
-            (C::F32(v1), C::F32(v2)) => Ok(C::F32(self.f(v1, l1, v2, l2)?)),
-            (C::F64(v1), C::F64(v2)) => Ok(C::F64(self.f(v1, l1, v2, l2)?)),
+            (R::U8(v1), R::U8(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?)),
+            (R::U32(v1), R::U32(v2)) => Ok(C::U32(self.f(v1, l1, v2, l2)?)),
+            (R::I64(v1), R::I64(v2)) => Ok(C::I64(self.f(v1, l1, v2, l2)?)),
+            (R::BF16(v1), R::BF16(v2)) => Ok(C::BF16(self.f(v1, l1, v2, l2)?)),
+            (R::F16(v1), R::F16(v2)) => Ok(C::F16(self.f(v1, l1, v2, l2)?)),
+            (R::F32(v1), R::F32(v2)) => Ok(C::F32(self.f(v1, l1, v2, l2)?)),
+            (R::F64(v1), R::F64(v2)) => Ok(C::F64(self.f(v1, l1, v2, l2)?)),
             _ => Err(Error::DTypeMismatchBinaryOp {
                 lhs: v1.dtype(),
                 rhs: v2.dtype(),
@@ -96,19 +208,19 @@ pub trait Map2U8 {
 
     fn map(
         &self,
-        v1: &CpuStorage,
+        v1: CpuStorageRef<'_>,
         l1: &Layout,
-        v2: &CpuStorage,
+        v2: CpuStorageRef<'_>,
         l2: &Layout,
     ) -> Result<CpuStorage> {
         match (v1, v2) {
-            (C::U8(v1), C::U8(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?)),
-            (C::U32(v1), C::U32(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?)),
-            (C::I64(v1), C::I64(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?)),
-            (C::BF16(v1), C::BF16(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?)),
-            (C::F16(v1), C::F16(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?)),
-            (C::F32(v1), C::F32(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?)),
-            (C::F64(v1), C::F64(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?)),
+            (R::U8(v1), R::U8(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?)),
+            (R::U32(v1), R::U32(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?)),
+            (R::I64(v1), R::I64(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?)),
+            (R::BF16(v1), R::BF16(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?)),
+            (R::F16(v1), R::F16(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?)),
+            (R::F32(v1), R::F32(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?)),
+            (R::F64(v1), R::F64(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?)),
             _ => Err(Error::DTypeMismatchBinaryOp {
                 lhs: v1.dtype(),
                 rhs: v2.dtype(),
@@ -1086,38 +1198,42 @@ impl<'a> Map2 for Conv1D<'a> {
             }
         }
 
-        for offset in 0..p.k_size {
-            (0..p.c_out).into_par_iter().for_each(|dst_c_idx| {
-                let dst_idx = dst_c_idx * l_out;
-                let k_cont = (0..p.c_in)
-                    .map(|c_in_idx| k[dst_c_idx * k_s0 + c_in_idx * k_s1 + offset * k_s2])
-                    .collect::<Vec<_>>();
-                for b_idx in 0..p.b_size {
-                    let dst_idx = dst_idx + b_idx * p.c_out * l_out;
-                    for dst_l in 0..l_out {
-                        let dst_idx = dst_idx + dst_l;
-                        let src_l = p.stride * dst_l + offset * p.dilation;
-                        if src_l < p.padding || src_l >= p.padding + p.l_in {
-                            continue;
-                        }
-                        let src_l = src_l - p.padding;
-                        let inp_cont = &inp_cont[b_idx * p.l_in * p.c_in + src_l * p.c_in..];
-                        assert!(inp_cont.len() >= p.c_in);
-                        assert!(k_cont.len() >= p.c_in);
-                        let mut d = T::zero();
-                        unsafe { T::vec_dot(inp_cont.as_ptr(), k_cont.as_ptr(), &mut d, p.c_in) }
-                        let dst_p = dst.as_ptr();
-                        // Safety: dst_idx are uniques per dst_c_idx which is used to parallelise
-                        // the different tasks so no two threads can try to write at the same
-                        // location.
-                        unsafe {
-                            let ptr = dst_p.add(dst_idx) as *mut T;
-                            *ptr += d
+        crate::cpu::thread_pool::install(|| {
+            for offset in 0..p.k_size {
+                (0..p.c_out).into_par_iter().for_each(|dst_c_idx| {
+                    let dst_idx = dst_c_idx * l_out;
+                    let k_cont = (0..p.c_in)
+                        .map(|c_in_idx| k[dst_c_idx * k_s0 + c_in_idx * k_s1 + offset * k_s2])
+                        .collect::<Vec<_>>();
+                    for b_idx in 0..p.b_size {
+                        let dst_idx = dst_idx + b_idx * p.c_out * l_out;
+                        for dst_l in 0..l_out {
+                            let dst_idx = dst_idx + dst_l;
+                            let src_l = p.stride * dst_l + offset * p.dilation;
+                            if src_l < p.padding || src_l >= p.padding + p.l_in {
+                                continue;
+                            }
+                            let src_l = src_l - p.padding;
+                            let inp_cont = &inp_cont[b_idx * p.l_in * p.c_in + src_l * p.c_in..];
+                            assert!(inp_cont.len() >= p.c_in);
+                            assert!(k_cont.len() >= p.c_in);
+                            let mut d = T::zero();
+                            unsafe {
+                                T::vec_dot(inp_cont.as_ptr(), k_cont.as_ptr(), &mut d, p.c_in)
+                            }
+                            let dst_p = dst.as_ptr();
+                            // Safety: dst_idx are uniques per dst_c_idx which is used to parallelise
+                            // the different tasks so no two threads can try to write at the same
+                            // location.
+                            unsafe {
+                                let ptr = dst_p.add(dst_idx) as *mut T;
+                                *ptr += d
+                            }
                         }
                     }
-                }
-            })
-        }
+                })
+            }
+        });
         Ok(dst)
     }
 }
@@ -1288,38 +1404,41 @@ impl<'a> Map2 for ConvTranspose1D<'a> {
             }
         }
 
-        for k_idx in 0..p.k_size {
-            (0..p.c_out).into_par_iter().for_each(|dst_c_idx| {
-                let k_cont = (0..p.c_in)
-                    .map(|c_in_idx| k[c_in_idx * k_s0 + dst_c_idx * k_s1 + k_idx * k_s2])
-                    .collect::<Vec<_>>();
-                for b_idx in 0..p.b_size {
-                    for l_idx in 0..p.l_in {
-                        let out_idx = l_idx * p.stride + k_idx * p.dilation;
-                        if out_idx < p.padding {
-                            continue;
-                        }
-                        let out_idx = out_idx - p.padding;
-                        if out_idx < l_out {
-                            let inp_cont = &inp_cont[b_idx * cont_s0 + l_idx * cont_s1..];
-                            let dst_idx = b_idx * dst_s0 + out_idx * dst_s2 + dst_c_idx * dst_s1;
-                            let mut d = T::zero();
-                            unsafe {
-                                T::vec_dot(inp_cont.as_ptr(), k_cont.as_ptr(), &mut d, p.c_in)
+        crate::cpu::thread_pool::install(|| {
+            for k_idx in 0..p.k_size {
+                (0..p.c_out).into_par_iter().for_each(|dst_c_idx| {
+                    let k_cont = (0..p.c_in)
+                        .map(|c_in_idx| k[c_in_idx * k_s0 + dst_c_idx * k_s1 + k_idx * k_s2])
+                        .collect::<Vec<_>>();
+                    for b_idx in 0..p.b_size {
+                        for l_idx in 0..p.l_in {
+                            let out_idx = l_idx * p.stride + k_idx * p.dilation;
+                            if out_idx < p.padding {
+                                continue;
                             }
-                            let dst_p = dst.as_ptr();
-                            // Safety: dst_idx are uniques per dst_c_idx which is used to
-                            // parallelise the different tasks so no two threads can try to
-                            // write at the same location.
-                            unsafe {
-                                let ptr = dst_p.add(dst_idx) as *mut T;
-                                *ptr += d
+                            let out_idx = out_idx - p.padding;
+                            if out_idx < l_out {
+                                let inp_cont = &inp_cont[b_idx * cont_s0 + l_idx * cont_s1..];
+                                let dst_idx =
+                                    b_idx * dst_s0 + out_idx * dst_s2 + dst_c_idx * dst_s1;
+                                let mut d = T::zero();
+                                unsafe {
+                                    T::vec_dot(inp_cont.as_ptr(), k_cont.as_ptr(), &mut d, p.c_in)
+                                }
+                                let dst_p = dst.as_ptr();
+                                // Safety: dst_idx are uniques per dst_c_idx which is used to
+                                // parallelise the different tasks so no two threads can try to
+                                // write at the same location.
+                                unsafe {
+                                    let ptr = dst_p.add(dst_idx) as *mut T;
+                                    *ptr += d
+                                }
                             }
                         }
                     }
-                }
-            })
-        }
+                })
+            }
+        });
         Ok(dst)
     }
 }
@@ -1357,56 +1476,63 @@ impl<'a> Map2 for Conv2D<'a> {
             }
         }
 
-        for offset_h in 0..p.k_h {
-            for offset_w in 0..p.k_w {
-                (0..p.c_out).into_par_iter().for_each(|dst_c_idx| {
-                    let dst_idx = dst_c_idx * out_w * out_h;
-                    let k_cont = (0..p.c_in)
-                        .map(|c_in_idx| {
-                            k[dst_c_idx * k_s0
-                                + c_in_idx * k_s1
-                                + offset_h * k_s2
-                                + offset_w * k_s3]
-                        })
-                        .collect::<Vec<_>>();
-                    for b_idx in 0..p.b_size {
-                        let dst_idx = dst_idx + b_idx * p.c_out * out_h * out_w;
-                        for dst_h in 0..out_h {
-                            let dst_idx = dst_idx + dst_h * out_w;
-                            let src_h = p.stride * dst_h + offset_h * p.dilation;
-                            if src_h < p.padding || src_h >= p.i_h + p.padding {
-                                continue;
-                            }
-                            let src_h = src_h - p.padding;
-                            for dst_w in 0..out_w {
-                                let dst_idx = dst_idx + dst_w;
-                                let src_w = p.stride * dst_w + offset_w * p.dilation;
-                                if src_w < p.padding || src_w >= p.i_w + p.padding {
+        crate::cpu::thread_pool::install(|| {
+            for offset_h in 0..p.k_h {
+                for offset_w in 0..p.k_w {
+                    (0..p.c_out).into_par_iter().for_each(|dst_c_idx| {
+                        let dst_idx = dst_c_idx * out_w * out_h;
+                        let k_cont = (0..p.c_in)
+                            .map(|c_in_idx| {
+                                k[dst_c_idx * k_s0
+                                    + c_in_idx * k_s1
+                                    + offset_h * k_s2
+                                    + offset_w * k_s3]
+                            })
+                            .collect::<Vec<_>>();
+                        for b_idx in 0..p.b_size {
+                            let dst_idx = dst_idx + b_idx * p.c_out * out_h * out_w;
+                            for dst_h in 0..out_h {
+                                let dst_idx = dst_idx + dst_h * out_w;
+                                let src_h = p.stride * dst_h + offset_h * p.dilation;
+                                if src_h < p.padding || src_h >= p.i_h + p.padding {
                                     continue;
                                 }
-                                let src_w = src_w - p.padding;
-                                let inp_cont = &inp_cont
-                                    [b_idx * cont_s0 + src_h * cont_s1 + src_w * cont_s2..];
-                                assert!(inp_cont.len() >= p.c_in);
-                                assert!(k_cont.len() >= p.c_in);
-                                let mut d = T::zero();
-                                unsafe {
-                                    T::vec_dot(inp_cont.as_ptr(), k_cont.as_ptr(), &mut d, p.c_in)
-                                }
-                                let dst_p = dst.as_ptr();
-                                // Safety: dst_idx are uniques per dst_c_idx which is used to parallelise
-                                // the different tasks so no two threads can try to write at the same
-                                // location.
-                                unsafe {
-                                    let ptr = dst_p.add(dst_idx) as *mut T;
-                                    *ptr += d
+                                let src_h = src_h - p.padding;
+                                for dst_w in 0..out_w {
+                                    let dst_idx = dst_idx + dst_w;
+                                    let src_w = p.stride * dst_w + offset_w * p.dilation;
+                                    if src_w < p.padding || src_w >= p.i_w + p.padding {
+                                        continue;
+                                    }
+                                    let src_w = src_w - p.padding;
+                                    let inp_cont = &inp_cont
+                                        [b_idx * cont_s0 + src_h * cont_s1 + src_w * cont_s2..];
+                                    assert!(inp_cont.len() >= p.c_in);
+                                    assert!(k_cont.len() >= p.c_in);
+                                    let mut d = T::zero();
+                                    unsafe {
+                                        T::vec_dot(
+                                            inp_cont.as_ptr(),
+                                            k_cont.as_ptr(),
+                                            &mut d,
+                                            p.c_in,
+                                        )
+                                    }
+                                    let dst_p = dst.as_ptr();
+                                    // Safety: dst_idx are uniques per dst_c_idx which is used to parallelise
+                                    // the different tasks so no two threads can try to write at the same
+                                    // location.
+                                    unsafe {
+                                        let ptr = dst_p.add(dst_idx) as *mut T;
+                                        *ptr += d
+                                    }
                                 }
                             }
                         }
-                    }
-                });
+                    });
+                }
             }
-        }
+        });
 
         Ok(dst)
     }
@@ -1449,55 +1575,57 @@ impl<'a> Map2 for ConvTranspose2D<'a> {
             }
         }
 
-        for k_y in 0..p.k_h {
-            for k_x in 0..p.k_w {
-                (0..p.c_out).into_par_iter().for_each(|dst_c_idx| {
-                    let k_cont = (0..p.c_in)
-                        .map(|c_in_idx| {
-                            k[c_in_idx * k_s0 + dst_c_idx * k_s1 + k_y * k_s2 + k_x * k_s3]
-                        })
-                        .collect::<Vec<_>>();
-                    for b_idx in 0..p.b_size {
-                        for inp_y in 0..p.i_h {
-                            for inp_x in 0..p.i_w {
-                                let out_x = inp_x * p.stride + k_x * p.dilation;
-                                let out_y = inp_y * p.stride + k_y * p.dilation;
-                                if out_x < p.padding || out_y < p.padding {
-                                    continue;
-                                }
-                                let out_x = out_x - p.padding;
-                                let out_y = out_y - p.padding;
-                                if out_x < out_w && out_y < out_h {
-                                    let inp_cont = &inp_cont
-                                        [b_idx * cont_s0 + inp_y * cont_s1 + inp_x * cont_s2..];
-                                    let dst_idx = b_idx * dst_s0
-                                        + out_y * dst_s2
-                                        + out_x * dst_s3
-                                        + dst_c_idx * dst_s1;
-                                    let mut d = T::zero();
-                                    unsafe {
-                                        T::vec_dot(
-                                            inp_cont.as_ptr(),
-                                            k_cont.as_ptr(),
-                                            &mut d,
-                                            p.c_in,
-                                        )
+        crate::cpu::thread_pool::install(|| {
+            for k_y in 0..p.k_h {
+                for k_x in 0..p.k_w {
+                    (0..p.c_out).into_par_iter().for_each(|dst_c_idx| {
+                        let k_cont = (0..p.c_in)
+                            .map(|c_in_idx| {
+                                k[c_in_idx * k_s0 + dst_c_idx * k_s1 + k_y * k_s2 + k_x * k_s3]
+                            })
+                            .collect::<Vec<_>>();
+                        for b_idx in 0..p.b_size {
+                            for inp_y in 0..p.i_h {
+                                for inp_x in 0..p.i_w {
+                                    let out_x = inp_x * p.stride + k_x * p.dilation;
+                                    let out_y = inp_y * p.stride + k_y * p.dilation;
+                                    if out_x < p.padding || out_y < p.padding {
+                                        continue;
                                     }
-                                    let dst_p = dst.as_ptr();
-                                    // Safety: dst_idx are uniques per dst_c_idx which is used to
-                                    // parallelise the different tasks so no two threads can try to
-                                    // write at the same location.
-                                    unsafe {
-                                        let ptr = dst_p.add(dst_idx) as *mut T;
-                                        *ptr += d
+                                    let out_x = out_x - p.padding;
+                                    let out_y = out_y - p.padding;
+                                    if out_x < out_w && out_y < out_h {
+                                        let inp_cont = &inp_cont
+                                            [b_idx * cont_s0 + inp_y * cont_s1 + inp_x * cont_s2..];
+                                        let dst_idx = b_idx * dst_s0
+                                            + out_y * dst_s2
+                                            + out_x * dst_s3
+                                            + dst_c_idx * dst_s1;
+                                        let mut d = T::zero();
+                                        unsafe {
+                                            T::vec_dot(
+                                                inp_cont.as_ptr(),
+                                                k_cont.as_ptr(),
+                                                &mut d,
+                                                p.c_in,
+                                            )
+                                        }
+                                        let dst_p = dst.as_ptr();
+                                        // Safety: dst_idx are uniques per dst_c_idx which is used to
+                                        // parallelise the different tasks so no two threads can try to
+                                        // write at the same location.
+                                        unsafe {
+                                            let ptr = dst_p.add(dst_idx) as *mut T;
+                                            *ptr += d
+                                        }
                                     }
                                 }
                             }
                         }
-                    }
-                })
+                    })
+                }
             }
-        }
+        });
         Ok(dst)
     }
 }
@@ -1567,40 +1695,43 @@ impl Map2 for MatMul {
         let dst_cs = dst_strides[1];
 
         let mut dst = vec![T::zero(); b * m * n];
-        let num_threads = crate::utils::get_num_threads();
-        let parallelism = if num_threads > 1 {
-            Parallelism::Rayon(num_threads)
-        } else {
-            Parallelism::None
-        };
-        for step in 0..b {
-            let lhs_p = &lhs[step * a_skip..];
-            let rhs_p = &rhs[step * b_skip..];
-            let dst_p = &mut dst[step * c_skip..];
-            unsafe {
-                gemm(
-                    /* m: usize = */ m,
-                    /* n: usize = */ n,
-                    /* k: usize = */ k,
-                    /* dst: *mut T = */ dst_p.as_mut_ptr(),
-                    /* dst_cs: isize = */ dst_cs as isize,
-                    /* dst_rs: isize = */ dst_rs as isize,
-                    /* read_dst: bool = */ false,
-                    /* lhs: *const T = */ lhs_p.as_ptr(),
-                    /* lhs_cs: isize = */ lhs_cs as isize,
-                    /* lhs_rs: isize = */ lhs_rs as isize,
-                    /* rhs: *const T = */ rhs_p.as_ptr(),
-                    /* rhs_cs: isize = */ rhs_cs as isize,
-                    /* rhs_rs: isize = */ rhs_rs as isize,
-                    /* alpha: T = */ T::zero(),
-                    /* beta: T = */ T::one(),
-                    /* conj_dst: bool = */ false,
-                    /* conj_lhs: bool = */ false,
-                    /* conj_rhs: bool = */ false,
-                    parallelism,
-                )
+        // Run gemm on the thread pool of the device if any, this also sets the number of threads.
+        crate::cpu::thread_pool::install(|| {
+            let num_threads = crate::utils::get_num_threads();
+            let parallelism = if num_threads > 1 {
+                Parallelism::Rayon(num_threads)
+            } else {
+                Parallelism::None
+            };
+            for step in 0..b {
+                let lhs_p = &lhs[step * a_skip..];
+                let rhs_p = &rhs[step * b_skip..];
+                let dst_p = &mut dst[step * c_skip..];
+                unsafe {
+                    gemm(
+                        /* m: usize = */ m,
+                        /* n: usize = */ n,
+                        /* k: usize = */ k,
+                        /* dst: *mut T = */ dst_p.as_mut_ptr(),
+                        /* dst_cs: isize = */ dst_cs as isize,
+                        /* dst_rs: isize = */ dst_rs as isize,
+                        /* read_dst: bool = */ false,
+                        /* lhs: *const T = */ lhs_p.as_ptr(),
+                        /* lhs_cs: isize = */ lhs_cs as isize,
+                        /* lhs_rs: isize = */ lhs_rs as isize,
+                        /* rhs: *const T = */ rhs_p.as_ptr(),
+                        /* rhs_cs: isize = */ rhs_cs as isize,
+                        /* rhs_rs: isize = */ rhs_rs as isize,
+                        /* alpha: T = */ T::zero(),
+                        /* beta: T = */ T::one(),
+                        /* conj_dst: bool = */ false,
+                        /* conj_lhs: bool = */ false,
+                        /* conj_rhs: bool = */ false,
+                        parallelism,
+                    )
+                }
             }
-        }
+        });
         Ok(dst)
     }
 
@@ -1851,6 +1982,18 @@ impl CpuStorage {
         D::cpu_storage_as_slice(self)
     }
 
+    pub fn storage_ref(&self) -> CpuStorageRef<'_> {
+        match self {
+            Self::U8(vs) => CpuStorageRef::U8(vs),
+            Self::U32(vs) => CpuStorageRef::U32(vs),
+            Self::I64(vs) => CpuStorageRef::I64(vs),
+            Self::BF16(vs) => CpuStorageRef::BF16(vs),
+            Self::F16(vs) => CpuStorageRef::F16(vs),
+            Self::F32(vs) => CpuStorageRef::F32(vs),
+            Self::F64(vs) => CpuStorageRef::F64(vs),
+        }
+    }
+
     pub fn concat(storages: &[CpuStorage]) -> Result<CpuStorage> {
         let storage0 = &storages[0];
         let s = match storage0 {
@@ -1936,224 +2079,217 @@ impl CpuStorage {
     }
 }
 
-impl BackendStorage for CpuStorage {
-    type Device = CpuDevice;
-
-    fn dtype(&self) -> DType {
-        match self {
-            Self::U8(_) => DType::U8,
-            Self::U32(_) => DType::U32,
-            Self::I64(_) => DType::I64,
-            Self::BF16(_) => DType::BF16,
-            Self::F16(_) => DType::F16,
-            Self::F32(_) => DType::F32,
-            Self::F64(_) => DType::F64,
-        }
-    }
-
-    fn to_dtype(&self, layout: &Layout, dtype: DType) -> Result<Self> {
+// The cpu operations, these borrow their inputs so that they also apply to memory mapped storages.
+// The `BackendStorage` implementation for `CpuStorage` delegates to them.
+impl<'a> CpuStorageRef<'a> {
+    pub(crate) fn to_dtype(self, layout: &Layout, dtype: DType) -> Result<CpuStorage> {
         // TODO: find a way around the quadratic number of cases below.
         match (self, dtype) {
-            (Self::U8(storage), DType::BF16) => {
+            (R::U8(storage), DType::BF16) => {
                 let data = unary_map(storage, layout, |v| bf16::from_f32(v as f32));
-                Ok(Self::BF16(data))
+                Ok(C::BF16(data))
             }
-            (Self::U32(storage), DType::BF16) => {
+            (R::U32(storage), DType::BF16) => {
                 let data = unary_map(storage, layout, |v| bf16::from_f32(v as f32));
-                Ok(Self::BF16(data))
+                Ok(C::BF16(data))
             }
-            (Self::I64(storage), DType::BF16) => {
+            (R::I64(storage), DType::BF16) => {
                 let data = unary_map(storage, layout, |v| bf16::from_f32(v as f32));
-                Ok(Self::BF16(data))
+                Ok(C::BF16(data))
             }
-            (Self::BF16(storage), DType::BF16) => {
+            (R::BF16(storage), DType::BF16) => {
                 let data = unary_map(storage, layout, |v| v);
-                Ok(Self::BF16(data))
+                Ok(C::BF16(data))
             }
-            (Self::F16(storage), DType::BF16) => {
+            (R::F16(storage), DType::BF16) => {
                 let data = unary_map(storage, layout, |v| bf16::from_f32(v.to_f32()));
-                Ok(Self::BF16(data))
+                Ok(C::BF16(data))
             }
-            (Self::F32(storage), DType::BF16) => {
+            (R::F32(storage), DType::BF16) => {
                 let data = unary_map(storage, layout, bf16::from_f32);
-                Ok(Self::BF16(data))
+                Ok(C::BF16(data))
             }
-            (Self::F64(storage), DType::BF16) => {
+            (R::F64(storage), DType::BF16) => {
                 let data = unary_map(storage, layout, bf16::from_f64);
-                Ok(Self::BF16(data))
+                Ok(C::BF16(data))
             }
-            (Self::U8(storage), DType::F16) => {
+            (R::U8(storage), DType::F16) => {
                 let data = unary_map(storage, layout, |v| f16::from_f32(v as f32));
-                Ok(Self::F16(data))
+                Ok(C::F16(data))
             }
-            (Self::U32(storage), DType::F16) => {
+            (R::U32(storage), DType::F16) => {
                 let data = unary_map(storage, layout, |v| f16::from_f32(v as f32));
-                Ok(Self::F16(data))
+                Ok(C::F16(data))
             }
-            (Self::I64(storage), DType::F16) => {
+            (R::I64(storage), DType::F16) => {
                 let data = unary_map(storage, layout, |v| f16::from_f32(v as f32));
-                Ok(Self::F16(data))
+                Ok(C::F16(data))
             }
-            (Self::BF16(storage), DType::F16) => {
+            (R::BF16(storage), DType::F16) => {
                 let data = unary_map(storage, layout, |v| f16::from_f32(v.to_f32()));
-                Ok(Self::F16(data))
+                Ok(C::F16(data))
             }
-            (Self::F16(storage), DType::F16) => {
+            (R::F16(storage), DType::F16) => {
                 let data = unary_map(storage, layout, |v| v);
-                Ok(Self::F16(data))
+                Ok(C::F16(data))
             }
-            (Self::F32(storage), DType::F16) => {
+            (R::F32(storage), DType::F16) => {
                 let data = unary_map(storage, layout, f16::from_f32);
-                Ok(Self::F16(data))
+                Ok(C::F16(data))
             }
-            (Self::F64(storage), DType::F16) => {
+            (R::F64(storage), DType::F16) => {
                 let data = unary_map(storage, layout, f16::from_f64);
-                Ok(Self::F16(data))
+                Ok(C::F16(data))
             }
-            (Self::U8(storage), DType::F32) => {
+            (R::U8(storage), DType::F32) => {
                 let data = unary_map(storage, layout, |v| v as f32);
-                Ok(Self::F32(data))
+                Ok(C::F32(data))
             }
-            (Self::U32(storage), DType::F32) => {
+            (R::U32(storage), DType::F32) => {
                 let data = unary_map(storage, layout, |v| v as f32);
-                Ok(Self::F32(data))
+                Ok(C::F32(data))
             }
-            (Self::I64(storage), DType::F32) => {
+            (R::I64(storage), DType::F32) => {
                 let data = unary_map(storage, layout, |v| v as f32);
-                Ok(Self::F32(data))
+                Ok(C::F32(data))
             }
-            (Self::BF16(storage), DType::F32) => {
+            (R::BF16(storage), DType::F32) => {
                 let data = unary_map(storage, layout, |v| v.to_f32());
-                Ok(Self::F32(data))
+                Ok(C::F32(data))
             }
-            (Self::F16(storage), DType::F32) => {
+            (R::F16(storage), DType::F32) => {
                 let data = unary_map(storage, layout, |v| v.to_f32());
-                Ok(Self::F32(data))
+                Ok(C::F32(data))
             }
-            (Self::F32(storage), DType::F32) => {
+            (R::F32(storage), DType::F32) => {
                 let data = unary_map(storage, layout, |v| v);
-                Ok(Self::F32(data))
+                Ok(C::F32(data))
             }
-            (Self::F64(storage), DType::F32) => {
+            (R::F64(storage), DType::F32) => {
                 let data = unary_map(storage, layout, |v| v as f32);
-                Ok(Self::F32(data))
+                Ok(C::F32(data))
             }
-            (Self::U8(storage), DType::U8) => {
+            (R::U8(storage), DType::U8) => {
                 let data = unary_map(storage, layout, |v| v);
-                Ok(Self::U8(data))
+                Ok(C::U8(data))
             }
-            (Self::BF16(storage), DType::U8) => {
+            (R::BF16(storage), DType::U8) => {
                 let data = unary_map(storage, layout, |v| v.to_f32() as u8);
-                Ok(Self::U8(data))
+                Ok(C::U8(data))
             }
-            (Self::F16(storage), DType::U8) => {
+            (R::F16(storage), DType::U8) => {
                 let data = unary_map(storage, layout, |v| v.to_f32() as u8);
-                Ok(Self::U8(data))
+                Ok(C::U8(data))
             }
-            (Self::F32(storage), DType::U8) => {
+            (R::F32(storage), DType::U8) => {
                 let data = unary_map(storage, layout, |v| v as u8);
-                Ok(Self::U8(data))
+                Ok(C::U8(data))
             }
-            (Self::F64(storage), DType::U8) => {
+            (R::F64(storage), DType::U8) => {
                 let data = unary_map(storage, layout, |v| v as u8);
-                Ok(Self::U8(data))
+                Ok(C::U8(data))
             }
-            (Self::U32(storage), DType::U8) => {
+            (R::U32(storage), DType::U8) => {
                 let data = unary_map(storage, layout, |v| v as u8);
-                Ok(Self::U8(data))
+                Ok(C::U8(data))
             }
-            (Self::I64(storage), DType::U8) => {
+            (R::I64(storage), DType::U8) => {
                 let data = unary_map(storage, layout, |v| v as u8);
-                Ok(Self::U8(data))
+                Ok(C::U8(data))
             }
-            (Self::U8(storage), DType::U32) => {
+            (R::U8(storage), DType::U32) => {
                 let data = unary_map(storage, layout, |v| v as u32);
-                Ok(Self::U32(data))
+                Ok(C::U32(data))
             }
-            (Self::U32(storage), DType::U32) => {
+            (R::U32(storage), DType::U32) => {
                 let data = unary_map(storage, layout, |v| v);
-                Ok(Self::U32(data))
+                Ok(C::U32(data))
             }
-            (Self::I64(storage), DType::U32) => {
+            (R::I64(storage), DType::U32) => {
                 let data = unary_map(storage, layout, |v| v as u32);
-                Ok(Self::U32(data))
+                Ok(C::U32(data))
             }
-            (Self::BF16(storage), DType::U32) => {
+            (R::BF16(storage), DType::U32) => {
                 let data = unary_map(storage, layout, |v| v.to_f32() as u32);
-                Ok(Self::U32(data))
+                Ok(C::U32(data))
             }
-            (Self::F16(storage), DType::U32) => {
+            (R::F16(storage), DType::U32) => {
                 let data = unary_map(storage, layout, |v| v.to_f32() as u32);
-                Ok(Self::U32(data))
+                Ok(C::U32(data))
             }
-            (Self::F32(storage), DType::U32) => {
+            (R::F32(storage), DType::U32) => {
                 let data = unary_map(storage, layout, |v| v as u32);
-                Ok(Self::U32(data))
+                Ok(C::U32(data))
             }
-            (Self::F64(storage), DType::U32) => {
+            (R::F64(storage), DType::U32) => {
                 let data = unary_map(storage, layout, |v| v as u32);
-                Ok(Self::U32(data))
+                Ok(C::U32(data))
             }
-            (Self::U8(storage), DType::I64) => {
+            (R::U8(storage), DType::I64) => {
                 let data = unary_map(storage, layout, |v| v as i64);
-                Ok(Self::I64(data))
+                Ok(C::I64(data))
             }
-            (Self::U32(storage), DType::I64) => {
+            (R::U32(storage), DType::I64) => {
                 let data = unary_map(storage, layout, |v| v as i64);
-                Ok(Self::I64(data))
+                Ok(C::I64(data))
             }
-            (Self::I64(storage), DType::I64) => {
+            (R::I64(storage), DType::I64) => {
                 let data = unary_map(storage, layout, |v| v);
-                Ok(Self::I64(data))
+                Ok(C::I64(data))
             }
-            (Self::BF16(storage), DType::I64) => {
+            (R::BF16(storage), DType::I64) => {
                 let data = unary_map(storage, layout, |v| v.to_f32() as i64);
-                Ok(Self::I64(data))
+                Ok(C::I64(data))
             }
-            (Self::F16(storage), DType::I64) => {
+            (R::F16(storage), DType::I64) => {
                 let data = unary_map(storage, layout, |v| v.to_f32() as i64);
-                Ok(Self::I64(data))
+                Ok(C::I64(data))
             }
-            (Self::F32(storage), DType::I64) => {
+            (R::F32(storage), DType::I64) => {
                 let data = unary_map(storage, layout, |v| v as i64);
-                Ok(Self::I64(data))
+                Ok(C::I64(data))
             }
-            (Self::F64(storage), DType::I64) => {
+            (R::F64(storage), DType::I64) => {
                 let data = unary_map(storage, layout, |v| v as i64);
-                Ok(Self::I64(data))
+                Ok(C::I64(data))
             }
-            (Self::U8(storage), DType::F64) => {
+            (R::U8(storage), DType::F64) => {
                 let data = unary_map(storage, layout, |v| v as f64);
-                Ok(Self::F64(data))
+                Ok(C::F64(data))
             }
-            (Self::U32(storage), DType::F64) => {
+            (R::U32(storage), DType::F64) => {
                 let data = unary_map(storage, layout, |v| v as f64);
-                Ok(Self::F64(data))
+                Ok(C::F64(data))
             }
-            (Self::I64(storage), DType::F64) => {
+            (R::I64(storage), DType::F64) => {
                 let data = unary_map(storage, layout, |v| v as f64);
-                Ok(Self::F64(data))
+                Ok(C::F64(data))
             }
-            (Self::BF16(storage), DType::F64) => {
+            (R::BF16(storage), DType::F64) => {
                 let data = unary_map(storage, layout, |v| v.to_f64());
-                Ok(Self::F64(data))
+                Ok(C::F64(data))
             }
-            (Self::F16(storage), DType::F64) => {
+            (R::F16(storage), DType::F64) => {
                 let data = unary_map(storage, layout, |v| v.to_f64());
-                Ok(Self::F64(data))
+                Ok(C::F64(data))
             }
-            (Self::F32(storage), DType::F64) => {
+            (R::F32(storage), DType::F64) => {
                 let data = unary_map(storage, layout, |v| v as f64);
-                Ok(Self::F64(data))
+                Ok(C::F64(data))
             }
-            (Self::F64(storage), DType::F64) => {
+            (R::F64(storage), DType::F64) => {
                 let data = unary_map(storage, layout, |v| v);
-                Ok(Self::F64(data))
+                Ok(C::F64(data))
             }
         }
     }
 
-    fn reduce_op(&self, op: ReduceOp, layout: &Layout, reduce_dims: &[usize]) -> Result<Self> {
+    pub(crate) fn reduce_op(
+        self,
+        op: ReduceOp,
+        layout: &Layout,
+        reduce_dims: &[usize],
+    ) -> Result<CpuStorage> {
         match op {
             ReduceOp::Sum => {
                 let src_dims = layout.dims();
@@ -2209,206 +2345,217 @@ impl BackendStorage for CpuStorage {
         }
     }
 
-    fn cmp(&self, op: CmpOp, rhs: &Self, lhs_l: &Layout, rhs_l: &Layout) -> Result<Self> {
+    pub(crate) fn cmp(
+        self,
+        op: CmpOp,
+        rhs: CpuStorageRef<'_>,
+        lhs_l: &Layout,
+        rhs_l: &Layout,
+    ) -> Result<CpuStorage> {
         Cmp(op).map(self, lhs_l, rhs, rhs_l)
     }
 
-    fn affine(&self, layout: &Layout, mul: f64, add: f64) -> Result<Self> {
+    pub(crate) fn affine(self, layout: &Layout, mul: f64, add: f64) -> Result<CpuStorage> {
         Affine(mul, add).map(self, layout)
     }
 
-    fn avg_pool2d(
-        &self,
+    pub(crate) fn avg_pool2d(
+        self,
         layout: &Layout,
         kernel_size: (usize, usize),
         stride: (usize, usize),
-    ) -> Result<Self> {
+    ) -> Result<CpuStorage> {
         AvgPool2D(kernel_size, stride).map(self, layout)
     }
 
-    fn max_pool2d(
-        &self,
+    pub(crate) fn max_pool2d(
+        self,
         layout: &Layout,
         kernel_size: (usize, usize),
         stride: (usize, usize),
-    ) -> Result<Self> {
+    ) -> Result<CpuStorage> {
         MaxPool2D(kernel_size, stride).map(self, layout)
     }
 
-    fn upsample_nearest1d(&self, layout: &Layout, sz: usize) -> Result<Self> {
+    pub(crate) fn upsample_nearest1d(self, layout: &Layout, sz: usize) -> Result<CpuStorage> {
         UpsampleNearest1D(sz).map(self, layout)
     }
 
-    fn upsample_nearest2d(&self, layout: &Layout, h: usize, w: usize) -> Result<Self> {
+    pub(crate) fn upsample_nearest2d(
+        self,
+        layout: &Layout,
+        h: usize,
+        w: usize,
+    ) -> Result<CpuStorage> {
         UpsampleNearest2D(h, w).map(self, layout)
     }
 
-    fn powf(&self, layout: &Layout, e: f64) -> Result<Self> {
+    pub(crate) fn powf(self, layout: &Layout, e: f64) -> Result<CpuStorage> {
         use num_traits::Float;
         // TODO: Have some generic map for functions that apply on num_traits::Float elements.
         match self {
-            Self::BF16(storage) => {
+            R::BF16(storage) => {
                 let data = unary_map(storage, layout, |v| v.powf(bf16::from_f64(e)));
-                Ok(Self::BF16(data))
+                Ok(C::BF16(data))
             }
-            Self::F16(storage) => {
+            R::F16(storage) => {
                 let data = unary_map(storage, layout, |v| v.powf(f16::from_f64(e)));
-                Ok(Self::F16(data))
+                Ok(C::F16(data))
             }
-            Self::F32(storage) => {
+            R::F32(storage) => {
                 let data = unary_map(storage, layout, |v| v.powf(e as f32));
-                Ok(Self::F32(data))
+                Ok(C::F32(data))
             }
-            Self::F64(storage) => {
+            R::F64(storage) => {
                 let data = unary_map(storage, layout, |v| v.powf(e));
-                Ok(Self::F64(data))
+                Ok(C::F64(data))
             }
-            Self::U8(_) => Err(Error::UnsupportedDTypeForOp(DType::U8, "elu").bt()),
-            Self::U32(_) => Err(Error::UnsupportedDTypeForOp(DType::U32, "elu").bt()),
-            Self::I64(_) => Err(Error::UnsupportedDTypeForOp(DType::I64, "elu").bt()),
+            R::U8(_) => Err(Error::UnsupportedDTypeForOp(DType::U8, "elu").bt()),
+            R::U32(_) => Err(Error::UnsupportedDTypeForOp(DType::U32, "elu").bt()),
+            R::I64(_) => Err(Error::UnsupportedDTypeForOp(DType::I64, "elu").bt()),
         }
     }
 
-    fn elu(&self, layout: &Layout, alpha: f64) -> Result<Self> {
+    pub(crate) fn elu(self, layout: &Layout, alpha: f64) -> Result<CpuStorage> {
         // TODO: Have some generic map for functions that apply on num_traits::Float elements.
         match self {
-            Self::BF16(storage) => {
+            R::BF16(storage) => {
                 let data = unary_map(storage, layout, |v| elu(v, bf16::from_f64(alpha)));
-                Ok(Self::BF16(data))
+                Ok(C::BF16(data))
             }
-            Self::F16(storage) => {
+            R::F16(storage) => {
                 let data = unary_map(storage, layout, |v| elu(v, f16::from_f64(alpha)));
-                Ok(Self::F16(data))
+                Ok(C::F16(data))
             }
-            Self::F32(storage) => {
+            R::F32(storage) => {
                 let data = unary_map(storage, layout, |v| elu(v, f32::from_f64(alpha)));
-                Ok(Self::F32(data))
+                Ok(C::F32(data))
             }
-            Self::F64(storage) => {
+            R::F64(storage) => {
                 let data = unary_map(storage, layout, |v| elu(v, alpha));
-                Ok(Self::F64(data))
+                Ok(C::F64(data))
             }
-            Self::U8(_) => Err(Error::UnsupportedDTypeForOp(DType::U8, "elu").bt()),
-            Self::U32(_) => Err(Error::UnsupportedDTypeForOp(DType::U32, "elu").bt()),
-            Self::I64(_) => Err(Error::UnsupportedDTypeForOp(DType::I64, "elu").bt()),
+            R::U8(_) => Err(Error::UnsupportedDTypeForOp(DType::U8, "elu").bt()),
+            R::U32(_) => Err(Error::UnsupportedDTypeForOp(DType::U32, "elu").bt()),
+            R::I64(_) => Err(Error::UnsupportedDTypeForOp(DType::I64, "elu").bt()),
         }
     }
 
-    fn unary_impl<B: UnaryOpT>(&self, layout: &Layout) -> Result<Self> {
+    pub(crate) fn unary_impl<B: UnaryOpT>(self, layout: &Layout) -> Result<CpuStorage> {
         match self {
-            Self::BF16(storage) => {
+            R::BF16(storage) => {
                 if B::BF16_VEC {
                     let data = unary_map_vec(storage, layout, B::bf16, B::bf16_vec);
-                    Ok(Self::BF16(data))
+                    Ok(C::BF16(data))
                 } else {
                     let data = unary_map(storage, layout, B::bf16);
-                    Ok(Self::BF16(data))
+                    Ok(C::BF16(data))
                 }
             }
-            Self::F16(storage) => {
+            R::F16(storage) => {
                 if B::F16_VEC {
                     let data = unary_map_vec(storage, layout, B::f16, B::f16_vec);
-                    Ok(Self::F16(data))
+                    Ok(C::F16(data))
                 } else {
                     let data = unary_map(storage, layout, B::f16);
-                    Ok(Self::F16(data))
+                    Ok(C::F16(data))
                 }
             }
-            Self::F32(storage) => {
+            R::F32(storage) => {
                 if B::F32_VEC {
                     let data = unary_map_vec(storage, layout, B::f32, B::f32_vec);
-                    Ok(Self::F32(data))
+                    Ok(C::F32(data))
                 } else {
                     let data = unary_map(storage, layout, B::f32);
-                    Ok(Self::F32(data))
+                    Ok(C::F32(data))
                 }
             }
-            Self::F64(storage) => {
+            R::F64(storage) => {
                 if B::F64_VEC {
                     let data = unary_map_vec(storage, layout, B::f64, B::f64_vec);
-                    Ok(Self::F64(data))
+                    Ok(C::F64(data))
                 } else {
                     let data = unary_map(storage, layout, B::f64);
-                    Ok(Self::F64(data))
+                    Ok(C::F64(data))
                 }
             }
-            Self::U8(storage) => {
+            R::U8(storage) => {
                 let data = unary_map(storage, layout, B::u8);
-                Ok(Self::U8(data))
+                Ok(C::U8(data))
             }
-            Self::U32(storage) => {
+            R::U32(storage) => {
                 let data = unary_map(storage, layout, B::u32);
-                Ok(Self::U32(data))
+                Ok(C::U32(data))
             }
-            Self::I64(storage) => {
+            R::I64(storage) => {
                 let data = unary_map(storage, layout, B::i64);
-                Ok(Self::I64(data))
+                Ok(C::I64(data))
             }
         }
     }
 
-    fn binary_impl<B: BinaryOpT>(
-        &self,
-        rhs: &Self,
+    pub(crate) fn binary_impl<B: BinaryOpT>(
+        self,
+        rhs: CpuStorageRef<'_>,
         lhs_l: &Layout,
         rhs_l: &Layout,
-    ) -> Result<Self> {
+    ) -> Result<CpuStorage> {
         match (self, rhs) {
-            (Self::BF16(lhs), Self::BF16(rhs)) => {
+            (R::BF16(lhs), R::BF16(rhs)) => {
                 let data = if B::BF16_VEC {
                     binary_map_vec(lhs_l, rhs_l, lhs, rhs, B::bf16, B::bf16_vec)
                 } else {
                     binary_map(lhs_l, rhs_l, lhs, rhs, B::bf16)
                 };
-                Ok(Self::BF16(data))
+                Ok(C::BF16(data))
             }
-            (Self::F16(lhs), Self::F16(rhs)) => {
+            (R::F16(lhs), R::F16(rhs)) => {
                 let data = if B::F16_VEC {
                     binary_map_vec(lhs_l, rhs_l, lhs, rhs, B::f16, B::f16_vec)
                 } else {
                     binary_map(lhs_l, rhs_l, lhs, rhs, B::f16)
                 };
-                Ok(Self::F16(data))
+                Ok(C::F16(data))
             }
-            (Self::F32(lhs), Self::F32(rhs)) => {
+            (R::F32(lhs), R::F32(rhs)) => {
                 let data = if B::F32_VEC {
                     binary_map_vec(lhs_l, rhs_l, lhs, rhs, B::f32, B::f32_vec)
                 } else {
                     binary_map(lhs_l, rhs_l, lhs, rhs, B::f32)
                 };
-                Ok(Self::F32(data))
+                Ok(C::F32(data))
             }
-            (Self::F64(lhs), Self::F64(rhs)) => {
+            (R::F64(lhs), R::F64(rhs)) => {
                 let data = if B::F64_VEC {
                     binary_map_vec(lhs_l, rhs_l, lhs, rhs, B::f64, B::f64_vec)
                 } else {
                     binary_map(lhs_l, rhs_l, lhs, rhs, B::f64)
                 };
-                Ok(Self::F64(data))
+                Ok(C::F64(data))
             }
-            (Self::U32(lhs), Self::U32(rhs)) => {
+            (R::U32(lhs), R::U32(rhs)) => {
                 let data = if B::U32_VEC {
                     binary_map_vec(lhs_l, rhs_l, lhs, rhs, B::u32, B::u32_vec)
                 } else {
                     binary_map(lhs_l, rhs_l, lhs, rhs, B::u32)
                 };
-                Ok(Self::U32(data))
+                Ok(C::U32(data))
             }
-            (Self::I64(lhs), Self::I64(rhs)) => {
+            (R::I64(lhs), R::I64(rhs)) => {
                 let data = if B::I64_VEC {
                     binary_map_vec(lhs_l, rhs_l, lhs, rhs, B::i64, B::i64_vec)
                 } else {
                     binary_map(lhs_l, rhs_l, lhs, rhs, B::i64)
                 };
-                Ok(Self::I64(data))
+                Ok(C::I64(data))
             }
-            (Self::U8(lhs), Self::U8(rhs)) => {
+            (R::U8(lhs), R::U8(rhs)) => {
                 let data = if B::U8_VEC {
                     binary_map_vec(lhs_l, rhs_l, lhs, rhs, B::u8, B::u8_vec)
                 } else {
                     binary_map(lhs_l, rhs_l, lhs, rhs, B::u8)
                 };
-                Ok(Self::U8(data))
+                Ok(C::U8(data))
             }
             _ => {
                 // This should be covered by the dtype check above.
@@ -2422,15 +2569,20 @@ impl BackendStorage for CpuStorage {
         }
     }
 
-    fn copy_strided_src(&self, dst: &mut Self, dst_offset: usize, src_l: &Layout) -> Result<()> {
+    pub(crate) fn copy_strided_src(
+        self,
+        dst: &mut CpuStorage,
+        dst_offset: usize,
+        src_l: &Layout,
+    ) -> Result<()> {
         match (self, dst) {
-            (Self::U8(src), Self::U8(dst)) => copy_strided_src_(src, dst, dst_offset, src_l),
-            (Self::U32(src), Self::U32(dst)) => copy_strided_src_(src, dst, dst_offset, src_l),
-            (Self::I64(src), Self::I64(dst)) => copy_strided_src_(src, dst, dst_offset, src_l),
-            (Self::BF16(src), Self::BF16(dst)) => copy_strided_src_(src, dst, dst_offset, src_l),
-            (Self::F16(src), Self::F16(dst)) => copy_strided_src_(src, dst, dst_offset, src_l),
-            (Self::F32(src), Self::F32(dst)) => copy_strided_src_(src, dst, dst_offset, src_l),
-            (Self::F64(src), Self::F64(dst)) => copy_strided_src_(src, dst, dst_offset, src_l),
+            (R::U8(src), C::U8(dst)) => copy_strided_src_(src, dst, dst_offset, src_l),
+            (R::U32(src), C::U32(dst)) => copy_strided_src_(src, dst, dst_offset, src_l),
+            (R::I64(src), C::I64(dst)) => copy_strided_src_(src, dst, dst_offset, src_l),
+            (R::BF16(src), C::BF16(dst)) => copy_strided_src_(src, dst, dst_offset, src_l),
+            (R::F16(src), C::F16(dst)) => copy_strided_src_(src, dst, dst_offset, src_l),
+            (R::F32(src), C::F32(dst)) => copy_strided_src_(src, dst, dst_offset, src_l),
+            (R::F64(src), C::F64(dst)) => copy_strided_src_(src, dst, dst_offset, src_l),
             (_, dst) => {
                 // This should be covered by the dtype check above.
                 return Err(Error::DTypeMismatchBinaryOp {
@@ -2444,29 +2596,29 @@ impl BackendStorage for CpuStorage {
         Ok(())
     }
 
-    fn where_cond(
-        &self,
+    pub(crate) fn where_cond(
+        self,
         layout: &Layout,
-        t: &Self,
+        t: CpuStorageRef<'_>,
         t_l: &Layout,
-        f: &Self,
+        f: CpuStorageRef<'_>,
         f_l: &Layout,
-    ) -> Result<Self> {
+    ) -> Result<CpuStorage> {
         match self {
-            Self::U8(pred) => WCond(pred, layout).map(t, t_l, f, f_l),
-            Self::U32(pred) => WCond(pred, layout).map(t, t_l, f, f_l),
-            Self::I64(pred) => WCond(pred, layout).map(t, t_l, f, f_l),
+            R::U8(pred) => WCond(pred, layout).map(t, t_l, f, f_l),
+            R::U32(pred) => WCond(pred, layout).map(t, t_l, f, f_l),
+            R::I64(pred) => WCond(pred, layout).map(t, t_l, f, f_l),
             _ => Err(Error::UnsupportedDTypeForOp(self.dtype(), "where-cond")),
         }
     }
 
-    fn conv1d(
-        &self,
+    pub(crate) fn conv1d(
+        self,
         l: &Layout,
-        kernel: &Self,
+        kernel: CpuStorageRef<'_>,
         kernel_l: &Layout,
         params: &crate::conv::ParamsConv1D,
-    ) -> Result<Self> {
+    ) -> Result<CpuStorage> {
         if !USE_IM2COL_CONV1D {
             return Conv1D(params).map(self, l, kernel, kernel_l);
         }
@@ -2487,39 +2639,41 @@ impl BackendStorage for CpuStorage {
             let kernel_l = Layout::contiguous_with_offset((1, n, k), kernel_l.start_offset())
                 .transpose(1, 2)?
                 .broadcast_as((b, k, n))?;
-            col.matmul(kernel, (b, m, n, k), &col_l, &kernel_l)?
+            col.storage_ref()
+                .matmul(kernel, (b, m, n, k), &col_l, &kernel_l)?
         } else {
             // Make the kernel contiguous if not already the case.
-            let mut kernel_c = self.device().zeros_impl(kernel_l.shape(), kernel.dtype())?;
+            let mut kernel_c = CpuDevice.zeros_impl(kernel_l.shape(), kernel.dtype())?;
             kernel.copy_strided_src(&mut kernel_c, 0, kernel_l)?;
             let kernel_l = Layout::contiguous_with_offset((1, n, k), kernel_l.start_offset())
                 .transpose(1, 2)?
                 .broadcast_as((b, k, n))?;
-            col.matmul(kernel, (b, m, n, k), &col_l, &kernel_l)?
+            col.storage_ref()
+                .matmul(kernel, (b, m, n, k), &col_l, &kernel_l)?
         };
         let res_l = Layout::contiguous((b, l_out, params.c_out)).transpose(1, 2)?;
-        let mut res_t = self.device().zeros_impl(res_l.shape(), res.dtype())?;
-        res.copy_strided_src(&mut res_t, 0, &res_l)?;
+        let mut res_t = CpuDevice.zeros_impl(res_l.shape(), res.dtype())?;
+        res.storage_ref().copy_strided_src(&mut res_t, 0, &res_l)?;
         Ok(res_t)
     }
 
-    fn conv_transpose1d(
-        &self,
+    pub(crate) fn conv_transpose1d(
+        self,
         l: &Layout,
-        kernel: &Self,
+        kernel: CpuStorageRef<'_>,
         kernel_l: &Layout,
         params: &crate::conv::ParamsConvTranspose1D,
-    ) -> Result<Self> {
+    ) -> Result<CpuStorage> {
         ConvTranspose1D(params).map(self, l, kernel, kernel_l)
     }
 
-    fn conv2d(
-        &self,
+    pub(crate) fn conv2d(
+        self,
         l: &Layout,
-        kernel: &Self,
+        kernel: CpuStorageRef<'_>,
         kernel_l: &Layout,
         params: &crate::conv::ParamsConv2D,
-    ) -> Result<Self> {
+    ) -> Result<CpuStorage> {
         if !USE_IM2COL_CONV2D {
             return Conv2D(params).map(self, l, kernel, kernel_l);
         }
@@ -2541,94 +2695,108 @@ impl BackendStorage for CpuStorage {
             let kernel_l = Layout::contiguous_with_offset((1, n, k), kernel_l.start_offset())
                 .transpose(1, 2)?
                 .broadcast_as((b, k, n))?;
-            col.matmul(kernel, (b, m, n, k), &col_l, &kernel_l)?
+            col.storage_ref()
+                .matmul(kernel, (b, m, n, k), &col_l, &kernel_l)?
         } else {
             // Make the kernel contiguous if not already the case.
-            let mut kernel_c = self.device().zeros_impl(kernel_l.shape(), kernel.dtype())?;
+            let mut kernel_c = CpuDevice.zeros_impl(kernel_l.shape(), kernel.dtype())?;
             kernel.copy_strided_src(&mut kernel_c, 0, kernel_l)?;
             let kernel_l = Layout::contiguous_with_offset((1, n, k), kernel_l.start_offset())
                 .transpose(1, 2)?
                 .broadcast_as((b, k, n))?;
-            col.matmul(kernel, (b, m, n, k), &col_l, &kernel_l)?
+            col.storage_ref()
+                .matmul(kernel, (b, m, n, k), &col_l, &kernel_l)?
         };
         let res_l = Layout::contiguous((b, h_out, w_out, params.c_out))
             .transpose(1, 2)?
             .transpose(1, 3)?;
-        let mut res_t = self.device().zeros_impl(res_l.shape(), res.dtype())?;
-        res.copy_strided_src(&mut res_t, 0, &res_l)?;
+        let mut res_t = CpuDevice.zeros_impl(res_l.shape(), res.dtype())?;
+        res.storage_ref().copy_strided_src(&mut res_t, 0, &res_l)?;
         Ok(res_t)
     }
 
-    fn conv_transpose2d(
-        &self,
+    pub(crate) fn conv_transpose2d(
+        self,
         l: &Layout,
-        kernel: &Self,
+        kernel: CpuStorageRef<'_>,
         kernel_l: &Layout,
         params: &crate::conv::ParamsConvTranspose2D,
-    ) -> Result<Self> {
+    ) -> Result<CpuStorage> {
         ConvTranspose2D(params).map(self, l, kernel, kernel_l)
     }
 
-    fn index_select(&self, ids: &Self, l: &Layout, ids_l: &Layout, dim: usize) -> Result<Self> {
+    pub(crate) fn index_select(
+        self,
+        ids: CpuStorageRef<'_>,
+        l: &Layout,
+        ids_l: &Layout,
+        dim: usize,
+    ) -> Result<CpuStorage> {
         match ids {
-            Self::U8(ids) => IndexSelect { ids, ids_l, dim }.map(self, l),
-            Self::U32(ids) => IndexSelect { ids, ids_l, dim }.map(self, l),
-            Self::I64(ids) => IndexSelect { ids, ids_l, dim }.map(self, l),
+            R::U8(ids) => IndexSelect { ids, ids_l, dim }.map(self, l),
+            R::U32(ids) => IndexSelect { ids, ids_l, dim }.map(self, l),
+            R::I64(ids) => IndexSelect { ids, ids_l, dim }.map(self, l),
             _ => Err(Error::UnsupportedDTypeForOp(self.dtype(), "index-select")),
         }
     }
 
-    fn gather(&self, l: &Layout, ids: &Self, ids_l: &Layout, dim: usize) -> Result<Self> {
+    pub(crate) fn gather(
+        self,
+        l: &Layout,
+        ids: CpuStorageRef<'_>,
+        ids_l: &Layout,
+        dim: usize,
+    ) -> Result<CpuStorage> {
         match ids {
-            Self::U8(ids) => Gather { ids, ids_l, dim }.map(self, l),
-            Self::U32(ids) => Gather { ids, ids_l, dim }.map(self, l),
-            Self::I64(ids) => Gather { ids, ids_l, dim }.map(self, l),
+            R::U8(ids) => Gather { ids, ids_l, dim }.map(self, l),
+            R::U32(ids) => Gather { ids, ids_l, dim }.map(self, l),
+            R::I64(ids) => Gather { ids, ids_l, dim }.map(self, l),
             _ => Err(Error::UnsupportedDTypeForOp(self.dtype(), "gather")),
         }
     }
 
-    fn scatter_add(
-        &self,
+    pub(crate) fn scatter_add(
+        self,
         l: &Layout,
-        ids: &Self,
+        ids: CpuStorageRef<'_>,
         ids_l: &Layout,
-        src: &Self,
+        src: CpuStorageRef<'_>,
         src_l: &Layout,
         dim: usize,
-    ) -> Result<Self> {
+    ) -> Result<CpuStorage> {
         match ids {
-            Self::U8(ids) => ScatterAdd { ids, ids_l, dim }.map(self, l, src, src_l),
-            Self::U32(ids) => ScatterAdd { ids, ids_l, dim }.map(self, l, src, src_l),
-            Self::I64(ids) => ScatterAdd { ids, ids_l, dim }.map(self, l, src, src_l),
+            R::U8(ids) => ScatterAdd { ids, ids_l, dim }.map(self, l, src, src_l),
+            R::U32(ids) => ScatterAdd { ids, ids_l, dim }.map(self, l, src, src_l),
+            R::I64(ids) => ScatterAdd { ids, ids_l, dim }.map(self, l, src, src_l),
             _ => Err(Error::UnsupportedDTypeForOp(self.dtype(), "scatter-add")),
         }
     }
 
-    fn index_add(
-        &self,
+    pub(crate) fn index_add(
+        self,
         l: &Layout,
-        ids: &Self,
+        ids: CpuStorageRef<'_>,
         ids_l: &Layout,
-        src: &Self,
+        src: CpuStorageRef<'_>,
         src_l: &Layout,
         dim: usize,
-    ) -> Result<Self> {
+    ) -> Result<CpuStorage> {
         match ids {
-            Self::U8(ids) => {
+            R::U8(ids) => {
                 let ids = match ids_l.contiguous_offsets() {
                     Some((a, b)) => &ids[a..b],
                     None => Err(Error::RequiresContiguous { op: "index-add" }.bt())?,
                 };
                 IndexAdd { ids, dim }.map(self, l, src, src_l)
             }
-            Self::U32(ids) => {
+            R::U32(ids) => {
                 let ids = match ids_l.contiguous_offsets() {
                     Some((a, b)) => &ids[a..b],
                     None => Err(Error::RequiresContiguous { op: "index-add" }.bt())?,
                 };
                 IndexAdd { ids, dim }.map(self, l, src, src_l)
             }
-            Self::I64(ids) => {
+            R::I64(ids) => {
                 let ids = match ids_l.contiguous_offsets() {
                     Some((a, b)) => &ids[a..b],
                     None => Err(Error::RequiresContiguous { op: "index-add" }.bt())?,
@@ -2639,6 +2807,191 @@ impl BackendStorage for CpuStorage {
         }
     }
 
+    pub(crate) fn matmul(
+        self,
+        rhs: CpuStorageRef<'_>,
+        bmnk: (usize, usize, usize, usize),
+        lhs_l: &Layout,
+        rhs_l: &Layout,
+    ) -> Result<CpuStorage> {
+        MatMul(bmnk).map(self, lhs_l, rhs, rhs_l)
+    }
+}
+
+impl BackendStorage for CpuStorage {
+    type Device = CpuDevice;
+
+    fn dtype(&self) -> DType {
+        match self {
+            Self::U8(_) => DType::U8,
+            Self::U32(_) => DType::U32,
+            Self::I64(_) => DType::I64,
+            Self::BF16(_) => DType::BF16,
+            Self::F16(_) => DType::F16,
+            Self::F32(_) => DType::F32,
+            Self::F64(_) => DType::F64,
+        }
+    }
+
+    fn to_dtype(&self, layout: &Layout, dtype: DType) -> Result<Self> {
+        self.storage_ref().to_dtype(layout, dtype)
+    }
+
+    fn reduce_op(&self, op: ReduceOp, layout: &Layout, reduce_dims: &[usize]) -> Result<Self> {
+        self.storage_ref().reduce_op(op, layout, reduce_dims)
+    }
+
+    fn avg_pool2d(
+        &self,
+        layout: &Layout,
+        kernel_size: (usize, usize),
+        stride: (usize, usize),
+    ) -> Result<Self> {
+        self.storage_ref().avg_pool2d(layout, kernel_size, stride)
+    }
+
+    fn max_pool2d(
+        &self,
+        layout: &Layout,
+        kernel_size: (usize, usize),
+        stride: (usize, usize),
+    ) -> Result<Self> {
+        self.storage_ref().max_pool2d(layout, kernel_size, stride)
+    }
+
+    fn cmp(&self, op: CmpOp, rhs: &Self, lhs_l: &Layout, rhs_l: &Layout) -> Result<Self> {
+        self.storage_ref().cmp(op, rhs.storage_ref(), lhs_l, rhs_l)
+    }
+
+    fn affine(&self, layout: &Layout, mul: f64, add: f64) -> Result<Self> {
+        self.storage_ref().affine(layout, mul, add)
+    }
+
+    fn upsample_nearest1d(&self, layout: &Layout, sz: usize) -> Result<Self> {
+        self.storage_ref().upsample_nearest1d(layout, sz)
+    }
+
+    fn upsample_nearest2d(&self, layout: &Layout, h: usize, w: usize) -> Result<Self> {
+        self.storage_ref().upsample_nearest2d(layout, h, w)
+    }
+
+    fn powf(&self, layout: &Layout, e: f64) -> Result<Self> {
+        self.storage_ref().powf(layout, e)
+    }
+
+    fn elu(&self, layout: &Layout, alpha: f64) -> Result<Self> {
+        self.storage_ref().elu(layout, alpha)
+    }
+
+    fn unary_impl<B: UnaryOpT>(&self, layout: &Layout) -> Result<Self> {
+        self.storage_ref().unary_impl::<B>(layout)
+    }
+
+    fn binary_impl<B: BinaryOpT>(
+        &self,
+        rhs: &Self,
+        lhs_l: &Layout,
+        rhs_l: &Layout,
+    ) -> Result<Self> {
+        self.storage_ref()
+            .binary_impl::<B>(rhs.storage_ref(), lhs_l, rhs_l)
+    }
+
+    fn copy_strided_src(&self, dst: &mut Self, dst_offset: usize, src_l: &Layout) -> Result<()> {
+        self.storage_ref().copy_strided_src(dst, dst_offset, src_l)
+    }
+
+    fn where_cond(
+        &self,
+        layout: &Layout,
+        t: &Self,
+        t_l: &Layout,
+        f: &Self,
+        f_l: &Layout,
+    ) -> Result<Self> {
+        self.storage_ref()
+            .where_cond(layout, t.storage_ref(), t_l, f.storage_ref(), f_l)
+    }
+
+    fn conv1d(
+        &self,
+        l: &Layout,
+        kernel: &Self,
+        kernel_l: &Layout,
+        params: &crate::conv::ParamsConv1D,
+    ) -> Result<Self> {
+        self.storage_ref()
+            .conv1d(l, kernel.storage_ref(), kernel_l, params)
+    }
+
+    fn conv_transpose1d(
+        &self,
+        l: &Layout,
+        kernel: &Self,
+        kernel_l: &Layout,
+        params: &crate::conv::ParamsConvTranspose1D,
+    ) -> Result<Self> {
+        self.storage_ref()
+            .conv_transpose1d(l, kernel.storage_ref(), kernel_l, params)
+    }
+
+    fn conv2d(
+        &self,
+        l: &Layout,
+        kernel: &Self,
+        kernel_l: &Layout,
+        params: &crate::conv::ParamsConv2D,
+    ) -> Result<Self> {
+        self.storage_ref()
+            .conv2d(l, kernel.storage_ref(), kernel_l, params)
+    }
+
+    fn conv_transpose2d(
+        &self,
+        l: &Layout,
+        kernel: &Self,
+        kernel_l: &Layout,
+        params: &crate::conv::ParamsConvTranspose2D,
+    ) -> Result<Self> {
+        self.storage_ref()
+            .conv_transpose2d(l, kernel.storage_ref(), kernel_l, params)
+    }
+
+    fn index_select(&self, ids: &Self, l: &Layout, ids_l: &Layout, dim: usize) -> Result<Self> {
+        self.storage_ref()
+            .index_select(ids.storage_ref(), l, ids_l, dim)
+    }
+
+    fn gather(&self, l: &Layout, ids: &Self, ids_l: &Layout, dim: usize) -> Result<Self> {
+        self.storage_ref().gather(l, ids.storage_ref(), ids_l, dim)
+    }
+
+    fn scatter_add(
+        &self,
+        l: &Layout,
+        ids: &Self,
+        ids_l: &Layout,
+        src: &Self,
+        src_l: &Layout,
+        dim: usize,
+    ) -> Result<Self> {
+        self.storage_ref()
+            .scatter_add(l, ids.storage_ref(), ids_l, src.storage_ref(), src_l, dim)
+    }
+
+    fn index_add(
+        &self,
+        l: &Layout,
+        ids: &Self,
+        ids_l: &Layout,
+        src: &Self,
+        src_l: &Layout,
+        dim: usize,
+    ) -> Result<Self> {
+        self.storage_ref()
+            .index_add(l, ids.storage_ref(), ids_l, src.storage_ref(), src_l, dim)
+    }
+
     fn matmul(
         &self,
         rhs: &Self,
@@ -2646,7 +2999,8 @@ impl BackendStorage for CpuStorage {
         lhs_l: &Layout,
         rhs_l: &Layout,
     ) -> Result<Self> {
-        MatMul(bmnk).map(self, lhs_l, rhs, rhs_l)
+        self.storage_ref()
+            .matmul(rhs.storage_ref(), bmnk, lhs_l, rhs_l)
     }
 
     fn device(&self) -> &Self::Device {
@@ -14,6 +14,9 @@ pub enum DeviceLocation {
 #[derive(Debug, Clone)]
 pub enum Device {
     Cpu,
+    /// A cpu device that runs its parallel kernels on its own thread pool rather than on the
+    /// global rayon pool.
+    CpuPool(crate::cpu::ThreadPool),
     Cuda(crate::CudaDevice),
     Metal(crate::MetalDevice),
 }
@@ -134,9 +137,39 @@ impl Device {
         Ok(Self::Metal(crate::MetalDevice::new(ordinal)?))
     }
 
+    /// Creates a cpu device with its own pool of `num_threads` threads.
+    pub fn new_cpu_pool(num_threads: usize) -> Result<Self> {
+        Ok(Self::CpuPool(crate::cpu::ThreadPool::new(num_threads)?))
+    }
+
+    /// Creates a cpu device with its own pool, using one thread pinned to each of the `core_ids`.
+    pub fn new_cpu_pool_with_affinity(core_ids: &[usize]) -> Result<Self> {
+        Ok(Self::CpuPool(crate::cpu::ThreadPool::with_affinity(
+            core_ids,
+        )?))
+    }
+
+    /// Runs `f` using the thread pool of this device for the cpu kernels, this is a no-op for
+    /// the other devices.
+    pub fn enter<R>(&self, f: impl FnOnce() -> R) -> R {
+        match self {
+            Self::CpuPool(pool) => pool.enter(f),
+            Self::Cpu | Self::Cuda(_) | Self::Metal(_) => f(),
+        }
+    }
+
+    /// Runs `f` on the thread pool of this device. For the other devices, `f` runs on the current
+    /// cpu pool as set by [`Device::enter`] if any.
+    pub fn install<R: Send>(&self, f: impl FnOnce() -> R + Send) -> R {
+        match self {
+            Self::CpuPool(pool) => pool.install(f),
+            Self::Cpu | Self::Cuda(_) | Self::Metal(_) => crate::cpu::thread_pool::install(f),
+        }
+    }
+
     pub fn set_seed(&self, seed: u64) -> Result<()> {
         match self {
-            Self::Cpu => CpuDevice.set_seed(seed),
+            Self::Cpu | Self::CpuPool(_) => CpuDevice.set_seed(seed),
             Self::Cuda(c) => c.set_seed(seed),
             Self::Metal(m) => m.set_seed(seed),
         }
@@ -145,6 +178,7 @@ impl Device {
     pub fn same_device(&self, rhs: &Self) -> bool {
         match (self, rhs) {
             (Self::Cpu, Self::Cpu) => true,
+            (Self::CpuPool(lhs), Self::CpuPool(rhs)) => lhs.same_pool(rhs),
             (Self::Cuda(lhs), Self::Cuda(rhs)) => lhs.same_device(rhs),
             (Self::Metal(lhs), Self::Metal(rhs)) => lhs.same_device(rhs),
             _ => false,
@@ -153,14 +187,14 @@ impl Device {
 
     pub fn location(&self) -> DeviceLocation {
         match self {
-            Self::Cpu => DeviceLocation::Cpu,
+            Self::Cpu | Self::CpuPool(_) => DeviceLocation::Cpu,
             Self::Cuda(device) => device.location(),
             Device::Metal(device) => device.location(),
         }
     }
 
     pub fn is_cpu(&self) -> bool {
-        matches!(self, Self::Cpu)
+        matches!(self, Self::Cpu | Self::CpuPool(_))
     }
 
     pub fn is_cuda(&self) -> bool {
@@ -187,7 +221,7 @@ impl Device {
         dtype: DType,
     ) -> Result<Storage> {
         match self {
-            Device::Cpu => {
+            Device::Cpu | Device::CpuPool(_) => {
                 let storage = CpuDevice.rand_uniform(shape, dtype, lo, up)?;
                 Ok(Storage::Cpu(storage))
             }
@@ -225,7 +259,7 @@ impl Device {
         dtype: DType,
     ) -> Result<Storage> {
         match self {
-            Device::Cpu => {
+            Device::Cpu | Device::CpuPool(_) => {
                 let storage = CpuDevice.rand_normal(shape, dtype, mean, std)?;
                 Ok(Storage::Cpu(storage))
             }
@@ -257,7 +291,7 @@ impl Device {
 
     pub(crate) fn ones(&self, shape: &Shape, dtype: DType) -> Result<Storage> {
         match self {
-            Device::Cpu => {
+            Device::Cpu | Device::CpuPool(_) => {
                 let storage = CpuDevice.ones_impl(shape, dtype)?;
                 Ok(Storage::Cpu(storage))
             }
@@ -274,7 +308,7 @@ impl Device {
 
     pub(crate) fn zeros(&self, shape: &Shape, dtype: DType) -> Result<Storage> {
         match self {
-            Device::Cpu => {
+            Device::Cpu | Device::CpuPool(_) => {
                 let storage = CpuDevice.zeros_impl(shape, dtype)?;
                 Ok(Storage::Cpu(storage))
             }
@@ -291,7 +325,7 @@ impl Device {
 
     pub(crate) fn storage<A: NdArray>(&self, array: A) -> Result<Storage> {
         match self {
-            Device::Cpu => Ok(Storage::Cpu(array.to_cpu_storage())),
+            Device::Cpu | Device::CpuPool(_) => Ok(Storage::Cpu(array.to_cpu_storage())),
             Device::Cuda(device) => {
                 let storage = array.to_cpu_storage();
                 let storage = device.storage_from_cpu_storage(&storage)?;
@@ -307,7 +341,7 @@ impl Device {
 
     pub(crate) fn storage_owned<S: WithDType>(&self, data: Vec<S>) -> Result<Storage> {
         match self {
-            Device::Cpu => Ok(Storage::Cpu(S::to_cpu_storage_owned(data))),
+            Device::Cpu | Device::CpuPool(_) => Ok(Storage::Cpu(S::to_cpu_storage_owned(data))),
             Device::Cuda(device) => {
                 let storage = S::to_cpu_storage_owned(data);
                 let storage = device.storage_from_cpu_storage(&storage)?;
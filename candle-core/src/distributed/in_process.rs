@@ -0,0 +1,86 @@
+use super::Communicator;
+use crate::Result;
+use std::sync::{Arc, Condvar, Mutex};
+
+struct State {
+    // Incremented each time all the ranks have provided their buffers.
+    round: u64,
+    slots: Vec<Option<Vec<u8>>>,
+    n_pending: usize,
+    last: Arc<Vec<Vec<u8>>>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    cvar: Condvar,
+}
+
+/// A communicator for ranks running as threads of the same process.
+#[derive(Clone)]
+pub struct InProcessCommunicator {
+    rank: usize,
+    world_size: usize,
+    shared: Arc<Shared>,
+}
+
+impl InProcessCommunicator {
+    /// Creates the communicators for all the ranks of a group, the i-th element being used by
+    /// rank i.
+    pub fn new_group(world_size: usize) -> Result<Vec<Self>> {
+        if world_size == 0 {
+            crate::bail!("the world size has to be positive")
+        }
+        let state = State {
+            round: 0,
+            slots: vec![None; world_size],
+            n_pending: world_size,
+            last: Arc::new(vec![]),
+        };
+        let shared = Arc::new(Shared {
+            state: Mutex::new(state),
+            cvar: Condvar::new(),
+        });
+        let comms = (0..world_size)
+            .map(|rank| Self {
+                rank,
+                world_size,
+                shared: shared.clone(),
+            })
+            .collect();
+        Ok(comms)
+    }
+}
+
+impl Communicator for InProcessCommunicator {
+    fn rank(&self) -> usize {
+        self.rank
+    }
+
+    fn world_size(&self) -> usize {
+        self.world_size
+    }
+
+    fn all_gather_bytes(&self, data: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.slots[self.rank].is_some() {
+            crate::bail!("rank {} is already taking part in a collective", self.rank)
+        }
+        state.slots[self.rank] = Some(data.to_vec());
+        state.n_pending -= 1;
+        if state.n_pending == 0 {
+            let slots = state.slots.iter_mut().map(|s| s.take().unwrap_or_default());
+            state.last = Arc::new(slots.collect());
+            state.n_pending = self.world_size;
+            state.round += 1;
+            self.shared.cvar.notify_all();
+        } else {
+            let round = state.round;
+            while state.round == round {
+                state = self.shared.cvar.wait(state).unwrap();
+            }
+        }
+        // The buffers of the next round are only published once all the ranks have taken part
+        // in it, including this one, so `last` still holds the buffers for this round.
+        Ok(state.last.as_ref().clone())
+    }
+}
@@ -0,0 +1,158 @@
+//! Collective communications between the ranks of a process group, e.g. for tensor parallelism.
+//!
+//! A [`Communicator`] only has to be able to exchange byte buffers between all the ranks, the
+//! tensor collectives are implemented on top of this exchange. The following implementations
+//! are available:
+//! - [`InProcessCommunicator`], the ranks are threads of the same process.
+//! - [`SharedMemoryCommunicator`], the ranks are processes on the same machine sharing a memory
+//!   mapped file.
+//! - [`TcpCommunicator`], the ranks are processes connected with tcp sockets, rank 0 acting as
+//!   the hub.
+//!
+//! All the ranks have to call the collective operations in the same order.
+use crate::{DType, Device, Result, Tensor};
+
+mod in_process;
+mod shm;
+mod tcp;
+
+pub use in_process::InProcessCommunicator;
+pub use shm::SharedMemoryCommunicator;
+pub use tcp::TcpCommunicator;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReduceOp {
+    Sum,
+    Min,
+    Max,
+}
+
+pub trait Communicator: Send + Sync {
+    fn rank(&self) -> usize;
+
+    fn world_size(&self) -> usize;
+
+    /// Sends `data` to all the ranks and returns the buffers sent by each rank, ordered by rank.
+    fn all_gather_bytes(&self, data: &[u8]) -> Result<Vec<Vec<u8>>>;
+
+    /// Blocks until all the ranks have reached this point.
+    fn barrier(&self) -> Result<()> {
+        self.all_gather_bytes(&[])?;
+        Ok(())
+    }
+
+    /// Returns the tensors from all the ranks, ordered by rank. The tensors can have different
+    /// shapes but must have the same dtype.
+    fn all_gather_tensors(&self, xs: &Tensor) -> Result<Vec<Tensor>> {
+        let data = encode(xs)?;
+        let data = self.all_gather_bytes(&data)?;
+        data.iter()
+            .map(|data| decode(data, xs.device()))
+            .collect::<Result<Vec<_>>>()
+    }
+
+    /// Reduces `xs` element-wise over all the ranks, all the ranks get the result.
+    fn all_reduce(&self, xs: &Tensor, op: ReduceOp) -> Result<Tensor> {
+        if self.world_size() == 1 {
+            return Ok(xs.clone());
+        }
+        let xs = self.all_gather_tensors(xs)?;
+        reduce(&xs, op)
+    }
+
+    /// Concatenates the tensors from all the ranks along `dim`, in rank order.
+    fn all_gather(&self, xs: &Tensor, dim: usize) -> Result<Tensor> {
+        if self.world_size() == 1 {
+            return Ok(xs.clone());
+        }
+        let xs = self.all_gather_tensors(xs)?;
+        Tensor::cat(&xs, dim)
+    }
+
+    /// Returns the tensor from rank `root`, `xs` is only used for its device on the other ranks.
+    fn broadcast(&self, xs: &Tensor, root: usize) -> Result<Tensor> {
+        let world_size = self.world_size();
+        if root >= world_size {
+            crate::bail!("broadcast root {root} is out of range for world size {world_size}")
+        }
+        let data = if self.rank() == root {
+            encode(xs)?
+        } else {
+            vec![]
+        };
+        let data = self.all_gather_bytes(&data)?;
+        decode(&data[root], xs.device())
+    }
+
+    /// Reduces `xs` element-wise over all the ranks, then splits the result in `world_size`
+    /// chunks along `dim`, each rank getting the chunk for its rank.
+    fn reduce_scatter(&self, xs: &Tensor, dim: usize, op: ReduceOp) -> Result<Tensor> {
+        let world_size = self.world_size();
+        let size = xs.dim(dim)?;
+        if size % world_size != 0 {
+            Err(crate::Error::ShapeMismatchSplit {
+                shape: xs.shape().clone(),
+                dim,
+                n_parts: world_size,
+            }
+            .bt())?
+        }
+        let chunk_size = size / world_size;
+        self.all_reduce(xs, op)?
+            .narrow(dim, self.rank() * chunk_size, chunk_size)
+    }
+}
+
+fn reduce(xs: &[Tensor], op: ReduceOp) -> Result<Tensor> {
+    let xs = Tensor::stack(xs, 0)?;
+    match op {
+        ReduceOp::Sum => xs.sum(0),
+        ReduceOp::Min => xs.min(0),
+        ReduceOp::Max => xs.max(0),
+    }
+}
+
+// The tensors are serialized as the dtype name, the dimensions, and the raw data.
+fn encode(xs: &Tensor) -> Result<Vec<u8>> {
+    let dtype = xs.dtype().as_str().as_bytes();
+    let data = crate::safetensors::convert_back(xs)?;
+    let mut buf = Vec::with_capacity(1 + dtype.len() + 4 + 8 * xs.rank() + data.len());
+    buf.push(dtype.len() as u8);
+    buf.extend_from_slice(dtype);
+    buf.extend_from_slice(&(xs.rank() as u32).to_le_bytes());
+    for &dim in xs.dims() {
+        buf.extend_from_slice(&(dim as u64).to_le_bytes());
+    }
+    buf.extend_from_slice(&data);
+    Ok(buf)
+}
+
+fn decode(buf: &[u8], device: &Device) -> Result<Tensor> {
+    fn take<'a>(buf: &mut &'a [u8], n: usize) -> Result<&'a [u8]> {
+        if buf.len() < n {
+            crate::bail!("truncated tensor buffer")
+        }
+        let (head, tail) = buf.split_at(n);
+        *buf = tail;
+        Ok(head)
+    }
+    let mut buf = buf;
+    let dtype_len = take(&mut buf, 1)?[0] as usize;
+    let dtype = String::from_utf8_lossy(take(&mut buf, dtype_len)?);
+    let dtype: DType = match dtype.parse() {
+        Ok(dtype) => dtype,
+        Err(_) => crate::bail!("unexpected dtype {dtype} in tensor buffer"),
+    };
+    let rank = u32::from_le_bytes(take(&mut buf, 4)?.try_into().unwrap()) as usize;
+    let dims = (0..rank)
+        .map(|_| Ok(u64::from_le_bytes(take(&mut buf, 8)?.try_into().unwrap()) as usize))
+        .collect::<Result<Vec<_>>>()?;
+    let elem_count = dims.iter().product::<usize>();
+    if buf.len() != elem_count * dtype.size_in_bytes() {
+        crate::bail!(
+            "unexpected tensor buffer size {} for {dtype:?} {dims:?}",
+            buf.len()
+        )
+    }
+    Tensor::from_raw_buffer(buf, dtype, &dims, device)
+}
@@ -0,0 +1,204 @@
+use super::Communicator;
+use crate::Result;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const MAGIC: u64 = 0x6d73_656c_646e_6163; // "candlesm"
+const HEADER_SIZE: usize = 64;
+
+/// A communicator for processes running on the same machine.
+///
+/// The processes share a memory mapped file made of a header, two counters per rank, and one
+/// buffer of `capacity` bytes per rank. For each collective, a rank writes its data to its buffer
+/// and bumps its `written` counter, then it waits for the `written` counters of all the ranks,
+/// copies all the buffers, and bumps its `read` counter. A rank only overwrites its buffer once
+/// all the ranks have read the previous round. The file is removed once all the ranks have mapped
+/// it, so a path should only be used for a single group.
+pub struct SharedMemoryCommunicator {
+    rank: usize,
+    world_size: usize,
+    capacity: usize,
+    timeout: Duration,
+    // The mapping is only used through this pointer, taken when creating the communicator.
+    ptr: *mut u8,
+    _mmap: memmap2::MmapMut,
+    round: Mutex<u64>,
+}
+
+// Safety: the mapped memory is only accessed through atomics or following the protocol above,
+// and the calls from multiple threads of the same rank are serialized with the `round` mutex.
+unsafe impl Send for SharedMemoryCommunicator {}
+unsafe impl Sync for SharedMemoryCommunicator {}
+
+impl SharedMemoryCommunicator {
+    /// Connects to the group using the file at `path`, the file is created by rank 0 and must not
+    /// exist beforehand. Each collective can transfer up to `capacity` bytes per rank.
+    pub fn new<P: AsRef<std::path::Path>>(
+        path: P,
+        rank: usize,
+        world_size: usize,
+        capacity: usize,
+    ) -> Result<Self> {
+        Self::with_timeout(path, rank, world_size, capacity, Duration::from_secs(60))
+    }
+
+    /// Same as [`SharedMemoryCommunicator::new`], `timeout` is the maximum time spent waiting
+    /// for the other ranks.
+    pub fn with_timeout<P: AsRef<std::path::Path>>(
+        path: P,
+        rank: usize,
+        world_size: usize,
+        capacity: usize,
+        timeout: Duration,
+    ) -> Result<Self> {
+        if rank >= world_size {
+            crate::bail!("rank {rank} is out of range for world size {world_size}")
+        }
+        let path = path.as_ref();
+        let capacity = capacity.div_ceil(8) * 8;
+        let file_size = Self::slot_offset(world_size, capacity, world_size);
+        let file = if rank == 0 {
+            // The file is initialized under a temporary name so that the other ranks never see
+            // it partially written.
+            let mut tmp_path = path.as_os_str().to_owned();
+            tmp_path.push(".tmp");
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&tmp_path)?;
+            file.set_len(file_size as u64)?;
+            let mut header = Vec::with_capacity(24);
+            header.extend_from_slice(&MAGIC.to_le_bytes());
+            header.extend_from_slice(&(world_size as u64).to_le_bytes());
+            header.extend_from_slice(&(capacity as u64).to_le_bytes());
+            std::io::Write::write_all(&mut &file, &header)?;
+            std::fs::rename(&tmp_path, path)?;
+            file
+        } else {
+            let start = Instant::now();
+            loop {
+                match std::fs::OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(path)
+                {
+                    Ok(file) => break file,
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                        if start.elapsed() > timeout {
+                            crate::bail!("timeout waiting for rank 0 to create {path:?}")
+                        }
+                        std::thread::sleep(Duration::from_millis(10))
+                    }
+                    Err(err) => Err(err)?,
+                }
+            }
+        };
+        let mut mmap = unsafe { memmap2::MmapMut::map_mut(&file)? };
+        let header = |i: usize| u64::from_le_bytes(mmap[8 * i..8 * i + 8].try_into().unwrap());
+        if mmap.len() != file_size
+            || header(0) != MAGIC
+            || header(1) != world_size as u64
+            || header(2) != capacity as u64
+        {
+            crate::bail!("{path:?} was not created for world size {world_size} and this capacity")
+        }
+        let comm = Self {
+            rank,
+            world_size,
+            capacity,
+            timeout,
+            ptr: mmap.as_mut_ptr(),
+            _mmap: mmap,
+            round: Mutex::new(0),
+        };
+        // Once all the ranks have mapped the file, it is not needed on the filesystem anymore.
+        comm.barrier()?;
+        if rank == 0 {
+            std::fs::remove_file(path)?;
+        }
+        Ok(comm)
+    }
+
+    fn slot_offset(world_size: usize, capacity: usize, rank: usize) -> usize {
+        let counters_size = (16 * world_size).div_ceil(64) * 64;
+        HEADER_SIZE + counters_size + rank * (8 + capacity)
+    }
+
+    fn counter(&self, rank: usize, read: bool) -> &AtomicU64 {
+        let offset = HEADER_SIZE + 16 * rank + if read { 8 } else { 0 };
+        // Safety: the mapping is page aligned and the offset is a multiple of 8.
+        unsafe { &*(self.ptr.add(offset) as *const AtomicU64) }
+    }
+
+    fn wait_all(&self, read: bool, round: u64) -> Result<()> {
+        let start = Instant::now();
+        for rank in 0..self.world_size {
+            let counter = self.counter(rank, read);
+            let mut n_spins = 0;
+            while counter.load(Ordering::Acquire) < round {
+                if n_spins < 1000 {
+                    n_spins += 1;
+                    std::hint::spin_loop()
+                } else if start.elapsed() > self.timeout {
+                    crate::bail!("timeout waiting for rank {rank} in shared memory collective")
+                } else {
+                    std::thread::sleep(Duration::from_micros(20))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Communicator for SharedMemoryCommunicator {
+    fn rank(&self) -> usize {
+        self.rank
+    }
+
+    fn world_size(&self) -> usize {
+        self.world_size
+    }
+
+    fn all_gather_bytes(&self, data: &[u8]) -> Result<Vec<Vec<u8>>> {
+        if data.len() > self.capacity {
+            crate::bail!(
+                "{} bytes exceed the shared memory capacity {}",
+                data.len(),
+                self.capacity
+            )
+        }
+        let mut round = self.round.lock().unwrap();
+        *round += 1;
+        let round = *round;
+        let ptr = self.ptr;
+        // Wait for all the ranks to have read the previous round before overwriting the buffer.
+        self.wait_all(true, round - 1)?;
+        let offset = Self::slot_offset(self.world_size, self.capacity, self.rank);
+        unsafe {
+            let len = (data.len() as u64).to_le_bytes();
+            std::ptr::copy_nonoverlapping(len.as_ptr(), ptr.add(offset), 8);
+            std::ptr::copy_nonoverlapping(data.as_ptr(), ptr.add(offset + 8), data.len());
+        }
+        self.counter(self.rank, false)
+            .store(round, Ordering::Release);
+        self.wait_all(false, round)?;
+        let mut res = Vec::with_capacity(self.world_size);
+        for rank in 0..self.world_size {
+            let offset = Self::slot_offset(self.world_size, self.capacity, rank);
+            let mut len = [0u8; 8];
+            unsafe { std::ptr::copy_nonoverlapping(ptr.add(offset), len.as_mut_ptr(), 8) };
+            let len = u64::from_le_bytes(len) as usize;
+            if len > self.capacity {
+                crate::bail!("unexpected length {len} in shared memory buffer of rank {rank}")
+            }
+            let mut buf = vec![0u8; len];
+            unsafe { std::ptr::copy_nonoverlapping(ptr.add(offset + 8), buf.as_mut_ptr(), len) };
+            res.push(buf)
+        }
+        self.counter(self.rank, true)
+            .store(round, Ordering::Release);
+        Ok(res)
+    }
+}
@@ -0,0 +1,134 @@
+use super::Communicator;
+use crate::Result;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A communicator for processes connected with tcp sockets.
+///
+/// Rank 0 listens on the given address and all the other ranks connect to it. For each
+/// collective, the other ranks send their data to rank 0 which sends back the data from all the
+/// ranks, so this is mostly suitable for small world sizes.
+pub struct TcpCommunicator {
+    rank: usize,
+    world_size: usize,
+    // For rank 0, the streams to the ranks 1 to world_size - 1. For the other ranks, the stream
+    // to rank 0.
+    streams: Mutex<Vec<TcpStream>>,
+}
+
+impl TcpCommunicator {
+    /// Connects to the group, rank 0 binds to `addr` and the other ranks connect to it.
+    pub fn new<A: ToSocketAddrs>(addr: A, rank: usize, world_size: usize) -> Result<Self> {
+        Self::with_timeout(addr, rank, world_size, Duration::from_secs(60))
+    }
+
+    /// Same as [`TcpCommunicator::new`], `timeout` is the maximum time spent waiting for rank 0
+    /// to accept connections.
+    pub fn with_timeout<A: ToSocketAddrs>(
+        addr: A,
+        rank: usize,
+        world_size: usize,
+        timeout: Duration,
+    ) -> Result<Self> {
+        if rank >= world_size {
+            crate::bail!("rank {rank} is out of range for world size {world_size}")
+        }
+        if rank == 0 {
+            Self::root(TcpListener::bind(addr)?, world_size)
+        } else {
+            let start = Instant::now();
+            let stream = loop {
+                match TcpStream::connect(&addr) {
+                    Ok(stream) => break stream,
+                    Err(err) => {
+                        if start.elapsed() > timeout {
+                            crate::bail!("timeout connecting to rank 0: {err}")
+                        }
+                        std::thread::sleep(Duration::from_millis(10))
+                    }
+                }
+            };
+            Self::peer(stream, rank, world_size)
+        }
+    }
+
+    /// Creates the communicator for rank 0 from a listener, e.g. bound to port 0 so that the
+    /// address can be sent to the other ranks once known.
+    pub fn root(listener: TcpListener, world_size: usize) -> Result<Self> {
+        let mut streams: Vec<Option<TcpStream>> = (1..world_size).map(|_| None).collect();
+        for _ in 1..world_size {
+            let (mut stream, _) = listener.accept()?;
+            stream.set_nodelay(true)?;
+            let mut rank = [0u8; 8];
+            stream.read_exact(&mut rank)?;
+            let rank = u64::from_le_bytes(rank) as usize;
+            match streams.get_mut(rank.wrapping_sub(1)) {
+                Some(slot @ None) => *slot = Some(stream),
+                _ => crate::bail!("unexpected connection from rank {rank}"),
+            }
+        }
+        let streams = streams.into_iter().map(|s| s.unwrap()).collect();
+        Ok(Self {
+            rank: 0,
+            world_size,
+            streams: Mutex::new(streams),
+        })
+    }
+
+    fn peer(mut stream: TcpStream, rank: usize, world_size: usize) -> Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.write_all(&(rank as u64).to_le_bytes())?;
+        Ok(Self {
+            rank,
+            world_size,
+            streams: Mutex::new(vec![stream]),
+        })
+    }
+}
+
+fn write_frame(stream: &mut TcpStream, data: &[u8]) -> Result<()> {
+    stream.write_all(&(data.len() as u64).to_le_bytes())?;
+    stream.write_all(data)?;
+    Ok(())
+}
+
+fn read_frame(stream: &mut TcpStream) -> Result<Vec<u8>> {
+    let mut len = [0u8; 8];
+    stream.read_exact(&mut len)?;
+    let mut data = vec![0u8; u64::from_le_bytes(len) as usize];
+    stream.read_exact(&mut data)?;
+    Ok(data)
+}
+
+impl Communicator for TcpCommunicator {
+    fn rank(&self) -> usize {
+        self.rank
+    }
+
+    fn world_size(&self) -> usize {
+        self.world_size
+    }
+
+    fn all_gather_bytes(&self, data: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let mut streams = self.streams.lock().unwrap();
+        if self.rank == 0 {
+            let mut res = Vec::with_capacity(self.world_size);
+            res.push(data.to_vec());
+            for stream in streams.iter_mut() {
+                res.push(read_frame(stream)?)
+            }
+            for stream in streams.iter_mut() {
+                for data in res.iter() {
+                    write_frame(stream, data)?
+                }
+            }
+            Ok(res)
+        } else {
+            let stream = &mut streams[0];
+            write_frame(stream, data)?;
+            (0..self.world_size).map(|_| read_frame(stream)).collect()
+        }
+    }
+}
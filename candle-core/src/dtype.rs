@@ -1,6 +1,7 @@
 //! Types for elements that can be stored and manipulated using tensors.
 #![allow(clippy::redundant_closure_call)]
 use crate::backend::BackendStorage;
+use crate::cpu_backend::CpuStorageRef;
 use crate::{CpuStorage, Error, Result};
 
 /// The different types of elements allowed in tensors.
@@ -105,6 +106,7 @@ pub trait WithDType:
     }
 
     fn cpu_storage_as_slice(s: &CpuStorage) -> Result<&[Self]>;
+    fn cpu_storage_ref_as_slice(s: CpuStorageRef<'_>) -> Result<&[Self]>;
     fn cpu_storage_data(s: CpuStorage) -> Result<Vec<Self>>;
 }
 
@@ -148,6 +150,18 @@ macro_rules! with_dtype {
                     .bt()),
                 }
             }
+
+            fn cpu_storage_ref_as_slice(s: CpuStorageRef<'_>) -> Result<&[Self]> {
+                match s {
+                    CpuStorageRef::$dtype(data) => Ok(data),
+                    _ => Err(Error::UnexpectedDType {
+                        expected: DType::$dtype,
+                        got: s.dtype(),
+                        msg: "unexpected dtype",
+                    }
+                    .bt()),
+                }
+            }
         }
     };
 }
@@ -0,0 +1,406 @@
+//! Einstein summation and tensor contractions.
+//!
+//! The contractions are lowered to `permute`, `reshape`, `sum` and `broadcast_matmul` so the
+//! usual backward pass applies.
+use crate::shape::Dims;
+use crate::{bail, Error, Result, Tensor};
+use std::collections::HashMap;
+
+// The labels for the dimensions covered by an ellipsis are numbered from the left, they are
+// right-aligned between operands so that they broadcast in the same way as for binary ops. These
+// labels are ordered before the character ones so that the implicit output has them first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Label {
+    Ellipsis(usize),
+    Char(char),
+}
+
+// A term of the equation, the position of the ellipsis within the characters if any.
+#[derive(Debug)]
+struct Term {
+    chars: Vec<char>,
+    ellipsis: Option<usize>,
+}
+
+impl Term {
+    fn parse(term: &str, equation: &str) -> Result<Self> {
+        let mut chars = vec![];
+        let mut ellipsis = None;
+        let mut rest = term;
+        while let Some(c) = rest.chars().next() {
+            // A dot is only valid as part of an ellipsis.
+            if let Some(after) = rest.strip_prefix("...") {
+                if ellipsis.is_some() {
+                    bail!("einsum: more than one ellipsis in {term:?} in {equation:?}")
+                }
+                ellipsis = Some(chars.len());
+                rest = after;
+                continue;
+            }
+            match c {
+                c if c.is_ascii_alphabetic() => chars.push(c),
+                c if c.is_whitespace() => {}
+                c => bail!("einsum: unexpected character {c:?} in {equation:?}"),
+            }
+            rest = &rest[c.len_utf8()..];
+        }
+        Ok(Self { chars, ellipsis })
+    }
+
+    // The number of dimensions covered by the ellipsis for an operand of rank `rank`.
+    fn ellipsis_rank(&self, rank: usize, equation: &str) -> Result<usize> {
+        match self.ellipsis {
+            Some(_) if self.chars.len() <= rank => Ok(rank - self.chars.len()),
+            None if self.chars.len() == rank => Ok(0),
+            _ => bail!(
+                "einsum: {:?} does not match an operand of rank {rank} in {equation:?}",
+                self.chars.iter().collect::<String>()
+            ),
+        }
+    }
+
+    fn labels(&self, ellipsis_rank: usize, max_ellipsis_rank: usize) -> Vec<Label> {
+        let chars = self.chars.iter().map(|&c| Label::Char(c));
+        match self.ellipsis {
+            None => chars.collect(),
+            Some(pos) => {
+                let ellipsis = (0..ellipsis_rank)
+                    .map(|i| Label::Ellipsis(max_ellipsis_rank - ellipsis_rank + i));
+                let mut chars = chars;
+                let mut labels: Vec<_> = chars.by_ref().take(pos).collect();
+                labels.extend(ellipsis);
+                labels.extend(chars);
+                labels
+            }
+        }
+    }
+}
+
+fn position(labels: &[Label], label: Label) -> usize {
+    // The labels are always looked up in a list that contains them.
+    labels.iter().position(|&l| l == label).unwrap()
+}
+
+// Takes the diagonal over the dimensions that share the same label, e.g. for `ii->i`.
+fn diagonal(t: Tensor, labels: Vec<Label>) -> Result<(Tensor, Vec<Label>)> {
+    let t_stride = t.shape().stride_contiguous();
+    let mut new_labels: Vec<Label> = vec![];
+    let mut dims = vec![];
+    let mut stride = vec![];
+    for (i, &label) in labels.iter().enumerate() {
+        match new_labels.iter().position(|&l| l == label) {
+            Some(j) => {
+                if dims[j] != t.dims()[i] {
+                    bail!(
+                        "einsum: repeated label {label:?} with sizes {} and {}",
+                        dims[j],
+                        t.dims()[i]
+                    )
+                }
+                stride[j] += t_stride[i]
+            }
+            None => {
+                new_labels.push(label);
+                dims.push(t.dims()[i]);
+                stride.push(t_stride[i])
+            }
+        }
+    }
+    if new_labels.len() == labels.len() {
+        return Ok((t, labels));
+    }
+    Ok((t.as_strided(dims, stride, 0)?, new_labels))
+}
+
+// Sums over the dimensions for which `keep` returns false.
+fn sum_labels(
+    t: Tensor,
+    labels: Vec<Label>,
+    keep: impl Fn(Label) -> bool,
+) -> Result<(Tensor, Vec<Label>)> {
+    let sum_dims = (0..labels.len())
+        .filter(|&i| !keep(labels[i]))
+        .collect::<Vec<_>>();
+    if sum_dims.is_empty() {
+        return Ok((t, labels));
+    }
+    let labels = labels.into_iter().filter(|&l| keep(l)).collect();
+    Ok((t.sum(sum_dims)?, labels))
+}
+
+// Contracts two operands, the labels that are in `keep` are preserved and the other shared ones
+// are summed over. The result dimensions are the batch ones, then the ones only in `lhs`, then the
+// ones only in `rhs`.
+fn contract(
+    (lhs, l_labels): (Tensor, Vec<Label>),
+    (rhs, r_labels): (Tensor, Vec<Label>),
+    keep: &[Label],
+) -> Result<(Tensor, Vec<Label>)> {
+    let (lhs, l_labels) = sum_labels(lhs, l_labels, |l| {
+        keep.contains(&l) || r_labels.contains(&l)
+    })?;
+    let (rhs, r_labels) = sum_labels(rhs, r_labels, |l| {
+        keep.contains(&l) || l_labels.contains(&l)
+    })?;
+    let in_rhs = |l: &&Label| r_labels.contains(l);
+    let batch: Vec<Label> = l_labels
+        .iter()
+        .filter(in_rhs)
+        .filter(|l| keep.contains(l))
+        .copied()
+        .collect();
+    let summed: Vec<Label> = l_labels
+        .iter()
+        .filter(in_rhs)
+        .filter(|l| !keep.contains(l))
+        .copied()
+        .collect();
+    let left: Vec<Label> = l_labels
+        .iter()
+        .filter(|l| !r_labels.contains(l))
+        .copied()
+        .collect();
+    let right: Vec<Label> = r_labels
+        .iter()
+        .filter(|l| !l_labels.contains(l))
+        .copied()
+        .collect();
+
+    let l_dims = |labels: &[Label]| {
+        labels
+            .iter()
+            .map(|&l| lhs.dims()[position(&l_labels, l)])
+            .collect::<Vec<_>>()
+    };
+    let r_dims = |labels: &[Label]| {
+        labels
+            .iter()
+            .map(|&l| rhs.dims()[position(&r_labels, l)])
+            .collect::<Vec<_>>()
+    };
+    // The summed dimensions can be broadcast, the batch ones are handled by broadcast_matmul.
+    let k_dims = l_dims(&summed)
+        .iter()
+        .zip(r_dims(&summed))
+        .map(|(&l, r)| usize::max(l, r))
+        .collect::<Vec<_>>();
+    let k = k_dims.iter().product::<usize>();
+    let (l_batch, left_dims, right_dims) = (l_dims(&batch), l_dims(&left), r_dims(&right));
+    let r_batch = r_dims(&batch);
+
+    let perm = [&batch[..], &left, &summed]
+        .concat()
+        .iter()
+        .map(|&l| position(&l_labels, l))
+        .collect::<Vec<_>>();
+    let lhs = lhs
+        .permute(perm)?
+        .broadcast_as([&l_batch[..], &left_dims, &k_dims].concat())?
+        .reshape([&l_batch[..], &[left_dims.iter().product(), k]].concat())?;
+    let perm = [&batch[..], &summed, &right]
+        .concat()
+        .iter()
+        .map(|&l| position(&r_labels, l))
+        .collect::<Vec<_>>();
+    let rhs = rhs
+        .permute(perm)?
+        .broadcast_as([&r_batch[..], &k_dims, &right_dims].concat())?
+        .reshape([&r_batch[..], &[k, right_dims.iter().product()]].concat())?;
+    let res = lhs.broadcast_matmul(&rhs)?;
+    let dims = [&res.dims()[..batch.len()], &left_dims, &right_dims].concat();
+    Ok((res.reshape(dims)?, [batch, left, right].concat()))
+}
+
+impl Tensor {
+    /// Einstein summation over the `operands` following `equation`, e.g. `"bhqd,bhkd->bhqk"`
+    /// for attention scores or `"ii->"` for a trace.
+    ///
+    /// The labels are ascii letters, a label that appears several times in an operand selects the
+    /// diagonal and the labels that do not appear in the output are summed over. An ellipsis
+    /// `...` stands for the dimensions that are not labeled, these broadcast between operands
+    /// like for binary ops. When the `->` part is omitted, the output is made of the ellipsis
+    /// dimensions followed by the labels appearing exactly once in alphabetical order.
+    ///
+    /// The contractions are done one pair of operands at a time, picking the pair with the
+    /// smallest result first, and use batched matrix multiplications.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let a = Tensor::arange(0f32, 6., &Device::Cpu)?.reshape((2, 3))?;
+    /// let b = Tensor::arange(0f32, 3., &Device::Cpu)?;
+    /// let c = Tensor::einsum("ij,j->i", &[&a, &b])?;
+    /// assert_eq!(c.to_vec1::<f32>()?, &[5., 14.]);
+    /// let c = Tensor::einsum("ij->ji", &[&a])?;
+    /// assert_eq!(c.to_vec2::<f32>()?, &[[0., 3.], [1., 4.], [2., 5.]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn einsum(equation: &str, operands: &[&Tensor]) -> Result<Tensor> {
+        let (inputs, output) = match equation.split_once("->") {
+            None => (equation, None),
+            Some((inputs, output)) => (inputs, Some(Term::parse(output, equation)?)),
+        };
+        let inputs = inputs
+            .split(',')
+            .map(|term| Term::parse(term, equation))
+            .collect::<Result<Vec<_>>>()?;
+        if inputs.len() != operands.len() {
+            bail!(
+                "einsum: {equation:?} expects {} operands, got {}",
+                inputs.len(),
+                operands.len()
+            )
+        }
+        let ellipsis_ranks = inputs
+            .iter()
+            .zip(operands.iter())
+            .map(|(term, t)| term.ellipsis_rank(t.rank(), equation))
+            .collect::<Result<Vec<_>>>()?;
+        let max_ellipsis_rank = ellipsis_ranks.iter().copied().max().unwrap_or(0);
+        let mut operands = inputs
+            .iter()
+            .zip(ellipsis_ranks)
+            .zip(operands.iter())
+            .map(|((term, rank), &t)| diagonal(t.clone(), term.labels(rank, max_ellipsis_rank)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut sizes = HashMap::new();
+        for (t, labels) in operands.iter() {
+            for (&label, &size) in labels.iter().zip(t.dims()) {
+                let prev = sizes.entry(label).or_insert(size);
+                if *prev != size && *prev != 1 && size != 1 {
+                    bail!(
+                        "einsum: incompatible sizes {} and {size} for {label:?}",
+                        *prev
+                    )
+                }
+                *prev = usize::max(*prev, size)
+            }
+        }
+        let output = match output {
+            Some(output) => {
+                if output.ellipsis.is_none() && max_ellipsis_rank > 0 {
+                    bail!("einsum: {equation:?} has no ellipsis in its output")
+                }
+                let labels = output.labels(max_ellipsis_rank, max_ellipsis_rank);
+                for (i, label) in labels.iter().enumerate() {
+                    if !sizes.contains_key(label) || labels[..i].contains(label) {
+                        bail!("einsum: invalid output label {label:?} in {equation:?}")
+                    }
+                }
+                labels
+            }
+            None => {
+                let mut counts = HashMap::new();
+                for label in inputs.iter().flat_map(|term| term.chars.iter()) {
+                    *counts.entry(Label::Char(*label)).or_insert(0) += 1
+                }
+                let mut labels = (0..max_ellipsis_rank)
+                    .map(Label::Ellipsis)
+                    .chain(counts.into_iter().filter(|(_, c)| *c == 1).map(|(l, _)| l))
+                    .collect::<Vec<_>>();
+                labels.sort();
+                labels
+            }
+        };
+
+        while operands.len() > 1 {
+            // Greedily contract the pair of operands with the smallest result.
+            let keep = |i: usize, j: usize| {
+                let mut keep = output.clone();
+                for (k, (_, labels)) in operands.iter().enumerate() {
+                    if k != i && k != j {
+                        keep.extend(labels)
+                    }
+                }
+                keep
+            };
+            let mut best = (usize::MAX, 0, 1);
+            for i in 0..operands.len() {
+                for j in i + 1..operands.len() {
+                    let keep = keep(i, j);
+                    let mut labels = operands[i].1.clone();
+                    labels.extend(&operands[j].1);
+                    labels.sort();
+                    labels.dedup();
+                    let size = labels
+                        .iter()
+                        .filter(|l| keep.contains(l))
+                        .map(|l| sizes[l])
+                        .product::<usize>();
+                    if size < best.0 {
+                        best = (size, i, j)
+                    }
+                }
+            }
+            let (_, i, j) = best;
+            let keep = keep(i, j);
+            let rhs = operands.remove(j);
+            let lhs = operands.remove(i);
+            operands.push(contract(lhs, rhs, &keep)?);
+        }
+        // There is at least one operand as the equation always has one term.
+        let (t, labels) = operands.remove(0);
+        let (t, labels) = sum_labels(t, labels, |l| output.contains(&l))?;
+        if labels.is_empty() {
+            return Ok(t);
+        }
+        let perm = output
+            .iter()
+            .map(|&l| position(&labels, l))
+            .collect::<Vec<_>>();
+        t.permute(perm)
+    }
+
+    /// Contracts the dimensions `lhs_dims` of this tensor with the dimensions `rhs_dims` of
+    /// `rhs`, the result has the remaining dimensions of this tensor followed by the remaining
+    /// dimensions of `rhs`.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let a = Tensor::ones((2, 3, 4), candle_core::DType::F32, &Device::Cpu)?;
+    /// let b = Tensor::ones((4, 3, 5), candle_core::DType::F32, &Device::Cpu)?;
+    /// let c = a.tensordot(&b, (1, 2), (1, 0))?;
+    /// assert_eq!(c.dims(), &[2, 5]);
+    /// assert_eq!(c.sum_all()?.to_scalar::<f32>()?, 120.);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn tensordot<D1: Dims, D2: Dims>(
+        &self,
+        rhs: &Self,
+        lhs_dims: D1,
+        rhs_dims: D2,
+    ) -> Result<Self> {
+        let lhs_dims = lhs_dims.to_indexes(self.shape(), "tensordot")?;
+        let rhs_dims = rhs_dims.to_indexes(rhs.shape(), "tensordot")?;
+        let same_sizes = lhs_dims.len() == rhs_dims.len()
+            && lhs_dims
+                .iter()
+                .zip(rhs_dims.iter())
+                .all(|(&l, &r)| self.dims()[l] == rhs.dims()[r]);
+        if !same_sizes {
+            Err(Error::ShapeMismatchBinaryOp {
+                lhs: self.shape().clone(),
+                rhs: rhs.shape().clone(),
+                op: "tensordot",
+            }
+            .bt())?
+        }
+        let lhs_free = (0..self.rank())
+            .filter(|d| !lhs_dims.contains(d))
+            .collect::<Vec<_>>();
+        let rhs_free = (0..rhs.rank())
+            .filter(|d| !rhs_dims.contains(d))
+            .collect::<Vec<_>>();
+        let k = lhs_dims.iter().map(|&d| self.dims()[d]).product::<usize>();
+        let lhs_free_dims = lhs_free.iter().map(|&d| self.dims()[d]).collect::<Vec<_>>();
+        let rhs_free_dims = rhs_free.iter().map(|&d| rhs.dims()[d]).collect::<Vec<_>>();
+        let lhs = self
+            .permute([lhs_free, lhs_dims].concat())?
+            .reshape((lhs_free_dims.iter().product::<usize>(), k))?;
+        let rhs = rhs
+            .permute([rhs_dims, rhs_free].concat())?
+            .reshape((k, rhs_free_dims.iter().product::<usize>()))?;
+        lhs.matmul(&rhs)?
+            .reshape([lhs_free_dims, rhs_free_dims].concat())
+    }
+}
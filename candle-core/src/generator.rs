@@ -0,0 +1,277 @@
+//! Seeded random number generation.
+use crate::{bail, DType, Device, Result, Shape, Tensor, WithDType, D};
+use rand::{Rng, SeedableRng};
+use rand_distr::Distribution;
+
+/// A seeded random number generator producing tensors on a given device.
+///
+/// The values are sampled sequentially on the host from a single stream and then copied to the
+/// device, so that the results only depend on the seed and on the sequence of calls, and not on
+/// the device, the number of threads or the `Device::set_seed` state.
+///
+/// ```rust
+/// use candle_core::{DType, Device, Generator};
+/// let mut g1 = Generator::new(42, &Device::Cpu);
+/// let mut g2 = Generator::new(42, &Device::Cpu);
+/// let t1 = g1.randn(0., 1., (2, 3), DType::F32)?;
+/// let t2 = g2.randn(0., 1., (2, 3), DType::F32)?;
+/// assert_eq!(t1.to_vec2::<f32>()?, t2.to_vec2::<f32>()?);
+/// # Ok::<(), candle_core::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Generator {
+    rng: rand::rngs::StdRng,
+    seed: u64,
+    device: Device,
+}
+
+impl Generator {
+    /// Creates a generator seeded with `seed` producing tensors on `device`.
+    pub fn new(seed: u64, device: &Device) -> Self {
+        Self {
+            rng: rand::rngs::StdRng::seed_from_u64(seed),
+            seed,
+            device: device.clone(),
+        }
+    }
+
+    /// The seed used when creating the generator or at the last [`Generator::manual_seed`] call.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    /// Resets the generator state using `seed`.
+    pub fn manual_seed(&mut self, seed: u64) {
+        self.rng = rand::rngs::StdRng::seed_from_u64(seed);
+        self.seed = seed
+    }
+
+    fn sample_f64<S: Into<Shape>, R: Distribution<f64>>(
+        &mut self,
+        distr: R,
+        shape: S,
+        dtype: DType,
+    ) -> Result<Tensor> {
+        let shape = shape.into();
+        let data = (&mut self.rng)
+            .sample_iter(distr)
+            .take(shape.elem_count())
+            .collect::<Vec<_>>();
+        self.host_tensor(data, shape, dtype)
+    }
+
+    // Converts the values to `dtype` on the host before copying them to the device, as not all
+    // the devices support the f64 and i64 conversions.
+    fn host_tensor<T: WithDType, S: Into<Shape>>(
+        &self,
+        data: Vec<T>,
+        shape: S,
+        dtype: DType,
+    ) -> Result<Tensor> {
+        Tensor::from_vec(data, shape, &Device::Cpu)?
+            .to_dtype(dtype)?
+            .to_device(&self.device)
+    }
+
+    // Reads the values of `t` broadcast to `shape` on the host.
+    fn broadcast_to_vec(t: &Tensor, shape: &Shape) -> Result<Vec<f64>> {
+        t.to_device(&Device::Cpu)?
+            .broadcast_as(shape)?
+            .to_dtype(DType::F64)?
+            .flatten_all()?
+            .to_vec1::<f64>()
+    }
+
+    /// Samples values uniformly in `[lo, up)`.
+    pub fn rand<S: Into<Shape>>(
+        &mut self,
+        lo: f64,
+        up: f64,
+        shape: S,
+        dtype: DType,
+    ) -> Result<Tensor> {
+        if lo >= up {
+            bail!("rand: lo ({lo}) must be smaller than up ({up})")
+        }
+        let distr = rand::distributions::Uniform::new(lo, up);
+        self.sample_f64(distr, shape, dtype)
+    }
+
+    /// Samples values from a normal distribution with the given mean and standard deviation.
+    pub fn randn<S: Into<Shape>>(
+        &mut self,
+        mean: f64,
+        std: f64,
+        shape: S,
+        dtype: DType,
+    ) -> Result<Tensor> {
+        let distr = rand_distr::Normal::new(mean, std).map_err(crate::Error::wrap)?;
+        self.sample_f64(distr, shape, dtype)
+    }
+
+    /// Samples values from normal distributions with per-element means and standard deviations,
+    /// `mean` and `std` are broadcast together. The result is computed as `mean + std * z` with
+    /// `z` drawn from a standard normal so the gradient flows to `mean` and `std`.
+    pub fn normal(&mut self, mean: &Tensor, std: &Tensor) -> Result<Tensor> {
+        let shape = mean
+            .shape()
+            .broadcast_shape_binary_op(std.shape(), "normal")?;
+        let z = self.randn(0., 1., shape, mean.dtype())?;
+        mean.broadcast_add(&z.broadcast_mul(std)?)
+    }
+
+    /// Samples integers uniformly in `[low, high)`.
+    pub fn randint<S: Into<Shape>>(
+        &mut self,
+        low: i64,
+        high: i64,
+        shape: S,
+        dtype: DType,
+    ) -> Result<Tensor> {
+        if low >= high {
+            bail!("randint: low ({low}) must be smaller than high ({high})")
+        }
+        let shape = shape.into();
+        let distr = rand::distributions::Uniform::new(low, high);
+        let data = (&mut self.rng)
+            .sample_iter(distr)
+            .take(shape.elem_count())
+            .collect::<Vec<_>>();
+        self.host_tensor(data, shape, dtype)
+    }
+
+    /// Returns a random permutation of the integers from `0` to `n - 1` as a `u32` tensor.
+    pub fn randperm(&mut self, n: usize) -> Result<Tensor> {
+        let mut perm = (0..n as u32).collect::<Vec<_>>();
+        rand::seq::SliceRandom::shuffle(perm.as_mut_slice(), &mut self.rng);
+        Tensor::from_vec(perm, n, &self.device)
+    }
+
+    /// Samples zeros and ones, each element being one with the probability given by the
+    /// corresponding element of `p`. The result has the shape and dtype of `p`.
+    pub fn bernoulli(&mut self, p: &Tensor) -> Result<Tensor> {
+        let p_vec = Self::broadcast_to_vec(p, p.shape())?;
+        let data = p_vec
+            .iter()
+            .map(|&p| u8::from(self.rng.gen::<f64>() < p))
+            .collect::<Vec<_>>();
+        self.host_tensor(data, p.shape(), p.dtype())
+    }
+
+    /// Samples values from an exponential distribution with rate `lambda`.
+    pub fn exponential<S: Into<Shape>>(
+        &mut self,
+        lambda: f64,
+        shape: S,
+        dtype: DType,
+    ) -> Result<Tensor> {
+        let distr = rand_distr::Exp::new(lambda).map_err(crate::Error::wrap)?;
+        self.sample_f64(distr, shape, dtype)
+    }
+
+    // Samples from gamma distributions with the given concentrations and a rate of one.
+    fn standard_gamma(&mut self, concentration: &[f64]) -> Result<Vec<f64>> {
+        concentration
+            .iter()
+            .map(|&alpha| {
+                let distr = rand_distr::Gamma::new(alpha, 1.).map_err(crate::Error::wrap)?;
+                Ok(distr.sample(&mut self.rng))
+            })
+            .collect()
+    }
+
+    /// Samples values from gamma distributions with per-element concentrations and rates, the
+    /// two tensors are broadcast together.
+    pub fn gamma(&mut self, concentration: &Tensor, rate: &Tensor) -> Result<Tensor> {
+        let shape = concentration
+            .shape()
+            .broadcast_shape_binary_op(rate.shape(), "gamma")?;
+        let alpha = Self::broadcast_to_vec(concentration, &shape)?;
+        let rate = Self::broadcast_to_vec(rate, &shape)?;
+        let data = self
+            .standard_gamma(&alpha)?
+            .into_iter()
+            .zip(rate.iter())
+            .map(|(v, rate)| v / rate)
+            .collect::<Vec<_>>();
+        self.host_tensor(data, shape, concentration.dtype())
+    }
+
+    /// Samples values from beta distributions with per-element parameters, `alpha` and `beta`
+    /// are broadcast together.
+    pub fn beta(&mut self, alpha: &Tensor, beta: &Tensor) -> Result<Tensor> {
+        let shape = alpha
+            .shape()
+            .broadcast_shape_binary_op(beta.shape(), "beta")?;
+        let a = Self::broadcast_to_vec(alpha, &shape)?;
+        let b = Self::broadcast_to_vec(beta, &shape)?;
+        // x / (x + y) with x ~ gamma(alpha) and y ~ gamma(beta).
+        let x = self.standard_gamma(&a)?;
+        let y = self.standard_gamma(&b)?;
+        let data = x
+            .iter()
+            .zip(y.iter())
+            .map(|(x, y)| x / (x + y))
+            .collect::<Vec<_>>();
+        self.host_tensor(data, shape, alpha.dtype())
+    }
+
+    /// Samples values from dirichlet distributions, the concentrations are given on the last
+    /// dimension of `concentration` and the other dimensions are batch dimensions.
+    pub fn dirichlet(&mut self, concentration: &Tensor) -> Result<Tensor> {
+        let alpha = Self::broadcast_to_vec(concentration, concentration.shape())?;
+        let n = concentration.dim(D::Minus1)?;
+        let mut data = self.standard_gamma(&alpha)?;
+        for row in data.chunks_exact_mut(n.max(1)) {
+            let sum = row.iter().sum::<f64>();
+            row.iter_mut().for_each(|v| *v /= sum)
+        }
+        self.host_tensor(data, concentration.shape(), concentration.dtype())
+    }
+
+    /// Samples `num_samples` indexes from the categorical distributions given by the weights on
+    /// the last dimension of `probs`, which has to be a 1D or 2D tensor. The weights do not have to
+    /// sum to one. Without `replacement`, an index is sampled at most once per row. The result is
+    /// a `u32` tensor with the last dimension of `probs` replaced by `num_samples`.
+    pub fn multinomial(
+        &mut self,
+        probs: &Tensor,
+        num_samples: usize,
+        replacement: bool,
+    ) -> Result<Tensor> {
+        let (n_rows, n) = match *probs.dims() {
+            [n] => (1, n),
+            [n_rows, n] => (n_rows, n),
+            _ => bail!(
+                "multinomial: expected a 1D or 2D tensor, got {:?}",
+                probs.shape()
+            ),
+        };
+        let mut dims = probs.dims()[..probs.rank() - 1].to_vec();
+        dims.push(num_samples);
+        let probs = Self::broadcast_to_vec(probs, probs.shape())?;
+        let mut data = Vec::with_capacity(n_rows * num_samples);
+        for row in probs.chunks_exact(n.max(1)).take(n_rows) {
+            if !replacement && row.iter().filter(|&&p| p > 0.).count() < num_samples {
+                bail!("multinomial: cannot sample {num_samples} indexes without replacement")
+            }
+            let mut distr =
+                rand::distributions::WeightedIndex::new(row).map_err(crate::Error::wrap)?;
+            for i in 0..num_samples {
+                let index = distr.sample(&mut self.rng);
+                data.push(index as u32);
+                // The weights cannot all be zero before the last sample as checked above.
+                if !replacement && i + 1 < num_samples {
+                    distr
+                        .update_weights(&[(index, &0.)])
+                        .map_err(crate::Error::wrap)?
+                }
+            }
+        }
+        Tensor::from_vec(data, dims, &self.device)
+    }
+}
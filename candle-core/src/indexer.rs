@@ -1,4 +1,5 @@
-use crate::{Error, Tensor};
+use crate::shape::Dim;
+use crate::{DType, Error, Shape, Tensor};
 use std::ops::{
     Bound, Range, RangeBounds, RangeFrom, RangeFull, RangeInclusive, RangeTo, RangeToInclusive,
 };
@@ -47,12 +48,14 @@ impl Tensor {
                     out
                 }
                 TensorIndexer::IndexSelect(indexes) => {
-                    if indexes.rank() != 1 {
-                        crate::bail!("multi-dimensional tensor indexing is not supported")
-                    }
-                    let out = x.index_select(&indexes.to_device(x.device())?, current_dim)?;
-                    current_dim += 1;
-                    out
+                    let indexes = indexes.to_device(x.device())?;
+                    let out = x.index_select(&indexes.flatten_all()?, current_dim)?;
+                    // The indexed dimension is replaced with the dimensions of the indexes.
+                    let mut dims = out.dims()[..current_dim].to_vec();
+                    dims.extend_from_slice(indexes.dims());
+                    dims.extend_from_slice(&out.dims()[current_dim + 1..]);
+                    current_dim += indexes.rank();
+                    out.reshape(dims)?
                 }
                 TensorIndexer::Err(e) => crate::bail!("indexing error {e:?}"),
             };
@@ -61,6 +64,251 @@ impl Tensor {
     }
 }
 
+// Numpy style advanced indexing. These ops are built on top of `index_select`, `index_add`,
+// `gather` and `where_cond` so that they get a backward pass, the index computations are done on
+// the host.
+impl Tensor {
+    /// Returns the indexes of the non-zero elements of the tensor, in row-major order, as a `u32`
+    /// tensor of shape `(n, rank)` where `n` is the number of non-zero elements.
+    ///
+    /// ```
+    /// # use candle_core::{Tensor, Device};
+    /// let a = Tensor::new(&[[0f32, 1.], [2., 0.]], &Device::Cpu)?;
+    /// assert_eq!(a.nonzero()?.to_vec2::<u32>()?, &[[0, 1], [1, 0]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn nonzero(&self) -> Result<Self, Error> {
+        let dims = self.dims();
+        let positions = nonzero_positions(self)?;
+        let mut indexes = Vec::with_capacity(positions.len() * dims.len());
+        for position in positions.iter() {
+            let mut position = *position as usize;
+            let start = indexes.len();
+            for &dim in dims.iter().rev() {
+                indexes.push((position % dim) as u32);
+                position /= dim;
+            }
+            indexes[start..].reverse();
+        }
+        Tensor::from_vec(indexes, (positions.len(), dims.len()), self.device())
+    }
+
+    /// Returns a 1D tensor with the elements for which `mask` is non-zero, in row-major order. The
+    /// mask has to be broadcastable to the shape of the tensor.
+    ///
+    /// ```
+    /// # use candle_core::{Tensor, Device};
+    /// let a = Tensor::new(&[[0f32, 1., 2.], [3., 4., 5.]], &Device::Cpu)?;
+    /// let mask = Tensor::new(&[1u8, 0, 1], &Device::Cpu)?;
+    /// assert_eq!(a.masked_select(&mask)?.to_vec1::<f32>()?, &[0., 2., 3., 5.]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn masked_select(&self, mask: &Self) -> Result<Self, Error> {
+        let positions = nonzero_positions(&mask.broadcast_as(self.shape())?)?;
+        let len = positions.len();
+        let positions = Tensor::from_vec(positions, len, self.device())?;
+        self.flatten_all()?.index_select(&positions, 0)
+    }
+
+    /// Replaces the elements for which `mask` is non-zero with `value`. The mask has to be an
+    /// integer tensor broadcastable to the shape of the tensor.
+    ///
+    /// ```
+    /// # use candle_core::{Tensor, Device};
+    /// let a = Tensor::new(&[[0f32, 1., 2.], [3., 4., 5.]], &Device::Cpu)?;
+    /// let mask = Tensor::new(&[[1u8], [0]], &Device::Cpu)?;
+    /// let a = a.masked_fill(&mask, -1.)?;
+    /// assert_eq!(a.to_vec2::<f32>()?, &[[-1., -1., -1.], [3., 4., 5.]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn masked_fill(&self, mask: &Self, value: f64) -> Result<Self, Error> {
+        let value = Tensor::new(value, self.device())?
+            .to_dtype(self.dtype())?
+            .broadcast_as(self.shape())?;
+        mask.broadcast_as(self.shape())?.where_cond(&value, self)
+    }
+
+    /// Selects the values along dimension `dim` using `indexes`, similar to numpy's
+    /// `take_along_axis`. The indexes must have the same rank as the tensor, the dimensions other
+    /// than `dim` are broadcast together.
+    ///
+    /// ```
+    /// # use candle_core::{Tensor, Device};
+    /// let a = Tensor::new(&[[10f32, 30., 20.], [60., 40., 50.]], &Device::Cpu)?;
+    /// let indexes = a.argmax_keepdim(1)?;
+    /// let top = a.take_along_dim(&indexes, 1)?;
+    /// assert_eq!(top.to_vec2::<f32>()?, &[[30.], [60.]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn take_along_dim<D: Dim>(&self, indexes: &Self, dim: D) -> Result<Self, Error> {
+        let dim = dim.to_index(self.shape(), "take-along-dim")?;
+        if indexes.rank() != self.rank() {
+            Err(Error::ShapeMismatchBinaryOp {
+                lhs: self.shape().clone(),
+                rhs: indexes.shape().clone(),
+                op: "take-along-dim",
+            }
+            .bt())?
+        }
+        let mut src_dims = self.dims().to_vec();
+        let mut ids_dims = indexes.dims().to_vec();
+        src_dims[dim] = 1;
+        ids_dims[dim] = 1;
+        let shape = Shape::from(src_dims)
+            .broadcast_shape_binary_op(&Shape::from(ids_dims), "take-along-dim")?;
+        let mut src_dims = shape.dims().to_vec();
+        let mut ids_dims = shape.dims().to_vec();
+        src_dims[dim] = self.dims()[dim];
+        ids_dims[dim] = indexes.dims()[dim];
+        let src = self.broadcast_as(src_dims)?.contiguous()?;
+        let indexes = indexes.broadcast_as(ids_dims)?.contiguous()?;
+        src.gather(&indexes, dim)
+    }
+
+    /// Integer array indexing on the leading dimensions. The `indexes` are broadcast together and
+    /// the element at position `p` of the result is `self[indexes[0][p], indexes[1][p], ...]`,
+    /// the result shape being the broadcast shape of the indexes followed by the non-indexed
+    /// dimensions. Negative indexes count from the end.
+    ///
+    /// ```
+    /// # use candle_core::{Tensor, Device};
+    /// let a = Tensor::arange(0u32, 12, &Device::Cpu)?.reshape((2, 3, 2))?;
+    /// let i = Tensor::new(&[0i64, 1, 1], &Device::Cpu)?;
+    /// let j = Tensor::new(&[2i64, 0, -1], &Device::Cpu)?;
+    /// let b = a.index_tensors(&[&i, &j])?;
+    /// assert_eq!(b.to_vec2::<u32>()?, &[[4, 5], [6, 7], [10, 11]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn index_tensors(&self, indexes: &[&Self]) -> Result<Self, Error> {
+        let (positions, shape) = self.flat_positions(indexes, "index-tensors")?;
+        let positions = Tensor::from_vec(positions, shape.elem_count(), self.device())?;
+        let rest = &self.dims()[indexes.len()..];
+        let values = self
+            .flatten_to(indexes.len() - 1)?
+            .index_select(&positions, 0)?;
+        values.reshape([shape.dims(), rest].concat())
+    }
+
+    /// Returns a tensor where the elements at the positions given by `indexes`, as in
+    /// [`Tensor::index_tensors`], are replaced by `values`, or incremented by `values` when
+    /// `accumulate` is true. The values are broadcast to the shape of the indexed elements. When
+    /// not accumulating and a position appears multiple times, the last value is used.
+    ///
+    /// ```
+    /// # use candle_core::{Tensor, Device};
+    /// let a = Tensor::zeros((2, 3), candle_core::DType::F32, &Device::Cpu)?;
+    /// let i = Tensor::new(&[0u32, 1, 1], &Device::Cpu)?;
+    /// let j = Tensor::new(&[2u32, 0, 0], &Device::Cpu)?;
+    /// let values = Tensor::new(&[1f32, 2., 3.], &Device::Cpu)?;
+    /// let b = a.index_put(&[&i, &j], &values, false)?;
+    /// assert_eq!(b.to_vec2::<f32>()?, &[[0., 0., 1.], [3., 0., 0.]]);
+    /// let b = a.index_put(&[&i, &j], &values, true)?;
+    /// assert_eq!(b.to_vec2::<f32>()?, &[[0., 0., 1.], [5., 0., 0.]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn index_put(
+        &self,
+        indexes: &[&Self],
+        values: &Self,
+        accumulate: bool,
+    ) -> Result<Self, Error> {
+        let (positions, shape) = self.flat_positions(indexes, "index-put")?;
+        let rest = &self.dims()[indexes.len()..];
+        let values = values
+            .broadcast_as([shape.dims(), rest].concat())?
+            .reshape([&[shape.elem_count()], rest].concat())?;
+        let flat = self.flatten_to(indexes.len() - 1)?;
+        if accumulate {
+            let len = positions.len();
+            let positions = Tensor::from_vec(positions, len, self.device())?;
+            return flat
+                .index_add(&positions, &values, 0)?
+                .reshape(self.shape());
+        }
+        // Only keep the last occurrence of each position.
+        let mut is_set = vec![0u8; flat.dim(0)?];
+        let mut kept = vec![];
+        for (i, &position) in positions.iter().enumerate().rev() {
+            if is_set[position as usize] == 0 {
+                is_set[position as usize] = 1;
+                kept.push(i as u32)
+            }
+        }
+        kept.reverse();
+        let kept_positions = kept
+            .iter()
+            .map(|&i| positions[i as usize])
+            .collect::<Vec<_>>();
+        let len = kept.len();
+        let kept = Tensor::from_vec(kept, len, self.device())?;
+        let kept_positions = Tensor::from_vec(kept_positions, len, self.device())?;
+        let values =
+            flat.zeros_like()?
+                .index_add(&kept_positions, &values.index_select(&kept, 0)?, 0)?;
+        let mut mask_dims = vec![1; flat.rank()];
+        mask_dims[0] = flat.dim(0)?;
+        let mask = Tensor::from_vec(is_set, mask_dims, self.device())?;
+        mask.broadcast_as(flat.shape())?
+            .where_cond(&values, &flat)?
+            .reshape(self.shape())
+    }
+
+    // Broadcasts the `indexes` together and returns, for each of their elements, the position in
+    // the first `indexes.len()` dimensions of the tensor flattened, as well as the broadcast shape.
+    fn flat_positions(
+        &self,
+        indexes: &[&Self],
+        op: &'static str,
+    ) -> Result<(Vec<u32>, Shape), Error> {
+        if indexes.is_empty() || indexes.len() > self.rank() {
+            crate::bail!(
+                "{op}: got {} indexes for a tensor of shape {:?}",
+                indexes.len(),
+                self.shape()
+            )
+        }
+        let mut shape = indexes[0].shape().clone();
+        for indexes in indexes[1..].iter() {
+            shape = shape.broadcast_shape_binary_op(indexes.shape(), op)?;
+        }
+        let mut positions = vec![0u32; shape.elem_count()];
+        for (dim, indexes) in indexes.iter().enumerate() {
+            let dim_len = self.dims()[dim];
+            let indexes = indexes
+                .broadcast_as(&shape)?
+                .to_dtype(DType::I64)?
+                .flatten_all()?
+                .to_vec1::<i64>()?;
+            for (position, &index) in positions.iter_mut().zip(indexes.iter()) {
+                let i = if index < 0 {
+                    index + dim_len as i64
+                } else {
+                    index
+                };
+                if i < 0 || i >= dim_len as i64 {
+                    crate::bail!(
+                        "{op}: index {index} is out of range for dim {dim} of size {dim_len}"
+                    )
+                }
+                *position = *position * dim_len as u32 + i as u32
+            }
+        }
+        Ok((positions, shape))
+    }
+}
+
+// The positions of the non-zero elements in the tensor flattened.
+fn nonzero_positions(t: &Tensor) -> Result<Vec<u32>, Error> {
+    let is_nonzero = t.ne(0f64)?.flatten_all()?.to_vec1::<u8>()?;
+    let positions = is_nonzero
+        .iter()
+        .enumerate()
+        .filter(|(_, &v)| v != 0)
+        .map(|(i, _)| i as u32)
+        .collect();
+    Ok(positions)
+}
+
 #[derive(Debug)]
 /// Generic structure used to index a slice of the tensor
 pub enum TensorIndexer {
@@ -68,7 +316,8 @@ pub enum TensorIndexer {
     Select(usize),
     /// This is a regular slice, purely indexing a chunk of the tensor
     Narrow(Bound<usize>, Bound<usize>),
-    /// Indexing via a 1d tensor
+    /// Indexing via an integer tensor, the indexed dimension is replaced by the dimensions of
+    /// this tensor.
     IndexSelect(Tensor),
     Err(Error),
 }
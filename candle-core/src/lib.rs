@@ -47,12 +47,16 @@ pub mod cuda_backend;
 pub mod cudnn;
 mod device;
 pub mod display;
+pub mod distributed;
 mod dtype;
 mod dummy_cuda_backend;
 mod dummy_metal_backend;
+mod einsum;
 pub mod error;
+mod generator;
 mod indexer;
 pub mod layout;
+pub mod linalg;
 #[cfg(feature = "metal")]
 pub mod metal_backend;
 #[cfg(feature = "mkl")]
@@ -75,6 +79,7 @@ pub use cpu_backend::CpuStorage;
 pub use device::{Device, DeviceLocation, NdArray};
 pub use dtype::{DType, FloatDType, IntDType, WithDType};
 pub use error::{Error, Result};
+pub use generator::Generator;
 pub use indexer::IndexOp;
 pub use layout::Layout;
 pub use op::{CustomOp1, CustomOp2, CustomOp3};
@@ -0,0 +1,640 @@
+//! Linear algebra: solving linear systems and matrix decompositions.
+//!
+//! The functions operate on the last two dimensions of their arguments, the leading dimensions
+//! are batch dimensions. The computations run on the cpu for `f32` and `f64` tensors, the
+//! intermediate values being computed in `f64`.
+//!
+//! [`solve`], [`inv`], [`det`] and [`cholesky`] support backpropagation. The eigenvalues returned
+//! by [`eigh`] and the singular values returned by [`svd`] also get a gradient, the eigenvectors
+//! and singular vectors, as well as the [`qr`] factors, are detached from the graph.
+use crate::backend::BackendStorage;
+use crate::{bail, CpuStorage, DType, Layout, Result, Shape, Tensor, D};
+
+// Returns the sizes of the last two dimensions of `t`.
+fn matrix_dims(t: &Tensor, op: &'static str) -> Result<(usize, usize)> {
+    let dims = t.dims();
+    if dims.len() < 2 {
+        bail!("{op}: expected a batch of matrixes, got {:?}", t.shape())
+    }
+    Ok((dims[dims.len() - 2], dims[dims.len() - 1]))
+}
+
+// Returns the size of the square matrixes in `t`.
+fn square_dims(t: &Tensor, op: &'static str) -> Result<usize> {
+    let dims = t.dims();
+    if dims.len() < 2 || dims[dims.len() - 1] != dims[dims.len() - 2] {
+        bail!(
+            "{op}: expected a batch of square matrixes, got {:?}",
+            t.shape()
+        )
+    }
+    Ok(dims[dims.len() - 1])
+}
+
+fn check_dtype(t: &Tensor, op: &'static str) -> Result<()> {
+    match t.dtype() {
+        DType::F32 | DType::F64 => Ok(()),
+        dtype => bail!("{op}: unsupported dtype {dtype:?}"),
+    }
+}
+
+fn cpu_to_f64(storage: &CpuStorage, layout: &Layout) -> Result<Vec<f64>> {
+    let (o1, o2) = match layout.contiguous_offsets() {
+        None => bail!("input has to be contiguous"),
+        Some(offsets) => offsets,
+    };
+    match storage {
+        CpuStorage::F32(vs) => Ok(vs[o1..o2].iter().map(|&v| v as f64).collect()),
+        CpuStorage::F64(vs) => Ok(vs[o1..o2].to_vec()),
+        storage => bail!("unsupported dtype {:?}", storage.dtype()),
+    }
+}
+
+fn cpu_from_f64(vs: Vec<f64>, dtype: DType) -> CpuStorage {
+    match dtype {
+        DType::F32 => CpuStorage::F32(vs.into_iter().map(|v| v as f32).collect()),
+        _ => CpuStorage::F64(vs),
+    }
+}
+
+// The decompositions that return multiple tensors are computed on the host.
+fn host_to_f64(t: &Tensor) -> Result<Vec<f64>> {
+    t.to_dtype(DType::F64)?.flatten_all()?.to_vec1::<f64>()
+}
+
+fn host_from_f64(vs: Vec<f64>, dims: Vec<usize>, like: &Tensor) -> Result<Tensor> {
+    Tensor::from_vec(vs, dims, like.device())?.to_dtype(like.dtype())
+}
+
+fn transpose(a: &[f64], m: usize, n: usize) -> Vec<f64> {
+    let mut t = vec![0.; m * n];
+    for i in 0..m {
+        for j in 0..n {
+            t[j * m + i] = a[i * n + j]
+        }
+    }
+    t
+}
+
+// In place LU decomposition of the n x n matrix `a` with partial pivoting. Returns the row
+// permutation and its sign, or `None` if the matrix is singular.
+fn lu(a: &mut [f64], n: usize) -> Option<(Vec<usize>, f64)> {
+    let mut perm = (0..n).collect::<Vec<_>>();
+    let mut sign = 1.;
+    for k in 0..n {
+        let p = (k..n).max_by(|&i, &j| a[i * n + k].abs().total_cmp(&a[j * n + k].abs()))?;
+        if a[p * n + k] == 0. {
+            return None;
+        }
+        if p != k {
+            for j in 0..n {
+                a.swap(p * n + j, k * n + j)
+            }
+            perm.swap(p, k);
+            sign = -sign
+        }
+        for i in k + 1..n {
+            let f = a[i * n + k] / a[k * n + k];
+            a[i * n + k] = f;
+            for j in k + 1..n {
+                a[i * n + j] -= f * a[k * n + j]
+            }
+        }
+    }
+    Some((perm, sign))
+}
+
+// Solves `a x = b` for a n x m matrix `b` using the LU decomposition of `a`.
+fn lu_solve(lu: &[f64], perm: &[usize], b: &[f64], n: usize, m: usize) -> Vec<f64> {
+    let mut x = perm
+        .iter()
+        .flat_map(|&p| b[p * m..(p + 1) * m].iter().copied())
+        .collect::<Vec<_>>();
+    for i in 0..n {
+        for k in 0..i {
+            let f = lu[i * n + k];
+            for j in 0..m {
+                x[i * m + j] -= f * x[k * m + j]
+            }
+        }
+    }
+    for i in (0..n).rev() {
+        for k in i + 1..n {
+            let f = lu[i * n + k];
+            for j in 0..m {
+                x[i * m + j] -= f * x[k * m + j]
+            }
+        }
+        let d = lu[i * n + i];
+        for j in 0..m {
+            x[i * m + j] /= d
+        }
+    }
+    x
+}
+
+// Returns the lower triangular `l` such that `a = l l^T`, or `None` if `a` is not positive
+// definite.
+fn cholesky_(a: &[f64], n: usize) -> Option<Vec<f64>> {
+    let mut l = vec![0.; n * n];
+    for j in 0..n {
+        let d = a[j * n + j] - (0..j).map(|k| l[j * n + k] * l[j * n + k]).sum::<f64>();
+        if d.is_nan() || d <= 0. {
+            return None;
+        }
+        let d = d.sqrt();
+        l[j * n + j] = d;
+        for i in j + 1..n {
+            let s = a[i * n + j] - (0..j).map(|k| l[i * n + k] * l[j * n + k]).sum::<f64>();
+            l[i * n + j] = s / d
+        }
+    }
+    Some(l)
+}
+
+// Householder QR decomposition of the m x n matrix `a`, returns the m x k matrix `q` and the
+// k x n matrix `r` with k = min(m, n).
+fn qr_(a: &[f64], m: usize, n: usize) -> (Vec<f64>, Vec<f64>) {
+    let k = usize::min(m, n);
+    let mut r = a.to_vec();
+    let mut q = vec![0.; m * m];
+    for i in 0..m {
+        q[i * m + i] = 1.
+    }
+    for j in 0..k {
+        let norm = (j..m)
+            .map(|i| r[i * n + j] * r[i * n + j])
+            .sum::<f64>()
+            .sqrt();
+        if norm == 0. {
+            continue;
+        }
+        let alpha = if r[j * n + j] > 0. { -norm } else { norm };
+        let mut v = (j..m).map(|i| r[i * n + j]).collect::<Vec<_>>();
+        v[0] -= alpha;
+        let v_norm2 = v.iter().map(|v| v * v).sum::<f64>();
+        if v_norm2 == 0. {
+            continue;
+        }
+        // r <- h r and q <- q h with h = I - 2 v v^T / |v|^2.
+        for c in 0..n {
+            let s = (j..m).map(|i| v[i - j] * r[i * n + c]).sum::<f64>();
+            let f = 2. * s / v_norm2;
+            for i in j..m {
+                r[i * n + c] -= f * v[i - j]
+            }
+        }
+        for row in 0..m {
+            let s = (j..m).map(|i| q[row * m + i] * v[i - j]).sum::<f64>();
+            let f = 2. * s / v_norm2;
+            for i in j..m {
+                q[row * m + i] -= f * v[i - j]
+            }
+        }
+    }
+    let q = (0..m)
+        .flat_map(|i| q[i * m..i * m + k].iter().copied())
+        .collect();
+    let mut r = r[..k * n].to_vec();
+    for i in 0..k {
+        for j in 0..i {
+            r[i * n + j] = 0.
+        }
+    }
+    (q, r)
+}
+
+// Applies the Jacobi rotation zeroing the (p, q) element of a symmetric matrix with diagonal
+// elements `app`, `aqq` and off-diagonal element `apq`, returns (c, s).
+fn jacobi_rotation(app: f64, aqq: f64, apq: f64) -> (f64, f64) {
+    let theta = (aqq - app) / (2. * apq);
+    let t = theta.signum() / (theta.abs() + (theta * theta + 1.).sqrt());
+    let c = 1. / (t * t + 1.).sqrt();
+    (c, t * c)
+}
+
+// Rotates the columns p and q of the matrix `a` with `n_cols` columns.
+fn rotate_cols(a: &mut [f64], n_cols: usize, p: usize, q: usize, c: f64, s: f64) {
+    for row in a.chunks_exact_mut(n_cols) {
+        let (vp, vq) = (row[p], row[q]);
+        row[p] = c * vp - s * vq;
+        row[q] = s * vp + c * vq;
+    }
+}
+
+const MAX_SWEEPS: usize = 100;
+
+// Cyclic Jacobi eigenvalue algorithm for the symmetric n x n matrix `a`. Returns the eigenvalues
+// in ascending order and the matrix with the corresponding eigenvectors as columns.
+fn eigh_(a: &[f64], n: usize) -> (Vec<f64>, Vec<f64>) {
+    let mut a = a.to_vec();
+    let mut v = vec![0.; n * n];
+    for i in 0..n {
+        v[i * n + i] = 1.
+    }
+    let norm2 = a.iter().map(|v| v * v).sum::<f64>();
+    for _sweep in 0..MAX_SWEEPS {
+        let off2 = (0..n)
+            .flat_map(|i| (0..n).filter(move |&j| j != i).map(move |j| (i, j)))
+            .map(|(i, j)| a[i * n + j] * a[i * n + j])
+            .sum::<f64>();
+        if off2 <= f64::EPSILON * f64::EPSILON * norm2 {
+            break;
+        }
+        for p in 0..n {
+            for q in p + 1..n {
+                let apq = a[p * n + q];
+                if apq == 0. {
+                    continue;
+                }
+                let (c, s) = jacobi_rotation(a[p * n + p], a[q * n + q], apq);
+                // a <- j^T a j, v <- v j
+                rotate_cols(&mut a, n, p, q, c, s);
+                for k in 0..n {
+                    let (apk, aqk) = (a[p * n + k], a[q * n + k]);
+                    a[p * n + k] = c * apk - s * aqk;
+                    a[q * n + k] = s * apk + c * aqk;
+                }
+                rotate_cols(&mut v, n, p, q, c, s);
+            }
+        }
+    }
+    let mut order = (0..n).collect::<Vec<_>>();
+    order.sort_by(|&i, &j| a[i * n + i].total_cmp(&a[j * n + j]));
+    let values = order.iter().map(|&i| a[i * n + i]).collect();
+    let vectors = (0..n)
+        .flat_map(|row| order.iter().map(move |&i| (row, i)))
+        .map(|(row, i)| v[row * n + i])
+        .collect();
+    (values, vectors)
+}
+
+// One-sided Jacobi svd of the m x n matrix `a` with m >= n. Returns the m x n matrix `u`, the
+// singular values in descending order and the n x n matrix `v`.
+fn svd_tall(a: &[f64], m: usize, n: usize) -> (Vec<f64>, Vec<f64>, Vec<f64>) {
+    let mut u = a.to_vec();
+    let mut v = vec![0.; n * n];
+    for i in 0..n {
+        v[i * n + i] = 1.
+    }
+    let col_dot = |u: &[f64], p: usize, q: usize| (0..m).map(|i| u[i * n + p] * u[i * n + q]).sum();
+    for _sweep in 0..MAX_SWEEPS {
+        let mut rotated = false;
+        for p in 0..n {
+            for q in p + 1..n {
+                let alpha: f64 = col_dot(&u, p, p);
+                let beta: f64 = col_dot(&u, q, q);
+                let gamma: f64 = col_dot(&u, p, q);
+                if gamma.abs() <= f64::EPSILON * (alpha * beta).sqrt() {
+                    continue;
+                }
+                rotated = true;
+                let (c, s) = jacobi_rotation(alpha, beta, gamma);
+                rotate_cols(&mut u, n, p, q, c, s);
+                rotate_cols(&mut v, n, p, q, c, s);
+            }
+        }
+        if !rotated {
+            break;
+        }
+    }
+    let norms = (0..n)
+        .map(|p| col_dot(&u, p, p).sqrt())
+        .collect::<Vec<f64>>();
+    let mut order = (0..n).collect::<Vec<_>>();
+    order.sort_by(|&i, &j| norms[j].total_cmp(&norms[i]));
+    let s = order.iter().map(|&i| norms[i]).collect::<Vec<_>>();
+    let tol = s.first().copied().unwrap_or(0.) * f64::EPSILON * m as f64;
+    let mut u_sorted = vec![0.; m * n];
+    let mut v_sorted = vec![0.; n * n];
+    for (c, &i) in order.iter().enumerate() {
+        for row in 0..m {
+            if norms[i] > tol {
+                u_sorted[row * n + c] = u[row * n + i] / norms[i]
+            }
+        }
+        for row in 0..n {
+            v_sorted[row * n + c] = v[row * n + i]
+        }
+    }
+    // The columns for the zero singular values are completed to an orthonormal family.
+    for c in 0..n {
+        if s[c] > tol {
+            continue;
+        }
+        for e in 0..m {
+            let mut x = vec![0.; m];
+            x[e] = 1.;
+            for other in (0..n).filter(|&o| o != c) {
+                let dot = u_sorted[e * n + other];
+                for (row, x) in x.iter_mut().enumerate() {
+                    *x -= dot * u_sorted[row * n + other]
+                }
+            }
+            let norm = x.iter().map(|x| x * x).sum::<f64>().sqrt();
+            if norm > 0.5 {
+                for (row, x) in x.iter().enumerate() {
+                    u_sorted[row * n + c] = x / norm
+                }
+                break;
+            }
+        }
+    }
+    (u_sorted, s, v_sorted)
+}
+
+struct Solve;
+
+impl crate::CustomOp2 for Solve {
+    fn name(&self) -> &'static str {
+        "solve"
+    }
+
+    fn cpu_fwd(
+        &self,
+        s1: &CpuStorage,
+        l1: &Layout,
+        s2: &CpuStorage,
+        l2: &Layout,
+    ) -> Result<(CpuStorage, Shape)> {
+        let a = cpu_to_f64(s1, l1)?;
+        let b = cpu_to_f64(s2, l2)?;
+        let n = l1.dims()[l1.dims().len() - 1];
+        let m = l2.dims()[l2.dims().len() - 1];
+        let mut x = Vec::with_capacity(b.len());
+        if n > 0 {
+            for (i, a) in a.chunks_exact(n * n).enumerate() {
+                let b = &b[i * n * m..(i + 1) * n * m];
+                let mut a = a.to_vec();
+                let (perm, _) = match lu(&mut a, n) {
+                    None => bail!("solve: the matrix is singular"),
+                    Some(v) => v,
+                };
+                x.extend(lu_solve(&a, &perm, b, n, m))
+            }
+        }
+        Ok((cpu_from_f64(x, s2.dtype()), l2.shape().clone()))
+    }
+
+    fn bwd(
+        &self,
+        a: &Tensor,
+        _b: &Tensor,
+        x: &Tensor,
+        grad_x: &Tensor,
+    ) -> Result<(Option<Tensor>, Option<Tensor>)> {
+        let grad_b = solve(&a.t()?, grad_x)?;
+        let grad_a = grad_b.matmul(&x.t()?)?.neg()?;
+        Ok((Some(grad_a), Some(grad_b)))
+    }
+}
+
+struct Det;
+
+impl crate::CustomOp1 for Det {
+    fn name(&self) -> &'static str {
+        "det"
+    }
+
+    fn cpu_fwd(&self, storage: &CpuStorage, layout: &Layout) -> Result<(CpuStorage, Shape)> {
+        let a = cpu_to_f64(storage, layout)?;
+        let dims = layout.dims();
+        let n = dims[dims.len() - 1];
+        let shape = Shape::from(&dims[..dims.len() - 2]);
+        // The determinant of an empty matrix is one.
+        let det = if n == 0 {
+            vec![1.; shape.elem_count()]
+        } else {
+            a.chunks_exact(n * n)
+                .map(|a| {
+                    let mut a = a.to_vec();
+                    match lu(&mut a, n) {
+                        None => 0.,
+                        Some((_, sign)) => (0..n).map(|i| a[i * n + i]).product::<f64>() * sign,
+                    }
+                })
+                .collect()
+        };
+        Ok((cpu_from_f64(det, storage.dtype()), shape))
+    }
+
+    fn bwd(&self, a: &Tensor, det: &Tensor, grad_det: &Tensor) -> Result<Option<Tensor>> {
+        let scale = (grad_det * det)?
+            .unsqueeze(D::Minus1)?
+            .unsqueeze(D::Minus1)?;
+        let grad_a = inv(a)?.t()?.broadcast_mul(&scale)?;
+        Ok(Some(grad_a))
+    }
+}
+
+struct Cholesky;
+
+impl crate::CustomOp1 for Cholesky {
+    fn name(&self) -> &'static str {
+        "cholesky"
+    }
+
+    fn cpu_fwd(&self, storage: &CpuStorage, layout: &Layout) -> Result<(CpuStorage, Shape)> {
+        let a = cpu_to_f64(storage, layout)?;
+        let n = layout.dims()[layout.dims().len() - 1];
+        let mut l = Vec::with_capacity(a.len());
+        if n > 0 {
+            for a in a.chunks_exact(n * n) {
+                match cholesky_(a, n) {
+                    None => bail!("cholesky: the matrix is not positive definite"),
+                    Some(v) => l.extend(v),
+                }
+            }
+        }
+        Ok((cpu_from_f64(l, storage.dtype()), layout.shape().clone()))
+    }
+
+    fn bwd(&self, _a: &Tensor, l: &Tensor, grad_l: &Tensor) -> Result<Option<Tensor>> {
+        // With phi taking the lower triangular part and halving the diagonal:
+        // s = l^-T phi(l^T grad_l) l^-1, grad_a = (s + s^T) / 2
+        let n = l.dim(D::Minus1)?;
+        let phi = (Tensor::tril2(n, l.dtype(), l.device())?
+            - (Tensor::eye(n, l.dtype(), l.device())? * 0.5)?)?;
+        let l_inv = inv(l)?;
+        let p = l.t()?.matmul(grad_l)?.broadcast_mul(&phi)?;
+        let s = l_inv.t()?.matmul(&p)?.matmul(&l_inv)?;
+        let grad_a = ((&s + s.t()?)? * 0.5)?;
+        Ok(Some(grad_a))
+    }
+}
+
+/// Solves the linear systems `a x = b` where `a` has shape `(.., n, n)` and `b` has shape
+/// `(.., n, k)`, the batch dimensions are broadcast.
+///
+/// ```rust
+/// use candle_core::{linalg, Tensor, Device};
+/// let a = Tensor::new(&[[2f64, 1.], [1., 3.]], &Device::Cpu)?;
+/// let b = Tensor::new(&[[3f64], [5.]], &Device::Cpu)?;
+/// let x = linalg::solve(&a, &b)?;
+/// assert_eq!(x.to_vec2::<f64>()?, &[[0.8], [1.4]]);
+/// # Ok::<(), candle_core::Error>(())
+/// ```
+pub fn solve(a: &Tensor, b: &Tensor) -> Result<Tensor> {
+    let n = square_dims(a, "solve")?;
+    check_dtype(a, "solve")?;
+    if b.rank() < 2 || b.dim(D::Minus2)? != n || b.dtype() != a.dtype() {
+        Err(crate::Error::ShapeMismatchBinaryOp {
+            lhs: a.shape().clone(),
+            rhs: b.shape().clone(),
+            op: "solve",
+        }
+        .bt())?
+    }
+    let k = b.dim(D::Minus1)?;
+    let a_batch = Shape::from(&a.dims()[..a.rank() - 2]);
+    let b_batch = Shape::from(&b.dims()[..b.rank() - 2]);
+    let batch = a_batch.broadcast_shape_binary_op(&b_batch, "solve")?;
+    let a = a.broadcast_as([batch.dims(), &[n, n]].concat())?;
+    let b = b.broadcast_as([batch.dims(), &[n, k]].concat())?;
+    a.contiguous()?.apply_op2(&b.contiguous()?, Solve)
+}
+
+/// Returns the inverse of the square matrixes `a`.
+pub fn inv(a: &Tensor) -> Result<Tensor> {
+    let n = square_dims(a, "inv")?;
+    let eye = Tensor::eye(n, a.dtype(), a.device())?;
+    solve(a, &eye)
+}
+
+/// Returns the determinant of the square matrixes `a`, the result has the batch dimensions of
+/// `a`.
+///
+/// ```rust
+/// use candle_core::{linalg, Tensor, Device};
+/// let a = Tensor::new(&[[[2f32, 1.], [1., 3.]], [[0., 1.], [1., 0.]]], &Device::Cpu)?;
+/// assert_eq!(linalg::det(&a)?.to_vec1::<f32>()?, &[5., -1.]);
+/// # Ok::<(), candle_core::Error>(())
+/// ```
+pub fn det(a: &Tensor) -> Result<Tensor> {
+    square_dims(a, "det")?;
+    check_dtype(a, "det")?;
+    a.contiguous()?.apply_op1(Det)
+}
+
+/// Returns the lower triangular matrixes `l` such that `a = l l^T`, `a` has to be symmetric
+/// positive definite. Only the lower triangular part of `a` is used.
+pub fn cholesky(a: &Tensor) -> Result<Tensor> {
+    square_dims(a, "cholesky")?;
+    check_dtype(a, "cholesky")?;
+    a.contiguous()?.apply_op1(Cholesky)
+}
+
+/// Returns the reduced QR decomposition `(q, r)` of the matrixes `a` of shape `(.., m, n)`, `q`
+/// has orthonormal columns and shape `(.., m, k)` and `r` is upper triangular with shape
+/// `(.., k, n)` where `k = min(m, n)`.
+pub fn qr(a: &Tensor) -> Result<(Tensor, Tensor)> {
+    check_dtype(a, "qr")?;
+    let (m, n) = matrix_dims(a, "qr")?;
+    let k = usize::min(m, n);
+    let batch_dims = &a.dims()[..a.rank() - 2];
+    let data = host_to_f64(a)?;
+    let (mut q, mut r) = (vec![], vec![]);
+    if m * n > 0 {
+        for a in data.chunks_exact(m * n) {
+            let (q_, r_) = qr_(a, m, n);
+            q.extend(q_);
+            r.extend(r_)
+        }
+    }
+    let q = host_from_f64(q, [batch_dims, &[m, k]].concat(), a)?;
+    let r = host_from_f64(r, [batch_dims, &[k, n]].concat(), a)?;
+    Ok((q, r))
+}
+
+/// Returns the reduced singular value decomposition `(u, s, vt)` of the matrixes `a` of shape
+/// `(.., m, n)` so that `a = u diag(s) vt`. With `k = min(m, n)`, `u` has shape `(.., m, k)`,
+/// `s` has shape `(.., k)` with the singular values in descending order and `vt` has shape
+/// `(.., k, n)`.
+///
+/// ```rust
+/// use candle_core::{linalg, Tensor, Device};
+/// let a = Tensor::new(&[[3f64, 0.], [0., -4.], [0., 0.]], &Device::Cpu)?;
+/// let (u, s, vt) = linalg::svd(&a)?;
+/// assert_eq!(s.to_vec1::<f64>()?, &[4., 3.]);
+/// assert_eq!(u.broadcast_mul(&s)?.matmul(&vt)?.to_vec2::<f64>()?, a.to_vec2::<f64>()?);
+/// # Ok::<(), candle_core::Error>(())
+/// ```
+pub fn svd(a: &Tensor) -> Result<(Tensor, Tensor, Tensor)> {
+    check_dtype(a, "svd")?;
+    let (m, n) = matrix_dims(a, "svd")?;
+    let k = usize::min(m, n);
+    let batch_dims = &a.dims()[..a.rank() - 2];
+    let data = host_to_f64(a)?;
+    let (mut us, mut ss, mut vts) = (vec![], vec![], vec![]);
+    if m * n > 0 {
+        for a in data.chunks_exact(m * n) {
+            if m >= n {
+                let (u, s, v) = svd_tall(a, m, n);
+                us.extend(u);
+                ss.extend(s);
+                vts.extend(transpose(&v, n, n))
+            } else {
+                // a^T = u' s v'^T so a = v' s u'^T.
+                let (u, s, v) = svd_tall(&transpose(a, m, n), n, m);
+                us.extend(v);
+                ss.extend(s);
+                vts.extend(transpose(&u, n, m))
+            }
+        }
+    }
+    let u = host_from_f64(us, [batch_dims, &[m, k]].concat(), a)?;
+    let vt = host_from_f64(vts, [batch_dims, &[k, n]].concat(), a)?;
+    // s = diag(u^T a v) with u and v detached, so that the gradient flows to a.
+    let s = if a.track_op() {
+        let eye = Tensor::eye(k, a.dtype(), a.device())?;
+        u.t()?
+            .matmul(a)?
+            .matmul(&vt.t()?)?
+            .broadcast_mul(&eye)?
+            .sum(D::Minus1)?
+    } else {
+        host_from_f64(ss, [batch_dims, &[k]].concat(), a)?
+    };
+    Ok((u, s, vt))
+}
+
+/// Returns the eigenvalues, in ascending order, and the eigenvectors, as columns, of the
+/// symmetric matrixes `a`. Only the symmetric part of `a` is used.
+///
+/// ```rust
+/// use candle_core::{linalg, Tensor, Device};
+/// let a = Tensor::new(&[[2f32, 1.], [1., 2.]], &Device::Cpu)?;
+/// let (values, vectors) = linalg::eigh(&a)?;
+/// assert_eq!(values.to_vec1::<f32>()?, &[1., 3.]);
+/// assert_eq!(vectors.dims(), &[2, 2]);
+/// # Ok::<(), candle_core::Error>(())
+/// ```
+pub fn eigh(a: &Tensor) -> Result<(Tensor, Tensor)> {
+    let n = square_dims(a, "eigh")?;
+    check_dtype(a, "eigh")?;
+    let a = ((a + a.t()?)? * 0.5)?;
+    let batch_dims = &a.dims()[..a.rank() - 2];
+    let data = host_to_f64(&a)?;
+    let (mut values, mut vectors) = (vec![], vec![]);
+    if n > 0 {
+        for a in data.chunks_exact(n * n) {
+            let (vs, ws) = eigh_(a, n);
+            values.extend(vs);
+            vectors.extend(ws)
+        }
+    }
+    let vectors = host_from_f64(vectors, a.dims().to_vec(), &a)?;
+    // values = diag(v^T a v) with v detached, so that the gradient flows to a.
+    let values = if a.track_op() {
+        let eye = Tensor::eye(n, a.dtype(), a.device())?;
+        vectors
+            .t()?
+            .matmul(&a)?
+            .matmul(&vectors)?
+            .broadcast_mul(&eye)?
+            .sum(D::Minus1)?
+    } else {
+        host_from_f64(values, [batch_dims, &[n]].concat(), &a)?
+    };
+    Ok((values, vectors))
+}
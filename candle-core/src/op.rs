@@ -155,6 +155,9 @@ pub enum Op {
     ToDevice(Tensor),
     Transpose(Tensor, usize, usize),
     Permute(Tensor, Vec<usize>),
+    // A view on the elements of the tensor, the layout gives the strides and offset to use if the
+    // tensor was contiguous.
+    AsStrided(Tensor, Layout),
     Elu(Tensor, f64),
     Powf(Tensor, f64),
     CustomOp1(Tensor, std::sync::Arc<Box<dyn CustomOp1 + Send + Sync>>),
@@ -47,6 +47,27 @@ pub(crate) unsafe fn mul_sum_i8_pairs_float(x: __m256i, y: __m256i) -> __m256 {
     mul_sum_us8_pairs_float(ax, sy)
 }
 
+/// Dot product of unsigned and signed bytes, summed in groups of 4 in each i32 lane. This uses
+/// the VNNI instructions when available.
+#[inline(always)]
+unsafe fn dot_us8_i8(ax: __m256i, sy: __m256i) -> __m256i {
+    #[cfg(target_feature = "avxvnni")]
+    return _mm256_dpbusd_avx_epi32(_mm256_setzero_si256(), ax, sy);
+
+    #[cfg(not(target_feature = "avxvnni"))]
+    return _mm256_madd_epi16(_mm256_set1_epi16(1), _mm256_maddubs_epi16(ax, sy));
+}
+
+/// The maximum number of rows processed at once by the `vec_dot_*_rows` kernels.
+const ROWS: usize = 4;
+
+#[inline(always)]
+unsafe fn hsum_float_4(x: __m128) -> f32 {
+    let x = _mm_add_ps(x, _mm_movehl_ps(x, x));
+    let x = _mm_add_ss(x, _mm_movehdup_ps(x));
+    _mm_cvtss_f32(x)
+}
+
 #[inline(always)]
 pub(crate) fn vec_dot_q4_0_q8_0(n: usize, xs: &[BlockQ4_0], ys: &[BlockQ8_0]) -> Result<f32> {
     let qk = QK8_0;
@@ -68,6 +89,41 @@ pub(crate) fn vec_dot_q4_0_q8_0(n: usize, xs: &[BlockQ4_0], ys: &[BlockQ8_0]) ->
     }
 }
 
+pub(crate) fn vec_dot_q4_0_q8_0_rows(
+    n: usize,
+    xs: &[BlockQ4_0],
+    ys: &[&[BlockQ8_0]],
+    dst: &mut [f32],
+) -> Result<()> {
+    let qk = QK8_0;
+    if n % QK8_0 != 0 {
+        crate::bail!("vec_dot_q4_0_q8_0: {n} is not divisible by {qk}")
+    }
+    for (ys, dst) in ys.chunks(ROWS).zip(dst.chunks_mut(ROWS)) {
+        unsafe {
+            let mut acc = [_mm256_setzero_ps(); ROWS];
+            for (i, x) in xs.iter().enumerate() {
+                let dx = f16::to_f32(x.d);
+                let bx = bytes_from_nibbles_32(x.qs.as_ptr());
+                let bx = _mm256_sub_epi8(bx, _mm256_set1_epi8(8));
+                let ax = _mm256_sign_epi8(bx, bx);
+                for (acc, ys) in acc.iter_mut().zip(ys.iter()) {
+                    let y = &ys[i];
+                    let d = _mm256_set1_ps(dx * f16::to_f32(y.d));
+                    let by = _mm256_loadu_si256(y.qs.as_ptr() as *const __m256i);
+                    let sy = _mm256_sign_epi8(by, bx);
+                    let q = _mm256_cvtepi32_ps(dot_us8_i8(ax, sy));
+                    *acc = _mm256_fmadd_ps(d, q, *acc);
+                }
+            }
+            for (dst, acc) in dst.iter_mut().zip(acc) {
+                *dst = hsum_float_8(acc)
+            }
+        }
+    }
+    Ok(())
+}
+
 #[inline(always)]
 pub(crate) fn vec_dot_q8_0_q8_0(n: usize, xs: &[BlockQ8_0], ys: &[BlockQ8_0]) -> Result<f32> {
     let qk = QK8_0;
@@ -87,6 +143,40 @@ pub(crate) fn vec_dot_q8_0_q8_0(n: usize, xs: &[BlockQ8_0], ys: &[BlockQ8_0]) ->
     }
 }
 
+pub(crate) fn vec_dot_q8_0_q8_0_rows(
+    n: usize,
+    xs: &[BlockQ8_0],
+    ys: &[&[BlockQ8_0]],
+    dst: &mut [f32],
+) -> Result<()> {
+    let qk = QK8_0;
+    if n % QK8_0 != 0 {
+        crate::bail!("vec_dot_q8_0_q8_0: {n} is not divisible by {qk}")
+    }
+    for (ys, dst) in ys.chunks(ROWS).zip(dst.chunks_mut(ROWS)) {
+        unsafe {
+            let mut acc = [_mm256_setzero_ps(); ROWS];
+            for (i, x) in xs.iter().enumerate() {
+                let dx = f16::to_f32(x.d);
+                let bx = _mm256_loadu_si256(x.qs.as_ptr() as *const __m256i);
+                let ax = _mm256_sign_epi8(bx, bx);
+                for (acc, ys) in acc.iter_mut().zip(ys.iter()) {
+                    let y = &ys[i];
+                    let d = _mm256_set1_ps(dx * f16::to_f32(y.d));
+                    let by = _mm256_loadu_si256(y.qs.as_ptr() as *const __m256i);
+                    let sy = _mm256_sign_epi8(by, bx);
+                    let q = _mm256_cvtepi32_ps(dot_us8_i8(ax, sy));
+                    *acc = _mm256_fmadd_ps(d, q, *acc);
+                }
+            }
+            for (dst, acc) in dst.iter_mut().zip(acc) {
+                *dst = hsum_float_8(acc)
+            }
+        }
+    }
+    Ok(())
+}
+
 #[inline(always)]
 unsafe fn get_scale_shuffle(i: usize) -> __m128i {
     const K_SHUFFLE: [u8; 128] = [
@@ -522,6 +612,88 @@ pub(crate) fn vec_dot_q4k_q8k(n: usize, xs: &[BlockQ4K], ys: &[BlockQ8K]) -> Res
     }
 }
 
+pub(crate) fn vec_dot_q4k_q8k_rows(
+    n: usize,
+    xs: &[BlockQ4K],
+    ys: &[&[BlockQ8K]],
+    dst: &mut [f32],
+) -> Result<()> {
+    if n % QK_K != 0 {
+        crate::bail!("vec_dot_q4k_q8k: {n} is not divisible by {QK_K}")
+    }
+    let mut utmp = [0u32; 4];
+    const KMASK1: u32 = 0x3f3f3f3f;
+    const KMASK2: u32 = 0x0f0f0f0f;
+    const KMASK3: u32 = 0x03030303;
+
+    for (ys, dst) in ys.chunks(ROWS).zip(dst.chunks_mut(ROWS)) {
+        unsafe {
+            let m4 = _mm256_set1_epi8(0xF);
+            let mut acc = [_mm256_setzero_ps(); ROWS];
+            let mut acc_m = [_mm_setzero_ps(); ROWS];
+            // The unpacked nibbles and the matching scales for each 32 values, these are shared
+            // by all the rows.
+            let mut q4 = [_mm256_setzero_si256(); QK_K / 32];
+            let mut scales_j = [_mm256_setzero_si256(); QK_K / 32];
+
+            for (i, x) in xs.iter().enumerate() {
+                LittleEndian::read_u32_into(&x.scales, &mut utmp[0..3]);
+
+                utmp[3] = ((utmp[2] >> 4) & KMASK2) | (((utmp[1] >> 6) & KMASK3) << 4);
+                let uaux = utmp[1] & KMASK1;
+                utmp[1] = (utmp[2] & KMASK2) | (((utmp[0] >> 6) & KMASK3) << 4);
+                utmp[2] = uaux;
+                utmp[0] &= KMASK1;
+
+                let mins_and_scales = _mm256_cvtepu8_epi16(_mm_set_epi32(
+                    utmp[3] as i32,
+                    utmp[2] as i32,
+                    utmp[1] as i32,
+                    utmp[0] as i32,
+                ));
+                let mins = _mm256_extracti128_si256(mins_and_scales, 1);
+                let sc128 = _mm256_extracti128_si256(mins_and_scales, 0);
+                let scales = mm256_set_m128i(sc128, sc128);
+
+                for j in 0..QK_K / 64 {
+                    scales_j[2 * j] = _mm256_shuffle_epi8(scales, get_scale_shuffle_k4(2 * j));
+                    scales_j[2 * j + 1] =
+                        _mm256_shuffle_epi8(scales, get_scale_shuffle_k4(2 * j + 1));
+                    let q4bits = _mm256_loadu_si256(x.qs.as_ptr().add(32 * j) as *const __m256i);
+                    q4[2 * j] = _mm256_and_si256(q4bits, m4);
+                    q4[2 * j + 1] = _mm256_and_si256(_mm256_srli_epi16(q4bits, 4), m4);
+                }
+
+                let xd = x.d.to_f32();
+                let xdmin = x.dmin.to_f32();
+                for ((acc, acc_m), ys) in acc.iter_mut().zip(acc_m.iter_mut()).zip(ys.iter()) {
+                    let y = &ys[i];
+                    let q8sums = _mm256_loadu_si256(y.bsums.as_ptr() as *const __m256i);
+                    let q8s = _mm_hadd_epi16(
+                        _mm256_extracti128_si256(q8sums, 0),
+                        _mm256_extracti128_si256(q8sums, 1),
+                    );
+                    let prod = _mm_madd_epi16(mins, q8s);
+                    *acc_m = _mm_fmadd_ps(_mm_set1_ps(-y.d * xdmin), _mm_cvtepi32_ps(prod), *acc_m);
+
+                    let mut sumi = _mm256_setzero_si256();
+                    for (j, (q4, scale)) in q4.iter().zip(scales_j.iter()).enumerate() {
+                        let q8 = _mm256_loadu_si256(y.qs.as_ptr().add(32 * j) as *const __m256i);
+                        let p16 = _mm256_maddubs_epi16(*q4, q8);
+                        sumi = _mm256_add_epi32(sumi, _mm256_madd_epi16(*scale, p16));
+                    }
+                    let vd = _mm256_set1_ps(y.d * xd);
+                    *acc = _mm256_fmadd_ps(vd, _mm256_cvtepi32_ps(sumi), *acc);
+                }
+            }
+            for ((dst, acc), acc_m) in dst.iter_mut().zip(acc).zip(acc_m) {
+                *dst = hsum_float_8(acc) + hsum_float_4(acc_m)
+            }
+        }
+    }
+    Ok(())
+}
+
 #[inline(always)]
 pub(crate) fn vec_dot_q5k_q8k(n: usize, xs: &[BlockQ5K], ys: &[BlockQ8K]) -> Result<f32> {
     if n % QK_K != 0 {
@@ -0,0 +1,566 @@
+//! Conversion of safetensors, PyTorch and npz weights to quantized GGUF files.
+//!
+//! The target type of each tensor is selected by a list of rules, each rule associates a regex
+//! matched against the tensor name with a [`GgmlDType`]. The first matching rule wins and the
+//! default type is used when no rule matches. Tensors whose last dimension is not a multiple of
+//! the block size of the selected type are stored using the fallback type instead.
+//!
+//! The tensor names are kept as is unless an architecture is set. In that case the Hugging Face
+//! tensor names are mapped to the GGUF ones, e.g. `model.layers.0.self_attn.q_proj.weight` to
+//! `blk.0.attn_q.weight`, and the query and key projections are permuted from the rotate-half
+//! RoPE layout to the interleaved one, see [`to_gguf_tensors`]. Only `llama` is supported. The
+//! hyperparameters written under the architecture prefix, e.g. `llama.block_count`, are read from
+//! the `config.json` file of the original model or set explicitly with [`Config::with_metadata`].
+//!
+//! ```no_run
+//! use candle_core::quantized::convert;
+//! # fn main() -> candle_core::Result<()> {
+//! let config = convert::Config::default()
+//!     .with_architecture("llama")
+//!     .with_hf_config("config.json")?;
+//! let report = convert::convert(&["model.safetensors"], "model.gguf", &config)?;
+//! println!("{report}");
+//! # Ok(())
+//! # }
+//! ```
+use super::{gguf_file, GgmlDType, QTensor};
+use crate::{DType, Device, Result, Tensor};
+use rayon::prelude::*;
+
+/// The GGML quantization version written to `general.quantization_version`.
+pub const QUANTIZATION_VERSION: u32 = 2;
+
+/// The architecture specific metadata keys, without the architecture prefix, that have to be
+/// present when `general.architecture` is set.
+pub const REQUIRED_ARCH_KEYS: [&str; 4] = [
+    "block_count",
+    "context_length",
+    "embedding_length",
+    "attention.head_count",
+];
+
+/// The Hugging Face names of the llama tensors that are not part of a layer, together with their
+/// GGUF names.
+const LLAMA_TENSOR_NAMES: [(&str, &str); 3] = [
+    ("model.embed_tokens", "token_embd"),
+    ("model.norm", "output_norm"),
+    ("lm_head", "output"),
+];
+
+/// The Hugging Face names of the llama layer tensors, relative to `model.layers.{i}`, together
+/// with their GGUF names relative to `blk.{i}`.
+const LLAMA_LAYER_TENSOR_NAMES: [(&str, &str); 9] = [
+    ("self_attn.q_proj", "attn_q"),
+    ("self_attn.k_proj", "attn_k"),
+    ("self_attn.v_proj", "attn_v"),
+    ("self_attn.o_proj", "attn_output"),
+    ("mlp.gate_proj", "ffn_gate"),
+    ("mlp.up_proj", "ffn_up"),
+    ("mlp.down_proj", "ffn_down"),
+    ("input_layernorm", "attn_norm"),
+    ("post_attention_layernorm", "ffn_norm"),
+];
+
+/// Maps the tensors whose name matches `pattern` to `dtype`.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pattern: regex::Regex,
+    dtype: GgmlDType,
+}
+
+impl Rule {
+    pub fn new(pattern: &str, dtype: GgmlDType) -> Result<Self> {
+        let pattern = regex::Regex::new(pattern).map_err(crate::Error::wrap)?;
+        Ok(Self { pattern, dtype })
+    }
+
+    pub fn pattern(&self) -> &str {
+        self.pattern.as_str()
+    }
+
+    pub fn dtype(&self) -> GgmlDType {
+        self.dtype
+    }
+
+    pub fn is_match(&self, name: &str) -> bool {
+        self.pattern.is_match(name)
+    }
+}
+
+/// Conversion settings: the per-tensor rules and the metadata to write in the GGUF file.
+#[derive(Debug, Clone)]
+pub struct Config {
+    rules: Vec<Rule>,
+    default_dtype: GgmlDType,
+    fallback_dtype: GgmlDType,
+    metadata: Vec<(String, gguf_file::Value)>,
+    // Hyperparameters keyed without the architecture prefix, the prefix is only known when
+    // writing as the architecture can be set after the hyperparameters.
+    hparams: Vec<(String, gguf_file::Value)>,
+}
+
+impl Default for Config {
+    /// Keeps the embeddings and the norms in F16, uses Q6K for the output projection and Q4K
+    /// for everything else.
+    fn default() -> Self {
+        let rules = [
+            (
+                r"(^|\.)(token_embd|tok_embeddings|embed_tokens|wte|wpe|word_embeddings)\.",
+                GgmlDType::F16,
+            ),
+            (r"norm|(^|\.)ln_", GgmlDType::F16),
+            (r"^(output|lm_head)\.weight$", GgmlDType::Q6K),
+        ];
+        let rules = rules
+            .into_iter()
+            .map(|(pattern, dtype)| Rule::new(pattern, dtype))
+            .collect::<Result<Vec<_>>>()
+            .expect("invalid default rules");
+        Self {
+            rules,
+            default_dtype: GgmlDType::Q4K,
+            fallback_dtype: GgmlDType::F16,
+            metadata: vec![],
+            hparams: vec![],
+        }
+    }
+}
+
+impl Config {
+    /// A configuration without any rule, all the tensors are converted to `default_dtype`.
+    pub fn new(default_dtype: GgmlDType) -> Self {
+        Self {
+            rules: vec![],
+            default_dtype,
+            fallback_dtype: GgmlDType::F16,
+            metadata: vec![],
+            hparams: vec![],
+        }
+    }
+
+    /// Adds a rule, rules are tried in the order in which they have been added.
+    pub fn with_rule(mut self, pattern: &str, dtype: GgmlDType) -> Result<Self> {
+        self.rules.push(Rule::new(pattern, dtype)?);
+        Ok(self)
+    }
+
+    /// Adds a rule that takes precedence over all the rules added so far.
+    pub fn with_rule_first(mut self, pattern: &str, dtype: GgmlDType) -> Result<Self> {
+        self.rules.insert(0, Rule::new(pattern, dtype)?);
+        Ok(self)
+    }
+
+    pub fn with_default_dtype(mut self, dtype: GgmlDType) -> Self {
+        self.default_dtype = dtype;
+        self
+    }
+
+    /// The type used for tensors that cannot be stored with the type selected by the rules.
+    pub fn with_fallback_dtype(mut self, dtype: GgmlDType) -> Result<Self> {
+        if dtype.block_size() != 1 {
+            crate::bail!("the fallback dtype must not be block quantized, got {dtype:?}")
+        }
+        self.fallback_dtype = dtype;
+        Ok(self)
+    }
+
+    /// Sets `general.architecture`, the prefix used by the architecture specific metadata keys
+    /// such as `llama.context_length`. The keys from [`REQUIRED_ARCH_KEYS`] then have to be set,
+    /// either via [`Config::with_hf_config`] or [`Config::with_metadata`].
+    pub fn with_architecture(self, architecture: &str) -> Self {
+        self.with_metadata(
+            "general.architecture",
+            gguf_file::Value::String(architecture.to_string()),
+        )
+    }
+
+    /// Reads the hyperparameters from the `config.json` file of a Hugging Face model. These are
+    /// written with the architecture prefix, the entries set with [`Config::with_metadata`] take
+    /// precedence.
+    pub fn with_hf_config<P: AsRef<std::path::Path>>(self, path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let config = std::fs::read_to_string(path)?;
+        let config: serde_json::Value =
+            serde_json::from_str(&config).map_err(|e| crate::Error::wrap(e).with_path(path))?;
+        self.with_hf_config_value(&config)
+    }
+
+    /// Same as [`Config::with_hf_config`] but using an already parsed `config.json`.
+    pub fn with_hf_config_value(mut self, config: &serde_json::Value) -> Result<Self> {
+        use gguf_file::Value;
+
+        let get_u32 = |key: &str| config.get(key).and_then(|v| v.as_u64()).map(|v| v as u32);
+        let get_f32 = |key: &str| config.get(key).and_then(|v| v.as_f64()).map(|v| v as f32);
+        let head_count = get_u32("num_attention_heads");
+        let head_dim = match (get_u32("head_dim"), get_u32("hidden_size"), head_count) {
+            (Some(head_dim), _, _) => Some(head_dim),
+            (None, Some(hidden_size), Some(head_count)) if head_count > 0 => {
+                Some(hidden_size / head_count)
+            }
+            _ => None,
+        };
+        let hparams = [
+            ("block_count", get_u32("num_hidden_layers").map(Value::U32)),
+            (
+                "context_length",
+                get_u32("max_position_embeddings").map(Value::U32),
+            ),
+            ("embedding_length", get_u32("hidden_size").map(Value::U32)),
+            (
+                "feed_forward_length",
+                get_u32("intermediate_size").map(Value::U32),
+            ),
+            ("attention.head_count", head_count.map(Value::U32)),
+            (
+                "attention.head_count_kv",
+                get_u32("num_key_value_heads")
+                    .or(head_count)
+                    .map(Value::U32),
+            ),
+            (
+                "attention.layer_norm_rms_epsilon",
+                get_f32("rms_norm_eps").map(Value::F32),
+            ),
+            ("rope.dimension_count", head_dim.map(Value::U32)),
+            ("rope.freq_base", get_f32("rope_theta").map(Value::F32)),
+        ];
+        for (key, value) in hparams {
+            if let Some(value) = value {
+                match self.hparams.iter_mut().find(|(k, _)| k == key) {
+                    Some((_, v)) => *v = value,
+                    None => self.hparams.push((key.to_string(), value)),
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    /// Sets a metadata entry, replacing any previous value for the same key.
+    pub fn with_metadata(mut self, key: &str, value: gguf_file::Value) -> Self {
+        match self.metadata.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value,
+            None => self.metadata.push((key.to_string(), value)),
+        }
+        self
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// The architecture set via [`Config::with_architecture`], if any.
+    pub fn architecture(&self) -> Option<&str> {
+        self.metadata.iter().find_map(|(k, v)| match v {
+            gguf_file::Value::String(v) if k == "general.architecture" => Some(v.as_str()),
+            _ => None,
+        })
+    }
+
+    pub fn metadata(&self) -> &[(String, gguf_file::Value)] {
+        &self.metadata
+    }
+
+    /// The metadata written to the GGUF file: the explicitly set entries followed by the
+    /// hyperparameters prefixed with the architecture. This fails if an architecture is set
+    /// but some of the [`REQUIRED_ARCH_KEYS`] are missing.
+    pub fn gguf_metadata(&self) -> Result<Vec<(String, gguf_file::Value)>> {
+        let mut metadata = self.metadata.clone();
+        let architecture = match metadata.iter().find(|(k, _)| k == "general.architecture") {
+            None => return Ok(metadata),
+            Some((_, architecture)) => architecture.to_string()?.clone(),
+        };
+        for (key, value) in self.hparams.iter() {
+            let key = format!("{architecture}.{key}");
+            if !metadata.iter().any(|(k, _)| *k == key) {
+                metadata.push((key, value.clone()))
+            }
+        }
+        let missing = REQUIRED_ARCH_KEYS
+            .iter()
+            .map(|key| format!("{architecture}.{key}"))
+            .filter(|key| !metadata.iter().any(|(k, _)| k == key))
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            crate::bail!("missing metadata for architecture {architecture}: {missing:?}")
+        }
+        Ok(metadata)
+    }
+
+    /// The type for the tensor `name` of shape `shape` taking the fallback into account.
+    pub fn dtype_for(&self, name: &str, shape: &crate::Shape) -> GgmlDType {
+        let dtype = self
+            .rules
+            .iter()
+            .find(|rule| rule.is_match(name))
+            .map_or(self.default_dtype, |rule| rule.dtype);
+        let last_dim = shape.dims().last().copied().unwrap_or(1);
+        if last_dim % dtype.block_size() == 0 {
+            dtype
+        } else {
+            self.fallback_dtype
+        }
+    }
+}
+
+/// Quantization error statistics for a single tensor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TensorStats {
+    pub name: String,
+    pub shape: crate::Shape,
+    pub dtype: GgmlDType,
+    pub size_in_bytes: usize,
+    /// Root mean square of the difference between the original and the dequantized values.
+    pub rmse: f32,
+    /// Largest absolute difference between the original and the dequantized values.
+    pub max_abs_error: f32,
+    /// `rmse` divided by the root mean square of the original values.
+    pub rel_error: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Report {
+    pub tensors: Vec<TensorStats>,
+}
+
+impl Report {
+    pub fn size_in_bytes(&self) -> usize {
+        self.tensors.iter().map(|t| t.size_in_bytes).sum()
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for t in self.tensors.iter() {
+            writeln!(
+                f,
+                "{:40} {:6} {:20} rmse {:.3e} max {:.3e} rel {:.3e}",
+                t.name,
+                format!("{:?}", t.dtype),
+                format!("{:?}", t.shape.dims()),
+                t.rmse,
+                t.max_abs_error,
+                t.rel_error
+            )?
+        }
+        write!(
+            f,
+            "{} tensors, {:.2}MB",
+            self.tensors.len(),
+            self.size_in_bytes() as f64 / 1e6
+        )
+    }
+}
+
+/// Loads all the tensors from the given files on the cpu, the format is selected based on the
+/// file extension: `safetensors`, `npz`, or a PyTorch pickle (`pth`, `pt`, `bin`).
+///
+/// The tensors are returned sorted by name, duplicated names result in an error.
+pub fn load<P: AsRef<std::path::Path>>(paths: &[P]) -> Result<Vec<(String, Tensor)>> {
+    let mut tensors = vec![];
+    for path in paths.iter() {
+        let path = path.as_ref();
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("safetensors") => tensors.extend(crate::safetensors::load(path, &Device::Cpu)?),
+            Some("npz") => tensors.extend(Tensor::read_npz(path)?),
+            Some("pth" | "pt" | "bin") => tensors.extend(crate::pickle::read_all(path)?),
+            _ => crate::bail!("unsupported file extension for {path:?}"),
+        }
+    }
+    tensors.sort_by(|(n1, _), (n2, _)| n1.cmp(n2));
+    if let Some(w) = tensors.windows(2).find(|w| w[0].0 == w[1].0) {
+        crate::bail!("duplicate tensor {}", w[0].0)
+    }
+    Ok(tensors)
+}
+
+fn tensor_stats(name: &str, src: &Tensor, qtensor: &QTensor) -> Result<TensorStats> {
+    let src = src.to_dtype(DType::F32)?.flatten_all()?;
+    let diff = (qtensor.dequantize(src.device())?.flatten_all()? - &src)?;
+    let elem_count = src.elem_count().max(1) as f64;
+    let sum_sq = diff
+        .sqr()?
+        .sum_all()?
+        .to_dtype(DType::F64)?
+        .to_scalar::<f64>()?;
+    let src_sum_sq = src
+        .sqr()?
+        .sum_all()?
+        .to_dtype(DType::F64)?
+        .to_scalar::<f64>()?;
+    let max_abs_error = if src.elem_count() == 0 {
+        0f32
+    } else {
+        diff.abs()?.max(0)?.to_scalar::<f32>()?
+    };
+    let rmse = (sum_sq / elem_count).sqrt();
+    let src_rms = (src_sum_sq / elem_count).sqrt();
+    let rel_error = if src_rms > 0. { rmse / src_rms } else { 0. };
+    Ok(TensorStats {
+        name: name.to_string(),
+        shape: qtensor.shape().clone(),
+        dtype: qtensor.dtype(),
+        size_in_bytes: qtensor.storage_size_in_bytes(),
+        rmse: rmse as f32,
+        max_abs_error,
+        rel_error: rel_error as f32,
+    })
+}
+
+fn check_architecture(architecture: &str) -> Result<()> {
+    if architecture != "llama" {
+        crate::bail!("no tensor name mapping for architecture {architecture}")
+    }
+    Ok(())
+}
+
+/// The GGUF name of the tensor `name` from a Hugging Face checkpoint of the given architecture.
+/// `None` is returned for the tensors that are not written to GGUF files, e.g. the precomputed
+/// rotary embedding frequencies, and names that cannot be mapped result in an error.
+pub fn gguf_tensor_name(architecture: &str, name: &str) -> Result<Option<String>> {
+    check_architecture(architecture)?;
+    if name.ends_with(".rotary_emb.inv_freq") {
+        return Ok(None);
+    }
+    let gguf_name = name.rsplit_once('.').and_then(|(base, suffix)| {
+        if let Some((_, gguf)) = LLAMA_TENSOR_NAMES.iter().find(|(hf, _)| *hf == base) {
+            return Some(format!("{gguf}.{suffix}"));
+        }
+        let (layer_idx, base) = base.strip_prefix("model.layers.")?.split_once('.')?;
+        let layer_idx = layer_idx.parse::<usize>().ok()?;
+        let (_, gguf) = LLAMA_LAYER_TENSOR_NAMES
+            .iter()
+            .find(|(hf, _)| *hf == base)?;
+        Some(format!("blk.{layer_idx}.{gguf}.{suffix}"))
+    });
+    match gguf_name {
+        Some(gguf_name) => Ok(Some(gguf_name)),
+        None => crate::bail!("cannot map tensor {name} to a GGUF name for {architecture}"),
+    }
+}
+
+// Hugging Face checkpoints store the query and key projections for the rotate-half RoPE, the
+// GGUF models apply RoPE to interleaved pairs. This reorders the output rows of each head the
+// same way as `permute` in the llama.cpp conversion script.
+fn permute_for_interleaved_rope(tensor: &Tensor, n_head: usize) -> Result<Tensor> {
+    let dims = tensor.dims();
+    let rows = dims.first().copied().unwrap_or(0);
+    if n_head == 0 || rows % (2 * n_head) != 0 {
+        crate::bail!("cannot split {dims:?} in {n_head} heads for the RoPE permutation")
+    }
+    let mut shape = vec![n_head, 2, rows / n_head / 2];
+    shape.extend_from_slice(&dims[1..]);
+    tensor
+        .reshape(shape)?
+        .transpose(1, 2)?
+        .contiguous()?
+        .reshape(dims)
+}
+
+/// Converts the tensors of a Hugging Face checkpoint to the GGUF names and layouts for the
+/// architecture set in `config`, see [`gguf_tensor_name`]. The query and key projections are
+/// permuted for the interleaved RoPE using the head counts from the config metadata. The tensors
+/// are returned unchanged when no architecture is set.
+pub fn to_gguf_tensors(
+    tensors: Vec<(String, Tensor)>,
+    config: &Config,
+) -> Result<Vec<(String, Tensor)>> {
+    let architecture = match config.architecture() {
+        None => return Ok(tensors),
+        Some(architecture) => architecture,
+    };
+    let metadata = config.gguf_metadata()?;
+    let get_u32 = |key: &str| {
+        let key = format!("{architecture}.{key}");
+        metadata
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.to_u32())
+            .transpose()
+    };
+    let head_count = get_u32("attention.head_count")?.unwrap_or(0) as usize;
+    let head_count_kv = get_u32("attention.head_count_kv")?.map_or(head_count, |v| v as usize);
+    let mut gguf_tensors = vec![];
+    for (name, tensor) in tensors {
+        let gguf_name = match gguf_tensor_name(architecture, &name)? {
+            None => continue,
+            Some(gguf_name) => gguf_name,
+        };
+        let tensor = if gguf_name.ends_with(".attn_q.weight") {
+            permute_for_interleaved_rope(&tensor, head_count)?
+        } else if gguf_name.ends_with(".attn_k.weight") {
+            permute_for_interleaved_rope(&tensor, head_count_kv)?
+        } else {
+            tensor
+        };
+        gguf_tensors.push((gguf_name, tensor))
+    }
+    gguf_tensors.sort_by(|(n1, _), (n2, _)| n1.cmp(n2));
+    if let Some(w) = gguf_tensors.windows(2).find(|w| w[0].0 == w[1].0) {
+        crate::bail!("duplicate tensor {}", w[0].0)
+    }
+    Ok(gguf_tensors)
+}
+
+/// Quantizes the tensors according to `config`, the tensors are processed in parallel.
+pub fn quantize(
+    tensors: &[(String, Tensor)],
+    config: &Config,
+) -> Result<(Vec<(String, QTensor)>, Report)> {
+    let converted = tensors
+        .par_iter()
+        .map(|(name, tensor)| {
+            let dtype = config.dtype_for(name, tensor.shape());
+            let qtensor = QTensor::quantize(tensor, dtype)?;
+            let stats = tensor_stats(name, tensor, &qtensor)?;
+            Ok(((name.clone(), qtensor), stats))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let (qtensors, stats): (Vec<_>, Vec<_>) = converted.into_iter().unzip();
+    Ok((qtensors, Report { tensors: stats }))
+}
+
+/// Writes the quantized tensors to a GGUF file together with the metadata from `config` and
+/// `general.quantization_version`.
+pub fn write<W: std::io::Seek + std::io::Write>(
+    w: &mut W,
+    qtensors: &[(String, QTensor)],
+    config: &Config,
+) -> Result<()> {
+    let quantization_version = gguf_file::Value::U32(QUANTIZATION_VERSION);
+    let metadata = config.gguf_metadata()?;
+    let mut metadata = metadata
+        .iter()
+        .map(|(k, v)| (k.as_str(), v))
+        .collect::<Vec<_>>();
+    if !metadata
+        .iter()
+        .any(|(k, _)| *k == "general.quantization_version")
+    {
+        metadata.push(("general.quantization_version", &quantization_version))
+    }
+    let qtensors = qtensors
+        .iter()
+        .map(|(n, t)| (n.as_str(), t))
+        .collect::<Vec<_>>();
+    gguf_file::write(w, &metadata, &qtensors)
+}
+
+/// Loads the tensors from `inputs`, converts them to the GGUF names with [`to_gguf_tensors`],
+/// quantizes them according to `config` and writes the result to the GGUF file `output`.
+pub fn convert<P: AsRef<std::path::Path>, Q: AsRef<std::path::Path>>(
+    inputs: &[P],
+    output: Q,
+    config: &Config,
+) -> Result<Report> {
+    // Check the metadata before the potentially long quantization.
+    config.gguf_metadata()?;
+    if let Some(architecture) = config.architecture() {
+        check_architecture(architecture)?;
+    }
+    let tensors = to_gguf_tensors(load(inputs)?, config)?;
+    let (qtensors, report) = quantize(&tensors, config)?;
+    let mut out = std::io::BufWriter::new(std::fs::File::create(output)?);
+    write(&mut out, &qtensors, config)?;
+    std::io::Write::flush(&mut out)?;
+    Ok(report)
+}
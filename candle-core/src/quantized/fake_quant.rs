@@ -0,0 +1,228 @@
+//! Fake quantization, used to simulate the effect of quantization during training.
+//!
+//! The quantization parameters are derived from the min/max values of the tensor, either over
+//! the whole tensor, per channel, or per group of consecutive values on the last dimension as
+//! done by the GGML block formats. The rounding itself is done by [`Tensor::fake_quantize`] which
+//! uses a straight-through estimator for the backward pass.
+use super::GgmlDType;
+use crate::{CpuStorage, DType, Layout, Result, Shape, Tensor, WithDType};
+use num_traits::Float;
+
+// The custom op behind [`Tensor::fake_quantize`], `scale` and `zero_point` are detached, on the
+// cpu and broadcast to the shape of the argument.
+pub(crate) struct FakeQuantizeOp {
+    pub(crate) scale: Tensor,
+    pub(crate) zero_point: Tensor,
+    pub(crate) quant_min: i64,
+    pub(crate) quant_max: i64,
+}
+
+impl crate::CustomOp1 for FakeQuantizeOp {
+    fn name(&self) -> &'static str {
+        "fake-quantize"
+    }
+
+    fn cpu_fwd(&self, storage: &CpuStorage, layout: &Layout) -> Result<(CpuStorage, Shape)> {
+        // The computations use the dtype of the argument so that the rounding matches the
+        // equivalent tensor ops.
+        fn fake_quantize<T: WithDType + Float>(
+            src: &[T],
+            layout: &Layout,
+            op: &FakeQuantizeOp,
+        ) -> Result<Vec<T>> {
+            let host = |t: &Tensor| t.to_dtype(T::DTYPE)?.flatten_all()?.to_vec1::<T>();
+            let scale = host(&op.scale)?;
+            let zero_point = host(&op.zero_point)?;
+            let quant_min = T::from_f64(op.quant_min as f64);
+            let quant_max = T::from_f64(op.quant_max as f64);
+            let dst = layout
+                .strided_index()
+                .zip(scale.iter().zip(zero_point.iter()))
+                .map(|(i, (&s, &z))| {
+                    let q = (src[i] / s).round() + z;
+                    let q = Float::min(Float::max(q, quant_min), quant_max);
+                    (q - z) * s
+                })
+                .collect();
+            Ok(dst)
+        }
+
+        let storage = match storage {
+            CpuStorage::BF16(s) => CpuStorage::BF16(fake_quantize(s, layout, self)?),
+            CpuStorage::F16(s) => CpuStorage::F16(fake_quantize(s, layout, self)?),
+            CpuStorage::F32(s) => CpuStorage::F32(fake_quantize(s, layout, self)?),
+            CpuStorage::F64(s) => CpuStorage::F64(fake_quantize(s, layout, self)?),
+            _ => crate::bail!(
+                "fake_quantize: unsupported dtype {:?}",
+                crate::backend::BackendStorage::dtype(storage)
+            ),
+        };
+        Ok((storage, layout.shape().clone()))
+    }
+
+    fn bwd(&self, arg: &Tensor, _res: &Tensor, grad_res: &Tensor) -> Result<Option<Tensor>> {
+        // Straight-through estimator, the gradient is zeroed for the clipped values.
+        let scale = self.scale.to_dtype(arg.dtype())?;
+        let zero_point = self.zero_point.to_dtype(arg.dtype())?;
+        let xs = ((arg / scale)?.round()? + zero_point)?;
+        let in_range = xs
+            .ge(self.quant_min as f64)?
+            .mul(&xs.le(self.quant_max as f64)?)?
+            .to_dtype(grad_res.dtype())?;
+        Ok(Some((grad_res * in_range)?))
+    }
+}
+
+/// The set of values that share the same quantization parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Granularity {
+    PerTensor,
+    /// One scale per index along the given dimension.
+    PerChannel(usize),
+    /// One scale per group of this many consecutive elements along the last dimension.
+    PerGroup(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FakeQuantizer {
+    bits: u32,
+    symmetric: bool,
+    granularity: Granularity,
+}
+
+impl FakeQuantizer {
+    /// Symmetric quantizers use a zero point of 0 and the range `[-2^(bits-1)+1, 2^(bits-1)-1]`,
+    /// asymmetric ones use the range `[0, 2^bits-1]` and a zero point such that 0 is exactly
+    /// representable.
+    pub fn new(bits: u32, symmetric: bool, granularity: Granularity) -> Result<Self> {
+        if !(2..=16).contains(&bits) {
+            crate::bail!("fake quantization only supports 2 to 16 bits, got {bits}")
+        }
+        if granularity == Granularity::PerGroup(0) {
+            crate::bail!("fake quantization group size cannot be 0")
+        }
+        Ok(Self {
+            bits,
+            symmetric,
+            granularity,
+        })
+    }
+
+    /// A quantizer approximating the given GGML type, the block scales of the k-quants are not
+    /// themselves quantized here.
+    pub fn for_ggml_dtype(dtype: GgmlDType) -> Result<Self> {
+        let (bits, symmetric, group_size) = match dtype {
+            GgmlDType::Q4_0 => (4, true, 32),
+            GgmlDType::Q4_1 => (4, false, 32),
+            GgmlDType::Q5_0 => (5, true, 32),
+            GgmlDType::Q5_1 => (5, false, 32),
+            GgmlDType::Q8_0 | GgmlDType::Q8_1 => (8, true, 32),
+            GgmlDType::Q2K => (2, false, 16),
+            GgmlDType::Q3K => (3, true, 16),
+            GgmlDType::Q4K => (4, false, 32),
+            GgmlDType::Q5K => (5, false, 32),
+            GgmlDType::Q6K => (6, true, 16),
+            GgmlDType::Q8K => (8, true, 256),
+            dtype => crate::bail!("no fake quantizer for {dtype:?}"),
+        };
+        Self::new(bits, symmetric, Granularity::PerGroup(group_size))
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    pub fn symmetric(&self) -> bool {
+        self.symmetric
+    }
+
+    pub fn granularity(&self) -> Granularity {
+        self.granularity
+    }
+
+    /// The range of the quantized integer values.
+    pub fn quant_range(&self) -> (i64, i64) {
+        if self.symmetric {
+            let max = (1 << (self.bits - 1)) - 1;
+            (-max, max)
+        } else {
+            (0, (1 << self.bits) - 1)
+        }
+    }
+
+    /// Returns `xs` reshaped so that the values sharing quantization parameters are contiguous
+    /// along the last dimension, with the leading dimension being the number of such sets.
+    fn grouped(&self, xs: &Tensor) -> Result<Tensor> {
+        match self.granularity {
+            Granularity::PerTensor => xs.flatten_all()?.unsqueeze(0),
+            Granularity::PerChannel(dim) => {
+                let channels = xs.dim(dim)?;
+                xs.transpose(0, dim)?.contiguous()?.reshape((channels, ()))
+            }
+            Granularity::PerGroup(group_size) => {
+                let last_dim = xs.dims().last().copied().unwrap_or(1);
+                if last_dim % group_size != 0 {
+                    crate::bail!(
+                        "last dim of {:?} is not divisible by the group size {group_size}",
+                        xs.shape()
+                    )
+                }
+                xs.reshape(((), group_size))
+            }
+        }
+    }
+
+    /// Computes the scale and zero point from the min/max values of `xs`. These are scalars for
+    /// per-tensor quantization and have a shape that broadcasts to `xs` for per-channel
+    /// quantization. For per-group quantization the shape is `(n_groups, 1)` and `xs` has to be
+    /// reshaped to `(n_groups, group_size)`.
+    pub fn qparams(&self, xs: &Tensor) -> Result<(Tensor, Tensor)> {
+        let grouped = self.grouped(&xs.detach()?.to_dtype(DType::F32)?)?;
+        let min = grouped.min_keepdim(1)?.minimum(0f64)?;
+        let max = grouped.max_keepdim(1)?.maximum(0f64)?;
+        let (quant_min, quant_max) = self.quant_range();
+        let (scale, zero_point) = if self.symmetric {
+            let scale = (min.abs()?.maximum(&max)? / quant_max as f64)?;
+            let scale = scale.maximum(f32::EPSILON as f64)?;
+            let zero_point = scale.zeros_like()?;
+            (scale, zero_point)
+        } else {
+            let scale = ((max - &min)? / (quant_max - quant_min) as f64)?;
+            let scale = scale.maximum(f32::EPSILON as f64)?;
+            let zero_point = (min / &scale)?
+                .round()?
+                .neg()?
+                .affine(1., quant_min as f64)?
+                .clamp(quant_min as f64, quant_max as f64)?;
+            (scale, zero_point)
+        };
+        match self.granularity {
+            Granularity::PerTensor => Ok((scale.reshape(())?, zero_point.reshape(())?)),
+            Granularity::PerChannel(dim) => {
+                let mut shape = vec![1; xs.rank()];
+                shape[dim] = xs.dim(dim)?;
+                Ok((scale.reshape(shape.as_slice())?, zero_point.reshape(shape)?))
+            }
+            Granularity::PerGroup(_) => Ok((scale, zero_point)),
+        }
+    }
+
+    /// Fake quantizes `xs` using quantization parameters derived from its own values.
+    pub fn fake_quantize(&self, xs: &Tensor) -> Result<Tensor> {
+        let (scale, zero_point) = self.qparams(xs)?;
+        let (scale, zero_point) = (
+            scale.to_dtype(xs.dtype())?,
+            zero_point.to_dtype(xs.dtype())?,
+        );
+        let (quant_min, quant_max) = self.quant_range();
+        match self.granularity {
+            Granularity::PerTensor | Granularity::PerChannel(_) => {
+                xs.fake_quantize(&scale, &zero_point, quant_min, quant_max)
+            }
+            Granularity::PerGroup(_) => self
+                .grouped(xs)?
+                .fake_quantize(&scale, &zero_point, quant_min, quant_max)?
+                .reshape(xs.shape()),
+        }
+    }
+}
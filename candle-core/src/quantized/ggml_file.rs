@@ -1,10 +1,13 @@
 //! Support for the GGML file format.
+//!
+//! These are the legacy formats used by llama.cpp before GGUF, the files can be read and written
+//! and [`Content::write_gguf`] upgrades them to GGUF.
 
 #[cfg(feature = "metal")]
 use super::metal::load_quantized_metal;
-use super::{k_quants, GgmlDType, QStorage};
+use super::{gguf_file, k_quants, GgmlDType, QStorage};
 use crate::{Device, Result};
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::collections::HashMap;
 
 // https://github.com/ggerganov/llama.cpp/blob/468ea24fb4633a0d681f7ac84089566c1c6190cb/llama.h#L37
@@ -32,6 +35,18 @@ impl TryFrom<u32> for Magic {
     }
 }
 
+impl Magic {
+    fn to_u32(self) -> u32 {
+        match self {
+            Self::Ggjt => 0x67676a74,
+            Self::Ggla => 0x67676c61,
+            Self::Ggmf => 0x67676d66,
+            Self::Ggml => 0x67676d6c,
+            Self::Ggsn => 0x6767736e,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum VersionedMagic {
     GgmlUnversioned,
@@ -59,6 +74,21 @@ impl VersionedMagic {
         Ok(versioned_magic)
     }
 
+    fn write<W: std::io::Write>(&self, w: &mut W) -> Result<()> {
+        let (magic, version) = match self {
+            Self::GgmlUnversioned => (Magic::Ggml, None),
+            Self::GgmfV1 => (Magic::Ggmf, Some(1)),
+            Self::GgjtV1 => (Magic::Ggjt, Some(1)),
+            Self::GgjtV2 => (Magic::Ggjt, Some(2)),
+            Self::GgjtV3 => (Magic::Ggjt, Some(3)),
+        };
+        w.write_u32::<LittleEndian>(magic.to_u32())?;
+        if let Some(version) = version {
+            w.write_u32::<LittleEndian>(version)?;
+        }
+        Ok(())
+    }
+
     fn align32(&self) -> bool {
         match self {
             Self::GgmlUnversioned | Self::GgmfV1 => false,
@@ -97,6 +127,17 @@ impl HParams {
             ftype,
         })
     }
+
+    fn write<W: std::io::Write>(&self, w: &mut W) -> Result<()> {
+        w.write_u32::<LittleEndian>(self.n_vocab)?;
+        w.write_u32::<LittleEndian>(self.n_embd)?;
+        w.write_u32::<LittleEndian>(self.n_mult)?;
+        w.write_u32::<LittleEndian>(self.n_head)?;
+        w.write_u32::<LittleEndian>(self.n_layer)?;
+        w.write_u32::<LittleEndian>(self.n_rot)?;
+        w.write_u32::<LittleEndian>(self.ftype)?;
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -117,6 +158,15 @@ impl Vocab {
         }
         Ok(Self { token_score_pairs })
     }
+
+    fn write<W: std::io::Write>(&self, w: &mut W) -> Result<()> {
+        for (word, score) in self.token_score_pairs.iter() {
+            w.write_u32::<LittleEndian>(word.len() as u32)?;
+            w.write_all(word)?;
+            w.write_f32::<LittleEndian>(*score)?;
+        }
+        Ok(())
+    }
 }
 
 fn from_raw_data<T: super::GgmlType + Send + Sync + 'static>(
@@ -129,7 +179,7 @@ fn from_raw_data<T: super::GgmlType + Send + Sync + 'static>(
     let n_blocks = size_in_bytes / std::mem::size_of::<T>();
     let data = unsafe { std::slice::from_raw_parts(raw_data_ptr as *const T, n_blocks) };
     let data: QStorage = match device {
-        Device::Cpu => QStorage::Cpu(Box::new(data.to_vec())),
+        Device::Cpu | Device::CpuPool(_) => QStorage::Cpu(Box::new(data.to_vec())),
         #[cfg(feature = "metal")]
         Device::Metal(metal) => load_quantized_metal(metal, data)?,
         #[cfg(not(feature = "metal"))]
@@ -190,6 +240,33 @@ pub fn qtensor_from_ggml(
         GgmlDType::Q6K => {
             from_raw_data::<k_quants::BlockQ6K>(raw_data, size_in_bytes, dims, device)
         }
+        GgmlDType::IQ2XXS => {
+            from_raw_data::<k_quants::BlockIQ2XXS>(raw_data, size_in_bytes, dims, device)
+        }
+        GgmlDType::IQ2XS => {
+            from_raw_data::<k_quants::BlockIQ2XS>(raw_data, size_in_bytes, dims, device)
+        }
+        GgmlDType::IQ2S => {
+            from_raw_data::<k_quants::BlockIQ2S>(raw_data, size_in_bytes, dims, device)
+        }
+        GgmlDType::IQ3XXS => {
+            from_raw_data::<k_quants::BlockIQ3XXS>(raw_data, size_in_bytes, dims, device)
+        }
+        GgmlDType::IQ3S => {
+            from_raw_data::<k_quants::BlockIQ3S>(raw_data, size_in_bytes, dims, device)
+        }
+        GgmlDType::IQ1S => {
+            from_raw_data::<k_quants::BlockIQ1S>(raw_data, size_in_bytes, dims, device)
+        }
+        GgmlDType::IQ1M => {
+            from_raw_data::<k_quants::BlockIQ1M>(raw_data, size_in_bytes, dims, device)
+        }
+        GgmlDType::IQ4NL => {
+            from_raw_data::<k_quants::BlockIQ4NL>(raw_data, size_in_bytes, dims, device)
+        }
+        GgmlDType::IQ4XS => {
+            from_raw_data::<k_quants::BlockIQ4XS>(raw_data, size_in_bytes, dims, device)
+        }
         _ => crate::bail!("quantized type {ggml_dtype:?} is not supported yet"),
     }
 }
@@ -228,6 +305,87 @@ fn read_one_tensor<R: std::io::Seek + std::io::Read>(
     }
 }
 
+fn write_one_tensor<W: std::io::Seek + std::io::Write>(
+    w: &mut W,
+    magic: VersionedMagic,
+    name: &str,
+    tensor: &super::QTensor,
+) -> Result<()> {
+    let dims = tensor.shape().dims();
+    w.write_u32::<LittleEndian>(dims.len() as u32)?;
+    w.write_u32::<LittleEndian>(name.len() as u32)?;
+    w.write_u32::<LittleEndian>(tensor.dtype().to_u32())?;
+    for &dim in dims.iter().rev() {
+        w.write_u32::<LittleEndian>(dim as u32)?;
+    }
+    w.write_all(name.as_bytes())?;
+    if magic.align32() {
+        let pos = w.stream_position()?;
+        w.write_all(&vec![0u8; ((32 - pos % 32) % 32) as usize])?;
+    }
+    w.write_all(&tensor.data()?)?;
+    Ok(())
+}
+
+/// Writes a file in one of the legacy GGML formats, the tensors are written in the provided
+/// order.
+pub fn write<W: std::io::Seek + std::io::Write>(
+    w: &mut W,
+    magic: VersionedMagic,
+    hparams: &HParams,
+    vocab: &Vocab,
+    tensors: &[(&str, &super::QTensor)],
+) -> Result<()> {
+    if vocab.token_score_pairs.len() != hparams.n_vocab as usize {
+        crate::bail!(
+            "vocab size {} does not match n_vocab {}",
+            vocab.token_score_pairs.len(),
+            hparams.n_vocab
+        )
+    }
+    magic.write(w)?;
+    hparams.write(w)?;
+    vocab.write(w)?;
+    for (name, tensor) in tensors.iter() {
+        write_one_tensor(w, magic, name, tensor)?
+    }
+    Ok(())
+}
+
+/// Maps the llama tensor names used in the GGML files to the GGUF ones, names that are not
+/// recognized are kept as is.
+pub fn gguf_tensor_name(name: &str) -> String {
+    let global = match name {
+        "tok_embeddings.weight" => Some("token_embd.weight"),
+        "norm.weight" => Some("output_norm.weight"),
+        "output.weight" => Some("output.weight"),
+        _ => None,
+    };
+    if let Some(global) = global {
+        return global.to_string();
+    }
+    if let Some(rest) = name.strip_prefix("layers.") {
+        if let Some((layer_idx, rest)) = rest.split_once('.') {
+            let rest = match rest {
+                "attention.wq.weight" => Some("attn_q.weight"),
+                "attention.wk.weight" => Some("attn_k.weight"),
+                "attention.wv.weight" => Some("attn_v.weight"),
+                "attention.wo.weight" => Some("attn_output.weight"),
+                "attention_norm.weight" => Some("attn_norm.weight"),
+                "feed_forward.w1.weight" => Some("ffn_gate.weight"),
+                "feed_forward.w2.weight" => Some("ffn_down.weight"),
+                "feed_forward.w3.weight" => Some("ffn_up.weight"),
+                "ffn_norm.weight" => Some("ffn_norm.weight"),
+                _ => None,
+            };
+            if let (Ok(layer_idx), Some(rest)) = (layer_idx.parse::<usize>(), rest) {
+                return format!("blk.{layer_idx}.{rest}");
+            }
+        }
+    }
+    name.to_string()
+}
+
 pub struct Content {
     pub magic: VersionedMagic,
     pub hparams: HParams,
@@ -266,4 +424,125 @@ impl Content {
             Some(tensor) => Ok(tensor),
         }
     }
+
+    /// Writes the content using its original format, the tensors are sorted by name.
+    pub fn write<W: std::io::Seek + std::io::Write>(&self, w: &mut W) -> Result<()> {
+        let mut tensors = self
+            .tensors
+            .iter()
+            .map(|(name, tensor)| (name.as_str(), tensor))
+            .collect::<Vec<_>>();
+        tensors.sort_by(|a, b| a.0.cmp(b.0));
+        write(w, self.magic, &self.hparams, &self.vocab, &tensors)
+    }
+
+    /// The GGUF metadata for this llama model. The hyperparameters that are not part of the GGML
+    /// header are inferred from the tensor shapes, the context length is set to 2048 and the rms
+    /// norm epsilon to 1e-5.
+    pub fn gguf_metadata(&self) -> Result<Vec<(String, gguf_file::Value)>> {
+        use gguf_file::Value;
+
+        let hp = &self.hparams;
+        if hp.n_head == 0 || hp.n_embd % hp.n_head != 0 {
+            crate::bail!(
+                "n_embd {} is not divisible by n_head {}",
+                hp.n_embd,
+                hp.n_head
+            )
+        }
+        let head_dim = hp.n_embd / hp.n_head;
+        let dim0 = |name: &str| match self.tensors.get(name) {
+            None => crate::bail!("cannot find tensor with name '{name}'"),
+            Some(tensor) => Ok(tensor.shape().dims()[0] as u32),
+        };
+        let n_ff = dim0("layers.0.feed_forward.w1.weight")?;
+        let n_head_kv = dim0("layers.0.attention.wk.weight")? / head_dim;
+
+        let mut tokens = Vec::with_capacity(self.vocab.token_score_pairs.len());
+        let mut scores = Vec::with_capacity(self.vocab.token_score_pairs.len());
+        let mut token_types = Vec::with_capacity(self.vocab.token_score_pairs.len());
+        for (token_id, (word, score)) in self.vocab.token_score_pairs.iter().enumerate() {
+            // Same token types as in sentencepiece: 1 normal, 3 control, 6 byte. The GGML files
+            // store the decoded pieces so the spaces are mapped back to U+2581.
+            let (token, token_type) = match word.as_slice() {
+                [] => (String::new(), 3),
+                [byte] if (3..259).contains(&token_id) => (format!("<0x{byte:02X}>"), 6),
+                word => (String::from_utf8_lossy(word).replace(' ', "\u{2581}"), 1),
+            };
+            tokens.push(Value::String(token));
+            scores.push(Value::F32(*score));
+            token_types.push(Value::I32(token_type));
+        }
+
+        let metadata = vec![
+            ("general.architecture", Value::String("llama".to_string())),
+            ("general.name", Value::String("llama".to_string())),
+            // The ggml format encodes the quantization version in the thousands.
+            ("general.file_type", Value::U32(hp.ftype % 1000)),
+            ("llama.context_length", Value::U32(2048)),
+            ("llama.embedding_length", Value::U32(hp.n_embd)),
+            ("llama.block_count", Value::U32(hp.n_layer)),
+            ("llama.feed_forward_length", Value::U32(n_ff)),
+            ("llama.rope.dimension_count", Value::U32(hp.n_rot)),
+            ("llama.attention.head_count", Value::U32(hp.n_head)),
+            ("llama.attention.head_count_kv", Value::U32(n_head_kv)),
+            ("llama.attention.layer_norm_rms_epsilon", Value::F32(1e-5)),
+            ("tokenizer.ggml.model", Value::String("llama".to_string())),
+            ("tokenizer.ggml.tokens", Value::Array(tokens)),
+            ("tokenizer.ggml.scores", Value::Array(scores)),
+            ("tokenizer.ggml.token_type", Value::Array(token_types)),
+            ("tokenizer.ggml.unknown_token_id", Value::U32(0)),
+            ("tokenizer.ggml.bos_token_id", Value::U32(1)),
+            ("tokenizer.ggml.eos_token_id", Value::U32(2)),
+        ];
+        Ok(metadata
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect())
+    }
+
+    /// Converts the content to a GGUF file, the tensor data is copied without requantization.
+    /// The entries of `metadata` are added to the inferred metadata, replacing the inferred
+    /// values that use the same keys.
+    ///
+    /// The quantized block layouts changed with ggjt v3 so only the files using this version can
+    /// contain quantized tensors, the files using older versions are limited to f32 and f16.
+    pub fn write_gguf<W: std::io::Seek + std::io::Write>(
+        &self,
+        w: &mut W,
+        metadata: &[(&str, &gguf_file::Value)],
+    ) -> Result<()> {
+        if self.magic != VersionedMagic::GgjtV3 {
+            let quantized = self
+                .tensors
+                .iter()
+                .find(|(_, t)| !matches!(t.dtype(), GgmlDType::F32 | GgmlDType::F16));
+            if let Some((name, tensor)) = quantized {
+                crate::bail!(
+                    "cannot convert {:?} tensor '{name}' from a {:?} file, only ggjt v3 quantized blocks are supported",
+                    tensor.dtype(),
+                    self.magic
+                )
+            }
+        }
+        let mut md = self.gguf_metadata()?;
+        for (key, value) in metadata.iter() {
+            match md.iter_mut().find(|(k, _)| k == key) {
+                Some((_, v)) => *v = (*value).clone(),
+                None => md.push((key.to_string(), (*value).clone())),
+            }
+        }
+        let md = md.iter().map(|(k, v)| (k.as_str(), v)).collect::<Vec<_>>();
+        let mut tensors = self
+            .tensors
+            .iter()
+            .map(|(name, tensor)| (gguf_tensor_name(name), tensor))
+            .collect::<Vec<_>>();
+        tensors.sort_by(|a, b| a.0.cmp(&b.0));
+        let tensors = tensors
+            .iter()
+            .map(|(name, tensor)| (name.as_str(), *tensor))
+            .collect::<Vec<_>>();
+        gguf_file::write(w, &md, &tensors)
+    }
 }
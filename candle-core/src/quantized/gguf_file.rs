@@ -4,8 +4,9 @@
 
 use super::{GgmlDType, QTensor};
 use crate::{Device, Result};
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::collections::HashMap;
+use std::sync::Arc;
 
 pub const DEFAULT_ALIGNMENT: u64 = 32;
 
@@ -32,18 +33,60 @@ pub enum VersionedMagic {
     GgufV3,
 }
 
+/// The byte order used for the header values and the tensor data of a GGUF file.
+///
+/// Only F32 and F16 tensors are supported in big-endian files, the scales stored within the
+/// quantized blocks would have to be byte-swapped depending on the layout of each block type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
+}
+
+impl Endianness {
+    fn check_dtype(&self, dtype: GgmlDType) -> Result<()> {
+        match (self, dtype) {
+            (Self::Little, _) | (Self::Big, GgmlDType::F32 | GgmlDType::F16) => Ok(()),
+            (Self::Big, dtype) => {
+                crate::bail!("gguf: {dtype:?} tensors are not supported in big-endian files")
+            }
+        }
+    }
+
+    fn swap_bytes(&self, data: &mut [u8], dtype: GgmlDType) -> Result<()> {
+        self.check_dtype(dtype)?;
+        let elem_size = match dtype {
+            GgmlDType::F32 => 4,
+            GgmlDType::F16 => 2,
+            _ => return Ok(()),
+        };
+        if *self == Self::Big {
+            data.chunks_exact_mut(elem_size).for_each(|v| v.reverse())
+        }
+        Ok(())
+    }
+}
+
 impl VersionedMagic {
-    fn read<R: std::io::Read>(reader: &mut R) -> Result<Self> {
+    fn read<R: std::io::Read>(reader: &mut R) -> Result<(Self, Endianness)> {
         let magic = reader.read_u32::<LittleEndian>()?;
         let magic = Magic::try_from(magic)?;
         let version = reader.read_u32::<LittleEndian>()?;
+        // The magic is the same for both byte orders, the version is not so the endianness
+        // gets detected from it: big-endian files have the version in the upper bytes.
+        let (version, endianness) = if version & 0xffff == 0 {
+            (version.swap_bytes(), Endianness::Big)
+        } else {
+            (version, Endianness::Little)
+        };
         let versioned_magic = match (magic, version) {
             (Magic::Gguf, 1) => Self::GgufV1,
             (Magic::Gguf, 2) => Self::GgufV2,
             (Magic::Gguf, 3) => Self::GgufV3,
             _ => crate::bail!("gguf: unsupported magic/version {magic:?}/{version}"),
         };
-        Ok(versioned_magic)
+        Ok((versioned_magic, endianness))
     }
 }
 
@@ -61,17 +104,20 @@ impl TensorInfo {
         tensor_data_offset: u64,
         device: &Device,
     ) -> Result<QTensor> {
-        let tensor_elems = self.shape.elem_count();
-        let block_size = self.ggml_dtype.block_size();
-        if tensor_elems % block_size != 0 {
-            crate::bail!(
-            "the number of elements {tensor_elems} is not divisible by the block size {block_size}"
-        )
-        }
-        let size_in_bytes = tensor_elems / block_size * self.ggml_dtype.type_size();
-        let mut raw_data = vec![0u8; size_in_bytes];
+        self.read_with_endianness(reader, tensor_data_offset, Endianness::Little, device)
+    }
+
+    pub fn read_with_endianness<R: std::io::Seek + std::io::Read>(
+        &self,
+        reader: &mut R,
+        tensor_data_offset: u64,
+        endianness: Endianness,
+        device: &Device,
+    ) -> Result<QTensor> {
+        let mut raw_data = vec![0u8; self.size_in_bytes()?];
         reader.seek(std::io::SeekFrom::Start(tensor_data_offset + self.offset))?;
         reader.read_exact(&mut raw_data)?;
+        endianness.swap_bytes(&mut raw_data, self.ggml_dtype)?;
         super::ggml_file::qtensor_from_ggml(
             self.ggml_dtype,
             &raw_data,
@@ -79,23 +125,44 @@ impl TensorInfo {
             device,
         )
     }
+
+    pub fn size_in_bytes(&self) -> Result<usize> {
+        let tensor_elems = self.shape.elem_count();
+        let block_size = self.ggml_dtype.block_size();
+        if tensor_elems % block_size != 0 {
+            crate::bail!(
+            "the number of elements {tensor_elems} is not divisible by the block size {block_size}"
+        )
+        }
+        Ok(tensor_elems / block_size * self.ggml_dtype.type_size())
+    }
 }
 
 #[derive(Debug)]
 pub struct Content {
     pub magic: VersionedMagic,
+    pub endianness: Endianness,
     pub metadata: HashMap<String, Value>,
     pub tensor_infos: HashMap<String, TensorInfo>,
     pub tensor_data_offset: u64,
 }
 
-fn read_string<R: std::io::Read>(reader: &mut R, magic: &VersionedMagic) -> Result<String> {
+fn read_len<R: std::io::Read, B: ByteOrder>(
+    reader: &mut R,
+    magic: &VersionedMagic,
+) -> Result<usize> {
     let len = match magic {
-        VersionedMagic::GgufV1 => reader.read_u32::<LittleEndian>()? as usize,
-        VersionedMagic::GgufV2 | VersionedMagic::GgufV3 => {
-            reader.read_u64::<LittleEndian>()? as usize
-        }
+        VersionedMagic::GgufV1 => reader.read_u32::<B>()? as usize,
+        VersionedMagic::GgufV2 | VersionedMagic::GgufV3 => reader.read_u64::<B>()? as usize,
     };
+    Ok(len)
+}
+
+fn read_string<R: std::io::Read, B: ByteOrder>(
+    reader: &mut R,
+    magic: &VersionedMagic,
+) -> Result<String> {
+    let len = read_len::<R, B>(reader, magic)?;
     let mut v = vec![0u8; len];
     reader.read_exact(&mut v)?;
     // GGUF strings are supposed to be non-null terminated but in practice this happens.
@@ -267,7 +334,7 @@ impl Value {
         }
     }
 
-    fn read<R: std::io::Read>(
+    fn read<R: std::io::Read, B: ByteOrder>(
         reader: &mut R,
         value_type: ValueType,
         magic: &VersionedMagic,
@@ -275,32 +342,27 @@ impl Value {
         let v = match value_type {
             ValueType::U8 => Self::U8(reader.read_u8()?),
             ValueType::I8 => Self::I8(reader.read_i8()?),
-            ValueType::U16 => Self::U16(reader.read_u16::<LittleEndian>()?),
-            ValueType::I16 => Self::I16(reader.read_i16::<LittleEndian>()?),
-            ValueType::U32 => Self::U32(reader.read_u32::<LittleEndian>()?),
-            ValueType::I32 => Self::I32(reader.read_i32::<LittleEndian>()?),
-            ValueType::U64 => Self::U64(reader.read_u64::<LittleEndian>()?),
-            ValueType::I64 => Self::I64(reader.read_i64::<LittleEndian>()?),
-            ValueType::F32 => Self::F32(reader.read_f32::<LittleEndian>()?),
-            ValueType::F64 => Self::F64(reader.read_f64::<LittleEndian>()?),
+            ValueType::U16 => Self::U16(reader.read_u16::<B>()?),
+            ValueType::I16 => Self::I16(reader.read_i16::<B>()?),
+            ValueType::U32 => Self::U32(reader.read_u32::<B>()?),
+            ValueType::I32 => Self::I32(reader.read_i32::<B>()?),
+            ValueType::U64 => Self::U64(reader.read_u64::<B>()?),
+            ValueType::I64 => Self::I64(reader.read_i64::<B>()?),
+            ValueType::F32 => Self::F32(reader.read_f32::<B>()?),
+            ValueType::F64 => Self::F64(reader.read_f64::<B>()?),
             ValueType::Bool => match reader.read_u8()? {
                 0 => Self::Bool(false),
                 1 => Self::Bool(true),
                 b => crate::bail!("unexpected bool value {b}"),
             },
-            ValueType::String => Self::String(read_string(reader, magic)?),
+            ValueType::String => Self::String(read_string::<R, B>(reader, magic)?),
             ValueType::Array => {
-                let value_type = reader.read_u32::<LittleEndian>()?;
+                let value_type = reader.read_u32::<B>()?;
                 let value_type = ValueType::from_u32(value_type)?;
-                let len = match magic {
-                    VersionedMagic::GgufV1 => reader.read_u32::<LittleEndian>()? as usize,
-                    VersionedMagic::GgufV2 | VersionedMagic::GgufV3 => {
-                        reader.read_u64::<LittleEndian>()? as usize
-                    }
-                };
+                let len = read_len::<R, B>(reader, magic)?;
                 let mut vs = Vec::with_capacity(len);
                 for _ in 0..len {
-                    vs.push(Value::read(reader, value_type, magic)?)
+                    vs.push(Value::read::<R, B>(reader, value_type, magic)?)
                 }
                 Self::Array(vs)
             }
@@ -308,20 +370,20 @@ impl Value {
         Ok(v)
     }
 
-    fn write<W: std::io::Write>(&self, w: &mut W) -> Result<()> {
+    fn write<W: std::io::Write, B: ByteOrder>(&self, w: &mut W) -> Result<()> {
         match self {
             &Self::U8(v) => w.write_u8(v)?,
             &Self::I8(v) => w.write_i8(v)?,
-            &Self::U16(v) => w.write_u16::<LittleEndian>(v)?,
-            &Self::I16(v) => w.write_i16::<LittleEndian>(v)?,
-            &Self::U32(v) => w.write_u32::<LittleEndian>(v)?,
-            &Self::I32(v) => w.write_i32::<LittleEndian>(v)?,
-            &Self::U64(v) => w.write_u64::<LittleEndian>(v)?,
-            &Self::I64(v) => w.write_i64::<LittleEndian>(v)?,
-            &Self::F32(v) => w.write_f32::<LittleEndian>(v)?,
-            &Self::F64(v) => w.write_f64::<LittleEndian>(v)?,
+            &Self::U16(v) => w.write_u16::<B>(v)?,
+            &Self::I16(v) => w.write_i16::<B>(v)?,
+            &Self::U32(v) => w.write_u32::<B>(v)?,
+            &Self::I32(v) => w.write_i32::<B>(v)?,
+            &Self::U64(v) => w.write_u64::<B>(v)?,
+            &Self::I64(v) => w.write_i64::<B>(v)?,
+            &Self::F32(v) => w.write_f32::<B>(v)?,
+            &Self::F64(v) => w.write_f64::<B>(v)?,
             &Self::Bool(v) => w.write_u8(u8::from(v))?,
-            Self::String(v) => write_string(w, v.as_str())?,
+            Self::String(v) => write_string::<W, B>(w, v.as_str())?,
             Self::Array(v) => {
                 // The `Value` type does not enforce that all the values in an Array have the same
                 // type.
@@ -336,10 +398,10 @@ impl Value {
                     }
                     value_type.into_iter().next().unwrap()
                 };
-                w.write_u32::<LittleEndian>(value_type.to_u32())?;
-                w.write_u64::<LittleEndian>(v.len() as u64)?;
+                w.write_u32::<B>(value_type.to_u32())?;
+                w.write_u64::<B>(v.len() as u64)?;
                 for elem in v.iter() {
-                    elem.write(w)?
+                    elem.write::<W, B>(w)?
                 }
             }
         }
@@ -389,51 +451,51 @@ impl ValueType {
 
 impl Content {
     pub fn read<R: std::io::Seek + std::io::Read>(reader: &mut R) -> Result<Self> {
-        let magic = VersionedMagic::read(reader)?;
+        let (magic, endianness) = VersionedMagic::read(reader)?;
+        match endianness {
+            Endianness::Little => Self::read_::<R, LittleEndian>(reader, magic, endianness),
+            Endianness::Big => Self::read_::<R, BigEndian>(reader, magic, endianness),
+        }
+    }
 
-        let tensor_count = match magic {
-            VersionedMagic::GgufV1 => reader.read_u32::<LittleEndian>()? as usize,
-            VersionedMagic::GgufV2 | VersionedMagic::GgufV3 => {
-                reader.read_u64::<LittleEndian>()? as usize
-            }
-        };
-        let metadata_kv_count = match magic {
-            VersionedMagic::GgufV1 => reader.read_u32::<LittleEndian>()? as usize,
-            VersionedMagic::GgufV2 | VersionedMagic::GgufV3 => {
-                reader.read_u64::<LittleEndian>()? as usize
-            }
-        };
+    fn read_<R: std::io::Seek + std::io::Read, B: ByteOrder>(
+        reader: &mut R,
+        magic: VersionedMagic,
+        endianness: Endianness,
+    ) -> Result<Self> {
+        let tensor_count = read_len::<R, B>(reader, &magic)?;
+        let metadata_kv_count = read_len::<R, B>(reader, &magic)?;
 
         let mut metadata = HashMap::new();
         for _idx in 0..metadata_kv_count {
-            let key = read_string(reader, &magic)?;
-            let value_type = reader.read_u32::<LittleEndian>()?;
+            let key = read_string::<R, B>(reader, &magic)?;
+            let value_type = reader.read_u32::<B>()?;
             let value_type = ValueType::from_u32(value_type)?;
-            let value = Value::read(reader, value_type, &magic)?;
+            let value = Value::read::<R, B>(reader, value_type, &magic)?;
             metadata.insert(key, value);
         }
         let mut tensor_infos = HashMap::new();
         for _idx in 0..tensor_count {
-            let tensor_name = read_string(reader, &magic)?;
-            let n_dimensions = reader.read_u32::<LittleEndian>()?;
+            let tensor_name = read_string::<R, B>(reader, &magic)?;
+            let n_dimensions = reader.read_u32::<B>()?;
 
             let mut dimensions: Vec<usize> = match magic {
                 VersionedMagic::GgufV1 => {
                     let mut dimensions = vec![0; n_dimensions as usize];
-                    reader.read_u32_into::<LittleEndian>(&mut dimensions)?;
+                    reader.read_u32_into::<B>(&mut dimensions)?;
                     dimensions.into_iter().map(|c| c as usize).collect()
                 }
                 VersionedMagic::GgufV2 | VersionedMagic::GgufV3 => {
                     let mut dimensions = vec![0; n_dimensions as usize];
-                    reader.read_u64_into::<LittleEndian>(&mut dimensions)?;
+                    reader.read_u64_into::<B>(&mut dimensions)?;
                     dimensions.into_iter().map(|c| c as usize).collect()
                 }
             };
 
             dimensions.reverse();
-            let ggml_dtype = reader.read_u32::<LittleEndian>()?;
+            let ggml_dtype = reader.read_u32::<B>()?;
             let ggml_dtype = GgmlDType::from_u32(ggml_dtype)?;
-            let offset = reader.read_u64::<LittleEndian>()?;
+            let offset = reader.read_u64::<B>()?;
             tensor_infos.insert(
                 tensor_name,
                 TensorInfo {
@@ -456,6 +518,7 @@ impl Content {
         let tensor_data_offset = (position + alignment - 1) / alignment * alignment;
         Ok(Self {
             magic,
+            endianness,
             metadata,
             tensor_infos,
             tensor_data_offset,
@@ -472,42 +535,132 @@ impl Content {
             Some(tensor_info) => tensor_info,
             None => crate::bail!("cannot find tensor info for {name}"),
         };
-        tensor_info.read(reader, self.tensor_data_offset, device)
+        tensor_info.read_with_endianness(reader, self.tensor_data_offset, self.endianness, device)
     }
 }
 
-fn write_string<W: std::io::Write>(w: &mut W, str: &str) -> Result<()> {
+/// A GGUF file mapped in memory, the tensors created on the cpu borrow their data directly from
+/// the mapping rather than copying it so loading is fast and does not duplicate the memory.
+pub struct MmapedContent {
+    content: Content,
+    mmap: Arc<memmap2::Mmap>,
+}
+
+impl MmapedContent {
+    /// Memory maps the file at `p` and reads the GGUF header.
+    ///
+    /// # Safety
+    ///
+    /// The unsafe is inherited from [`memmap2::MmapOptions`].
+    pub unsafe fn new<P: AsRef<std::path::Path>>(p: P) -> Result<Self> {
+        let p = p.as_ref();
+        let file = std::fs::File::open(p).map_err(|e| crate::Error::from(e).with_path(p))?;
+        let mmap = memmap2::MmapOptions::new()
+            .map(&file)
+            .map_err(|e| crate::Error::from(e).with_path(p))?;
+        let content = Content::read(&mut std::io::Cursor::new(&mmap[..]))?;
+        Ok(Self {
+            content,
+            mmap: Arc::new(mmap),
+        })
+    }
+
+    pub fn content(&self) -> &Content {
+        &self.content
+    }
+
+    /// Returns the tensor `name`. On the cpu the data is shared with the mapping, it is only
+    /// copied when it has to be byte-swapped or is not properly aligned.
+    pub fn tensor(&self, name: &str, device: &Device) -> Result<QTensor> {
+        let tensor_info = match self.content.tensor_infos.get(name) {
+            Some(tensor_info) => tensor_info,
+            None => crate::bail!("cannot find tensor info for {name}"),
+        };
+        let size_in_bytes = tensor_info.size_in_bytes()?;
+        let offset = (self.content.tensor_data_offset + tensor_info.offset) as usize;
+        if offset + size_in_bytes > self.mmap.len() {
+            crate::bail!(
+                "tensor {name} at offset {offset} with {size_in_bytes} bytes is out of bounds, file size {}",
+                self.mmap.len()
+            )
+        }
+        let dtype = tensor_info.ggml_dtype;
+        let needs_swap = self.content.endianness == Endianness::Big;
+        if device.is_cpu() && !needs_swap {
+            if let Some(storage) = dtype.cpu_mmaped(&self.mmap, offset, size_in_bytes) {
+                return QTensor::new(super::QStorage::Cpu(storage), tensor_info.shape.clone());
+            }
+        }
+        let mut cursor = std::io::Cursor::new(&self.mmap[..]);
+        tensor_info.read_with_endianness(
+            &mut cursor,
+            self.content.tensor_data_offset,
+            self.content.endianness,
+            device,
+        )
+    }
+}
+
+fn write_string<W: std::io::Write, B: ByteOrder>(w: &mut W, str: &str) -> Result<()> {
     let bytes = str.as_bytes();
-    w.write_u64::<LittleEndian>(bytes.len() as u64)?;
+    w.write_u64::<B>(bytes.len() as u64)?;
     w.write_all(bytes)?;
     Ok(())
 }
 
+/// Writes a GGUF v3 file in little-endian byte order.
 pub fn write<W: std::io::Seek + std::io::Write>(
     w: &mut W,
     metadata: &[(&str, &Value)],
     tensors: &[(&str, &QTensor)],
 ) -> Result<()> {
+    write_with_endianness(w, Endianness::Little, metadata, tensors)
+}
+
+/// Writes a GGUF v3 file using the specified byte order, big-endian files can only contain F32
+/// and F16 tensors.
+pub fn write_with_endianness<W: std::io::Seek + std::io::Write>(
+    w: &mut W,
+    endianness: Endianness,
+    metadata: &[(&str, &Value)],
+    tensors: &[(&str, &QTensor)],
+) -> Result<()> {
+    for (_name, tensor) in tensors.iter() {
+        endianness.check_dtype(tensor.dtype())?
+    }
+    match endianness {
+        Endianness::Little => write_::<W, LittleEndian>(w, endianness, metadata, tensors),
+        Endianness::Big => write_::<W, BigEndian>(w, endianness, metadata, tensors),
+    }
+}
+
+fn write_<W: std::io::Seek + std::io::Write, B: ByteOrder>(
+    w: &mut W,
+    endianness: Endianness,
+    metadata: &[(&str, &Value)],
+    tensors: &[(&str, &QTensor)],
+) -> Result<()> {
+    // The magic is written as bytes so it does not depend on the byte order.
     w.write_u32::<LittleEndian>(0x46554747)?;
-    w.write_u32::<LittleEndian>(2)?; // version 2.
-    w.write_u64::<LittleEndian>(tensors.len() as u64)?;
-    w.write_u64::<LittleEndian>(metadata.len() as u64)?;
+    w.write_u32::<B>(3)?; // version 3.
+    w.write_u64::<B>(tensors.len() as u64)?;
+    w.write_u64::<B>(metadata.len() as u64)?;
     for (name, value) in metadata.iter() {
-        write_string(w, name)?;
-        w.write_u32::<LittleEndian>(value.value_type().to_u32())?;
-        value.write(w)?;
+        write_string::<W, B>(w, name)?;
+        w.write_u32::<B>(value.value_type().to_u32())?;
+        value.write::<W, B>(w)?;
     }
     let mut offset = 0usize;
     let mut offsets = Vec::with_capacity(tensors.len());
     for (name, tensor) in tensors.iter() {
-        write_string(w, name)?;
+        write_string::<W, B>(w, name)?;
         let dims = tensor.shape().dims();
-        w.write_u32::<LittleEndian>(dims.len() as u32)?;
+        w.write_u32::<B>(dims.len() as u32)?;
         for &dim in dims.iter().rev() {
-            w.write_u64::<LittleEndian>(dim as u64)?;
+            w.write_u64::<B>(dim as u64)?;
         }
-        w.write_u32::<LittleEndian>(tensor.dtype().to_u32())?;
-        w.write_u64::<LittleEndian>(offset as u64)?;
+        w.write_u32::<B>(tensor.dtype().to_u32())?;
+        w.write_u64::<B>(offset as u64)?;
         offsets.push(offset);
         let size_in_bytes = tensor.storage_size_in_bytes();
         let padding = 31 - (31 + size_in_bytes) % 32;
@@ -524,7 +677,10 @@ pub fn write<W: std::io::Seek + std::io::Write>(
                 "internal error, unexpected current position {tensor_start_pos} {offset} {pos}"
             )
         }
-        let data = tensor.data()?;
+        let mut data = tensor.data()?;
+        if endianness == Endianness::Big {
+            endianness.swap_bytes(data.to_mut(), tensor.dtype())?;
+        }
         let size_in_bytes = data.len();
         w.write_all(&data)?;
         let padding = 31 - (31 + size_in_bytes) % 32;
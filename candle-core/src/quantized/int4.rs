@@ -0,0 +1,319 @@
+//! Group-wise 4-bit weights as used by the GPTQ and AWQ checkpoints.
+//!
+//! Both formats split the input features in groups of `group_size` values. Each group has a
+//! scale and a zero point per output feature and the dequantized weights are
+//! `(q - zero) * scale`. The packed values are unpacked at load time to a layout where the input
+//! values for an output feature are contiguous, so that the matmul is a sequence of dot products.
+use crate::{CpuStorage, Layout, Result, Shape, Tensor};
+use rayon::prelude::*;
+
+/// AWQ interleaves the output features packed in a `u32`, the i-th nibble of a packed value holds
+/// the output feature `8 * c + AWQ_ORDER[i]`.
+const AWQ_ORDER: [usize; 8] = [0, 2, 4, 6, 1, 3, 5, 7];
+
+#[derive(Debug, Clone)]
+pub struct Int4Weight {
+    out_dim: usize,
+    in_dim: usize,
+    group_size: usize,
+    /// The 4-bit values with shape `(out_dim, in_dim / 2)`, the low nibble comes first.
+    qs: Vec<u8>,
+    /// The scales with shape `(out_dim, n_groups)`.
+    scales: Vec<f32>,
+    /// The zero points multiplied by the scales, shape `(out_dim, n_groups)`.
+    scaled_zeros: Vec<f32>,
+    /// When the groups are not made of consecutive input features (GPTQ checkpoints using
+    /// `desc_act`), the input features are reordered so that they are. `perm[i]` is the original
+    /// index of the i-th input feature.
+    perm: Option<Vec<usize>>,
+}
+
+/// Returns the packed values of a 2d tensor, the packed tensors are stored as `i32` in the
+/// checkpoints so they can be loaded either as `u32` or as `i64`.
+fn packed_u32(t: &Tensor, name: &str) -> Result<Vec<Vec<u32>>> {
+    match t.dtype() {
+        crate::DType::U32 => t.to_vec2::<u32>(),
+        crate::DType::I64 => {
+            let vs = t.to_vec2::<i64>()?;
+            Ok(vs
+                .into_iter()
+                .map(|v| v.into_iter().map(|v| v as u32).collect())
+                .collect())
+        }
+        dtype => crate::bail!("unexpected dtype {dtype:?} for {name}, expected u32 or i64"),
+    }
+}
+
+fn nibble(v: u32, i: usize) -> u8 {
+    ((v >> (4 * i)) & 0xF) as u8
+}
+
+impl Int4Weight {
+    /// Builds the weights from the GPTQ tensors as saved by AutoGPTQ.
+    ///
+    /// - `qweight` has shape `(in_dim / 8, out_dim)`, eight input features are packed per value.
+    /// - `qzeros` has shape `(n_groups, out_dim / 8)`, the zero points are stored minus one.
+    /// - `scales` has shape `(n_groups, out_dim)`.
+    /// - `g_idx` has shape `(in_dim,)` and contains the group for each input feature, when
+    ///   missing the groups are made of consecutive input features.
+    pub fn from_gptq(
+        qweight: &Tensor,
+        qzeros: &Tensor,
+        scales: &Tensor,
+        g_idx: Option<&Tensor>,
+    ) -> Result<Self> {
+        let (in_dim_8, out_dim) = qweight.dims2()?;
+        let in_dim = in_dim_8 * 8;
+        let (n_groups, scale_dim) = scales.dims2()?;
+        if scale_dim != out_dim || qzeros.dims2()? != (n_groups, out_dim / 8) {
+            crate::bail!(
+                "gptq shape mismatch, qweight {:?}, qzeros {:?}, scales {:?}",
+                qweight.shape(),
+                qzeros.shape(),
+                scales.shape()
+            )
+        }
+        let group_size = group_size(in_dim, n_groups)?;
+        let g_idx = match g_idx {
+            None => (0..in_dim).map(|i| i / group_size).collect::<Vec<_>>(),
+            Some(g_idx) => {
+                let g_idx = g_idx.to_dtype(crate::DType::I64)?.to_vec1::<i64>()?;
+                if g_idx.len() != in_dim {
+                    crate::bail!("unexpected g_idx len {}, expected {in_dim}", g_idx.len())
+                }
+                g_idx.into_iter().map(|g| g as usize).collect()
+            }
+        };
+        let perm = group_permutation(&g_idx, n_groups, group_size)?;
+        let qweight = packed_u32(qweight, "qweight")?;
+        let qzeros = packed_u32(qzeros, "qzeros")?;
+        let scales = scales.to_dtype(crate::DType::F32)?.to_vec2::<f32>()?;
+        let q = |o: usize, i: usize| nibble(qweight[i / 8][o], i % 8);
+        let zero = |o: usize, g: usize| nibble(qzeros[g][o / 8], o % 8) as f32 + 1.;
+        Self::new(
+            (out_dim, in_dim, group_size),
+            q,
+            |o, g| scales[g][o],
+            zero,
+            perm,
+        )
+    }
+
+    /// Builds the weights from the AWQ tensors as saved by AutoAWQ using the GEMM layout.
+    ///
+    /// - `qweight` has shape `(in_dim, out_dim / 8)`, eight output features are packed per value.
+    /// - `qzeros` has shape `(n_groups, out_dim / 8)`.
+    /// - `scales` has shape `(n_groups, out_dim)`.
+    pub fn from_awq(qweight: &Tensor, qzeros: &Tensor, scales: &Tensor) -> Result<Self> {
+        let (in_dim, out_dim_8) = qweight.dims2()?;
+        let out_dim = out_dim_8 * 8;
+        let (n_groups, scale_dim) = scales.dims2()?;
+        if scale_dim != out_dim || qzeros.dims2()? != (n_groups, out_dim_8) {
+            crate::bail!(
+                "awq shape mismatch, qweight {:?}, qzeros {:?}, scales {:?}",
+                qweight.shape(),
+                qzeros.shape(),
+                scales.shape()
+            )
+        }
+        let group_size = group_size(in_dim, n_groups)?;
+        let qweight = packed_u32(qweight, "qweight")?;
+        let qzeros = packed_u32(qzeros, "qzeros")?;
+        let scales = scales.to_dtype(crate::DType::F32)?.to_vec2::<f32>()?;
+        let mut nibble_idx = [0; 8];
+        for (i, &o) in AWQ_ORDER.iter().enumerate() {
+            nibble_idx[o] = i
+        }
+        let q = |o: usize, i: usize| nibble(qweight[i][o / 8], nibble_idx[o % 8]);
+        let zero = |o: usize, g: usize| nibble(qzeros[g][o / 8], nibble_idx[o % 8]) as f32;
+        Self::new(
+            (out_dim, in_dim, group_size),
+            q,
+            |o, g| scales[g][o],
+            zero,
+            None,
+        )
+    }
+
+    fn new(
+        (out_dim, in_dim, group_size): (usize, usize, usize),
+        q: impl Fn(usize, usize) -> u8,
+        scale: impl Fn(usize, usize) -> f32,
+        zero: impl Fn(usize, usize) -> f32,
+        perm: Option<Vec<usize>>,
+    ) -> Result<Self> {
+        if group_size % 2 != 0 {
+            crate::bail!("int4 group size {group_size} has to be even")
+        }
+        let n_groups = in_dim / group_size;
+        let mut qs = Vec::with_capacity(out_dim * in_dim / 2);
+        let mut scales = Vec::with_capacity(out_dim * n_groups);
+        let mut scaled_zeros = Vec::with_capacity(out_dim * n_groups);
+        for o in 0..out_dim {
+            for i in (0..in_dim).step_by(2) {
+                let (i0, i1) = match &perm {
+                    None => (i, i + 1),
+                    Some(perm) => (perm[i], perm[i + 1]),
+                };
+                qs.push(q(o, i0) | (q(o, i1) << 4))
+            }
+            for g in 0..n_groups {
+                let scale = scale(o, g);
+                scales.push(scale);
+                scaled_zeros.push(scale * zero(o, g))
+            }
+        }
+        Ok(Self {
+            out_dim,
+            in_dim,
+            group_size,
+            qs,
+            scales,
+            scaled_zeros,
+            perm,
+        })
+    }
+
+    /// The shape of the weights, `(out_dim, in_dim)`.
+    pub fn shape(&self) -> Shape {
+        Shape::from((self.out_dim, self.in_dim))
+    }
+
+    pub fn group_size(&self) -> usize {
+        self.group_size
+    }
+
+    /// Returns the dequantized weights with shape `(out_dim, in_dim)`.
+    pub fn dequantize(&self, device: &crate::Device) -> Result<Tensor> {
+        let n_groups = self.in_dim / self.group_size;
+        let mut ws = vec![0f32; self.out_dim * self.in_dim];
+        for o in 0..self.out_dim {
+            let qs = &self.qs[o * self.in_dim / 2..(o + 1) * self.in_dim / 2];
+            for i in 0..self.in_dim {
+                let q = (qs[i / 2] >> (4 * (i % 2))) & 0xF;
+                let g = o * n_groups + i / self.group_size;
+                let dst_i = match &self.perm {
+                    None => i,
+                    Some(perm) => perm[i],
+                };
+                ws[o * self.in_dim + dst_i] = q as f32 * self.scales[g] - self.scaled_zeros[g]
+            }
+        }
+        Tensor::from_vec(ws, (self.out_dim, self.in_dim), device)
+    }
+
+    /// Computes `lhs * self^T` where `lhs` has shape `(m, in_dim)`.
+    fn matmul_t(&self, m: usize, lhs: &[f32], dst: &mut [f32]) {
+        let (k, n) = (self.in_dim, self.out_dim);
+        let n_groups = k / self.group_size;
+        let permuted;
+        let lhs = match &self.perm {
+            None => lhs,
+            Some(perm) => {
+                permuted = lhs
+                    .chunks(k)
+                    .flat_map(|row| perm.iter().map(|&i| row[i]))
+                    .collect::<Vec<_>>();
+                permuted.as_slice()
+            }
+        };
+        // The sums of the activations over each group, used to apply the zero points.
+        let sums = lhs
+            .chunks(self.group_size)
+            .map(|vs| vs.iter().sum::<f32>())
+            .collect::<Vec<_>>();
+        // The results are computed column by column and transposed at the end, so that the
+        // weights for an output feature are only read once.
+        let mut dst_t = vec![0f32; m * n];
+        crate::cpu::thread_pool::install(|| {
+            dst_t.par_chunks_mut(m).enumerate().for_each(|(o, dst_t)| {
+                let qs = &self.qs[o * k / 2..(o + 1) * k / 2];
+                let scales = &self.scales[o * n_groups..(o + 1) * n_groups];
+                let scaled_zeros = &self.scaled_zeros[o * n_groups..(o + 1) * n_groups];
+                for (row_idx, dst) in dst_t.iter_mut().enumerate() {
+                    let lhs = &lhs[row_idx * k..(row_idx + 1) * k];
+                    let sums = &sums[row_idx * n_groups..(row_idx + 1) * n_groups];
+                    let mut acc = 0f32;
+                    for g in 0..n_groups {
+                        let qs = &qs[g * self.group_size / 2..(g + 1) * self.group_size / 2];
+                        let lhs = &lhs[g * self.group_size..(g + 1) * self.group_size];
+                        let mut dot = 0f32;
+                        for (&q, lhs) in qs.iter().zip(lhs.chunks_exact(2)) {
+                            dot += lhs[0] * (q & 0xF) as f32 + lhs[1] * (q >> 4) as f32
+                        }
+                        acc += scales[g] * dot - scaled_zeros[g] * sums[g]
+                    }
+                    *dst = acc
+                }
+            })
+        });
+        for (o, dst_t) in dst_t.chunks(m).enumerate() {
+            for (row_idx, &v) in dst_t.iter().enumerate() {
+                dst[row_idx * n + o] = v
+            }
+        }
+    }
+}
+
+fn group_size(in_dim: usize, n_groups: usize) -> Result<usize> {
+    if n_groups == 0 || in_dim % n_groups != 0 {
+        crate::bail!("input dim {in_dim} cannot be split in {n_groups} groups")
+    }
+    Ok(in_dim / n_groups)
+}
+
+/// Returns the permutation that makes each group contiguous, or `None` if this is already the
+/// case.
+fn group_permutation(
+    g_idx: &[usize],
+    n_groups: usize,
+    group_size: usize,
+) -> Result<Option<Vec<usize>>> {
+    let mut counts = vec![0; n_groups];
+    for &g in g_idx.iter() {
+        if g >= n_groups {
+            crate::bail!("g_idx contains the group {g} but there are only {n_groups} groups")
+        }
+        counts[g] += 1
+    }
+    if counts.iter().any(|&c| c != group_size) {
+        crate::bail!("g_idx groups do not all have {group_size} elements")
+    }
+    if g_idx.iter().enumerate().all(|(i, &g)| i / group_size == g) {
+        return Ok(None);
+    }
+    let mut perm = (0..g_idx.len()).collect::<Vec<_>>();
+    perm.sort_by_key(|&i| g_idx[i]);
+    Ok(Some(perm))
+}
+
+impl crate::CustomOp1 for Int4Weight {
+    fn name(&self) -> &'static str {
+        "int4-matmul"
+    }
+
+    fn cpu_fwd(&self, storage: &CpuStorage, layout: &Layout) -> Result<(CpuStorage, Shape)> {
+        if !layout.is_contiguous() {
+            crate::bail!("input tensor is not contiguous {layout:?}")
+        }
+        let src_shape = layout.shape();
+        if src_shape.rank() < 2 {
+            crate::bail!("input tensor has only one dimension {layout:?}")
+        }
+        let mut dst_shape = src_shape.dims().to_vec();
+        let last_k = dst_shape.pop().unwrap();
+        if last_k != self.in_dim {
+            crate::bail!(
+                "input tensor {layout:?} incompatible with {:?}",
+                self.shape()
+            )
+        }
+        dst_shape.push(self.out_dim);
+        let dst_shape = Shape::from(dst_shape);
+        let slice = storage.as_slice::<f32>()?;
+        let slice = &slice[layout.start_offset()..layout.start_offset() + src_shape.elem_count()];
+        let mut dst = vec![0f32; dst_shape.elem_count()];
+        self.matmul_t(dst_shape.elem_count() / self.out_dim, slice, &mut dst);
+        Ok((CpuStorage::F32(dst), dst_shape))
+    }
+}
@@ -0,0 +1,221 @@
+//! Symmetric integer quantization with explicit scale and zero point tensors.
+//!
+//! Contrary to the GGML formats, the quantized values, the scales, and the zero points are stored
+//! as separate tensors so that they can be saved as safetensors and mapped to the ONNX
+//! `QuantizeLinear`/`DequantizeLinear` operators, the dequantized values being
+//! `(q - zero_point) * scale`.
+//!
+//! - `Int8` uses one scale per output channel (the first dimension), the scale and zero point
+//!   tensors have shape `(out_dim,)` as for the per-axis ONNX quantization. The values are stored
+//!   as `u8` with a zero point of 128.
+//! - `Int4` uses one scale per group of `group_size` consecutive input values, the scale and zero
+//!   point tensors have shape `(out_dim, in_dim / group_size)` as for the blocked ONNX
+//!   quantization. The values are stored as `u8` with a zero point of 8, two values per byte with
+//!   the first one in the low nibble.
+use crate::{DType, Result, Shape, Tensor, D};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntQuantFormat {
+    Int8,
+    Int4 { group_size: usize },
+}
+
+impl IntQuantFormat {
+    /// The maximum absolute value of the quantized integers, the range is symmetric.
+    pub fn max_int(&self) -> i64 {
+        match self {
+            Self::Int8 => 127,
+            Self::Int4 { .. } => 7,
+        }
+    }
+
+    /// The zero point used when storing the quantized values as unsigned integers.
+    pub fn zero_point(&self) -> u8 {
+        match self {
+            Self::Int8 => 128,
+            Self::Int4 { .. } => 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IntQTensor {
+    format: IntQuantFormat,
+    shape: Shape,
+    data: Tensor,
+    scale: Tensor,
+    zero_point: Tensor,
+}
+
+impl IntQTensor {
+    /// Quantizes a 2d tensor of shape `(out_dim, in_dim)`.
+    pub fn quantize(xs: &Tensor, format: IntQuantFormat) -> Result<Self> {
+        let (out_dim, in_dim) = xs.dims2()?;
+        let group_size = match format {
+            IntQuantFormat::Int8 => in_dim,
+            IntQuantFormat::Int4 { group_size } => {
+                if group_size == 0 || group_size % 2 != 0 || in_dim % group_size != 0 {
+                    crate::bail!("int4 group size {group_size} incompatible with {in_dim}")
+                }
+                group_size
+            }
+        };
+        let max_int = format.max_int() as f64;
+        let zero_point = format.zero_point() as f64;
+        let xs = xs
+            .to_dtype(DType::F32)?
+            .reshape((out_dim, in_dim / group_size, group_size))?;
+        let scale = (xs.abs()?.max_keepdim(D::Minus1)? / max_int)?;
+        // All zero groups use a scale of 1 so that the scales are always positive.
+        let scale = scale.eq(0f64)?.where_cond(&scale.ones_like()?, &scale)?;
+        let qs = xs
+            .broadcast_div(&scale)?
+            .round()?
+            .clamp(-max_int, max_int)?
+            .affine(1., zero_point)?;
+        let qs = match format {
+            IntQuantFormat::Int8 => qs.reshape((out_dim, in_dim))?,
+            IntQuantFormat::Int4 { .. } => {
+                let qs = qs.reshape((out_dim, in_dim / 2, 2))?;
+                (qs.narrow(2, 0, 1)? + (qs.narrow(2, 1, 1)? * 16.)?)?.reshape((out_dim, ()))?
+            }
+        };
+        let scale = match format {
+            IntQuantFormat::Int8 => scale.reshape(out_dim)?,
+            IntQuantFormat::Int4 { .. } => scale.squeeze(D::Minus1)?,
+        };
+        let zero_point = scale
+            .ones_like()?
+            .affine(zero_point, 0.)?
+            .to_dtype(DType::U8)?;
+        Ok(Self {
+            format,
+            shape: Shape::from((out_dim, in_dim)),
+            data: qs.to_dtype(DType::U8)?,
+            scale,
+            zero_point,
+        })
+    }
+
+    /// Builds a quantized tensor from its components, the format is inferred from the rank of the
+    /// scale tensor.
+    pub fn from_tensors(data: Tensor, scale: Tensor, zero_point: Tensor) -> Result<Self> {
+        if data.dtype() != DType::U8 || zero_point.dtype() != DType::U8 {
+            crate::bail!(
+                "unexpected dtypes {:?} {:?}, expected u8 values and zero points",
+                data.dtype(),
+                zero_point.dtype()
+            )
+        }
+        if scale.shape() != zero_point.shape() {
+            crate::bail!(
+                "scale and zero point shapes differ {:?} {:?}",
+                scale.shape(),
+                zero_point.shape()
+            )
+        }
+        let (out_dim, data_dim) = data.dims2()?;
+        let (format, in_dim) = match *scale.dims() {
+            [d] if d == out_dim => (IntQuantFormat::Int8, data_dim),
+            [d, n_groups] if d == out_dim && n_groups > 0 && (2 * data_dim) % n_groups == 0 => {
+                let group_size = 2 * data_dim / n_groups;
+                (IntQuantFormat::Int4 { group_size }, 2 * data_dim)
+            }
+            _ => crate::bail!(
+                "unexpected scale shape {:?} for values of shape {:?}",
+                scale.shape(),
+                data.shape()
+            ),
+        };
+        Ok(Self {
+            format,
+            shape: Shape::from((out_dim, in_dim)),
+            data,
+            scale: scale.to_dtype(DType::F32)?,
+            zero_point,
+        })
+    }
+
+    pub fn format(&self) -> IntQuantFormat {
+        self.format
+    }
+
+    /// The shape of the dequantized tensor.
+    pub fn shape(&self) -> &Shape {
+        &self.shape
+    }
+
+    /// The quantized values stored as `u8`, for int4 two values are packed per byte.
+    pub fn data(&self) -> &Tensor {
+        &self.data
+    }
+
+    pub fn scale(&self) -> &Tensor {
+        &self.scale
+    }
+
+    pub fn zero_point(&self) -> &Tensor {
+        &self.zero_point
+    }
+
+    /// Returns the quantized values as `u8`, one value per element of the dequantized tensor.
+    pub fn unpacked_data(&self) -> Result<Tensor> {
+        match self.format {
+            IntQuantFormat::Int8 => Ok(self.data.clone()),
+            IntQuantFormat::Int4 { .. } => {
+                let data = self.data.to_dtype(DType::F32)?.unsqueeze(D::Minus1)?;
+                let high = (&data / 16.)?.floor()?;
+                let low = (data - (&high * 16.)?)?;
+                Tensor::cat(&[low, high], D::Minus1)?
+                    .reshape(&self.shape)?
+                    .to_dtype(DType::U8)
+            }
+        }
+    }
+
+    pub fn dequantize(&self) -> Result<Tensor> {
+        let (out_dim, in_dim) = self.shape.dims2()?;
+        let qs = self.unpacked_data()?.to_dtype(DType::F32)?;
+        let zero_point = self.zero_point.to_dtype(DType::F32)?;
+        match self.format {
+            IntQuantFormat::Int8 => {
+                let zero_point = zero_point.unsqueeze(1)?;
+                qs.broadcast_sub(&zero_point)?
+                    .broadcast_mul(&self.scale.unsqueeze(1)?)
+            }
+            IntQuantFormat::Int4 { group_size } => {
+                let qs = qs.reshape((out_dim, in_dim / group_size, group_size))?;
+                qs.broadcast_sub(&zero_point.unsqueeze(2)?)?
+                    .broadcast_mul(&self.scale.unsqueeze(2)?)?
+                    .reshape((out_dim, in_dim))
+            }
+        }
+    }
+
+    /// Returns the tensors to serialize, using the ONNX naming convention: `{name}`,
+    /// `{name}_scale`, and `{name}_zero_point`.
+    pub fn to_named_tensors(&self, name: &str) -> Vec<(String, Tensor)> {
+        vec![
+            (name.to_string(), self.data.clone()),
+            (format!("{name}_scale"), self.scale.clone()),
+            (format!("{name}_zero_point"), self.zero_point.clone()),
+        ]
+    }
+
+    /// Retrieves a quantized tensor saved with [`IntQTensor::to_named_tensors`].
+    pub fn from_named_tensors(ts: &HashMap<String, Tensor>, name: &str) -> Result<Self> {
+        let get = |name: &str| match ts.get(name) {
+            None => Err(crate::Error::CannotFindTensor {
+                path: name.to_string(),
+            }
+            .bt()),
+            Some(t) => Ok(t.clone()),
+        };
+        Self::from_tensors(
+            get(name)?,
+            get(&format!("{name}_scale"))?,
+            get(&format!("{name}_zero_point"))?,
+        )
+    }
+}
@@ -0,0 +1,1250 @@
+//! Quantization of the i-quant formats, this is a port of the quantization functions from
+//! llama.cpp ggml-quants.c.
+//!
+//! Most of these formats store groups of 4 or 8 values as an index in a fixed grid of points plus
+//! some sign bits. The quantization searches for the block scales and grid points that minimize
+//! the squared error, weighted by the importance matrix when one is available.
+use super::iq_grids::*;
+use super::k_quants::{
+    BlockIQ1M, BlockIQ1S, BlockIQ2S, BlockIQ2XS, BlockIQ2XXS, BlockIQ3S, BlockIQ3XXS, BlockIQ4NL,
+    BlockIQ4XS, GgmlType, QK4_NL, QK_K,
+};
+use super::utils::{make_qp_quants, nearest_int};
+use crate::Result;
+use half::f16;
+use rayon::prelude::*;
+use std::sync::OnceLock;
+
+const GROUP_MAX_EPS: f32 = 1e-15;
+const GROUP_MAX_EPS_IQ3_XXS: f32 = 1e-8;
+const GROUP_MAX_EPS_IQ2_S: f32 = 1e-8;
+const GROUP_MAX_EPS_IQ1_M: f32 = 1e-7;
+const GROUP_MAX_EPS_IQ1_S: f32 = 1e-12;
+pub(crate) const IQ1S_DELTA: f32 = 0.125;
+pub(crate) const IQ1M_DELTA: f32 = 0.125;
+
+/// Quantizes `xs` block by block in parallel. When an importance matrix is provided, `xs` is made
+/// of rows of `imatrix.len()` elements and each block gets the weights for its columns.
+pub(super) fn quantize_blocks<T: GgmlType>(
+    xs: &[f32],
+    ys: &mut [T],
+    imatrix: Option<&[f32]>,
+    f: impl Fn(&[f32], &mut T, Option<&[f32]>) + Sync,
+) -> Result<()> {
+    let block_size = T::BLCK_SIZE;
+    let dtype = T::DTYPE;
+    if xs.len() != ys.len() * block_size {
+        crate::bail!(
+            "quantize {dtype:?}: expected {} blocks for {} elements, got {}",
+            xs.len() / block_size,
+            xs.len(),
+            ys.len()
+        )
+    }
+    if let Some(imatrix) = imatrix {
+        let n_per_row = imatrix.len();
+        if n_per_row == 0 || n_per_row % block_size != 0 || xs.len() % n_per_row != 0 {
+            crate::bail!(
+                "quantize {dtype:?}: imatrix length {n_per_row} is incompatible with {} elements and a block size of {block_size}",
+                xs.len()
+            )
+        }
+    }
+    crate::cpu::thread_pool::install(|| {
+        ys.par_iter_mut()
+            .zip(xs.par_chunks_exact(block_size))
+            .enumerate()
+            .for_each(|(i, (y, x))| {
+                let qw = imatrix.map(|qw| {
+                    let start = (i * block_size) % qw.len();
+                    &qw[start..start + block_size]
+                });
+                f(x, y, qw)
+            })
+    });
+    Ok(())
+}
+
+/// A grid of points used by the i-quants together with a lookup table from the quantized
+/// coordinates to the grid index, or to the closest grid points for coordinates that are not on
+/// the grid.
+struct IqGrid {
+    /// The number of values in each grid point, 8 for the 2-bit and 1-bit grids, 4 for the 3-bit
+    /// ones.
+    group: usize,
+    bits: usize,
+    /// The levels for each grid point, flattened.
+    levels: Vec<u8>,
+    /// Maps the packed levels to the grid index when non-negative. Otherwise `-(offset + 1)`
+    /// where `neighbours[offset]` is the number of neighbours, followed by their indexes.
+    map: Vec<i32>,
+    neighbours: Vec<u16>,
+}
+
+impl IqGrid {
+    /// Builds the grid from the dequantization table, the grid values are converted to levels
+    /// by ranking the distinct values. The neighbours of a point that is not on the grid are the
+    /// grid points within the `nwant` smallest distances.
+    fn new(points: &[[u8; 8]], group: usize, bits: usize, nwant: usize) -> Self {
+        let mut values: Vec<i8> = points
+            .iter()
+            .flat_map(|p| p[..group].iter().map(|&v| v as i8))
+            .collect();
+        values.sort_unstable();
+        values.dedup();
+        let nlevels = values.len();
+        let levels: Vec<u8> = points
+            .iter()
+            .flat_map(|p| {
+                p[..group]
+                    .iter()
+                    .map(|&v| values.iter().position(|&u| u == v as i8).unwrap_or(0) as u8)
+            })
+            .collect();
+        let grid_size = points.len();
+        let mut map = vec![i32::MIN; 1 << (bits * group)];
+        for (index, point) in levels.chunks_exact(group).enumerate() {
+            let u = point
+                .iter()
+                .enumerate()
+                .fold(0, |u, (k, &l)| u | ((l as usize) << (bits * k)));
+            map[u] = index as i32
+        }
+
+        let mut neighbours = vec![];
+        let mut dist2 = Vec::with_capacity(grid_size);
+        let mut pos = vec![0i32; group];
+        for (u, entry) in map.iter_mut().enumerate() {
+            if *entry >= 0 {
+                continue;
+            }
+            let mut valid = true;
+            for (k, pos) in pos.iter_mut().enumerate() {
+                let l = (u >> (bits * k)) & ((1 << bits) - 1);
+                valid &= l < nlevels;
+                *pos = l as i32;
+            }
+            // Only the coordinates with levels actually used by the grid are ever looked up.
+            if !valid {
+                continue;
+            }
+            dist2.clear();
+            for (j, point) in levels.chunks_exact(group).enumerate() {
+                let d2: i32 = point
+                    .iter()
+                    .zip(pos.iter())
+                    .map(|(&l, &p)| (l as i32 - p) * (l as i32 - p))
+                    .sum();
+                dist2.push((d2, j as u16))
+            }
+            dist2.sort_unstable();
+            *entry = -(neighbours.len() as i32 + 1);
+            let start = neighbours.len();
+            neighbours.push(0);
+            let mut d2 = dist2[0].0;
+            let mut nhave = 1;
+            for &(d, j) in dist2.iter() {
+                if d > d2 {
+                    if nhave == nwant {
+                        break;
+                    }
+                    d2 = d;
+                    nhave += 1;
+                }
+                neighbours.push(j)
+            }
+            neighbours[start] = (neighbours.len() - start - 1) as u16;
+        }
+        Self {
+            group,
+            bits,
+            levels,
+            map,
+            neighbours,
+        }
+    }
+
+    fn point(&self, index: usize) -> &[u8] {
+        &self.levels[index * self.group..(index + 1) * self.group]
+    }
+
+    /// Returns the map entry for some levels, non-negative values are grid indexes.
+    fn lookup(&self, l: &[i8]) -> i32 {
+        let u = l
+            .iter()
+            .enumerate()
+            .fold(0, |u, (k, &l)| u | ((l as usize) << (self.bits * k)));
+        self.map[u]
+    }
+
+    fn neighbours(&self, entry: i32) -> &[u16] {
+        let offset = (-entry - 1) as usize;
+        let n = self.neighbours[offset] as usize;
+        &self.neighbours[offset + 1..offset + 1 + n]
+    }
+
+    /// Returns the neighbour closest to `xval` when using the odd values `2 * level + 1`, and
+    /// updates the levels in `l`.
+    fn best_neighbour(
+        &self,
+        entry: i32,
+        xval: &[f32],
+        weight: &[f32],
+        scale: f32,
+        l: &mut [i8],
+    ) -> usize {
+        let mut best_d2 = f32::MAX;
+        let mut grid_index = 0;
+        for &j in self.neighbours(entry) {
+            let d2: f32 = self
+                .point(j as usize)
+                .iter()
+                .zip(xval.iter().zip(weight.iter()))
+                .map(|(&q, (&x, &w))| {
+                    let diff = scale * (2 * q + 1) as f32 - x;
+                    w * diff * diff
+                })
+                .sum();
+            if d2 < best_d2 {
+                best_d2 = d2;
+                grid_index = j as usize;
+            }
+        }
+        for (l, &q) in l.iter_mut().zip(self.point(grid_index)) {
+            *l = q as i8
+        }
+        grid_index
+    }
+
+    /// Same as `best_neighbour` but with the level values provided by `xx`, used by the 1-bit
+    /// quants.
+    fn best_neighbour_iq1(
+        &self,
+        entry: i32,
+        xval: &[f32],
+        weight: &[f32],
+        scale: f32,
+        xx: &[f32; 3],
+        l: &mut [i8],
+    ) -> usize {
+        let mut best_d2 = f32::MAX;
+        let mut grid_index = 0;
+        for &j in self.neighbours(entry) {
+            let d2: f32 = self
+                .point(j as usize)
+                .iter()
+                .zip(xval.iter().zip(weight.iter()))
+                .map(|(&q, (&x, &w))| {
+                    let diff = scale * xx[q as usize] - x;
+                    w * diff * diff
+                })
+                .sum();
+            if d2 < best_d2 {
+                best_d2 = d2;
+                grid_index = j as usize;
+            }
+        }
+        for (l, &q) in l.iter_mut().zip(self.point(grid_index)) {
+            *l = q as i8
+        }
+        grid_index
+    }
+}
+
+fn grid_points_u64(grid: &[u64]) -> Vec<[u8; 8]> {
+    grid.iter().map(|v| v.to_le_bytes()).collect()
+}
+
+fn grid_points_u32(grid: &[u32]) -> Vec<[u8; 8]> {
+    grid.iter()
+        .map(|v| {
+            let mut p = [0u8; 8];
+            p[..4].copy_from_slice(&v.to_le_bytes());
+            p
+        })
+        .collect()
+}
+
+fn iq2xxs_grid() -> &'static IqGrid {
+    static GRID: OnceLock<IqGrid> = OnceLock::new();
+    GRID.get_or_init(|| IqGrid::new(&grid_points_u64(&IQ2XXS_GRID), 8, 2, 2))
+}
+
+fn iq2xs_grid() -> &'static IqGrid {
+    static GRID: OnceLock<IqGrid> = OnceLock::new();
+    GRID.get_or_init(|| IqGrid::new(&grid_points_u64(&IQ2XS_GRID), 8, 2, 2))
+}
+
+fn iq2s_grid() -> &'static IqGrid {
+    static GRID: OnceLock<IqGrid> = OnceLock::new();
+    GRID.get_or_init(|| IqGrid::new(&grid_points_u64(&IQ2S_GRID), 8, 2, 1))
+}
+
+fn iq1s_grid() -> &'static IqGrid {
+    static GRID: OnceLock<IqGrid> = OnceLock::new();
+    GRID.get_or_init(|| IqGrid::new(&grid_points_u64(&IQ1S_GRID), 8, 2, 3))
+}
+
+fn iq3xxs_grid() -> &'static IqGrid {
+    static GRID: OnceLock<IqGrid> = OnceLock::new();
+    GRID.get_or_init(|| IqGrid::new(&grid_points_u32(&IQ3XXS_GRID), 4, 3, 2))
+}
+
+fn iq3s_grid() -> &'static IqGrid {
+    static GRID: OnceLock<IqGrid> = OnceLock::new();
+    GRID.get_or_init(|| IqGrid::new(&grid_points_u32(&IQ3S_GRID), 4, 3, 3))
+}
+
+/// The importance weights for a block of values, `sigma2` is a fraction of the mean squared value
+/// over the super-block.
+fn block_weights(xb: &[f32], qw: Option<&[f32]>, sigma2: f32, weight: &mut [f32]) {
+    match qw {
+        Some(qw) => {
+            for ((w, &q), &x) in weight.iter_mut().zip(qw.iter()).zip(xb.iter()) {
+                *w = q * (sigma2 + x * x).sqrt()
+            }
+        }
+        None => {
+            for (w, &x) in weight.iter_mut().zip(xb.iter()) {
+                *w = x * x
+            }
+        }
+    }
+}
+
+fn sum_squares(xs: &[f32]) -> f32 {
+    xs.iter().map(|x| x * x).sum()
+}
+
+/// Stores the absolute values of a group of 8 in `xval` and returns the sign bits. When
+/// `even_signs` is set only 7 bits are stored, the number of negative values has to be even so
+/// the sign of the value with the least importance is flipped if needed.
+fn group_signs(xb: &[f32], weight: &[f32], xval: &mut [f32], even_signs: bool) -> u8 {
+    let mut nflip = 0;
+    let mut s = 0u8;
+    for i in 0..8 {
+        if xb[i] >= 0. {
+            xval[i] = xb[i]
+        } else {
+            xval[i] = -xb[i];
+            nflip += 1;
+            s |= 1 << i;
+        }
+    }
+    if !even_signs {
+        return s;
+    }
+    if nflip % 2 == 1 {
+        let mut imin = 0;
+        let mut min = weight[0] * xb[0] * xb[0];
+        for i in 1..8 {
+            let ax = weight[i] * xb[i] * xb[i];
+            if ax < min {
+                min = ax;
+                imin = i;
+            }
+        }
+        xval[imin] = -xval[imin];
+        s ^= 1 << imin;
+    }
+    s & 127
+}
+
+/// Searches for the scale that minimizes the weighted error when the groups of `xval` are
+/// snapped to the grid using the odd values `2 * level + 1`. The scales tried are
+/// `max / (2 * kmax_q - 1 + is * step)` for `is` in `-nsteps..=nsteps`. On return `l` holds
+/// levels that are on the grid.
+#[allow(clippy::too_many_arguments)]
+fn search_grid_scale(
+    grid: &IqGrid,
+    kmax_q: i32,
+    nsteps: i32,
+    step: f32,
+    max: f32,
+    mut scale: f32,
+    refine_all: bool,
+    xval: &[f32],
+    weight: &[f32],
+    waux: &[f32],
+    l: &mut [i8],
+) -> f32 {
+    let n = xval.len();
+    let group = grid.group;
+    let ngroups = n / group;
+    let mut laux = [0i8; 32];
+    let laux = &mut laux[..n];
+    let mut is_on_grid = [true; 8];
+    let mut is_on_grid_aux = [true; 8];
+    l.fill(0);
+    let weighted_sums = |l: &[i8]| {
+        let mut sumqx = 0f32;
+        let mut sumq2 = 0f32;
+        for i in 0..n {
+            let q = (2 * l[i] + 1) as f32;
+            sumqx += weight[i] * xval[i] * q;
+            sumq2 += weight[i] * q * q;
+        }
+        (sumqx, sumq2)
+    };
+    let mut best = 0f32;
+    for is in -nsteps..=nsteps {
+        let id = (2. * kmax_q as f32 - 1. + is as f32 * step) / max;
+        let this_scale = 1. / id;
+        for (k, is_on_grid) in is_on_grid_aux[..ngroups].iter_mut().enumerate() {
+            let r = k * group..(k + 1) * group;
+            for i in r.clone() {
+                let li = nearest_int(0.5 * (id * xval[i] - 1.));
+                laux[i] = li.clamp(0, kmax_q - 1) as i8;
+            }
+            let entry = grid.lookup(&laux[r.clone()]);
+            *is_on_grid = entry >= 0;
+            if entry < 0 {
+                grid.best_neighbour(
+                    entry,
+                    &xval[r.clone()],
+                    &waux[r.clone()],
+                    this_scale,
+                    &mut laux[r],
+                );
+            }
+        }
+        let (sumqx, sumq2) = weighted_sums(laux);
+        if sumq2 > 0. && sumqx * sumqx > best * sumq2 {
+            scale = sumqx / sumq2;
+            best = scale * sumqx;
+            l.copy_from_slice(laux);
+            is_on_grid[..ngroups].copy_from_slice(&is_on_grid_aux[..ngroups]);
+        }
+    }
+    let any_off_grid = is_on_grid[..ngroups].iter().any(|&v| !v);
+    if (refine_all || any_off_grid) && scale > 0. {
+        let id = 1. / scale;
+        for (k, &is_on_grid) in is_on_grid[..ngroups].iter().enumerate() {
+            if !refine_all && is_on_grid {
+                continue;
+            }
+            let r = k * group..(k + 1) * group;
+            for i in r.clone() {
+                let li = nearest_int(0.5 * (id * xval[i] - 1.));
+                l[i] = li.clamp(0, kmax_q - 1) as i8;
+            }
+            let entry = grid.lookup(&l[r.clone()]);
+            if entry < 0 {
+                grid.best_neighbour(entry, &xval[r.clone()], &waux[r.clone()], scale, &mut l[r]);
+            }
+        }
+        let (sumqx, sumq2) = weighted_sums(l);
+        if sumq2 > 0. {
+            scale = sumqx / sumq2
+        }
+    }
+    scale
+}
+
+/// Returns the grid index for levels that are known to be on the grid.
+fn grid_index(grid: &IqGrid, l: &[i8]) -> usize {
+    let entry = grid.lookup(l);
+    debug_assert!(entry >= 0, "point {l:?} is not on the grid");
+    entry.max(0) as usize
+}
+
+/// Encodes the sub-block scales relative to the super-block scale `d` with 4 bits.
+fn scale_4bits(id: f32, scale: f32) -> u8 {
+    nearest_int(0.5 * (id * scale - 1.)).clamp(0, 15) as u8
+}
+
+pub(super) fn quantize_iq2_xxs(x: &[f32], y: &mut BlockIQ2XXS, qw: Option<&[f32]>) {
+    const KMAX_Q: i32 = 3;
+    let grid = iq2xxs_grid();
+    let ones = [1f32; QK_K];
+    let qw = qw.unwrap_or(&ones);
+    let mut scales = [0f32; QK_K / 32];
+    let mut weight = [0f32; 32];
+    let mut waux = [0f32; 32];
+    let mut xval = [0f32; 32];
+    let mut l = [0i8; 32];
+    let mut lp = [0u8; 32];
+    let mut q2 = [0u32; 2 * (QK_K / 32)];
+    let mut max_scale = 0f32;
+    let sigma2 = sum_squares(x) / QK_K as f32;
+    for ib in 0..QK_K / 32 {
+        let xb = &x[32 * ib..32 * (ib + 1)];
+        block_weights(xb, Some(&qw[32 * ib..32 * (ib + 1)]), sigma2, &mut weight);
+        for (wa, &w) in waux.iter_mut().zip(weight.iter()) {
+            *wa = w.sqrt()
+        }
+        let mut block_signs = [0u8; 4];
+        for (k, s) in block_signs.iter_mut().enumerate() {
+            let r = 8 * k..8 * (k + 1);
+            *s = group_signs(&xb[r.clone()], &weight[r.clone()], &mut xval[r], true);
+        }
+        let max = xval.iter().fold(xval[0], |m, &v| m.max(v));
+        if max < GROUP_MAX_EPS {
+            scales[ib] = 0.;
+            continue;
+        }
+        let scale = make_qp_quants(KMAX_Q + 1, &xval, &mut lp, &weight);
+        let eff_max = scale * KMAX_Q as f32;
+        let mut scale = search_grid_scale(
+            grid, KMAX_Q, 6, 0.1, eff_max, scale, true, &xval, &weight, &waux, &mut l,
+        );
+        if scale < 0. {
+            scale = -scale;
+            for s in block_signs.iter_mut() {
+                *s = !*s & 127
+            }
+        }
+        for k in 0..4 {
+            let index = grid_index(grid, &l[8 * k..8 * (k + 1)]);
+            q2[2 * ib] |= (index as u32) << (8 * k);
+            q2[2 * ib + 1] |= (block_signs[k] as u32) << (7 * k);
+        }
+        scales[ib] = scale;
+        max_scale = max_scale.max(scale);
+    }
+    y.d = f16::ZERO;
+    y.qs.fill(0);
+    if max_scale == 0. {
+        return;
+    }
+    let d = max_scale / 31.;
+    y.d = f16::from_f32(d);
+    let id = 1. / d;
+    for ib in 0..QK_K / 32 {
+        q2[2 * ib + 1] |= (scale_4bits(id, scales[ib]) as u32) << 28;
+    }
+    for (i, q) in q2.iter().enumerate() {
+        y.qs[2 * i] = *q as u16;
+        y.qs[2 * i + 1] = (*q >> 16) as u16;
+    }
+}
+
+pub(super) fn quantize_iq2_xs(x: &[f32], y: &mut BlockIQ2XS, qw: Option<&[f32]>) {
+    const KMAX_Q: i32 = 3;
+    let grid = iq2xs_grid();
+    let ones = [1f32; QK_K];
+    let qw = qw.unwrap_or(&ones);
+    let mut scales = [0f32; QK_K / 16];
+    let mut weight = [0f32; 16];
+    let mut waux = [0f32; 16];
+    let mut xval = [0f32; 16];
+    let mut l = [0i8; 16];
+    let mut q2 = [0u16; 2 * (QK_K / 16)];
+    let mut max_scale = 0f32;
+    let sigma2 = sum_squares(x) / QK_K as f32;
+    for ib in 0..QK_K / 16 {
+        let xb = &x[16 * ib..16 * (ib + 1)];
+        block_weights(xb, Some(&qw[16 * ib..16 * (ib + 1)]), sigma2, &mut weight);
+        for (wa, &w) in waux.iter_mut().zip(weight.iter()) {
+            *wa = w.sqrt()
+        }
+        let mut block_signs = [0u8; 2];
+        for (k, s) in block_signs.iter_mut().enumerate() {
+            let r = 8 * k..8 * (k + 1);
+            *s = group_signs(&xb[r.clone()], &weight[r.clone()], &mut xval[r], true);
+        }
+        let max = xval.iter().fold(xval[0], |m, &v| m.max(v));
+        if max < GROUP_MAX_EPS {
+            scales[ib] = 0.;
+            continue;
+        }
+        let scale = max / (2 * KMAX_Q - 1) as f32;
+        let mut scale = search_grid_scale(
+            grid, KMAX_Q, 9, 0.1, max, scale, false, &xval, &weight, &waux, &mut l,
+        );
+        if scale < 0. {
+            scale = -scale;
+            for s in block_signs.iter_mut() {
+                *s = !*s & 127
+            }
+        }
+        for k in 0..2 {
+            let index = grid_index(grid, &l[8 * k..8 * (k + 1)]);
+            q2[2 * ib + k] = index as u16 | ((block_signs[k] as u16) << 9);
+        }
+        scales[ib] = scale;
+        max_scale = max_scale.max(scale);
+    }
+    y.d = f16::ZERO;
+    y.qs.fill(0);
+    y.scales.fill(0);
+    if max_scale == 0. {
+        return;
+    }
+    let d = max_scale / 31.;
+    y.d = f16::from_f32(d);
+    let id = 1. / d;
+    for (ib, &scale) in scales.iter().enumerate() {
+        y.scales[ib / 2] |= scale_4bits(id, scale) << (4 * (ib % 2));
+    }
+    y.qs.copy_from_slice(&q2);
+}
+
+pub(super) fn quantize_iq2_s(x: &[f32], y: &mut BlockIQ2S, qw: Option<&[f32]>) {
+    const KMAX_Q: i32 = 3;
+    let grid = iq2s_grid();
+    let mut scales = [0f32; QK_K / 16];
+    let mut weight = [0f32; 16];
+    let mut waux = [0f32; 16];
+    let mut xval = [0f32; 16];
+    let mut l = [0i8; 16];
+    let mut max_scale = 0f32;
+    y.d = f16::ZERO;
+    y.qs.fill(0);
+    y.qh.fill(0);
+    y.scales.fill(0);
+    let sigma2 = 2. * sum_squares(x) / QK_K as f32;
+    for ib in 0..QK_K / 16 {
+        let xb = &x[16 * ib..16 * (ib + 1)];
+        match qw {
+            Some(qw) => block_weights(xb, Some(&qw[16 * ib..16 * (ib + 1)]), sigma2, &mut weight),
+            None => {
+                for (w, &x) in weight.iter_mut().zip(xb.iter()) {
+                    *w = 0.25 * sigma2 + x * x
+                }
+            }
+        }
+        for (wa, &w) in waux.iter_mut().zip(weight.iter()) {
+            *wa = w.sqrt()
+        }
+        let mut block_signs = [0u8; 2];
+        for (k, s) in block_signs.iter_mut().enumerate() {
+            let r = 8 * k..8 * (k + 1);
+            *s = group_signs(&xb[r.clone()], &weight[r.clone()], &mut xval[r], false);
+        }
+        let max = xval.iter().fold(xval[0], |m, &v| m.max(v));
+        if max < GROUP_MAX_EPS_IQ2_S {
+            scales[ib] = 0.;
+            continue;
+        }
+        let scale = max / (2 * KMAX_Q - 1) as f32;
+        let mut scale = search_grid_scale(
+            grid, KMAX_Q, 9, 0.1, max, scale, false, &xval, &weight, &waux, &mut l,
+        );
+        if scale < 0. {
+            scale = -scale;
+            for s in block_signs.iter_mut() {
+                *s = !*s
+            }
+        }
+        for k in 0..2 {
+            let index = grid_index(grid, &l[8 * k..8 * (k + 1)]);
+            let i8 = 2 * ib + k;
+            y.qs[i8] = (index & 255) as u8;
+            y.qh[i8 / 4] |= ((index >> 8) << (2 * (i8 % 4))) as u8;
+            y.qs[QK_K / 8 + i8] = block_signs[k];
+        }
+        scales[ib] = scale;
+        max_scale = max_scale.max(scale);
+    }
+    if max_scale == 0. {
+        return;
+    }
+    let d = max_scale / 31.;
+    y.d = f16::from_f32(d * 0.9875);
+    let id = 1. / d;
+    for (ib, &scale) in scales.iter().enumerate() {
+        y.scales[ib / 2] |= scale_4bits(id, scale) << (4 * (ib % 2));
+    }
+}
+
+pub(super) fn quantize_iq3_xxs(x: &[f32], y: &mut BlockIQ3XXS, qw: Option<&[f32]>) {
+    const KMAX_Q: i32 = 8;
+    let grid = iq3xxs_grid();
+    let mut scales = [0f32; QK_K / 32];
+    let mut weight = [0f32; 32];
+    let mut waux = [0f32; 32];
+    let mut xval = [0f32; 32];
+    let mut l = [0i8; 32];
+    let mut scales_and_signs = [0u32; QK_K / 32];
+    let mut max_scale = 0f32;
+    y.d = f16::ZERO;
+    y.qs.fill(0);
+    let sigma2 = 2. * sum_squares(x) / QK_K as f32;
+    for ib in 0..QK_K / 32 {
+        let xb = &x[32 * ib..32 * (ib + 1)];
+        block_weights(
+            xb,
+            qw.map(|qw| &qw[32 * ib..32 * (ib + 1)]),
+            sigma2,
+            &mut weight,
+        );
+        for (wa, &w) in waux.iter_mut().zip(weight.iter()) {
+            *wa = w.sqrt()
+        }
+        let mut block_signs = [0u8; 4];
+        for (k, s) in block_signs.iter_mut().enumerate() {
+            let r = 8 * k..8 * (k + 1);
+            *s = group_signs(&xb[r.clone()], &weight[r.clone()], &mut xval[r], true);
+        }
+        let max = xval.iter().fold(xval[0], |m, &v| m.max(v));
+        if max < GROUP_MAX_EPS_IQ3_XXS {
+            scales[ib] = 0.;
+            continue;
+        }
+        let scale = max / (2 * KMAX_Q - 1) as f32;
+        let mut scale = search_grid_scale(
+            grid, KMAX_Q, 15, 0.2, max, scale, false, &xval, &weight, &waux, &mut l,
+        );
+        if scale < 0. {
+            scale = -scale;
+            for s in block_signs.iter_mut() {
+                *s = !*s & 127
+            }
+        }
+        for k in 0..8 {
+            y.qs[8 * ib + k] = grid_index(grid, &l[4 * k..4 * (k + 1)]) as u8;
+        }
+        scales_and_signs[ib] = block_signs[0] as u32
+            | (block_signs[1] as u32) << 7
+            | (block_signs[2] as u32) << 14
+            | (block_signs[3] as u32) << 21;
+        scales[ib] = scale;
+        max_scale = max_scale.max(scale);
+    }
+    if max_scale == 0. {
+        y.qs.fill(0);
+        return;
+    }
+    let d = max_scale / 31.;
+    y.d = f16::from_f32(d * 1.0125);
+    let id = 1. / d;
+    for ib in 0..QK_K / 32 {
+        let v = scales_and_signs[ib] | (scale_4bits(id, scales[ib]) as u32) << 28;
+        y.qs[QK_K / 4 + 4 * ib..QK_K / 4 + 4 * (ib + 1)].copy_from_slice(&v.to_le_bytes());
+    }
+}
+
+pub(super) fn quantize_iq3_s(x: &[f32], y: &mut BlockIQ3S, qw: Option<&[f32]>) {
+    const KMAX_Q: i32 = 8;
+    const BLOCK_SIZE: usize = 32;
+    let grid = iq3s_grid();
+    let mut scales = [0f32; QK_K / BLOCK_SIZE];
+    let mut weight = [0f32; BLOCK_SIZE];
+    let mut waux = [0f32; BLOCK_SIZE];
+    let mut xval = [0f32; BLOCK_SIZE];
+    let mut l = [0i8; BLOCK_SIZE];
+    let mut max_scale = 0f32;
+    y.d = f16::ZERO;
+    y.qs.fill(0);
+    y.qh.fill(0);
+    y.signs.fill(0);
+    y.scales.fill(0);
+    let sigma2 = 2. * sum_squares(x) / QK_K as f32;
+    for ib in 0..QK_K / BLOCK_SIZE {
+        let xb = &x[BLOCK_SIZE * ib..BLOCK_SIZE * (ib + 1)];
+        let qw = qw.map(|qw| &qw[BLOCK_SIZE * ib..BLOCK_SIZE * (ib + 1)]);
+        block_weights(xb, qw, sigma2, &mut weight);
+        for (wa, &w) in waux.iter_mut().zip(weight.iter()) {
+            *wa = w.sqrt()
+        }
+        let mut block_signs = [0u8; BLOCK_SIZE / 8];
+        for (k, s) in block_signs.iter_mut().enumerate() {
+            let r = 8 * k..8 * (k + 1);
+            *s = group_signs(&xb[r.clone()], &weight[r.clone()], &mut xval[r], false);
+        }
+        let max = xval.iter().fold(xval[0], |m, &v| m.max(v));
+        if max == 0. {
+            scales[ib] = 0.;
+            continue;
+        }
+        let scale = max / (2 * KMAX_Q - 1) as f32;
+        let mut scale = search_grid_scale(
+            grid, KMAX_Q, 9, 0.2, max, scale, true, &xval, &weight, &waux, &mut l,
+        );
+        if scale < 0. {
+            scale = -scale;
+            for s in block_signs.iter_mut() {
+                *s = !*s
+            }
+        }
+        let bs4 = BLOCK_SIZE / 4;
+        for k in 0..bs4 {
+            let index = grid_index(grid, &l[4 * k..4 * (k + 1)]);
+            y.qs[ib * bs4 + k] = (index & 255) as u8;
+            let i = ib * bs4 + k;
+            y.qh[i / 8] |= ((index >> 8) << (i % 8)) as u8;
+        }
+        let bs8 = BLOCK_SIZE / 8;
+        y.signs[ib * bs8..(ib + 1) * bs8].copy_from_slice(&block_signs);
+        scales[ib] = scale;
+        max_scale = max_scale.max(scale);
+    }
+    if max_scale == 0. {
+        return;
+    }
+    let d = max_scale / 31.;
+    y.d = f16::from_f32(d * 1.033);
+    let id = 1. / d;
+    for ib in (0..QK_K / BLOCK_SIZE).step_by(2) {
+        let l1 = scale_4bits(id, scales[ib]);
+        let l2 = scale_4bits(id, scales[ib + 1]);
+        y.scales[ib / 2] = l1 | (l2 << 4);
+    }
+}
+
+/// Sorts the indexes of `xb` by increasing value and returns the prefix sums of the weighted
+/// values and of the weights in that order.
+fn sorted_prefix_sums(
+    xb: &[f32],
+    weight: &[f32],
+    idx: &mut [usize],
+    sumx: &mut [f32],
+    sumw: &mut [f32],
+) {
+    for (j, idx) in idx.iter_mut().enumerate() {
+        *idx = j
+    }
+    idx.sort_by(|&a, &b| xb[a].total_cmp(&xb[b]));
+    sumx[0] = 0.;
+    sumw[0] = 0.;
+    for (j, &i) in idx.iter().enumerate() {
+        sumx[j + 1] = sumx[j] + weight[i] * xb[i];
+        sumw[j + 1] = sumw[j] + weight[i];
+    }
+}
+
+pub(super) fn quantize_iq1_s(x: &[f32], y: &mut BlockIQ1S, qw: Option<&[f32]>) {
+    const BLOCK_SIZE: usize = 32;
+    let grid = iq1s_grid();
+    let ones = [1f32; QK_K];
+    let qw = qw.unwrap_or(&ones);
+    let x_p = [-1. + IQ1S_DELTA, IQ1S_DELTA, 1. + IQ1S_DELTA];
+    let x_m = [-1. - IQ1S_DELTA, -IQ1S_DELTA, 1. - IQ1S_DELTA];
+    let mut scales = [0f32; QK_K / BLOCK_SIZE];
+    let mut shifts = [0i8; QK_K / BLOCK_SIZE];
+    let mut weight = [0f32; BLOCK_SIZE];
+    let mut l = [0i8; BLOCK_SIZE];
+    let mut idx = [0usize; BLOCK_SIZE];
+    let mut sumx = [0f32; BLOCK_SIZE + 1];
+    let mut sumw = [0f32; BLOCK_SIZE + 1];
+    let mut index = [0usize; BLOCK_SIZE / 8];
+    let mut max_scale = 0f32;
+    y.d = f16::ZERO;
+    y.qs.fill(0);
+    y.qh.fill(0);
+    let sigma2 = 2. * sum_squares(x) / QK_K as f32;
+    for ib in 0..QK_K / BLOCK_SIZE {
+        let xb = &x[BLOCK_SIZE * ib..BLOCK_SIZE * (ib + 1)];
+        let qwb = &qw[BLOCK_SIZE * ib..BLOCK_SIZE * (ib + 1)];
+        block_weights(xb, Some(qwb), sigma2, &mut weight);
+        let max = xb.iter().fold(0f32, |m, &v| m.max(v.abs()));
+        if max < GROUP_MAX_EPS_IQ1_S {
+            scales[ib] = 0.;
+            continue;
+        }
+        // With only 3 quantized values (-1, 0, 1), the weighted squared error minimization can
+        // be solved exactly by searching over the two boundaries splitting the sorted values in
+        // 3 groups.
+        sorted_prefix_sums(xb, &weight, &mut idx, &mut sumx, &mut sumw);
+        let mut best_score = -f32::MIN_POSITIVE;
+        let mut scale = max;
+        let (mut besti1, mut besti2, mut best_shift) = (0, 0, 0i8);
+        let n = BLOCK_SIZE;
+        for i1 in 0..=n {
+            for i2 in i1..=n {
+                for (xx, shift) in [(&x_p, 1i8), (&x_m, -1i8)] {
+                    let sumqx = sumx[i1] * xx[0]
+                        + (sumx[i2] - sumx[i1]) * xx[1]
+                        + (sumx[n] - sumx[i2]) * xx[2];
+                    let sumq2 = sumw[i1] * xx[0] * xx[0]
+                        + (sumw[i2] - sumw[i1]) * xx[1] * xx[1]
+                        + (sumw[n] - sumw[i2]) * xx[2] * xx[2];
+                    if sumq2 > 0. && sumqx * sumqx > best_score * sumq2 {
+                        scale = sumqx / sumq2;
+                        best_score = scale * sumqx;
+                        besti1 = i1;
+                        besti2 = i2;
+                        best_shift = shift;
+                    }
+                }
+            }
+        }
+        for (j, &i) in idx.iter().enumerate() {
+            l[i] = if j < besti1 {
+                0
+            } else if j < besti2 {
+                1
+            } else {
+                2
+            }
+        }
+        if scale < 0. {
+            for l in l.iter_mut() {
+                *l = 2 - *l
+            }
+            scale = -scale;
+            best_shift = -best_shift;
+        }
+        let xx = if best_shift == 1 { &x_p } else { &x_m };
+        let mut all_on_grid = true;
+        for (k, index) in index.iter_mut().enumerate() {
+            let r = 8 * k..8 * (k + 1);
+            let entry = grid.lookup(&l[r.clone()]);
+            *index = if entry >= 0 {
+                entry as usize
+            } else {
+                all_on_grid = false;
+                grid.best_neighbour_iq1(
+                    entry,
+                    &xb[r.clone()],
+                    &weight[r.clone()],
+                    scale,
+                    xx,
+                    &mut l[r],
+                )
+            };
+        }
+        if !all_on_grid {
+            let mut sumqx = 0f32;
+            let mut sumq2 = 0f32;
+            for (k, &index) in index.iter().enumerate() {
+                for (j, &q) in grid.point(index).iter().enumerate() {
+                    let w = weight[8 * k + j];
+                    let q = xx[q as usize];
+                    sumqx += w * q * xb[8 * k + j];
+                    sumq2 += w * q * q;
+                }
+            }
+            if sumqx > 0. && sumq2 > 0. {
+                scale = sumqx / sumq2
+            }
+        }
+        let mut h = 0u16;
+        for (k, &index) in index.iter().enumerate() {
+            y.qs[(BLOCK_SIZE / 8) * ib + k] = (index & 255) as u8;
+            h |= ((index >> 8) << (3 * k)) as u16;
+        }
+        y.qh[ib] = h;
+        scales[ib] = scale;
+        shifts[ib] = best_shift;
+        max_scale = max_scale.max(scale);
+    }
+    if max_scale == 0. {
+        return;
+    }
+    let d = max_scale / 15.;
+    y.d = f16::from_f32(d * 1.125);
+    let id = 1. / d;
+    for ib in 0..QK_K / BLOCK_SIZE {
+        let mut l = nearest_int(0.5 * (id * scales[ib] - 1.)).clamp(0, 7) as u16;
+        if shifts[ib] == -1 {
+            l |= 8
+        }
+        y.qh[ib] |= l << 12;
+    }
+}
+
+pub(super) fn quantize_iq1_m(x: &[f32], y: &mut BlockIQ1M, qw: Option<&[f32]>) {
+    const BLOCK_SIZE: usize = 16;
+    let grid = iq1s_grid();
+    let x_p = [-1. + IQ1M_DELTA, IQ1M_DELTA, 1. + IQ1M_DELTA];
+    let x_m = [-1. - IQ1M_DELTA, -IQ1M_DELTA, 1. - IQ1M_DELTA];
+    const MASKS: [u8; 4] = [0x00, 0x80, 0x08, 0x88];
+    let mut scales = [0f32; QK_K / BLOCK_SIZE];
+    let mut shifts = [0usize; QK_K / BLOCK_SIZE];
+    let mut weight = [0f32; BLOCK_SIZE];
+    let mut l = [0i8; BLOCK_SIZE];
+    let mut idx = [0usize; BLOCK_SIZE];
+    let mut index = [0usize; BLOCK_SIZE / 8];
+    let mut max_scale = 0f32;
+    y.qs.fill(0);
+    y.qh.fill(0);
+    y.scales.fill(0);
+    // The shift of the first and second group of 8 for the 4 possible shift combinations.
+    let shift_values = |k: usize, group: usize| {
+        let negative = if group == 0 { k >= 2 } else { k % 2 == 1 };
+        if negative {
+            &x_m
+        } else {
+            &x_p
+        }
+    };
+    let sigma2 = 2. * sum_squares(x) / QK_K as f32;
+    for ib in 0..QK_K / BLOCK_SIZE {
+        let xb = &x[BLOCK_SIZE * ib..BLOCK_SIZE * (ib + 1)];
+        let qwb = qw.map(|qw| &qw[BLOCK_SIZE * ib..BLOCK_SIZE * (ib + 1)]);
+        block_weights(xb, qwb, sigma2, &mut weight);
+        let max = xb.iter().fold(0f32, |m, &v| m.max(v.abs()));
+        if max < GROUP_MAX_EPS_IQ1_M {
+            scales[ib] = 0.;
+            continue;
+        }
+        for (j, idx) in idx.iter_mut().enumerate() {
+            *idx = j
+        }
+        idx.sort_by(|&a, &b| xb[a].total_cmp(&xb[b]));
+        let mut best_score = -f32::MIN_POSITIVE;
+        let mut scale = max;
+        let (mut besti1, mut besti2, mut best_k) = (0, 0, 0);
+        for i1 in 0..=BLOCK_SIZE {
+            for i2 in i1..=BLOCK_SIZE {
+                let mut sumqx = [0f32; 4];
+                let mut sumq2 = [0f32; 4];
+                for (j, &i) in idx.iter().enumerate() {
+                    let level = if j < i1 {
+                        0
+                    } else if j < i2 {
+                        1
+                    } else {
+                        2
+                    };
+                    let group = i / 8;
+                    for k in 0..4 {
+                        let q = shift_values(k, group)[level];
+                        sumqx[k] += weight[i] * q * xb[i];
+                        sumq2[k] += weight[i] * q * q;
+                    }
+                }
+                for k in 0..4 {
+                    if sumq2[k] > 0. && sumqx[k] * sumqx[k] > best_score * sumq2[k] {
+                        scale = sumqx[k] / sumq2[k];
+                        best_score = scale * sumqx[k];
+                        besti1 = i1;
+                        besti2 = i2;
+                        best_k = k;
+                    }
+                }
+            }
+        }
+        for (j, &i) in idx.iter().enumerate() {
+            l[i] = if j < besti1 {
+                0
+            } else if j < besti2 {
+                1
+            } else {
+                2
+            }
+        }
+        if scale < 0. {
+            for l in l.iter_mut() {
+                *l = 2 - *l
+            }
+            scale = -scale;
+            best_k = 3 - best_k;
+        }
+        let mut all_on_grid = true;
+        for (k, index) in index.iter_mut().enumerate() {
+            let xx = shift_values(best_k, k);
+            let r = 8 * k..8 * (k + 1);
+            let entry = grid.lookup(&l[r.clone()]);
+            *index = if entry >= 0 {
+                entry as usize
+            } else {
+                all_on_grid = false;
+                grid.best_neighbour_iq1(
+                    entry,
+                    &xb[r.clone()],
+                    &weight[r.clone()],
+                    scale,
+                    xx,
+                    &mut l[r],
+                )
+            };
+        }
+        if !all_on_grid {
+            let mut sumqx = 0f32;
+            let mut sumq2 = 0f32;
+            for (k, &index) in index.iter().enumerate() {
+                let xx = shift_values(best_k, k);
+                for (j, &q) in grid.point(index).iter().enumerate() {
+                    let w = weight[8 * k + j];
+                    let q = xx[q as usize];
+                    sumqx += w * q * xb[8 * k + j];
+                    sumq2 += w * q * q;
+                }
+            }
+            if sumqx > 0. && sumq2 > 0. {
+                scale = sumqx / sumq2
+            }
+        }
+        y.qs[2 * ib] = (index[0] & 255) as u8;
+        y.qs[2 * ib + 1] = (index[1] & 255) as u8;
+        y.qh[ib] = ((index[0] >> 8) | ((index[1] >> 8) << 4)) as u8;
+        scales[ib] = scale;
+        shifts[ib] = best_k;
+        max_scale = max_scale.max(scale);
+    }
+    if max_scale == 0. {
+        return;
+    }
+    let mut sc = [0u16; 4];
+    let mut d = max_scale / 15.;
+    let id = 1. / d;
+    let mut sumqx = 0f32;
+    let mut sumq2 = 0f32;
+    for ib in 0..QK_K / BLOCK_SIZE {
+        let l = nearest_int(0.5 * (id * scales[ib] - 1.)).clamp(0, 7);
+        sc[ib / 4] |= (l as u16) << (3 * (ib % 4));
+        y.qh[ib] |= MASKS[shifts[ib]];
+        let xb = &x[BLOCK_SIZE * ib..BLOCK_SIZE * (ib + 1)];
+        let qwb = qw.map(|qw| &qw[BLOCK_SIZE * ib..BLOCK_SIZE * (ib + 1)]);
+        block_weights(xb, qwb, sigma2, &mut weight);
+        for k in 0..BLOCK_SIZE / 8 {
+            let xx = shift_values(shifts[ib], k);
+            let index = y.qs[2 * ib + k] as usize | (((y.qh[ib] as usize) << (8 - 4 * k)) & 0x700);
+            for (j, &q) in grid.point(index).iter().enumerate() {
+                let w = weight[8 * k + j];
+                let q = xx[q as usize] * (2 * l + 1) as f32;
+                sumqx += w * q * xb[8 * k + j];
+                sumq2 += w * q * q;
+            }
+        }
+    }
+    if sumq2 > 0. {
+        d = sumqx / sumq2
+    }
+    let s = f16::from_f32(d * 1.1125).to_bits();
+    sc[0] |= (s & 0x000f) << 12;
+    sc[1] |= (s & 0x00f0) << 8;
+    sc[2] |= (s & 0x0f00) << 4;
+    sc[3] |= s & 0xf000;
+    for (i, sc) in sc.iter().enumerate() {
+        y.scales[2 * i..2 * i + 2].copy_from_slice(&sc.to_le_bytes())
+    }
+}
+
+/// Returns the index of the value closest to `x` in the sorted `values`.
+fn best_index_int8(values: &[i8], x: f32) -> usize {
+    let n = values.len();
+    if x <= values[0] as f32 {
+        return 0;
+    }
+    if x >= values[n - 1] as f32 {
+        return n - 1;
+    }
+    let (mut ml, mut mu) = (0, n - 1);
+    while mu - ml > 1 {
+        let mav = (ml + mu) / 2;
+        if x < values[mav] as f32 {
+            mu = mav
+        } else {
+            ml = mav
+        }
+    }
+    if x - (values[mu - 1] as f32) < values[mu] as f32 - x {
+        mu - 1
+    } else {
+        mu
+    }
+}
+
+/// Shared implementation for the non-linear 4-bit quants, the super-block is made of blocks of
+/// 32 values, each with their own scale when there are more than one of them. Returns the
+/// super-block scale.
+fn quantize_iq4_impl(
+    x: &[f32],
+    q4: &mut [u8],
+    scales_h: &mut u16,
+    scales_l: &mut [u8],
+    qw: Option<&[f32]>,
+) -> f32 {
+    const BLOCK_SIZE: usize = 32;
+    const NTRY: i32 = 7;
+    let values = &KVALUES_IQ4NL;
+    let super_block_size = x.len();
+    let nb = super_block_size / BLOCK_SIZE;
+    let mut l = [0u8; QK_K];
+    let mut scales = [0f32; QK_K / BLOCK_SIZE];
+    let mut weight = [0f32; BLOCK_SIZE];
+    let sigma2 = 2. * sum_squares(x) / super_block_size as f32;
+    q4.fill(0);
+
+    let mut max_scale = 0f32;
+    let mut amax_scale = 0f32;
+    for ib in 0..nb {
+        let xb = &x[ib * BLOCK_SIZE..(ib + 1) * BLOCK_SIZE];
+        let lb = &mut l[ib * BLOCK_SIZE..(ib + 1) * BLOCK_SIZE];
+        let qwb = qw.map(|qw| &qw[ib * BLOCK_SIZE..(ib + 1) * BLOCK_SIZE]);
+        block_weights(xb, qwb, sigma2, &mut weight);
+        let mut amax = 0f32;
+        let mut max = 0f32;
+        for &v in xb.iter() {
+            if v.abs() > amax {
+                amax = v.abs();
+                max = v;
+            }
+        }
+        if amax < GROUP_MAX_EPS {
+            scales[ib] = 0.;
+            continue;
+        }
+        let mut d = -max / values[0] as f32;
+        let mut id = 1. / d;
+        let mut sumqx = 0f32;
+        let mut sumq2 = 0f32;
+        for (j, &v) in xb.iter().enumerate() {
+            let li = best_index_int8(values, id * v);
+            lb[j] = li as u8;
+            let q = values[li] as f32;
+            sumqx += weight[j] * q * v;
+            sumq2 += weight[j] * q * q;
+        }
+        d = sumqx / sumq2;
+        let mut best = d * sumqx;
+        for itry in -NTRY..=NTRY {
+            id = (itry as f32 + values[0] as f32) / max;
+            sumqx = 0.;
+            sumq2 = 0.;
+            for (j, &v) in xb.iter().enumerate() {
+                let q = values[best_index_int8(values, id * v)] as f32;
+                sumqx += weight[j] * q * v;
+                sumq2 += weight[j] * q * q;
+            }
+            if sumq2 > 0. && sumqx * sumqx > best * sumq2 {
+                d = sumqx / sumq2;
+                best = d * sumqx;
+            }
+        }
+        scales[ib] = d;
+        if d.abs() > amax_scale {
+            amax_scale = d.abs();
+            max_scale = d;
+        }
+    }
+
+    let dh = if nb > 1 {
+        *scales_h = 0;
+        scales_l.fill(0);
+        let d = -max_scale / 32.;
+        let id = if d != 0. { 1. / d } else { 0. };
+        for ib in 0..nb {
+            let li = nearest_int(id * scales[ib]).clamp(-32, 31);
+            let dl = d * li as f32;
+            let idl = if dl != 0. { 1. / dl } else { 0. };
+            let xb = &x[ib * BLOCK_SIZE..(ib + 1) * BLOCK_SIZE];
+            for (lb, &v) in l[ib * BLOCK_SIZE..].iter_mut().zip(xb.iter()) {
+                *lb = best_index_int8(values, idl * v) as u8;
+            }
+            let li = (li + 32) as u8;
+            scales_l[ib / 2] |= (li & 0xf) << (4 * (ib % 2));
+            *scales_h |= ((li >> 4) as u16) << (2 * (ib % 8));
+        }
+        d
+    } else {
+        let d = scales[0];
+        let id = if d != 0. { 1. / d } else { 0. };
+        for (l, &v) in l.iter_mut().zip(x.iter()) {
+            *l = best_index_int8(values, id * v) as u8;
+        }
+        d
+    };
+    for i in 0..super_block_size / 32 {
+        for j in 0..16 {
+            q4[16 * i + j] = l[32 * i + j] | (l[32 * i + 16 + j] << 4);
+        }
+    }
+    dh
+}
+
+pub(super) fn quantize_iq4_nl(x: &[f32], y: &mut BlockIQ4NL, qw: Option<&[f32]>) {
+    debug_assert_eq!(x.len(), QK4_NL);
+    let mut scales_h = 0u16;
+    let d = quantize_iq4_impl(x, &mut y.qs, &mut scales_h, &mut [], qw);
+    y.d = f16::from_f32(d);
+}
+
+pub(super) fn quantize_iq4_xs(x: &[f32], y: &mut BlockIQ4XS, qw: Option<&[f32]>) {
+    let d = quantize_iq4_impl(x, &mut y.qs, &mut y.scales_h, &mut y.scales_l, qw);
+    y.d = f16::from_f32(d);
+}
@@ -1,3 +1,8 @@
+use super::iq_grids::{
+    IQ1S_GRID, IQ2S_GRID, IQ2XS_GRID, IQ2XXS_GRID, IQ3S_GRID, IQ3XXS_GRID, KMASK_IQ2XS,
+    KSIGNS_IQ2XS, KVALUES_IQ4NL,
+};
+use super::iq_quants;
 use super::utils::{
     get_scale_min_k4, group_for_dequantization, group_for_quantization, make_q3_quants,
     make_qkx1_quants, make_qx_quants, nearest_int,
@@ -18,6 +23,7 @@ pub const QK5_0: usize = 32;
 pub const QK5_1: usize = 32;
 pub const QK8_0: usize = 32;
 pub const QK8_1: usize = 32;
+pub const QK4_NL: usize = 32;
 
 pub trait GgmlType: Sized + Clone + Send + Sync {
     const DTYPE: GgmlDType;
@@ -31,12 +37,35 @@ pub trait GgmlType: Sized + Clone + Send + Sync {
     fn to_float(xs: &[Self], ys: &mut [f32]) -> Result<()>;
     fn from_float(xs: &[f32], ys: &mut [Self]) -> Result<()>;
 
+    /// Quantization using an importance matrix, `imatrix` has one weight per column and `xs` is
+    /// made of rows of `imatrix.len()` elements. The weights are ignored by default, only the
+    /// i-quants make use of them.
+    fn from_float_imatrix(xs: &[f32], ys: &mut [Self], imatrix: &[f32]) -> Result<()> {
+        let _ = imatrix;
+        Self::from_float(xs, ys)
+    }
+
     /// Dot product used as a building block for quantized mat-mul.
     /// n is the number of elements to be considered.
     fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32>;
 
     /// Generic implementation of the dot product without simd optimizations.
     fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32>;
+
+    /// Dot products between `xs` and multiple rows, the result for `ys[i]` is written in
+    /// `dst[i]`. This is used by the tiled mat-mul, kernels can override it to decode each block
+    /// of `xs` only once for all the rows.
+    fn vec_dot_rows(
+        n: usize,
+        xs: &[Self],
+        ys: &[&[Self::VecDotType]],
+        dst: &mut [f32],
+    ) -> Result<()> {
+        for (ys, dst) in ys.iter().zip(dst.iter_mut()) {
+            *dst = Self::vec_dot(n, xs, ys)?
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -154,6 +183,90 @@ pub struct BlockQ8K {
 }
 const _: () = assert!(4 + QK_K + QK_K / 16 * 2 == std::mem::size_of::<BlockQ8K>());
 
+#[derive(Debug, Clone, PartialEq)]
+#[repr(C)]
+pub struct BlockIQ2XXS {
+    pub(crate) d: f16,
+    pub(crate) qs: [u16; QK_K / 8],
+}
+const _: () = assert!(2 + QK_K / 4 == std::mem::size_of::<BlockIQ2XXS>());
+
+#[derive(Debug, Clone, PartialEq)]
+#[repr(C)]
+pub struct BlockIQ2XS {
+    pub(crate) d: f16,
+    pub(crate) qs: [u16; QK_K / 8],
+    pub(crate) scales: [u8; QK_K / 32],
+}
+const _: () = assert!(2 + QK_K / 4 + QK_K / 32 == std::mem::size_of::<BlockIQ2XS>());
+
+#[derive(Debug, Clone, PartialEq)]
+#[repr(C)]
+pub struct BlockIQ2S {
+    pub(crate) d: f16,
+    pub(crate) qs: [u8; QK_K / 4],
+    pub(crate) qh: [u8; QK_K / 32],
+    pub(crate) scales: [u8; QK_K / 32],
+}
+const _: () = assert!(2 + QK_K / 4 + QK_K / 16 == std::mem::size_of::<BlockIQ2S>());
+
+#[derive(Debug, Clone, PartialEq)]
+#[repr(C)]
+pub struct BlockIQ3XXS {
+    pub(crate) d: f16,
+    pub(crate) qs: [u8; 3 * QK_K / 8],
+}
+const _: () = assert!(2 + 3 * QK_K / 8 == std::mem::size_of::<BlockIQ3XXS>());
+
+#[derive(Debug, Clone, PartialEq)]
+#[repr(C)]
+pub struct BlockIQ3S {
+    pub(crate) d: f16,
+    pub(crate) qs: [u8; QK_K / 4],
+    pub(crate) qh: [u8; QK_K / 32],
+    pub(crate) signs: [u8; QK_K / 8],
+    pub(crate) scales: [u8; QK_K / 64],
+}
+const _: () =
+    assert!(2 + QK_K / 4 + QK_K / 32 + QK_K / 8 + QK_K / 64 == std::mem::size_of::<BlockIQ3S>());
+
+#[derive(Debug, Clone, PartialEq)]
+#[repr(C)]
+pub struct BlockIQ1S {
+    pub(crate) d: f16,
+    pub(crate) qs: [u8; QK_K / 8],
+    pub(crate) qh: [u16; QK_K / 32],
+}
+const _: () = assert!(2 + QK_K / 8 + QK_K / 16 == std::mem::size_of::<BlockIQ1S>());
+
+// The super-block scale is split in 4 nibbles stored in the top bits of the scales.
+#[derive(Debug, Clone, PartialEq)]
+#[repr(C)]
+pub struct BlockIQ1M {
+    pub(crate) qs: [u8; QK_K / 8],
+    pub(crate) qh: [u8; QK_K / 16],
+    pub(crate) scales: [u8; QK_K / 32],
+}
+const _: () = assert!(QK_K / 8 + QK_K / 16 + QK_K / 32 == std::mem::size_of::<BlockIQ1M>());
+
+#[derive(Debug, Clone, PartialEq)]
+#[repr(C)]
+pub struct BlockIQ4NL {
+    pub(crate) d: f16,
+    pub(crate) qs: [u8; QK4_NL / 2],
+}
+const _: () = assert!(std::mem::size_of::<BlockIQ4NL>() == 18);
+
+#[derive(Debug, Clone, PartialEq)]
+#[repr(C)]
+pub struct BlockIQ4XS {
+    pub(crate) d: f16,
+    pub(crate) scales_h: u16,
+    pub(crate) scales_l: [u8; QK_K / 64],
+    pub(crate) qs: [u8; QK_K / 2],
+}
+const _: () = assert!(4 + QK_K / 64 + QK_K / 2 == std::mem::size_of::<BlockIQ4XS>());
+
 impl GgmlType for BlockQ4_0 {
     const DTYPE: GgmlDType = GgmlDType::Q4_0;
     const BLCK_SIZE: usize = QK4_0;
@@ -234,6 +347,22 @@ impl GgmlType for BlockQ4_0 {
         Self::vec_dot_unopt(n, xs, ys)
     }
 
+    #[allow(unreachable_code)]
+    fn vec_dot_rows(
+        n: usize,
+        xs: &[Self],
+        ys: &[&[Self::VecDotType]],
+        dst: &mut [f32],
+    ) -> Result<()> {
+        #[cfg(target_feature = "avx")]
+        return super::avx::vec_dot_q4_0_q8_0_rows(n, xs, ys, dst);
+
+        for (ys, dst) in ys.iter().zip(dst.iter_mut()) {
+            *dst = Self::vec_dot(n, xs, ys)?
+        }
+        Ok(())
+    }
+
     fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
         let qk = QK8_0;
         if n % QK8_0 != 0 {
@@ -628,6 +757,22 @@ impl GgmlType for BlockQ8_0 {
         Self::vec_dot_unopt(n, xs, ys)
     }
 
+    #[allow(unreachable_code)]
+    fn vec_dot_rows(
+        n: usize,
+        xs: &[Self],
+        ys: &[&[Self::VecDotType]],
+        dst: &mut [f32],
+    ) -> Result<()> {
+        #[cfg(target_feature = "avx")]
+        return super::avx::vec_dot_q8_0_q8_0_rows(n, xs, ys, dst);
+
+        for (ys, dst) in ys.iter().zip(dst.iter_mut()) {
+            *dst = Self::vec_dot(n, xs, ys)?
+        }
+        Ok(())
+    }
+
     fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
         let qk = QK8_0;
         if n % QK8_0 != 0 {
@@ -1168,6 +1313,22 @@ impl GgmlType for BlockQ4K {
         Self::vec_dot_unopt(n, xs, ys)
     }
 
+    #[allow(unreachable_code)]
+    fn vec_dot_rows(
+        n: usize,
+        xs: &[Self],
+        ys: &[&[Self::VecDotType]],
+        dst: &mut [f32],
+    ) -> Result<()> {
+        #[cfg(target_feature = "avx")]
+        return super::avx::vec_dot_q4k_q8k_rows(n, xs, ys, dst);
+
+        for (ys, dst) in ys.iter().zip(dst.iter_mut()) {
+            *dst = Self::vec_dot(n, xs, ys)?
+        }
+        Ok(())
+    }
+
     fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
         if n % QK_K != 0 {
             crate::bail!("vec_dot_q4k_q8k: {n} is not divisible by {QK_K}")
@@ -1838,12 +1999,685 @@ impl GgmlType for BlockQ8K {
     }
 }
 
+#[inline(always)]
+fn iq_sign(signs: u8, j: usize) -> i32 {
+    if signs & KMASK_IQ2XS[j] != 0 {
+        -1
+    } else {
+        1
+    }
+}
+
+impl GgmlType for BlockIQ2XXS {
+    const DTYPE: GgmlDType = GgmlDType::IQ2XXS;
+    const BLCK_SIZE: usize = QK_K;
+    type VecDotType = BlockQ8K;
+
+    fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        Self::vec_dot_unopt(n, xs, ys)
+    }
+
+    fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        if n % QK_K != 0 {
+            crate::bail!("vec_dot_iq2_xxs_q8k: {n} is not divisible by {QK_K}")
+        }
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let d = x.d.to_f32() * y.d;
+            let mut bsum = 0i32;
+            for (qs, q8) in x.qs.chunks_exact(4).zip(y.qs.chunks_exact(32)) {
+                let aux0 = qs[0] as u32 | (qs[1] as u32) << 16;
+                let aux1 = qs[2] as u32 | (qs[3] as u32) << 16;
+                let ls = 2 * (aux1 >> 28) as i32 + 1;
+                let mut sumi = 0i32;
+                for l in 0..4 {
+                    let grid = IQ2XXS_GRID[((aux0 >> (8 * l)) & 255) as usize].to_le_bytes();
+                    let signs = KSIGNS_IQ2XS[((aux1 >> (7 * l)) & 127) as usize];
+                    for j in 0..8 {
+                        sumi += grid[j] as i32 * q8[8 * l + j] as i32 * iq_sign(signs, j)
+                    }
+                }
+                bsum += sumi * ls;
+            }
+            sumf += d * bsum as f32;
+        }
+        Ok(0.125 * sumf)
+    }
+
+    fn from_float(xs: &[f32], ys: &mut [Self]) -> Result<()> {
+        iq_quants::quantize_blocks(xs, ys, None, iq_quants::quantize_iq2_xxs)
+    }
+
+    fn from_float_imatrix(xs: &[f32], ys: &mut [Self], imatrix: &[f32]) -> Result<()> {
+        iq_quants::quantize_blocks(xs, ys, Some(imatrix), iq_quants::quantize_iq2_xxs)
+    }
+
+    fn to_float(xs: &[Self], ys: &mut [f32]) -> Result<()> {
+        let k = ys.len();
+        if k % QK_K != 0 {
+            crate::bail!("dequantize_row_iq2_xxs: {k} is not divisible by {QK_K}")
+        }
+        for (x, ys) in xs.iter().zip(ys.chunks_exact_mut(QK_K)) {
+            let d = x.d.to_f32();
+            for (qs, ys) in x.qs.chunks_exact(4).zip(ys.chunks_exact_mut(32)) {
+                let aux0 = qs[0] as u32 | (qs[1] as u32) << 16;
+                let aux1 = qs[2] as u32 | (qs[3] as u32) << 16;
+                let db = d * (0.5 + (aux1 >> 28) as f32) * 0.25;
+                for l in 0..4 {
+                    let grid = IQ2XXS_GRID[((aux0 >> (8 * l)) & 255) as usize].to_le_bytes();
+                    let signs = KSIGNS_IQ2XS[((aux1 >> (7 * l)) & 127) as usize];
+                    for j in 0..8 {
+                        ys[8 * l + j] = db * grid[j] as f32 * iq_sign(signs, j) as f32
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl GgmlType for BlockIQ2XS {
+    const DTYPE: GgmlDType = GgmlDType::IQ2XS;
+    const BLCK_SIZE: usize = QK_K;
+    type VecDotType = BlockQ8K;
+
+    fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        Self::vec_dot_unopt(n, xs, ys)
+    }
+
+    fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        if n % QK_K != 0 {
+            crate::bail!("vec_dot_iq2_xs_q8k: {n} is not divisible by {QK_K}")
+        }
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let d = x.d.to_f32() * y.d;
+            let mut bsum = 0i32;
+            for ib32 in 0..QK_K / 32 {
+                let ls = [
+                    2 * (x.scales[ib32] & 0xf) as i32 + 1,
+                    2 * (x.scales[ib32] >> 4) as i32 + 1,
+                ];
+                for l in 0..4 {
+                    let q = x.qs[4 * ib32 + l];
+                    let grid = IQ2XS_GRID[(q & 511) as usize].to_le_bytes();
+                    let signs = KSIGNS_IQ2XS[(q >> 9) as usize];
+                    let q8 = &y.qs[32 * ib32 + 8 * l..32 * ib32 + 8 * (l + 1)];
+                    let mut sumi = 0i32;
+                    for j in 0..8 {
+                        sumi += grid[j] as i32 * q8[j] as i32 * iq_sign(signs, j)
+                    }
+                    bsum += sumi * ls[l / 2];
+                }
+            }
+            sumf += d * bsum as f32;
+        }
+        Ok(0.125 * sumf)
+    }
+
+    fn from_float(xs: &[f32], ys: &mut [Self]) -> Result<()> {
+        iq_quants::quantize_blocks(xs, ys, None, iq_quants::quantize_iq2_xs)
+    }
+
+    fn from_float_imatrix(xs: &[f32], ys: &mut [Self], imatrix: &[f32]) -> Result<()> {
+        iq_quants::quantize_blocks(xs, ys, Some(imatrix), iq_quants::quantize_iq2_xs)
+    }
+
+    fn to_float(xs: &[Self], ys: &mut [f32]) -> Result<()> {
+        let k = ys.len();
+        if k % QK_K != 0 {
+            crate::bail!("dequantize_row_iq2_xs: {k} is not divisible by {QK_K}")
+        }
+        for (x, ys) in xs.iter().zip(ys.chunks_exact_mut(QK_K)) {
+            let d = x.d.to_f32();
+            for (ib32, ys) in ys.chunks_exact_mut(32).enumerate() {
+                let db = [
+                    d * (0.5 + (x.scales[ib32] & 0xf) as f32) * 0.25,
+                    d * (0.5 + (x.scales[ib32] >> 4) as f32) * 0.25,
+                ];
+                for l in 0..4 {
+                    let q = x.qs[4 * ib32 + l];
+                    let grid = IQ2XS_GRID[(q & 511) as usize].to_le_bytes();
+                    let signs = KSIGNS_IQ2XS[(q >> 9) as usize];
+                    for j in 0..8 {
+                        ys[8 * l + j] = db[l / 2] * grid[j] as f32 * iq_sign(signs, j) as f32
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl GgmlType for BlockIQ2S {
+    const DTYPE: GgmlDType = GgmlDType::IQ2S;
+    const BLCK_SIZE: usize = QK_K;
+    type VecDotType = BlockQ8K;
+
+    fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        Self::vec_dot_unopt(n, xs, ys)
+    }
+
+    fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        if n % QK_K != 0 {
+            crate::bail!("vec_dot_iq2_s_q8k: {n} is not divisible by {QK_K}")
+        }
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let d = x.d.to_f32() * y.d;
+            let (qs, signs) = x.qs.split_at(QK_K / 8);
+            let mut bsum = 0i32;
+            for ib32 in 0..QK_K / 32 {
+                let ls = [
+                    2 * (x.scales[ib32] & 0xf) as i32 + 1,
+                    2 * (x.scales[ib32] >> 4) as i32 + 1,
+                ];
+                let qh = x.qh[ib32] as usize;
+                for l in 0..4 {
+                    let index = qs[4 * ib32 + l] as usize | ((qh << (8 - 2 * l)) & 0x300);
+                    let grid = IQ2S_GRID[index].to_le_bytes();
+                    let signs = signs[4 * ib32 + l];
+                    let q8 = &y.qs[32 * ib32 + 8 * l..32 * ib32 + 8 * (l + 1)];
+                    let mut sumi = 0i32;
+                    for j in 0..8 {
+                        sumi += grid[j] as i32 * q8[j] as i32 * iq_sign(signs, j)
+                    }
+                    bsum += sumi * ls[l / 2];
+                }
+            }
+            sumf += d * bsum as f32;
+        }
+        Ok(0.125 * sumf)
+    }
+
+    fn from_float(xs: &[f32], ys: &mut [Self]) -> Result<()> {
+        iq_quants::quantize_blocks(xs, ys, None, iq_quants::quantize_iq2_s)
+    }
+
+    fn from_float_imatrix(xs: &[f32], ys: &mut [Self], imatrix: &[f32]) -> Result<()> {
+        iq_quants::quantize_blocks(xs, ys, Some(imatrix), iq_quants::quantize_iq2_s)
+    }
+
+    fn to_float(xs: &[Self], ys: &mut [f32]) -> Result<()> {
+        let k = ys.len();
+        if k % QK_K != 0 {
+            crate::bail!("dequantize_row_iq2_s: {k} is not divisible by {QK_K}")
+        }
+        for (x, ys) in xs.iter().zip(ys.chunks_exact_mut(QK_K)) {
+            let d = x.d.to_f32();
+            let (qs, signs) = x.qs.split_at(QK_K / 8);
+            for (ib32, ys) in ys.chunks_exact_mut(32).enumerate() {
+                let db = [
+                    d * (0.5 + (x.scales[ib32] & 0xf) as f32) * 0.25,
+                    d * (0.5 + (x.scales[ib32] >> 4) as f32) * 0.25,
+                ];
+                let qh = x.qh[ib32] as usize;
+                for l in 0..4 {
+                    let index = qs[4 * ib32 + l] as usize | ((qh << (8 - 2 * l)) & 0x300);
+                    let grid = IQ2S_GRID[index].to_le_bytes();
+                    let signs = signs[4 * ib32 + l];
+                    for j in 0..8 {
+                        ys[8 * l + j] = db[l / 2] * grid[j] as f32 * iq_sign(signs, j) as f32
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl GgmlType for BlockIQ3XXS {
+    const DTYPE: GgmlDType = GgmlDType::IQ3XXS;
+    const BLCK_SIZE: usize = QK_K;
+    type VecDotType = BlockQ8K;
+
+    fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        Self::vec_dot_unopt(n, xs, ys)
+    }
+
+    fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        if n % QK_K != 0 {
+            crate::bail!("vec_dot_iq3_xxs_q8k: {n} is not divisible by {QK_K}")
+        }
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let d = x.d.to_f32() * y.d;
+            let (qs, scales_and_signs) = x.qs.split_at(QK_K / 4);
+            let mut bsum = 0i32;
+            for ib32 in 0..QK_K / 32 {
+                let aux = LittleEndian::read_u32(&scales_and_signs[4 * ib32..]);
+                let ls = 2 * (aux >> 28) as i32 + 1;
+                let mut sumi = 0i32;
+                for l in 0..4 {
+                    let signs = KSIGNS_IQ2XS[((aux >> (7 * l)) & 127) as usize];
+                    let grid1 = IQ3XXS_GRID[qs[8 * ib32 + 2 * l] as usize].to_le_bytes();
+                    let grid2 = IQ3XXS_GRID[qs[8 * ib32 + 2 * l + 1] as usize].to_le_bytes();
+                    let q8 = &y.qs[32 * ib32 + 8 * l..32 * ib32 + 8 * (l + 1)];
+                    for j in 0..4 {
+                        sumi += grid1[j] as i32 * q8[j] as i32 * iq_sign(signs, j);
+                        sumi += grid2[j] as i32 * q8[j + 4] as i32 * iq_sign(signs, j + 4);
+                    }
+                }
+                bsum += sumi * ls;
+            }
+            sumf += d * bsum as f32;
+        }
+        Ok(0.25 * sumf)
+    }
+
+    fn from_float(xs: &[f32], ys: &mut [Self]) -> Result<()> {
+        iq_quants::quantize_blocks(xs, ys, None, iq_quants::quantize_iq3_xxs)
+    }
+
+    fn from_float_imatrix(xs: &[f32], ys: &mut [Self], imatrix: &[f32]) -> Result<()> {
+        iq_quants::quantize_blocks(xs, ys, Some(imatrix), iq_quants::quantize_iq3_xxs)
+    }
+
+    fn to_float(xs: &[Self], ys: &mut [f32]) -> Result<()> {
+        let k = ys.len();
+        if k % QK_K != 0 {
+            crate::bail!("dequantize_row_iq3_xxs: {k} is not divisible by {QK_K}")
+        }
+        for (x, ys) in xs.iter().zip(ys.chunks_exact_mut(QK_K)) {
+            let d = x.d.to_f32();
+            let (qs, scales_and_signs) = x.qs.split_at(QK_K / 4);
+            for (ib32, ys) in ys.chunks_exact_mut(32).enumerate() {
+                let aux = LittleEndian::read_u32(&scales_and_signs[4 * ib32..]);
+                let db = d * (0.5 + (aux >> 28) as f32) * 0.5;
+                for l in 0..4 {
+                    let signs = KSIGNS_IQ2XS[((aux >> (7 * l)) & 127) as usize];
+                    let grid1 = IQ3XXS_GRID[qs[8 * ib32 + 2 * l] as usize].to_le_bytes();
+                    let grid2 = IQ3XXS_GRID[qs[8 * ib32 + 2 * l + 1] as usize].to_le_bytes();
+                    for j in 0..4 {
+                        ys[8 * l + j] = db * grid1[j] as f32 * iq_sign(signs, j) as f32;
+                        ys[8 * l + j + 4] = db * grid2[j] as f32 * iq_sign(signs, j + 4) as f32;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl GgmlType for BlockIQ3S {
+    const DTYPE: GgmlDType = GgmlDType::IQ3S;
+    const BLCK_SIZE: usize = QK_K;
+    type VecDotType = BlockQ8K;
+
+    fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        Self::vec_dot_unopt(n, xs, ys)
+    }
+
+    fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        if n % QK_K != 0 {
+            crate::bail!("vec_dot_iq3_s_q8k: {n} is not divisible by {QK_K}")
+        }
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let d = x.d.to_f32() * y.d;
+            let mut bsum = 0i32;
+            for ib32 in 0..QK_K / 32 {
+                let ls = 2 * ((x.scales[ib32 / 2] >> (4 * (ib32 % 2))) & 0xf) as i32 + 1;
+                let qh = x.qh[ib32] as usize;
+                let mut sumi = 0i32;
+                for l in 0..4 {
+                    let index1 = x.qs[8 * ib32 + 2 * l] as usize | ((qh << (8 - 2 * l)) & 256);
+                    let index2 = x.qs[8 * ib32 + 2 * l + 1] as usize | ((qh << (7 - 2 * l)) & 256);
+                    let grid1 = IQ3S_GRID[index1].to_le_bytes();
+                    let grid2 = IQ3S_GRID[index2].to_le_bytes();
+                    let signs = x.signs[4 * ib32 + l];
+                    let q8 = &y.qs[32 * ib32 + 8 * l..32 * ib32 + 8 * (l + 1)];
+                    for j in 0..4 {
+                        sumi += grid1[j] as i32 * q8[j] as i32 * iq_sign(signs, j);
+                        sumi += grid2[j] as i32 * q8[j + 4] as i32 * iq_sign(signs, j + 4);
+                    }
+                }
+                bsum += sumi * ls;
+            }
+            sumf += d * bsum as f32;
+        }
+        Ok(sumf)
+    }
+
+    fn from_float(xs: &[f32], ys: &mut [Self]) -> Result<()> {
+        iq_quants::quantize_blocks(xs, ys, None, iq_quants::quantize_iq3_s)
+    }
+
+    fn from_float_imatrix(xs: &[f32], ys: &mut [Self], imatrix: &[f32]) -> Result<()> {
+        iq_quants::quantize_blocks(xs, ys, Some(imatrix), iq_quants::quantize_iq3_s)
+    }
+
+    fn to_float(xs: &[Self], ys: &mut [f32]) -> Result<()> {
+        let k = ys.len();
+        if k % QK_K != 0 {
+            crate::bail!("dequantize_row_iq3_s: {k} is not divisible by {QK_K}")
+        }
+        for (x, ys) in xs.iter().zip(ys.chunks_exact_mut(QK_K)) {
+            let d = x.d.to_f32();
+            for (ib32, ys) in ys.chunks_exact_mut(32).enumerate() {
+                let db = d * (1 + 2 * ((x.scales[ib32 / 2] >> (4 * (ib32 % 2))) & 0xf)) as f32;
+                let qh = x.qh[ib32] as usize;
+                for l in 0..4 {
+                    let index1 = x.qs[8 * ib32 + 2 * l] as usize | ((qh << (8 - 2 * l)) & 256);
+                    let index2 = x.qs[8 * ib32 + 2 * l + 1] as usize | ((qh << (7 - 2 * l)) & 256);
+                    let grid1 = IQ3S_GRID[index1].to_le_bytes();
+                    let grid2 = IQ3S_GRID[index2].to_le_bytes();
+                    let signs = x.signs[4 * ib32 + l];
+                    for j in 0..4 {
+                        ys[8 * l + j] = db * grid1[j] as f32 * iq_sign(signs, j) as f32;
+                        ys[8 * l + j + 4] = db * grid2[j] as f32 * iq_sign(signs, j + 4) as f32;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl GgmlType for BlockIQ1S {
+    const DTYPE: GgmlDType = GgmlDType::IQ1S;
+    const BLCK_SIZE: usize = QK_K;
+    type VecDotType = BlockQ8K;
+
+    fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        Self::vec_dot_unopt(n, xs, ys)
+    }
+
+    fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        if n % QK_K != 0 {
+            crate::bail!("vec_dot_iq1_s_q8k: {n} is not divisible by {QK_K}")
+        }
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let mut sumi = 0i32;
+            let mut sumi1 = 0i32;
+            for ib in 0..QK_K / 32 {
+                let qh = x.qh[ib] as usize;
+                let ls = 2 * ((qh >> 12) & 7) as i32 + 1;
+                let delta = if qh & 0x8000 != 0 { -1 } else { 1 };
+                let mut lsum = 0i32;
+                for l in 0..4 {
+                    let index = x.qs[4 * ib + l] as usize | (((qh >> (3 * l)) & 7) << 8);
+                    let grid = IQ1S_GRID[index].to_le_bytes();
+                    let q8 = &y.qs[32 * ib + 8 * l..32 * ib + 8 * (l + 1)];
+                    for j in 0..8 {
+                        lsum += q8[j] as i32 * grid[j] as i8 as i32
+                    }
+                }
+                sumi += ls * lsum;
+                sumi1 += ls * delta * (y.bsums[2 * ib] as i32 + y.bsums[2 * ib + 1] as i32);
+            }
+            sumf += x.d.to_f32() * y.d * (sumi as f32 + iq_quants::IQ1S_DELTA * sumi1 as f32);
+        }
+        Ok(sumf)
+    }
+
+    fn from_float(xs: &[f32], ys: &mut [Self]) -> Result<()> {
+        iq_quants::quantize_blocks(xs, ys, None, iq_quants::quantize_iq1_s)
+    }
+
+    fn from_float_imatrix(xs: &[f32], ys: &mut [Self], imatrix: &[f32]) -> Result<()> {
+        iq_quants::quantize_blocks(xs, ys, Some(imatrix), iq_quants::quantize_iq1_s)
+    }
+
+    fn to_float(xs: &[Self], ys: &mut [f32]) -> Result<()> {
+        let k = ys.len();
+        if k % QK_K != 0 {
+            crate::bail!("dequantize_row_iq1_s: {k} is not divisible by {QK_K}")
+        }
+        for (x, ys) in xs.iter().zip(ys.chunks_exact_mut(QK_K)) {
+            let d = x.d.to_f32();
+            for (ib, ys) in ys.chunks_exact_mut(32).enumerate() {
+                let qh = x.qh[ib] as usize;
+                let dl = d * (2 * ((qh >> 12) & 7) + 1) as f32;
+                let delta = if qh & 0x8000 != 0 {
+                    -iq_quants::IQ1S_DELTA
+                } else {
+                    iq_quants::IQ1S_DELTA
+                };
+                for l in 0..4 {
+                    let index = x.qs[4 * ib + l] as usize | (((qh >> (3 * l)) & 7) << 8);
+                    let grid = IQ1S_GRID[index].to_le_bytes();
+                    for j in 0..8 {
+                        ys[8 * l + j] = dl * (grid[j] as i8 as f32 + delta)
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl BlockIQ1M {
+    fn scales_u16(&self) -> [u16; 4] {
+        let mut sc = [0u16; 4];
+        LittleEndian::read_u16_into(&self.scales, &mut sc);
+        sc
+    }
+
+    fn d(sc: &[u16; 4]) -> f16 {
+        let d =
+            (sc[0] >> 12) | ((sc[1] >> 8) & 0x00f0) | ((sc[2] >> 4) & 0x0f00) | (sc[3] & 0xf000);
+        f16::from_bits(d)
+    }
+
+    /// The grid index and negative shift flag for the `k`-th group of 8 in sub-block `ib16`.
+    fn group(&self, ib16: usize, k: usize) -> (usize, bool) {
+        let qh = self.qh[ib16] as usize;
+        let index = self.qs[2 * ib16 + k] as usize | ((qh << (8 - 4 * k)) & 0x700);
+        (index, qh & (0x08 << (4 * k)) != 0)
+    }
+}
+
+impl GgmlType for BlockIQ1M {
+    const DTYPE: GgmlDType = GgmlDType::IQ1M;
+    const BLCK_SIZE: usize = QK_K;
+    type VecDotType = BlockQ8K;
+
+    fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        Self::vec_dot_unopt(n, xs, ys)
+    }
+
+    fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        if n % QK_K != 0 {
+            crate::bail!("vec_dot_iq1_m_q8k: {n} is not divisible by {QK_K}")
+        }
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let sc = x.scales_u16();
+            let mut sumi1 = 0i32;
+            let mut sumi2 = 0i32;
+            for ib16 in 0..QK_K / 16 {
+                let ls = 2 * ((sc[ib16 / 4] >> (3 * (ib16 % 4))) & 7) as i32 + 1;
+                for k in 0..2 {
+                    let (index, negative) = x.group(ib16, k);
+                    let grid = IQ1S_GRID[index].to_le_bytes();
+                    let q8 = &y.qs[16 * ib16 + 8 * k..16 * ib16 + 8 * (k + 1)];
+                    let mut lsum1 = 0i32;
+                    let mut lsum2 = 0i32;
+                    for j in 0..8 {
+                        lsum1 += q8[j] as i32 * grid[j] as i8 as i32;
+                        lsum2 += q8[j] as i32;
+                    }
+                    let delta = if negative { -1 } else { 1 };
+                    sumi1 += ls * lsum1;
+                    sumi2 += ls * delta * lsum2;
+                }
+            }
+            let d = BlockIQ1M::d(&sc).to_f32();
+            sumf += d * y.d * (sumi1 as f32 + iq_quants::IQ1M_DELTA * sumi2 as f32);
+        }
+        Ok(sumf)
+    }
+
+    fn from_float(xs: &[f32], ys: &mut [Self]) -> Result<()> {
+        iq_quants::quantize_blocks(xs, ys, None, iq_quants::quantize_iq1_m)
+    }
+
+    fn from_float_imatrix(xs: &[f32], ys: &mut [Self], imatrix: &[f32]) -> Result<()> {
+        iq_quants::quantize_blocks(xs, ys, Some(imatrix), iq_quants::quantize_iq1_m)
+    }
+
+    fn to_float(xs: &[Self], ys: &mut [f32]) -> Result<()> {
+        let k = ys.len();
+        if k % QK_K != 0 {
+            crate::bail!("dequantize_row_iq1_m: {k} is not divisible by {QK_K}")
+        }
+        for (x, ys) in xs.iter().zip(ys.chunks_exact_mut(QK_K)) {
+            let sc = x.scales_u16();
+            let d = BlockIQ1M::d(&sc).to_f32();
+            for (ib16, ys) in ys.chunks_exact_mut(16).enumerate() {
+                let dl = d * (2 * ((sc[ib16 / 4] >> (3 * (ib16 % 4))) & 7) + 1) as f32;
+                for k in 0..2 {
+                    let (index, negative) = x.group(ib16, k);
+                    let grid = IQ1S_GRID[index].to_le_bytes();
+                    let delta = if negative {
+                        -iq_quants::IQ1M_DELTA
+                    } else {
+                        iq_quants::IQ1M_DELTA
+                    };
+                    for j in 0..8 {
+                        ys[8 * k + j] = dl * (grid[j] as i8 as f32 + delta)
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl GgmlType for BlockIQ4NL {
+    const DTYPE: GgmlDType = GgmlDType::IQ4NL;
+    const BLCK_SIZE: usize = QK4_NL;
+    type VecDotType = BlockQ8_0;
+
+    fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        Self::vec_dot_unopt(n, xs, ys)
+    }
+
+    fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        if n % QK4_NL != 0 {
+            crate::bail!("vec_dot_iq4_nl_q8_0: {n} is not divisible by {QK4_NL}")
+        }
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let mut sumi = 0i32;
+            for j in 0..QK4_NL / 2 {
+                let q = x.qs[j];
+                sumi += KVALUES_IQ4NL[(q & 0xf) as usize] as i32 * y.qs[j] as i32;
+                sumi += KVALUES_IQ4NL[(q >> 4) as usize] as i32 * y.qs[j + QK4_NL / 2] as i32;
+            }
+            sumf += x.d.to_f32() * y.d.to_f32() * sumi as f32;
+        }
+        Ok(sumf)
+    }
+
+    fn from_float(xs: &[f32], ys: &mut [Self]) -> Result<()> {
+        iq_quants::quantize_blocks(xs, ys, None, iq_quants::quantize_iq4_nl)
+    }
+
+    fn from_float_imatrix(xs: &[f32], ys: &mut [Self], imatrix: &[f32]) -> Result<()> {
+        iq_quants::quantize_blocks(xs, ys, Some(imatrix), iq_quants::quantize_iq4_nl)
+    }
+
+    fn to_float(xs: &[Self], ys: &mut [f32]) -> Result<()> {
+        let k = ys.len();
+        if k % QK4_NL != 0 {
+            crate::bail!("dequantize_row_iq4_nl: {k} is not divisible by {QK4_NL}")
+        }
+        for (x, ys) in xs.iter().zip(ys.chunks_exact_mut(QK4_NL)) {
+            let d = x.d.to_f32();
+            for (j, &q) in x.qs.iter().enumerate() {
+                ys[j] = d * KVALUES_IQ4NL[(q & 0xf) as usize] as f32;
+                ys[j + QK4_NL / 2] = d * KVALUES_IQ4NL[(q >> 4) as usize] as f32;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl BlockIQ4XS {
+    fn scale(&self, ib: usize) -> i32 {
+        let ls_l = (self.scales_l[ib / 2] >> (4 * (ib % 2))) & 0xf;
+        let ls_h = (self.scales_h >> (2 * ib)) & 3;
+        (ls_l as i32 | (ls_h as i32) << 4) - 32
+    }
+}
+
+impl GgmlType for BlockIQ4XS {
+    const DTYPE: GgmlDType = GgmlDType::IQ4XS;
+    const BLCK_SIZE: usize = QK_K;
+    type VecDotType = BlockQ8K;
+
+    fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        Self::vec_dot_unopt(n, xs, ys)
+    }
+
+    fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        if n % QK_K != 0 {
+            crate::bail!("vec_dot_iq4_xs_q8k: {n} is not divisible by {QK_K}")
+        }
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let mut bsum = 0i32;
+            for ib in 0..QK_K / 32 {
+                let qs = &x.qs[16 * ib..16 * (ib + 1)];
+                let q8 = &y.qs[32 * ib..32 * (ib + 1)];
+                let mut sumi = 0i32;
+                for j in 0..16 {
+                    sumi += KVALUES_IQ4NL[(qs[j] & 0xf) as usize] as i32 * q8[j] as i32;
+                    sumi += KVALUES_IQ4NL[(qs[j] >> 4) as usize] as i32 * q8[j + 16] as i32;
+                }
+                bsum += sumi * x.scale(ib);
+            }
+            sumf += x.d.to_f32() * y.d * bsum as f32;
+        }
+        Ok(sumf)
+    }
+
+    fn from_float(xs: &[f32], ys: &mut [Self]) -> Result<()> {
+        iq_quants::quantize_blocks(xs, ys, None, iq_quants::quantize_iq4_xs)
+    }
+
+    fn from_float_imatrix(xs: &[f32], ys: &mut [Self], imatrix: &[f32]) -> Result<()> {
+        iq_quants::quantize_blocks(xs, ys, Some(imatrix), iq_quants::quantize_iq4_xs)
+    }
+
+    fn to_float(xs: &[Self], ys: &mut [f32]) -> Result<()> {
+        let k = ys.len();
+        if k % QK_K != 0 {
+            crate::bail!("dequantize_row_iq4_xs: {k} is not divisible by {QK_K}")
+        }
+        for (x, ys) in xs.iter().zip(ys.chunks_exact_mut(QK_K)) {
+            let d = x.d.to_f32();
+            for (ib, ys) in ys.chunks_exact_mut(32).enumerate() {
+                let dl = d * x.scale(ib) as f32;
+                for (j, &q) in x.qs[16 * ib..16 * (ib + 1)].iter().enumerate() {
+                    ys[j] = dl * KVALUES_IQ4NL[(q & 0xf) as usize] as f32;
+                    ys[j + 16] = dl * KVALUES_IQ4NL[(q >> 4) as usize] as f32;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 // https://github.com/ggerganov/llama.cpp/blob/b5ffb2849d23afe73647f68eec7b68187af09be6/ggml.c#L10605
 pub fn matmul<T: GgmlType>(
     mkn: (usize, usize, usize),
     lhs: &[f32],
     rhs_t: &[T],
     dst: &mut [f32],
+) -> Result<()> {
+    // Runs on the thread pool of the current cpu device, if any.
+    crate::cpu::thread_pool::install(|| matmul_(mkn, lhs, rhs_t, dst))
+}
+
+fn matmul_<T: GgmlType>(
+    mkn: (usize, usize, usize),
+    lhs: &[f32],
+    rhs_t: &[T],
+    dst: &mut [f32],
 ) -> Result<()> {
     let (m, k, n) = mkn;
     if m * k != lhs.len() {
@@ -1854,14 +2688,18 @@ pub fn matmul<T: GgmlType>(
     let k_in_rhs_blocks = (k + T::VecDotType::BLCK_SIZE - 1) / T::VecDotType::BLCK_SIZE;
     // TODO: Do not make this copy if the DotType is f32.
     // TODO: Pre-allocate this.
+    // The activations are only quantized once and then shared by all the weight columns.
     let mut lhs_b = vec![T::VecDotType::zeros(); m * k_in_lhs_blocks];
-    for row_idx in 0..m {
-        let lhs_b = &mut lhs_b[row_idx * k_in_lhs_blocks..(row_idx + 1) * k_in_lhs_blocks];
-        let lhs = &lhs[row_idx * k..(row_idx + 1) * k];
-        T::VecDotType::from_float(lhs, lhs_b)?
-    }
+    lhs_b
+        .par_chunks_mut(k_in_lhs_blocks)
+        .zip(lhs.par_chunks(k))
+        .try_for_each(|(lhs_b, lhs)| T::VecDotType::from_float(lhs, lhs_b))?;
     let lhs_b = lhs_b.as_slice();
 
+    if m > 1 {
+        return matmul_tiled::<T>(mkn, lhs_b, k_in_lhs_blocks, rhs_t, k_in_rhs_blocks, dst);
+    }
+
     for row_idx in 0..m {
         let lhs_row = &lhs_b[row_idx * k_in_lhs_blocks..(row_idx + 1) * k_in_lhs_blocks];
         let dst_row = &mut dst[row_idx * n..(row_idx + 1) * n];
@@ -1882,6 +2720,55 @@ pub fn matmul<T: GgmlType>(
     Ok(())
 }
 
+/// Number of activation rows passed together to `GgmlType::vec_dot_rows`.
+const MATMUL_ROW_TILE: usize = 4;
+/// Number of weight columns processed by each task of the tiled mat-mul. The weights for a tile
+/// stay in cache while iterating over all the activation rows.
+const MATMUL_COL_TILE: usize = 16;
+
+/// Mat-mul for multiple activation rows. The work is split in tiles of weight columns that are
+/// processed in parallel, within a tile each block of weights is combined with multiple rows
+/// at once.
+fn matmul_tiled<T: GgmlType>(
+    (m, k, n): (usize, usize, usize),
+    lhs_b: &[T::VecDotType],
+    k_in_lhs_blocks: usize,
+    rhs_t: &[T],
+    k_in_rhs_blocks: usize,
+    dst: &mut [f32],
+) -> Result<()> {
+    // The results are computed column by column and transposed at the end.
+    let mut dst_t = vec![0f32; m * n];
+    dst_t
+        .par_chunks_mut(m * MATMUL_COL_TILE)
+        .enumerate()
+        .try_for_each(|(tile_idx, dst_t)| {
+            let col_offset = tile_idx * MATMUL_COL_TILE;
+            let mut rows: [&[T::VecDotType]; MATMUL_ROW_TILE] = [&[]; MATMUL_ROW_TILE];
+            for row_offset in (0..m).step_by(MATMUL_ROW_TILE) {
+                let nrows = usize::min(MATMUL_ROW_TILE, m - row_offset);
+                for (i, row) in rows[..nrows].iter_mut().enumerate() {
+                    let row_idx = row_offset + i;
+                    *row = &lhs_b[row_idx * k_in_lhs_blocks..(row_idx + 1) * k_in_lhs_blocks];
+                }
+                for (i, dst_col) in dst_t.chunks_exact_mut(m).enumerate() {
+                    let col_idx = col_offset + i;
+                    let rhs_col =
+                        &rhs_t[col_idx * k_in_rhs_blocks..(col_idx + 1) * k_in_rhs_blocks];
+                    let dst = &mut dst_col[row_offset..row_offset + nrows];
+                    T::vec_dot_rows(k, rhs_col, &rows[..nrows], dst)?
+                }
+            }
+            Ok::<_, crate::Error>(())
+        })?;
+    for (col_idx, dst_col) in dst_t.chunks_exact(m).enumerate() {
+        for (row_idx, &v) in dst_col.iter().enumerate() {
+            dst[row_idx * n + col_idx] = v
+        }
+    }
+    Ok(())
+}
+
 impl GgmlType for f32 {
     const DTYPE: GgmlDType = GgmlDType::F32;
     const BLCK_SIZE: usize = 1;
@@ -113,6 +113,60 @@ impl QMetalStorage {
                 use crate::quantized::k_quants::GgmlType;
                 crate::quantized::BlockQ8K::to_float(&vec, &mut out)?;
             }
+            GgmlDType::IQ2XXS => {
+                let vec: Vec<crate::quantized::BlockIQ2XXS> =
+                    read_to_vec(&buffer, elem_count / self.dtype.block_size());
+                use crate::quantized::k_quants::GgmlType;
+                crate::quantized::BlockIQ2XXS::to_float(&vec, &mut out)?;
+            }
+            GgmlDType::IQ2XS => {
+                let vec: Vec<crate::quantized::BlockIQ2XS> =
+                    read_to_vec(&buffer, elem_count / self.dtype.block_size());
+                use crate::quantized::k_quants::GgmlType;
+                crate::quantized::BlockIQ2XS::to_float(&vec, &mut out)?;
+            }
+            GgmlDType::IQ2S => {
+                let vec: Vec<crate::quantized::BlockIQ2S> =
+                    read_to_vec(&buffer, elem_count / self.dtype.block_size());
+                use crate::quantized::k_quants::GgmlType;
+                crate::quantized::BlockIQ2S::to_float(&vec, &mut out)?;
+            }
+            GgmlDType::IQ3XXS => {
+                let vec: Vec<crate::quantized::BlockIQ3XXS> =
+                    read_to_vec(&buffer, elem_count / self.dtype.block_size());
+                use crate::quantized::k_quants::GgmlType;
+                crate::quantized::BlockIQ3XXS::to_float(&vec, &mut out)?;
+            }
+            GgmlDType::IQ3S => {
+                let vec: Vec<crate::quantized::BlockIQ3S> =
+                    read_to_vec(&buffer, elem_count / self.dtype.block_size());
+                use crate::quantized::k_quants::GgmlType;
+                crate::quantized::BlockIQ3S::to_float(&vec, &mut out)?;
+            }
+            GgmlDType::IQ1S => {
+                let vec: Vec<crate::quantized::BlockIQ1S> =
+                    read_to_vec(&buffer, elem_count / self.dtype.block_size());
+                use crate::quantized::k_quants::GgmlType;
+                crate::quantized::BlockIQ1S::to_float(&vec, &mut out)?;
+            }
+            GgmlDType::IQ1M => {
+                let vec: Vec<crate::quantized::BlockIQ1M> =
+                    read_to_vec(&buffer, elem_count / self.dtype.block_size());
+                use crate::quantized::k_quants::GgmlType;
+                crate::quantized::BlockIQ1M::to_float(&vec, &mut out)?;
+            }
+            GgmlDType::IQ4NL => {
+                let vec: Vec<crate::quantized::BlockIQ4NL> =
+                    read_to_vec(&buffer, elem_count / self.dtype.block_size());
+                use crate::quantized::k_quants::GgmlType;
+                crate::quantized::BlockIQ4NL::to_float(&vec, &mut out)?;
+            }
+            GgmlDType::IQ4XS => {
+                let vec: Vec<crate::quantized::BlockIQ4XS> =
+                    read_to_vec(&buffer, elem_count / self.dtype.block_size());
+                use crate::quantized::k_quants::GgmlType;
+                crate::quantized::BlockIQ4XS::to_float(&vec, &mut out)?;
+            }
         }
 
         let buffer = self.device.new_buffer_with_data(&out)?;
@@ -130,6 +184,18 @@ impl QMetalStorage {
         self.buffer = buffer;
         Ok(())
     }
+
+    pub fn quantize_imatrix(&mut self, src: &MetalStorage, imatrix: &[f32]) -> Result<()> {
+        // Quantization only happens on CPU for now.
+        let src = src.to_cpu::<f32>()?;
+        let elem_count = src.len();
+        let src = crate::Storage::Cpu(crate::CpuStorage::F32(src));
+        let mut qcpu_storage = crate::Device::Cpu.qzeros(elem_count, self.dtype)?;
+        qcpu_storage.quantize_imatrix(&src, imatrix)?;
+        let buffer = self.device.new_buffer_with_data(&qcpu_storage.data()?)?;
+        self.buffer = buffer;
+        Ok(())
+    }
 }
 
 pub fn load_quantized_metal<T: super::GgmlType + Send + Sync + 'static>(
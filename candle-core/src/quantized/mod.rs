@@ -3,11 +3,19 @@ use crate::{backend::BackendStorage, DType};
 use crate::{CpuStorage, Device, Result, Shape, Storage, Tensor};
 use k_quants::*;
 use std::borrow::Cow;
+use std::sync::Arc;
 
 #[cfg(target_feature = "avx")]
 pub mod avx;
+#[cfg(feature = "convert")]
+pub mod convert;
+pub mod fake_quant;
 pub mod ggml_file;
 pub mod gguf_file;
+pub mod int4;
+pub mod int_quant;
+mod iq_grids;
+mod iq_quants;
 pub mod k_quants;
 #[cfg(feature = "metal")]
 pub mod metal;
@@ -28,7 +36,7 @@ pub struct QTensor {
 impl Device {
     fn qzeros(&self, elem_count: usize, dtype: GgmlDType) -> Result<QStorage> {
         match self {
-            Device::Cpu => {
+            Device::Cpu | Device::CpuPool(_) => {
                 let storage = dtype.cpu_zeros(elem_count);
                 Ok(QStorage::Cpu(storage))
             }
@@ -89,6 +97,9 @@ impl QStorage {
             (QStorage::Cpu(storage), Storage::Cpu(src)) => {
                 storage.from_float(src.as_slice::<f32>()?)?;
             }
+            (QStorage::Cpu(storage), Storage::CpuMmap(src)) => {
+                storage.from_float(src.storage_ref().as_slice::<f32>()?)?;
+            }
             #[cfg(feature = "metal")]
             (QStorage::Metal(storage), Storage::Metal(src)) => storage.quantize(src)?,
             _ => crate::bail!("Invalid dequantize storage locations do not match"),
@@ -96,6 +107,23 @@ impl QStorage {
         Ok(())
     }
 
+    fn quantize_imatrix(&mut self, src: &Storage, imatrix: &[f32]) -> Result<()> {
+        match (self, src) {
+            (QStorage::Cpu(storage), Storage::Cpu(src)) => {
+                storage.from_float_imatrix(src.as_slice::<f32>()?, imatrix)?;
+            }
+            (QStorage::Cpu(storage), Storage::CpuMmap(src)) => {
+                storage.from_float_imatrix(src.storage_ref().as_slice::<f32>()?, imatrix)?;
+            }
+            #[cfg(feature = "metal")]
+            (QStorage::Metal(storage), Storage::Metal(src)) => {
+                storage.quantize_imatrix(src, imatrix)?
+            }
+            _ => crate::bail!("Invalid quantize storage locations do not match"),
+        }
+        Ok(())
+    }
+
     fn dequantize(&self, elem_count: usize) -> Result<Storage> {
         match self {
             QStorage::Cpu(storage) => Ok(Storage::Cpu(storage.dequantize(elem_count)?)),
@@ -136,6 +164,15 @@ pub enum GgmlDType {
     Q5K,
     Q6K,
     Q8K,
+    IQ2XXS,
+    IQ2XS,
+    IQ2S,
+    IQ3XXS,
+    IQ3S,
+    IQ1S,
+    IQ1M,
+    IQ4NL,
+    IQ4XS,
 }
 
 impl GgmlDType {
@@ -155,6 +192,15 @@ impl GgmlDType {
             13 => Self::Q5K,
             14 => Self::Q6K,
             15 => Self::Q8K,
+            16 => Self::IQ2XXS,
+            17 => Self::IQ2XS,
+            18 => Self::IQ3XXS,
+            19 => Self::IQ1S,
+            20 => Self::IQ4NL,
+            21 => Self::IQ3S,
+            22 => Self::IQ2S,
+            23 => Self::IQ4XS,
+            29 => Self::IQ1M,
             _ => crate::bail!("unknown dtype for tensor {u}"),
         };
         Ok(dtype)
@@ -176,6 +222,15 @@ impl GgmlDType {
             Self::Q5K => 13,
             Self::Q6K => 14,
             Self::Q8K => 15,
+            Self::IQ2XXS => 16,
+            Self::IQ2XS => 17,
+            Self::IQ3XXS => 18,
+            Self::IQ1S => 19,
+            Self::IQ4NL => 20,
+            Self::IQ3S => 21,
+            Self::IQ2S => 22,
+            Self::IQ4XS => 23,
+            Self::IQ1M => 29,
         }
     }
 
@@ -196,6 +251,65 @@ impl GgmlDType {
             Self::Q5K => Box::new(vec![BlockQ5K::zeros(); elem_count / BlockQ5K::BLCK_SIZE]),
             Self::Q6K => Box::new(vec![BlockQ6K::zeros(); elem_count / BlockQ6K::BLCK_SIZE]),
             Self::Q8K => Box::new(vec![BlockQ8K::zeros(); elem_count / BlockQ8K::BLCK_SIZE]),
+            Self::IQ2XXS => Box::new(vec![
+                BlockIQ2XXS::zeros();
+                elem_count / BlockIQ2XXS::BLCK_SIZE
+            ]),
+            Self::IQ2XS => Box::new(vec![
+                BlockIQ2XS::zeros();
+                elem_count / BlockIQ2XS::BLCK_SIZE
+            ]),
+            Self::IQ2S => Box::new(vec![BlockIQ2S::zeros(); elem_count / BlockIQ2S::BLCK_SIZE]),
+            Self::IQ3XXS => Box::new(vec![
+                BlockIQ3XXS::zeros();
+                elem_count / BlockIQ3XXS::BLCK_SIZE
+            ]),
+            Self::IQ3S => Box::new(vec![BlockIQ3S::zeros(); elem_count / BlockIQ3S::BLCK_SIZE]),
+            Self::IQ1S => Box::new(vec![BlockIQ1S::zeros(); elem_count / BlockIQ1S::BLCK_SIZE]),
+            Self::IQ1M => Box::new(vec![BlockIQ1M::zeros(); elem_count / BlockIQ1M::BLCK_SIZE]),
+            Self::IQ4NL => Box::new(vec![
+                BlockIQ4NL::zeros();
+                elem_count / BlockIQ4NL::BLCK_SIZE
+            ]),
+            Self::IQ4XS => Box::new(vec![
+                BlockIQ4XS::zeros();
+                elem_count / BlockIQ4XS::BLCK_SIZE
+            ]),
+        }
+    }
+
+    /// Wraps the bytes `mmap[offset..offset + size_in_bytes]` without copying them, `None` is
+    /// returned when the data is not suitably aligned for the block type.
+    pub(crate) fn cpu_mmaped(
+        &self,
+        mmap: &Arc<memmap2::Mmap>,
+        offset: usize,
+        size_in_bytes: usize,
+    ) -> Option<Box<dyn QuantizedType>> {
+        match self {
+            Self::F32 => MmapedBlocks::<f32>::boxed(mmap, offset, size_in_bytes),
+            Self::F16 => MmapedBlocks::<f16>::boxed(mmap, offset, size_in_bytes),
+            Self::Q4_0 => MmapedBlocks::<BlockQ4_0>::boxed(mmap, offset, size_in_bytes),
+            Self::Q4_1 => MmapedBlocks::<BlockQ4_1>::boxed(mmap, offset, size_in_bytes),
+            Self::Q5_0 => MmapedBlocks::<BlockQ5_0>::boxed(mmap, offset, size_in_bytes),
+            Self::Q5_1 => MmapedBlocks::<BlockQ5_1>::boxed(mmap, offset, size_in_bytes),
+            Self::Q8_0 => MmapedBlocks::<BlockQ8_0>::boxed(mmap, offset, size_in_bytes),
+            Self::Q8_1 => MmapedBlocks::<BlockQ8_1>::boxed(mmap, offset, size_in_bytes),
+            Self::Q2K => MmapedBlocks::<BlockQ2K>::boxed(mmap, offset, size_in_bytes),
+            Self::Q3K => MmapedBlocks::<BlockQ3K>::boxed(mmap, offset, size_in_bytes),
+            Self::Q4K => MmapedBlocks::<BlockQ4K>::boxed(mmap, offset, size_in_bytes),
+            Self::Q5K => MmapedBlocks::<BlockQ5K>::boxed(mmap, offset, size_in_bytes),
+            Self::Q6K => MmapedBlocks::<BlockQ6K>::boxed(mmap, offset, size_in_bytes),
+            Self::Q8K => MmapedBlocks::<BlockQ8K>::boxed(mmap, offset, size_in_bytes),
+            Self::IQ2XXS => MmapedBlocks::<BlockIQ2XXS>::boxed(mmap, offset, size_in_bytes),
+            Self::IQ2XS => MmapedBlocks::<BlockIQ2XS>::boxed(mmap, offset, size_in_bytes),
+            Self::IQ2S => MmapedBlocks::<BlockIQ2S>::boxed(mmap, offset, size_in_bytes),
+            Self::IQ3XXS => MmapedBlocks::<BlockIQ3XXS>::boxed(mmap, offset, size_in_bytes),
+            Self::IQ3S => MmapedBlocks::<BlockIQ3S>::boxed(mmap, offset, size_in_bytes),
+            Self::IQ1S => MmapedBlocks::<BlockIQ1S>::boxed(mmap, offset, size_in_bytes),
+            Self::IQ1M => MmapedBlocks::<BlockIQ1M>::boxed(mmap, offset, size_in_bytes),
+            Self::IQ4NL => MmapedBlocks::<BlockIQ4NL>::boxed(mmap, offset, size_in_bytes),
+            Self::IQ4XS => MmapedBlocks::<BlockIQ4XS>::boxed(mmap, offset, size_in_bytes),
         }
     }
     /// The type size for blocks in bytes.
@@ -217,6 +331,15 @@ impl GgmlDType {
             Self::Q5K => std::mem::size_of::<BlockQ5K>(),
             Self::Q6K => std::mem::size_of::<BlockQ6K>(),
             Self::Q8K => std::mem::size_of::<BlockQ8K>(),
+            Self::IQ2XXS => std::mem::size_of::<BlockIQ2XXS>(),
+            Self::IQ2XS => std::mem::size_of::<BlockIQ2XS>(),
+            Self::IQ2S => std::mem::size_of::<BlockIQ2S>(),
+            Self::IQ3XXS => std::mem::size_of::<BlockIQ3XXS>(),
+            Self::IQ3S => std::mem::size_of::<BlockIQ3S>(),
+            Self::IQ1S => std::mem::size_of::<BlockIQ1S>(),
+            Self::IQ1M => std::mem::size_of::<BlockIQ1M>(),
+            Self::IQ4NL => std::mem::size_of::<BlockIQ4NL>(),
+            Self::IQ4XS => std::mem::size_of::<BlockIQ4XS>(),
         }
     }
 
@@ -231,7 +354,21 @@ impl GgmlDType {
             Self::Q5_1 => k_quants::QK5_1,
             Self::Q8_0 => k_quants::QK8_0,
             Self::Q8_1 => k_quants::QK8_1,
-            Self::Q2K | Self::Q3K | Self::Q4K | Self::Q5K | Self::Q6K | Self::Q8K => k_quants::QK_K,
+            Self::IQ4NL => k_quants::QK4_NL,
+            Self::Q2K
+            | Self::Q3K
+            | Self::Q4K
+            | Self::Q5K
+            | Self::Q6K
+            | Self::Q8K
+            | Self::IQ2XXS
+            | Self::IQ2XS
+            | Self::IQ2S
+            | Self::IQ3XXS
+            | Self::IQ3S
+            | Self::IQ1S
+            | Self::IQ1M
+            | Self::IQ4XS => k_quants::QK_K,
         }
     }
 }
@@ -246,6 +383,8 @@ pub trait QuantizedType: Send + Sync {
     fn block_size(&self) -> usize;
     #[allow(clippy::wrong_self_convention)]
     fn from_float(&mut self, xs: &[f32]) -> Result<()>;
+    #[allow(clippy::wrong_self_convention)]
+    fn from_float_imatrix(&mut self, xs: &[f32], imatrix: &[f32]) -> Result<()>;
     fn size(&self) -> usize;
 }
 
@@ -262,6 +401,10 @@ impl<T: k_quants::GgmlType + Send + Sync> QuantizedType for Vec<T> {
         T::from_float(xs, self)
     }
 
+    fn from_float_imatrix(&mut self, xs: &[f32], imatrix: &[f32]) -> Result<()> {
+        T::from_float_imatrix(xs, self, imatrix)
+    }
+
     fn dtype(&self) -> GgmlDType {
         T::DTYPE
     }
@@ -285,6 +428,81 @@ impl<T: k_quants::GgmlType + Send + Sync> QuantizedType for Vec<T> {
     }
 }
 
+/// Blocks that live in a read-only memory mapped file, e.g. the tensor data of a GGUF file.
+struct MmapedBlocks<T> {
+    mmap: Arc<memmap2::Mmap>,
+    offset: usize,
+    len: usize,
+    _phantom: std::marker::PhantomData<T>,
+}
+
+impl<T: k_quants::GgmlType + Send + Sync + 'static> MmapedBlocks<T> {
+    fn boxed(
+        mmap: &Arc<memmap2::Mmap>,
+        offset: usize,
+        size_in_bytes: usize,
+    ) -> Option<Box<dyn QuantizedType>> {
+        let ptr = mmap.get(offset..offset + size_in_bytes)?.as_ptr();
+        if ptr as usize % std::mem::align_of::<T>() != 0 {
+            return None;
+        }
+        let blocks = Self {
+            mmap: mmap.clone(),
+            offset,
+            len: size_in_bytes / std::mem::size_of::<T>(),
+            _phantom: std::marker::PhantomData,
+        };
+        Some(Box::new(blocks))
+    }
+
+    fn as_slice(&self) -> &[T] {
+        let ptr = self.mmap[self.offset..].as_ptr() as *const T;
+        // Safety: the bounds and alignment have been checked on creation, the ggml block types
+        // are plain old data so any bit pattern is valid.
+        unsafe { std::slice::from_raw_parts(ptr, self.len) }
+    }
+}
+
+impl<T: k_quants::GgmlType + Send + Sync + 'static> QuantizedType for MmapedBlocks<T> {
+    fn matmul_t(&self, mkn: (usize, usize, usize), lhs: &[f32], dst: &mut [f32]) -> Result<()> {
+        k_quants::matmul(mkn, lhs, self.as_slice(), dst)
+    }
+
+    fn size(&self) -> usize {
+        self.len * core::mem::size_of::<T>()
+    }
+
+    fn from_float(&mut self, _xs: &[f32]) -> Result<()> {
+        crate::bail!("cannot quantize into a memory mapped tensor")
+    }
+
+    fn from_float_imatrix(&mut self, _xs: &[f32], _imatrix: &[f32]) -> Result<()> {
+        crate::bail!("cannot quantize into a memory mapped tensor")
+    }
+
+    fn dtype(&self) -> GgmlDType {
+        T::DTYPE
+    }
+
+    fn block_size(&self) -> usize {
+        T::BLCK_SIZE
+    }
+
+    fn dequantize(&self, elem_count: usize) -> Result<CpuStorage> {
+        let mut ys = vec![0.0f32; elem_count];
+        T::to_float(self.as_slice(), &mut ys)?;
+        Ok(CpuStorage::F32(ys))
+    }
+
+    fn storage_size_in_bytes(&self) -> usize {
+        self.len * std::mem::size_of::<T>()
+    }
+
+    fn as_ptr(&self) -> *const u8 {
+        self.as_slice().as_ptr() as *const u8
+    }
+}
+
 impl std::fmt::Debug for QTensor {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "QTensor[{:?}; {:?}]", self.shape, self.dtype())
@@ -332,6 +550,33 @@ impl QTensor {
         })
     }
 
+    /// Quantizes `src` using an importance matrix, i.e. one weight per column of `src` that
+    /// measures how much each column contributes to the model outputs. These weights are
+    /// typically computed as the mean of the squared activations on some calibration data, the
+    /// quantization error is then weighted accordingly.
+    ///
+    /// The importance matrix is used by the i-quants, other types ignore it.
+    pub fn quantize_with_imatrix(src: &Tensor, imatrix: &[f32], dtype: GgmlDType) -> Result<Self> {
+        let shape = src.shape();
+        let block_size = dtype.block_size();
+        check_shape(shape, block_size)?;
+        let n_per_row = shape.dims()[shape.rank() - 1];
+        if imatrix.len() != n_per_row {
+            crate::bail!(
+                "imatrix has {} elements but the last dim of {shape:?} is {n_per_row}",
+                imatrix.len()
+            )
+        }
+        let src = src.to_dtype(crate::DType::F32)?.flatten_all()?;
+        let elem_count = shape.elem_count();
+        let mut storage = src.device().qzeros(elem_count, dtype)?;
+        storage.quantize_imatrix(&src.storage(), imatrix)?;
+        Ok(Self {
+            storage,
+            shape: shape.clone(),
+        })
+    }
+
     pub fn dtype(&self) -> GgmlDType {
         self.storage.dtype()
     }
@@ -348,8 +593,20 @@ impl QTensor {
         let storage = self.storage.dequantize(self.shape.elem_count())?;
         let none = crate::op::BackpropOp::none();
         let is_variable = false;
-        crate::tensor::from_storage(storage, self.shape.clone(), none, is_variable)
-            .to_device(device)
+        // The tensor is labeled with the device holding the dequantized storage, keeping the
+        // target cpu device so that its thread pool is used.
+        let storage_device = match storage.device() {
+            Device::Cpu if device.is_cpu() => device.clone(),
+            storage_device => storage_device,
+        };
+        crate::tensor::from_storage(
+            storage,
+            &storage_device,
+            self.shape.clone(),
+            none,
+            is_variable,
+        )
+        .to_device(device)
     }
 
     pub fn storage_size_in_bytes(&self) -> usize {
@@ -365,6 +622,8 @@ impl QTensor {
 pub enum QMatMul {
     QTensor(std::sync::Arc<QTensor>),
     Tensor(Tensor),
+    /// Group-wise 4-bit weights from a GPTQ or AWQ checkpoint.
+    Int4(std::sync::Arc<int4::Int4Weight>),
 }
 
 thread_local! {
@@ -396,6 +655,15 @@ impl QMatMul {
     pub fn from_qtensor(qtensor: QTensor) -> Result<Self> {
         Self::from_arc(std::sync::Arc::new(qtensor))
     }
+
+    pub fn from_int4(weight: int4::Int4Weight) -> Result<Self> {
+        let t = if DEQUANTIZE_ALL.with(|b| *b) {
+            Self::Tensor(weight.dequantize(&Device::Cpu)?)
+        } else {
+            Self::Int4(std::sync::Arc::new(weight))
+        };
+        Ok(t)
+    }
 }
 
 impl crate::CustomOp1 for QTensor {
@@ -478,7 +746,7 @@ impl crate::CustomOp1 for QTensor {
             device.device(),
             &command_buffer,
             device.kernels(),
-            dtype.into(),
+            dtype.try_into()?,
             (b, m, n, k),
             storage.buffer(),
             layout.start_offset() * storage.dtype().size_in_bytes(),
@@ -492,9 +760,11 @@ impl crate::CustomOp1 for QTensor {
 }
 
 #[cfg(feature = "metal")]
-impl From<GgmlDType> for candle_metal_kernels::GgmlDType {
-    fn from(value: GgmlDType) -> Self {
-        match value {
+impl TryFrom<GgmlDType> for candle_metal_kernels::GgmlDType {
+    type Error = crate::Error;
+
+    fn try_from(value: GgmlDType) -> Result<Self> {
+        let dtype = match value {
             GgmlDType::Q4_0 => candle_metal_kernels::GgmlDType::Q4_0,
             GgmlDType::Q4_1 => candle_metal_kernels::GgmlDType::Q4_1,
             GgmlDType::Q5_0 => candle_metal_kernels::GgmlDType::Q5_0,
@@ -509,7 +779,19 @@ impl From<GgmlDType> for candle_metal_kernels::GgmlDType {
             GgmlDType::Q8K => candle_metal_kernels::GgmlDType::Q8K,
             GgmlDType::F16 => candle_metal_kernels::GgmlDType::F16,
             GgmlDType::F32 => candle_metal_kernels::GgmlDType::F32,
-        }
+            GgmlDType::IQ2XXS
+            | GgmlDType::IQ2XS
+            | GgmlDType::IQ2S
+            | GgmlDType::IQ3XXS
+            | GgmlDType::IQ3S
+            | GgmlDType::IQ1S
+            | GgmlDType::IQ1M
+            | GgmlDType::IQ4NL
+            | GgmlDType::IQ4XS => {
+                crate::bail!("quantized matmul is not supported for {value:?} on metal")
+            }
+        };
+        Ok(dtype)
     }
 }
 
@@ -517,6 +799,11 @@ impl crate::Module for QMatMul {
     fn forward(&self, xs: &Tensor) -> Result<Tensor> {
         match self {
             Self::QTensor(t) => xs.apply_op1_no_bwd(t.as_ref()),
+            Self::Int4(w) => xs
+                .to_dtype(crate::DType::F32)?
+                .contiguous()?
+                .apply_op1_no_bwd(w.as_ref())?
+                .to_dtype(xs.dtype()),
             Self::Tensor(w) => {
                 let w = match *xs.dims() {
                     [b1, b2, _, _] => w.broadcast_left((b1, b2))?.t()?,
@@ -324,3 +324,73 @@ pub(super) fn make_q3_quants(x: &[f32], nmax: i32, do_rmse: bool) -> f32 {
     }
     1.0 / iscale
 }
+
+/// Weighted quantization of positive values to `0..=nmax`, returns the scale. This is a port of
+/// `make_qp_quants` from llama.cpp ggml-quants.c.
+pub(super) fn make_qp_quants(nmax: i32, x: &[f32], l: &mut [u8], quant_weights: &[f32]) -> f32 {
+    let max = x.iter().fold(0f32, |m, &v| m.max(v));
+    if max == 0. {
+        l.fill(0);
+        return 0.;
+    }
+    let mut iscale = nmax as f32 / max;
+    for (l, &x) in l.iter_mut().zip(x.iter()) {
+        *l = nearest_int(iscale * x) as u8;
+    }
+    let scale = 1. / iscale;
+    let mut best_mse = 0f32;
+    for ((&x, &l), &w) in x.iter().zip(l.iter()).zip(quant_weights.iter()) {
+        let diff = x - scale * l as f32;
+        best_mse += w * diff * diff;
+    }
+    for is in -4..=4 {
+        if is == 0 {
+            continue;
+        }
+        let iscale_is = (0.1 * is as f32 + nmax as f32) / max;
+        let scale_is = 1. / iscale_is;
+        let mut mse = 0f32;
+        for (&x, &w) in x.iter().zip(quant_weights.iter()) {
+            let l = nearest_int(iscale_is * x).min(nmax);
+            let diff = x - scale_is * l as f32;
+            mse += w * diff * diff;
+        }
+        if mse < best_mse {
+            best_mse = mse;
+            iscale = iscale_is;
+        }
+    }
+    let mut sumlx = 0f32;
+    let mut suml2 = 0f32;
+    for ((&x, l), &w) in x.iter().zip(l.iter_mut()).zip(quant_weights.iter()) {
+        let li = nearest_int(iscale * x).min(nmax);
+        *l = li as u8;
+        sumlx += w * x * li as f32;
+        suml2 += w * (li * li) as f32;
+    }
+    for _itry in 0..5 {
+        let mut n_changed = 0;
+        for ((&x, l), &w) in x.iter().zip(l.iter_mut()).zip(quant_weights.iter()) {
+            let li = *l as f32;
+            let mut slx = sumlx - w * x * li;
+            let mut sl2 = suml2 - w * li * li;
+            if slx > 0. && sl2 > 0. {
+                let new_l = nearest_int(x * sl2 / slx).min(nmax);
+                if new_l != *l as i32 {
+                    slx += w * x * new_l as f32;
+                    sl2 += w * (new_l * new_l) as f32;
+                    if slx * slx * suml2 > sumlx * sumlx * sl2 {
+                        *l = new_l as u8;
+                        sumlx = slx;
+                        suml2 = sl2;
+                        n_changed += 1;
+                    }
+                }
+            }
+        }
+        if n_changed == 0 {
+            break;
+        }
+    }
+    sumlx / suml2
+}
@@ -1,9 +1,13 @@
+use crate::cpu_backend::CpuMmapStorage;
+use crate::op::BackpropOp;
+use crate::storage::Storage;
 use crate::{DType, Device, Error, Result, Tensor, WithDType};
 use safetensors::tensor as st;
 use safetensors::tensor::SafeTensors;
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::path::Path;
+use std::sync::Arc;
 
 impl From<DType> for st::Dtype {
     fn from(value: DType) -> Self {
@@ -217,7 +221,7 @@ fn convert(view: &st::TensorView<'_>, device: &Device) -> Result<Tensor> {
     }
 }
 
-fn convert_back(tensor: &Tensor) -> Result<Vec<u8>> {
+pub(crate) fn convert_back(tensor: &Tensor) -> Result<Vec<u8>> {
     // TODO: This makes an unnecessary copy when the tensor is on the cpu.
     let tensor = tensor.flatten_all()?;
     match tensor.dtype() {
@@ -251,11 +255,128 @@ pub fn save<K: AsRef<str> + Ord + std::fmt::Display, P: AsRef<Path>>(
     Ok(st::serialize_to_file(tensors, &None, filename.as_ref())?)
 }
 
+/// The name of the index file for a sharded checkpoint, as used by the transformers library.
+pub const INDEX_FILENAME: &str = "model.safetensors.index.json";
+
+/// Saves the tensors in multiple safetensors files of at most `max_shard_size` bytes each, along
+/// with an index file mapping each tensor name to its file, using the same layout as the
+/// transformers library. The tensors are written sorted by name, a tensor larger than
+/// `max_shard_size` gets its own file. Returns the path of the index file.
+pub fn save_sharded<K: AsRef<str> + Ord + std::fmt::Display, P: AsRef<Path>>(
+    tensors: &HashMap<K, Tensor>,
+    dir: P,
+    max_shard_size: usize,
+) -> Result<std::path::PathBuf> {
+    let dir = dir.as_ref();
+    let mut tensors = tensors.iter().collect::<Vec<_>>();
+    tensors.sort_by_key(|(k, _)| *k);
+    let mut shards: Vec<Vec<(&K, &Tensor)>> = vec![];
+    let mut shard_size = 0;
+    let mut total_size = 0;
+    for (name, tensor) in tensors {
+        let size = tensor.elem_count() * tensor.dtype().size_in_bytes();
+        match shards.last_mut() {
+            Some(shard) if shard_size + size <= max_shard_size => {
+                shard.push((name, tensor));
+                shard_size += size
+            }
+            _ => {
+                shards.push(vec![(name, tensor)]);
+                shard_size = size
+            }
+        }
+        total_size += size;
+    }
+    let n_shards = shards.len();
+    let mut weight_map = serde_json::Map::new();
+    for (index, shard) in shards.into_iter().enumerate() {
+        let filename = format!("model-{:05}-of-{n_shards:05}.safetensors", index + 1);
+        for (name, _) in shard.iter() {
+            weight_map.insert(name.to_string(), filename.clone().into());
+        }
+        let data = shard
+            .into_iter()
+            .map(|(name, tensor)| (name.as_ref(), tensor));
+        st::serialize_to_file(data, &None, &dir.join(&filename))?;
+    }
+    let index = serde_json::json!({
+        "metadata": { "total_size": total_size },
+        "weight_map": weight_map,
+    });
+    let index_path = dir.join(INDEX_FILENAME);
+    let index = serde_json::to_string_pretty(&index).map_err(Error::wrap)?;
+    std::fs::write(&index_path, index).map_err(|e| Error::from(e).with_path(&index_path))?;
+    Ok(index_path)
+}
+
+/// Reads the index file of a sharded checkpoint and returns the mapping from tensor names to the
+/// path of the files containing them, the paths being relative to the index file directory.
+pub fn read_index<P: AsRef<Path>>(index_path: P) -> Result<HashMap<String, std::path::PathBuf>> {
+    let index_path = index_path.as_ref();
+    let index = std::fs::read(index_path).map_err(|e| Error::from(e).with_path(index_path))?;
+    let index: serde_json::Value =
+        serde_json::from_slice(&index).map_err(|e| Error::wrap(e).with_path(index_path))?;
+    let weight_map = match index.get("weight_map").and_then(|v| v.as_object()) {
+        Some(weight_map) => weight_map,
+        None => crate::bail!("no weight_map in {index_path:?}"),
+    };
+    let dir = index_path.parent().unwrap_or_else(|| Path::new(""));
+    let mut files = HashMap::new();
+    for (name, filename) in weight_map.iter() {
+        match filename.as_str() {
+            Some(filename) => files.insert(name.to_string(), dir.join(filename)),
+            None => crate::bail!("unexpected weight_map entry for {name} in {index_path:?}"),
+        };
+    }
+    Ok(files)
+}
+
 #[derive(yoke::Yokeable)]
 struct SafeTensors_<'a>(SafeTensors<'a>);
 
+type MmapedSafetensors_ = yoke::Yoke<SafeTensors_<'static>, Arc<memmap2::Mmap>>;
+
+unsafe fn mmap_safetensors(p: &Path) -> Result<MmapedSafetensors_> {
+    let file = std::fs::File::open(p).map_err(|e| Error::from(e).with_path(p))?;
+    let file = memmap2::MmapOptions::new()
+        .map(&file)
+        .map_err(|e| Error::from(e).with_path(p))?;
+    yoke::Yoke::<SafeTensors_<'static>, Arc<memmap2::Mmap>>::try_attach_to_cart(
+        Arc::new(file),
+        |data: &memmap2::Mmap| {
+            let st = safetensors::SafeTensors::deserialize(data)
+                .map_err(|e| Error::from(e).with_path(p))?;
+            Ok::<_, Error>(SafeTensors_(st))
+        },
+    )
+}
+
+// Creates a cpu tensor that reads its data directly from the mapped file, `None` is returned if
+// the dtype has to be converted or if the data is not aligned.
+fn mmaped_tensor(
+    view: &st::TensorView<'_>,
+    mmap: &Arc<memmap2::Mmap>,
+    device: &Device,
+) -> Option<Tensor> {
+    let dtype = DType::try_from(view.dtype()).ok()?;
+    let offset = view.data().as_ptr() as usize - mmap.as_ptr() as usize;
+    let elem_count = view.shape().iter().product();
+    let storage = CpuMmapStorage::new(mmap.clone(), offset, elem_count, dtype).ok()?;
+    let storage = Storage::CpuMmap(storage);
+    let none = BackpropOp::none();
+    Some(crate::tensor::from_storage(
+        storage,
+        device,
+        view.shape(),
+        none,
+        false,
+    ))
+}
+
 pub struct MmapedSafetensors {
-    safetensors: Vec<yoke::Yoke<SafeTensors_<'static>, memmap2::Mmap>>,
+    // The files are only mapped on first access when created from an index file.
+    safetensors: Vec<std::sync::OnceLock<MmapedSafetensors_>>,
+    paths: Vec<std::path::PathBuf>,
     routing: Option<HashMap<String, usize>>,
 }
 
@@ -267,20 +388,10 @@ impl MmapedSafetensors {
     /// The unsafe is inherited from [`memmap2::MmapOptions`].
     pub unsafe fn new<P: AsRef<Path>>(p: P) -> Result<Self> {
         let p = p.as_ref();
-        let file = std::fs::File::open(p).map_err(|e| Error::from(e).with_path(p))?;
-        let file = memmap2::MmapOptions::new()
-            .map(&file)
-            .map_err(|e| Error::from(e).with_path(p))?;
-        let safetensors = yoke::Yoke::<SafeTensors_<'static>, memmap2::Mmap>::try_attach_to_cart(
-            file,
-            |data: &[u8]| {
-                let st = safetensors::SafeTensors::deserialize(data)
-                    .map_err(|e| Error::from(e).with_path(p))?;
-                Ok::<_, Error>(SafeTensors_(st))
-            },
-        )?;
+        let safetensors = mmap_safetensors(p)?;
         Ok(Self {
-            safetensors: vec![safetensors],
+            safetensors: vec![safetensors.into()],
+            paths: vec![p.to_path_buf()],
             routing: None,
         })
     }
@@ -296,45 +407,100 @@ impl MmapedSafetensors {
         let mut routing = HashMap::new();
         let mut safetensors = vec![];
         for (index, p) in paths.iter().enumerate() {
-            let p = p.as_ref();
-            let file = std::fs::File::open(p).map_err(|e| Error::from(e).with_path(p))?;
-            let file = memmap2::MmapOptions::new()
-                .map(&file)
-                .map_err(|e| Error::from(e).with_path(p))?;
-            let data = yoke::Yoke::<SafeTensors_<'static>, memmap2::Mmap>::try_attach_to_cart(
-                file,
-                |data: &[u8]| {
-                    let st = safetensors::SafeTensors::deserialize(data)
-                        .map_err(|e| Error::from(e).with_path(p))?;
-                    Ok::<_, Error>(SafeTensors_(st))
-                },
-            )?;
+            let data = mmap_safetensors(p.as_ref())?;
             for k in data.get().0.names() {
                 routing.insert(k.to_string(), index);
             }
-            safetensors.push(data)
+            safetensors.push(data.into())
         }
         Ok(Self {
             safetensors,
+            paths: paths.iter().map(|p| p.as_ref().to_path_buf()).collect(),
+            routing: Some(routing),
+        })
+    }
+
+    /// Creates a wrapper around the files of a sharded checkpoint using its index file, e.g.
+    /// `model.safetensors.index.json`. The files are only memory mapped when one of their
+    /// tensors is first accessed.
+    ///
+    /// # Safety
+    ///
+    /// The unsafe is inherited from [`memmap2::MmapOptions`].
+    pub unsafe fn from_index<P: AsRef<Path>>(index_path: P) -> Result<Self> {
+        let files = read_index(index_path)?;
+        let mut paths: Vec<std::path::PathBuf> = vec![];
+        let mut routing = HashMap::new();
+        for (name, path) in files.into_iter() {
+            let index = match paths.iter().position(|p| p == &path) {
+                Some(index) => index,
+                None => {
+                    paths.push(path);
+                    paths.len() - 1
+                }
+            };
+            routing.insert(name, index);
+        }
+        Ok(Self {
+            safetensors: paths.iter().map(|_| std::sync::OnceLock::new()).collect(),
+            paths,
             routing: Some(routing),
         })
     }
 
+    fn shard(&self, index: usize) -> Result<&MmapedSafetensors_> {
+        let lock = &self.safetensors[index];
+        if let Some(safetensors) = lock.get() {
+            return Ok(safetensors);
+        }
+        // Safety: the file was provided to one of the unsafe constructors.
+        let safetensors = unsafe { mmap_safetensors(&self.paths[index])? };
+        // If another thread mapped the file in the meantime, its mapping is used.
+        let _ = lock.set(safetensors);
+        Ok(lock.get().unwrap())
+    }
+
+    /// Loads the tensor `name` on `dev`. On cpu devices, the tensor data is not copied but read
+    /// in place from the mapped file, see [`CpuMmapStorage`], unless its dtype has to be converted
+    /// or it is not properly aligned.
     pub fn load(&self, name: &str, dev: &Device) -> Result<Tensor> {
-        self.get(name)?.load(dev)
+        let shard = self.shard(self.index(name)?)?;
+        let view = shard.get().0.tensor(name)?;
+        if dev.is_cpu() {
+            if let Some(tensor) = mmaped_tensor(&view, shard.backing_cart(), dev) {
+                return Ok(tensor);
+            }
+        }
+        view.load(dev)
     }
 
-    pub fn tensors(&self) -> Vec<(String, st::TensorView<'_>)> {
+    /// Returns all the tensors, mapping the files that have not been accessed yet. This fails if
+    /// one of the files listed in the index file is missing or cannot be mapped.
+    pub fn tensors(&self) -> Result<Vec<(String, st::TensorView<'_>)>> {
         let mut tensors = vec![];
-        for safetensors in self.safetensors.iter() {
-            tensors.push(safetensors.get().0.tensors())
+        for index in 0..self.safetensors.len() {
+            tensors.extend(self.shard(index)?.get().0.tensors())
+        }
+        Ok(tensors)
+    }
+
+    /// Returns true if the tensor is present, without mapping any file.
+    pub fn contains(&self, name: &str) -> bool {
+        match &self.routing {
+            None => self.get(name).is_ok(),
+            Some(routing) => routing.contains_key(name),
         }
-        tensors.into_iter().flatten().collect()
     }
 
     pub fn get(&self, name: &str) -> Result<st::TensorView<'_>> {
-        let index = match &self.routing {
-            None => 0,
+        let shard = self.shard(self.index(name)?)?;
+        Ok(shard.get().0.tensor(name)?)
+    }
+
+    // The index of the file containing `name`.
+    fn index(&self, name: &str) -> Result<usize> {
+        match &self.routing {
+            None => Ok(0),
             Some(routing) => {
                 let index = routing.get(name).ok_or_else(|| {
                     Error::CannotFindTensor {
@@ -342,10 +508,9 @@ impl MmapedSafetensors {
                     }
                     .bt()
                 })?;
-                *index
+                Ok(*index)
             }
-        };
-        Ok(self.safetensors[index].get().0.tensor(name)?)
+        }
     }
 }
 
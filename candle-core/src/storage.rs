@@ -1,4 +1,5 @@
 use crate::backend::BackendStorage;
+use crate::cpu_backend::{CpuMmapStorage, CpuStorageRef};
 use crate::op::{self, CmpOp, CustomOp1, CustomOp2, CustomOp3, ReduceOp};
 use crate::{CpuStorage, CudaStorage, DType, Device, Error, Layout, MetalStorage, Result, Shape};
 
@@ -7,6 +8,7 @@ use crate::{CpuStorage, CudaStorage, DType, Device, Error, Layout, MetalStorage,
 #[derive(Debug)]
 pub enum Storage {
     Cpu(CpuStorage),
+    CpuMmap(CpuMmapStorage),
     Cuda(CudaStorage),
     Metal(MetalStorage),
 }
@@ -15,6 +17,7 @@ impl Storage {
     pub fn try_clone(&self, layout: &Layout) -> Result<Self> {
         match self {
             Self::Cpu(storage) => Ok(Self::Cpu(storage.clone())),
+            Self::CpuMmap(storage) => Ok(Self::CpuMmap(storage.clone())),
             Self::Cuda(storage) => {
                 let storage = storage.try_clone(layout)?;
                 Ok(Self::Cuda(storage))
@@ -28,7 +31,7 @@ impl Storage {
 
     pub fn device(&self) -> Device {
         match self {
-            Self::Cpu(_) => Device::Cpu,
+            Self::Cpu(_) | Self::CpuMmap(_) => Device::Cpu,
             Self::Cuda(storage) => Device::Cuda(storage.device().clone()),
             Self::Metal(storage) => Device::Metal(storage.device().clone()),
         }
@@ -37,11 +40,33 @@ impl Storage {
     pub fn dtype(&self) -> DType {
         match self {
             Self::Cpu(storage) => storage.dtype(),
+            Self::CpuMmap(storage) => storage.dtype(),
             Self::Cuda(storage) => storage.dtype(),
             Self::Metal(storage) => storage.dtype(),
         }
     }
 
+    /// A view on the data of the cpu storages, either owned or memory mapped.
+    fn cpu_ref(&self) -> Option<CpuStorageRef<'_>> {
+        match self {
+            Self::Cpu(storage) => Some(storage.storage_ref()),
+            Self::CpuMmap(storage) => Some(storage.storage_ref()),
+            Self::Cuda(_) | Self::Metal(_) => None,
+        }
+    }
+
+    /// The cpu storage used by custom ops. Memory mapped storages are replaced with an owned
+    /// copy at the tensor level before applying a custom op, see `Tensor::custom_op_storage`.
+    fn cpu_storage(&self, op: &str) -> Result<Option<&CpuStorage>> {
+        match self {
+            Self::Cpu(storage) => Ok(Some(storage)),
+            Self::CpuMmap(_) => {
+                crate::bail!("custom op {op} cannot be applied to a memory mapped storage")
+            }
+            Self::Cuda(_) | Self::Metal(_) => Ok(None),
+        }
+    }
+
     pub(crate) fn same_device(&self, rhs: &Self, op: &'static str) -> Result<()> {
         let lhs = self.device().location();
         let rhs = rhs.device().location();
@@ -68,6 +93,10 @@ impl Storage {
                 let storage = storage.affine(layout, mul, add)?;
                 Ok(Self::Cpu(storage))
             }
+            Self::CpuMmap(storage) => {
+                let storage = storage.storage_ref().affine(layout, mul, add)?;
+                Ok(Self::Cpu(storage))
+            }
             Self::Cuda(storage) => {
                 let storage = storage.affine(layout, mul, add)?;
                 Ok(Self::Cuda(storage))
@@ -85,6 +114,10 @@ impl Storage {
                 let storage = storage.powf(layout, alpha)?;
                 Ok(Self::Cpu(storage))
             }
+            Self::CpuMmap(storage) => {
+                let storage = storage.storage_ref().powf(layout, alpha)?;
+                Ok(Self::Cpu(storage))
+            }
             Self::Cuda(storage) => {
                 let storage = storage.powf(layout, alpha)?;
                 Ok(Self::Cuda(storage))
@@ -102,6 +135,10 @@ impl Storage {
                 let storage = storage.elu(layout, alpha)?;
                 Ok(Self::Cpu(storage))
             }
+            Self::CpuMmap(storage) => {
+                let storage = storage.storage_ref().elu(layout, alpha)?;
+                Ok(Self::Cpu(storage))
+            }
             Self::Cuda(storage) => {
                 let storage = storage.elu(layout, alpha)?;
                 Ok(Self::Cuda(storage))
@@ -122,11 +159,11 @@ impl Storage {
     ) -> Result<Self> {
         self.same_device(rhs, "cmp")?;
         self.same_dtype(rhs, "cmp")?;
+        if let (Some(lhs), Some(rhs)) = (self.cpu_ref(), rhs.cpu_ref()) {
+            let storage = lhs.cmp(op, rhs, lhs_layout, rhs_layout)?;
+            return Ok(Self::Cpu(storage));
+        }
         match (self, rhs) {
-            (Storage::Cpu(lhs), Storage::Cpu(rhs)) => {
-                let storage = lhs.cmp(op, rhs, lhs_layout, rhs_layout)?;
-                Ok(Self::Cpu(storage))
-            }
             (Self::Cuda(lhs), Self::Cuda(rhs)) => {
                 let storage = lhs.cmp(op, rhs, lhs_layout, rhs_layout)?;
                 Ok(Self::Cuda(storage))
@@ -154,6 +191,10 @@ impl Storage {
                 let storage = storage.reduce_op(op, layout, s)?;
                 Ok(Self::Cpu(storage))
             }
+            Self::CpuMmap(storage) => {
+                let storage = storage.storage_ref().reduce_op(op, layout, s)?;
+                Ok(Self::Cpu(storage))
+            }
             Self::Cuda(storage) => {
                 let storage = storage.reduce_op(op, layout, s)?;
                 Ok(Self::Cuda(storage))
@@ -171,6 +212,10 @@ impl Storage {
                 let storage = storage.to_dtype(layout, dtype)?;
                 Ok(Self::Cpu(storage))
             }
+            Self::CpuMmap(storage) => {
+                let storage = storage.storage_ref().to_dtype(layout, dtype)?;
+                Ok(Self::Cpu(storage))
+            }
             Self::Cuda(storage) => {
                 let storage = storage.to_dtype(layout, dtype)?;
                 Ok(Self::Cuda(storage))
@@ -188,6 +233,12 @@ impl Storage {
                 let (storage, shape) = c.cpu_fwd(storage, l)?;
                 Ok((Self::Cpu(storage), shape))
             }
+            Self::CpuMmap(_) => {
+                crate::bail!(
+                    "custom op {} cannot be applied to a memory mapped storage",
+                    c.name()
+                )
+            }
             Self::Cuda(storage) => {
                 let (storage, shape) = c.cuda_fwd(storage, l)?;
                 Ok((Self::Cuda(storage), shape))
@@ -207,11 +258,11 @@ impl Storage {
         c: &dyn CustomOp2,
     ) -> Result<(Self, Shape)> {
         self.same_device(t2, c.name())?;
+        if let (Some(s1), Some(s2)) = (self.cpu_storage(c.name())?, t2.cpu_storage(c.name())?) {
+            let (s, shape) = c.cpu_fwd(s1, l1, s2, l2)?;
+            return Ok((Self::Cpu(s), shape));
+        }
         match (self, t2) {
-            (Self::Cpu(s1), Self::Cpu(s2)) => {
-                let (s, shape) = c.cpu_fwd(s1, l1, s2, l2)?;
-                Ok((Self::Cpu(s), shape))
-            }
             (Self::Cuda(s1), Self::Cuda(s2)) => {
                 let (s, shape) = c.cuda_fwd(s1, l1, s2, l2)?;
                 Ok((Self::Cuda(s), shape))
@@ -235,11 +286,15 @@ impl Storage {
     ) -> Result<(Self, Shape)> {
         self.same_device(t2, c.name())?;
         self.same_device(t3, c.name())?;
+        if let (Some(s1), Some(s2), Some(s3)) = (
+            self.cpu_storage(c.name())?,
+            t2.cpu_storage(c.name())?,
+            t3.cpu_storage(c.name())?,
+        ) {
+            let (s, shape) = c.cpu_fwd(s1, l1, s2, l2, s3, l3)?;
+            return Ok((Self::Cpu(s), shape));
+        }
         match (self, t2, t3) {
-            (Self::Cpu(s1), Self::Cpu(s2), Self::Cpu(s3)) => {
-                let (s, shape) = c.cpu_fwd(s1, l1, s2, l2, s3, l3)?;
-                Ok((Self::Cpu(s), shape))
-            }
             (Self::Cuda(s1), Self::Cuda(s2), Self::Cuda(s3)) => {
                 let (s, shape) = c.cuda_fwd(s1, l1, s2, l2, s3, l3)?;
                 Ok((Self::Cuda(s), shape))
@@ -258,6 +313,10 @@ impl Storage {
                 let storage = storage.unary_impl::<B>(layout)?;
                 Ok(Self::Cpu(storage))
             }
+            Self::CpuMmap(storage) => {
+                let storage = storage.storage_ref().unary_impl::<B>(layout)?;
+                Ok(Self::Cpu(storage))
+            }
             Self::Cuda(storage) => {
                 let storage = storage.unary_impl::<B>(layout)?;
                 Ok(Self::Cuda(storage))
@@ -277,11 +336,11 @@ impl Storage {
     ) -> Result<Self> {
         self.same_device(rhs, B::NAME)?;
         self.same_dtype(rhs, B::NAME)?;
+        if let (Some(lhs), Some(rhs)) = (self.cpu_ref(), rhs.cpu_ref()) {
+            let storage = lhs.binary_impl::<B>(rhs, lhs_layout, rhs_layout)?;
+            return Ok(Self::Cpu(storage));
+        }
         match (self, rhs) {
-            (Storage::Cpu(lhs), Storage::Cpu(rhs)) => {
-                let storage = lhs.binary_impl::<B>(rhs, lhs_layout, rhs_layout)?;
-                Ok(Self::Cpu(storage))
-            }
             (Self::Cuda(lhs), Self::Cuda(rhs)) => {
                 let storage = lhs.binary_impl::<B>(rhs, lhs_layout, rhs_layout)?;
                 Ok(Self::Cuda(storage))
@@ -312,11 +371,11 @@ impl Storage {
     ) -> Result<Self> {
         self.same_device(kernel, "conv1d")?;
         self.same_dtype(kernel, "conv1d")?;
+        if let (Some(inp), Some(kernel)) = (self.cpu_ref(), kernel.cpu_ref()) {
+            let s = inp.conv1d(l, kernel, kernel_l, params)?;
+            return Ok(Self::Cpu(s));
+        }
         match (self, &kernel) {
-            (Storage::Cpu(inp), Storage::Cpu(kernel)) => {
-                let s = inp.conv1d(l, kernel, kernel_l, params)?;
-                Ok(Self::Cpu(s))
-            }
             (Storage::Cuda(inp), Storage::Cuda(kernel)) => {
                 let s = inp.conv1d(l, kernel, kernel_l, params)?;
                 Ok(Self::Cuda(s))
@@ -343,11 +402,11 @@ impl Storage {
     ) -> Result<Self> {
         self.same_device(kernel, "conv-transpose1d")?;
         self.same_dtype(kernel, "conv-transpose1d")?;
+        if let (Some(inp), Some(kernel)) = (self.cpu_ref(), kernel.cpu_ref()) {
+            let s = inp.conv_transpose1d(l, kernel, kernel_l, params)?;
+            return Ok(Self::Cpu(s));
+        }
         match (self, &kernel) {
-            (Storage::Cpu(inp), Storage::Cpu(kernel)) => {
-                let s = inp.conv_transpose1d(l, kernel, kernel_l, params)?;
-                Ok(Self::Cpu(s))
-            }
             (Storage::Cuda(inp), Storage::Cuda(kernel)) => {
                 let s = inp.conv_transpose1d(l, kernel, kernel_l, params)?;
                 Ok(Self::Cuda(s))
@@ -370,11 +429,11 @@ impl Storage {
     ) -> Result<Self> {
         self.same_device(kernel, "conv2d")?;
         self.same_dtype(kernel, "conv2d")?;
+        if let (Some(inp), Some(kernel)) = (self.cpu_ref(), kernel.cpu_ref()) {
+            let s = inp.conv2d(l, kernel, kernel_l, params)?;
+            return Ok(Self::Cpu(s));
+        }
         match (self, &kernel) {
-            (Storage::Cpu(inp), Storage::Cpu(kernel)) => {
-                let s = inp.conv2d(l, kernel, kernel_l, params)?;
-                Ok(Self::Cpu(s))
-            }
             (Storage::Cuda(inp), Storage::Cuda(kernel)) => {
                 let s = inp.conv2d(l, kernel, kernel_l, params)?;
                 Ok(Self::Cuda(s))
@@ -401,11 +460,11 @@ impl Storage {
     ) -> Result<Self> {
         self.same_device(kernel, "conv_transpose2d")?;
         self.same_dtype(kernel, "conv_transpose2d")?;
+        if let (Some(inp), Some(kernel)) = (self.cpu_ref(), kernel.cpu_ref()) {
+            let s = inp.conv_transpose2d(l, kernel, kernel_l, params)?;
+            return Ok(Self::Cpu(s));
+        }
         match (self, &kernel) {
-            (Storage::Cpu(inp), Storage::Cpu(kernel)) => {
-                let s = inp.conv_transpose2d(l, kernel, kernel_l, params)?;
-                Ok(Self::Cpu(s))
-            }
             (Storage::Cuda(inp), Storage::Cuda(kernel)) => {
                 let s = inp.conv_transpose2d(l, kernel, kernel_l, params)?;
                 Ok(Self::Cuda(s))
@@ -434,6 +493,12 @@ impl Storage {
                 let storage = storage.avg_pool2d(layout, kernel_size, stride)?;
                 Ok(Self::Cpu(storage))
             }
+            Self::CpuMmap(storage) => {
+                let storage = storage
+                    .storage_ref()
+                    .avg_pool2d(layout, kernel_size, stride)?;
+                Ok(Self::Cpu(storage))
+            }
             Self::Cuda(storage) => {
                 let storage = storage.avg_pool2d(layout, kernel_size, stride)?;
                 Ok(Self::Cuda(storage))
@@ -456,6 +521,12 @@ impl Storage {
                 let storage = storage.max_pool2d(layout, kernel_size, stride)?;
                 Ok(Self::Cpu(storage))
             }
+            Self::CpuMmap(storage) => {
+                let storage = storage
+                    .storage_ref()
+                    .max_pool2d(layout, kernel_size, stride)?;
+                Ok(Self::Cpu(storage))
+            }
             Self::Cuda(storage) => {
                 let storage = storage.max_pool2d(layout, kernel_size, stride)?;
                 Ok(Self::Cuda(storage))
@@ -473,6 +544,10 @@ impl Storage {
                 let storage = storage.upsample_nearest1d(layout, sz)?;
                 Ok(Self::Cpu(storage))
             }
+            Self::CpuMmap(storage) => {
+                let storage = storage.storage_ref().upsample_nearest1d(layout, sz)?;
+                Ok(Self::Cpu(storage))
+            }
             Self::Cuda(storage) => {
                 let storage = storage.upsample_nearest1d(layout, sz)?;
                 Ok(Self::Cuda(storage))
@@ -490,6 +565,10 @@ impl Storage {
                 let storage = storage.upsample_nearest2d(layout, h, w)?;
                 Ok(Self::Cpu(storage))
             }
+            Self::CpuMmap(storage) => {
+                let storage = storage.storage_ref().upsample_nearest2d(layout, h, w)?;
+                Ok(Self::Cpu(storage))
+            }
             Self::Cuda(storage) => {
                 let storage = storage.upsample_nearest2d(layout, h, w)?;
                 Ok(Self::Cuda(storage))
@@ -512,11 +591,11 @@ impl Storage {
         self.same_device(t, "where")?;
         self.same_device(f, "where")?;
         t.same_dtype(f, "where")?;
+        if let (Some(cond), Some(t), Some(f)) = (self.cpu_ref(), t.cpu_ref(), f.cpu_ref()) {
+            let storage = cond.where_cond(layout, t, layout_t, f, layout_f)?;
+            return Ok(Self::Cpu(storage));
+        }
         match (self, t, f) {
-            (Storage::Cpu(cond), Storage::Cpu(t), Storage::Cpu(f)) => {
-                let storage = cond.where_cond(layout, t, layout_t, f, layout_f)?;
-                Ok(Self::Cpu(storage))
-            }
             (Self::Cuda(cond), Self::Cuda(t), Self::Cuda(f)) => {
                 let storage = cond.where_cond(layout, t, layout_t, f, layout_f)?;
                 Ok(Self::Cuda(storage))
@@ -542,11 +621,11 @@ impl Storage {
         d: usize,
     ) -> Result<Self> {
         self.same_device(indexes, "index-add")?;
+        if let (Some(s), Some(indexes)) = (self.cpu_ref(), indexes.cpu_ref()) {
+            let storage = s.gather(l, indexes, indexes_l, d)?;
+            return Ok(Self::Cpu(storage));
+        }
         match (self, indexes) {
-            (Self::Cpu(s), Self::Cpu(indexes)) => {
-                let storage = s.gather(l, indexes, indexes_l, d)?;
-                Ok(Self::Cpu(storage))
-            }
             (Self::Cuda(s), Self::Cuda(indexes)) => {
                 let storage = s.gather(l, indexes, indexes_l, d)?;
                 Ok(Self::Cuda(storage))
@@ -570,11 +649,13 @@ impl Storage {
     ) -> Result<Self> {
         self.same_device(indexes, "scatter-add")?;
         self.same_device(source, "scatter-add")?;
+        if let (Some(s), Some(indexes), Some(source)) =
+            (self.cpu_ref(), indexes.cpu_ref(), source.cpu_ref())
+        {
+            let storage = s.scatter_add(l, indexes, indexes_l, source, source_l, d)?;
+            return Ok(Self::Cpu(storage));
+        }
         match (self, indexes, source) {
-            (Self::Cpu(s), Self::Cpu(indexes), Self::Cpu(source)) => {
-                let storage = s.scatter_add(l, indexes, indexes_l, source, source_l, d)?;
-                Ok(Self::Cpu(storage))
-            }
             (Self::Cuda(s), Self::Cuda(indexes), Self::Cuda(source)) => {
                 let storage = s.scatter_add(l, indexes, indexes_l, source, source_l, d)?;
                 Ok(Self::Cuda(storage))
@@ -598,11 +679,13 @@ impl Storage {
     ) -> Result<Self> {
         self.same_device(indexes, "index-add")?;
         self.same_device(source, "index-add")?;
+        if let (Some(s), Some(indexes), Some(source)) =
+            (self.cpu_ref(), indexes.cpu_ref(), source.cpu_ref())
+        {
+            let storage = s.index_add(l, indexes, indexes_l, source, source_l, d)?;
+            return Ok(Self::Cpu(storage));
+        }
         match (self, indexes, source) {
-            (Self::Cpu(s), Self::Cpu(indexes), Self::Cpu(source)) => {
-                let storage = s.index_add(l, indexes, indexes_l, source, source_l, d)?;
-                Ok(Self::Cpu(storage))
-            }
             (Self::Cuda(s), Self::Cuda(indexes), Self::Cuda(source)) => {
                 let storage = s.index_add(l, indexes, indexes_l, source, source_l, d)?;
                 Ok(Self::Cuda(storage))
@@ -623,11 +706,11 @@ impl Storage {
         d: usize,
     ) -> Result<Self> {
         self.same_device(rhs, "index-select")?;
+        if let (Some(lhs), Some(rhs)) = (self.cpu_ref(), rhs.cpu_ref()) {
+            let storage = lhs.index_select(rhs, lhs_l, rhs_l, d)?;
+            return Ok(Self::Cpu(storage));
+        }
         match (self, rhs) {
-            (Self::Cpu(lhs), Self::Cpu(rhs)) => {
-                let storage = lhs.index_select(rhs, lhs_l, rhs_l, d)?;
-                Ok(Self::Cpu(storage))
-            }
             (Self::Cuda(lhs), Self::Cuda(rhs)) => {
                 let storage = lhs.index_select(rhs, lhs_l, rhs_l, d)?;
                 Ok(Self::Cuda(storage))
@@ -654,11 +737,11 @@ impl Storage {
     ) -> Result<Self> {
         self.same_device(rhs, "matmul")?;
         self.same_dtype(rhs, "matmul")?;
+        if let (Some(lhs), Some(rhs)) = (self.cpu_ref(), rhs.cpu_ref()) {
+            let storage = lhs.matmul(rhs, bmnk, lhs_layout, rhs_layout)?;
+            return Ok(Self::Cpu(storage));
+        }
         match (self, rhs) {
-            (Self::Cpu(lhs), Self::Cpu(rhs)) => {
-                let storage = lhs.matmul(rhs, bmnk, lhs_layout, rhs_layout)?;
-                Ok(Self::Cpu(storage))
-            }
             (Self::Cuda(lhs), Self::Cuda(rhs)) => {
                 let storage = lhs.matmul(rhs, bmnk, lhs_layout, rhs_layout)?;
                 Ok(Self::Cuda(storage))
@@ -685,6 +768,10 @@ impl Storage {
     ) -> Result<()> {
         match (self, dst) {
             (Self::Cpu(src), Self::Cpu(dst)) => src.copy_strided_src(dst, dst_offset, src_l),
+            (Self::CpuMmap(src), Self::Cpu(dst)) => {
+                src.storage_ref().copy_strided_src(dst, dst_offset, src_l)
+            }
+            (_, Self::CpuMmap(_)) => crate::bail!("cannot copy to a memory mapped storage"),
             (Self::Cuda(src), Self::Cuda(dst)) => Ok(src.copy_strided_src(dst, dst_offset, src_l)?),
             (Self::Metal(src), Self::Metal(dst)) => {
                 Ok(src.copy_strided_src(dst, dst_offset, src_l)?)
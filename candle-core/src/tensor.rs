@@ -1,6 +1,7 @@
 //! Tensors are N-dimensional matrixes of elements using a single data type.
 #![allow(clippy::redundant_closure_call)]
 use crate::backend::{BackendDevice, BackendStorage};
+use crate::cpu_backend::CpuStorageRef;
 use crate::op::{
     BackpropOp, BinaryOp, CmpOp, CustomOp1, CustomOp2, CustomOp3, Op, ReduceOp, UnaryOp,
 };
@@ -85,7 +86,13 @@ macro_rules! unary_op {
                 .storage()
                 .unary_impl::<crate::op::$op_name>(self.layout())?;
             let op = BackpropOp::new1(self, |s| Op::Unary(s, UnaryOp::$op_name));
-            Ok(from_storage(storage, shape.clone(), op, false))
+            Ok(from_storage(
+                storage,
+                self.device(),
+                shape.clone(),
+                op,
+                false,
+            ))
         }
     };
 }
@@ -100,7 +107,13 @@ macro_rules! binary_op {
                 rhs.layout(),
             )?;
             let op = BackpropOp::new2(self, rhs, |t1, t2| Op::Binary(t1, t2, BinaryOp::$op_name));
-            Ok(from_storage(storage, shape.clone(), op, false))
+            Ok(from_storage(
+                storage,
+                self.device(),
+                shape.clone(),
+                op,
+                false,
+            ))
         }
     };
 }
@@ -122,7 +135,13 @@ macro_rules! binary_op_scalar {
                 rhs.layout(),
             )?;
             let op = BackpropOp::new2(self, &rhs, |t1, t2| Op::Binary(t1, t2, BinaryOp::$op_name));
-            Ok(from_storage(storage, shape.clone(), op, false))
+            Ok(from_storage(
+                storage,
+                self.device(),
+                shape.clone(),
+                op,
+                false,
+            ))
         }
     };
 }
@@ -151,12 +170,14 @@ macro_rules! broadcast_binary_op {
 /// Creates a fresh tensor structure based on a storage and a shape, this uses contiguous strides.
 pub(crate) fn from_storage<S: Into<Shape>>(
     storage: Storage,
+    device: &Device,
     shape: S,
     op: BackpropOp,
     is_variable: bool,
 ) -> Tensor {
     let dtype = storage.dtype();
-    let device = storage.device();
+    // The device is not taken from the storage so that the thread pool of cpu devices is kept.
+    let device = device.clone();
     let tensor_ = Tensor_ {
         id: TensorId::new(),
         storage: Arc::new(RwLock::new(storage)),
@@ -179,7 +200,7 @@ impl Tensor {
         let none = BackpropOp::none();
         let shape = shape.into();
         let storage = device.ones(&shape, dtype)?;
-        Ok(from_storage(storage, shape, none, is_variable))
+        Ok(from_storage(storage, device, shape, none, is_variable))
     }
 
     /// Creates a new tensor filled with ones.
@@ -219,7 +240,7 @@ impl Tensor {
         let none = BackpropOp::none();
         let shape = shape.into();
         let storage = device.zeros(&shape, dtype)?;
-        Ok(from_storage(storage, shape, none, is_variable))
+        Ok(from_storage(storage, device, shape, none, is_variable))
     }
 
     /// Creates a new tensor filled with zeros.
@@ -259,7 +280,7 @@ impl Tensor {
         let s = s.into();
         let storage = device.rand_uniform(lo, up, &s)?;
         let none = BackpropOp::none();
-        Ok(from_storage(storage, s, none, is_variable))
+        Ok(from_storage(storage, device, s, none, is_variable))
     }
 
     pub(crate) fn rand_f64_impl<S: Into<Shape>>(
@@ -273,7 +294,7 @@ impl Tensor {
         let s = s.into();
         let storage = device.rand_uniform_f64(lo, up, &s, dtype)?;
         let none = BackpropOp::none();
-        Ok(from_storage(storage, s, none, is_variable))
+        Ok(from_storage(storage, device, s, none, is_variable))
     }
 
     /// Creates a new tensor initialized with values sampled uniformly between `lo` and `up`.
@@ -300,7 +321,7 @@ impl Tensor {
         let s = s.into();
         let storage = device.rand_normal(mean, std, &s)?;
         let none = BackpropOp::none();
-        Ok(from_storage(storage, s, none, is_variable))
+        Ok(from_storage(storage, device, s, none, is_variable))
     }
 
     pub(crate) fn randn_f64_impl<S: Into<Shape>>(
@@ -314,7 +335,7 @@ impl Tensor {
         let s = s.into();
         let storage = device.rand_normal_f64(mean, std, &s, dtype)?;
         let none = BackpropOp::none();
-        Ok(from_storage(storage, s, none, is_variable))
+        Ok(from_storage(storage, device, s, none, is_variable))
     }
 
     pub fn randn_like(&self, mean: f64, stdev: f64) -> Result<Self> {
@@ -352,7 +373,7 @@ impl Tensor {
         }
         let storage = device.storage(array)?;
         let none = BackpropOp::none();
-        Ok(from_storage(storage, shape, none, is_variable))
+        Ok(from_storage(storage, device, shape, none, is_variable))
     }
 
     /// Creates a new tensor on the specified device using the content and shape of the input.
@@ -428,7 +449,7 @@ impl Tensor {
         }
         let storage = device.storage_owned(data)?;
         let none = BackpropOp::none();
-        Ok(from_storage(storage, shape, none, is_variable))
+        Ok(from_storage(storage, device, shape, none, is_variable))
     }
 
     /// Creates a new tensor initialized with values from the input vector. The number of elements
@@ -532,14 +553,15 @@ impl Tensor {
             }
             .bt())?
         }
-        let from_cpu_storage = |cpu_storage: &crate::CpuStorage| {
-            let data = S::cpu_storage_as_slice(cpu_storage)?;
+        let from_cpu_storage = |cpu_storage: CpuStorageRef<'_>| {
+            let data = cpu_storage.as_slice::<S>()?;
             Ok::<_, Error>(data[self.layout().start_offset()])
         };
         match &*self.storage() {
-            Storage::Cpu(cpu_storage) => from_cpu_storage(cpu_storage),
-            Storage::Cuda(storage) => from_cpu_storage(&storage.to_cpu_storage()?),
-            Storage::Metal(storage) => from_cpu_storage(&storage.to_cpu_storage()?),
+            Storage::Cpu(cpu_storage) => from_cpu_storage(cpu_storage.storage_ref()),
+            Storage::CpuMmap(cpu_storage) => from_cpu_storage(cpu_storage.storage_ref()),
+            Storage::Cuda(storage) => from_cpu_storage(storage.to_cpu_storage()?.storage_ref()),
+            Storage::Metal(storage) => from_cpu_storage(storage.to_cpu_storage()?.storage_ref()),
         }
     }
 
@@ -648,21 +670,76 @@ impl Tensor {
     pub fn affine(&self, mul: f64, add: f64) -> Result<Self> {
         let storage = self.storage().affine(self.layout(), mul, add)?;
         let op = BackpropOp::new1(self, |arg| Op::Affine { arg, mul, add });
-        Ok(from_storage(storage, self.shape(), op, false))
+        Ok(from_storage(
+            storage,
+            self.device(),
+            self.shape(),
+            op,
+            false,
+        ))
     }
 
     /// Applies the Exponential Linear Unit (ELU) function on each element of the input tensor.
     pub fn elu(&self, alpha: f64) -> Result<Self> {
         let storage = self.storage().elu(self.layout(), alpha)?;
         let op = BackpropOp::new1(self, |t| Op::Elu(t, alpha));
-        Ok(from_storage(storage, self.shape(), op, false))
+        Ok(from_storage(
+            storage,
+            self.device(),
+            self.shape(),
+            op,
+            false,
+        ))
+    }
+
+    /// Simulates the quantization of the tensor: the values are mapped to integers in
+    /// `[quant_min, quant_max]` using `round(x / scale) + zero_point`, then mapped back to floats.
+    ///
+    /// `scale` and `zero_point` are broadcasted to the shape of the tensor, so per-channel
+    /// quantization uses a shape such as `(channels, 1)`. The gradient uses the straight-through
+    /// estimator: it is passed through unchanged for the values in the quantization range and set
+    /// to zero for the clipped ones. No gradient flows to `scale` and `zero_point`.
+    ///
+    /// This is implemented as a cpu custom op, the values are copied to the host and back when
+    /// using another device.
+    pub fn fake_quantize(
+        &self,
+        scale: &Tensor,
+        zero_point: &Tensor,
+        quant_min: i64,
+        quant_max: i64,
+    ) -> Result<Self> {
+        if quant_min >= quant_max {
+            bail!("fake_quantize: empty quantization range {quant_min} {quant_max}")
+        }
+        let cpu = &Device::Cpu;
+        let op = crate::quantized::fake_quant::FakeQuantizeOp {
+            scale: scale.detach()?.to_device(cpu)?.broadcast_as(self.shape())?,
+            zero_point: zero_point
+                .detach()?
+                .to_device(cpu)?
+                .broadcast_as(self.shape())?,
+            quant_min,
+            quant_max,
+        };
+        if self.device().is_cpu() {
+            self.apply_op1(op)
+        } else {
+            self.to_device(cpu)?.apply_op1(op)?.to_device(self.device())
+        }
     }
 
     /// Raise the tensor to some float exponent `e`.
     pub fn powf(&self, e: f64) -> Result<Self> {
         let storage = self.storage().powf(self.layout(), e)?;
         let op = BackpropOp::new1(self, |t| Op::Powf(t, e));
-        Ok(from_storage(storage, self.shape(), op, false))
+        Ok(from_storage(
+            storage,
+            self.device(),
+            self.shape(),
+            op,
+            false,
+        ))
     }
 
     fn check_dim(&self, dim: usize, op: &'static str) -> Result<()> {
@@ -778,7 +855,7 @@ impl Tensor {
             }
             ReduceOp::ArgMin | ReduceOp::ArgMax => BackpropOp::none(),
         };
-        let res = from_storage(storage, dims, op, false);
+        let res = from_storage(storage, self.device(), dims, op, false);
         if keepdim {
             Ok(res)
         } else {
@@ -796,7 +873,7 @@ impl Tensor {
             dims[sum_dim] = 1
         }
         let op = BackpropOp::new1(self, |a| Op::Reduce(a, ReduceOp::Sum, dims.to_vec()));
-        let sum = from_storage(storage, dims, op, false);
+        let sum = from_storage(storage, self.device(), dims, op, false);
         if keepdim {
             Ok(sum)
         } else {
@@ -833,6 +910,186 @@ impl Tensor {
         }
     }
 
+    /// Returns a view on the elements of the input tensor with the given shape and strides. The
+    /// elements are taken as if the input tensor was contiguous, the view element at index
+    /// `(i_1, ..., i_k)` being the input element `offset + i_1 * stride_1 + ... + i_k * stride_k`
+    /// in row-major order. Elements can appear multiple times in the view, e.g. with a zero
+    /// stride.
+    ///
+    /// No data is copied if the input tensor is contiguous.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let tensor = Tensor::arange(0u32, 6, &Device::Cpu)?;
+    /// let tensor = tensor.as_strided((3, 2), vec![2, 1], 0)?;
+    /// assert_eq!(tensor.to_vec2::<u32>()?, &[[0, 1], [2, 3], [4, 5]]);
+    /// let tensor = Tensor::arange(0u32, 6, &Device::Cpu)?;
+    /// let tensor = tensor.as_strided((2, 3), vec![1, 2], 0)?;
+    /// assert_eq!(tensor.to_vec2::<u32>()?, &[[0, 2, 4], [1, 3, 5]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn as_strided<S: Into<Shape>>(
+        &self,
+        shape: S,
+        stride: Vec<usize>,
+        offset: usize,
+    ) -> Result<Self> {
+        let shape = shape.into();
+        if stride.len() != shape.rank() {
+            bail!(
+                "as_strided: stride {stride:?} does not match the shape {:?}",
+                shape.dims()
+            )
+        }
+        let max_index = shape
+            .dims()
+            .iter()
+            .zip(stride.iter())
+            .map(|(&d, &s)| d.saturating_sub(1) * s)
+            .sum::<usize>();
+        let out_of_bounds = if shape.elem_count() == 0 {
+            offset > self.elem_count()
+        } else {
+            offset + max_index >= self.elem_count()
+        };
+        if out_of_bounds {
+            bail!(
+                "as_strided: shape {:?} with stride {stride:?} and offset {offset} is out of bounds for {:?}",
+                shape.dims(),
+                self.shape()
+            )
+        }
+        if !self.is_contiguous() {
+            return self.contiguous()?.as_strided(shape, stride, offset);
+        }
+        let relative_layout = Layout::new(shape.clone(), stride.clone(), offset);
+        let layout = Layout::new(shape, stride, self.layout.start_offset() + offset);
+        Ok(self.strided_view(layout, relative_layout))
+    }
+
+    /// Returns a view containing all the slices of size `size` along dimension `dim`, separated by
+    /// `step` elements. The dimension `dim` is replaced by the number of slices and a new last
+    /// dimension of size `size` is added, this is similar to PyTorch's `Tensor.unfold`.
+    ///
+    /// No data is copied, slices overlap when `step < size`.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let tensor = Tensor::arange(0u32, 7, &Device::Cpu)?;
+    /// let tensor = tensor.unfold(0, 3, 2)?;
+    /// assert_eq!(tensor.to_vec2::<u32>()?, &[[0, 1, 2], [2, 3, 4], [4, 5, 6]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn unfold<D: Dim>(&self, dim: D, size: usize, step: usize) -> Result<Self> {
+        let dim = dim.to_index(self.shape(), "unfold")?;
+        let dim_len = self.dims()[dim];
+        if size == 0 || step == 0 || size > dim_len {
+            bail!(
+                "unfold: invalid size {size} or step {step} for dim {dim} of {:?}",
+                self.shape()
+            )
+        }
+        let n_slices = (dim_len - size) / step + 1;
+        let mut dims = self.dims().to_vec();
+        dims[dim] = n_slices;
+        dims.push(size);
+        let unfold_stride = |stride: &[usize]| {
+            let mut stride = stride.to_vec();
+            stride.push(stride[dim]);
+            stride[dim] *= step;
+            stride
+        };
+        let stride = unfold_stride(self.stride());
+        let relative_stride = unfold_stride(&self.shape().stride_contiguous());
+        let layout = Layout::new(dims.clone().into(), stride, self.layout.start_offset());
+        let relative_layout = Layout::new(dims.into(), relative_stride, 0);
+        Ok(self.strided_view(layout, relative_layout))
+    }
+
+    /// Returns a view containing all the windows of the given sizes along the dimensions `dims`,
+    /// with a step of 1. Each dimension in `dims` is replaced by the number of window positions
+    /// along it and the window dimensions are appended, this is similar to numpy's
+    /// `sliding_window_view`.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let tensor = Tensor::arange(0u32, 9, &Device::Cpu)?.reshape((3, 3))?;
+    /// let windows = tensor.sliding_window((0, 1), &[2, 2])?;
+    /// assert_eq!(windows.dims(), &[2, 2, 2, 2]);
+    /// assert_eq!(windows.get(1)?.get(0)?.to_vec2::<u32>()?, &[[3, 4], [6, 7]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn sliding_window<D: Dims>(&self, dims: D, sizes: &[usize]) -> Result<Self> {
+        let dims = dims.to_indexes(self.shape(), "sliding-window")?;
+        if dims.len() != sizes.len() {
+            bail!(
+                "sliding_window: got {} sizes for dims {dims:?}",
+                sizes.len()
+            )
+        }
+        let mut windows = self.clone();
+        for (&dim, &size) in dims.iter().zip(sizes.iter()) {
+            windows = windows.unfold(dim, size, 1)?;
+        }
+        Ok(windows)
+    }
+
+    // Creates a view sharing the storage of self, `relative_layout` is the layout that would be
+    // used if self was contiguous and is used for backprop.
+    fn strided_view(&self, layout: Layout, relative_layout: Layout) -> Self {
+        let op = BackpropOp::new1(self, |t| Op::AsStrided(t, relative_layout.clone()));
+        let tensor_ = Tensor_ {
+            id: TensorId::new(),
+            storage: self.storage.clone(),
+            layout,
+            op,
+            is_variable: false,
+            dtype: self.dtype,
+            device: self.device.clone(),
+        };
+        Tensor(Arc::new(tensor_))
+    }
+
+    /// Reverses the order of the elements along the given dimensions.
+    ///
+    /// Layouts only support non-negative strides so this is not a view: the data is copied with a
+    /// single gather over the flattened tensor whatever the number of flipped dimensions.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let tensor = Tensor::new(&[[0f32, 1., 2.], [3., 4., 5.]], &Device::Cpu)?;
+    /// assert_eq!(tensor.flip(1)?.to_vec2::<f32>()?, &[[2., 1., 0.], [5., 4., 3.]]);
+    /// assert_eq!(tensor.flip((0, 1))?.to_vec2::<f32>()?, &[[5., 4., 3.], [2., 1., 0.]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn flip<D: Dims>(&self, dims: D) -> Result<Self> {
+        let dims = dims.to_indexes(self.shape(), "flip")?;
+        if dims.is_empty() {
+            return Ok(self.clone());
+        }
+        let elem_count = self.elem_count();
+        if elem_count > u32::MAX as usize {
+            bail!("flip: too many elements {elem_count}")
+        }
+        let shape = self.dims();
+        let stride = self.shape().stride_contiguous();
+        let ids = (0..elem_count)
+            .map(|index| {
+                let mut src_index = 0;
+                for (d, (&len, &stride)) in shape.iter().zip(stride.iter()).enumerate() {
+                    let i = index / stride % len;
+                    let i = if dims.contains(&d) { len - 1 - i } else { i };
+                    src_index += i * stride;
+                }
+                src_index as u32
+            })
+            .collect::<Vec<_>>();
+        let ids = Tensor::from_vec(ids, elem_count, self.device())?;
+        self.flatten_all()?
+            .index_select(&ids, 0)?
+            .reshape(self.shape())
+    }
+
     /// Returns the sum of all elements in the input tensor. The sum is performed over all the
     /// input dimensions.
     ///
@@ -966,7 +1223,13 @@ impl Tensor {
             .storage()
             .cmp(op, &rhs.storage(), self.layout(), rhs.layout())?;
         let op = BackpropOp::new1(self, |a| Op::Cmp(a, op));
-        Ok(from_storage(storage, shape.dims(), op, false))
+        Ok(from_storage(
+            storage,
+            self.device(),
+            shape.dims(),
+            op,
+            false,
+        ))
     }
 
     /// Element-wise equality.
@@ -1018,7 +1281,13 @@ impl Tensor {
         let storage = self
             .storage()
             .upsample_nearest1d(self.layout(), target_size)?;
-        Ok(from_storage(storage, (n, c, target_size), op, false))
+        Ok(from_storage(
+            storage,
+            self.device(),
+            (n, c, target_size),
+            op,
+            false,
+        ))
     }
 
     /// Alias for `interpolate1d`.
@@ -1041,7 +1310,13 @@ impl Tensor {
         let storage = self
             .storage()
             .upsample_nearest2d(self.layout(), target_h, target_w)?;
-        Ok(from_storage(storage, (n, c, target_h, target_w), op, false))
+        Ok(from_storage(
+            storage,
+            self.device(),
+            (n, c, target_h, target_w),
+            op,
+            false,
+        ))
     }
 
     /// Alias for `interpolate2d`.
@@ -1084,7 +1359,13 @@ impl Tensor {
         let storage = self
             .storage()
             .avg_pool2d(self.layout(), kernel_size, stride)?;
-        Ok(from_storage(storage, (n, c, h_out, w_out), op, false))
+        Ok(from_storage(
+            storage,
+            self.device(),
+            (n, c, h_out, w_out),
+            op,
+            false,
+        ))
     }
 
     /// 2D max pooling over an input tensor with multiple channels.
@@ -1122,7 +1403,13 @@ impl Tensor {
         let storage = self
             .storage()
             .max_pool2d(self.layout(), kernel_size, stride)?;
-        Ok(from_storage(storage, (n, c, h_out, w_out), op, false))
+        Ok(from_storage(
+            storage,
+            self.device(),
+            (n, c, h_out, w_out),
+            op,
+            false,
+        ))
     }
 
     /// Returns the matrix-multiplication of the input tensor with the other provided tensor.
@@ -1165,14 +1452,16 @@ impl Tensor {
             .bt())?
         }
 
-        let storage = self.storage().matmul(
-            &rhs.storage(),
-            (batching, m, n, k),
-            self.layout(),
-            rhs.layout(),
-        )?;
+        let storage = self.device().enter(|| {
+            self.storage().matmul(
+                &rhs.storage(),
+                (batching, m, n, k),
+                self.layout(),
+                rhs.layout(),
+            )
+        })?;
         let op = BackpropOp::new2(self, rhs, Op::Matmul);
-        Ok(from_storage(storage, c_shape, op, false))
+        Ok(from_storage(storage, self.device(), c_shape, op, false))
     }
 
     /// Matrix-multiplication with broadcasting support.
@@ -1211,7 +1500,7 @@ impl Tensor {
             on_false.layout(),
         )?;
         let op = BackpropOp::new3(self, on_true, on_false, Op::WhereCond);
-        Ok(from_storage(storage, shape, op, false))
+        Ok(from_storage(storage, self.device(), shape, op, false))
     }
 
     /// Returns a tensor with the values from the `self` tensor at the index corresponding to the
@@ -1288,7 +1577,13 @@ impl Tensor {
         let op = BackpropOp::new3(self, indexes, source, |t1, t2, t3| {
             Op::ScatterAdd(t1, t2, t3, dim)
         });
-        Ok(from_storage(storage, self.shape(), op, false))
+        Ok(from_storage(
+            storage,
+            self.device(),
+            self.shape(),
+            op,
+            false,
+        ))
     }
 
     /// Embeds the values of the `src` tensor into the `self` tensor on the specified dimension.
@@ -1357,7 +1652,13 @@ impl Tensor {
         src.storage()
             .copy_strided_src(&mut storage, offset, src.layout())?;
         let op = BackpropOp::new2(self, src, |t1, t2| Op::SliceScatter0(t1, t2, start));
-        Ok(from_storage(storage, self.shape(), op, false))
+        Ok(from_storage(
+            storage,
+            self.device(),
+            self.shape(),
+            op,
+            false,
+        ))
     }
 
     /// Accumulate element from `source` at indexes `indexes` and add them to `self`.
@@ -1408,7 +1709,13 @@ impl Tensor {
         let op = BackpropOp::new3(self, indexes, source, |t1, t2, t3| {
             Op::IndexAdd(t1, t2, t3, dim)
         });
-        Ok(from_storage(storage, self.shape(), op, false))
+        Ok(from_storage(
+            storage,
+            self.device(),
+            self.shape(),
+            op,
+            false,
+        ))
     }
 
     /// Gather values across the target dimension.
@@ -1450,7 +1757,13 @@ impl Tensor {
             self.storage()
                 .gather(self.layout(), &indexes.storage(), indexes.layout(), dim)?;
         let op = BackpropOp::new2(self, indexes, |t1, t2| Op::Gather(t1, t2, dim));
-        Ok(from_storage(storage, indexes.shape(), op, false))
+        Ok(from_storage(
+            storage,
+            self.device(),
+            indexes.shape(),
+            op,
+            false,
+        ))
     }
 
     /// Select values for the input tensor at the target indexes across the specified dimension.
@@ -1480,7 +1793,7 @@ impl Tensor {
         let mut dims = self.dims().to_vec();
         dims[dim] = indexes_len;
         let op = BackpropOp::new2(self, indexes, |t1, t2| Op::IndexSelect(t1, t2, dim));
-        Ok(from_storage(storage, dims, op, false))
+        Ok(from_storage(storage, self.device(), dims, op, false))
     }
 
     /// Returns an iterator over position of the elements in the storage when ranging over the
@@ -1507,8 +1820,8 @@ impl Tensor {
             }
             .bt())?
         }
-        let from_cpu_storage = |cpu_storage: &crate::CpuStorage| {
-            let data = S::cpu_storage_as_slice(cpu_storage)?;
+        let from_cpu_storage = |cpu_storage: CpuStorageRef<'_>| {
+            let data = cpu_storage.as_slice::<S>()?;
             let data = match self.layout.contiguous_offsets() {
                 Some((o1, o2)) => data[o1..o2].to_vec(),
                 None => self.strided_index().map(|i| data[i]).collect(),
@@ -1516,17 +1829,18 @@ impl Tensor {
             Ok::<Vec<_>, Error>(data)
         };
         match &*self.storage() {
-            Storage::Cpu(storage) => from_cpu_storage(storage),
-            Storage::Cuda(storage) => from_cpu_storage(&storage.to_cpu_storage()?),
-            Storage::Metal(storage) => from_cpu_storage(&storage.to_cpu_storage()?),
+            Storage::Cpu(storage) => from_cpu_storage(storage.storage_ref()),
+            Storage::CpuMmap(storage) => from_cpu_storage(storage.storage_ref()),
+            Storage::Cuda(storage) => from_cpu_storage(storage.to_cpu_storage()?.storage_ref()),
+            Storage::Metal(storage) => from_cpu_storage(storage.to_cpu_storage()?.storage_ref()),
         }
     }
 
     /// Returns the data contained in a 2D tensor as a vector of vector of scalar values.
     pub fn to_vec2<S: crate::WithDType>(&self) -> Result<Vec<Vec<S>>> {
         let (dim1, dim2) = self.dims2()?;
-        let from_cpu_storage = |cpu_storage: &crate::CpuStorage| {
-            let data = S::cpu_storage_as_slice(cpu_storage)?;
+        let from_cpu_storage = |cpu_storage: CpuStorageRef<'_>| {
+            let data = cpu_storage.as_slice::<S>()?;
             let mut rows = vec![];
             match self.layout.contiguous_offsets() {
                 Some((o1, o2)) => {
@@ -1547,17 +1861,18 @@ impl Tensor {
             Ok(rows)
         };
         match &*self.storage() {
-            Storage::Cpu(storage) => from_cpu_storage(storage),
-            Storage::Cuda(storage) => from_cpu_storage(&storage.to_cpu_storage()?),
-            Storage::Metal(storage) => from_cpu_storage(&storage.to_cpu_storage()?),
+            Storage::Cpu(storage) => from_cpu_storage(storage.storage_ref()),
+            Storage::CpuMmap(storage) => from_cpu_storage(storage.storage_ref()),
+            Storage::Cuda(storage) => from_cpu_storage(storage.to_cpu_storage()?.storage_ref()),
+            Storage::Metal(storage) => from_cpu_storage(storage.to_cpu_storage()?.storage_ref()),
         }
     }
 
     /// Returns the data contained in a 3D tensor.
     pub fn to_vec3<S: crate::WithDType>(&self) -> Result<Vec<Vec<Vec<S>>>> {
         let (dim1, dim2, dim3) = self.dims3()?;
-        let from_cpu_storage = |cpu_storage: &crate::CpuStorage| {
-            let data = S::cpu_storage_as_slice(cpu_storage)?;
+        let from_cpu_storage = |cpu_storage: CpuStorageRef<'_>| {
+            let data = cpu_storage.as_slice::<S>()?;
             let mut top_rows = vec![];
             match self.layout.contiguous_offsets() {
                 Some((o1, o2)) => {
@@ -1588,9 +1903,10 @@ impl Tensor {
             Ok(top_rows)
         };
         match &*self.storage() {
-            Storage::Cpu(storage) => from_cpu_storage(storage),
-            Storage::Cuda(storage) => from_cpu_storage(&storage.to_cpu_storage()?),
-            Storage::Metal(storage) => from_cpu_storage(&storage.to_cpu_storage()?),
+            Storage::Cpu(storage) => from_cpu_storage(storage.storage_ref()),
+            Storage::CpuMmap(storage) => from_cpu_storage(storage.storage_ref()),
+            Storage::Cuda(storage) => from_cpu_storage(storage.to_cpu_storage()?.storage_ref()),
+            Storage::Metal(storage) => from_cpu_storage(storage.to_cpu_storage()?.storage_ref()),
         }
     }
 
@@ -1903,6 +2219,18 @@ impl Tensor {
     pub fn to_device(&self, device: &Device) -> Result<Tensor> {
         if self.device().same_device(device) {
             Ok(self.clone())
+        } else if self.device().is_cpu() && device.is_cpu() {
+            // Moving between the cpu devices only changes the thread pool, the data is shared.
+            let tensor_ = Tensor_ {
+                id: TensorId::new(),
+                storage: self.storage.clone(),
+                layout: self.layout.clone(),
+                op: BackpropOp::new1(self, Op::ToDevice),
+                is_variable: false,
+                dtype: self.dtype,
+                device: device.clone(),
+            };
+            Ok(Tensor(Arc::new(tensor_)))
         } else {
             let storage = match (&*self.storage(), device) {
                 (Storage::Cpu(storage), Device::Cuda(cuda)) => {
@@ -1911,15 +2239,24 @@ impl Tensor {
                 (Storage::Cpu(storage), Device::Metal(metal)) => {
                     Storage::Metal(metal.storage_from_cpu_storage(storage)?)
                 }
-                (Storage::Cuda(storage), Device::Cpu) => Storage::Cpu(storage.to_cpu_storage()?),
-                (Storage::Metal(storage), Device::Cpu) => Storage::Cpu(storage.to_cpu_storage()?),
+                (Storage::CpuMmap(storage), Device::Cuda(cuda)) => {
+                    Storage::Cuda(cuda.storage_from_cpu_storage(&storage.to_cpu_storage())?)
+                }
+                (Storage::CpuMmap(storage), Device::Metal(metal)) => {
+                    Storage::Metal(metal.storage_from_cpu_storage(&storage.to_cpu_storage())?)
+                }
+                (Storage::Cuda(storage), Device::Cpu | Device::CpuPool(_)) => {
+                    Storage::Cpu(storage.to_cpu_storage()?)
+                }
+                (Storage::Metal(storage), Device::Cpu | Device::CpuPool(_)) => {
+                    Storage::Cpu(storage.to_cpu_storage()?)
+                }
                 (Storage::Cuda(storage), Device::Cuda(cuda)) => {
                     // TODO: Avoid passing through the cpu storage here, especially if the gpu ids
                     // are the same.
                     let cpu_storage = storage.to_cpu_storage()?;
                     Storage::Cuda(cuda.storage_from_cpu_storage(&cpu_storage)?)
                 }
-                (Storage::Cpu(storage), Device::Cpu) => Storage::Cpu(storage.clone()),
                 _ => {
                     bail!("not implemented yet")
                 }
@@ -1967,7 +2304,17 @@ impl Tensor {
         Ok(Tensor(Arc::new(tensor_)))
     }
 
-    /// An alias for broadcast_as.
+    /// Expands the dimensions of size 1 to the target shape, this is an alias for
+    /// [`Tensor::broadcast_as`]. No data is copied, the expanded dimensions get a stride of 0.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let tensor = Tensor::new(&[[0f32], [1.]], &Device::Cpu)?;
+    /// let tensor = tensor.expand((2, 2, 3))?;
+    /// assert_eq!(tensor.stride(), &[0, 1, 0]);
+    /// assert_eq!(tensor.to_vec3::<f32>()?[1], &[[0., 0., 0.], [1., 1., 1.]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
     pub fn expand<S: Into<Shape>>(&self, shape: S) -> Result<Self> {
         self.broadcast_as(shape)
     }
@@ -1989,7 +2336,13 @@ impl Tensor {
             let shape = self.shape();
             let storage = self.storage().to_dtype(self.layout(), dtype)?;
             let op = BackpropOp::new1(self, Op::ToDType);
-            Ok(from_storage(storage, shape.clone(), op, false))
+            Ok(from_storage(
+                storage,
+                self.device(),
+                shape.clone(),
+                op,
+                false,
+            ))
         }
     }
 
@@ -2004,7 +2357,13 @@ impl Tensor {
             self.storage()
                 .copy_strided_src(&mut storage, 0, self.layout())?;
             let op = BackpropOp::new1(self, Op::Copy);
-            Ok(from_storage(storage, shape.clone(), op, false))
+            Ok(from_storage(
+                storage,
+                self.device(),
+                shape.clone(),
+                op,
+                false,
+            ))
         }
     }
 
@@ -2015,7 +2374,13 @@ impl Tensor {
         let mut storage = self.device().zeros(&shape, self.dtype())?;
         self.storage()
             .copy_strided_src(&mut storage, 0, self.layout())?;
-        Ok(from_storage(storage, shape, BackpropOp::none(), true))
+        Ok(from_storage(
+            storage,
+            self.device(),
+            shape,
+            BackpropOp::none(),
+            true,
+        ))
     }
 
     /// Reshape returns a tensor with the target shape provided that the number of elements of the
@@ -2068,7 +2433,7 @@ impl Tensor {
             let mut storage = self.device().zeros(&shape, self.dtype())?;
             self.storage()
                 .copy_strided_src(&mut storage, 0, self.layout())?;
-            Ok(from_storage(storage, shape, op, false))
+            Ok(from_storage(storage, self.device(), shape, op, false))
         }
     }
 
@@ -2291,7 +2656,7 @@ impl Tensor {
             arg.storage()
                 .copy_strided_src(&mut storage, offset, arg.layout())?;
         }
-        Ok(from_storage(storage, shape, op, false))
+        Ok(from_storage(storage, device, shape, op, false))
     }
 
     /// Pad the input tensor using 0s along dimension `dim`. This adds `left` elements before the
@@ -2381,10 +2746,23 @@ impl Tensor {
     pub(crate) fn storage_mut_and_layout(
         &self,
     ) -> (std::sync::RwLockWriteGuard<'_, Storage>, &Layout) {
-        let storage = self.storage.write().unwrap();
+        let mut storage = self.storage.write().unwrap();
+        // Memory mapped storages are read-only, they get replaced with a copy on the first write.
+        if let Storage::CpuMmap(s) = &*storage {
+            *storage = Storage::Cpu(s.to_cpu_storage());
+        }
         (storage, &self.layout)
     }
 
+    // Custom ops operate on `CpuStorage`, a memory mapped storage is replaced with an owned copy
+    // once so that the data does not get copied again on each call.
+    fn custom_op_storage(&self) -> std::sync::RwLockReadGuard<'_, Storage> {
+        if matches!(&*self.storage(), Storage::CpuMmap(_)) {
+            drop(self.storage_mut_and_layout());
+        }
+        self.storage()
+    }
+
     /// The storage used by this tensor, together with the layout to use to access it safely.
     pub fn storage_and_layout(&self) -> (std::sync::RwLockReadGuard<'_, Storage>, &Layout) {
         let storage = self.storage.read().unwrap();
@@ -2399,38 +2777,66 @@ impl Tensor {
 
     /// Applies a unary custom op without backward support
     pub fn apply_op1_no_bwd<C: CustomOp1>(&self, c: &C) -> Result<Self> {
-        let (storage, shape) = self.storage().apply_op1(self.layout(), c)?;
-        Ok(from_storage(storage, shape, BackpropOp::none(), false))
+        let (storage, shape) = self
+            .device()
+            .enter(|| self.custom_op_storage().apply_op1(self.layout(), c))?;
+        Ok(from_storage(
+            storage,
+            self.device(),
+            shape,
+            BackpropOp::none(),
+            false,
+        ))
     }
 
     /// Applies a binary custom op without backward support
     pub fn apply_op2_no_bwd<C: CustomOp2>(&self, rhs: &Self, c: &C) -> Result<Self> {
-        let (storage, shape) =
-            self.storage()
-                .apply_op2(self.layout(), &rhs.storage(), rhs.layout(), c)?;
-        Ok(from_storage(storage, shape, BackpropOp::none(), false))
+        let (storage, shape) = self.device().enter(|| {
+            self.custom_op_storage().apply_op2(
+                self.layout(),
+                &rhs.custom_op_storage(),
+                rhs.layout(),
+                c,
+            )
+        })?;
+        Ok(from_storage(
+            storage,
+            self.device(),
+            shape,
+            BackpropOp::none(),
+            false,
+        ))
     }
 
     /// Applies a ternary custom op without backward support
     pub fn apply_op3_no_bwd<C: CustomOp3>(&self, t2: &Self, t3: &Self, c: &C) -> Result<Self> {
-        let (storage, shape) = self.storage().apply_op3(
-            self.layout(),
-            &t2.storage(),
-            t2.layout(),
-            &t3.storage(),
-            t3.layout(),
-            c,
-        )?;
-        Ok(from_storage(storage, shape, BackpropOp::none(), false))
+        let (storage, shape) = self.device().enter(|| {
+            self.custom_op_storage().apply_op3(
+                self.layout(),
+                &t2.custom_op_storage(),
+                t2.layout(),
+                &t3.custom_op_storage(),
+                t3.layout(),
+                c,
+            )
+        })?;
+        Ok(from_storage(
+            storage,
+            self.device(),
+            shape,
+            BackpropOp::none(),
+            false,
+        ))
     }
 
     /// Applies a unary custom op.
     pub fn apply_op1_arc(&self, c: Arc<Box<dyn CustomOp1 + Send + Sync>>) -> Result<Self> {
-        let (storage, shape) = self
-            .storage()
-            .apply_op1(self.layout(), c.as_ref().as_ref())?;
+        let (storage, shape) = self.device().enter(|| {
+            self.custom_op_storage()
+                .apply_op1(self.layout(), c.as_ref().as_ref())
+        })?;
         let op = BackpropOp::new1(self, |s| Op::CustomOp1(s, c.clone()));
-        Ok(from_storage(storage, shape, op, false))
+        Ok(from_storage(storage, self.device(), shape, op, false))
     }
 
     pub fn apply_op1<C: 'static + CustomOp1 + Send + Sync>(&self, c: C) -> Result<Self> {
@@ -2443,14 +2849,16 @@ impl Tensor {
         rhs: &Self,
         c: Arc<Box<dyn CustomOp2 + Send + Sync>>,
     ) -> Result<Self> {
-        let (storage, shape) = self.storage().apply_op2(
-            self.layout(),
-            &rhs.storage(),
-            rhs.layout(),
-            c.as_ref().as_ref(),
-        )?;
+        let (storage, shape) = self.device().enter(|| {
+            self.custom_op_storage().apply_op2(
+                self.layout(),
+                &rhs.custom_op_storage(),
+                rhs.layout(),
+                c.as_ref().as_ref(),
+            )
+        })?;
         let op = BackpropOp::new2(self, rhs, |t1, t2| Op::CustomOp2(t1, t2, c.clone()));
-        Ok(from_storage(storage, shape, op, false))
+        Ok(from_storage(storage, self.device(), shape, op, false))
     }
 
     pub fn apply_op2<C: 'static + CustomOp2 + Send + Sync>(&self, r: &Self, c: C) -> Result<Self> {
@@ -2464,18 +2872,20 @@ impl Tensor {
         t3: &Self,
         c: Arc<Box<dyn CustomOp3 + Send + Sync>>,
     ) -> Result<Self> {
-        let (storage, shape) = self.storage().apply_op3(
-            self.layout(),
-            &t2.storage(),
-            t2.layout(),
-            &t3.storage(),
-            t3.layout(),
-            c.as_ref().as_ref(),
-        )?;
+        let (storage, shape) = self.device().enter(|| {
+            self.custom_op_storage().apply_op3(
+                self.layout(),
+                &t2.custom_op_storage(),
+                t2.layout(),
+                &t3.custom_op_storage(),
+                t3.layout(),
+                c.as_ref().as_ref(),
+            )
+        })?;
         let op = BackpropOp::new3(self, t2, t3, |t1, t2, t3| {
             Op::CustomOp3(t1, t2, t3, c.clone())
         });
-        Ok(from_storage(storage, shape, op, false))
+        Ok(from_storage(storage, self.device(), shape, op, false))
     }
 
     pub fn apply_op3<C: 'static + CustomOp3 + Send + Sync>(
@@ -2606,6 +3016,81 @@ impl Tensor {
         mask.where_cond(/* on_true= */ &src, /* on_false= */ self)
     }
 
+    /// Set the values on `self` using values from `src`. The copy starts at the specified
+    /// `offset` for the target dimension `dim` on `self`.
+    /// `self` and `src` must have the same shape except on dimension `dim` where the `self` size
+    /// has to be greater than or equal to `offset` plus the `src` size.
+    ///
+    /// Note that this modifies `self` in place and as such is not compatible with
+    /// back-propagation.
+    pub fn slice_set<D: Dim>(&self, src: &Self, dim: D, offset: usize) -> Result<()> {
+        let dim = dim.to_index(self.shape(), "slice-set")?;
+        if !self.is_contiguous() {
+            Err(Error::RequiresContiguous { op: "slice-set" }.bt())?
+        }
+        if self.same_storage(src) {
+            crate::bail!("cannot use slice_set when self and src share their storage")
+        }
+        if self.dtype() != src.dtype() {
+            Err(Error::DTypeMismatchBinaryOp {
+                lhs: self.dtype(),
+                rhs: src.dtype(),
+                op: "slice-set",
+            }
+            .bt())?
+        }
+        if self.device().location() != src.device().location() {
+            Err(Error::DeviceMismatchBinaryOp {
+                lhs: self.device().location(),
+                rhs: src.device().location(),
+                op: "slice-set",
+            }
+            .bt())?
+        }
+        if self.rank() != src.rank() {
+            Err(Error::UnexpectedNumberOfDims {
+                expected: self.rank(),
+                got: src.rank(),
+                shape: self.shape().clone(),
+            }
+            .bt())?
+        }
+        for (dim_idx, (v1, v2)) in self.dims().iter().zip(src.dims().iter()).enumerate() {
+            if dim_idx == dim && *v2 + offset > *v1 {
+                crate::bail!("shape mismatch on target dim, dst: {v1}, src: {v2} + {offset}")
+            }
+            if dim_idx != dim && v1 != v2 {
+                crate::bail!("shape mismatch on dim {dim_idx}, {v1} <> {v2}")
+            }
+        }
+        if src.elem_count() == 0 {
+            return Ok(());
+        }
+        // Each block spans the dimensions after `dim` and is contiguous on `self`, there is one
+        // such block per element of the leading dimensions.
+        let block_size: usize = src.dims().iter().skip(1 + dim).product();
+        let dst_row_len = self.dims()[dim] * block_size;
+        let lead_dims = &src.dims()[..dim];
+        let n_rows: usize = lead_dims.iter().product();
+        let src_l = src.layout();
+        let row_shape = Shape::from(&src.dims()[dim..]);
+        let row_stride = src_l.stride()[dim..].to_vec();
+        let (mut dst_storage, dst_l) = self.storage_mut_and_layout();
+        let src_storage = src.storage();
+        for row_idx in 0..n_rows {
+            let mut src_offset = src_l.start_offset();
+            let mut rem = row_idx;
+            for (d, &s) in lead_dims.iter().zip(src_l.stride().iter()).rev() {
+                src_offset += (rem % d) * s;
+                rem /= d;
+            }
+            let row_l = Layout::new(row_shape.clone(), row_stride.clone(), src_offset);
+            let dst_offset = dst_l.start_offset() + row_idx * dst_row_len + offset * block_size;
+            src_storage.copy_strided_src(&mut dst_storage, dst_offset, &row_l)?;
+        }
+        Ok(())
+    }
+
     /// Returns log(sum(exp(tensor), dim)).
     pub fn log_sum_exp<D: Dims>(&self, sum_dims: D) -> Result<Self> {
         let exp = self.exp()?;
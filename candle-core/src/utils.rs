@@ -1,6 +1,10 @@
 use std::str::FromStr;
 
 pub fn get_num_threads() -> usize {
+    // Use the size of the pool the cpu kernels run on, e.g. the pool of a cpu device.
+    if let Some(num_threads) = crate::cpu::thread_pool::current_num_threads() {
+        return num_threads;
+    }
     // Respond to the same environment variable as rayon.
     match std::env::var("RAYON_NUM_THREADS")
         .ok()
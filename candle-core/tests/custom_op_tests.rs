@@ -112,3 +112,34 @@ fn custom_op1_with_backward() -> Result<()> {
 
     Ok(())
 }
+
+/// Returns the number of threads available to the cpu kernels.
+struct NumThreads;
+
+impl CustomOp1 for NumThreads {
+    fn name(&self) -> &'static str {
+        "num-threads"
+    }
+
+    fn cpu_fwd(&self, _: &CpuStorage, _: &Layout) -> Result<(CpuStorage, Shape)> {
+        let in_pool = candle_core::cpu::thread_pool::install(rayon::current_num_threads);
+        let num_threads = candle_core::utils::get_num_threads();
+        let storage = CpuStorage::U32(vec![in_pool as u32, num_threads as u32]);
+        Ok((storage, Shape::from(2)))
+    }
+}
+
+#[test]
+fn custom_op_cpu_pool() -> Result<()> {
+    let pool = Device::new_cpu_pool(3)?;
+    let t = Tensor::zeros(1, DType::F32, &pool)?;
+    let num_threads = t.apply_op1_no_bwd(&NumThreads)?;
+    assert_eq!(num_threads.to_vec1::<u32>()?, [3, 3]);
+    assert!(num_threads.device().same_device(&pool));
+
+    // Outside of the pool, the kernels use the global rayon pool.
+    let t = Tensor::zeros(1, DType::F32, &Device::Cpu)?;
+    let num_threads = t.apply_op1_no_bwd(&NumThreads)?.to_vec1::<u32>()?;
+    assert_eq!(num_threads[0] as usize, rayon::current_num_threads());
+    Ok(())
+}
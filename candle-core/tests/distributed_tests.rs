@@ -0,0 +1,160 @@
+use candle_core::distributed::{
+    Communicator, InProcessCommunicator, ReduceOp, SharedMemoryCommunicator, TcpCommunicator,
+};
+use candle_core::{DType, Device, Result, Tensor};
+
+// Runs the same collectives on all the ranks and checks the results.
+fn run_collectives(comm: &dyn Communicator) -> Result<()> {
+    let dev = &Device::Cpu;
+    let rank = comm.rank() as f32;
+    let world_size = comm.world_size();
+    let xs = Tensor::new(&[[rank, 1.0], [2.0 * rank, -rank]], dev)?;
+
+    let sum = comm.all_reduce(&xs, ReduceOp::Sum)?;
+    let n = (world_size * (world_size - 1) / 2) as f32;
+    assert_eq!(
+        sum.to_vec2::<f32>()?,
+        [[n, world_size as f32], [2.0 * n, -n]]
+    );
+    let max = comm.all_reduce(&xs, ReduceOp::Max)?;
+    let last = (world_size - 1) as f32;
+    assert_eq!(max.to_vec2::<f32>()?, [[last, 1.0], [2.0 * last, 0.0]]);
+    let min = comm.all_reduce(&xs, ReduceOp::Min)?;
+    assert_eq!(min.to_vec2::<f32>()?, [[0.0, 1.0], [0.0, -last]]);
+
+    let ys = Tensor::full(comm.rank() as u32, (1, 2), dev)?;
+    let gathered = comm.all_gather(&ys, 0)?;
+    assert_eq!(gathered.dims(), [world_size, 2]);
+    let expected = (0..world_size as u32)
+        .map(|i| vec![i, i])
+        .collect::<Vec<_>>();
+    assert_eq!(gathered.to_vec2::<u32>()?, expected);
+
+    let zs = if comm.rank() == 1 {
+        Tensor::new(&[3f64, 1., 4.], dev)?
+    } else {
+        Tensor::zeros(1, DType::F64, dev)?
+    };
+    let zs = comm.broadcast(&zs, 1)?;
+    assert_eq!(zs.to_vec1::<f64>()?, [3., 1., 4.]);
+
+    let ws = Tensor::arange(0u32, 2 * world_size as u32, dev)?;
+    let ws = comm.reduce_scatter(&ws, 0, ReduceOp::Sum)?;
+    let r = comm.rank() as u32;
+    let w = world_size as u32;
+    assert_eq!(ws.to_vec1::<u32>()?, [2 * r * w, (2 * r + 1) * w]);
+
+    comm.barrier()?;
+    Ok(())
+}
+
+fn run_threads<C: Communicator + 'static>(comms: Vec<C>) -> Result<()> {
+    let handles = comms
+        .into_iter()
+        .map(|comm| std::thread::spawn(move || run_collectives(&comm)))
+        .collect::<Vec<_>>();
+    for handle in handles {
+        handle.join().unwrap()?
+    }
+    Ok(())
+}
+
+#[test]
+fn in_process_collectives() -> Result<()> {
+    let comms = InProcessCommunicator::new_group(3)?;
+    run_threads(comms)?;
+    let comms = InProcessCommunicator::new_group(1)?;
+    let xs = Tensor::new(&[1f32, 2.], &Device::Cpu)?;
+    assert_eq!(
+        comms[0].all_reduce(&xs, ReduceOp::Sum)?.to_vec1::<f32>()?,
+        [1., 2.]
+    );
+    Ok(())
+}
+
+#[test]
+fn shm_collectives() -> Result<()> {
+    let path = std::env::temp_dir().join(format!("candle-shm-{}", std::process::id()));
+    let handles = (0..4)
+        .map(|rank| {
+            let path = path.clone();
+            std::thread::spawn(move || -> Result<()> {
+                let comm = SharedMemoryCommunicator::new(&path, rank, 4, 1024)?;
+                // Run a few rounds to exercise the reuse of the buffers.
+                for _ in 0..3 {
+                    run_collectives(&comm)?
+                }
+                let too_large = vec![0u8; 2048];
+                assert!(comm.all_gather_bytes(&too_large).is_err());
+                Ok(())
+            })
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        handle.join().unwrap()?
+    }
+    assert!(!path.exists());
+    Ok(())
+}
+
+#[test]
+fn tcp_collectives() -> Result<()> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let mut handles = (1..3)
+        .map(|rank| {
+            std::thread::spawn(move || run_collectives(&TcpCommunicator::new(addr, rank, 3)?))
+        })
+        .collect::<Vec<_>>();
+    handles.push(std::thread::spawn(move || {
+        run_collectives(&TcpCommunicator::root(listener, 3)?)
+    }));
+    for handle in handles {
+        handle.join().unwrap()?
+    }
+    Ok(())
+}
+
+// The multi-process test re-runs the current test binary, only running the worker test below
+// with the rank and world size passed through environment variables.
+const WORKER_ENV: &str = "CANDLE_DISTRIBUTED_TEST_WORKER";
+
+#[test]
+#[ignore = "only run as a worker of multi_process_collectives"]
+fn multi_process_worker() -> Result<()> {
+    let var = |name: &str| std::env::var(format!("{WORKER_ENV}_{name}")).unwrap();
+    let rank: usize = var("RANK").parse().unwrap();
+    let world_size: usize = var("WORLD_SIZE").parse().unwrap();
+    let shm = SharedMemoryCommunicator::new(var("SHM_PATH"), rank, world_size, 1024)?;
+    run_collectives(&shm)?;
+    let tcp = TcpCommunicator::new(var("TCP_ADDR"), rank, world_size)?;
+    run_collectives(&tcp)
+}
+
+#[test]
+fn multi_process_collectives() -> Result<()> {
+    let world_size = 3;
+    let exe = std::env::current_exe()?;
+    let shm_path = std::env::temp_dir().join(format!("candle-shm-mp-{}", std::process::id()));
+    // Reserve a port for rank 0, the listener is dropped before rank 0 binds to it.
+    let tcp_addr = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+    let children = (0..world_size)
+        .map(|rank| {
+            std::process::Command::new(&exe)
+                .args(["--ignored", "--exact", "multi_process_worker"])
+                .env(format!("{WORKER_ENV}_RANK"), rank.to_string())
+                .env(format!("{WORKER_ENV}_WORLD_SIZE"), world_size.to_string())
+                .env(format!("{WORKER_ENV}_SHM_PATH"), &shm_path)
+                .env(format!("{WORKER_ENV}_TCP_ADDR"), tcp_addr.to_string())
+                .stdout(std::process::Stdio::piped())
+                .spawn()
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+    for (rank, child) in children.into_iter().enumerate() {
+        let output = child.wait_with_output()?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "rank {rank} failed:\n{stdout}");
+        assert!(stdout.contains("1 passed"), "rank {rank}:\n{stdout}");
+    }
+    Ok(())
+}
@@ -475,6 +475,114 @@ fn binary_grad(device: &Device) -> Result<()> {
     Ok(())
 }
 
+fn fake_quantize_grad(device: &Device) -> Result<()> {
+    let x = Var::new(&[1.2f32, -5.1, 2.0, 4.6, -0.2], device)?;
+    let x = x.as_tensor();
+    let scale = Tensor::new(0.5f32, device)?;
+    let zero_point = Tensor::new(0f32, device)?;
+    let y = x.fake_quantize(&scale, &zero_point, -8, 7)?;
+    let grads = (&y * 3.)?.backward()?;
+    let grad_x = grads.get(x).context("no grad for x")?;
+    assert_eq!(y.to_vec1::<f32>()?, [1.0, -4.0, 2.0, 3.5, 0.0]);
+    // Straight-through estimator, the gradient is zero for the clipped values.
+    assert_eq!(grad_x.to_vec1::<f32>()?, [3., 0., 3., 0., 3.]);
+
+    // Per-channel parameters.
+    let x = Var::new(&[[1.2f32, -0.1, 2.0], [0.4, -1.6, 3.0]], device)?;
+    let x = x.as_tensor();
+    let scale = Tensor::new(&[[0.5f32], [1.0]], device)?;
+    let zero_point = Tensor::new(&[[0f32], [2.0]], device)?;
+    let y = x.fake_quantize(&scale, &zero_point, 0, 3)?;
+    let grads = y.sqr()?.sum_all()?.backward()?;
+    let grad_x = grads.get(x).context("no grad for x")?;
+    assert_eq!(y.to_vec2::<f32>()?, [[1.0, 0.0, 1.5], [0.0, -2.0, 1.0]]);
+    assert_eq!(
+        grad_x.to_vec2::<f32>()?,
+        [[2.0, 0.0, 0.0], [0.0, -4.0, 0.0]]
+    );
+    assert!(x.fake_quantize(&scale, &zero_point, 3, 3).is_err());
+    Ok(())
+}
+
+fn strided_view_grad(device: &Device) -> Result<()> {
+    let x = Var::new(&[[1f32, 2., 3.], [4., 5., 6.]], device)?;
+    let x = x.as_tensor();
+    // Overlapping windows, the middle column appears in both of them.
+    let y = x.unfold(1, 2, 1)?;
+    let grads = (y.sqr()? * 0.5)?.sum_all()?.backward()?;
+    let grad_x = grads.get(x).context("no grad for x")?;
+    assert_eq!(grad_x.to_vec2::<f32>()?, [[1., 4., 3.], [4., 10., 6.]]);
+
+    // Same with a non-contiguous input.
+    let grads = x.t()?.unfold(0, 2, 1)?.sum_all()?.backward()?;
+    let grad_x = grads.get(x).context("no grad for x")?;
+    assert_eq!(grad_x.to_vec2::<f32>()?, [[1., 2., 1.], [1., 2., 1.]]);
+
+    let y = x.as_strided((2, 2), vec![0, 2], 1)?;
+    let grads = y.sum_all()?.backward()?;
+    let grad_x = grads.get(x).context("no grad for x")?;
+    assert_eq!(grad_x.to_vec2::<f32>()?, [[0., 2., 0.], [2., 0., 0.]]);
+
+    let grads = (x.flip(1)? * x)?.sum_all()?.backward()?;
+    let grad_x = grads.get(x).context("no grad for x")?;
+    assert_eq!(grad_x.to_vec2::<f32>()?, [[6., 4., 2.], [12., 10., 8.]]);
+    Ok(())
+}
+
+fn fancy_indexing_grad(device: &Device) -> Result<()> {
+    let x = Var::new(&[[1f32, 2., 3.], [4., 5., 6.]], device)?;
+    let x = x.as_tensor();
+    let mask = Tensor::new(&[1u8, 0, 1], device)?;
+    let grads = x.masked_select(&mask)?.sqr()?.sum_all()?.backward()?;
+    let grad_x = grads.get(x).context("no grad for x")?;
+    assert_eq!(grad_x.to_vec2::<f32>()?, [[2., 0., 6.], [8., 0., 12.]]);
+
+    let grads = x.masked_fill(&mask, 0.)?.sum_all()?.backward()?;
+    let grad_x = grads.get(x).context("no grad for x")?;
+    assert_eq!(grad_x.to_vec2::<f32>()?, [[0., 1., 0.], [0., 1., 0.]]);
+
+    let rows = Tensor::new(&[0u32, 0, 1], device)?;
+    let cols = Tensor::new(&[2u32, 2, 0], device)?;
+    let grads = x.index_tensors(&[&rows, &cols])?.sum_all()?.backward()?;
+    let grad_x = grads.get(x).context("no grad for x")?;
+    assert_eq!(grad_x.to_vec2::<f32>()?, [[0., 0., 2.], [1., 0., 0.]]);
+
+    let values = Var::new(&[1f32, 2., 3.], device)?;
+    let values = values.as_tensor();
+    let y = x.index_put(&[&rows, &cols], values, false)?;
+    let grads = (y * 2.)?.sum_all()?.backward()?;
+    let grad_x = grads.get(x).context("no grad for x")?;
+    assert_eq!(grad_x.to_vec2::<f32>()?, [[2., 2., 0.], [0., 2., 2.]]);
+    // Only the last value written at a given position gets a gradient.
+    let grad_values = grads.get(values).context("no grad for values")?;
+    assert_eq!(grad_values.to_vec1::<f32>()?, [0., 2., 2.]);
+
+    let ids = Tensor::new(&[[1u32], [2]], device)?;
+    let grads = x.take_along_dim(&ids, 1)?.sum_all()?.backward()?;
+    let grad_x = grads.get(x).context("no grad for x")?;
+    assert_eq!(grad_x.to_vec2::<f32>()?, [[0., 1., 0.], [0., 0., 1.]]);
+    Ok(())
+}
+
+fn einsum_grad(device: &Device) -> Result<()> {
+    let a = Var::new(&[[1f32, 2., 3.], [4., 5., 6.]], device)?;
+    let b = Var::new(&[[1f32, 0.], [0., 1.], [1., 1.]], device)?;
+    let (a, b) = (a.as_tensor(), b.as_tensor());
+    let c = Tensor::einsum("ij,jk->ik", &[a, b])?;
+    let grads = c.sum_all()?.backward()?;
+    let grad_a = grads.get(a).context("no grad for a")?;
+    let grad_b = grads.get(b).context("no grad for b")?;
+    assert_eq!(grad_a.to_vec2::<f32>()?, [[1., 1., 2.], [1., 1., 2.]]);
+    assert_eq!(grad_b.to_vec2::<f32>()?, [[5., 5.], [7., 7.], [9., 9.]]);
+
+    let x = Var::new(&[[1f32, 2.], [3., 4.]], device)?;
+    let x = x.as_tensor();
+    let grads = Tensor::einsum("ii", &[x])?.backward()?;
+    let grad_x = grads.get(x).context("no grad for x")?;
+    assert_eq!(grad_x.to_vec2::<f32>()?, [[1., 0.], [0., 1.]]);
+    Ok(())
+}
+
 test_device!(
     simple_grad,
     simple_grad_cpu,
@@ -501,3 +609,27 @@ test_device!(
     binary_grad_gpu,
     binary_grad_metal
 );
+test_device!(
+    fake_quantize_grad,
+    fake_quantize_grad_cpu,
+    fake_quantize_grad_gpu,
+    fake_quantize_grad_metal
+);
+test_device!(
+    strided_view_grad,
+    strided_view_grad_cpu,
+    strided_view_grad_gpu,
+    strided_view_grad_metal
+);
+test_device!(
+    fancy_indexing_grad,
+    fancy_indexing_grad_cpu,
+    fancy_indexing_grad_gpu,
+    fancy_indexing_grad_metal
+);
+test_device!(
+    einsum_grad,
+    einsum_grad_cpu,
+    einsum_grad_gpu,
+    einsum_grad_metal
+);
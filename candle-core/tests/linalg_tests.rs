@@ -0,0 +1,218 @@
+use candle_core::test_utils::{to_vec1_round, to_vec2_round};
+use candle_core::{linalg, DType, Device, Result, Tensor, Var, D};
+
+// The largest absolute difference between the elements of two tensors.
+fn max_diff(a: &Tensor, b: &Tensor) -> Result<f64> {
+    (a - b)?
+        .abs()?
+        .flatten_all()?
+        .max(0)?
+        .to_dtype(DType::F64)?
+        .to_scalar::<f64>()
+}
+
+fn matrixes(dev: &Device) -> Result<Tensor> {
+    Tensor::new(
+        &[
+            [[4f64, 1., -2.], [1., 3., 0.5], [-2., 0.5, 5.]],
+            [[1., 2., 3.], [0., -1., 4.], [2., 2., 0.]],
+        ],
+        dev,
+    )
+}
+
+#[test]
+fn solve_inv_det() -> Result<()> {
+    let dev = &Device::Cpu;
+    let a = matrixes(dev)?;
+    let b = Tensor::new(&[[1f64, 0.], [2., 1.], [3., -1.]], dev)?;
+    let x = linalg::solve(&a, &b)?;
+    assert_eq!(x.dims(), [2, 3, 2]);
+    assert!(max_diff(&a.broadcast_matmul(&x)?, &b.broadcast_as((2, 3, 2))?)? < 1e-12);
+
+    let a_inv = linalg::inv(&a)?;
+    let eye = Tensor::eye(3, DType::F64, dev)?.broadcast_as((2, 3, 3))?;
+    assert!(max_diff(&a.matmul(&a_inv)?, &eye)? < 1e-12);
+
+    let det = linalg::det(&a)?;
+    assert_eq!(to_vec1_round(&det.to_dtype(DType::F32)?, 4)?, [40., 14.]);
+    let a = a.to_dtype(DType::F32)?;
+    assert_eq!(to_vec1_round(&linalg::det(&a)?, 4)?, [40., 14.]);
+
+    let singular = Tensor::new(&[[1f32, 2.], [2., 4.]], dev)?;
+    assert_eq!(linalg::det(&singular)?.to_scalar::<f32>()?, 0.);
+    assert!(linalg::inv(&singular).is_err());
+    assert!(linalg::det(&Tensor::zeros((2, 3), DType::F32, dev)?).is_err());
+    assert!(linalg::det(&Tensor::zeros((2, 2), DType::U32, dev)?).is_err());
+    Ok(())
+}
+
+#[test]
+fn cholesky() -> Result<()> {
+    let dev = &Device::Cpu;
+    let a = matrixes(dev)?.get(0)?;
+    let l = linalg::cholesky(&a)?;
+    assert!(max_diff(&l.matmul(&l.t()?)?, &a)? < 1e-12);
+    assert_eq!(to_vec2_round(&l.to_dtype(DType::F32)?, 4)?[0], [2., 0., 0.]);
+    assert!(linalg::cholesky(&matrixes(dev)?).is_err());
+    Ok(())
+}
+
+#[test]
+fn qr() -> Result<()> {
+    let dev = &Device::Cpu;
+    for a in [
+        matrixes(dev)?,
+        matrixes(dev)?.narrow(2, 0, 2)?,
+        matrixes(dev)?.narrow(1, 0, 2)?,
+    ] {
+        let (q, r) = linalg::qr(&a)?;
+        let (b_size, m, n) = a.dims3()?;
+        let k = usize::min(m, n);
+        assert_eq!(q.dims(), [b_size, m, k]);
+        assert_eq!(r.dims(), [b_size, k, n]);
+        assert!(max_diff(&q.matmul(&r)?, &a)? < 1e-12);
+        let eye = Tensor::eye(k, DType::F64, dev)?.broadcast_as((b_size, k, k))?;
+        assert!(max_diff(&q.t()?.matmul(&q)?, &eye)? < 1e-12);
+        assert_eq!(r.get(0)?.get(1)?.get(0)?.to_scalar::<f64>()?, 0.);
+    }
+    Ok(())
+}
+
+#[test]
+fn svd() -> Result<()> {
+    let dev = &Device::Cpu;
+    let rank_deficient = Tensor::new(&[[1f64, 2., 3.], [2., 4., 6.], [1., 0., 1.]], dev)?;
+    for a in [
+        matrixes(dev)?,
+        matrixes(dev)?.narrow(2, 0, 2)?,
+        matrixes(dev)?.narrow(1, 0, 2)?,
+        rank_deficient.unsqueeze(0)?,
+    ] {
+        let (u, s, vt) = linalg::svd(&a)?;
+        let k = usize::min(a.dim(1)?, a.dim(2)?);
+        let b_size = a.dim(0)?;
+        assert_eq!(s.dims(), [b_size, k]);
+        let rec = u.broadcast_mul(&s.unsqueeze(1)?)?.matmul(&vt)?;
+        assert!(max_diff(&rec, &a)? < 1e-12);
+        let eye = Tensor::eye(k, DType::F64, dev)?.broadcast_as((b_size, k, k))?;
+        assert!(max_diff(&u.t()?.matmul(&u)?, &eye)? < 1e-12);
+        assert!(max_diff(&vt.matmul(&vt.t()?)?, &eye)? < 1e-12);
+        let s = s.to_vec2::<f64>()?;
+        assert!(s.iter().all(|s| s.windows(2).all(|w| w[0] >= w[1])));
+    }
+    let (_, s, _) = linalg::svd(&rank_deficient)?;
+    assert_eq!(to_vec1_round(&s.to_dtype(DType::F32)?, 4)?[2], 0.);
+    Ok(())
+}
+
+#[test]
+fn eigh() -> Result<()> {
+    let dev = &Device::Cpu;
+    let a = matrixes(dev)?.get(0)?;
+    let a = Tensor::stack(&[&a, &(a.neg()? + Tensor::eye(3, DType::F64, dev)?)?], 0)?;
+    let (values, vectors) = linalg::eigh(&a)?;
+    assert_eq!(values.dims(), [2, 3]);
+    let av = a.matmul(&vectors)?;
+    let vl = vectors.broadcast_mul(&values.unsqueeze(1)?)?;
+    assert!(max_diff(&av, &vl)? < 1e-12);
+    let eye = Tensor::eye(3, DType::F64, dev)?.broadcast_as((2, 3, 3))?;
+    assert!(max_diff(&vectors.t()?.matmul(&vectors)?, &eye)? < 1e-12);
+    let values = values.to_vec2::<f64>()?;
+    assert!(values.iter().all(|v| v.windows(2).all(|w| w[0] <= w[1])));
+    // The eigenvalues of 1 - a are 1 minus the eigenvalues of a.
+    for i in 0..3 {
+        assert!((values[1][i] - 1. + values[0][2 - i]).abs() < 1e-12)
+    }
+    Ok(())
+}
+
+#[test]
+fn empty_matrixes() -> Result<()> {
+    let dev = &Device::Cpu;
+    let a = Tensor::zeros((2, 0, 0), DType::F64, dev)?;
+    assert_eq!(linalg::det(&a)?.to_vec1::<f64>()?, [1., 1.]);
+    let b = Tensor::zeros((2, 0, 3), DType::F64, dev)?;
+    assert_eq!(linalg::solve(&a, &b)?.dims(), [2, 0, 3]);
+    assert_eq!(linalg::inv(&a)?.dims(), [2, 0, 0]);
+    assert_eq!(linalg::cholesky(&a)?.dims(), [2, 0, 0]);
+    let (values, vectors) = linalg::eigh(&a)?;
+    assert_eq!(values.dims(), [2, 0]);
+    assert_eq!(vectors.dims(), [2, 0, 0]);
+    let (q, r) = linalg::qr(&a)?;
+    assert_eq!(q.dims(), [2, 0, 0]);
+    assert_eq!(r.dims(), [2, 0, 0]);
+    Ok(())
+}
+
+#[test]
+fn linalg_grad() -> Result<()> {
+    let dev = &Device::Cpu;
+    let a = Var::new(&[[1f64, 2.], [3., 4.]], dev)?;
+    let a = a.as_tensor();
+    let grads = linalg::det(a)?.backward()?;
+    let grad_a = grads.get(a).unwrap();
+    assert_eq!(
+        to_vec2_round(&grad_a.to_dtype(DType::F32)?, 4)?,
+        [[4., -3.], [-2., 1.]]
+    );
+
+    let b = Var::new(&[[1f64], [1.]], dev)?;
+    let b = b.as_tensor();
+    let x = linalg::solve(a, b)?;
+    let grads = x.sum_all()?.backward()?;
+    // grad_b = a^-T 1 and grad_a = -grad_b x^T with x = (-1, 1)
+    let grad_b = grads.get(b).unwrap();
+    assert_eq!(
+        to_vec2_round(&grad_b.to_dtype(DType::F32)?, 4)?,
+        [[-0.5], [0.5]]
+    );
+    let grad_a = grads.get(a).unwrap();
+    assert_eq!(
+        to_vec2_round(&grad_a.to_dtype(DType::F32)?, 4)?,
+        [[-0.5, 0.5], [0.5, -0.5]]
+    );
+
+    let grads = linalg::inv(a)?.sum_all()?.backward()?;
+    let grad_a = grads.get(a).unwrap();
+    assert_eq!(
+        to_vec2_round(&grad_a.to_dtype(DType::F32)?, 4)?,
+        [[-0.5, 0.5], [0.5, -0.5]]
+    );
+
+    // Check the cholesky gradient against finite differences for a symmetric perturbation.
+    let s = Var::new(&[[4f64, 1.], [1., 3.]], dev)?;
+    let s = s.as_tensor();
+    let w = Tensor::new(&[[1f64, 0.], [2., -1.]], dev)?;
+    let loss = |s: &Tensor| -> Result<Tensor> { (linalg::cholesky(s)? * &w)?.sum_all() };
+    let grads = loss(s)?.backward()?;
+    let grad_s = grads.get(s).unwrap();
+    let dir = Tensor::new(&[[0.3f64, -0.2], [-0.2, 0.5]], dev)?;
+    let eps = 1e-6;
+    let l_plus = loss(&(s + (&dir * eps)?)?)?.to_scalar::<f64>()?;
+    let l_minus = loss(&(s - (&dir * eps)?)?)?.to_scalar::<f64>()?;
+    let numerical = (l_plus - l_minus) / (2. * eps);
+    let analytical = (grad_s * &dir)?.sum_all()?.to_scalar::<f64>()?;
+    assert!((numerical - analytical).abs() < 1e-6);
+
+    // The sum of the eigenvalues is the trace.
+    let (values, _) = linalg::eigh(s)?;
+    let grads = values.sum_all()?.backward()?;
+    let grad_s = grads.get(s).unwrap();
+    assert_eq!(
+        to_vec2_round(&grad_s.to_dtype(DType::F32)?, 4)?,
+        [[1., 0.], [0., 1.]]
+    );
+
+    let c = Var::new(&[[3f64, 0.], [0., -4.], [0., 0.]], dev)?;
+    let c = c.as_tensor();
+    let (_, s, _) = linalg::svd(c)?;
+    let grads = s.sum_all()?.backward()?;
+    let grad_c = grads.get(c).unwrap();
+    assert_eq!(
+        to_vec2_round(&grad_c.to_dtype(DType::F32)?, 4)?,
+        [[1., 0.], [0., -1.], [0., 0.]]
+    );
+    assert_eq!(s.dim(D::Minus1)?, 2);
+    Ok(())
+}
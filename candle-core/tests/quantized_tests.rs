@@ -187,6 +187,13 @@ fn quantize_q4_0(device: &Device) -> Result<()> {
     let src = Tensor::from_slice(&src, (32 * 4,), device)?;
     let quant = quantized::QTensor::quantize(&src, GgmlDType::Q4_0)?;
     let dst = quant.dequantize(device)?;
+    assert!(dst.device().same_device(device));
+    // The dequantized values are moved to the cpu when the storage is on another device.
+    let dst_cpu = quant.dequantize(&Device::Cpu)?;
+    assert!(dst_cpu.device().is_cpu());
+    assert_eq!(dst_cpu.to_vec1::<f32>()?, dst.to_vec1::<f32>()?);
+    let pool = Device::new_cpu_pool(2)?;
+    assert!(quant.dequantize(&pool)?.device().same_device(&pool));
     assert_eq!(
         dst.to_vec1::<f32>()?,
         &[
@@ -1032,3 +1039,942 @@ fn quantized_matmul_q8k() -> Result<()> {
     ggml_matmul_error_test::<BlockQ8K>()?;
     Ok(())
 }
+
+const IQUANT_DTYPES: [GgmlDType; 9] = [
+    GgmlDType::IQ1S,
+    GgmlDType::IQ1M,
+    GgmlDType::IQ2XXS,
+    GgmlDType::IQ2XS,
+    GgmlDType::IQ2S,
+    GgmlDType::IQ3XXS,
+    GgmlDType::IQ3S,
+    GgmlDType::IQ4NL,
+    GgmlDType::IQ4XS,
+];
+
+/// Random weights with a heavier tail than a uniform distribution.
+fn iquants_test_weights(rows: usize, cols: usize) -> Vec<f32> {
+    let mut rng = StdRng::seed_from_u64(42);
+    (0..rows * cols)
+        .map(|_| (rng.gen::<f32>() * 2. - 1.).powi(3))
+        .collect()
+}
+
+#[test]
+fn iquants_quantization_error() -> Result<()> {
+    for dtype in IQUANT_DTYPES {
+        let max_error = match dtype {
+            GgmlDType::IQ1S | GgmlDType::IQ1M => 0.015,
+            GgmlDType::IQ2XXS | GgmlDType::IQ2XS | GgmlDType::IQ2S => {
+                GGML_MAX_QUANTIZATION_TOTAL_ERROR_2BITS
+            }
+            GgmlDType::IQ3XXS | GgmlDType::IQ3S => 0.005,
+            _ => GGML_MAX_QUANTIZATION_TOTAL_ERROR,
+        };
+        ggml_quantization_error_test(dtype, &Device::Cpu, max_error)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn iquants_matmul() -> Result<()> {
+    let cpu = &Device::Cpu;
+    let (m, k, n) = (3, 512, 16);
+    let (lhs, rhs, _mm) = get_random_tensors(m, k, n, cpu)?;
+    for dtype in IQUANT_DTYPES {
+        let rhs = quantized::QTensor::quantize(&rhs, dtype)?;
+        // The quantized matmul only differs from the dequantized one by the quantization of lhs.
+        let expected = lhs.matmul(&rhs.dequantize(cpu)?.t()?)?;
+        let mm = quantized::QMatMul::from_qtensor(rhs)?.forward(&lhs)?;
+        assert_eq!(mm.dims(), [m, n]);
+        let diff = (mm - &expected)?.abs()?.flatten_all()?.max(0)?;
+        let diff = diff.to_scalar::<f32>()?;
+        if diff > 0.05 {
+            bail!("{dtype:?}: quantized matmul differs from the dequantized matmul by {diff}")
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn iquants_imatrix() -> Result<()> {
+    let cpu = &Device::Cpu;
+    let (rows, cols) = (16, 512);
+    let w = iquants_test_weights(rows, cols);
+    let imatrix: Vec<f32> = (0..cols)
+        .map(|i| if i % 7 == 0 { 100. } else { 1. })
+        .collect();
+    let weighted_error = |dst: &[f32]| -> f32 {
+        w.iter()
+            .zip(dst.iter())
+            .enumerate()
+            .map(|(i, (a, b))| imatrix[i % cols] * (a - b).powi(2))
+            .sum()
+    };
+    let src = Tensor::from_vec(w.clone(), (rows, cols), cpu)?;
+    for dtype in IQUANT_DTYPES {
+        let q = quantized::QTensor::quantize(&src, dtype)?;
+        let dst = q.dequantize(cpu)?.flatten_all()?.to_vec1::<f32>()?;
+        let q = quantized::QTensor::quantize_with_imatrix(&src, &imatrix, dtype)?;
+        let dst_imatrix = q.dequantize(cpu)?.flatten_all()?.to_vec1::<f32>()?;
+        let (err, err_imatrix) = (weighted_error(&dst), weighted_error(&dst_imatrix));
+        if err_imatrix >= err {
+            bail!("{dtype:?}: imatrix weighted error {err_imatrix} is not below {err}")
+        }
+    }
+    // The importance matrix must have one weight per column.
+    assert!(
+        quantized::QTensor::quantize_with_imatrix(&src, &imatrix[1..], GgmlDType::IQ4XS).is_err()
+    );
+    Ok(())
+}
+
+#[test]
+fn iquants_gguf_roundtrip() -> Result<()> {
+    use quantized::gguf_file;
+
+    let cpu = &Device::Cpu;
+    let src = Tensor::from_vec(iquants_test_weights(4, 256), (4, 256), cpu)?;
+    let qtensors = IQUANT_DTYPES
+        .iter()
+        .map(|&dtype| quantized::QTensor::quantize(&src, dtype))
+        .collect::<Result<Vec<_>>>()?;
+    let names = IQUANT_DTYPES
+        .iter()
+        .map(|dtype| format!("{dtype:?}"))
+        .collect::<Vec<_>>();
+    let tensors = names
+        .iter()
+        .zip(qtensors.iter())
+        .map(|(n, t)| (n.as_str(), t))
+        .collect::<Vec<_>>();
+    let mut buffer = std::io::Cursor::new(Vec::new());
+    gguf_file::write(&mut buffer, &[], &tensors)?;
+    buffer.set_position(0);
+    let content = gguf_file::Content::read(&mut buffer)?;
+    for (name, qtensor) in tensors {
+        let read = content.tensor(&mut buffer, name, cpu)?;
+        assert_eq!(read.dtype(), qtensor.dtype());
+        assert_eq!(read.shape(), qtensor.shape());
+        assert_eq!(read.data()?, qtensor.data()?);
+    }
+    Ok(())
+}
+
+// Dequantizes a single raw block, the bytes use the llama.cpp `block_iq*` layouts.
+fn iquants_dequantize_block(dtype: GgmlDType, bytes: &[u8]) -> Result<Vec<f32>> {
+    let cpu = &Device::Cpu;
+    assert_eq!(bytes.len(), dtype.type_size());
+    let qtensor =
+        quantized::ggml_file::qtensor_from_ggml(dtype, bytes, vec![dtype.block_size()], cpu)?;
+    qtensor.dequantize(cpu)?.to_vec1::<f32>()
+}
+
+// The expected values follow the llama.cpp `dequantize_row_iq*` functions and grid tables, the
+// blocks only use the first grid entries and the remaining sub-blocks are left zeroed.
+#[test]
+fn iquants_llama_cpp_reference() -> Result<()> {
+    // IQ4_NL, d = 0.5, the first half uses kvalues_iq4nl in order and the second half reversed.
+    let mut block = vec![0x00, 0x38];
+    block.extend((0..16u8).map(|j| j | ((15 - j) << 4)));
+    let kvalues = [
+        -63.5, -52., -41.5, -32.5, -24.5, -17.5, -11., -5., 0.5, 6.5, 12.5, 19., 26.5, 34.5, 44.5,
+        56.5,
+    ];
+    let mut expected = kvalues.to_vec();
+    expected.extend(kvalues.iter().rev());
+    assert_eq!(
+        iquants_dequantize_block(GgmlDType::IQ4NL, &block)?,
+        expected
+    );
+
+    // IQ4_XS, d = 0.5, the 6 bits scales 33, 31, 32, 48 then 34 give d * (ls - 32).
+    let mut block = vec![0x00, 0x38, 0xe6, 0xaa, 0xf1, 0x00, 0x22, 0x22];
+    for _ib in 0..8 {
+        block.extend((0..16u8).map(|j| j | ((15 - j) << 4)));
+    }
+    let mut expected = vec![];
+    for dl in [1., -1., 0., 16., 2., 2., 2., 2.] {
+        expected.extend(kvalues.iter().map(|v| dl * v));
+        expected.extend(kvalues.iter().rev().map(|v| dl * v));
+    }
+    assert_eq!(
+        iquants_dequantize_block(GgmlDType::IQ4XS, &block)?,
+        expected
+    );
+
+    // IQ2_XXS, d = 1, grid indexes 1, 2, 3, 0, sign indexes 1, 3, 0, 127 and scale 3.
+    let mut block = vec![0u8; GgmlDType::IQ2XXS.type_size()];
+    block[0..2].copy_from_slice(&[0x00, 0x3c]);
+    block[2..10].copy_from_slice(&[0x01, 0x02, 0x03, 0x00, 0x81, 0x01, 0xe0, 0x3f]);
+    let mut expected = vec![
+        -37.625, 7., 7., 7., 7., 7., 7., -7., -21.875, -21.875, 7., 7., 7., 7., 7., 7., 7., 37.625,
+        7., 7., 7., 7., 7., 7., -7., -7., -7., -7., -7., -7., -7., -7.,
+    ];
+    expected.resize(256, 1.);
+    assert_eq!(
+        iquants_dequantize_block(GgmlDType::IQ2XXS, &block)?,
+        expected
+    );
+
+    // IQ2_XS, d = 1, grid indexes 7, 4, 5, 6, sign indexes 2, 0, 4, 127 and scales 1 and 6.
+    let mut block = vec![0u8; GgmlDType::IQ2XS.type_size()];
+    block[0..2].copy_from_slice(&[0x00, 0x3c]);
+    block[2..10].copy_from_slice(&[0x07, 0x04, 0x04, 0x00, 0x05, 0x08, 0x06, 0xfe]);
+    block[66] = 0x61;
+    let mut expected = vec![
+        16.125, -9.375, 9.375, 3., 3., 3., 3., -3., 16.125, 16.125, 3., 3., 3., 3., 3., 3., 40.625,
+        13., -40.625, 13., 13., 13., 13., -13., -13., -40.625, -40.625, -13., -13., -13., -13.,
+        -13.,
+    ];
+    expected.resize(256, 1.);
+    assert_eq!(
+        iquants_dequantize_block(GgmlDType::IQ2XS, &block)?,
+        expected
+    );
+
+    // IQ2_S, d = 1, grid indexes 1, 7, 0, 9, signs 0x01, 0x80, 0xff, 0x0a and scales 2 and 5.
+    let mut block = vec![0u8; GgmlDType::IQ2S.type_size()];
+    block[0..2].copy_from_slice(&[0x00, 0x3c]);
+    block[2..6].copy_from_slice(&[1, 7, 0, 9]);
+    block[34..38].copy_from_slice(&[0x01, 0x80, 0xff, 0x0a]);
+    block[74] = 0x52;
+    let mut expected = vec![
+        -26.875, 5., 5., 5., 5., 5., 5., 5., 26.875, 15.625, 15.625, 5., 5., 5., 5., -5., -11.,
+        -11., -11., -11., -11., -11., -11., -11., 11., -11., 59.125, -11., 11., 11., 11., 11.,
+    ];
+    expected.resize(256, 1.);
+    assert_eq!(iquants_dequantize_block(GgmlDType::IQ2S, &block)?, expected);
+
+    // IQ3_XXS, d = 1, grid indexes 1, 2, 3, 4, sign indexes 1, 5, 0, 127 and scale 2.
+    let mut block = vec![0u8; GgmlDType::IQ3XXS.type_size()];
+    block[0..2].copy_from_slice(&[0x00, 0x3c]);
+    block[2..6].copy_from_slice(&[1, 2, 3, 4]);
+    block[66..70].copy_from_slice(&[0x81, 0x02, 0xe0, 0x2f]);
+    let mut expected = vec![
+        -25., 5., 5., 5., 45., 5., 5., -5., -15., 15., -5., 5., 35., 15., 5., 5., 5., 5., 5., 5.,
+        5., 5., 5., 5., -5., -5., -5., -5., -5., -5., -5., -5.,
+    ];
+    expected.resize(256, 1.);
+    assert_eq!(
+        iquants_dequantize_block(GgmlDType::IQ3XXS, &block)?,
+        expected
+    );
+
+    // IQ3_S, d = 0.5, grid indexes 1, 2, 3, 5, signs 0x01, 0x10, 0x00, 0xff and scale 1 for the
+    // first sub-block, sign 0x80 and scale 3 for the second one.
+    let mut block = vec![0u8; GgmlDType::IQ3S.type_size()];
+    block[0..2].copy_from_slice(&[0x00, 0x38]);
+    block[2..6].copy_from_slice(&[1, 2, 3, 5]);
+    block[74..78].copy_from_slice(&[0x01, 0x10, 0x00, 0xff]);
+    block[78] = 0x80;
+    block[106] = 0x31;
+    let mut expected = vec![
+        -4.5, 1.5, 1.5, 1.5, 7.5, 1.5, 1.5, 1.5, 16.5, 1.5, 1.5, 1.5, -1.5, 4.5, 1.5, 1.5, 1.5,
+        1.5, 1.5, 1.5, 1.5, 1.5, 1.5, 1.5, -1.5, -1.5, -1.5, -1.5, -1.5, -1.5, -1.5, -1.5,
+    ];
+    expected.extend([3.5, 3.5, 3.5, 3.5, 3.5, 3.5, 3.5, -3.5]);
+    expected.resize(64, 3.5);
+    expected.resize(256, 0.5);
+    assert_eq!(iquants_dequantize_block(GgmlDType::IQ3S, &block)?, expected);
+
+    // IQ1_S, d = 1, grid indexes 0, 1, 2, 5, scale 2 and a negative delta.
+    let mut block = vec![0u8; GgmlDType::IQ1S.type_size()];
+    block[0..2].copy_from_slice(&[0x00, 0x3c]);
+    block[2..6].copy_from_slice(&[0, 1, 2, 5]);
+    block[34..36].copy_from_slice(&[0x00, 0xa0]);
+    let mut expected = vec![-5.625; 8];
+    expected.extend([
+        4.375, -5.625, -5.625, -5.625, -5.625, -5.625, -5.625, -5.625,
+    ]);
+    expected.extend([
+        -0.625, -0.625, -5.625, -5.625, -5.625, -5.625, -5.625, -5.625,
+    ]);
+    expected.extend([
+        -0.625, -5.625, -0.625, -5.625, -5.625, -5.625, -5.625, -5.625,
+    ]);
+    expected.resize(256, -0.875);
+    assert_eq!(iquants_dequantize_block(GgmlDType::IQ1S, &block)?, expected);
+
+    // IQ1_M, d = 1 split over the scales top nibbles, grid indexes 1, 2, 0, 5, scales 1 and 2,
+    // negative deltas for the first and last groups.
+    let mut block = vec![0u8; GgmlDType::IQ1M.type_size()];
+    block[0..4].copy_from_slice(&[1, 2, 0, 5]);
+    block[32..34].copy_from_slice(&[0x08, 0x80]);
+    block[48..56].copy_from_slice(&[0x11, 0x00, 0x00, 0x00, 0x00, 0xc0, 0x00, 0x30]);
+    let mut expected = vec![
+        2.625, -3.375, -3.375, -3.375, -3.375, -3.375, -3.375, -3.375,
+    ];
+    expected.extend([0.375, 0.375, -2.625, -2.625, -2.625, -2.625, -2.625, -2.625]);
+    expected.extend([-4.375; 8]);
+    expected.extend([
+        -0.625, -5.625, -0.625, -5.625, -5.625, -5.625, -5.625, -5.625,
+    ]);
+    expected.resize(256, -0.875);
+    assert_eq!(iquants_dequantize_block(GgmlDType::IQ1M, &block)?, expected);
+    Ok(())
+}
+
+fn vec_dot_rows_test<T: GgmlType>() -> Result<()> {
+    let (nrows, k) = (6, 512);
+    let xs = create_ggml_like_vector(0.0)[..k].to_vec();
+    let mut xs_q = vec![T::zeros(); k / T::BLCK_SIZE];
+    T::from_float(&xs, &mut xs_q)?;
+    let mut rng = StdRng::seed_from_u64(42);
+    let ys = (0..nrows)
+        .map(|_| {
+            let ys = (0..k).map(|_| rng.gen::<f32>() - 0.5).collect::<Vec<_>>();
+            let mut ys_q = vec![T::VecDotType::zeros(); k / T::VecDotType::BLCK_SIZE];
+            T::VecDotType::from_float(&ys, &mut ys_q)?;
+            Ok(ys_q)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let rows = ys.iter().map(|ys| ys.as_slice()).collect::<Vec<_>>();
+    let mut dst = vec![0f32; nrows];
+    T::vec_dot_rows(k, &xs_q, &rows, &mut dst)?;
+    for (ys, dst) in ys.iter().zip(dst.iter()) {
+        let expected = T::vec_dot(k, &xs_q, ys)?;
+        if (expected - dst).abs() > 1e-4 * expected.abs().max(1.) {
+            bail!(
+                "{:?}: vec_dot_rows returned {dst}, expected {expected}",
+                T::DTYPE
+            )
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn quantized_vec_dot_rows() -> Result<()> {
+    vec_dot_rows_test::<k_quants::BlockQ4_0>()?;
+    vec_dot_rows_test::<k_quants::BlockQ8_0>()?;
+    vec_dot_rows_test::<k_quants::BlockQ4K>()?;
+    vec_dot_rows_test::<k_quants::BlockQ6K>()?;
+    Ok(())
+}
+
+#[test]
+fn quantized_matmul_tiled() -> Result<()> {
+    let cpu = &Device::Cpu;
+    // Sizes that are not multiples of the row and column tiles.
+    let (m, k, n) = (7, 512, 37);
+    let (lhs, rhs, _mm) = get_random_tensors(m, k, n, cpu)?;
+    for dtype in [
+        GgmlDType::Q4_0,
+        GgmlDType::Q8_0,
+        GgmlDType::Q4K,
+        GgmlDType::Q6K,
+        GgmlDType::IQ4XS,
+    ] {
+        let rhs = quantized::QTensor::quantize(&rhs, dtype)?;
+        let rhs = quantized::QMatMul::from_qtensor(rhs)?;
+        let mm = rhs.forward(&lhs)?;
+        assert_eq!(mm.dims(), [m, n]);
+        // The single row matmul does not use the tiled path.
+        for row_idx in 0..m {
+            let expected = rhs.forward(&lhs.narrow(0, row_idx, 1)?)?;
+            let diff = (mm.narrow(0, row_idx, 1)? - expected)?
+                .abs()?
+                .flatten_all()?
+                .max(0)?
+                .to_scalar::<f32>()?;
+            if diff > 1e-4 {
+                bail!("{dtype:?}: tiled matmul differs by {diff} on row {row_idx}")
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "convert")]
+#[test]
+fn quantized_convert() -> Result<()> {
+    use quantized::{convert, gguf_file};
+
+    let cpu = &Device::Cpu;
+    let weights =
+        |rows, cols| Tensor::from_vec(iquants_test_weights(rows, cols), (rows, cols), cpu);
+    let tensors: std::collections::HashMap<String, Tensor> = [
+        ("model.embed_tokens.weight", weights(8, 256)?),
+        (
+            "model.layers.0.input_layernorm.weight",
+            weights(1, 256)?.squeeze(0)?,
+        ),
+        ("model.layers.0.mlp.up_proj.weight", weights(4, 512)?),
+        ("model.layers.0.mlp.down_proj.weight", weights(4, 100)?),
+        ("lm_head.weight", weights(8, 256)?),
+    ]
+    .into_iter()
+    .map(|(n, t)| (n.to_string(), t))
+    .collect();
+    let dir = std::env::temp_dir().join(format!("candle-convert-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let src = dir.join("model.safetensors");
+    let dst = dir.join("model.gguf");
+    candle_core::safetensors::save(&tensors, &src)?;
+
+    let config = convert::Config::default().with_architecture("llama");
+    assert!(convert::convert(&[&src], &dst, &config).is_err());
+    let hf_config = serde_json::json!({
+        "hidden_size": 256,
+        "intermediate_size": 4,
+        "num_attention_heads": 8,
+        "num_key_value_heads": 2,
+        "num_hidden_layers": 2,
+        "max_position_embeddings": 4096,
+        "rms_norm_eps": 1e-5,
+        "rope_theta": 5e5,
+    });
+    let config = config
+        .with_hf_config_value(&hf_config)?
+        .with_metadata("llama.block_count", gguf_file::Value::U32(1));
+    let report = convert::convert(&[&src], &dst, &config)?;
+    // The tensors use the GGUF names, sorted by name.
+    let expected = [
+        (
+            "blk.0.attn_norm.weight",
+            "model.layers.0.input_layernorm.weight",
+            GgmlDType::F16,
+        ),
+        (
+            "blk.0.ffn_down.weight",
+            "model.layers.0.mlp.down_proj.weight",
+            GgmlDType::F16,
+        ),
+        (
+            "blk.0.ffn_up.weight",
+            "model.layers.0.mlp.up_proj.weight",
+            GgmlDType::Q4K,
+        ),
+        ("output.weight", "lm_head.weight", GgmlDType::Q6K),
+        (
+            "token_embd.weight",
+            "model.embed_tokens.weight",
+            GgmlDType::F16,
+        ),
+    ];
+    assert_eq!(report.tensors.len(), expected.len());
+    for (stats, (name, hf_name, dtype)) in report.tensors.iter().zip(expected) {
+        assert_eq!(stats.name, name);
+        assert_eq!(stats.dtype, dtype);
+        assert_eq!(stats.shape, *tensors[hf_name].shape());
+        assert!(stats.max_abs_error >= stats.rmse, "{stats:?}");
+        let max_rel_error = if dtype == GgmlDType::F16 { 1e-3 } else { 0.1 };
+        assert!(stats.rel_error < max_rel_error, "{stats:?}");
+    }
+    assert_eq!(
+        convert::gguf_tensor_name("llama", "model.layers.12.self_attn.k_proj.weight")?,
+        Some("blk.12.attn_k.weight".to_string())
+    );
+    assert_eq!(
+        convert::gguf_tensor_name("llama", "model.layers.0.self_attn.rotary_emb.inv_freq")?,
+        None
+    );
+    assert!(convert::gguf_tensor_name("llama", "blk.0.attn_k.weight").is_err());
+    assert!(convert::gguf_tensor_name("gpt2", "lm_head.weight").is_err());
+    let gpt2_config = config.clone().with_architecture("gpt2");
+    assert!(convert::convert(&[&src], &dst, &gpt2_config).is_err());
+
+    let mut file = std::fs::File::open(&dst)?;
+    let content = gguf_file::Content::read(&mut file)?;
+    assert_eq!(content.magic, gguf_file::VersionedMagic::GgufV3);
+    assert_eq!(
+        content.metadata["general.architecture"].to_string()?,
+        "llama"
+    );
+    // The explicitly set metadata takes precedence over the config.json values.
+    assert_eq!(content.metadata["llama.block_count"].to_u32()?, 1);
+    assert_eq!(content.metadata["llama.context_length"].to_u32()?, 4096);
+    assert_eq!(content.metadata["llama.embedding_length"].to_u32()?, 256);
+    assert_eq!(content.metadata["llama.feed_forward_length"].to_u32()?, 4);
+    assert_eq!(content.metadata["llama.attention.head_count"].to_u32()?, 8);
+    assert_eq!(
+        content.metadata["llama.attention.head_count_kv"].to_u32()?,
+        2
+    );
+    assert_eq!(content.metadata["llama.rope.dimension_count"].to_u32()?, 32);
+    assert_eq!(content.metadata["llama.rope.freq_base"].to_f32()?, 5e5);
+    assert_eq!(
+        content.metadata["llama.attention.layer_norm_rms_epsilon"].to_f32()?,
+        1e-5
+    );
+    assert_eq!(
+        content.metadata["general.quantization_version"].to_u32()?,
+        convert::QUANTIZATION_VERSION
+    );
+    for stats in report.tensors.iter() {
+        let qtensor = content.tensor(&mut file, &stats.name, cpu)?;
+        assert_eq!(qtensor.dtype(), stats.dtype);
+        assert_eq!(qtensor.storage_size_in_bytes(), stats.size_in_bytes);
+    }
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[cfg(feature = "convert")]
+#[test]
+fn quantized_convert_rules() -> Result<()> {
+    use quantized::convert::Config;
+
+    let config = Config::new(GgmlDType::Q8_0)
+        .with_rule(r"\.bias$", GgmlDType::F32)?
+        .with_rule_first(r"^keep\.", GgmlDType::F16)?;
+    let shape: candle_core::Shape = (4, 64).into();
+    assert_eq!(config.dtype_for("a.weight", &shape), GgmlDType::Q8_0);
+    assert_eq!(config.dtype_for("a.bias", &shape), GgmlDType::F32);
+    assert_eq!(config.dtype_for("keep.bias", &shape), GgmlDType::F16);
+    assert_eq!(
+        config.dtype_for("a.weight", &(4, 33).into()),
+        GgmlDType::F16
+    );
+    let config = config.with_fallback_dtype(GgmlDType::F32)?;
+    assert_eq!(
+        config.dtype_for("a.weight", &(4, 33).into()),
+        GgmlDType::F32
+    );
+    assert!(config.with_fallback_dtype(GgmlDType::Q4_0).is_err());
+    assert!(Config::new(GgmlDType::Q4K)
+        .with_rule("(", GgmlDType::F16)
+        .is_err());
+    Ok(())
+}
+
+fn gguf_test_tensors() -> Result<Vec<(String, quantized::QTensor)>> {
+    let cpu = &Device::Cpu;
+    let src = Tensor::from_vec(iquants_test_weights(4, 256), (4, 256), cpu)?;
+    [
+        GgmlDType::F32,
+        GgmlDType::F16,
+        GgmlDType::Q4_0,
+        GgmlDType::Q4K,
+    ]
+    .iter()
+    .map(|&dtype| {
+        Ok((
+            format!("{dtype:?}"),
+            quantized::QTensor::quantize(&src, dtype)?,
+        ))
+    })
+    .collect()
+}
+
+#[test]
+fn gguf_big_endian() -> Result<()> {
+    use quantized::gguf_file::{self, Endianness, Value, VersionedMagic};
+
+    let qtensors = gguf_test_tensors()?;
+    let tensors = qtensors
+        .iter()
+        .map(|(n, t)| (n.as_str(), t))
+        .collect::<Vec<_>>();
+    let values = [
+        Value::U32(42),
+        Value::F32(-1.5),
+        Value::String("llama".to_string()),
+        Value::Array(vec![Value::I64(-3), Value::I64(7)]),
+    ];
+    let metadata = ["a", "b", "c", "d"]
+        .iter()
+        .zip(values.iter())
+        .map(|(k, v)| (*k, v))
+        .collect::<Vec<_>>();
+    // The quantized tensors cannot be stored in big-endian files.
+    let mut buffer = std::io::Cursor::new(Vec::new());
+    assert!(gguf_file::write_with_endianness(&mut buffer, Endianness::Big, &[], &tensors).is_err());
+    let tensors = &tensors[..2];
+    let mut contents = vec![];
+    for endianness in [Endianness::Little, Endianness::Big] {
+        let mut buffer = std::io::Cursor::new(Vec::new());
+        gguf_file::write_with_endianness(&mut buffer, endianness, &metadata, tensors)?;
+        contents.push(buffer.into_inner());
+    }
+    // The magic is the same for both byte orders but the other header values are swapped.
+    assert_eq!(contents[0][..4], contents[1][..4]);
+    assert_eq!(contents[0][4..8], [3, 0, 0, 0]);
+    assert_eq!(contents[1][4..8], [0, 0, 0, 3]);
+
+    let mut buffer = std::io::Cursor::new(&contents[1]);
+    let content = gguf_file::Content::read(&mut buffer)?;
+    assert_eq!(content.magic, VersionedMagic::GgufV3);
+    assert_eq!(content.endianness, Endianness::Big);
+    assert_eq!(content.metadata["a"].to_u32()?, 42);
+    assert_eq!(content.metadata["b"].to_f32()?, -1.5);
+    assert_eq!(content.metadata["c"].to_string()?, "llama");
+    let d = content.metadata["d"].to_vec()?;
+    assert_eq!(
+        d.iter().map(|v| v.to_i64()).collect::<Result<Vec<_>>>()?,
+        [-3, 7]
+    );
+    for (name, qtensor) in tensors {
+        let read = content.tensor(&mut buffer, name, &Device::Cpu)?;
+        assert_eq!(read.dtype(), qtensor.dtype());
+        assert_eq!(read.shape(), qtensor.shape());
+        assert_eq!(read.data()?, qtensor.data()?);
+    }
+
+    // Turn the f32 tensor of a big-endian file into a q4_0 one, reading it has to fail. The
+    // dtype comes after the 24 bytes header, the name and the two dimensions.
+    let mut buffer = std::io::Cursor::new(Vec::new());
+    gguf_file::write_with_endianness(&mut buffer, Endianness::Big, &[], &tensors[..1])?;
+    let mut data = buffer.into_inner();
+    let dtype_offset = 24 + 8 + tensors[0].0.len() + 4 + 2 * 8;
+    assert_eq!(data[dtype_offset..dtype_offset + 4], [0, 0, 0, 0]);
+    data[dtype_offset + 3] = 2;
+    let mut buffer = std::io::Cursor::new(&data);
+    let content = gguf_file::Content::read(&mut buffer)?;
+    assert_eq!(content.tensor_infos["F32"].ggml_dtype, GgmlDType::Q4_0);
+    assert!(content.tensor(&mut buffer, "F32", &Device::Cpu).is_err());
+    Ok(())
+}
+
+#[test]
+fn gguf_mmaped() -> Result<()> {
+    use quantized::gguf_file;
+
+    let cpu = &Device::Cpu;
+    let qtensors = gguf_test_tensors()?;
+    let tensors = qtensors
+        .iter()
+        .map(|(n, t)| (n.as_str(), t))
+        .collect::<Vec<_>>();
+    let dir = std::env::temp_dir().join(format!("candle-gguf-mmap-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    for endianness in [gguf_file::Endianness::Little, gguf_file::Endianness::Big] {
+        let path = dir.join(format!("{endianness:?}.gguf"));
+        // Only the f32 and f16 tensors can be stored in big-endian files.
+        let tensors = match endianness {
+            gguf_file::Endianness::Little => &tensors[..],
+            gguf_file::Endianness::Big => &tensors[..2],
+        };
+        let mut file = std::fs::File::create(&path)?;
+        gguf_file::write_with_endianness(&mut file, endianness, &[], tensors)?;
+        drop(file);
+
+        let content = unsafe { gguf_file::MmapedContent::new(&path)? };
+        assert_eq!(content.content().endianness, endianness);
+        let xs = Tensor::from_vec(iquants_test_weights(3, 256), (3, 256), cpu)?;
+        for ((name, qtensor), (_, expected)) in tensors.iter().zip(gguf_test_tensors()?) {
+            let read = content.tensor(name, cpu)?;
+            assert_eq!(read.dtype(), qtensor.dtype());
+            assert_eq!(read.shape(), qtensor.shape());
+            assert_eq!(read.data()?, qtensor.data()?);
+            let ys = quantized::QMatMul::from_qtensor(read)?.forward(&xs)?;
+            let expected = quantized::QMatMul::from_qtensor(expected)?.forward(&xs)?;
+            let diff = (ys - expected)?.abs()?.max_keepdim(0)?.max(1)?;
+            assert_eq!(diff.to_vec1::<f32>()?, [0.]);
+        }
+    }
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn ggml_write_and_gguf_upgrade() -> Result<()> {
+    use quantized::{ggml_file, gguf_file};
+
+    let cpu = &Device::Cpu;
+    let q = |rows, cols, dtype| {
+        let t = Tensor::from_vec(iquants_test_weights(rows, cols), (rows, cols), cpu)?;
+        quantized::QTensor::quantize(&t, dtype)
+    };
+    // A tiny llama with a single layer, four heads of size 8 and two kv heads.
+    let make_tensors = || -> Result<std::collections::HashMap<String, quantized::QTensor>> {
+        [
+            ("tok_embeddings.weight", 6, 32, GgmlDType::F16),
+            ("norm.weight", 1, 32, GgmlDType::F32),
+            ("output.weight", 6, 32, GgmlDType::Q8_0),
+            ("layers.0.attention.wq.weight", 32, 32, GgmlDType::Q4_0),
+            ("layers.0.attention.wk.weight", 16, 32, GgmlDType::Q4_0),
+            ("layers.0.attention.wv.weight", 16, 32, GgmlDType::Q4_0),
+            ("layers.0.attention.wo.weight", 32, 32, GgmlDType::Q4_0),
+            ("layers.0.attention_norm.weight", 1, 32, GgmlDType::F32),
+            ("layers.0.feed_forward.w1.weight", 64, 32, GgmlDType::Q8_0),
+            ("layers.0.feed_forward.w2.weight", 32, 64, GgmlDType::Q8_0),
+            ("layers.0.feed_forward.w3.weight", 64, 32, GgmlDType::Q8_0),
+            ("layers.0.ffn_norm.weight", 1, 32, GgmlDType::F32),
+        ]
+        .into_iter()
+        .map(|(n, rows, cols, dtype)| Ok((n.to_string(), q(rows, cols, dtype)?)))
+        .collect()
+    };
+    let tensors = make_tensors()?;
+    let words: [&[u8]; 6] = [b"", b"", b"", &[0x41], b" the", b"ab"];
+    let vocab = ggml_file::Vocab {
+        token_score_pairs: words
+            .iter()
+            .enumerate()
+            .map(|(i, w)| (w.to_vec(), -(i as f32)))
+            .collect(),
+    };
+    let hparams = ggml_file::HParams {
+        n_vocab: 6,
+        n_embd: 32,
+        n_mult: 16,
+        n_head: 4,
+        n_layer: 1,
+        n_rot: 8,
+        ftype: 2,
+    };
+
+    for magic in [
+        ggml_file::VersionedMagic::GgmlUnversioned,
+        ggml_file::VersionedMagic::GgmfV1,
+        ggml_file::VersionedMagic::GgjtV1,
+        ggml_file::VersionedMagic::GgjtV3,
+    ] {
+        let content = ggml_file::Content {
+            magic,
+            hparams: hparams.clone(),
+            vocab: vocab.clone(),
+            tensors: make_tensors()?,
+        };
+        let mut buffer = std::io::Cursor::new(Vec::<u8>::new());
+        content.write(&mut buffer)?;
+        buffer.set_position(0);
+        let content = ggml_file::Content::read(&mut buffer, cpu)?;
+        assert_eq!(content.magic, magic);
+        assert_eq!(content.hparams, hparams);
+        assert_eq!(content.vocab, vocab);
+        assert_eq!(content.tensors.len(), tensors.len());
+        for (name, tensor) in tensors.iter() {
+            let read = &content.tensors[name];
+            assert_eq!(read.dtype(), tensor.dtype());
+            assert_eq!(read.shape(), tensor.shape());
+            assert_eq!(read.data()?, tensor.data()?);
+        }
+
+        let mut gguf = std::io::Cursor::new(Vec::<u8>::new());
+        let ctx = gguf_file::Value::U32(4096);
+        if magic != ggml_file::VersionedMagic::GgjtV3 {
+            // The quantized blocks of the older versions use a different layout.
+            assert!(content.write_gguf(&mut gguf, &[]).is_err());
+            // The files that only contain f32 and f16 tensors can still be converted.
+            let tensors = content
+                .tensors
+                .iter()
+                .map(|(n, t)| {
+                    let t = quantized::QTensor::quantize(&t.dequantize(cpu)?, GgmlDType::F32)?;
+                    Ok((n.clone(), t))
+                })
+                .collect::<Result<_>>()?;
+            let content = ggml_file::Content { tensors, ..content };
+            content.write_gguf(&mut gguf, &[])?;
+            gguf.set_position(0);
+            let gguf_content = gguf_file::Content::read(&mut gguf)?;
+            assert_eq!(gguf_content.tensor_infos.len(), content.tensors.len());
+            continue;
+        }
+        content.write_gguf(&mut gguf, &[("llama.context_length", &ctx)])?;
+        gguf.set_position(0);
+        let gguf_content = gguf_file::Content::read(&mut gguf)?;
+        let md = |key: &str| gguf_content.metadata[key].clone();
+        assert_eq!(md("general.architecture").to_string()?, "llama");
+        assert_eq!(md("general.file_type").to_u32()?, 2);
+        assert_eq!(md("llama.context_length").to_u32()?, 4096);
+        assert_eq!(md("llama.embedding_length").to_u32()?, 32);
+        assert_eq!(md("llama.block_count").to_u32()?, 1);
+        assert_eq!(md("llama.feed_forward_length").to_u32()?, 64);
+        assert_eq!(md("llama.rope.dimension_count").to_u32()?, 8);
+        assert_eq!(md("llama.attention.head_count").to_u32()?, 4);
+        assert_eq!(md("llama.attention.head_count_kv").to_u32()?, 2);
+        let tokens = md("tokenizer.ggml.tokens")
+            .to_vec()?
+            .iter()
+            .map(|v| v.to_string().cloned())
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(tokens, ["", "", "", "<0x41>", "\u{2581}the", "ab"]);
+        let token_types = md("tokenizer.ggml.token_type")
+            .to_vec()?
+            .iter()
+            .map(|v| v.to_i32())
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(token_types, [3, 3, 3, 6, 1, 1]);
+        assert_eq!(md("tokenizer.ggml.scores").to_vec()?.len(), 6);
+
+        assert_eq!(gguf_content.tensor_infos.len(), tensors.len());
+        for (name, tensor) in tensors.iter() {
+            let gguf_name = ggml_file::gguf_tensor_name(name);
+            let read = gguf_content.tensor(&mut gguf, &gguf_name, cpu)?;
+            assert_eq!(read.dtype(), tensor.dtype());
+            assert_eq!(read.shape(), tensor.shape());
+            assert_eq!(read.data()?, tensor.data()?);
+        }
+    }
+    assert_eq!(
+        ggml_file::gguf_tensor_name("layers.12.feed_forward.w2.weight"),
+        "blk.12.ffn_down.weight"
+    );
+    assert_eq!(ggml_file::gguf_tensor_name("layers.x.foo"), "layers.x.foo");
+    Ok(())
+}
+
+/// Packs eight 4-bit values per `u32`, the i-th value going to the i-th nibble.
+fn pack_int4(vs: &[u32]) -> i64 {
+    vs.iter()
+        .enumerate()
+        .fold(0u32, |acc, (i, &v)| acc | (v << (4 * i))) as i32 as i64
+}
+
+#[test]
+fn int4_gptq_awq() -> Result<()> {
+    let cpu = &Device::Cpu;
+    let (m, in_dim, out_dim, group_size) = (3, 64, 16, 16);
+    let n_groups = in_dim / group_size;
+    let mut rng = StdRng::seed_from_u64(42);
+    let q: Vec<Vec<u32>> = (0..in_dim)
+        .map(|_| (0..out_dim).map(|_| rng.gen_range(0..16)).collect())
+        .collect();
+    let zeros: Vec<Vec<u32>> = (0..n_groups)
+        .map(|_| (0..out_dim).map(|_| rng.gen_range(1..16)).collect())
+        .collect();
+    let scales = Tensor::rand(0f32, 0.1, (n_groups, out_dim), cpu)?;
+    let scales_v = scales.to_vec2::<f32>()?;
+    let xs = Tensor::randn(0f32, 1., (m, in_dim), cpu)?;
+    // Act-order checkpoints assign the input features to groups in an arbitrary order.
+    let mut g_idx = (0..in_dim).map(|i| i / group_size).collect::<Vec<_>>();
+    g_idx.shuffle(&mut rng);
+
+    let expected = |g_idx: &[usize]| -> Result<Tensor> {
+        let mut ws = vec![0f32; out_dim * in_dim];
+        for o in 0..out_dim {
+            for i in 0..in_dim {
+                let g = g_idx[i];
+                ws[o * in_dim + i] = (q[i][o] as f32 - zeros[g][o] as f32) * scales_v[g][o]
+            }
+        }
+        Tensor::from_vec(ws, (out_dim, in_dim), cpu)
+    };
+    let check = |mm: quantized::QMatMul, ws: &Tensor| -> Result<()> {
+        let quantized::QMatMul::Int4(w) = &mm else {
+            bail!("unexpected qmatmul {mm:?}")
+        };
+        let diff = (w.dequantize(cpu)? - ws)?.abs()?.max_keepdim(1)?.max(0)?;
+        assert!(diff.to_vec1::<f32>()?[0] < 1e-6);
+        let diff = (mm.forward(&xs)? - xs.matmul(&ws.t()?)?)?
+            .abs()?
+            .flatten_all()?
+            .max(0)?
+            .to_scalar::<f32>()?;
+        assert!(diff < 1e-4, "{diff}");
+        Ok(())
+    };
+
+    // GPTQ packs the input features in qweight and the output features in qzeros, the zero
+    // points are stored minus one.
+    let qweight = (0..in_dim / 8)
+        .map(|r| {
+            (0..out_dim)
+                .map(|o| pack_int4(&(0..8).map(|i| q[8 * r + i][o]).collect::<Vec<_>>()))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let qweight = Tensor::new(qweight, cpu)?;
+    let qzeros = zeros
+        .iter()
+        .map(|z| {
+            z.chunks(8)
+                .map(|z| pack_int4(&z.iter().map(|z| z - 1).collect::<Vec<_>>()))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let qzeros = Tensor::new(qzeros, cpu)?;
+    let scales_f16 = scales.to_dtype(candle_core::DType::F16)?;
+    let seq_g_idx = (0..in_dim).map(|i| i / group_size).collect::<Vec<_>>();
+    let w = quantized::int4::Int4Weight::from_gptq(&qweight, &qzeros, &scales, None)?;
+    assert_eq!(w.shape().dims(), [out_dim, in_dim]);
+    check(quantized::QMatMul::from_int4(w)?, &expected(&seq_g_idx)?)?;
+    let g_idx_t = Tensor::new(g_idx.iter().map(|&g| g as u32).collect::<Vec<_>>(), cpu)?;
+    let w = quantized::int4::Int4Weight::from_gptq(&qweight, &qzeros, &scales, Some(&g_idx_t))?;
+    check(quantized::QMatMul::from_int4(w)?, &expected(&g_idx)?)?;
+    let w = quantized::int4::Int4Weight::from_gptq(&qweight, &qzeros, &scales_f16, None)?;
+    let w = w.dequantize(cpu)?;
+    let diff = (w - expected(&seq_g_idx)?)?.abs()?.flatten_all()?.max(0)?;
+    assert!(diff.to_scalar::<f32>()? < 1e-3);
+    let bad_g_idx = Tensor::zeros(in_dim, candle_core::DType::U32, cpu)?;
+    assert!(
+        quantized::int4::Int4Weight::from_gptq(&qweight, &qzeros, &scales, Some(&bad_g_idx))
+            .is_err()
+    );
+
+    // AWQ packs the output features in both qweight and qzeros using an interleaved order.
+    const AWQ_ORDER: [usize; 8] = [0, 2, 4, 6, 1, 3, 5, 7];
+    let pack_awq = |vs: &[u32]| -> Vec<i64> {
+        vs.chunks(8)
+            .map(|vs| pack_int4(&AWQ_ORDER.iter().map(|&i| vs[i]).collect::<Vec<_>>()))
+            .collect()
+    };
+    let qweight = Tensor::new(q.iter().map(|q| pack_awq(q)).collect::<Vec<_>>(), cpu)?;
+    let qzeros = Tensor::new(zeros.iter().map(|z| pack_awq(z)).collect::<Vec<_>>(), cpu)?;
+    let w = quantized::int4::Int4Weight::from_awq(&qweight, &qzeros, &scales)?;
+    assert_eq!(w.group_size(), group_size);
+    check(quantized::QMatMul::from_int4(w)?, &expected(&seq_g_idx)?)?;
+    Ok(())
+}
+
+#[test]
+fn int_quant() -> Result<()> {
+    use quantized::int_quant::{IntQTensor, IntQuantFormat};
+
+    let cpu = &Device::Cpu;
+    let xs = Tensor::new(
+        &[
+            [-1.27f32, 0.5, 0.0, 0.25, 1.0, 0.1, -0.2, 0.3],
+            [0.0, 0.0, 0.0, 0.0, 0.7, -0.35, 0.0, 0.1],
+        ],
+        cpu,
+    )?;
+    let q = IntQTensor::quantize(&xs, IntQuantFormat::Int8)?;
+    assert_eq!(q.scale().to_vec1::<f32>()?, [0.01, 0.7 / 127.]);
+    assert_eq!(q.zero_point().to_vec1::<u8>()?, [128, 128]);
+    assert_eq!(
+        q.data().to_vec2::<u8>()?[0],
+        [1, 178, 128, 153, 228, 138, 108, 158]
+    );
+    let diff = (q.dequantize()? - &xs)?.abs()?.flatten_all()?.max(0)?;
+    assert!(diff.to_scalar::<f32>()? < 0.005);
+
+    let format = IntQuantFormat::Int4 { group_size: 4 };
+    let q = IntQTensor::quantize(&xs, format)?;
+    assert_eq!(q.data().dims(), [2, 4]);
+    assert_eq!(q.scale().dims(), [2, 2]);
+    // The all zero group gets a scale of one.
+    assert_eq!(q.scale().to_vec2::<f32>()?[1][0], 1.0);
+    assert_eq!(
+        q.unpacked_data()?.to_vec2::<u8>()?,
+        [[1, 11, 8, 9, 15, 9, 7, 10], [8, 8, 8, 8, 15, 4, 8, 9]]
+    );
+    assert_eq!(
+        q.data().to_vec2::<u8>()?[0],
+        [1 + 11 * 16, 8 + 9 * 16, 15 + 9 * 16, 7 + 160]
+    );
+    assert_eq!(
+        to_vec2_round(&q.dequantize()?, 4)?,
+        [
+            [-1.27, 0.5443, 0.0, 0.1814, 1.0, 0.1429, -0.1429, 0.2857],
+            [0.0, 0.0, 0.0, 0.0, 0.7, -0.4, 0.0, 0.1]
+        ]
+    );
+    assert!(IntQTensor::quantize(&xs, IntQuantFormat::Int4 { group_size: 3 }).is_err());
+
+    // Safetensors round trip, the format is recovered from the shape of the scales.
+    let int8 = IntQTensor::quantize(&xs, IntQuantFormat::Int8)?;
+    let int4 = IntQTensor::quantize(&xs, IntQuantFormat::Int4 { group_size: 8 })?;
+    let ts = int8
+        .to_named_tensors("a")
+        .into_iter()
+        .chain(int4.to_named_tensors("b"))
+        .collect::<std::collections::HashMap<_, _>>();
+    let path = std::env::temp_dir().join(format!("candle-int-quant-{}.st", std::process::id()));
+    candle_core::safetensors::save(&ts, &path)?;
+    let ts = candle_core::safetensors::load(&path, cpu)?;
+    std::fs::remove_file(&path)?;
+    for (name, q) in [("a", int8), ("b", int4)] {
+        let read = IntQTensor::from_named_tensors(&ts, name)?;
+        assert_eq!(read.format(), q.format());
+        assert_eq!(read.shape(), q.shape());
+        let diff = (read.dequantize()? - q.dequantize()?)?.abs()?.sum_all()?;
+        assert_eq!(diff.to_scalar::<f32>()?, 0.);
+    }
+    assert!(IntQTensor::from_named_tensors(&ts, "c").is_err());
+    Ok(())
+}
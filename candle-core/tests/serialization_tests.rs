@@ -22,3 +22,105 @@ fn npz() -> Result<()> {
     );
     Ok(())
 }
+
+#[test]
+fn safetensors_sharded() -> Result<()> {
+    use candle_core::safetensors::{save_sharded, MmapedSafetensors, INDEX_FILENAME};
+    let dev = &candle_core::Device::Cpu;
+    let dir = std::env::temp_dir().join(format!("candle-sharded-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let tensors = std::collections::HashMap::from([
+        ("a", Tensor::arange(0f32, 6., dev)?.reshape((2, 3))?),
+        ("b", Tensor::arange(0u32, 2, dev)?),
+        ("c", Tensor::arange(0f64, 10., dev)?),
+        ("d", Tensor::ones(3, DType::U8, dev)?),
+    ]);
+    // a and b fit in the first 32 bytes shard, c is larger than a shard, d gets the last one.
+    let index_path = save_sharded(&tensors, &dir, 32)?;
+    assert_eq!(index_path, dir.join(INDEX_FILENAME));
+    let index: serde_json::Value = serde_json::from_slice(&std::fs::read(&index_path)?).unwrap();
+    assert_eq!(index["metadata"]["total_size"], 24 + 8 + 80 + 3);
+    let weight_map = &index["weight_map"];
+    assert_eq!(weight_map["a"], "model-00001-of-00003.safetensors");
+    assert_eq!(weight_map["b"], "model-00001-of-00003.safetensors");
+    assert_eq!(weight_map["c"], "model-00002-of-00003.safetensors");
+    assert_eq!(weight_map["d"], "model-00003-of-00003.safetensors");
+
+    let st = unsafe { MmapedSafetensors::from_index(&index_path)? };
+    assert_eq!(st.tensors()?.len(), 4);
+    let st = unsafe { MmapedSafetensors::from_index(&index_path)? };
+    assert!(st.contains("b"));
+    assert!(!st.contains("e"));
+    assert!(st.get("e").is_err());
+    // Only the last shard is needed here so removing the other ones is fine.
+    std::fs::remove_file(dir.join("model-00001-of-00003.safetensors"))?;
+    assert_eq!(st.load("d", dev)?.to_vec1::<u8>()?, [1, 1, 1]);
+    assert!(st.load("a", dev).is_err());
+    // Listing the tensors requires mapping the removed shard.
+    assert!(st.tensors().is_err());
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn safetensors_mmap() -> Result<()> {
+    use candle_core::safetensors::{Load, MmapedSafetensors};
+    use candle_core::{Storage, Var};
+    let dev = &candle_core::Device::Cpu;
+    let path = std::env::temp_dir().join(format!("candle-mmap-{}.safetensors", std::process::id()));
+    let a = Tensor::arange(0f32, 6., dev)?.reshape((2, 3))?;
+    let b = Tensor::arange(0i64, 3, dev)?;
+    candle_core::safetensors::save(
+        &std::collections::HashMap::from([("a", a), ("b", b)]),
+        &path,
+    )?;
+    let is_mmaped = |t: &Tensor| matches!(&*t.storage_and_layout().0, Storage::CpuMmap(_));
+
+    let st = unsafe { MmapedSafetensors::new(&path)? };
+    let a = st.load("a", dev)?;
+    assert!(is_mmaped(&a));
+    let copied = st.get("a")?.load(dev)?;
+    assert!(!is_mmaped(&copied));
+    let ys = a.matmul(&a.t()?)?.sqrt()?;
+    assert_eq!(
+        ys.to_vec2::<f32>()?,
+        copied.matmul(&copied.t()?)?.sqrt()?.to_vec2::<f32>()?
+    );
+    assert_eq!(a.narrow(1, 1, 2)?.to_vec2::<f32>()?, [[1., 2.], [4., 5.]]);
+    let b = st.load("b", dev)?;
+    assert!(is_mmaped(&b));
+    assert_eq!(
+        a.index_select(&b.narrow(0, 1, 1)?, 0)?.to_vec2::<f32>()?,
+        [[3., 4., 5.]]
+    );
+
+    // Custom ops operate on owned cpu storages, the mapped data gets copied once and the copy is
+    // then shared by all the views.
+    let c = st.load("a", dev)?;
+    let c_view = c.narrow(1, 0, 2)?;
+    let (scale, zero_point) = (Tensor::new(1f32, dev)?, Tensor::new(0f32, dev)?);
+    let q = c.fake_quantize(&scale, &zero_point, 0, 4)?;
+    assert_eq!(q.to_vec2::<f32>()?, [[0., 1., 2.], [3., 4., 4.]]);
+    assert!(!is_mmaped(&c));
+    assert!(!is_mmaped(&c_view));
+    assert_eq!(c_view.to_vec2::<f32>()?, [[0., 1.], [3., 4.]]);
+    assert!(is_mmaped(&a));
+
+    // Writes replace the mapped data with a copy, the file is left untouched.
+    let var = Var::from_tensor(&a)?;
+    var.set(&Tensor::zeros((2, 3), DType::F32, dev)?)?;
+    assert!(!is_mmaped(&var));
+    assert_eq!(var.sum_all()?.to_scalar::<f32>()?, 0.);
+    assert!(is_mmaped(&a));
+    assert_eq!(a.sum_all()?.to_scalar::<f32>()?, 15.);
+    a.slice_set(&Tensor::ones((1, 3), DType::F32, dev)?, 0, 1)?;
+    assert!(!is_mmaped(&a));
+    assert_eq!(a.to_vec2::<f32>()?, [[0., 1., 2.], [1., 1., 1.]]);
+    let reloaded = st.load("a", dev)?;
+    assert_eq!(reloaded.to_vec2::<f32>()?, [[0., 1., 2.], [3., 4., 5.]]);
+    drop(st);
+    // The tensors keep the mapping alive.
+    assert_eq!(reloaded.sum_all()?.to_scalar::<f32>()?, 15.);
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use candle_core::{test_device, test_utils, DType, Device, IndexOp, Result, Tensor, D};
+use candle_core::{test_device, test_utils, DType, Device, Generator, IndexOp, Result, Tensor, D};
 
 fn zeros(device: &Device) -> Result<()> {
     let tensor = Tensor::zeros((5, 2), DType::F32, device)?;
@@ -613,6 +613,188 @@ fn broadcast(device: &Device) -> Result<()> {
     Ok(())
 }
 
+fn strided_views(device: &Device) -> Result<()> {
+    let tensor = Tensor::arange(0f32, 12., device)?.reshape((3, 4))?;
+    // Views on a non-contiguous tensor do not copy for unfold, as_strided makes it contiguous.
+    let t = tensor.t()?;
+    assert_eq!(
+        t.unfold(1, 2, 1)?.to_vec3::<f32>()?,
+        [
+            [[0., 4.], [4., 8.]],
+            [[1., 5.], [5., 9.]],
+            [[2., 6.], [6., 10.]],
+            [[3., 7.], [7., 11.]]
+        ]
+    );
+    assert_eq!(
+        t.as_strided((2, 2), vec![3, 1], 1)?.to_vec2::<f32>()?,
+        [[4., 8.], [5., 9.]]
+    );
+    assert_eq!(
+        tensor
+            .narrow(1, 1, 2)?
+            .as_strided(3, vec![2], 1)?
+            .to_vec1::<f32>()?,
+        [2., 6., 10.]
+    );
+    assert_eq!(
+        tensor.as_strided((2, 3), vec![0, 5], 1)?.to_vec2::<f32>()?,
+        [[1., 6., 11.], [1., 6., 11.]]
+    );
+    assert!(tensor.as_strided((2, 3), vec![1, 5], 2).is_err());
+    assert!(tensor.as_strided((2, 3), vec![1], 0).is_err());
+    // Audio like framing.
+    let frames = tensor.flatten_all()?.unfold(0, 4, 3)?;
+    assert_eq!(
+        frames.to_vec2::<f32>()?,
+        [[0., 1., 2., 3.], [3., 4., 5., 6.], [6., 7., 8., 9.]]
+    );
+    assert!(tensor.unfold(0, 4, 1).is_err());
+    let windows = tensor.sliding_window((0, 1), &[2, 3])?;
+    assert_eq!(windows.dims(), [2, 2, 2, 3]);
+    assert_eq!(
+        windows.i((1, 1))?.to_vec2::<f32>()?,
+        [[5., 6., 7.], [9., 10., 11.]]
+    );
+    assert_eq!(windows.sum_all()?.to_scalar::<f32>()?, 132.);
+    assert_eq!(
+        tensor.flip(0)?.to_vec2::<f32>()?,
+        [[8., 9., 10., 11.], [4., 5., 6., 7.], [0., 1., 2., 3.]]
+    );
+    let xs = Tensor::arange(0u32, 24, device)?.reshape((2, 3, 4))?.t()?;
+    assert_eq!(
+        xs.flip((0, 2))?.to_vec3::<u32>()?,
+        [
+            [[20, 16, 12], [21, 17, 13], [22, 18, 14], [23, 19, 15]],
+            [[8, 4, 0], [9, 5, 1], [10, 6, 2], [11, 7, 3]]
+        ]
+    );
+    Ok(())
+}
+
+fn fancy_indexing(device: &Device) -> Result<()> {
+    let tensor = Tensor::arange(0f32, 12., device)?.reshape((3, 4))?;
+    let mask = tensor.ge(6f64)?;
+    assert_eq!(
+        tensor.masked_select(&mask)?.to_vec1::<f32>()?,
+        [6., 7., 8., 9., 10., 11.]
+    );
+    assert_eq!(
+        tensor
+            .masked_fill(&mask, 0.)?
+            .sum_all()?
+            .to_scalar::<f32>()?,
+        15.
+    );
+    assert_eq!(mask.nonzero()?.i(0)?.to_vec1::<u32>()?, [1, 2],);
+    // Multi-dimensional index tensors.
+    let ids = Tensor::new(&[[2u32, 0], [1, 1]], device)?;
+    let t = tensor.i((.., &ids))?;
+    assert_eq!(t.dims(), [3, 2, 2]);
+    assert_eq!(t.i(1)?.to_vec2::<f32>()?, [[6., 4.], [5., 5.]]);
+    let rows = Tensor::new(&[[0i64], [-1]], device)?;
+    let cols = Tensor::new(&[1i64, 3], device)?;
+    assert_eq!(
+        tensor.index_tensors(&[&rows, &cols])?.to_vec2::<f32>()?,
+        [[1., 3.], [9., 11.]]
+    );
+    assert!(tensor
+        .index_tensors(&[&Tensor::new(&[3u32], device)?])
+        .is_err());
+    let rows = Tensor::new(&[0u32, 2, 0], device)?;
+    let values = Tensor::new(&[[1f32, 2., 3., 4.]], device)?;
+    let t = tensor.index_put(&[&rows], &values, true)?;
+    assert_eq!(t.i(0)?.to_vec1::<f32>()?, [2., 5., 8., 11.]);
+    assert_eq!(t.i(2)?.to_vec1::<f32>()?, [9., 11., 13., 15.]);
+    let t = tensor.index_put(&[&rows], &values, false)?;
+    assert_eq!(t.sum_all()?.to_scalar::<f32>()?, 42.);
+    let ids = Tensor::new(&[[3u32], [0], [1]], device)?;
+    assert_eq!(
+        tensor.take_along_dim(&ids, 1)?.to_vec2::<f32>()?,
+        [[3.], [4.], [9.]]
+    );
+    let ids = Tensor::new(&[[2u32, 1, 0, 0]], device)?;
+    assert_eq!(
+        tensor.take_along_dim(&ids, 0)?.to_vec2::<f32>()?,
+        [[8., 5., 2., 3.]]
+    );
+    Ok(())
+}
+
+fn einsum(device: &Device) -> Result<()> {
+    let a = Tensor::arange(0f32, 6., device)?.reshape((2, 3))?;
+    let b = Tensor::arange(0f32, 12., device)?.reshape((3, 4))?;
+    let c = Tensor::einsum("ij,jk->ik", &[&a, &b])?;
+    assert_eq!(c.to_vec2::<f32>()?, a.matmul(&b)?.to_vec2::<f32>()?);
+    // Implicit output, the labels appearing once in alphabetical order.
+    let c = Tensor::einsum("jk,ij", &[&b, &a])?;
+    assert_eq!(c.to_vec2::<f32>()?, a.matmul(&b)?.to_vec2::<f32>()?);
+    let sq = Tensor::arange(0f32, 9., device)?.reshape((3, 3))?;
+    assert_eq!(Tensor::einsum("ii->", &[&sq])?.to_scalar::<f32>()?, 12.);
+    assert_eq!(
+        Tensor::einsum("ii->i", &[&sq])?.to_vec1::<f32>()?,
+        [0., 4., 8.]
+    );
+    assert_eq!(
+        Tensor::einsum("ij->j", &[&a])?.to_vec1::<f32>()?,
+        [3., 5., 7.]
+    );
+    let v = Tensor::new(&[1f32, 2., 3.], device)?;
+    assert_eq!(Tensor::einsum("i,i", &[&v, &v])?.to_scalar::<f32>()?, 14.);
+    assert_eq!(
+        Tensor::einsum("i,j->ij", &[&v, &v.narrow(0, 0, 2)?])?.to_vec2::<f32>()?,
+        [[1., 2.], [2., 4.], [3., 6.]]
+    );
+    // Attention scores with batch dimensions.
+    let q = Tensor::arange(0f32, 48., device)?.reshape((2, 2, 3, 4))?;
+    let k = Tensor::arange(0f32, 80., device)?.reshape((2, 2, 5, 4))?;
+    let scores = Tensor::einsum("bhqd,bhkd->bhqk", &[&q, &k])?;
+    let expected = q.matmul(&k.t()?)?;
+    assert_eq!(scores.dims(), [2, 2, 3, 5]);
+    assert_eq!(
+        (scores - &expected)?.abs()?.sum_all()?.to_scalar::<f32>()?,
+        0.
+    );
+    let out = Tensor::einsum(
+        "bqhd,bkhd->bhqk",
+        &[&q.transpose(1, 2)?, &k.transpose(1, 2)?],
+    )?;
+    assert_eq!((out - &expected)?.abs()?.sum_all()?.to_scalar::<f32>()?, 0.);
+    // Ellipsis with broadcasting.
+    let out = Tensor::einsum("...qd,kd->...qk", &[&q, &k.i((0, 0))?])?;
+    let expected = q.broadcast_matmul(&k.i((0, 0))?.t()?)?;
+    assert_eq!((out - expected)?.abs()?.sum_all()?.to_scalar::<f32>()?, 0.);
+    let out = Tensor::einsum(
+        "...ij,...jk",
+        &[&a.unsqueeze(0)?, &b.unsqueeze(0)?.repeat((2, 1, 1))?],
+    )?;
+    assert_eq!(out.dims(), [2, 2, 4]);
+    // Three operands.
+    let out = Tensor::einsum(
+        "ij,jk,k->i",
+        &[&a, &b, &Tensor::ones(4, DType::F32, device)?],
+    )?;
+    assert_eq!(out.to_vec1::<f32>()?, [98., 296.]);
+    assert!(Tensor::einsum("ij,jk->ik", &[&a, &a]).is_err());
+    assert!(Tensor::einsum("ij->ik", &[&a]).is_err());
+    assert!(Tensor::einsum("ijk->i", &[&a]).is_err());
+    // Dots are only valid as part of an ellipsis.
+    assert!(Tensor::einsum("i.j,jk->ik", &[&a, &b]).is_err());
+    assert!(Tensor::einsum("i..j,jk->ik", &[&a, &b]).is_err());
+    assert!(Tensor::einsum("....ij->ij", &[&a]).is_err());
+    assert!(Tensor::einsum("...i...j->ij", &[&a]).is_err());
+    assert_eq!(Tensor::einsum("...ij->ij", &[&a])?.dims(), [2, 3]);
+
+    let x = Tensor::arange(0f32, 24., device)?.reshape((2, 3, 4))?;
+    let y = Tensor::arange(0f32, 60., device)?.reshape((4, 3, 5))?;
+    let z = x.tensordot(&y, (1, 2), (1, 0))?;
+    let expected = Tensor::einsum("ijk,kjl->il", &[&x, &y])?;
+    assert_eq!(z.to_vec2::<f32>()?, expected.to_vec2::<f32>()?);
+    assert_eq!(x.tensordot(&a, 0, 0)?.dims(), [3, 4, 3]);
+    assert!(x.tensordot(&y, 0, 0).is_err());
+    Ok(())
+}
+
 fn cat(device: &Device) -> Result<()> {
     // 1D
     let t1 = Tensor::new(&[3f32, 1., 4.], device)?;
@@ -668,6 +850,35 @@ fn cat(device: &Device) -> Result<()> {
     Ok(())
 }
 
+fn slice_set(device: &Device) -> Result<()> {
+    let (b, h, max_t, d) = (2, 3, 6, 4);
+    let cache = Tensor::zeros((b, h, max_t, d), DType::F32, device)?;
+    let tensor = Tensor::randn(0f32, 1f32, (b, h, 4, d), device)?;
+    cache.slice_set(&tensor, 2, 0)?;
+    let cache_t = cache.narrow(2, 0, 4)?;
+    let diff = (cache_t - &tensor)?.abs()?.sum_all()?.to_vec0::<f32>()?;
+    assert_eq!(diff, 0.);
+    cache.slice_set(&tensor, 2, 1)?;
+    let cache_t = cache.narrow(2, 1, 4)?;
+    let diff = (cache_t - &tensor)?.abs()?.sum_all()?.to_vec0::<f32>()?;
+    assert_eq!(diff, 0.);
+    // Non-contiguous sources are supported too.
+    let ones = Tensor::ones((b, 2, h, d), DType::F32, device)?.transpose(1, 2)?;
+    cache.slice_set(&ones, 2, 4)?;
+    let cache_t = cache.narrow(2, 4, 2)?;
+    let diff = (cache_t - 1.0)?.abs()?.sum_all()?.to_vec0::<f32>()?;
+    assert_eq!(diff, 0.);
+    let cache_t = cache.narrow(2, 1, 3)?;
+    let diff = (cache_t - tensor.narrow(2, 0, 3)?)?
+        .abs()?
+        .sum_all()?
+        .to_vec0::<f32>()?;
+    assert_eq!(diff, 0.);
+    assert!(cache.slice_set(&tensor, 2, 3).is_err());
+    assert!(cache.slice_set(&cache, 2, 0).is_err());
+    Ok(())
+}
+
 fn embeddings(device: &Device) -> Result<()> {
     let ids = Tensor::new(&[0u32, 2u32, 1u32], device)?;
     let t = Tensor::new(&[[0f32, 1f32], [2f32, 3f32], [4f32, 5f32]], device)?;
@@ -1078,6 +1289,74 @@ fn randn(device: &Device) -> Result<()> {
     Ok(())
 }
 
+fn generator(device: &Device) -> Result<()> {
+    let mut g = Generator::new(299792458, device);
+    let t1 = g.randn(0., 1., (3, 4), DType::F32)?;
+    let t2 = g.randn(0., 1., (3, 4), DType::F32)?;
+    assert_ne!(t1.to_vec2::<f32>()?, t2.to_vec2::<f32>()?);
+    // The samples only depend on the seed, and not on the device.
+    g.manual_seed(299792458);
+    assert_eq!(
+        g.randn(0., 1., (3, 4), DType::F32)?.to_vec2::<f32>()?,
+        t1.to_vec2::<f32>()?
+    );
+    let mut cpu_g = Generator::new(299792458, &Device::Cpu);
+    let t = cpu_g.randn(0., 1., (3, 4), DType::F32)?;
+    assert_eq!(t.to_vec2::<f32>()?, t1.to_vec2::<f32>()?);
+
+    let t = g.rand(-2., 3., 1000, DType::F32)?.to_vec1::<f32>()?;
+    assert!(t.iter().all(|&v| (-2. ..3.).contains(&v)));
+    if device.is_cpu() {
+        let t = g.rand(-2., 3., 1000, DType::F64)?.to_vec1::<f64>()?;
+        assert!(t.iter().all(|&v| (-2. ..3.).contains(&v)));
+    }
+    let t = g.randint(-3, 5, 1000, DType::I64)?.to_vec1::<i64>()?;
+    assert!(t.iter().all(|&v| (-3..5).contains(&v)));
+    assert!((-3..5).all(|i| t.contains(&i)));
+    let mut perm = g.randperm(10)?.to_vec1::<u32>()?;
+    perm.sort();
+    assert_eq!(perm, (0..10).collect::<Vec<_>>());
+
+    let p = Tensor::new(&[[0f32, 1.], [0.5, 0.5]], device)?;
+    let b = g.bernoulli(&p.broadcast_as((500, 2, 2))?)?;
+    assert_eq!(b.dims(), [500, 2, 2]);
+    let b = b.mean(0)?.to_vec2::<f32>()?;
+    assert_eq!((b[0][0], b[0][1]), (0., 1.));
+    assert!((b[1][0] - 0.5).abs() < 0.1);
+
+    let mean = Tensor::new(&[-10f32, 10.], device)?;
+    let std = Tensor::new(&[[1f32], [0.]], device)?;
+    let t = g.normal(&mean, &std)?;
+    assert_eq!(t.i(1)?.to_vec1::<f32>()?, [-10., 10.]);
+    let t = g.exponential(2., 2000, DType::F32)?;
+    assert!((t.mean_all()?.to_scalar::<f32>()? - 0.5).abs() < 0.05);
+
+    let concentration = Tensor::new(&[1f32, 4.], device)?;
+    let rate = Tensor::new(&[2f32], device)?;
+    let t = g.gamma(&concentration.broadcast_as((2000, 2))?, &rate)?;
+    let t = t.mean(0)?.to_vec1::<f32>()?;
+    assert!((t[0] - 0.5).abs() < 0.1 && (t[1] - 2.).abs() < 0.2);
+    let t = g.beta(
+        &concentration.broadcast_as((2000, 2))?,
+        &Tensor::new(3f32, device)?,
+    )?;
+    let t = t.mean(0)?.to_vec1::<f32>()?;
+    assert!((t[0] - 0.25).abs() < 0.05 && (t[1] - 4. / 7.).abs() < 0.05);
+    let t = g.dirichlet(&Tensor::new(&[[1f32, 2., 3.], [0.5, 0.5, 0.5]], device)?)?;
+    let sums = t.sum(1)?.to_vec1::<f32>()?;
+    assert!(sums.iter().all(|s| (s - 1.).abs() < 1e-5));
+
+    let probs = Tensor::new(&[[0f32, 1., 0.], [1., 1., 1.]], device)?;
+    let t = g.multinomial(&probs, 3, true)?.to_vec2::<u32>()?;
+    assert_eq!(t[0], [1, 1, 1]);
+    let mut t = g.multinomial(&probs.i(1)?, 3, false)?.to_vec1::<u32>()?;
+    t.sort();
+    assert_eq!(t, [0, 1, 2]);
+    assert!(g.multinomial(&probs, 2, false).is_err());
+    assert!(g.rand(1., 1., 2, DType::F32).is_err());
+    Ok(())
+}
+
 test_device!(zeros, zeros_cpu, zeros_gpu, zeros_metal);
 test_device!(ones, ones_cpu, ones_gpu, ones_metal);
 test_device!(full, full_cpu, full_gpu, full_metal);
@@ -1087,6 +1366,20 @@ test_device!(tensor_2d, tensor_2d_cpu, tensor_2d_gpu, tensor_2d_metal);
 test_device!(narrow, narrow_cpu, narrow_gpu, narrow_metal);
 test_device!(broadcast, broadcast_cpu, broadcast_gpu, broadcast_metal);
 test_device!(cat, cat_cpu, cat_gpu, cat_metal);
+test_device!(
+    strided_views,
+    strided_views_cpu,
+    strided_views_gpu,
+    strided_views_metal
+);
+test_device!(
+    fancy_indexing,
+    fancy_indexing_cpu,
+    fancy_indexing_gpu,
+    fancy_indexing_metal
+);
+test_device!(einsum, einsum_cpu, einsum_gpu, einsum_metal);
+test_device!(slice_set, ss_cpu, ss_gpu, ss_metal);
 test_device!(sum, sum_cpu, sum_gpu, sum_metal);
 test_device!(min, min_cpu, min_gpu, min_metal);
 test_device!(max, max_cpu, max_gpu, max_metal);
@@ -1131,6 +1424,7 @@ test_device!(
     slice_scatter_metal
 );
 test_device!(randn, randn_cpu, randn_gpu, randn_metal);
+test_device!(generator, generator_cpu, generator_gpu, generator_metal);
 test_device!(clamp, clamp_cpu, clamp_gpu, clamp_metal);
 test_device!(var, var_cpu, var_gpu, var_metal);
 
@@ -1265,3 +1559,55 @@ fn pow() -> Result<()> {
     );
     Ok(())
 }
+
+#[test]
+fn cpu_pool() -> Result<()> {
+    let pool = Device::new_cpu_pool(2)?;
+    assert!(pool.is_cpu());
+    assert!(pool.same_device(&pool.clone()));
+    assert!(!pool.same_device(&Device::Cpu));
+    assert!(!pool.same_device(&Device::new_cpu_pool(2)?));
+
+    let lhs = Tensor::arange(0f32, 24., &Device::Cpu)?.reshape((2, 3, 4))?;
+    let rhs = Tensor::arange(0f32, 40., &Device::Cpu)?.reshape((2, 4, 5))?;
+    let expected = lhs.matmul(&rhs)?.sum_all()?;
+    let (lhs, rhs) = (lhs.to_device(&pool)?, rhs.to_device(&pool)?);
+    // The results of the operations stay on the device with the thread pool.
+    let res = lhs.matmul(&rhs)?.sum_all()?;
+    assert!(matches!(res.device(), Device::CpuPool(_)));
+    assert!(res.device().same_device(&pool));
+    assert_eq!(res.to_vec0::<f32>()?, expected.to_vec0::<f32>()?);
+
+    let xs = Tensor::ones((1, 2, 5, 5), DType::F32, &pool)?;
+    let kernel = Tensor::ones((3, 2, 3, 3), DType::F32, &pool)?;
+    let ys = xs.conv2d(&kernel, 1, 1, 1, 1)?;
+    assert!(ys.device().same_device(&pool));
+    assert_eq!(ys.i((0, 0, 2, 2))?.to_vec0::<f32>()?, 18.);
+    let ys = ys.to_device(&Device::Cpu)?;
+    assert!(matches!(ys.device(), Device::Cpu));
+
+    // Moving between cpu devices shares the data rather than copying it.
+    let xs = Tensor::zeros((2, 3), DType::F32, &Device::Cpu)?;
+    let pool_xs = xs.to_device(&pool)?;
+    xs.slice_set(&Tensor::ones((1, 3), DType::F32, &Device::Cpu)?, 0, 1)?;
+    assert_eq!(pool_xs.to_vec2::<f32>()?, [[0., 0., 0.], [1., 1., 1.]]);
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn cpu_pool_affinity() -> Result<()> {
+    let device = Device::new_cpu_pool_with_affinity(&[0])?;
+    match &device {
+        Device::CpuPool(pool) => {
+            assert_eq!(pool.num_threads(), 1);
+            assert_eq!(pool.core_ids(), Some([0].as_slice()));
+        }
+        _ => panic!("unexpected device {device:?}"),
+    }
+    let xs = Tensor::arange(0f32, 6., &device)?.reshape((2, 3))?;
+    let ys = xs.matmul(&xs.t()?)?;
+    assert_eq!(ys.to_vec2::<f32>()?, [[5., 14.], [14., 50.]]);
+    assert!(Device::new_cpu_pool_with_affinity(&[]).is_err());
+    Ok(())
+}
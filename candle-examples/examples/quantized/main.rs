@@ -25,6 +25,23 @@ enum Prompt {
     One(String),
 }
 
+#[derive(Clone, Debug, Copy, PartialEq, Eq, ValueEnum)]
+enum KvCacheDType {
+    #[value(name = "q8_0")]
+    Q8_0,
+    #[value(name = "int8")]
+    Int8,
+}
+
+impl KvCacheDType {
+    fn dtype(&self) -> model::QuantizedKvDType {
+        match self {
+            Self::Q8_0 => model::QuantizedKvDType::Q8_0,
+            Self::Int8 => model::QuantizedKvDType::Int8,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Copy, PartialEq, Eq, ValueEnum)]
 enum Which {
     #[value(name = "7b")]
@@ -227,6 +244,10 @@ struct Args {
     /// Group-Query Attention, use 8 for the 70B version of LLaMAv2.
     #[arg(long)]
     gqa: Option<usize>,
+
+    /// Store the kv cache in a quantized format rather than in f32.
+    #[arg(long)]
+    kv_cache_dtype: Option<KvCacheDType>,
 }
 
 impl Args {
@@ -421,6 +442,7 @@ fn main() -> anyhow::Result<()> {
             ModelWeights::from_ggml(model, args.gqa.unwrap_or(default_gqa))?
         }
     };
+    model.set_kv_cache_dtype(args.kv_cache_dtype.map(|d| d.dtype()));
     println!("model built");
 
     let tokenizer = args.tokenizer()?;
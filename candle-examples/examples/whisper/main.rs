@@ -542,7 +542,7 @@ fn main() -> Result<()> {
         anyhow::bail!("input file must have a {} sampling rate", m::SAMPLE_RATE)
     }
     println!("pcm data loaded {}", pcm_data.len());
-    let mel = audio::pcm_to_mel(&config, &pcm_data, &mel_filters);
+    let mel = audio::pcm_to_mel(&config, &pcm_data, &mel_filters)?;
     let mel_len = mel.len();
     let mel = Tensor::from_vec(
         mel,
@@ -0,0 +1,174 @@
+//! Data parallel training
+//!
+//! Each rank of a process group holds a full copy of the model and computes the gradients on its
+//! own part of the batch. [`DistributedOptimizer`] wraps any [`Optimizer`] so that, before each
+//! step, the gradients are averaged over all the ranks. The variables are broadcast from rank 0
+//! when the optimizer is created so that all the ranks start from the same weights, and they then
+//! stay in sync as all the ranks apply the same updates.
+//!
+//! The variables have to be passed in the same order on all the ranks, e.g. using
+//! [`crate::VarMap::sorted_vars`].
+use crate::Optimizer;
+use candle::backprop::GradStore;
+use candle::distributed::{Communicator, ReduceOp};
+use candle::{Result, Tensor, Var};
+use std::sync::Arc;
+
+/// The default maximum size of the gradient buckets, in bytes.
+pub const DEFAULT_BUCKET_SIZE: usize = 25 * 1024 * 1024;
+
+#[derive(Clone)]
+pub struct ParamsDistributed<C> {
+    /// The configuration of the wrapped optimizer.
+    pub inner: C,
+    pub comm: Arc<dyn Communicator>,
+    /// The gradients are concatenated in buckets of up to this many bytes so that there is a
+    /// single all-reduce per bucket rather than per variable.
+    pub bucket_size: usize,
+}
+
+impl<C> ParamsDistributed<C> {
+    pub fn new(inner: C, comm: Arc<dyn Communicator>) -> Self {
+        Self {
+            inner,
+            comm,
+            bucket_size: DEFAULT_BUCKET_SIZE,
+        }
+    }
+}
+
+/// Sets the variables to the values they have on rank 0.
+pub fn broadcast_vars(vars: &[Var], comm: &dyn Communicator) -> Result<()> {
+    if comm.world_size() == 1 {
+        return Ok(());
+    }
+    for var in vars.iter() {
+        let value = comm.broadcast(var.as_tensor(), 0)?;
+        if comm.rank() != 0 {
+            var.set(&value)?
+        }
+    }
+    Ok(())
+}
+
+/// An optimizer that averages the gradients over all the ranks before running the wrapped
+/// optimizer.
+pub struct DistributedOptimizer<O: Optimizer> {
+    inner: O,
+    vars: Vec<Var>,
+    comm: Arc<dyn Communicator>,
+    bucket_size: usize,
+}
+
+impl<O: Optimizer> DistributedOptimizer<O> {
+    pub fn inner(&self) -> &O {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> O {
+        self.inner
+    }
+
+    pub fn comm(&self) -> &Arc<dyn Communicator> {
+        &self.comm
+    }
+
+    /// Replaces the gradients in `grads` with their average over all the ranks. A variable
+    /// without gradient on some rank is treated as having a zero gradient there, so after this
+    /// all the variables have a gradient.
+    pub fn all_reduce_grads(&self, grads: &mut GradStore) -> Result<()> {
+        let mut bucket: Vec<(&Var, Tensor)> = vec![];
+        let mut bucket_size = 0;
+        for var in self.vars.iter() {
+            let grad = match grads.get(var) {
+                Some(grad) => grad.flatten_all()?,
+                None => var.zeros_like()?.flatten_all()?,
+            };
+            let size = grad.elem_count() * grad.dtype().size_in_bytes();
+            // The buckets only contain gradients of the same dtype so that they can be
+            // concatenated. As the variables are in the same order on all the ranks, the buckets
+            // are the same everywhere.
+            let dtype_changed = bucket
+                .first()
+                .is_some_and(|(_, g)| g.dtype() != grad.dtype());
+            if !bucket.is_empty() && (dtype_changed || bucket_size + size > self.bucket_size) {
+                self.all_reduce_bucket(&mut bucket, grads)?;
+                bucket_size = 0;
+            }
+            bucket.push((var, grad));
+            bucket_size += size;
+        }
+        if !bucket.is_empty() {
+            self.all_reduce_bucket(&mut bucket, grads)?;
+        }
+        Ok(())
+    }
+
+    fn all_reduce_bucket(
+        &self,
+        bucket: &mut Vec<(&Var, Tensor)>,
+        grads: &mut GradStore,
+    ) -> Result<()> {
+        let flat = bucket.iter().map(|(_, g)| g).collect::<Vec<_>>();
+        let flat = Tensor::cat(&flat, 0)?;
+        let flat = self.comm.all_reduce(&flat, ReduceOp::Sum)?;
+        let flat = (flat / self.comm.world_size() as f64)?;
+        let mut offset = 0;
+        for (var, grad) in bucket.drain(..) {
+            let n = grad.elem_count();
+            let grad = flat.narrow(0, offset, n)?.reshape(var.shape())?;
+            grads.insert(var, grad);
+            offset += n;
+        }
+        Ok(())
+    }
+}
+
+impl<O: Optimizer> Optimizer for DistributedOptimizer<O> {
+    type Config = ParamsDistributed<O::Config>;
+
+    fn new(vars: Vec<Var>, config: Self::Config) -> Result<Self> {
+        let ParamsDistributed {
+            inner,
+            comm,
+            bucket_size,
+        } = config;
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .collect::<Vec<_>>();
+        broadcast_vars(&vars, comm.as_ref())?;
+        let inner = O::new(vars.clone(), inner)?;
+        Ok(Self {
+            inner,
+            vars,
+            comm,
+            bucket_size,
+        })
+    }
+
+    fn step(&mut self, grads: &GradStore) -> Result<()> {
+        if self.comm.world_size() == 1 {
+            return self.inner.step(grads);
+        }
+        let mut grads = grads.clone();
+        self.all_reduce_grads(&mut grads)?;
+        self.inner.step(&grads)
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.inner.learning_rate()
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.inner.set_learning_rate(lr)
+    }
+
+    fn backward_step(&mut self, loss: &Tensor) -> Result<()> {
+        let mut grads = loss.backward()?;
+        if self.comm.world_size() > 1 {
+            self.all_reduce_grads(&mut grads)?;
+        }
+        self.inner.step(&grads)
+    }
+}
@@ -0,0 +1,684 @@
+//! Cache Implementations
+//!
+//! Key-value caches used by attention layers during auto-regressive generation. The caches
+//! preallocate their storage and append new entries in place rather than concatenating the
+//! whole history on each step.
+use candle::quantized::k_quants::{BlockQ8_0, GgmlType};
+use candle::{DType, Result, Tensor};
+use rayon::prelude::*;
+
+/// A growable buffer along dimension `dim`, the storage is allocated on the first append and
+/// new values are copied in place using [`Tensor::slice_set`].
+///
+/// As the storage is modified in place, cloning a non-empty cache results in both instances
+/// sharing the same storage.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    // all_data is an option on a Tensor, this makes it possible to only create the actual tensor
+    // on the first call where the batch size is easily known.
+    // Also this makes it safe to clone a Cache that has been reset (as in it will not share
+    // its internal state with the cloned instance).
+    all_data: Option<Tensor>,
+    dim: usize,
+    current_seq_len: usize,
+    max_seq_len: usize,
+}
+
+impl Cache {
+    /// Creates a new cache along dimension `dim`, `max_seq_len` is the number of elements that
+    /// are preallocated on the first append. The storage grows if this turns out to be too small.
+    pub fn new(dim: usize, max_seq_len: usize) -> Self {
+        Self {
+            all_data: None,
+            dim,
+            current_seq_len: 0,
+            max_seq_len,
+        }
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    pub fn current_seq_len(&self) -> usize {
+        self.current_seq_len
+    }
+
+    /// The number of elements along `dim` that can be stored without reallocating.
+    pub fn max_seq_len(&self) -> usize {
+        self.max_seq_len
+    }
+
+    /// The underlying storage, this includes the preallocated slots that have not been filled.
+    pub fn all_data(&self) -> &Option<Tensor> {
+        &self.all_data
+    }
+
+    /// The values that have been appended so far, `None` if the cache is empty.
+    pub fn current_data(&self) -> Result<Option<Tensor>> {
+        let data = match self.all_data.as_ref() {
+            None => None,
+            Some(_) if self.current_seq_len == 0 => None,
+            Some(d) => Some(d.narrow(self.dim, 0, self.current_seq_len)?),
+        };
+        Ok(data)
+    }
+
+    /// Empties the cache and releases the underlying storage.
+    pub fn reset(&mut self) {
+        self.current_seq_len = 0;
+        self.all_data = None;
+    }
+
+    /// Only keep the first `len` elements, this is a no-op if the cache holds fewer elements.
+    pub fn truncate(&mut self, len: usize) {
+        self.current_seq_len = self.current_seq_len.min(len)
+    }
+
+    /// The size in bytes of the allocated storage.
+    pub fn allocated_bytes(&self) -> usize {
+        match self.all_data.as_ref() {
+            None => 0,
+            Some(d) => d.elem_count() * d.dtype().size_in_bytes(),
+        }
+    }
+
+    /// The size in bytes of the values currently stored in the cache.
+    pub fn used_bytes(&self) -> usize {
+        match self.all_data.as_ref() {
+            Some(_) if self.max_seq_len > 0 => {
+                self.allocated_bytes() / self.max_seq_len * self.current_seq_len
+            }
+            _ => 0,
+        }
+    }
+
+    fn alloc(&self, src: &Tensor, max_seq_len: usize) -> Result<Tensor> {
+        let mut shape = src.dims().to_vec();
+        shape[self.dim] = max_seq_len;
+        Tensor::zeros(shape, src.dtype(), src.device())
+    }
+
+    pub fn append(&mut self, src: &Tensor) -> Result<()> {
+        let seq_len = src.dim(self.dim)?;
+        let required = self.current_seq_len + seq_len;
+        let all_data = match self.all_data.take() {
+            None => {
+                self.max_seq_len = usize::max(self.max_seq_len, required);
+                self.alloc(src, self.max_seq_len)?
+            }
+            Some(all_data) if required > self.max_seq_len => {
+                let max_seq_len = usize::max(2 * self.max_seq_len, required);
+                let new_data = self.alloc(src, max_seq_len)?;
+                if self.current_seq_len > 0 {
+                    let prev = all_data.narrow(self.dim, 0, self.current_seq_len)?;
+                    new_data.slice_set(&prev, self.dim, 0)?;
+                }
+                self.max_seq_len = max_seq_len;
+                new_data
+            }
+            Some(all_data) => all_data,
+        };
+        all_data.slice_set(src, self.dim, self.current_seq_len)?;
+        self.all_data = Some(all_data);
+        self.current_seq_len = required;
+        Ok(())
+    }
+}
+
+/// A pair of [`Cache`] for the keys and values of an attention layer.
+#[derive(Debug, Clone)]
+pub struct KvCache {
+    k: Cache,
+    v: Cache,
+}
+
+impl KvCache {
+    pub fn new(dim: usize, max_seq_len: usize) -> Self {
+        let k = Cache::new(dim, max_seq_len);
+        let v = Cache::new(dim, max_seq_len);
+        Self { k, v }
+    }
+
+    pub fn k_cache(&self) -> &Cache {
+        &self.k
+    }
+
+    pub fn v_cache(&self) -> &Cache {
+        &self.v
+    }
+
+    pub fn k_cache_mut(&mut self) -> &mut Cache {
+        &mut self.k
+    }
+
+    pub fn v_cache_mut(&mut self) -> &mut Cache {
+        &mut self.v
+    }
+
+    pub fn k(&self) -> Result<Option<Tensor>> {
+        self.k.current_data()
+    }
+
+    pub fn v(&self) -> Result<Option<Tensor>> {
+        self.v.current_data()
+    }
+
+    /// Appends the new keys and values and returns the full content of the cache.
+    pub fn append(&mut self, k: &Tensor, v: &Tensor) -> Result<(Tensor, Tensor)> {
+        self.k.append(k)?;
+        self.v.append(v)?;
+        let out_k = self.k.current_data()?;
+        let out_v = self.v.current_data()?;
+        let k = match out_k {
+            None => k.narrow(self.k.dim, 0, 0)?,
+            Some(k) => k,
+        };
+        let v = match out_v {
+            None => v.narrow(self.v.dim, 0, 0)?,
+            Some(v) => v,
+        };
+        Ok((k, v))
+    }
+
+    pub fn current_seq_len(&self) -> usize {
+        self.k.current_seq_len()
+    }
+
+    pub fn reset(&mut self) {
+        self.k.reset();
+        self.v.reset();
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.k.truncate(len);
+        self.v.truncate(len);
+    }
+
+    pub fn allocated_bytes(&self) -> usize {
+        self.k.allocated_bytes() + self.v.allocated_bytes()
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        self.k.used_bytes() + self.v.used_bytes()
+    }
+}
+
+/// A fixed size ring buffer along dimension `dim` that only retains the last `max_seq_len`
+/// values, this is used for sliding window attention.
+#[derive(Debug, Clone)]
+pub struct RotatingCache {
+    all_data: Option<Tensor>,
+    dim: usize,
+    // `offset` is the total number of values that have been appended, the next value gets
+    // written at position `offset % max_seq_len`.
+    offset: usize,
+    current_seq_len: usize,
+    max_seq_len: usize,
+}
+
+impl RotatingCache {
+    pub fn new(dim: usize, max_seq_len: usize) -> Self {
+        Self {
+            all_data: None,
+            dim,
+            offset: 0,
+            current_seq_len: 0,
+            max_seq_len,
+        }
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// The total number of values that have been appended since the last reset.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The number of values currently stored, this is at most `max_seq_len`.
+    pub fn current_seq_len(&self) -> usize {
+        self.current_seq_len
+    }
+
+    /// The size of the sliding window.
+    pub fn max_seq_len(&self) -> usize {
+        self.max_seq_len
+    }
+
+    pub fn all_data(&self) -> &Option<Tensor> {
+        &self.all_data
+    }
+
+    /// The values currently stored in the cache, from the oldest to the most recent one.
+    pub fn current_data(&self) -> Result<Option<Tensor>> {
+        match self.all_data.as_ref() {
+            Some(all_data) if self.current_seq_len > 0 => Ok(Some(self.chronological(all_data)?)),
+            _ => Ok(None),
+        }
+    }
+
+    fn chronological(&self, all_data: &Tensor) -> Result<Tensor> {
+        if self.current_seq_len < self.max_seq_len {
+            return all_data.narrow(self.dim, 0, self.current_seq_len);
+        }
+        let start = self.offset % self.max_seq_len;
+        if start == 0 {
+            Ok(all_data.clone())
+        } else {
+            let oldest = all_data.narrow(self.dim, start, self.max_seq_len - start)?;
+            let newest = all_data.narrow(self.dim, 0, start)?;
+            Tensor::cat(&[&oldest, &newest], self.dim)
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.offset = 0;
+        self.current_seq_len = 0;
+        self.all_data = None;
+    }
+
+    pub fn allocated_bytes(&self) -> usize {
+        match self.all_data.as_ref() {
+            None => 0,
+            Some(d) => d.elem_count() * d.dtype().size_in_bytes(),
+        }
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        match self.all_data.as_ref() {
+            Some(_) if self.max_seq_len > 0 => {
+                self.allocated_bytes() / self.max_seq_len * self.current_seq_len
+            }
+            _ => 0,
+        }
+    }
+
+    /// Appends `src` to the cache and returns the values to attend over.
+    ///
+    /// When a single value is appended to a full cache, the whole ring buffer is returned as is
+    /// so the values are not in chronological order. This is fine for attention as long as no
+    /// mask has to be applied. In all the other cases the returned values are the previously
+    /// stored values followed by `src`, in chronological order, so that the result can be
+    /// longer than the window when multiple values are appended at once.
+    pub fn append(&mut self, src: &Tensor) -> Result<Tensor> {
+        let seq_len = src.dim(self.dim)?;
+        let all_data = match self.all_data.take() {
+            Some(all_data) => all_data,
+            None => {
+                let mut shape = src.dims().to_vec();
+                shape[self.dim] = self.max_seq_len;
+                Tensor::zeros(shape, src.dtype(), src.device())?
+            }
+        };
+        let out = if self.offset + seq_len <= self.max_seq_len {
+            all_data.slice_set(src, self.dim, self.offset)?;
+            all_data.narrow(self.dim, 0, self.offset + seq_len)?
+        } else if seq_len == 1 {
+            all_data.slice_set(src, self.dim, self.offset % self.max_seq_len)?;
+            all_data.clone()
+        } else {
+            let out = if self.current_seq_len == 0 {
+                src.clone()
+            } else {
+                let prev = self.chronological(&all_data)?;
+                Tensor::cat(&[&prev, src], self.dim)?
+            };
+            // Only the last max_seq_len values have to be written, possibly in two chunks when
+            // wrapping around the end of the buffer.
+            let to_write = usize::min(seq_len, self.max_seq_len);
+            let src = src.narrow(self.dim, seq_len - to_write, to_write)?;
+            let pos = (self.offset + seq_len - to_write) % self.max_seq_len;
+            let first_len = usize::min(to_write, self.max_seq_len - pos);
+            all_data.slice_set(&src.narrow(self.dim, 0, first_len)?, self.dim, pos)?;
+            if first_len < to_write {
+                let rest = src.narrow(self.dim, first_len, to_write - first_len)?;
+                all_data.slice_set(&rest, self.dim, 0)?;
+            }
+            out
+        };
+        self.all_data = Some(all_data);
+        self.offset += seq_len;
+        self.current_seq_len = usize::min(self.offset, self.max_seq_len);
+        Ok(out)
+    }
+}
+
+/// A pair of [`RotatingCache`] for the keys and values of a sliding window attention layer.
+#[derive(Debug, Clone)]
+pub struct RotatingKvCache {
+    k: RotatingCache,
+    v: RotatingCache,
+}
+
+impl RotatingKvCache {
+    pub fn new(dim: usize, max_seq_len: usize) -> Self {
+        let k = RotatingCache::new(dim, max_seq_len);
+        let v = RotatingCache::new(dim, max_seq_len);
+        Self { k, v }
+    }
+
+    pub fn k_cache(&self) -> &RotatingCache {
+        &self.k
+    }
+
+    pub fn v_cache(&self) -> &RotatingCache {
+        &self.v
+    }
+
+    pub fn k(&self) -> Result<Option<Tensor>> {
+        self.k.current_data()
+    }
+
+    pub fn v(&self) -> Result<Option<Tensor>> {
+        self.v.current_data()
+    }
+
+    /// Appends the new keys and values, see [`RotatingCache::append`] for the ordering of the
+    /// returned values.
+    pub fn append(&mut self, k: &Tensor, v: &Tensor) -> Result<(Tensor, Tensor)> {
+        let out_k = self.k.append(k)?;
+        let out_v = self.v.append(v)?;
+        Ok((out_k, out_v))
+    }
+
+    pub fn offset(&self) -> usize {
+        self.k.offset()
+    }
+
+    pub fn current_seq_len(&self) -> usize {
+        self.k.current_seq_len()
+    }
+
+    pub fn reset(&mut self) {
+        self.k.reset();
+        self.v.reset();
+    }
+
+    pub fn allocated_bytes(&self) -> usize {
+        self.k.allocated_bytes() + self.v.allocated_bytes()
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        self.k.used_bytes() + self.v.used_bytes()
+    }
+}
+
+/// The storage format for the entries of a [`QuantizedKvCache`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuantizedKvDType {
+    /// GGML `Q8_0` blocks of 32 values sharing a f16 scale, the head dimension has to be a
+    /// multiple of 32.
+    Q8_0,
+    /// int8 values with one f32 scale per head and position.
+    Int8,
+}
+
+/// The quantized keys or values for a single head, one row per position.
+#[derive(Debug, Clone)]
+enum QuantizedRows {
+    Q8_0(Vec<BlockQ8_0>),
+    Int8 { qs: Vec<i8>, scales: Vec<f32> },
+}
+
+impl QuantizedRows {
+    fn new(dtype: QuantizedKvDType) -> Self {
+        match dtype {
+            QuantizedKvDType::Q8_0 => Self::Q8_0(vec![]),
+            QuantizedKvDType::Int8 => Self::Int8 {
+                qs: vec![],
+                scales: vec![],
+            },
+        }
+    }
+
+    fn push(&mut self, row: &[f32]) -> Result<()> {
+        match self {
+            Self::Q8_0(blocks) => {
+                let len = blocks.len();
+                blocks.resize(len + row.len() / BlockQ8_0::BLCK_SIZE, BlockQ8_0::zeros());
+                BlockQ8_0::from_float(row, &mut blocks[len..])?
+            }
+            Self::Int8 { qs, scales } => {
+                let amax = row.iter().fold(0f32, |acc, v| acc.max(v.abs()));
+                let scale = amax / 127.;
+                let inv_scale = if scale == 0. { 0. } else { 1. / scale };
+                qs.extend(row.iter().map(|v| (v * inv_scale).round() as i8));
+                scales.push(scale)
+            }
+        }
+        Ok(())
+    }
+
+    fn truncate(&mut self, n_rows: usize, head_dim: usize) {
+        match self {
+            Self::Q8_0(blocks) => blocks.truncate(n_rows * head_dim / BlockQ8_0::BLCK_SIZE),
+            Self::Int8 { qs, scales } => {
+                qs.truncate(n_rows * head_dim);
+                scales.truncate(n_rows)
+            }
+        }
+    }
+
+    fn size_in_bytes(&self) -> usize {
+        match self {
+            Self::Q8_0(blocks) => std::mem::size_of_val(blocks.as_slice()),
+            Self::Int8 { qs, scales } => qs.len() + std::mem::size_of_val(scales.as_slice()),
+        }
+    }
+
+    /// The dot product between the row at `row_idx` and `q`, `q_blocks` has to contain `q`
+    /// quantized to `Q8_0` when using this format.
+    fn dot(&self, row_idx: usize, q: &[f32], q_blocks: &[BlockQ8_0]) -> Result<f32> {
+        let head_dim = q.len();
+        match self {
+            Self::Q8_0(blocks) => {
+                let nb = head_dim / BlockQ8_0::BLCK_SIZE;
+                BlockQ8_0::vec_dot(
+                    head_dim,
+                    &blocks[row_idx * nb..(row_idx + 1) * nb],
+                    q_blocks,
+                )
+            }
+            Self::Int8 { qs, scales } => {
+                let qs = &qs[row_idx * head_dim..(row_idx + 1) * head_dim];
+                let dot = qs
+                    .iter()
+                    .zip(q.iter())
+                    .map(|(&k, q)| k as f32 * q)
+                    .sum::<f32>();
+                Ok(dot * scales[row_idx])
+            }
+        }
+    }
+
+    /// Adds `alpha` times the row at `row_idx` to `dst`, `buf` is used as scratch space.
+    fn axpy(&self, row_idx: usize, alpha: f32, dst: &mut [f32], buf: &mut [f32]) -> Result<()> {
+        let head_dim = dst.len();
+        match self {
+            Self::Q8_0(blocks) => {
+                let nb = head_dim / BlockQ8_0::BLCK_SIZE;
+                BlockQ8_0::to_float(&blocks[row_idx * nb..(row_idx + 1) * nb], buf)?;
+                for (d, v) in dst.iter_mut().zip(buf.iter()) {
+                    *d += alpha * v
+                }
+            }
+            Self::Int8 { qs, scales } => {
+                let qs = &qs[row_idx * head_dim..(row_idx + 1) * head_dim];
+                let alpha = alpha * scales[row_idx];
+                for (d, &v) in dst.iter_mut().zip(qs.iter()) {
+                    *d += alpha * v as f32
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A key-value cache storing its entries in a quantized format, this reduces the memory used for
+/// long contexts. The attention is computed directly against the quantized entries using
+/// [`QuantizedKvCache::attention`], this is only supported on the cpu.
+///
+/// The keys and values have shape `(b_sz, num_kv_heads, seq_len, head_dim)`.
+#[derive(Debug, Clone)]
+pub struct QuantizedKvCache {
+    dtype: QuantizedKvDType,
+    /// The batch size, number of heads, and head dimension, set on the first append.
+    dims: Option<(usize, usize, usize)>,
+    k: Vec<QuantizedRows>,
+    v: Vec<QuantizedRows>,
+    current_seq_len: usize,
+}
+
+impl QuantizedKvCache {
+    pub fn new(dtype: QuantizedKvDType) -> Self {
+        Self {
+            dtype,
+            dims: None,
+            k: vec![],
+            v: vec![],
+            current_seq_len: 0,
+        }
+    }
+
+    pub fn dtype(&self) -> QuantizedKvDType {
+        self.dtype
+    }
+
+    pub fn current_seq_len(&self) -> usize {
+        self.current_seq_len
+    }
+
+    pub fn reset(&mut self) {
+        self.dims = None;
+        self.k.clear();
+        self.v.clear();
+        self.current_seq_len = 0;
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        if let Some((_, _, head_dim)) = self.dims {
+            let len = usize::min(len, self.current_seq_len);
+            for rows in self.k.iter_mut().chain(self.v.iter_mut()) {
+                rows.truncate(len, head_dim)
+            }
+            self.current_seq_len = len
+        }
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        self.k
+            .iter()
+            .chain(self.v.iter())
+            .map(|rows| rows.size_in_bytes())
+            .sum()
+    }
+
+    /// Quantizes and appends the new keys and values.
+    pub fn append(&mut self, k: &Tensor, v: &Tensor) -> Result<()> {
+        let (b_sz, num_kv_heads, seq_len, head_dim) = k.dims4()?;
+        if v.dims() != k.dims() {
+            candle::bail!("kv-cache: key and value shapes differ {k:?} {v:?}")
+        }
+        if self.dtype == QuantizedKvDType::Q8_0 && head_dim % BlockQ8_0::BLCK_SIZE != 0 {
+            candle::bail!("kv-cache: head dim {head_dim} is not a multiple of the q8_0 block size")
+        }
+        match self.dims {
+            None => {
+                self.dims = Some((b_sz, num_kv_heads, head_dim));
+                let n = b_sz * num_kv_heads;
+                self.k = vec![QuantizedRows::new(self.dtype); n];
+                self.v = vec![QuantizedRows::new(self.dtype); n];
+            }
+            Some(dims) => {
+                if dims != (b_sz, num_kv_heads, head_dim) {
+                    candle::bail!("kv-cache: unexpected shape {:?}, cache {dims:?}", k.shape())
+                }
+            }
+        }
+        let to_vec = |t: &Tensor| t.to_dtype(DType::F32)?.flatten_all()?.to_vec1::<f32>();
+        let (k, v) = (to_vec(k)?, to_vec(v)?);
+        let row_len = seq_len * head_dim;
+        for (idx, (k_rows, v_rows)) in self.k.iter_mut().zip(self.v.iter_mut()).enumerate() {
+            let k = &k[idx * row_len..(idx + 1) * row_len];
+            let v = &v[idx * row_len..(idx + 1) * row_len];
+            for (k, v) in k.chunks(head_dim).zip(v.chunks(head_dim)) {
+                k_rows.push(k)?;
+                v_rows.push(v)?;
+            }
+        }
+        self.current_seq_len += seq_len;
+        Ok(())
+    }
+
+    /// Computes `softmax(q @ k^T * softmax_scale) @ v` over the cached keys and values, `q` has
+    /// shape `(b_sz, num_heads, q_len, head_dim)` and the queries are for the last `q_len`
+    /// positions. When `num_heads` is a multiple of `num_kv_heads`, each key-value head is
+    /// shared by a group of query heads. With `causal`, each query only attends to the positions
+    /// up to its own.
+    pub fn attention(&self, q: &Tensor, softmax_scale: f64, causal: bool) -> Result<Tensor> {
+        let (b_sz, num_heads, q_len, head_dim) = q.dims4()?;
+        let (cache_b_sz, num_kv_heads, cache_head_dim) = match self.dims {
+            None => candle::bail!("kv-cache: attention over an empty cache"),
+            Some(dims) => dims,
+        };
+        if cache_b_sz != b_sz || cache_head_dim != head_dim || num_heads % num_kv_heads != 0 {
+            candle::bail!(
+                "kv-cache: query shape {:?} incompatible with cache {:?}",
+                q.shape(),
+                self.dims
+            )
+        }
+        let kv_len = self.current_seq_len;
+        if q_len > kv_len {
+            candle::bail!("kv-cache: {q_len} queries but only {kv_len} cached positions")
+        }
+        let n_rep = num_heads / num_kv_heads;
+        let softmax_scale = softmax_scale as f32;
+        let qs = q.to_dtype(DType::F32)?.flatten_all()?.to_vec1::<f32>()?;
+        let mut dst = vec![0f32; qs.len()];
+        q.device().install(|| {
+            dst.par_chunks_mut(q_len * head_dim)
+                .zip(qs.par_chunks(q_len * head_dim))
+                .enumerate()
+                .try_for_each(|(idx, (dst, qs))| {
+                    let (b_idx, h_idx) = (idx / num_heads, idx % num_heads);
+                    let kv_idx = b_idx * num_kv_heads + h_idx / n_rep;
+                    let (k, v) = (&self.k[kv_idx], &self.v[kv_idx]);
+                    let mut q_blocks = vec![BlockQ8_0::zeros(); head_dim / BlockQ8_0::BLCK_SIZE];
+                    let mut scores = vec![0f32; kv_len];
+                    let mut buf = vec![0f32; head_dim];
+                    for (i, (dst, q)) in dst
+                        .chunks_mut(head_dim)
+                        .zip(qs.chunks(head_dim))
+                        .enumerate()
+                    {
+                        let n_keys = if causal {
+                            kv_len - q_len + i + 1
+                        } else {
+                            kv_len
+                        };
+                        if self.dtype == QuantizedKvDType::Q8_0 {
+                            BlockQ8_0::from_float(q, &mut q_blocks)?
+                        }
+                        let mut max_score = f32::NEG_INFINITY;
+                        for (t, score) in scores[..n_keys].iter_mut().enumerate() {
+                            *score = k.dot(t, q, &q_blocks)? * softmax_scale;
+                            max_score = max_score.max(*score)
+                        }
+                        let mut sum = 0f32;
+                        for score in scores[..n_keys].iter_mut() {
+                            *score = (*score - max_score).exp();
+                            sum += *score
+                        }
+                        for (t, score) in scores[..n_keys].iter().enumerate() {
+                            v.axpy(t, score / sum, dst, &mut buf)?
+                        }
+                    }
+                    Ok::<_, candle::Error>(())
+                })
+        })?;
+        Tensor::from_vec(dst, q.shape(), q.device())?.to_dtype(q.dtype())
+    }
+}
@@ -1,18 +1,22 @@
 pub mod activation;
 pub mod batch_norm;
 pub mod conv;
+pub mod data_parallel;
 pub mod embedding;
 pub mod encoding;
 pub mod func;
 pub mod group_norm;
 pub mod init;
+pub mod kv_cache;
 pub mod layer_norm;
 pub mod linear;
 pub mod loss;
 pub mod ops;
 pub mod optim;
+pub mod quant;
 pub mod rnn;
 pub mod sequential;
+pub mod tensor_parallel;
 pub mod var_builder;
 pub mod var_map;
 
@@ -22,16 +26,18 @@ pub use conv::{
     conv1d, conv2d, conv2d_no_bias, conv_transpose2d, conv_transpose2d_no_bias, Conv1d,
     Conv1dConfig, Conv2d, Conv2dConfig, ConvTranspose2d, ConvTranspose2dConfig,
 };
+pub use data_parallel::{DistributedOptimizer, ParamsDistributed};
 pub use embedding::{embedding, Embedding};
 pub use func::{func, func_t, Func, FuncT};
 pub use group_norm::{group_norm, GroupNorm};
 pub use init::Init;
 pub use layer_norm::{layer_norm, rms_norm, LayerNorm, LayerNormConfig, RmsNorm};
-pub use linear::{linear, linear_no_bias, Linear};
+pub use linear::{linear, linear_no_bias, qlinear, qlinear_no_bias, Linear, QLinear};
 pub use ops::Dropout;
 pub use optim::{AdamW, Optimizer, ParamsAdamW, SGD};
 pub use rnn::{gru, lstm, GRUConfig, LSTMConfig, GRU, LSTM, RNN};
 pub use sequential::{seq, Sequential};
+pub use tensor_parallel::{ColumnParallelLinear, RowParallelLinear};
 pub use var_builder::VarBuilder;
 pub use var_map::VarMap;
 
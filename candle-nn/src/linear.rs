@@ -17,6 +17,7 @@
 //! assert_eq!(ys.to_vec2::<f32>()?, &[[210.0, 430.0, 650.0]]);
 //! # Ok(()) }
 //! ```
+use candle::quantized::{GgmlDType, QMatMul};
 use candle::{Result, Tensor};
 
 #[derive(Clone, Debug)]
@@ -75,3 +76,83 @@ pub fn linear_no_bias(in_dim: usize, out_dim: usize, vs: crate::VarBuilder) -> R
     let ws = vs.get_with_hints((out_dim, in_dim), "weight", init_ws)?;
     Ok(Linear::new(ws, None))
 }
+
+/// A linear layer whose weights are held in a [`QMatMul`], these can either be a float tensor or
+/// a quantized tensor depending on the `VarBuilder` used to create the layer. This lets a single
+/// model definition load both float checkpoints and quantized GGUF files.
+#[derive(Clone, Debug)]
+pub struct QLinear {
+    weight: QMatMul,
+    bias: Option<Tensor>,
+}
+
+impl QLinear {
+    pub fn new(weight: QMatMul, bias: Option<Tensor>) -> Self {
+        Self { weight, bias }
+    }
+
+    pub fn weight(&self) -> &QMatMul {
+        &self.weight
+    }
+
+    pub fn bias(&self) -> Option<&Tensor> {
+        self.bias.as_ref()
+    }
+}
+
+impl super::Module for QLinear {
+    fn forward(&self, x: &Tensor) -> candle::Result<Tensor> {
+        let x = x.apply(&self.weight)?;
+        match &self.bias {
+            None => Ok(x),
+            Some(bias) => x.broadcast_add(bias),
+        }
+    }
+}
+
+fn qlinear_weight(in_dim: usize, out_dim: usize, vs: &crate::VarBuilder) -> Result<QMatMul> {
+    match vs.get_qtensor("weight")? {
+        Some(qtensor) => {
+            if qtensor.shape().dims() != [out_dim, in_dim] {
+                candle::bail!(
+                    "shape mismatch for {}.weight, got {:?}, expected {:?}",
+                    vs.prefix(),
+                    qtensor.shape(),
+                    (out_dim, in_dim)
+                )
+            }
+            match qtensor.dtype() {
+                GgmlDType::F32 | GgmlDType::F16 => {
+                    let ws = qtensor.dequantize(vs.device())?.to_dtype(vs.dtype())?;
+                    Ok(QMatMul::Tensor(ws))
+                }
+                _ => QMatMul::from_arc(qtensor),
+            }
+        }
+        None => {
+            let init_ws = crate::init::DEFAULT_KAIMING_NORMAL;
+            let ws = vs.get_with_hints((out_dim, in_dim), "weight", init_ws)?;
+            Ok(QMatMul::Tensor(ws))
+        }
+    }
+}
+
+/// Create or initialize a new linear layer, the weights are kept quantized if the `VarBuilder`
+/// holds quantized tensors.
+pub fn qlinear(in_dim: usize, out_dim: usize, vs: crate::VarBuilder) -> Result<QLinear> {
+    let ws = qlinear_weight(in_dim, out_dim, &vs)?;
+    let bound = 1. / (in_dim as f64).sqrt();
+    let init_bs = crate::Init::Uniform {
+        lo: -bound,
+        up: bound,
+    };
+    let bs = vs.get_with_hints(out_dim, "bias", init_bs)?;
+    Ok(QLinear::new(ws, Some(bs)))
+}
+
+/// Create or initialize a new linear layer without biases, the weights are kept quantized if the
+/// `VarBuilder` holds quantized tensors.
+pub fn qlinear_no_bias(in_dim: usize, out_dim: usize, vs: crate::VarBuilder) -> Result<QLinear> {
+    let ws = qlinear_weight(in_dim, out_dim, &vs)?;
+    Ok(QLinear::new(ws, None))
+}
@@ -120,20 +120,22 @@ impl candle::CustomOp1 for SoftmaxLastDim {
             let dims = layout.shape().dims();
             let dim_m1 = dims[dims.len() - 1];
             let mut dst = vec![T::zero(); el_count];
-            src.par_chunks(dim_m1)
-                .zip(dst.par_chunks_mut(dim_m1))
-                .for_each(|(src, dst)| {
-                    let mut max = T::neg_infinity();
-                    unsafe { T::vec_reduce_max(src.as_ptr(), &mut max, dim_m1) };
-                    for (s, d) in src.iter().zip(dst.iter_mut()) {
-                        *d = (*s - max).exp();
-                    }
-                    let mut sum_exp = T::zero();
-                    unsafe { T::vec_reduce_sum(dst.as_ptr(), &mut sum_exp, dim_m1) };
-                    for d in dst.iter_mut() {
-                        *d /= sum_exp
-                    }
-                });
+            candle::cpu::thread_pool::install(|| {
+                src.par_chunks(dim_m1)
+                    .zip(dst.par_chunks_mut(dim_m1))
+                    .for_each(|(src, dst)| {
+                        let mut max = T::neg_infinity();
+                        unsafe { T::vec_reduce_max(src.as_ptr(), &mut max, dim_m1) };
+                        for (s, d) in src.iter().zip(dst.iter_mut()) {
+                            *d = (*s - max).exp();
+                        }
+                        let mut sum_exp = T::zero();
+                        unsafe { T::vec_reduce_sum(dst.as_ptr(), &mut sum_exp, dim_m1) };
+                        for d in dst.iter_mut() {
+                            *d /= sum_exp
+                        }
+                    })
+            });
             let storage = candle::WithDType::to_cpu_storage_owned(dst);
             Ok((storage, Shape::from_dims(dims)))
         }
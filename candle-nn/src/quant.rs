@@ -0,0 +1,104 @@
+//! Quantization-aware training.
+//!
+//! [`QatLinear`] is a linear layer whose weights are fake quantized in the forward pass so that
+//! training can adapt the weights to the quantization error. Once trained, the weights are
+//! exported to a [`QMatMul`] using the real quantized format.
+use candle::quantized::fake_quant::FakeQuantizer;
+use candle::quantized::{GgmlDType, QMatMul, QTensor};
+use candle::{Result, Tensor};
+
+#[derive(Clone, Debug)]
+pub struct QatLinear {
+    weight: Tensor,
+    bias: Option<Tensor>,
+    dtype: GgmlDType,
+    quantizer: FakeQuantizer,
+}
+
+impl QatLinear {
+    /// Creates a layer that will be exported to `dtype`, the fake quantizer approximates this
+    /// format.
+    pub fn new(weight: Tensor, bias: Option<Tensor>, dtype: GgmlDType) -> Result<Self> {
+        let quantizer = FakeQuantizer::for_ggml_dtype(dtype)?;
+        Self::with_quantizer(weight, bias, dtype, quantizer)
+    }
+
+    pub fn with_quantizer(
+        weight: Tensor,
+        bias: Option<Tensor>,
+        dtype: GgmlDType,
+        quantizer: FakeQuantizer,
+    ) -> Result<Self> {
+        let (_out_dim, in_dim) = weight.dims2()?;
+        if in_dim % dtype.block_size() != 0 {
+            candle::bail!(
+                "input dim {in_dim} is not divisible by the {dtype:?} block size {}",
+                dtype.block_size()
+            )
+        }
+        Ok(Self {
+            weight,
+            bias,
+            dtype,
+            quantizer,
+        })
+    }
+
+    pub fn weight(&self) -> &Tensor {
+        &self.weight
+    }
+
+    pub fn bias(&self) -> Option<&Tensor> {
+        self.bias.as_ref()
+    }
+
+    pub fn dtype(&self) -> GgmlDType {
+        self.dtype
+    }
+
+    pub fn quantizer(&self) -> &FakeQuantizer {
+        &self.quantizer
+    }
+
+    /// The fake quantized weights as used in the forward pass.
+    pub fn fake_quantized_weight(&self) -> Result<Tensor> {
+        self.quantizer.fake_quantize(&self.weight)
+    }
+
+    /// Quantizes the weights to the target format, the bias is not part of the result and can be
+    /// retrieved with [`QatLinear::bias`].
+    pub fn to_qmatmul(&self) -> Result<QMatMul> {
+        let weight = self.weight.detach()?;
+        QMatMul::from_qtensor(QTensor::quantize(&weight, self.dtype)?)
+    }
+}
+
+impl candle::Module for QatLinear {
+    fn forward(&self, x: &Tensor) -> Result<Tensor> {
+        let w = self.fake_quantized_weight()?;
+        crate::Linear::new(w, self.bias.clone()).forward(x)
+    }
+}
+
+/// Create or initialize a new quantization-aware linear layer, the weights are exported to
+/// `dtype`.
+pub fn qat_linear(
+    in_dim: usize,
+    out_dim: usize,
+    dtype: GgmlDType,
+    vs: crate::VarBuilder,
+) -> Result<QatLinear> {
+    let linear = crate::linear(in_dim, out_dim, vs)?;
+    QatLinear::new(linear.weight().clone(), linear.bias().cloned(), dtype)
+}
+
+/// Create or initialize a new quantization-aware linear layer without biases.
+pub fn qat_linear_no_bias(
+    in_dim: usize,
+    out_dim: usize,
+    dtype: GgmlDType,
+    vs: crate::VarBuilder,
+) -> Result<QatLinear> {
+    let linear = crate::linear_no_bias(in_dim, out_dim, vs)?;
+    QatLinear::new(linear.weight().clone(), None, dtype)
+}
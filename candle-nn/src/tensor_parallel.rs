@@ -0,0 +1,178 @@
+//! Tensor parallel layers
+//!
+//! These layers split the weights of a linear layer between the ranks of a process group, each
+//! rank only loading its shard from a [`ShardedVarBuilder`]. A [`ColumnParallelLinear`] splits
+//! the output features between the ranks whereas a [`RowParallelLinear`] splits the input
+//! features and sums the partial results with an all-reduce. A column parallel layer followed by
+//! a row parallel one, e.g. for the two projections of an MLP, only requires a single collective
+//! operation.
+use crate::var_builder::{Shard, ShardedVarBuilder};
+use crate::Linear;
+use candle::distributed::{Communicator, ReduceOp};
+use candle::{Module, Result, Tensor};
+use std::sync::Arc;
+
+fn shard(dim: usize, comm: &dyn Communicator) -> Shard {
+    Shard {
+        dim,
+        rank: comm.rank(),
+        world_size: comm.world_size(),
+    }
+}
+
+fn shard_size(size: usize, comm: &dyn Communicator) -> Result<usize> {
+    let world_size = comm.world_size();
+    if size % world_size != 0 {
+        candle::bail!("size {size} is not divisible by the world size {world_size}")
+    }
+    Ok(size / world_size)
+}
+
+/// A linear layer whose output features are split between the ranks.
+#[derive(Clone)]
+pub struct ColumnParallelLinear {
+    linear: Linear,
+    comm: Arc<dyn Communicator>,
+    gather_output: bool,
+}
+
+impl std::fmt::Debug for ColumnParallelLinear {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ColumnParallelLinear")
+            .field("linear", &self.linear)
+            .field("rank", &self.comm.rank())
+            .field("world_size", &self.comm.world_size())
+            .field("gather_output", &self.gather_output)
+            .finish()
+    }
+}
+
+impl ColumnParallelLinear {
+    pub fn new(linear: Linear, comm: Arc<dyn Communicator>) -> Self {
+        Self {
+            linear,
+            comm,
+            gather_output: false,
+        }
+    }
+
+    /// When set, the outputs of all the ranks are concatenated so that each rank gets the full
+    /// output. Otherwise each rank only gets the output features of its shard.
+    pub fn with_gather_output(mut self, gather_output: bool) -> Self {
+        self.gather_output = gather_output;
+        self
+    }
+
+    pub fn linear(&self) -> &Linear {
+        &self.linear
+    }
+
+    pub fn load(
+        in_dim: usize,
+        out_dim: usize,
+        bias: bool,
+        vb: ShardedVarBuilder,
+        comm: Arc<dyn Communicator>,
+    ) -> Result<Self> {
+        let out_dim = shard_size(out_dim, comm.as_ref())?;
+        let shard = shard(0, comm.as_ref());
+        let weight = vb.get_with_hints((out_dim, in_dim), "weight", shard)?;
+        let bias = if bias {
+            Some(vb.get_with_hints(out_dim, "bias", shard)?)
+        } else {
+            None
+        };
+        Ok(Self::new(Linear::new(weight, bias), comm))
+    }
+}
+
+impl Module for ColumnParallelLinear {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let xs = self.linear.forward(xs)?;
+        if self.gather_output {
+            self.comm.all_gather(&xs.contiguous()?, xs.rank() - 1)
+        } else {
+            Ok(xs)
+        }
+    }
+}
+
+/// A linear layer whose input features are split between the ranks, each rank gets the full
+/// output.
+#[derive(Clone)]
+pub struct RowParallelLinear {
+    linear: Linear,
+    comm: Arc<dyn Communicator>,
+    input_is_parallel: bool,
+}
+
+impl std::fmt::Debug for RowParallelLinear {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RowParallelLinear")
+            .field("linear", &self.linear)
+            .field("rank", &self.comm.rank())
+            .field("world_size", &self.comm.world_size())
+            .field("input_is_parallel", &self.input_is_parallel)
+            .finish()
+    }
+}
+
+impl RowParallelLinear {
+    /// The bias of `linear`, if any, should be the full bias and is only added once the partial
+    /// results have been summed.
+    pub fn new(linear: Linear, comm: Arc<dyn Communicator>) -> Self {
+        Self {
+            linear,
+            comm,
+            input_is_parallel: true,
+        }
+    }
+
+    /// By default the input is expected to only contain the input features of this rank, as
+    /// returned by a [`ColumnParallelLinear`]. When unset, the input contains all the input
+    /// features and each rank selects its own.
+    pub fn with_input_is_parallel(mut self, input_is_parallel: bool) -> Self {
+        self.input_is_parallel = input_is_parallel;
+        self
+    }
+
+    pub fn linear(&self) -> &Linear {
+        &self.linear
+    }
+
+    pub fn load(
+        in_dim: usize,
+        out_dim: usize,
+        bias: bool,
+        vb: ShardedVarBuilder,
+        comm: Arc<dyn Communicator>,
+    ) -> Result<Self> {
+        let in_dim = shard_size(in_dim, comm.as_ref())?;
+        let weight = vb.get_with_hints((out_dim, in_dim), "weight", shard(1, comm.as_ref()))?;
+        let bias = if bias {
+            Some(vb.get(out_dim, "bias")?)
+        } else {
+            None
+        };
+        Ok(Self::new(Linear::new(weight, bias), comm))
+    }
+}
+
+impl Module for RowParallelLinear {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let xs = if self.input_is_parallel {
+            xs.clone()
+        } else {
+            let dim = xs.rank() - 1;
+            let size = shard_size(xs.dim(dim)?, self.comm.as_ref())?;
+            xs.narrow(dim, self.comm.rank() * size, size)?
+        };
+        let linear = Linear::new(self.linear.weight().clone(), None);
+        let xs = linear.forward(&xs)?;
+        let xs = self.comm.all_reduce(&xs, ReduceOp::Sum)?;
+        match self.linear.bias() {
+            None => Ok(xs),
+            Some(bias) => xs.broadcast_add(bias),
+        }
+    }
+}
@@ -2,6 +2,7 @@
 //! from a pre-trained checkpoint, e.g. using `VarBuilder::from_mmaped_safetensors`, or initialized
 //! for training, e.g. using `VarBuilder::from_varmap`.
 use crate::VarMap;
+use candle::quantized::QTensor;
 use candle::{safetensors::Load, DType, Device, Error, Result, Shape, Tensor};
 use safetensors::{slice::IndexOp, tensor::SafeTensors};
 use std::collections::HashMap;
@@ -56,6 +57,18 @@ pub trait Backend: Send + Sync {
         dev: &Device,
     ) -> Result<Tensor>;
 
+    /// Retrieve a tensor without checking its shape, this is used for tensors whose shape
+    /// depends on the checkpoint, e.g. the scales of quantized weights.
+    fn get_unchecked(&self, name: &str, _dtype: DType, _dev: &Device) -> Result<Tensor> {
+        candle::bail!("get_unchecked is not supported by this backend, trying to get {name}")
+    }
+
+    /// Retrieve a quantized tensor, `None` is returned when the backend does not hold quantized
+    /// tensors in which case the float tensor should be used.
+    fn get_qtensor(&self, _name: &str) -> Result<Option<Arc<QTensor>>> {
+        Ok(None)
+    }
+
     fn contains_tensor(&self, name: &str) -> bool;
 }
 
@@ -70,6 +83,16 @@ pub trait SimpleBackend: Send + Sync {
         dev: &Device,
     ) -> Result<Tensor>;
 
+    /// Retrieve a tensor based on its name only, the shape is not checked.
+    fn get_unchecked(&self, name: &str, _dtype: DType, _dev: &Device) -> Result<Tensor> {
+        candle::bail!("get_unchecked is not supported by this backend, trying to get {name}")
+    }
+
+    /// Retrieve a quantized tensor, `None` is returned for backends holding float tensors.
+    fn get_qtensor(&self, _name: &str) -> Result<Option<Arc<QTensor>>> {
+        Ok(None)
+    }
+
     fn contains_tensor(&self, name: &str) -> bool;
 }
 
@@ -86,6 +109,14 @@ impl<'a> Backend for Box<dyn SimpleBackend + 'a> {
         self.as_ref().get(s, name, h, dtype, dev)
     }
 
+    fn get_unchecked(&self, name: &str, dtype: DType, dev: &Device) -> Result<Tensor> {
+        self.as_ref().get_unchecked(name, dtype, dev)
+    }
+
+    fn get_qtensor(&self, name: &str) -> Result<Option<Arc<QTensor>>> {
+        self.as_ref().get_qtensor(name)
+    }
+
     fn contains_tensor(&self, name: &str) -> bool {
         self.as_ref().contains_tensor(name)
     }
@@ -188,6 +219,22 @@ impl<'a, B: Backend> VarBuilderArgs<'a, B> {
     pub fn get<S: Into<Shape>>(&self, s: S, name: &str) -> Result<Tensor> {
         self.get_with_hints(s, name, Default::default())
     }
+
+    /// Retrieve the tensor associated with the given name at the current path without checking
+    /// its shape, the tensor is converted to `dtype` rather than to the default dtype.
+    pub fn get_unchecked_dtype(&self, name: &str, dtype: DType) -> Result<Tensor> {
+        let path = self.path(name);
+        self.data
+            .backend
+            .get_unchecked(&path, dtype, &self.data.device)
+    }
+
+    /// Retrieve the quantized tensor associated with the given name at the current path, `None`
+    /// is returned when the backend only holds float tensors.
+    pub fn get_qtensor(&self, name: &str) -> Result<Option<Arc<QTensor>>> {
+        let path = self.path(name);
+        self.data.backend.get_qtensor(&path)
+    }
 }
 
 struct Zeros;
@@ -231,6 +278,16 @@ impl SimpleBackend for HashMap<String, Tensor> {
         tensor.to_device(dev)?.to_dtype(dtype)
     }
 
+    fn get_unchecked(&self, name: &str, dtype: DType, dev: &Device) -> Result<Tensor> {
+        let tensor = self.get(name).ok_or_else(|| {
+            Error::CannotFindTensor {
+                path: name.to_string(),
+            }
+            .bt()
+        })?;
+        tensor.to_device(dev)?.to_dtype(dtype)
+    }
+
     fn contains_tensor(&self, name: &str) -> bool {
         self.contains_key(name)
     }
@@ -288,6 +345,19 @@ impl<'a> SimpleBackend for SafeTensorWithRouting<'a> {
         Ok(tensor)
     }
 
+    fn get_unchecked(&self, path: &str, dtype: DType, dev: &Device) -> Result<Tensor> {
+        let index = self.routing.get(path).ok_or_else(|| {
+            Error::CannotFindTensor {
+                path: path.to_string(),
+            }
+            .bt()
+        })?;
+        self.safetensors[*index]
+            .tensor(path)?
+            .load(dev)?
+            .to_dtype(dtype)
+    }
+
     fn contains_tensor(&self, name: &str) -> bool {
         self.routing.contains_key(name)
     }
@@ -380,8 +450,12 @@ impl SimpleBackend for candle::safetensors::MmapedSafetensors {
         Ok(tensor)
     }
 
+    fn get_unchecked(&self, name: &str, dtype: DType, dev: &Device) -> Result<Tensor> {
+        self.load(name, dev)?.to_dtype(dtype)
+    }
+
     fn contains_tensor(&self, name: &str) -> bool {
-        self.get(name).is_ok()
+        self.contains(name)
     }
 }
 
@@ -406,11 +480,64 @@ impl SimpleBackend for candle::safetensors::BufferedSafetensors {
         Ok(tensor)
     }
 
+    fn get_unchecked(&self, name: &str, dtype: DType, dev: &Device) -> Result<Tensor> {
+        self.load(name, dev)?.to_dtype(dtype)
+    }
+
     fn contains_tensor(&self, name: &str) -> bool {
         self.get(name).is_ok()
     }
 }
 
+/// Quantized tensors, e.g. read from a GGUF file. The float accessors return the dequantized
+/// tensors.
+struct QTensors(HashMap<String, Arc<QTensor>>);
+
+impl QTensors {
+    fn qtensor(&self, name: &str) -> Result<&Arc<QTensor>> {
+        self.0.get(name).ok_or_else(|| {
+            Error::CannotFindTensor {
+                path: name.to_string(),
+            }
+            .bt()
+        })
+    }
+}
+
+impl SimpleBackend for QTensors {
+    fn get(
+        &self,
+        s: Shape,
+        name: &str,
+        _: crate::Init,
+        dtype: DType,
+        dev: &Device,
+    ) -> Result<Tensor> {
+        let qtensor = self.qtensor(name)?;
+        if qtensor.shape() != &s {
+            Err(candle::Error::UnexpectedShape {
+                msg: format!("shape mismatch for {name}"),
+                expected: s,
+                got: qtensor.shape().clone(),
+            }
+            .bt())?
+        }
+        qtensor.dequantize(dev)?.to_dtype(dtype)
+    }
+
+    fn get_unchecked(&self, name: &str, dtype: DType, dev: &Device) -> Result<Tensor> {
+        self.qtensor(name)?.dequantize(dev)?.to_dtype(dtype)
+    }
+
+    fn get_qtensor(&self, name: &str) -> Result<Option<Arc<QTensor>>> {
+        Ok(Some(self.qtensor(name)?.clone()))
+    }
+
+    fn contains_tensor(&self, name: &str) -> bool {
+        self.0.contains_key(name)
+    }
+}
+
 impl<'a> VarBuilder<'a> {
     /// Initializes a `VarBuilder` using a custom backend.
     ///
@@ -470,6 +597,22 @@ impl<'a> VarBuilder<'a> {
         Ok(Self::from_backend(Box::new(tensors), dtype, dev.clone()))
     }
 
+    /// Initializes a `VarBuilder` that retrieves tensors stored in a sharded checkpoint, using its
+    /// index file, e.g. `model.safetensors.index.json`. The files are only memory mapped when one
+    /// of their tensors is first retrieved.
+    ///
+    /// # Safety
+    ///
+    /// The unsafe is inherited from [`memmap2::MmapOptions`].
+    pub unsafe fn from_mmaped_safetensors_index<P: AsRef<std::path::Path>>(
+        index_path: P,
+        dtype: DType,
+        dev: &Device,
+    ) -> Result<Self> {
+        let tensors = candle::safetensors::MmapedSafetensors::from_index(index_path)?;
+        Ok(Self::from_backend(Box::new(tensors), dtype, dev.clone()))
+    }
+
     /// Initializes a `VarBuilder` from a binary builder in the safetensor format.
     pub fn from_buffered_safetensors(data: Vec<u8>, dtype: DType, dev: &Device) -> Result<Self> {
         let tensors = candle::safetensors::BufferedSafetensors::new(data)?;
@@ -487,6 +630,60 @@ impl<'a> VarBuilder<'a> {
         let pth = candle::pickle::PthTensors::new(p, None)?;
         Ok(Self::from_backend(Box::new(pth), dtype, dev.clone()))
     }
+
+    /// Initializes a `VarBuilder` from quantized tensors. Layers created with
+    /// [`crate::linear::qlinear`] keep their weights quantized, the other tensors are dequantized
+    /// to `dtype`.
+    pub fn from_qtensors(ts: HashMap<String, QTensor>, dtype: DType, dev: &Device) -> Self {
+        let ts = ts.into_iter().map(|(k, v)| (k, Arc::new(v))).collect();
+        Self::from_backend(Box::new(QTensors(ts)), dtype, dev.clone())
+    }
+
+    /// Initializes a `VarBuilder` that reads the quantized tensors from a GGUF file.
+    pub fn from_gguf<P: AsRef<std::path::Path>>(p: P, dtype: DType, dev: &Device) -> Result<Self> {
+        let mut file = std::fs::File::open(p)?;
+        Self::from_gguf_reader(&mut file, dtype, dev)
+    }
+
+    /// Initializes a `VarBuilder` that reads the quantized tensors from a GGUF buffer.
+    pub fn from_gguf_buffer(buffer: &[u8], dtype: DType, dev: &Device) -> Result<Self> {
+        let mut cursor = std::io::Cursor::new(buffer);
+        Self::from_gguf_reader(&mut cursor, dtype, dev)
+    }
+
+    /// Initializes a `VarBuilder` that memory maps a GGUF file, on the cpu the quantized tensors
+    /// borrow their data from the mapping rather than being copied.
+    ///
+    /// # Safety
+    ///
+    /// The unsafe is inherited from [`candle::quantized::gguf_file::MmapedContent::new`].
+    pub unsafe fn from_mmaped_gguf<P: AsRef<std::path::Path>>(
+        p: P,
+        dtype: DType,
+        dev: &Device,
+    ) -> Result<Self> {
+        let content = candle::quantized::gguf_file::MmapedContent::new(p)?;
+        let mut ts = HashMap::new();
+        for tensor_name in content.content().tensor_infos.keys() {
+            let tensor = content.tensor(tensor_name, dev)?;
+            ts.insert(tensor_name.to_string(), tensor);
+        }
+        Ok(Self::from_qtensors(ts, dtype, dev))
+    }
+
+    fn from_gguf_reader<R: std::io::Seek + std::io::Read>(
+        reader: &mut R,
+        dtype: DType,
+        dev: &Device,
+    ) -> Result<Self> {
+        let content = candle::quantized::gguf_file::Content::read(reader)?;
+        let mut ts = HashMap::new();
+        for tensor_name in content.tensor_infos.keys() {
+            let tensor = content.tensor(reader, tensor_name, dev)?;
+            ts.insert(tensor_name.to_string(), tensor);
+        }
+        Ok(Self::from_qtensors(ts, dtype, dev))
+    }
 }
 
 pub struct ShardedSafeTensors(candle::safetensors::MmapedSafetensors);
@@ -509,6 +706,22 @@ impl ShardedSafeTensors {
         let backend = ShardedSafeTensors(tensors);
         Ok(VarBuilderArgs::new_with_args(backend, dtype, dev))
     }
+
+    /// Same as [`ShardedSafeTensors::var_builder`] but using the index file of a sharded
+    /// checkpoint, e.g. `model.safetensors.index.json`, to locate the tensors.
+    ///
+    /// # Safety
+    ///
+    /// The unsafe is inherited from [`memmap2::MmapOptions`].
+    pub unsafe fn var_builder_from_index<P: AsRef<std::path::Path>>(
+        index_path: P,
+        dtype: DType,
+        dev: &Device,
+    ) -> Result<ShardedVarBuilder<'static>> {
+        let tensors = candle::safetensors::MmapedSafetensors::from_index(index_path)?;
+        let backend = ShardedSafeTensors(tensors);
+        Ok(VarBuilderArgs::new_with_args(backend, dtype, dev))
+    }
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
@@ -603,7 +816,11 @@ impl Backend for ShardedSafeTensors {
         Tensor::from_raw_buffer(&raw, view_dtype, &shape, dev)?.to_dtype(dtype)
     }
 
+    fn get_unchecked(&self, name: &str, dtype: DType, dev: &Device) -> Result<Tensor> {
+        SimpleBackend::get_unchecked(&self.0, name, dtype, dev)
+    }
+
     fn contains_tensor(&self, name: &str) -> bool {
-        self.0.get(name).is_ok()
+        self.0.contains(name)
     }
 }
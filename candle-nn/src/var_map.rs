@@ -26,6 +26,15 @@ impl VarMap {
         tensor_data.values().map(|c| c.clone()).collect::<Vec<_>>()
     }
 
+    /// Retrieve all the variables currently stored in the map, sorted by name. Contrary to
+    /// [`VarMap::all_vars`], the order is the same for all the processes building the same model.
+    pub fn sorted_vars(&self) -> Vec<Var> {
+        let tensor_data = self.data.lock().unwrap();
+        let mut vars = tensor_data.iter().collect::<Vec<_>>();
+        vars.sort_by_key(|(k, _)| *k);
+        vars.into_iter().map(|(_, v)| v.clone()).collect()
+    }
+
     /// Save the map in the safetensors format.
     pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> Result<()> {
         let tensor_data = self.data.lock().unwrap();
@@ -34,6 +43,22 @@ impl VarMap {
         Ok(())
     }
 
+    /// Save the map as a sharded checkpoint in the `dir` directory, each safetensors file being
+    /// at most `max_shard_size` bytes. An index file mapping the variable names to the files is
+    /// written alongside, its path is returned.
+    pub fn save_sharded<P: AsRef<std::path::Path>>(
+        &self,
+        dir: P,
+        max_shard_size: usize,
+    ) -> Result<std::path::PathBuf> {
+        let tensor_data = self.data.lock().unwrap();
+        let data = tensor_data
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_tensor().clone()))
+            .collect::<HashMap<_, _>>();
+        candle::safetensors::save_sharded(&data, dir, max_shard_size)
+    }
+
     /// Load some values from a safetensors file and modify the existing variables to have these
     /// values.
     ///
@@ -41,6 +66,22 @@ impl VarMap {
     pub fn load<P: AsRef<std::path::Path>>(&mut self, path: P) -> Result<()> {
         let path = path.as_ref();
         let data = unsafe { candle::safetensors::MmapedSafetensors::new(path)? };
+        self.load_from(&data, path)
+    }
+
+    /// Same as [`VarMap::load`] but using the index file of a sharded checkpoint, e.g.
+    /// `model.safetensors.index.json`.
+    pub fn load_sharded<P: AsRef<std::path::Path>>(&mut self, index_path: P) -> Result<()> {
+        let path = index_path.as_ref();
+        let data = unsafe { candle::safetensors::MmapedSafetensors::from_index(path)? };
+        self.load_from(&data, path)
+    }
+
+    fn load_from(
+        &mut self,
+        data: &candle::safetensors::MmapedSafetensors,
+        path: &std::path::Path,
+    ) -> Result<()> {
         let mut tensor_data = self.data.lock().unwrap();
         for (name, var) in tensor_data.iter_mut() {
             let data = data.load(name, var.device())?;
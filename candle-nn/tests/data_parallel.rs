@@ -0,0 +1,176 @@
+#[cfg(feature = "mkl")]
+extern crate intel_mkl_src;
+
+#[cfg(feature = "accelerate")]
+extern crate accelerate_src;
+
+use anyhow::Result;
+use candle::distributed::{Communicator, InProcessCommunicator, TcpCommunicator};
+use candle::{DType, Device, Module, Tensor};
+use candle_nn::{
+    linear, AdamW, DistributedOptimizer, Optimizer, ParamsAdamW, ParamsDistributed, VarBuilder,
+    VarMap, SGD,
+};
+use std::sync::Arc;
+
+// Trains a two layer model on `xs`, `ys` and returns the final weights sorted by name.
+fn train<O: Optimizer>(
+    xs: &Tensor,
+    ys: &Tensor,
+    seed: f64,
+    config: impl FnOnce(&VarMap) -> O::Config,
+) -> Result<Vec<Vec<f32>>> {
+    let dev = &Device::Cpu;
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let l1 = linear(3, 4, vb.pp("l1"))?;
+    let l2 = linear(4, 1, vb.pp("l2"))?;
+    // Use deterministic initial weights that differ between the ranks, these are overwritten with
+    // the weights of rank 0 when creating the distributed optimizer.
+    for (i, var) in varmap.sorted_vars().iter().enumerate() {
+        let init = Tensor::arange(0f32, var.elem_count() as f32, dev)?;
+        let init = ((init + i as f64)? * 0.7)?.sin()?.affine(0.5, seed)?;
+        var.set(&init.reshape(var.shape())?)?
+    }
+    let config = config(&varmap);
+    let mut opt = O::new(varmap.sorted_vars(), config)?;
+    for _step in 0..5 {
+        let pred = l2.forward(&l1.forward(xs)?.tanh()?)?;
+        let loss = pred.sub(ys)?.sqr()?.mean_all()?;
+        opt.backward_step(&loss)?;
+    }
+    let weights = varmap
+        .sorted_vars()
+        .iter()
+        .map(|v| v.flatten_all()?.to_vec1::<f32>())
+        .collect::<candle::Result<Vec<_>>>()?;
+    Ok(weights)
+}
+
+fn check_close(lhs: &[Vec<f32>], rhs: &[Vec<f32>]) {
+    assert_eq!(lhs.len(), rhs.len());
+    for (lhs, rhs) in lhs.iter().zip(rhs.iter()) {
+        for (l, r) in lhs.iter().zip(rhs.iter()) {
+            assert!((l - r).abs() < 1e-5, "{lhs:?} {rhs:?}")
+        }
+    }
+}
+
+fn data() -> Result<(Tensor, Tensor)> {
+    let dev = &Device::Cpu;
+    let xs = Tensor::new(
+        &[
+            [0.1f32, -0.3, 0.5],
+            [1.2, 0.4, -0.7],
+            [-0.8, 0.9, 0.2],
+            [0.3, 0.3, 0.3],
+        ],
+        dev,
+    )?;
+    let ys = Tensor::new(&[[0.5f32], [-1.0], [0.7], [0.1]], dev)?;
+    Ok((xs, ys))
+}
+
+fn run_ranks<O: Optimizer>(
+    comms: Vec<Arc<dyn Communicator>>,
+    config: fn() -> O::Config,
+) -> Result<Vec<Vec<Vec<f32>>>>
+where
+    O::Config: 'static,
+{
+    let (xs, ys) = data()?;
+    let chunk = xs.dim(0)? / comms.len();
+    let handles = comms
+        .into_iter()
+        .map(|comm| {
+            let rank = comm.rank();
+            let xs = xs.narrow(0, rank * chunk, chunk);
+            let ys = ys.narrow(0, rank * chunk, chunk);
+            std::thread::spawn(move || {
+                let config = |_: &VarMap| ParamsDistributed::new(config(), comm);
+                train::<DistributedOptimizer<O>>(&xs?, &ys?, rank as f64, config)
+            })
+        })
+        .collect::<Vec<_>>();
+    handles.into_iter().map(|h| h.join().unwrap()).collect()
+}
+
+#[test]
+fn data_parallel_sgd() -> Result<()> {
+    let (xs, ys) = data()?;
+    // With the same number of samples per rank, averaging the gradients of the per-rank mean
+    // losses gives the gradients of the mean loss over the full batch.
+    let expected = train::<SGD>(&xs, &ys, 0., |_| 0.1)?;
+    let comms = InProcessCommunicator::new_group(2)?
+        .into_iter()
+        .map(|c| Arc::new(c) as Arc<dyn Communicator>)
+        .collect();
+    for weights in run_ranks::<SGD>(comms, || 0.1)? {
+        check_close(&weights, &expected)
+    }
+    Ok(())
+}
+
+#[test]
+fn data_parallel_adamw_tcp() -> Result<()> {
+    let (xs, ys) = data()?;
+    fn params() -> ParamsAdamW {
+        ParamsAdamW {
+            lr: 0.05,
+            ..Default::default()
+        }
+    }
+    let expected = train::<AdamW>(&xs, &ys, 0., |_| params())?;
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let peer = std::thread::spawn(move || TcpCommunicator::new(addr, 1, 2));
+    let root = TcpCommunicator::root(listener, 2)?;
+    let comms: Vec<Arc<dyn Communicator>> = vec![Arc::new(root), Arc::new(peer.join().unwrap()?)];
+    for weights in run_ranks::<AdamW>(comms, params)? {
+        check_close(&weights, &expected)
+    }
+    Ok(())
+}
+
+#[test]
+fn data_parallel_buckets() -> Result<()> {
+    let dev = &Device::Cpu;
+    let comms = InProcessCommunicator::new_group(3)?;
+    let handles = comms
+        .into_iter()
+        .map(|comm| {
+            std::thread::spawn(move || -> Result<Vec<Vec<f32>>> {
+                let rank = comm.rank() as f32;
+                let vars = [
+                    candle::Var::new(&[1f32, 2., 3.], dev)?,
+                    candle::Var::new(&[[4f32, 5.], [6., 7.]], dev)?,
+                    candle::Var::new(&[1f64], dev)?,
+                ];
+                let loss = ((vars[0].sum_all()? + vars[1].sum_all()?)? * rank as f64)?;
+                let mut grads = loss.backward()?;
+                // Only the first rank gets a gradient for the last variable.
+                if rank == 0. {
+                    grads.insert(&vars[2], Tensor::new(&[6f64], dev)?);
+                }
+                let mut config = ParamsDistributed::new(0., Arc::new(comm));
+                // Small enough to have one bucket per variable.
+                config.bucket_size = 12;
+                let opt = DistributedOptimizer::<SGD>::new(vars.to_vec(), config)?;
+                opt.all_reduce_grads(&mut grads)?;
+                let mut res = vec![];
+                for var in vars[..2].iter() {
+                    res.push(grads.get(var).unwrap().flatten_all()?.to_vec1::<f32>()?)
+                }
+                res.push(vec![
+                    grads.get(&vars[2]).unwrap().to_vec1::<f64>()?[0] as f32,
+                ]);
+                Ok(res)
+            })
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        let grads = handle.join().unwrap()?;
+        assert_eq!(grads, [vec![1., 1., 1.], vec![1., 1., 1., 1.], vec![2.]]);
+    }
+    Ok(())
+}
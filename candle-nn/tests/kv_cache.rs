@@ -0,0 +1,193 @@
+#[cfg(feature = "mkl")]
+extern crate intel_mkl_src;
+
+#[cfg(feature = "accelerate")]
+extern crate accelerate_src;
+
+use candle::{Device, Result, Tensor};
+
+#[test]
+fn kv_cache() -> Result<()> {
+    let mut cache = candle_nn::kv_cache::Cache::new(0, 16);
+    let data = cache.current_data()?;
+    assert!(data.is_none());
+    let t = Tensor::new(&[1f32, 2., 3.], &Device::Cpu)?;
+    cache.append(&t)?;
+    let data = cache.current_data()?.unwrap();
+    assert_eq!(data.to_vec1::<f32>()?, [1., 2., 3.]);
+    let t = Tensor::new(&[4f32], &Device::Cpu)?;
+    cache.append(&t)?;
+    let data = cache.current_data()?.unwrap();
+    assert_eq!(data.to_vec1::<f32>()?, [1., 2., 3., 4.]);
+    let t = Tensor::new(&[0f32, 5., 6., 7.], &Device::Cpu)?;
+    cache.append(&t)?;
+    let data = cache.current_data()?.unwrap();
+    assert_eq!(data.to_vec1::<f32>()?, [1., 2., 3., 4., 0., 5., 6., 7.]);
+    assert_eq!(cache.current_seq_len(), 8);
+    assert_eq!(cache.allocated_bytes(), 64);
+    assert_eq!(cache.used_bytes(), 32);
+
+    cache.truncate(2);
+    let t = Tensor::new(&[9f32], &Device::Cpu)?;
+    cache.append(&t)?;
+    let data = cache.current_data()?.unwrap();
+    assert_eq!(data.to_vec1::<f32>()?, [1., 2., 9.]);
+
+    // Appending past the preallocated size grows the storage.
+    let t = Tensor::arange(0f32, 20., &Device::Cpu)?;
+    cache.append(&t)?;
+    let data = cache.current_data()?.unwrap();
+    assert_eq!(data.dims1()?, 23);
+    assert_eq!(data.narrow(0, 0, 4)?.to_vec1::<f32>()?, [1., 2., 9., 0.]);
+    assert_eq!(cache.max_seq_len(), 32);
+
+    cache.reset();
+    assert!(cache.current_data()?.is_none());
+    assert_eq!(cache.allocated_bytes(), 0);
+    Ok(())
+}
+
+#[test]
+fn kv_cache_4d() -> Result<()> {
+    let device = &Device::Cpu;
+    let mut cache = candle_nn::kv_cache::KvCache::new(2, 4);
+    let k = Tensor::randn(0f32, 1., (1, 2, 3, 4), device)?;
+    let v = Tensor::randn(0f32, 1., (1, 2, 3, 4), device)?;
+    let (k1, v1) = cache.append(&k, &v)?;
+    assert_eq!(k1.dims(), [1, 2, 3, 4]);
+    let k_new = Tensor::randn(0f32, 1., (1, 2, 2, 4), device)?;
+    let v_new = Tensor::randn(0f32, 1., (1, 2, 2, 4), device)?;
+    let (k2, v2) = cache.append(&k_new, &v_new)?;
+    let k_exp = Tensor::cat(&[&k, &k_new], 2)?;
+    let v_exp = Tensor::cat(&[&v, &v_new], 2)?;
+    let diff = (k2 - k_exp)?.abs()?.sum_all()?.to_vec0::<f32>()?;
+    assert_eq!(diff, 0.);
+    let diff = (v2 - v_exp)?.abs()?.sum_all()?.to_vec0::<f32>()?;
+    assert_eq!(diff, 0.);
+    assert_eq!(cache.current_seq_len(), 5);
+    Ok(())
+}
+
+#[test]
+fn rotating_kv_cache() -> Result<()> {
+    let mut cache = candle_nn::kv_cache::RotatingCache::new(0, 6);
+    assert_eq!(cache.offset(), 0);
+    assert_eq!(cache.current_seq_len(), 0);
+    let data = cache.current_data()?;
+    assert!(data.is_none());
+    let t = Tensor::new(&[1f32, 2., 3.], &Device::Cpu)?;
+    let data = cache.append(&t)?;
+    assert_eq!(data.to_vec1::<f32>()?, [1., 2., 3.]);
+    let t = Tensor::new(&[4f32], &Device::Cpu)?;
+    let data = cache.append(&t)?;
+    assert_eq!(data.to_vec1::<f32>()?, [1., 2., 3., 4.]);
+    let t = Tensor::new(&[0f32, 5., 6., 7.], &Device::Cpu)?;
+    let data = cache.append(&t)?;
+    assert_eq!(data.to_vec1::<f32>()?, [1., 2., 3., 4., 0., 5., 6., 7.]);
+    assert_eq!(cache.current_seq_len(), 6);
+    assert_eq!(cache.offset(), 8);
+    let data = cache.current_data()?.unwrap();
+    assert_eq!(data.to_vec1::<f32>()?, [3., 4., 0., 5., 6., 7.]);
+
+    // A single step returns the raw ring buffer.
+    let t = Tensor::new(&[8f32], &Device::Cpu)?;
+    let data = cache.append(&t)?;
+    assert_eq!(data.to_vec1::<f32>()?, [6., 7., 8., 4., 0., 5.]);
+    let data = cache.current_data()?.unwrap();
+    assert_eq!(data.to_vec1::<f32>()?, [4., 0., 5., 6., 7., 8.]);
+
+    let t = Tensor::new(&[9f32, 10., 11., 12., 13., 14., 15., 16.], &Device::Cpu)?;
+    let data = cache.append(&t)?;
+    assert_eq!(data.dims1()?, 14);
+    let data = cache.current_data()?.unwrap();
+    assert_eq!(data.to_vec1::<f32>()?, [11., 12., 13., 14., 15., 16.]);
+    assert_eq!(cache.offset(), 17);
+
+    cache.reset();
+    assert!(cache.current_data()?.is_none());
+    assert_eq!(cache.offset(), 0);
+    Ok(())
+}
+
+fn float_attention(q: &Tensor, k: &Tensor, v: &Tensor, causal: bool) -> Result<Tensor> {
+    let (_, num_heads, q_len, head_dim) = q.dims4()?;
+    let (b_sz, num_kv_heads, kv_len, _) = k.dims4()?;
+    let n_rep = num_heads / num_kv_heads;
+    let repeat = |xs: &Tensor| {
+        xs.unsqueeze(2)?
+            .expand((b_sz, num_kv_heads, n_rep, kv_len, head_dim))?
+            .reshape((b_sz, num_heads, kv_len, head_dim))
+    };
+    let (k, v) = (repeat(k)?, repeat(v)?);
+    let att = (q.matmul(&k.t()?)? / (head_dim as f64).sqrt())?;
+    let att = if causal {
+        let mask: Vec<f32> = (0..q_len)
+            .flat_map(|i| {
+                (0..kv_len).map(move |j| {
+                    if j > kv_len - q_len + i {
+                        f32::NEG_INFINITY
+                    } else {
+                        0.
+                    }
+                })
+            })
+            .collect();
+        let mask = Tensor::from_vec(mask, (q_len, kv_len), q.device())?;
+        att.broadcast_add(&mask)?
+    } else {
+        att
+    };
+    candle_nn::ops::softmax_last_dim(&att)?.matmul(&v)
+}
+
+#[test]
+fn quantized_kv_cache() -> Result<()> {
+    use candle_nn::kv_cache::{QuantizedKvCache, QuantizedKvDType};
+
+    let dev = &Device::Cpu;
+    let (b_sz, num_heads, num_kv_heads, head_dim) = (2, 4, 2, 64);
+    for dtype in [QuantizedKvDType::Q8_0, QuantizedKvDType::Int8] {
+        let mut cache = QuantizedKvCache::new(dtype);
+        let mut k_all: Option<Tensor> = None;
+        let mut v_all: Option<Tensor> = None;
+        // A prompt followed by a few single token steps.
+        for seq_len in [7, 1, 1, 3] {
+            let q = Tensor::randn(0f32, 1., (b_sz, num_heads, seq_len, head_dim), dev)?;
+            let k = Tensor::randn(0f32, 1., (b_sz, num_kv_heads, seq_len, head_dim), dev)?;
+            let v = Tensor::randn(0f32, 1., (b_sz, num_kv_heads, seq_len, head_dim), dev)?;
+            cache.append(&k, &v)?;
+            let cat = |all: Option<Tensor>, xs: &Tensor| match all {
+                None => Ok(xs.clone()),
+                Some(all) => Tensor::cat(&[&all, xs], 2),
+            };
+            k_all = Some(cat(k_all, &k)?);
+            v_all = Some(cat(v_all, &v)?);
+            let (k_all, v_all) = (k_all.as_ref().unwrap(), v_all.as_ref().unwrap());
+            for causal in [true, false] {
+                let scale = 1. / (head_dim as f64).sqrt();
+                let ys = cache.attention(&q, scale, causal)?;
+                let expected = float_attention(&q, k_all, v_all, causal)?;
+                assert_eq!(ys.dims(), expected.dims());
+                let err = (ys - &expected)?.abs()?.flatten_all()?.max(0)?;
+                let err = err.to_scalar::<f32>()?;
+                assert!(err < 0.03, "{dtype:?} {seq_len} {causal}: {err}");
+            }
+        }
+        assert_eq!(cache.current_seq_len(), 12);
+        let used_bytes = cache.used_bytes();
+        // The float cache would use 4 bytes per value.
+        let float_bytes = 2 * b_sz * num_kv_heads * 12 * head_dim * 4;
+        assert!(used_bytes * 3 < float_bytes, "{used_bytes} {float_bytes}");
+        cache.truncate(10);
+        assert_eq!(cache.current_seq_len(), 10);
+        assert!(cache.used_bytes() < used_bytes);
+        cache.reset();
+        assert_eq!(cache.used_bytes(), 0);
+        let q = Tensor::zeros((b_sz, num_heads, 1, head_dim), candle::DType::F32, dev)?;
+        assert!(cache.attention(&q, 1., true).is_err());
+    }
+    let mut cache = QuantizedKvCache::new(QuantizedKvDType::Q8_0);
+    let k = Tensor::zeros((1, 1, 1, 48), candle::DType::F32, dev)?;
+    assert!(cache.append(&k, &k).is_err());
+    Ok(())
+}
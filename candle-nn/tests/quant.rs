@@ -0,0 +1,138 @@
+#[cfg(feature = "mkl")]
+extern crate intel_mkl_src;
+
+#[cfg(feature = "accelerate")]
+extern crate accelerate_src;
+
+use anyhow::Result;
+use candle::quantized::fake_quant::{FakeQuantizer, Granularity};
+use candle::quantized::{gguf_file, GgmlDType, QMatMul, QTensor};
+use candle::{DType, Device, Module, Tensor};
+use candle_nn::{quant, AdamW, Optimizer, ParamsAdamW, VarBuilder, VarMap};
+use std::collections::HashMap;
+
+#[test]
+fn fake_quantizer() -> Result<()> {
+    let dev = &Device::Cpu;
+    let xs = Tensor::new(&[[-1f32, 0.5, 2.0, 0.25], [0.3, 0.1, -0.2, 0.0]], dev)?;
+
+    let q = FakeQuantizer::new(8, true, Granularity::PerTensor)?;
+    assert_eq!(q.quant_range(), (-127, 127));
+    let (scale, zero_point) = q.qparams(&xs)?;
+    assert_eq!(scale.to_scalar::<f32>()?, 2.0 / 127.);
+    assert_eq!(zero_point.to_scalar::<f32>()?, 0.);
+
+    let q = FakeQuantizer::new(2, false, Granularity::PerChannel(0))?;
+    assert_eq!(q.quant_range(), (0, 3));
+    let (scale, zero_point) = q.qparams(&xs)?;
+    assert_eq!(scale.dims(), [2, 1]);
+    assert_eq!(scale.to_vec2::<f32>()?, [[1.0], [0.5 / 3.]]);
+    assert_eq!(zero_point.to_vec2::<f32>()?, [[1.0], [1.0]]);
+    let ys = q.fake_quantize(&xs)?;
+    assert_eq!(
+        candle::test_utils::to_vec2_round(&ys, 4)?,
+        [[-1.0, 1.0, 2.0, 0.0], [0.3333, 0.1667, -0.1667, 0.0]]
+    );
+
+    let q = FakeQuantizer::new(3, true, Granularity::PerGroup(2))?;
+    let ys = q.fake_quantize(&xs)?;
+    assert_eq!(
+        candle::test_utils::to_vec2_round(&ys, 4)?,
+        [[-1.0, 0.6667, 2.0, 0.0], [0.3, 0.1, -0.2, 0.0]]
+    );
+    assert!(q.fake_quantize(&xs.narrow(1, 0, 3)?).is_err());
+    assert!(FakeQuantizer::new(1, true, Granularity::PerTensor).is_err());
+    assert!(FakeQuantizer::for_ggml_dtype(GgmlDType::F16).is_err());
+    Ok(())
+}
+
+#[test]
+fn qat_linear() -> Result<()> {
+    let dev = &Device::Cpu;
+    let (in_dim, out_dim) = (64, 4);
+    let w_gen = Tensor::randn(0f32, 1., (out_dim, in_dim), dev)?;
+    let xs = Tensor::randn(0f32, 1., (128, in_dim), dev)?;
+    let ys = xs.matmul(&w_gen.t()?)?;
+
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let lin = quant::qat_linear_no_bias(in_dim, out_dim, GgmlDType::Q8_0, vb)?;
+    let params = ParamsAdamW {
+        lr: 0.05,
+        ..Default::default()
+    };
+    let mut opt = AdamW::new(varmap.all_vars(), params)?;
+    let loss = |lin: &quant::QatLinear| (lin.forward(&xs)? - &ys)?.sqr()?.mean_all();
+    let initial_loss = loss(&lin)?.to_scalar::<f32>()?;
+    for _step in 0..200 {
+        opt.backward_step(&loss(&lin)?)?;
+    }
+    let final_loss = loss(&lin)?.to_scalar::<f32>()?;
+    assert!(
+        final_loss < initial_loss * 1e-2,
+        "{initial_loss} {final_loss}"
+    );
+
+    // The exported weights match the fake quantized ones up to the f16 block scales, the inputs
+    // also get quantized by the quantized matmul.
+    let qmatmul = lin.to_qmatmul()?;
+    let expected = lin.forward(&xs)?;
+    let err = (qmatmul.forward(&xs)? - &expected)?
+        .sqr()?
+        .mean_all()?
+        .to_scalar::<f32>()?;
+    let norm = expected.sqr()?.mean_all()?.to_scalar::<f32>()?;
+    assert!(err / norm < 1e-4, "{err} {norm}");
+    Ok(())
+}
+
+#[test]
+fn gguf_var_builder() -> Result<()> {
+    let dev = &Device::Cpu;
+    let w = Tensor::randn(0f32, 1., (8, 64), dev)?;
+    let b = Tensor::randn(0f32, 1., 8, dev)?;
+    let xs = Tensor::randn(0f32, 1., (3, 64), dev)?;
+    let qw = QTensor::quantize(&w, GgmlDType::Q8_0)?;
+    let qb = QTensor::quantize(&b, GgmlDType::F32)?;
+    let mut buffer = std::io::Cursor::new(Vec::new());
+    gguf_file::write(&mut buffer, &[], &[("lin.weight", &qw), ("lin.bias", &qb)])?;
+    let vb = VarBuilder::from_gguf_buffer(buffer.get_ref(), DType::F32, dev)?;
+
+    // The linear layer keeps the quantized weights, other accesses dequantize the tensors.
+    let lin = candle_nn::qlinear(64, 8, vb.pp("lin"))?;
+    assert!(matches!(lin.weight(), QMatMul::QTensor(_)));
+    let expected = QMatMul::from_qtensor(qw)?.forward(&xs)?.broadcast_add(&b)?;
+    let diff = (lin.forward(&xs)? - expected)?.abs()?.sum_all()?;
+    assert_eq!(diff.to_scalar::<f32>()?, 0.);
+    assert_eq!(
+        vb.get(8, "lin.bias")?.to_vec1::<f32>()?,
+        b.to_vec1::<f32>()?
+    );
+    let w_deq = vb.get((8, 64), "lin.weight")?;
+    let err = (w_deq - &w)?.abs()?.flatten_all()?.max(0)?;
+    assert!(err.to_scalar::<f32>()? < 0.05);
+    assert!(candle_nn::qlinear(32, 8, vb.pp("lin")).is_err());
+
+    // Memory mapping the file gives the same layer.
+    let path = std::env::temp_dir().join(format!("candle-vb-{}.gguf", std::process::id()));
+    std::fs::write(&path, buffer.get_ref())?;
+    let vb = unsafe { VarBuilder::from_mmaped_gguf(&path, DType::F32, dev)? };
+    let mmaped_lin = candle_nn::qlinear(64, 8, vb.pp("lin"))?;
+    let diff = (mmaped_lin.forward(&xs)? - lin.forward(&xs)?)?
+        .abs()?
+        .sum_all()?;
+    assert_eq!(diff.to_scalar::<f32>()?, 0.);
+    std::fs::remove_file(&path)?;
+
+    // The same layer loaded from float tensors.
+    let ts = HashMap::from([("lin.weight".to_string(), w.clone())]);
+    let vb = VarBuilder::from_tensors(ts, DType::F32, dev);
+    let lin = candle_nn::qlinear_no_bias(64, 8, vb.pp("lin"))?;
+    assert!(matches!(lin.weight(), QMatMul::Tensor(_)));
+    let diff = (lin.forward(&xs)? - xs.matmul(&w.t()?)?)?
+        .abs()?
+        .sum_all()?;
+    assert_eq!(diff.to_scalar::<f32>()?, 0.);
+    assert!(vb.get_qtensor("lin.weight")?.is_none());
+    Ok(())
+}
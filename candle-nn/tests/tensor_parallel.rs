@@ -0,0 +1,106 @@
+#[cfg(feature = "mkl")]
+extern crate intel_mkl_src;
+
+#[cfg(feature = "accelerate")]
+extern crate accelerate_src;
+
+use anyhow::Result;
+use candle::distributed::{Communicator, InProcessCommunicator};
+use candle::{DType, Device, Module, Tensor};
+use candle_nn::var_builder::ShardedSafeTensors;
+use candle_nn::{ColumnParallelLinear, Linear, RowParallelLinear};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[test]
+fn parallel_mlp() -> Result<()> {
+    let dev = &Device::Cpu;
+    let (in_dim, hidden_dim, out_dim) = (6, 8, 5);
+    let tensors = HashMap::from([
+        (
+            "up.weight".to_string(),
+            Tensor::randn(0f32, 1., (hidden_dim, in_dim), dev)?,
+        ),
+        (
+            "up.bias".to_string(),
+            Tensor::randn(0f32, 1., hidden_dim, dev)?,
+        ),
+        (
+            "down.weight".to_string(),
+            Tensor::randn(0f32, 1., (out_dim, hidden_dim), dev)?,
+        ),
+        (
+            "down.bias".to_string(),
+            Tensor::randn(0f32, 1., out_dim, dev)?,
+        ),
+    ]);
+    let path = std::env::temp_dir().join(format!("candle-tp-{}.st", std::process::id()));
+    candle::safetensors::save(&tensors, &path)?;
+
+    let up = Linear::new(
+        tensors["up.weight"].clone(),
+        Some(tensors["up.bias"].clone()),
+    );
+    let down = Linear::new(
+        tensors["down.weight"].clone(),
+        Some(tensors["down.bias"].clone()),
+    );
+    let xs = Tensor::randn(0f32, 1., (2, 3, in_dim), dev)?;
+    let expected_hidden = up.forward(&xs)?;
+    let expected = down.forward(&expected_hidden)?;
+
+    let world_size = 2;
+    let comms = InProcessCommunicator::new_group(world_size)?;
+    let handles = comms
+        .into_iter()
+        .map(|comm| {
+            let path = path.clone();
+            let xs = xs.clone();
+            std::thread::spawn(move || -> Result<(Tensor, Tensor, Tensor)> {
+                let comm: Arc<dyn Communicator> = Arc::new(comm);
+                let vb = unsafe { ShardedSafeTensors::var_builder(&[&path], DType::F32, dev)? };
+                let up = ColumnParallelLinear::load(
+                    in_dim,
+                    hidden_dim,
+                    true,
+                    vb.pp("up"),
+                    comm.clone(),
+                )?;
+                let down = RowParallelLinear::load(
+                    hidden_dim,
+                    out_dim,
+                    true,
+                    vb.pp("down"),
+                    comm.clone(),
+                )?;
+                let hidden = up.forward(&xs)?;
+                let ys = down.forward(&hidden)?;
+                // The row parallel layer can also take the full input.
+                let full_hidden = up.clone().with_gather_output(true).forward(&xs)?;
+                let ys_full = down
+                    .clone()
+                    .with_input_is_parallel(false)
+                    .forward(&full_hidden)?;
+                Ok((hidden, ys, ys_full))
+            })
+        })
+        .collect::<Vec<_>>();
+    let results = handles
+        .into_iter()
+        .map(|h| h.join().unwrap())
+        .collect::<Result<Vec<_>>>()?;
+    std::fs::remove_file(&path)?;
+
+    let diff = |a: &Tensor, b: &Tensor| -> Result<f32> {
+        Ok(a.sub(b)?.abs()?.flatten_all()?.max(0)?.to_scalar::<f32>()?)
+    };
+    let hidden_size = hidden_dim / world_size;
+    for (rank, (hidden, ys, ys_full)) in results.iter().enumerate() {
+        assert_eq!(hidden.dims(), [2, 3, hidden_size]);
+        let expected_hidden = expected_hidden.narrow(2, rank * hidden_size, hidden_size)?;
+        assert!(diff(hidden, &expected_hidden)? < 1e-5);
+        assert!(diff(ys, &expected)? < 1e-5);
+        assert!(diff(ys_full, &expected)? < 1e-5);
+    }
+    Ok(())
+}
@@ -0,0 +1,50 @@
+#[cfg(feature = "mkl")]
+extern crate intel_mkl_src;
+
+#[cfg(feature = "accelerate")]
+extern crate accelerate_src;
+
+use anyhow::Result;
+use candle::{DType, Device};
+use candle_nn::var_builder::ShardedSafeTensors;
+use candle_nn::{Init, VarBuilder, VarMap};
+
+#[test]
+fn sharded_checkpoint() -> Result<()> {
+    let dev = &Device::Cpu;
+    let dir = std::env::temp_dir().join(format!("candle-nn-sharded-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    vb.get_with_hints((4, 2), "l1.weight", Init::Const(1.))?;
+    vb.get_with_hints(4, "l1.bias", Init::Const(2.))?;
+    vb.get_with_hints((3, 4), "l2.weight", Init::Const(3.))?;
+    let index_path = varmap.save_sharded(&dir, 48)?;
+    let n_files = std::fs::read_dir(&dir)?.count();
+    // Two shards and the index file.
+    assert_eq!(n_files, 3);
+
+    let vb = unsafe { VarBuilder::from_mmaped_safetensors_index(&index_path, DType::F32, dev)? };
+    assert!(vb.contains_tensor("l1.bias"));
+    assert!(!vb.contains_tensor("l3.bias"));
+    let w = vb.pp("l2").get((3, 4), "weight")?;
+    assert_eq!(w.sum_all()?.to_scalar::<f32>()?, 36.);
+
+    let vb = unsafe { ShardedSafeTensors::var_builder_from_index(&index_path, DType::F32, dev)? };
+    let shard = candle_nn::var_builder::Shard {
+        dim: 0,
+        rank: 1,
+        world_size: 2,
+    };
+    let w = vb.get_with_hints((2, 2), "l1.weight", shard)?;
+    assert_eq!(w.dims(), [2, 2]);
+
+    let mut other = VarMap::new();
+    let vb = VarBuilder::from_varmap(&other, DType::F32, dev);
+    vb.get_with_hints(4, "l1.bias", Init::Const(0.))?;
+    other.load_sharded(&index_path)?;
+    let bias = other.data().lock().unwrap()["l1.bias"].as_tensor().clone();
+    assert_eq!(bias.to_vec1::<f32>()?, [2., 2., 2., 2.]);
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -277,6 +277,17 @@ pub fn simple_eval(
                 let output = input0.broadcast_matmul(input1)?;
                 values.insert(node.output[0].clone(), output);
             }
+            "Einsum" => {
+                // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Einsum
+                let equation = get_attr::<str>(node, "equation")?;
+                let inputs = node
+                    .input
+                    .iter()
+                    .map(|n| get(n.as_str()))
+                    .collect::<Result<Vec<_>>>()?;
+                let output = Tensor::einsum(equation, &inputs)?;
+                values.insert(node.output[0].clone(), output);
+            }
             "Reshape" => {
                 let input0 = get(&node.input[0])?;
                 let input1 = get(&node.input[1])?.to_vec1::<i64>()?;
@@ -365,6 +365,73 @@ fn test_matmul_operation() -> Result<()> {
     Ok(())
 }
 
+// "Einsum"
+#[test]
+fn test_einsum_operation() -> Result<()> {
+    let att_equation = AttributeProto {
+        name: "equation".to_string(),
+        ref_attr_name: "equation".to_string(),
+        i: 0,
+        doc_string: "equation".to_string(),
+        r#type: 3,
+        f: 0.0,
+        s: b"ij,kj->ik".to_vec(),
+        t: None,
+        g: None,
+        sparse_tensor: None,
+        tp: None,
+        floats: vec![],
+        ints: vec![],
+        strings: vec![],
+        tensors: vec![],
+        graphs: vec![],
+        sparse_tensors: vec![],
+        type_protos: vec![],
+    };
+    let manual_graph = create_model_proto_with_graph(Some(GraphProto {
+        node: vec![NodeProto {
+            op_type: "Einsum".to_string(),
+            domain: "".to_string(),
+            attribute: vec![att_equation],
+            input: vec![INPUT_X.to_string(), INPUT_Y.to_string()],
+            output: vec![OUTPUT_Z.to_string()],
+            name: "".to_string(),
+            doc_string: "".to_string(),
+        }],
+        name: "".to_string(),
+        initializer: vec![],
+        input: vec![],
+        output: vec![ValueInfoProto {
+            name: OUTPUT_Z.to_string(),
+            doc_string: "".to_string(),
+            r#type: None,
+        }],
+        value_info: vec![],
+        doc_string: "".to_string(),
+        sparse_initializer: vec![],
+        quantization_annotation: vec![],
+    }));
+
+    let mut inputs: HashMap<String, Tensor> = HashMap::new();
+    inputs.insert(
+        INPUT_X.to_string(),
+        Tensor::new(&[[1f32, 2.], [3., 4.]], &Device::Cpu)?,
+    );
+    inputs.insert(
+        INPUT_Y.to_string(),
+        Tensor::new(&[[5f32, 6.], [7., 8.]], &Device::Cpu)?,
+    );
+
+    let eval = candle_onnx::simple_eval(&manual_graph, inputs)?;
+    assert_eq!(eval.len(), 1);
+
+    let z = eval.get(OUTPUT_Z).expect("Output 'z' not found");
+    let results = z.to_vec2::<f32>()?;
+    assert_eq!(results, vec![vec![17.0, 23.0], vec![39.0, 53.0]]);
+
+    Ok(())
+}
+
 // "Reshape"
 #[test]
 fn test_reshape_operation() -> Result<()> {
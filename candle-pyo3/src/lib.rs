@@ -83,7 +83,7 @@ enum PyDevice {
 impl PyDevice {
     fn from_device(device: &Device) -> Self {
         match device {
-            Device::Cpu => Self::Cpu,
+            Device::Cpu | Device::CpuPool(_) => Self::Cpu,
             Device::Cuda(_) => Self::Cuda,
             Device::Metal(_) => Self::Metal,
         }
@@ -0,0 +1,268 @@
+//! Beam search for encoder-decoder models.
+//!
+//! The search runs all the beams as a single batch through the decoder and keeps the decoder kv
+//! caches in sync with the surviving beams by reordering them after each step.
+use candle::{DType, Result, Tensor, D};
+
+/// A decoder that can be driven by [`BeamSearch`], e.g. the decoder part of a translation or OCR
+/// model.
+pub trait Decoder {
+    /// Runs the decoder and returns the logits for the last position of each sequence, with
+    /// shape `(batch, vocab_size)`.
+    ///
+    /// When [`Decoder::use_kv_cache`] returns `true`, `tokens` only contains the tokens that
+    /// have not been processed yet and `past_len` is the number of tokens already in the kv
+    /// cache. Otherwise `tokens` contains the full sequences and `past_len` is always 0.
+    fn decode_step(
+        &mut self,
+        tokens: &Tensor,
+        encoder_output: &Tensor,
+        past_len: usize,
+    ) -> Result<Tensor>;
+
+    /// Reorders the batch dimension of the decoder kv cache, the new batch element `i` uses the
+    /// cache of the old batch element `indices[i]`.
+    fn reorder_kv_cache(&mut self, indices: &Tensor) -> Result<()>;
+
+    fn reset_kv_cache(&mut self);
+
+    fn use_kv_cache(&self) -> bool {
+        true
+    }
+}
+
+/// A finished sequence returned by [`BeamSearch::generate`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hypothesis {
+    /// The generated tokens, excluding the decoder start token and the end of sequence token.
+    pub tokens: Vec<u32>,
+    /// The sum of the token log-probabilities divided by `length ^ length_penalty`.
+    pub score: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BeamSearch {
+    /// The number of beams kept at each step, this is also the maximum number of returned
+    /// hypotheses.
+    pub num_beams: usize,
+    /// The maximum number of generated tokens, including the end of sequence token.
+    pub max_new_tokens: usize,
+    /// The end of sequence token cannot be generated before this many tokens.
+    pub min_new_tokens: usize,
+    /// Exponent applied to the sequence length when normalizing the scores, values above 0
+    /// favor longer sequences and values below 0 favor shorter ones.
+    pub length_penalty: f64,
+    /// Stop as soon as `num_beams` hypotheses are finished rather than when no running beam
+    /// can improve on the finished hypotheses anymore.
+    pub early_stopping: bool,
+    /// The number of hypotheses returned, best first.
+    pub num_return_sequences: usize,
+    /// When non-zero, n-grams of this size can only appear once in each sequence.
+    pub no_repeat_ngram_size: usize,
+    pub decoder_start_token_id: u32,
+    pub eos_token_id: u32,
+}
+
+impl BeamSearch {
+    pub fn new(num_beams: usize, decoder_start_token_id: u32, eos_token_id: u32) -> Self {
+        Self {
+            num_beams,
+            max_new_tokens: 128,
+            min_new_tokens: 0,
+            length_penalty: 1.0,
+            early_stopping: false,
+            num_return_sequences: 1,
+            no_repeat_ngram_size: 0,
+            decoder_start_token_id,
+            eos_token_id,
+        }
+    }
+
+    fn normalized_score(&self, score: f64, len: usize) -> f64 {
+        score / (len.max(1) as f64).powf(self.length_penalty)
+    }
+
+    /// Bans the tokens that would complete an n-gram already present in `tokens`.
+    fn ban_repeated_ngrams(&self, tokens: &[u32], log_probs: &mut [f32]) {
+        let n = self.no_repeat_ngram_size;
+        if n == 0 || tokens.len() + 1 < n {
+            return;
+        }
+        let prefix = &tokens[tokens.len() + 1 - n..];
+        for ngram in tokens.windows(n) {
+            if &ngram[..n - 1] == prefix {
+                if let Some(v) = log_probs.get_mut(ngram[n - 1] as usize) {
+                    *v = f32::NEG_INFINITY
+                }
+            }
+        }
+    }
+
+    /// Runs the search for a single input, `encoder_output` must have a batch dimension of 1.
+    /// The returned hypotheses are sorted by decreasing score.
+    pub fn generate<M: Decoder>(
+        &self,
+        model: &mut M,
+        encoder_output: &Tensor,
+    ) -> Result<Vec<Hypothesis>> {
+        let num_beams = self.num_beams;
+        if num_beams == 0 {
+            candle::bail!("beam search requires at least one beam")
+        }
+        if self.num_return_sequences > num_beams {
+            candle::bail!(
+                "num_return_sequences ({}) cannot exceed num_beams ({num_beams})",
+                self.num_return_sequences
+            )
+        }
+        let (b_sz, _, _) = encoder_output.dims3()?;
+        if b_sz != 1 {
+            candle::bail!("beam search expects an encoder output with batch size 1, got {b_sz}")
+        }
+        let device = encoder_output.device();
+        let encoder_output = encoder_output.repeat((num_beams, 1, 1))?;
+        model.reset_kv_cache();
+
+        let mut beams = vec![vec![self.decoder_start_token_id]; num_beams];
+        // Only the first beam is expanded at the first step, the other ones would produce the
+        // exact same candidates.
+        let mut beam_scores = vec![f64::NEG_INFINITY; num_beams];
+        beam_scores[0] = 0.;
+        let mut finished: Vec<Hypothesis> = vec![];
+        let worst_finished = |finished: &[Hypothesis]| {
+            finished
+                .iter()
+                .map(|h| h.score)
+                .fold(f64::INFINITY, f64::min)
+        };
+
+        let mut done = false;
+        for step in 0..self.max_new_tokens {
+            let seq_len = beams[0].len();
+            let (tokens, past_len) = if model.use_kv_cache() {
+                let tokens: Vec<u32> = beams.iter().map(|b| b[seq_len - 1]).collect();
+                (
+                    Tensor::from_vec(tokens, (num_beams, 1), device)?,
+                    seq_len - 1,
+                )
+            } else {
+                let tokens: Vec<u32> = beams.iter().flatten().copied().collect();
+                (Tensor::from_vec(tokens, (num_beams, seq_len), device)?, 0)
+            };
+            let logits = model.decode_step(&tokens, &encoder_output, past_len)?;
+            let log_probs = candle_nn::ops::log_softmax(&logits.to_dtype(DType::F32)?, D::Minus1)?
+                .to_vec2::<f32>()?;
+
+            // Collect the 2 * num_beams best candidates, this ensures that there are at least
+            // num_beams candidates that do not end the sequence.
+            let mut candidates = Vec::with_capacity(num_beams * 2 * num_beams);
+            for (beam_idx, mut log_probs) in log_probs.into_iter().enumerate() {
+                let beam_score = beam_scores[beam_idx];
+                if beam_score == f64::NEG_INFINITY {
+                    continue;
+                }
+                self.ban_repeated_ngrams(&beams[beam_idx], &mut log_probs);
+                if step < self.min_new_tokens {
+                    if let Some(v) = log_probs.get_mut(self.eos_token_id as usize) {
+                        *v = f32::NEG_INFINITY
+                    }
+                }
+                let mut tokens: Vec<(u32, f32)> = log_probs
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(_, lp)| *lp > f32::NEG_INFINITY)
+                    .map(|(token, lp)| (token as u32, lp))
+                    .collect();
+                let k = usize::min(2 * num_beams, tokens.len());
+                if k < tokens.len() {
+                    tokens.select_nth_unstable_by(k, |a, b| b.1.total_cmp(&a.1));
+                    tokens.truncate(k);
+                }
+                candidates.extend(
+                    tokens
+                        .into_iter()
+                        .map(|(token, lp)| (beam_score + lp as f64, beam_idx, token)),
+                );
+            }
+            candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+            let mut next_beams = Vec::with_capacity(num_beams);
+            for (rank, &(score, beam_idx, token)) in candidates.iter().enumerate() {
+                if token == self.eos_token_id {
+                    // Finished hypotheses are only considered when they would have been kept
+                    // as a beam.
+                    if rank >= num_beams {
+                        continue;
+                    }
+                    let score = self.normalized_score(score, seq_len);
+                    if finished.len() < num_beams || score > worst_finished(&finished) {
+                        finished.push(Hypothesis {
+                            tokens: beams[beam_idx][1..].to_vec(),
+                            score,
+                        });
+                        if finished.len() > num_beams {
+                            finished.sort_by(|a, b| b.score.total_cmp(&a.score));
+                            finished.truncate(num_beams);
+                        }
+                    }
+                } else {
+                    next_beams.push((score, beam_idx, token));
+                }
+                if next_beams.len() == num_beams {
+                    break;
+                }
+            }
+            if next_beams.is_empty() {
+                done = true;
+                break;
+            }
+            // Pad with dead beams if the vocabulary is too small to fill all the beams.
+            while next_beams.len() < num_beams {
+                next_beams.push((f64::NEG_INFINITY, next_beams[0].1, next_beams[0].2))
+            }
+
+            let best_running = next_beams[0].0;
+            done = finished.len() >= num_beams
+                && (self.early_stopping
+                    || self.normalized_score(best_running, seq_len) <= worst_finished(&finished));
+            if done {
+                break;
+            }
+
+            let indices: Vec<u32> = next_beams.iter().map(|b| b.1 as u32).collect();
+            beams = next_beams
+                .iter()
+                .map(|&(_, beam_idx, token)| {
+                    let mut tokens = beams[beam_idx].clone();
+                    tokens.push(token);
+                    tokens
+                })
+                .collect();
+            beam_scores = next_beams.iter().map(|b| b.0).collect();
+            if model.use_kv_cache() {
+                model.reorder_kv_cache(&Tensor::new(indices, device)?)?;
+            }
+        }
+
+        // Beams that are still running when hitting the length limit are finished as is.
+        if !done {
+            for (tokens, &score) in beams.iter().zip(beam_scores.iter()) {
+                if score == f64::NEG_INFINITY {
+                    continue;
+                }
+                let score = self.normalized_score(score, tokens.len() - 1);
+                if finished.len() < num_beams || score > worst_finished(&finished) {
+                    finished.push(Hypothesis {
+                        tokens: tokens[1..].to_vec(),
+                        score,
+                    });
+                    finished.sort_by(|a, b| b.score.total_cmp(&a.score));
+                    finished.truncate(num_beams);
+                }
+            }
+        }
+        finished.sort_by(|a, b| b.score.total_cmp(&a.score));
+        finished.truncate(self.num_return_sequences);
+        Ok(finished)
+    }
+}
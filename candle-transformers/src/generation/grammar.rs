@@ -0,0 +1,1043 @@
+//! Grammar constrained generation.
+//!
+//! Regular expressions and [GBNF](https://github.com/ggerganov/llama.cpp/blob/master/grammars/README.md)
+//! grammars are compiled to a byte level grammar which is then matched by a pushdown automaton.
+//! At each step, [`GrammarConstraint`] walks a trie of the tokenizer vocabulary to find the
+//! tokens that can extend the current prefix and masks all the others, see
+//! [`super::LogitsProcessor::sample_masked`].
+//!
+//! Working on bytes rather than on characters makes it possible to handle tokens that only
+//! contain part of a multi-byte UTF-8 character. Left recursive grammars are not supported.
+use candle::{bail, Result};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::TokenMask;
+
+/// A grammar in GBNF format that matches any JSON value.
+pub const JSON_GBNF: &str = r#"
+root   ::= value
+value  ::= object | array | string | number | ("true" | "false" | "null") ws
+object ::= "{" ws ( string ":" ws value ("," ws string ":" ws value)* )? "}" ws
+array  ::= "[" ws ( value ("," ws value)* )? "]" ws
+string ::= "\"" ( [^"\\\x7F\x00-\x1F] | "\\" (["\\/bfnrt] | "u" [0-9a-fA-F]{4}) )* "\"" ws
+number ::= "-"? ([0-9] | [1-9] [0-9]*) ("." [0-9]+)? ([eE] [-+]? [0-9]+)? ws
+ws     ::= "" | " " | "\n" [ \t]{0,20}
+"#;
+
+const MAX_CHAR: u32 = 0x10FFFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Element {
+    /// A byte in the inclusive range.
+    Bytes(u8, u8),
+    Rule(usize),
+}
+
+/// A byte level grammar, each rule is a list of alternatives and each alternative is a sequence
+/// of byte ranges and rule references.
+#[derive(Debug, Clone)]
+pub struct Grammar {
+    rules: Vec<Vec<Vec<Element>>>,
+    root: usize,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Literal(String),
+    /// Sorted and non-overlapping inclusive ranges of code points.
+    Class(Vec<(u32, u32)>),
+    RuleRef(String),
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+    },
+}
+
+fn normalize_ranges(mut ranges: Vec<(u32, u32)>, negated: bool) -> Vec<(u32, u32)> {
+    ranges.sort();
+    let mut merged: Vec<(u32, u32)> = Vec::with_capacity(ranges.len());
+    for (lo, hi) in ranges {
+        match merged.last_mut() {
+            Some(last) if lo <= last.1.saturating_add(1) => last.1 = last.1.max(hi),
+            _ => merged.push((lo, hi)),
+        }
+    }
+    if !negated {
+        return merged;
+    }
+    let mut complement = vec![];
+    let mut start = 0;
+    for (lo, hi) in merged {
+        if lo > start {
+            complement.push((start, lo - 1))
+        }
+        start = hi + 1;
+    }
+    if start <= MAX_CHAR {
+        complement.push((start, MAX_CHAR))
+    }
+    complement
+}
+
+fn encode_utf8(c: u32, dst: &mut [u8; 4]) -> &[u8] {
+    match char::from_u32(c) {
+        Some(c) => c.encode_utf8(dst).as_bytes(),
+        None => &[],
+    }
+}
+
+/// Splits a range of code points in sequences of byte ranges that match the UTF-8 encodings of
+/// exactly these code points, surrogates are skipped.
+fn utf8_sequences(lo: u32, hi: u32, out: &mut Vec<Vec<(u8, u8)>>) {
+    let mut todo = vec![(lo, hi)];
+    'outer: while let Some((lo, hi)) = todo.pop() {
+        if lo > hi {
+            continue;
+        }
+        if lo < 0xE000 && hi > 0xD7FF {
+            todo.push((0xE000, hi));
+            todo.push((lo, 0xD7FF));
+            continue;
+        }
+        if (0xD800..0xE000).contains(&lo) {
+            continue;
+        }
+        // Split on the boundaries where the encoded length changes.
+        for max in [0x7F, 0x7FF, 0xFFFF] {
+            if lo <= max && max < hi {
+                todo.push((max + 1, hi));
+                todo.push((lo, max));
+                continue 'outer;
+            }
+        }
+        if hi <= 0x7F {
+            out.push(vec![(lo as u8, hi as u8)]);
+            continue;
+        }
+        // Split so that all the continuation bytes cover their full range except for the
+        // last differing one.
+        for i in 1..4 {
+            let m = (1u32 << (6 * i)) - 1;
+            if lo & !m != hi & !m {
+                if lo & m != 0 {
+                    todo.push(((lo | m) + 1, hi));
+                    todo.push((lo, lo | m));
+                    continue 'outer;
+                }
+                if hi & m != m {
+                    todo.push((hi & !m, hi));
+                    todo.push((lo, (hi & !m) - 1));
+                    continue 'outer;
+                }
+            }
+        }
+        let (mut b1, mut b2) = ([0u8; 4], [0u8; 4]);
+        let lo = encode_utf8(lo, &mut b1);
+        let hi = encode_utf8(hi, &mut b2);
+        out.push(lo.iter().zip(hi.iter()).map(|(&l, &h)| (l, h)).collect())
+    }
+}
+
+struct Parser<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    kind: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn new(src: &str, kind: &'a str) -> Self {
+        Self {
+            chars: src.chars().collect(),
+            pos: 0,
+            kind,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Result<char> {
+        match self.chars.get(self.pos) {
+            Some(&c) => {
+                self.pos += 1;
+                Ok(c)
+            }
+            None => bail!("{}: unexpected end of input", self.kind),
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        if !self.eat(c) {
+            bail!("{}: expected '{c}' at position {}", self.kind, self.pos)
+        }
+        Ok(())
+    }
+
+    fn hex(&mut self, len: usize) -> Result<u32> {
+        let mut v = 0;
+        for _ in 0..len {
+            let c = self.next()?;
+            match c.to_digit(16) {
+                Some(d) => v = v * 16 + d,
+                None => bail!("{}: invalid hex digit '{c}'", self.kind),
+            }
+        }
+        Ok(v)
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_ascii_digit()) {
+            self.pos += 1
+        }
+        let digits: String = self.chars[start..self.pos].iter().collect();
+        digits.parse().ok()
+    }
+
+    /// Parses an escape sequence after the backslash, returns either a single code point or a
+    /// class for the `\d`, `\w` and `\s` shorthands.
+    fn escape(&mut self, shorthands: bool) -> Result<std::result::Result<u32, Vec<(u32, u32)>>> {
+        let c = self.next()?;
+        let class = |ranges: &[(char, char)], negated| {
+            let ranges = ranges.iter().map(|&(l, h)| (l as u32, h as u32)).collect();
+            Err(normalize_ranges(ranges, negated))
+        };
+        let v = match c {
+            'n' => '\n' as u32,
+            'r' => '\r' as u32,
+            't' => '\t' as u32,
+            'f' => 0x0C,
+            'v' => 0x0B,
+            '0' => 0,
+            'x' => self.hex(2)?,
+            'u' => {
+                if self.eat('{') {
+                    let start = self.pos;
+                    while matches!(self.peek(), Some(c) if c.is_ascii_hexdigit()) {
+                        self.pos += 1
+                    }
+                    let len = self.pos - start;
+                    self.pos = start;
+                    let v = self.hex(len)?;
+                    self.expect('}')?;
+                    v
+                } else {
+                    self.hex(4)?
+                }
+            }
+            'U' => self.hex(8)?,
+            'd' | 'D' if shorthands => return Ok(class(&[('0', '9')], c == 'D')),
+            'w' | 'W' if shorthands => {
+                let ranges = [('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')];
+                return Ok(class(&ranges, c == 'W'));
+            }
+            's' | 'S' if shorthands => {
+                let ranges = [('\t', '\r'), (' ', ' ')];
+                return Ok(class(&ranges, c == 'S'));
+            }
+            c if c.is_ascii_alphanumeric() => {
+                bail!("{}: unsupported escape sequence '\\{c}'", self.kind)
+            }
+            c => c as u32,
+        };
+        if v > MAX_CHAR {
+            bail!("{}: invalid code point {v:#x}", self.kind)
+        }
+        Ok(Ok(v))
+    }
+
+    /// Parses a character class after the opening bracket.
+    fn class(&mut self, shorthands: bool) -> Result<Node> {
+        let negated = self.eat('^');
+        let mut ranges = vec![];
+        let mut first = true;
+        loop {
+            let c = self.next()?;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+            let lo = if c == '\\' {
+                match self.escape(shorthands)? {
+                    Ok(v) => v,
+                    Err(class) => {
+                        ranges.extend(class);
+                        continue;
+                    }
+                }
+            } else {
+                c as u32
+            };
+            let is_range = self.peek() == Some('-')
+                && self.chars.get(self.pos + 1).map_or(false, |&c| c != ']');
+            let hi = if is_range {
+                self.pos += 1;
+                match self.next()? {
+                    '\\' => match self.escape(false)? {
+                        Ok(v) => v,
+                        Err(_) => unreachable!(),
+                    },
+                    c => c as u32,
+                }
+            } else {
+                lo
+            };
+            if hi < lo {
+                bail!("{}: invalid class range {lo:#x}-{hi:#x}", self.kind)
+            }
+            ranges.push((lo, hi))
+        }
+        Ok(Node::Class(normalize_ranges(ranges, negated)))
+    }
+
+    /// Parses the quantifiers following an item.
+    fn quantifiers(&mut self, mut node: Node) -> Result<Node> {
+        loop {
+            let (min, max) = match self.peek() {
+                Some('*') => (0, None),
+                Some('+') => (1, None),
+                Some('?') => (0, Some(1)),
+                Some('{') => {
+                    let start = self.pos;
+                    self.pos += 1;
+                    let min = self.number();
+                    let max = if self.eat(',') { self.number() } else { min };
+                    match (min, self.eat('}')) {
+                        (Some(min), true) => {
+                            if matches!(max, Some(max) if max < min) {
+                                bail!("{}: invalid repetition at position {start}", self.kind)
+                            }
+                            self.pos -= 1;
+                            (min, max)
+                        }
+                        _ => bail!("{}: invalid repetition at position {start}", self.kind),
+                    }
+                }
+                _ => return Ok(node),
+            };
+            self.pos += 1;
+            node = Node::Repeat {
+                node: Box::new(node),
+                min,
+                max,
+            }
+        }
+    }
+}
+
+fn parse_regex(pattern: &str) -> Result<Node> {
+    fn alt(p: &mut Parser) -> Result<Node> {
+        let mut alts = vec![seq(p)?];
+        while p.eat('|') {
+            alts.push(seq(p)?)
+        }
+        Ok(if alts.len() == 1 {
+            alts.remove(0)
+        } else {
+            Node::Alt(alts)
+        })
+    }
+
+    fn seq(p: &mut Parser) -> Result<Node> {
+        let mut items = vec![];
+        while let Some(c) = p.peek() {
+            let item = match c {
+                '|' | ')' => break,
+                '(' => {
+                    p.pos += 1;
+                    if p.eat('?') {
+                        p.expect(':')?
+                    }
+                    let node = alt(p)?;
+                    p.expect(')')?;
+                    node
+                }
+                '[' => {
+                    p.pos += 1;
+                    p.class(true)?
+                }
+                '.' => {
+                    p.pos += 1;
+                    Node::Class(normalize_ranges(vec![('\n' as u32, '\n' as u32)], true))
+                }
+                '\\' => {
+                    p.pos += 1;
+                    match p.escape(true)? {
+                        Ok(v) => Node::Class(vec![(v, v)]),
+                        Err(class) => Node::Class(class),
+                    }
+                }
+                '^' if p.pos == 0 => {
+                    p.pos += 1;
+                    continue;
+                }
+                '$' if p.pos + 1 == p.chars.len() => {
+                    p.pos += 1;
+                    continue;
+                }
+                '*' | '+' | '?' | '{' | '^' | '$' => {
+                    bail!("regex: unexpected '{c}' at position {}", p.pos)
+                }
+                c => {
+                    p.pos += 1;
+                    Node::Literal(c.to_string())
+                }
+            };
+            items.push(p.quantifiers(item)?)
+        }
+        Ok(Node::Concat(items))
+    }
+
+    let mut p = Parser::new(pattern, "regex");
+    let node = alt(&mut p)?;
+    if p.pos != p.chars.len() {
+        bail!(
+            "regex: unexpected '{}' at position {}",
+            p.chars[p.pos],
+            p.pos
+        )
+    }
+    Ok(node)
+}
+
+fn parse_gbnf(src: &str) -> Result<Vec<(String, Node)>> {
+    fn is_name_char(c: char) -> bool {
+        c.is_ascii_alphanumeric() || c == '-' || c == '_'
+    }
+
+    // Skips spaces and comments, newlines are only skipped when `newlines` is set.
+    fn space(p: &mut Parser, newlines: bool) {
+        while let Some(c) = p.peek() {
+            match c {
+                ' ' | '\t' => p.pos += 1,
+                '\r' | '\n' if newlines => p.pos += 1,
+                '#' => {
+                    while !matches!(p.peek(), None | Some('\n')) {
+                        p.pos += 1
+                    }
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn name(p: &mut Parser) -> Result<String> {
+        let start = p.pos;
+        while matches!(p.peek(), Some(c) if is_name_char(c)) {
+            p.pos += 1
+        }
+        if start == p.pos {
+            bail!("gbnf: expected a rule name at position {start}")
+        }
+        Ok(p.chars[start..p.pos].iter().collect())
+    }
+
+    fn alternatives(p: &mut Parser, nested: bool) -> Result<Node> {
+        let mut alts = vec![sequence(p, nested)?];
+        loop {
+            // At the top level, an alternative can continue on the next line if it starts with
+            // a '|'.
+            let start = p.pos;
+            space(p, true);
+            if p.eat('|') {
+                space(p, true);
+                alts.push(sequence(p, nested)?)
+            } else {
+                p.pos = start;
+                break;
+            }
+        }
+        Ok(if alts.len() == 1 {
+            alts.remove(0)
+        } else {
+            Node::Alt(alts)
+        })
+    }
+
+    fn sequence(p: &mut Parser, nested: bool) -> Result<Node> {
+        let mut items = vec![];
+        loop {
+            space(p, nested);
+            let item = match p.peek() {
+                Some('"') => {
+                    p.pos += 1;
+                    let mut literal = String::new();
+                    loop {
+                        match p.next()? {
+                            '"' => break,
+                            '\\' => match p.escape(false)? {
+                                Ok(v) => literal.push(char::from_u32(v).unwrap_or('\u{FFFD}')),
+                                Err(_) => unreachable!(),
+                            },
+                            c => literal.push(c),
+                        }
+                    }
+                    Node::Literal(literal)
+                }
+                Some('[') => {
+                    p.pos += 1;
+                    p.class(false)?
+                }
+                Some('.') => {
+                    p.pos += 1;
+                    Node::Class(vec![(0, MAX_CHAR)])
+                }
+                Some('(') => {
+                    p.pos += 1;
+                    space(p, true);
+                    let node = alternatives(p, true)?;
+                    space(p, true);
+                    p.expect(')')?;
+                    node
+                }
+                Some(c) if is_name_char(c) => {
+                    // A name followed by '::=' starts the next rule.
+                    let start = p.pos;
+                    let name = name(p)?;
+                    space(p, true);
+                    let is_def = p.chars[p.pos..].starts_with(&[':', ':', '=']);
+                    if is_def {
+                        p.pos = start;
+                        break;
+                    }
+                    p.pos = start + name.chars().count();
+                    Node::RuleRef(name)
+                }
+                _ => break,
+            };
+            items.push(p.quantifiers(item)?)
+        }
+        Ok(Node::Concat(items))
+    }
+
+    let mut p = Parser::new(src, "gbnf");
+    let mut rules = vec![];
+    loop {
+        space(&mut p, true);
+        if p.peek().is_none() {
+            break;
+        }
+        let name = name(&mut p)?;
+        space(&mut p, false);
+        for c in "::=".chars() {
+            p.expect(c)?
+        }
+        space(&mut p, true);
+        let node = alternatives(&mut p, false)?;
+        space(&mut p, false);
+        if !matches!(p.peek(), None | Some('\n') | Some('\r')) {
+            bail!(
+                "gbnf: unexpected '{}' at position {}",
+                p.chars[p.pos],
+                p.pos
+            )
+        }
+        rules.push((name, node))
+    }
+    Ok(rules)
+}
+
+#[derive(Default)]
+struct GrammarBuilder {
+    rules: Vec<Vec<Vec<Element>>>,
+    names: HashMap<String, usize>,
+}
+
+impl GrammarBuilder {
+    fn new_rule(&mut self) -> usize {
+        self.rules.push(vec![]);
+        self.rules.len() - 1
+    }
+
+    fn lower(&mut self, node: &Node, out: &mut Vec<Element>) -> Result<()> {
+        match node {
+            Node::Literal(s) => out.extend(s.bytes().map(|b| Element::Bytes(b, b))),
+            Node::Class(ranges) => {
+                let mut seqs = vec![];
+                for &(lo, hi) in ranges.iter() {
+                    utf8_sequences(lo, hi, &mut seqs)
+                }
+                let to_elements =
+                    |seq: Vec<(u8, u8)>| seq.into_iter().map(|(l, h)| Element::Bytes(l, h));
+                if seqs.len() == 1 {
+                    out.extend(to_elements(seqs.remove(0)))
+                } else {
+                    let rule = self.new_rule();
+                    self.rules[rule] = seqs.into_iter().map(|s| to_elements(s).collect()).collect();
+                    out.push(Element::Rule(rule))
+                }
+            }
+            Node::RuleRef(name) => match self.names.get(name) {
+                Some(&rule) => out.push(Element::Rule(rule)),
+                None => bail!("gbnf: undefined rule '{name}'"),
+            },
+            Node::Concat(nodes) => {
+                for node in nodes.iter() {
+                    self.lower(node, out)?
+                }
+            }
+            Node::Alt(nodes) => {
+                let rule = self.new_rule();
+                for node in nodes.iter() {
+                    let mut alt = vec![];
+                    self.lower(node, &mut alt)?;
+                    self.rules[rule].push(alt)
+                }
+                out.push(Element::Rule(rule))
+            }
+            Node::Repeat { node, min, max } => {
+                for _ in 0..*min {
+                    self.lower(node, out)?
+                }
+                match max {
+                    // x* is expanded as r ::= x r | ""
+                    None => {
+                        let rule = self.new_rule();
+                        let mut alt = vec![];
+                        self.lower(node, &mut alt)?;
+                        alt.push(Element::Rule(rule));
+                        self.rules[rule] = vec![alt, vec![]];
+                        out.push(Element::Rule(rule))
+                    }
+                    // x{0,n} is expanded as r1 ::= x r2 | "", ..., rn ::= x | ""
+                    Some(max) => {
+                        let mut next = None;
+                        for _ in *min..*max {
+                            let rule = self.new_rule();
+                            let mut alt = vec![];
+                            self.lower(node, &mut alt)?;
+                            alt.extend(next.map(Element::Rule));
+                            self.rules[rule] = vec![alt, vec![]];
+                            next = Some(rule)
+                        }
+                        out.extend(next.map(Element::Rule))
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn build(self, root: usize) -> Result<Grammar> {
+        let grammar = Grammar {
+            rules: self.rules,
+            root,
+        };
+        if let Some(rule) = grammar.left_recursive_rule() {
+            let name = self.names.iter().find(|(_, &r)| r == rule);
+            match name {
+                Some((name, _)) => bail!("grammar: rule '{name}' is left recursive"),
+                None => bail!("grammar: the grammar is left recursive, e.g. x* with x matching ''"),
+            }
+        }
+        Ok(grammar)
+    }
+}
+
+impl Grammar {
+    /// Compiles a regular expression, the whole generated text has to match it.
+    ///
+    /// The supported syntax includes alternations, groups, character classes with the `\d`, `\w`
+    /// and `\s` shorthands, `.` and the `*`, `+`, `?` and `{m,n}` quantifiers.
+    pub fn from_regex(pattern: &str) -> Result<Self> {
+        let node = parse_regex(pattern)?;
+        let mut builder = GrammarBuilder::default();
+        let root = builder.new_rule();
+        let mut alt = vec![];
+        builder.lower(&node, &mut alt)?;
+        builder.rules[root].push(alt);
+        builder.build(root)
+    }
+
+    /// Compiles a grammar in the GBNF format, generation starts from the `root` rule.
+    pub fn from_gbnf(src: &str) -> Result<Self> {
+        let rules = parse_gbnf(src)?;
+        let mut builder = GrammarBuilder::default();
+        for (name, _) in rules.iter() {
+            if builder.names.contains_key(name) {
+                bail!("gbnf: rule '{name}' is defined multiple times")
+            }
+            let rule = builder.new_rule();
+            builder.names.insert(name.clone(), rule);
+        }
+        let root = match builder.names.get("root") {
+            Some(&root) => root,
+            None => bail!("gbnf: no root rule"),
+        };
+        for (name, node) in rules.iter() {
+            let rule = builder.names[name];
+            let alts = match node {
+                Node::Alt(nodes) => nodes.as_slice(),
+                node => std::slice::from_ref(node),
+            };
+            for node in alts {
+                let mut alt = vec![];
+                builder.lower(node, &mut alt)?;
+                builder.rules[rule].push(alt)
+            }
+        }
+        builder.build(root)
+    }
+
+    /// A grammar matching any JSON value, see [`JSON_GBNF`].
+    pub fn json() -> Result<Self> {
+        Self::from_gbnf(JSON_GBNF)
+    }
+
+    /// Returns a rule that can derive itself without consuming any input, if any.
+    fn left_recursive_rule(&self) -> Option<usize> {
+        let n = self.rules.len();
+        let mut nullable = vec![false; n];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (rule, alts) in self.rules.iter().enumerate() {
+                if nullable[rule] {
+                    continue;
+                }
+                let is_nullable = alts.iter().any(|alt| {
+                    alt.iter()
+                        .all(|e| matches!(e, Element::Rule(r) if nullable[*r]))
+                });
+                if is_nullable {
+                    nullable[rule] = true;
+                    changed = true;
+                }
+            }
+        }
+        // Edges to the rules that can appear in leftmost position.
+        let edges: Vec<Vec<usize>> = self
+            .rules
+            .iter()
+            .map(|alts| {
+                let mut edges = vec![];
+                for alt in alts.iter() {
+                    for e in alt.iter() {
+                        match e {
+                            Element::Bytes(_, _) => break,
+                            Element::Rule(r) => {
+                                edges.push(*r);
+                                if !nullable[*r] {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }
+                edges
+            })
+            .collect();
+        // Iterative depth first search for a cycle, 0: unvisited, 1: in progress, 2: done.
+        let mut state = vec![0u8; n];
+        for start in 0..n {
+            if state[start] != 0 {
+                continue;
+            }
+            let mut stack = vec![(start, 0)];
+            state[start] = 1;
+            while let Some((rule, i)) = stack.pop() {
+                match edges[rule].get(i) {
+                    None => state[rule] = 2,
+                    Some(&next) => {
+                        stack.push((rule, i + 1));
+                        match state[next] {
+                            0 => {
+                                state[next] = 1;
+                                stack.push((next, 0))
+                            }
+                            1 => return Some(next),
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+/// A position in the grammar: an element of an alternative of a rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Pos {
+    rule: u32,
+    alt: u32,
+    idx: u32,
+}
+
+/// The stack of positions to return to, the last position always points to a byte range. An
+/// empty stack means that the input matched the whole grammar.
+type Stack = Vec<Pos>;
+
+impl Grammar {
+    fn element(&self, pos: Pos) -> Element {
+        self.rules[pos.rule as usize][pos.alt as usize][pos.idx as usize]
+    }
+
+    /// Pushes the position following `pos` if it is not the end of its alternative.
+    fn push_next(&self, stack: &mut Stack, pos: Pos) {
+        let len = self.rules[pos.rule as usize][pos.alt as usize].len();
+        if (pos.idx as usize + 1) < len {
+            stack.push(Pos {
+                idx: pos.idx + 1,
+                ..pos
+            })
+        }
+    }
+
+    /// Expands the rule references at the top of the stack until reaching byte ranges.
+    fn expand(&self, stack: Stack, out: &mut Vec<Stack>) {
+        let top = match stack.last() {
+            None => return out.push(stack),
+            Some(&top) => top,
+        };
+        match self.element(top) {
+            Element::Bytes(_, _) => out.push(stack),
+            Element::Rule(rule) => {
+                let mut base = stack;
+                base.pop();
+                self.push_next(&mut base, top);
+                for (alt_idx, alt) in self.rules[rule].iter().enumerate() {
+                    let mut stack = base.clone();
+                    if !alt.is_empty() {
+                        stack.push(Pos {
+                            rule: rule as u32,
+                            alt: alt_idx as u32,
+                            idx: 0,
+                        })
+                    }
+                    self.expand(stack, out)
+                }
+            }
+        }
+    }
+
+    fn initial_stacks(&self) -> Vec<Stack> {
+        let mut stacks = vec![];
+        for (alt_idx, alt) in self.rules[self.root].iter().enumerate() {
+            let mut stack = vec![];
+            if !alt.is_empty() {
+                stack.push(Pos {
+                    rule: self.root as u32,
+                    alt: alt_idx as u32,
+                    idx: 0,
+                })
+            }
+            self.expand(stack, &mut stacks)
+        }
+        stacks.sort();
+        stacks.dedup();
+        stacks
+    }
+
+    fn advance(&self, stacks: &[Stack], byte: u8) -> Vec<Stack> {
+        let mut out = vec![];
+        for stack in stacks.iter() {
+            let top = match stack.last() {
+                None => continue,
+                Some(&top) => top,
+            };
+            if let Element::Bytes(lo, hi) = self.element(top) {
+                if lo <= byte && byte <= hi {
+                    let mut stack = stack[..stack.len() - 1].to_vec();
+                    self.push_next(&mut stack, top);
+                    self.expand(stack, &mut out)
+                }
+            }
+        }
+        out.sort();
+        out.dedup();
+        out
+    }
+
+    /// Returns true if `text` matches the whole grammar.
+    pub fn matches(&self, text: &[u8]) -> bool {
+        let mut stacks = self.initial_stacks();
+        for &byte in text.iter() {
+            stacks = self.advance(&stacks, byte);
+        }
+        stacks.iter().any(|s| s.is_empty())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct TrieNode {
+    children: Vec<(u8, usize)>,
+    tokens: Vec<u32>,
+}
+
+/// The byte representation of the tokens of a tokenizer, organized as a trie.
+#[derive(Debug, Clone)]
+pub struct Vocabulary {
+    tokens: Vec<Vec<u8>>,
+    eos_token_id: Option<u32>,
+    trie: Vec<TrieNode>,
+}
+
+impl Vocabulary {
+    /// Creates a vocabulary from the bytes that each token decodes to, indexed by token id.
+    /// Tokens with no bytes, e.g. special tokens, are never allowed. The end of sequence token
+    /// is only allowed once the grammar has been fully matched, whatever its bytes.
+    pub fn new(tokens: Vec<Vec<u8>>, eos_token_id: Option<u32>) -> Self {
+        let mut trie = vec![TrieNode::default()];
+        for (token_id, bytes) in tokens.iter().enumerate() {
+            if bytes.is_empty() || Some(token_id as u32) == eos_token_id {
+                continue;
+            }
+            let mut node = 0;
+            for &b in bytes.iter() {
+                node = match trie[node].children.iter().find(|(c, _)| *c == b) {
+                    Some(&(_, child)) => child,
+                    None => {
+                        trie.push(TrieNode::default());
+                        let child = trie.len() - 1;
+                        trie[node].children.push((b, child));
+                        child
+                    }
+                }
+            }
+            trie[node].tokens.push(token_id as u32)
+        }
+        Self {
+            tokens,
+            eos_token_id,
+            trie,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    pub fn token(&self, token_id: u32) -> Option<&[u8]> {
+        self.tokens.get(token_id as usize).map(|t| t.as_slice())
+    }
+
+    pub fn eos_token_id(&self) -> Option<u32> {
+        self.eos_token_id
+    }
+}
+
+/// A [`TokenMask`] that only allows the tokens for which the generated text can still match a
+/// grammar.
+#[derive(Debug, Clone)]
+pub struct GrammarConstraint {
+    grammar: Arc<Grammar>,
+    vocab: Arc<Vocabulary>,
+    stacks: Vec<Stack>,
+    finished: bool,
+}
+
+impl GrammarConstraint {
+    pub fn new(grammar: Arc<Grammar>, vocab: Arc<Vocabulary>) -> Self {
+        let stacks = grammar.initial_stacks();
+        Self {
+            grammar,
+            vocab,
+            stacks,
+            finished: false,
+        }
+    }
+
+    /// Restarts from the beginning of the grammar.
+    pub fn reset(&mut self) {
+        self.stacks = self.grammar.initial_stacks();
+        self.finished = false;
+    }
+
+    /// Returns true if the text generated so far matches the whole grammar.
+    pub fn is_accepting(&self) -> bool {
+        !self.finished && self.stacks.iter().any(|s| s.is_empty())
+    }
+
+    /// Returns true once the end of sequence token has been generated or when no token can
+    /// extend the text generated so far.
+    pub fn is_finished(&self) -> bool {
+        self.finished || self.stacks.iter().all(|s| s.is_empty())
+    }
+
+    /// The ids of the tokens that can be generated at the next step, sorted.
+    pub fn allowed_tokens(&self) -> Vec<u32> {
+        let mut allowed = vec![];
+        if self.finished {
+            return allowed;
+        }
+        self.walk_trie(0, &self.stacks, &mut allowed);
+        if let Some(eos) = self.vocab.eos_token_id {
+            if self.is_accepting() {
+                allowed.push(eos)
+            }
+        }
+        allowed.sort();
+        allowed.dedup();
+        allowed
+    }
+
+    fn walk_trie(&self, node: usize, stacks: &[Stack], allowed: &mut Vec<u32>) {
+        for &(byte, child) in self.vocab.trie[node].children.iter() {
+            let stacks = self.grammar.advance(stacks, byte);
+            if !stacks.is_empty() {
+                allowed.extend_from_slice(&self.vocab.trie[child].tokens);
+                self.walk_trie(child, &stacks, allowed)
+            }
+        }
+    }
+}
+
+impl TokenMask for GrammarConstraint {
+    fn mask(&mut self, allowed: &mut [bool]) -> Result<()> {
+        let mut ok = vec![false; allowed.len()];
+        for token_id in self.allowed_tokens() {
+            if let Some(v) = ok.get_mut(token_id as usize) {
+                *v = true
+            }
+        }
+        for (a, ok) in allowed.iter_mut().zip(ok) {
+            *a &= ok
+        }
+        Ok(())
+    }
+
+    fn advance(&mut self, token: u32) -> Result<()> {
+        if self.finished {
+            bail!("grammar: token {token} generated after the end of sequence")
+        }
+        if Some(token) == self.vocab.eos_token_id {
+            if !self.is_accepting() {
+                bail!("grammar: end of sequence generated before the grammar is complete")
+            }
+            self.finished = true;
+            return Ok(());
+        }
+        let bytes = match self.vocab.token(token) {
+            Some(bytes) if !bytes.is_empty() => bytes,
+            _ => bail!("grammar: token {token} is not allowed"),
+        };
+        let mut stacks = self.stacks.clone();
+        for &byte in bytes.iter() {
+            stacks = self.grammar.advance(&stacks, byte);
+            if stacks.is_empty() {
+                bail!("grammar: token {token} is not allowed")
+            }
+        }
+        self.stacks = stacks;
+        Ok(())
+    }
+}
@@ -1,25 +1,192 @@
+pub mod beam_search;
+pub mod grammar;
+pub mod paged;
+
 use candle::{DType, Error, Result, Tensor};
 use rand::{distributions::Distribution, SeedableRng};
 
-pub struct LogitsProcessor {
-    rng: rand::rngs::StdRng,
+/// A hook restricting the tokens that can be sampled at each step, e.g. to only generate text
+/// matching a grammar, see [`grammar::GrammarConstraint`].
+pub trait TokenMask {
+    /// Sets `allowed[i]` to `false` for the tokens that cannot be sampled at the next step.
+    /// `allowed` has one entry per logit and all the entries are initially `true`.
+    fn mask(&mut self, allowed: &mut [bool]) -> Result<()>;
+
+    /// Updates the state of the mask once `token` has been sampled.
+    fn advance(&mut self, token: u32) -> Result<()>;
+}
+
+/// Configures a [`LogitsProcessor`].
+///
+/// The penalties, logit biases and banned tokens are applied to the logits first, then the
+/// temperature. The truncation filters are applied on the resulting probabilities in the
+/// following order: top-k, tail-free, typical, top-p and min-p. When no temperature is set,
+/// the most likely token is picked after applying the penalties and biases.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogitsProcessorBuilder {
+    seed: u64,
     temperature: Option<f64>,
+    top_k: Option<usize>,
     top_p: Option<f64>,
+    min_p: Option<f64>,
+    typical_p: Option<f64>,
+    tfs_z: Option<f64>,
+    repeat_penalty: f32,
+    frequency_penalty: f32,
+    presence_penalty: f32,
+    penalty_last_n: Option<usize>,
+    logit_bias: Vec<(u32, f32)>,
+    banned_tokens: Vec<u32>,
 }
 
-impl LogitsProcessor {
-    pub fn new(seed: u64, temperature: Option<f64>, top_p: Option<f64>) -> Self {
-        let temperature = if temperature.map_or(true, |v| v < 1e-7) {
+impl LogitsProcessorBuilder {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            temperature: None,
+            top_k: None,
+            top_p: None,
+            min_p: None,
+            typical_p: None,
+            tfs_z: None,
+            repeat_penalty: 1.,
+            frequency_penalty: 0.,
+            presence_penalty: 0.,
+            penalty_last_n: None,
+            logit_bias: vec![],
+            banned_tokens: vec![],
+        }
+    }
+
+    /// The sampling temperature, the most likely token is always picked when this is not set
+    /// or close to zero.
+    pub fn temperature(mut self, temperature: f64) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    /// Only sample among the `k` most likely tokens.
+    pub fn top_k(mut self, top_k: usize) -> Self {
+        self.top_k = Some(top_k);
+        self
+    }
+
+    /// Nucleus sampling, only sample among the most likely tokens which cumulative probability
+    /// reaches `top_p`.
+    pub fn top_p(mut self, top_p: f64) -> Self {
+        self.top_p = Some(top_p);
+        self
+    }
+
+    /// Only sample among the tokens which probability is at least `min_p` times the
+    /// probability of the most likely token.
+    pub fn min_p(mut self, min_p: f64) -> Self {
+        self.min_p = Some(min_p);
+        self
+    }
+
+    /// Locally typical sampling, only sample among the tokens which information content is
+    /// closest to the entropy of the distribution, up to a cumulative probability `typical_p`.
+    pub fn typical_p(mut self, typical_p: f64) -> Self {
+        self.typical_p = Some(typical_p);
+        self
+    }
+
+    /// Tail free sampling, drop the tail of the sorted probabilities based on their second
+    /// derivative.
+    pub fn tfs_z(mut self, tfs_z: f64) -> Self {
+        self.tfs_z = Some(tfs_z);
+        self
+    }
+
+    /// Divides the positive logits (and multiplies the negative ones) of the tokens that appear
+    /// in the context by `penalty`.
+    pub fn repeat_penalty(mut self, penalty: f32) -> Self {
+        self.repeat_penalty = penalty;
+        self
+    }
+
+    /// Subtracts `penalty` times the number of occurrences in the context from the logits.
+    pub fn frequency_penalty(mut self, penalty: f32) -> Self {
+        self.frequency_penalty = penalty;
+        self
+    }
+
+    /// Subtracts `penalty` from the logits of the tokens that appear in the context.
+    pub fn presence_penalty(mut self, penalty: f32) -> Self {
+        self.presence_penalty = penalty;
+        self
+    }
+
+    /// Only consider the last `n` tokens of the context for the penalties.
+    pub fn penalty_last_n(mut self, n: usize) -> Self {
+        self.penalty_last_n = Some(n);
+        self
+    }
+
+    /// Adds `bias` to the logit of `token`.
+    pub fn logit_bias(mut self, token: u32, bias: f32) -> Self {
+        self.logit_bias.push((token, bias));
+        self
+    }
+
+    /// Never sample these tokens.
+    pub fn banned_tokens(mut self, tokens: &[u32]) -> Self {
+        self.banned_tokens.extend_from_slice(tokens);
+        self
+    }
+
+    pub fn build(self) -> LogitsProcessor {
+        let temperature = if self.temperature.map_or(true, |v| v < 1e-7) {
             None
         } else {
-            temperature
+            self.temperature
         };
-        Self {
-            rng: rand::rngs::StdRng::seed_from_u64(seed),
+        LogitsProcessor {
+            rng: rand::rngs::StdRng::seed_from_u64(self.seed),
             temperature,
-            top_p,
+            top_k: self.top_k,
+            top_p: self.top_p,
+            min_p: self.min_p,
+            typical_p: self.typical_p,
+            tfs_z: self.tfs_z,
+            repeat_penalty: self.repeat_penalty,
+            frequency_penalty: self.frequency_penalty,
+            presence_penalty: self.presence_penalty,
+            penalty_last_n: self.penalty_last_n,
+            logit_bias: self.logit_bias,
+            banned_tokens: self.banned_tokens,
         }
     }
+}
+
+pub struct LogitsProcessor {
+    rng: rand::rngs::StdRng,
+    temperature: Option<f64>,
+    top_k: Option<usize>,
+    top_p: Option<f64>,
+    min_p: Option<f64>,
+    typical_p: Option<f64>,
+    tfs_z: Option<f64>,
+    repeat_penalty: f32,
+    frequency_penalty: f32,
+    presence_penalty: f32,
+    penalty_last_n: Option<usize>,
+    logit_bias: Vec<(u32, f32)>,
+    banned_tokens: Vec<u32>,
+}
+
+impl LogitsProcessor {
+    pub fn new(seed: u64, temperature: Option<f64>, top_p: Option<f64>) -> Self {
+        let mut builder = LogitsProcessorBuilder::new(seed);
+        builder.temperature = temperature;
+        builder.top_p = top_p;
+        builder.build()
+    }
+
+    pub fn builder(seed: u64) -> LogitsProcessorBuilder {
+        LogitsProcessorBuilder::new(seed)
+    }
 
     fn sample_argmax(&mut self, logits: Tensor) -> Result<u32> {
         let logits_v: Vec<f32> = logits.to_vec1()?;
@@ -38,44 +205,215 @@ impl LogitsProcessor {
         Ok(next_token)
     }
 
-    fn sample_topp(&mut self, prs: &mut Vec<f32>, top_p: f32) -> Result<u32> {
+    /// Indexes of the non-zero probabilities sorted by decreasing probability.
+    fn sorted_indices(prs: &[f32]) -> Vec<usize> {
+        let mut argsort_indices = (0..prs.len()).filter(|&i| prs[i] > 0.).collect::<Vec<_>>();
+        argsort_indices.sort_by(|&i, &j| prs[j].total_cmp(&prs[i]));
+        argsort_indices
+    }
+
+    fn apply_top_k(prs: &mut [f32], top_k: usize) {
+        let argsort_indices = Self::sorted_indices(prs);
+        for &index in argsort_indices.iter().skip(top_k.max(1)) {
+            prs[index] = 0.0;
+        }
+    }
+
+    fn apply_top_p(prs: &mut [f32], top_p: f32) {
         // top-p sampling (or "nucleus sampling") samples from the smallest set of
         // tokens that exceed probability top_p. This way we never sample tokens that
         // have very low probabilities and are less likely to go "off the rails".
-        let mut argsort_indices = (0..prs.len()).collect::<Vec<_>>();
-
-        // Sort by descending probability.
-        argsort_indices.sort_by(|&i, &j| prs[j].partial_cmp(&prs[i]).unwrap());
-
+        let argsort_indices = Self::sorted_indices(prs);
+        let total: f32 = prs.iter().sum();
         // Clamp smaller probabilities to zero.
         let mut cumsum = 0.;
         for index in &argsort_indices {
-            if cumsum >= top_p {
+            if cumsum >= top_p * total {
                 prs[*index] = 0.0;
             } else {
                 cumsum += prs[*index];
             }
         }
-        // Sample with clamped probabilities.
-        self.sample_multinomial(prs)
+    }
+
+    fn apply_min_p(prs: &mut [f32], min_p: f32) {
+        let max_p = prs.iter().fold(0f32, |acc, &p| acc.max(p));
+        let threshold = max_p * min_p;
+        for p in prs.iter_mut() {
+            if *p < threshold {
+                *p = 0.0
+            }
+        }
+    }
+
+    fn apply_typical_p(prs: &mut [f32], typical_p: f32) {
+        let total: f32 = prs.iter().sum();
+        let entropy: f32 = prs
+            .iter()
+            .filter(|&&p| p > 0.)
+            .map(|&p| {
+                let p = p / total;
+                -p * p.ln()
+            })
+            .sum();
+        let mut indices = (0..prs.len()).filter(|&i| prs[i] > 0.).collect::<Vec<_>>();
+        let shifted = |i: usize| (-(prs[i] / total).ln() - entropy).abs();
+        indices.sort_by(|&i, &j| shifted(i).total_cmp(&shifted(j)));
+        let mut cumsum = 0.;
+        for index in indices {
+            if cumsum >= typical_p * total {
+                prs[index] = 0.0;
+            } else {
+                cumsum += prs[index];
+            }
+        }
+    }
+
+    fn apply_tfs(prs: &mut [f32], z: f32) {
+        let argsort_indices = Self::sorted_indices(prs);
+        if argsort_indices.len() <= 2 {
+            return;
+        }
+        let sorted: Vec<f32> = argsort_indices.iter().map(|&i| prs[i]).collect();
+        let first: Vec<f32> = sorted.windows(2).map(|w| w[0] - w[1]).collect();
+        let second: Vec<f32> = first.windows(2).map(|w| (w[0] - w[1]).abs()).collect();
+        let total: f32 = second.iter().sum();
+        if total <= 0. {
+            return;
+        }
+        let mut cumsum = 0.;
+        let mut keep = argsort_indices.len();
+        for (i, d) in second.iter().enumerate() {
+            cumsum += d / total;
+            if cumsum > z && i >= 1 {
+                keep = i;
+                break;
+            }
+        }
+        for &index in argsort_indices.iter().skip(keep) {
+            prs[index] = 0.0;
+        }
+    }
+
+    /// Applies the penalties, biases and banned tokens to the logits, this is done on the same
+    /// device as the logits.
+    fn apply_penalties(&self, logits: &Tensor, context: &[u32]) -> Result<Tensor> {
+        let vocab_size = logits.dim(0)?;
+        let device = logits.device();
+        let mut logits = logits.clone();
+        let has_penalties = self.repeat_penalty != 1.
+            || self.frequency_penalty != 0.
+            || self.presence_penalty != 0.;
+        if has_penalties && !context.is_empty() {
+            let context = match self.penalty_last_n {
+                Some(n) => &context[context.len().saturating_sub(n)..],
+                None => context,
+            };
+            let mut counts = vec![0f32; vocab_size];
+            for &token in context.iter() {
+                if let Some(c) = counts.get_mut(token as usize) {
+                    *c += 1.
+                }
+            }
+            let counts = Tensor::from_vec(counts, vocab_size, device)?;
+            let present = counts.gt(0f64)?;
+            if self.repeat_penalty != 1. {
+                let penalty = self.repeat_penalty as f64;
+                let penalized = logits
+                    .ge(0f64)?
+                    .where_cond(&(&logits / penalty)?, &(&logits * penalty)?)?;
+                logits = present.where_cond(&penalized, &logits)?;
+            }
+            if self.frequency_penalty != 0. {
+                logits = (logits - (&counts * self.frequency_penalty as f64)?)?;
+            }
+            if self.presence_penalty != 0. {
+                let present = (present.to_dtype(DType::F32)? * self.presence_penalty as f64)?;
+                logits = (logits - present)?;
+            }
+        }
+        if !self.logit_bias.is_empty() || !self.banned_tokens.is_empty() {
+            let mut bias = vec![0f32; vocab_size];
+            for &(token, b) in self.logit_bias.iter() {
+                if let Some(v) = bias.get_mut(token as usize) {
+                    *v += b
+                }
+            }
+            for &token in self.banned_tokens.iter() {
+                if let Some(v) = bias.get_mut(token as usize) {
+                    *v = f32::NEG_INFINITY
+                }
+            }
+            let bias = Tensor::from_vec(bias, vocab_size, device)?;
+            logits = (logits + bias)?;
+        }
+        Ok(logits)
+    }
+
+    /// Samples the next token among the ones allowed by `mask`, the mask is then advanced with
+    /// the sampled token.
+    pub fn sample_masked(
+        &mut self,
+        logits: &Tensor,
+        context: &[u32],
+        mask: &mut dyn TokenMask,
+    ) -> Result<u32> {
+        let vocab_size = logits.dim(0)?;
+        let mut allowed = vec![true; vocab_size];
+        mask.mask(&mut allowed)?;
+        if !allowed.iter().any(|&a| a) {
+            candle::bail!("no token is allowed by the mask")
+        }
+        let bias: Vec<f32> = allowed
+            .iter()
+            .map(|&a| if a { 0. } else { f32::NEG_INFINITY })
+            .collect();
+        let bias = Tensor::from_vec(bias, vocab_size, logits.device())?;
+        let logits = (logits.to_dtype(DType::F32)? + bias)?;
+        let next_token = self.sample_with_context(&logits, context)?;
+        mask.advance(next_token)?;
+        Ok(next_token)
     }
 
     pub fn sample(&mut self, logits: &Tensor) -> Result<u32> {
+        self.sample_with_context(logits, &[])
+    }
+
+    /// Samples the next token, `context` contains the previous tokens and is used for the
+    /// repeat, frequency and presence penalties.
+    pub fn sample_with_context(&mut self, logits: &Tensor, context: &[u32]) -> Result<u32> {
         let logits = logits.to_dtype(DType::F32)?;
+        let logits = self.apply_penalties(&logits, context)?;
         let next_token = match self.temperature {
             None => self.sample_argmax(logits)?,
             Some(temperature) => {
                 let logits = &(&logits / temperature)?;
                 let prs = candle_nn::ops::softmax_last_dim(logits)?;
                 let mut prs: Vec<f32> = prs.to_vec1()?;
+                if let Some(top_k) = self.top_k {
+                    Self::apply_top_k(&mut prs, top_k)
+                }
+                if let Some(z) = self.tfs_z {
+                    if z < 1.0 {
+                        Self::apply_tfs(&mut prs, z as f32)
+                    }
+                }
+                if let Some(typical_p) = self.typical_p {
+                    if typical_p < 1.0 {
+                        Self::apply_typical_p(&mut prs, typical_p as f32)
+                    }
+                }
                 let top_p = self.top_p.unwrap_or(1.);
-                if top_p <= 0.0 || top_p >= 1.0 {
-                    // simply sample from the predicted probability distribution
-                    self.sample_multinomial(&prs)?
-                } else {
+                if top_p > 0.0 && top_p < 1.0 {
                     // top-p (nucleus) sampling, clamping the least likely tokens to zero
-                    self.sample_topp(&mut prs, top_p as f32)?
+                    Self::apply_top_p(&mut prs, top_p as f32)
+                }
+                if let Some(min_p) = self.min_p {
+                    if min_p > 0.0 {
+                        Self::apply_min_p(&mut prs, min_p as f32)
+                    }
                 }
+                self.sample_multinomial(&prs)?
             }
         };
         Ok(next_token)
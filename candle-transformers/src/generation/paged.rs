@@ -0,0 +1,257 @@
+//! Paged kv cache shared by multiple sequences, and the model interface used to run a batch of
+//! sequences against it. The generation engine lives in [`crate::pipelines::text_generation`].
+use candle::{DType, Device, Result, Tensor};
+
+/// The size of the paged kv cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// The number of positions stored in each block.
+    pub block_size: usize,
+    /// The number of blocks in the pool, shared between all the sequences.
+    pub num_blocks: usize,
+}
+
+/// A block based kv cache shared by multiple sequences.
+///
+/// For each layer, the keys and values are stored in tensors of shape
+/// `(num_blocks * block_size, num_kv_heads, head_dim)`, position `p` of a sequence lives in
+/// slot `block_table[p / block_size] * block_size + p % block_size`.
+#[derive(Debug)]
+pub struct PagedKvCache {
+    block_size: usize,
+    num_blocks: usize,
+    layers: Vec<(Tensor, Tensor)>,
+    free_blocks: Vec<usize>,
+}
+
+impl PagedKvCache {
+    pub fn new(
+        cfg: &CacheConfig,
+        num_layers: usize,
+        num_kv_heads: usize,
+        head_dim: usize,
+        dtype: DType,
+        device: &Device,
+    ) -> Result<Self> {
+        if cfg.block_size == 0 || cfg.num_blocks == 0 {
+            candle::bail!("invalid cache config {cfg:?}")
+        }
+        let num_slots = cfg.num_blocks * cfg.block_size;
+        let layers = (0..num_layers)
+            .map(|_| {
+                let k = Tensor::zeros((num_slots, num_kv_heads, head_dim), dtype, device)?;
+                let v = Tensor::zeros((num_slots, num_kv_heads, head_dim), dtype, device)?;
+                Ok((k, v))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // Blocks are popped from the end so reverse the list to hand them in increasing order.
+        let free_blocks = (0..cfg.num_blocks).rev().collect();
+        Ok(Self {
+            block_size: cfg.block_size,
+            num_blocks: cfg.num_blocks,
+            layers,
+            free_blocks,
+        })
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    pub fn num_blocks(&self) -> usize {
+        self.num_blocks
+    }
+
+    pub fn num_free_blocks(&self) -> usize {
+        self.free_blocks.len()
+    }
+
+    /// The number of blocks needed to store `num_tokens` positions.
+    pub fn blocks_for(&self, num_tokens: usize) -> usize {
+        (num_tokens + self.block_size - 1) / self.block_size
+    }
+
+    /// Reserves a block, returns `None` when the pool is exhausted.
+    pub fn allocate(&mut self) -> Option<usize> {
+        self.free_blocks.pop()
+    }
+
+    /// Returns some blocks to the pool.
+    pub fn free(&mut self, blocks: &[usize]) {
+        self.free_blocks.extend(blocks.iter().rev())
+    }
+
+    /// The size in bytes of the cache storage.
+    pub fn allocated_bytes(&self) -> usize {
+        self.layers
+            .iter()
+            .map(|(k, v)| (k.elem_count() + v.elem_count()) * k.dtype().size_in_bytes())
+            .sum()
+    }
+
+    fn slot(&self, block_table: &[usize], pos: usize) -> Result<usize> {
+        match block_table.get(pos / self.block_size) {
+            Some(block) => Ok(block * self.block_size + pos % self.block_size),
+            None => candle::bail!("no block allocated for position {pos}"),
+        }
+    }
+
+    /// Stores the keys and values for positions `position..position + len` of a sequence, `k`
+    /// and `v` have shape `(len, num_kv_heads, head_dim)`.
+    pub fn write(
+        &self,
+        layer_idx: usize,
+        block_table: &[usize],
+        position: usize,
+        k: &Tensor,
+        v: &Tensor,
+    ) -> Result<()> {
+        let (cache_k, cache_v) = &self.layers[layer_idx];
+        let len = k.dim(0)?;
+        // Copy runs of consecutive positions that belong to the same block at once.
+        let mut start = 0;
+        while start < len {
+            let pos = position + start;
+            let run = usize::min(self.block_size - pos % self.block_size, len - start);
+            let slot = self.slot(block_table, pos)?;
+            cache_k.slice_set(&k.narrow(0, start, run)?, 0, slot)?;
+            cache_v.slice_set(&v.narrow(0, start, run)?, 0, slot)?;
+            start += run;
+        }
+        Ok(())
+    }
+
+    /// Returns the keys and values for positions `0..len` of a sequence, both with shape
+    /// `(len, num_kv_heads, head_dim)`.
+    pub fn gather(
+        &self,
+        layer_idx: usize,
+        block_table: &[usize],
+        len: usize,
+    ) -> Result<(Tensor, Tensor)> {
+        let (cache_k, cache_v) = &self.layers[layer_idx];
+        let slots = (0..len)
+            .map(|pos| self.slot(block_table, pos).map(|s| s as u32))
+            .collect::<Result<Vec<_>>>()?;
+        let slots = Tensor::new(slots, cache_k.device())?;
+        let k = cache_k.index_select(&slots, 0)?;
+        let v = cache_v.index_select(&slots, 0)?;
+        Ok((k, v))
+    }
+}
+
+/// The tokens to process for a sequence in a batched forward pass.
+#[derive(Debug, Clone, Copy)]
+pub struct SequenceInput<'a> {
+    /// The new tokens, these have not been written to the cache yet.
+    pub tokens: &'a [u32],
+    /// The position of the first new token, i.e. the number of tokens already in the cache.
+    pub position: usize,
+    /// The blocks used by this sequence, these must be able to hold `position + tokens.len()`
+    /// positions.
+    pub block_table: &'a [usize],
+}
+
+/// Causal attention for a single sequence using the paged kv cache.
+///
+/// `q` has shape `(1, num_heads, seq_len, head_dim)` and `k`, `v` have shape
+/// `(1, num_kv_heads, seq_len, head_dim)`, the rotary embeddings have to be applied beforehand.
+/// The new keys and values are written to the cache and the attention is computed over all the
+/// positions of the sequence. Returns a tensor with the same shape as `q`.
+pub fn paged_attention(
+    q: &Tensor,
+    k: &Tensor,
+    v: &Tensor,
+    cache: &PagedKvCache,
+    layer_idx: usize,
+    seq: &SequenceInput,
+    sliding_window: Option<usize>,
+) -> Result<Tensor> {
+    let (_b_sz, num_heads, seq_len, head_dim) = q.dims4()?;
+    let num_kv_heads = k.dim(1)?;
+    let k = k.squeeze(0)?.transpose(0, 1)?;
+    let v = v.squeeze(0)?.transpose(0, 1)?;
+    cache.write(layer_idx, seq.block_table, seq.position, &k, &v)?;
+    let kv_len = seq.position + seq_len;
+    let (k, v) = cache.gather(layer_idx, seq.block_table, kv_len)?;
+    let repeat_kv = |xs: Tensor| {
+        let n_rep = num_heads / num_kv_heads;
+        // (kv_len, num_kv_heads, head_dim) -> (1, num_heads, kv_len, head_dim)
+        let xs = xs.transpose(0, 1)?;
+        let xs = if n_rep == 1 {
+            xs
+        } else {
+            xs.unsqueeze(1)?
+                .expand((num_kv_heads, n_rep, kv_len, head_dim))?
+                .reshape((num_heads, kv_len, head_dim))?
+        };
+        xs.unsqueeze(0)?.contiguous()
+    };
+    let k = repeat_kv(k)?;
+    let v = repeat_kv(v)?;
+
+    let in_dtype = q.dtype();
+    let q = q.to_dtype(DType::F32)?.contiguous()?;
+    let k = k.to_dtype(DType::F32)?;
+    let v = v.to_dtype(DType::F32)?;
+    let att = (q.matmul(&k.t()?)? / (head_dim as f64).sqrt())?;
+    let needs_window = sliding_window.map_or(false, |w| kv_len > w);
+    let att = if seq_len > 1 || needs_window {
+        let position = seq.position;
+        let mask: Vec<_> = (0..seq_len)
+            .flat_map(|i| {
+                let i = i + position;
+                (0..kv_len).map(move |j| {
+                    let too_far = sliding_window.map_or(false, |w| j + w <= i);
+                    if j > i || too_far {
+                        f32::NEG_INFINITY
+                    } else {
+                        0.
+                    }
+                })
+            })
+            .collect();
+        let mask = Tensor::from_slice(&mask, (seq_len, kv_len), q.device())?;
+        att.broadcast_add(&mask)?
+    } else {
+        att
+    };
+    let att = candle_nn::ops::softmax_last_dim(&att)?;
+    att.matmul(&v)?.to_dtype(in_dtype)
+}
+
+/// A model that can be driven by the
+/// [`TextGeneration`](crate::pipelines::text_generation::TextGeneration) engine.
+pub trait PagedModel {
+    fn num_layers(&self) -> usize;
+    fn num_kv_heads(&self) -> usize;
+    fn head_dim(&self) -> usize;
+    fn dtype(&self) -> DType;
+    fn device(&self) -> &Device;
+
+    /// Runs a forward pass on a batch of sequences, the keys and values for the new tokens are
+    /// stored in `cache`. Returns the logits for the last token of each sequence, with shape
+    /// `(seqs.len(), vocab_size)`.
+    fn forward_paged(&self, seqs: &[SequenceInput], cache: &PagedKvCache) -> Result<Tensor>;
+}
+
+/// Returns the hidden states at the last position of each sequence, `xs` has shape
+/// `(1, total_tokens, hidden_size)` where the tokens of all sequences are concatenated.
+pub fn last_positions(xs: &Tensor, seqs: &[SequenceInput]) -> Result<Tensor> {
+    let mut offset = 0;
+    let indexes: Vec<u32> = seqs
+        .iter()
+        .map(|seq| {
+            offset += seq.tokens.len();
+            offset as u32 - 1
+        })
+        .collect();
+    let indexes = Tensor::new(indexes, xs.device())?;
+    xs.squeeze(0)?.index_select(&indexes, 0)
+}
+
+/// Concatenates the tokens of all sequences into a `(1, total_tokens)` tensor.
+pub fn batch_tokens(seqs: &[SequenceInput], device: &Device) -> Result<Tensor> {
+    let tokens: Vec<u32> = seqs.iter().flat_map(|s| s.tokens.iter().copied()).collect();
+    Tensor::new(tokens, device)?.unsqueeze(0)
+}
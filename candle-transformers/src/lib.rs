@@ -2,6 +2,7 @@ pub mod generation;
 pub mod models;
 pub mod object_detection;
 pub mod pipelines;
+pub mod quantized_int4;
 pub mod quantized_nn;
 pub mod quantized_var_builder;
 pub mod utils;
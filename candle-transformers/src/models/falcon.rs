@@ -1,7 +1,8 @@
 use candle::{DType, Device, Result, Tensor, D};
-use candle_nn::{embedding, Embedding, LayerNorm, Linear, Module, VarBuilder};
+use candle_nn::{embedding, kv_cache::KvCache, Embedding, LayerNorm, Linear, Module, VarBuilder};
 
 const MAX_SEQ_LEN: usize = 5000;
+const KV_CACHE_INIT_LEN: usize = 512;
 
 fn linear(size1: usize, size2: usize, bias: bool, vb: VarBuilder) -> Result<Linear> {
     let weight = vb.get((size2, size1), "weight")?;
@@ -198,7 +199,7 @@ struct FalconAttention {
     query_key_value: Linear,
     dense: Linear,
     maybe_rotary: Option<FalconRotaryEmbedding>,
-    kv_cache: Option<(Tensor, Tensor)>,
+    kv_cache: KvCache,
     inv_norm_factor: f64,
     multi_query: bool,
     use_cache: bool,
@@ -228,7 +229,7 @@ impl FalconAttention {
             query_key_value,
             dense,
             maybe_rotary,
-            kv_cache: None,
+            kv_cache: KvCache::new(1, KV_CACHE_INIT_LEN),
             inv_norm_factor: 1. / (head_dim as f64).sqrt(),
             multi_query: cfg.multi_query,
             use_cache: cfg.use_cache,
@@ -279,13 +280,9 @@ impl FalconAttention {
         let (mut key, mut value) = (key, value);
         let mask = masked_fill(&mask.to_dtype(DType::F32)?, mask, -1e9)?.to_dtype(query.dtype())?;
         if self.use_cache {
-            if let Some((cache_k, cache_v)) = &self.kv_cache {
-                // TODO: we could trim the tensors to MAX_SEQ_LEN so that this would work for
-                // arbitrarily large sizes.
-                key = Tensor::cat(&[cache_k, &key], 1)?.contiguous()?;
-                value = Tensor::cat(&[cache_v, &value], 1)?.contiguous()?;
-            }
-            self.kv_cache = Some((key.clone(), value.clone()))
+            let (cache_k, cache_v) = self.kv_cache.append(&key, &value)?;
+            key = cache_k.contiguous()?;
+            value = cache_v.contiguous()?;
         }
         let query = query.reshape((b_sz * self.num_heads, seq_len, head_dim))?;
         let all_len = past_kv_len + seq_len;
@@ -463,10 +460,7 @@ impl Falcon {
     pub fn forward(&mut self, input_ids: &Tensor) -> Result<Tensor> {
         let (b_sz, seq_len) = input_ids.dims2()?;
         let mut hidden_state = self.word_embeddings.forward(input_ids)?;
-        let past_kv_len = match &self.blocks[0].self_attention.kv_cache {
-            Some((k, _)) => k.dim(1)?,
-            None => 0,
-        };
+        let past_kv_len = self.blocks[0].self_attention.kv_cache.current_seq_len();
         let causal_mask = prepare_attn_mask(b_sz, seq_len)?.to_device(input_ids.device())?;
         for block in self.blocks.iter_mut() {
             hidden_state = block.forward(&hidden_state, &causal_mask, past_kv_len)?;
@@ -1,11 +1,15 @@
 use super::with_tracing::{linear_no_bias as linear, Linear};
+use crate::generation::paged::{
+    batch_tokens, last_positions, paged_attention, PagedKvCache, PagedModel, SequenceInput,
+};
 use candle::{DType, Device, IndexOp, Result, Tensor, D};
-use candle_nn::{embedding, Embedding, Module, VarBuilder};
+use candle_nn::{embedding, kv_cache::KvCache, Embedding, Module, VarBuilder};
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
 pub const MAX_SEQ_LEN: usize = 4096;
+const KV_CACHE_INIT_LEN: usize = 512;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct LlamaConfig {
@@ -87,8 +91,7 @@ impl Config {
 pub struct Cache {
     masks: Arc<Mutex<HashMap<usize, Tensor>>>,
     pub use_kv_cache: bool,
-    #[allow(clippy::type_complexity)]
-    kvs: Arc<Mutex<Vec<Option<(Tensor, Tensor)>>>>,
+    kvs: Arc<Mutex<Vec<KvCache>>>,
     cos: Tensor,
     sin: Tensor,
     device: Device,
@@ -115,7 +118,12 @@ impl Cache {
         Ok(Self {
             masks: Arc::new(Mutex::new(HashMap::new())),
             use_kv_cache,
-            kvs: Arc::new(Mutex::new(vec![None; config.num_hidden_layers])),
+            // The kv caches start small and grow as needed rather than preallocating
+            // MAX_SEQ_LEN positions for each layer.
+            kvs: Arc::new(Mutex::new(vec![
+                KvCache::new(2, KV_CACHE_INIT_LEN);
+                config.num_hidden_layers
+            ])),
             device: device.clone(),
             cos,
             sin,
@@ -224,23 +232,9 @@ impl CausalSelfAttention {
 
         if self.cache.use_kv_cache {
             let mut cache = self.cache.kvs.lock().unwrap();
-            if let Some((cache_k, cache_v)) = &cache[block_idx] {
-                k = Tensor::cat(&[cache_k, &k], 2)?.contiguous()?;
-                v = Tensor::cat(&[cache_v, &v], 2)?.contiguous()?;
-                let k_seq_len = k.dims()[1];
-                if k_seq_len > MAX_SEQ_LEN {
-                    k = k
-                        .narrow(D::Minus1, k_seq_len - MAX_SEQ_LEN, MAX_SEQ_LEN)?
-                        .contiguous()?
-                }
-                let v_seq_len = v.dims()[1];
-                if v_seq_len > 2 * MAX_SEQ_LEN {
-                    v = v
-                        .narrow(D::Minus1, v_seq_len - MAX_SEQ_LEN, MAX_SEQ_LEN)?
-                        .contiguous()?
-                }
-            }
-            cache[block_idx] = Some((k.clone(), v.clone()))
+            let (cache_k, cache_v) = cache[block_idx].append(&k, &v)?;
+            k = cache_k.contiguous()?;
+            v = cache_v.contiguous()?;
         }
 
         let k = self.repeat_kv(k)?;
@@ -270,6 +264,50 @@ impl CausalSelfAttention {
         Ok(y)
     }
 
+    fn forward_paged(
+        &self,
+        x: &Tensor,
+        seqs: &[SequenceInput],
+        cache: &PagedKvCache,
+        block_idx: usize,
+    ) -> Result<Tensor> {
+        let _enter = self.span.enter();
+        let (b_sz, total_len, hidden_size) = x.dims3()?;
+        let q = self.q_proj.forward(x)?.reshape((
+            b_sz,
+            total_len,
+            self.num_attention_heads,
+            self.head_dim,
+        ))?;
+        let k = self.k_proj.forward(x)?.reshape((
+            b_sz,
+            total_len,
+            self.num_key_value_heads,
+            self.head_dim,
+        ))?;
+        let v = self.v_proj.forward(x)?.reshape((
+            b_sz,
+            total_len,
+            self.num_key_value_heads,
+            self.head_dim,
+        ))?;
+        let mut offset = 0;
+        let mut ys = Vec::with_capacity(seqs.len());
+        for seq in seqs.iter() {
+            let seq_len = seq.tokens.len();
+            let q = q.narrow(1, offset, seq_len)?.transpose(1, 2)?;
+            let k = k.narrow(1, offset, seq_len)?.transpose(1, 2)?;
+            let v = v.narrow(1, offset, seq_len)?.transpose(1, 2)?;
+            let q = self.apply_rotary_emb(&q, seq.position)?;
+            let k = self.apply_rotary_emb(&k, seq.position)?;
+            let y = paged_attention(&q, &k, &v, cache, block_idx, seq, None)?;
+            ys.push(y.transpose(1, 2)?.reshape((b_sz, seq_len, hidden_size))?);
+            offset += seq_len;
+        }
+        let y = Tensor::cat(&ys, 1)?;
+        self.o_proj.forward(&y)
+    }
+
     fn repeat_kv(&self, x: Tensor) -> Result<Tensor> {
         let n_rep = self.num_attention_heads / self.num_key_value_heads;
         if n_rep == 1 {
@@ -368,6 +406,22 @@ impl Block {
         Ok(x)
     }
 
+    fn forward_paged(
+        &self,
+        x: &Tensor,
+        seqs: &[SequenceInput],
+        cache: &PagedKvCache,
+        block_idx: usize,
+    ) -> Result<Tensor> {
+        let _enter = self.span.enter();
+        let residual = x;
+        let x = self.rms_1.forward(x)?;
+        let x = (self.attn.forward_paged(&x, seqs, cache, block_idx)? + residual)?;
+        let residual = &x;
+        let x = (self.mlp.forward(&self.rms_2.forward(&x)?)? + residual)?;
+        Ok(x)
+    }
+
     fn load(vb: VarBuilder, cache: &Cache, cfg: &Config) -> Result<Self> {
         let span = tracing::span!(tracing::Level::TRACE, "block");
         let attn = CausalSelfAttention::load(vb.pp("self_attn"), cache, cfg)?;
@@ -425,3 +479,37 @@ impl Llama {
         })
     }
 }
+
+impl PagedModel for Llama {
+    fn num_layers(&self) -> usize {
+        self.blocks.len()
+    }
+
+    fn num_kv_heads(&self) -> usize {
+        self.blocks[0].attn.num_key_value_heads
+    }
+
+    fn head_dim(&self) -> usize {
+        self.blocks[0].attn.head_dim
+    }
+
+    fn dtype(&self) -> DType {
+        self.wte.embeddings().dtype()
+    }
+
+    fn device(&self) -> &Device {
+        self.wte.embeddings().device()
+    }
+
+    fn forward_paged(&self, seqs: &[SequenceInput], cache: &PagedKvCache) -> Result<Tensor> {
+        let x = batch_tokens(seqs, self.device())?;
+        let mut x = self.wte.forward(&x)?;
+        for (block_idx, block) in self.blocks.iter().enumerate() {
+            x = block.forward_paged(&x, seqs, cache, block_idx)?;
+        }
+        let x = self.ln_f.forward(&x)?;
+        let x = last_positions(&x, seqs)?;
+        let logits = self.lm_head.forward(&x)?;
+        logits.to_dtype(DType::F32)
+    }
+}
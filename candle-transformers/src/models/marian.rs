@@ -217,6 +217,15 @@ impl Attention {
     fn reset_kv_cache(&mut self) {
         self.kv_cache = None
     }
+
+    fn reorder_kv_cache(&mut self, indices: &Tensor) -> Result<()> {
+        if let Some((k, v)) = &self.kv_cache {
+            let k = k.contiguous()?.index_select(indices, 0)?;
+            let v = v.contiguous()?.index_select(indices, 0)?;
+            self.kv_cache = Some((k, v))
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -327,6 +336,10 @@ impl DecoderLayer {
         self.self_attn.reset_kv_cache();
         self.encoder_attn.reset_kv_cache()
     }
+
+    fn reorder_kv_cache(&mut self, indices: &Tensor) -> Result<()> {
+        self.self_attn.reorder_kv_cache(indices)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -441,6 +454,13 @@ impl Decoder {
             layer.reset_kv_cache()
         }
     }
+
+    pub fn reorder_kv_cache(&mut self, indices: &Tensor) -> Result<()> {
+        for layer in self.layers.iter_mut() {
+            layer.reorder_kv_cache(indices)?
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -517,4 +537,31 @@ impl MTModel {
     pub fn reset_kv_cache(&mut self) {
         self.model.reset_kv_cache();
     }
+
+    /// Reorders the decoder kv cache along the batch dimension, see
+    /// [`crate::generation::beam_search::Decoder::reorder_kv_cache`].
+    pub fn reorder_kv_cache(&mut self, indices: &Tensor) -> Result<()> {
+        self.model.decoder.reorder_kv_cache(indices)
+    }
+}
+
+impl crate::generation::beam_search::Decoder for MTModel {
+    fn decode_step(
+        &mut self,
+        tokens: &Tensor,
+        encoder_output: &Tensor,
+        past_len: usize,
+    ) -> Result<Tensor> {
+        let logits = self.decode(tokens, encoder_output, past_len)?;
+        let seq_len = logits.dim(1)?;
+        logits.narrow(1, seq_len - 1, 1)?.squeeze(1)
+    }
+
+    fn reorder_kv_cache(&mut self, indices: &Tensor) -> Result<()> {
+        MTModel::reorder_kv_cache(self, indices)
+    }
+
+    fn reset_kv_cache(&mut self) {
+        self.model.decoder.reset_kv_cache()
+    }
 }
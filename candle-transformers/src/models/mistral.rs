@@ -1,23 +1,26 @@
+use crate::generation::paged::{
+    batch_tokens, last_positions, paged_attention, PagedKvCache, PagedModel, SequenceInput,
+};
 use crate::models::with_tracing::{linear_no_bias, Linear};
 /// Mistral LLM, https://github.com/mistralai/mistral-src
 use candle::{DType, Device, Module, Result, Tensor, D};
-use candle_nn::{Activation, VarBuilder};
+use candle_nn::{kv_cache::RotatingKvCache, Activation, VarBuilder};
 use std::sync::Arc;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Config {
-    pub(crate) vocab_size: usize,
-    pub(crate) hidden_size: usize,
-    pub(crate) intermediate_size: usize,
-    pub(crate) num_hidden_layers: usize,
-    pub(crate) num_attention_heads: usize,
-    pub(crate) num_key_value_heads: usize,
-    pub(crate) hidden_act: Activation,
-    pub(crate) max_position_embeddings: usize,
-    pub(crate) rms_norm_eps: f64,
-    pub(crate) rope_theta: f64,
-    pub(crate) sliding_window: usize,
-    pub(crate) use_flash_attn: bool,
+    pub vocab_size: usize,
+    pub hidden_size: usize,
+    pub intermediate_size: usize,
+    pub num_hidden_layers: usize,
+    pub num_attention_heads: usize,
+    pub num_key_value_heads: usize,
+    pub hidden_act: Activation,
+    pub max_position_embeddings: usize,
+    pub rms_norm_eps: f64,
+    pub rope_theta: f64,
+    pub sliding_window: usize,
+    pub use_flash_attn: bool,
 }
 
 impl Config {
@@ -210,7 +213,7 @@ struct Attention {
     head_dim: usize,
     hidden_size: usize,
     rotary_emb: Arc<RotaryEmbedding>,
-    kv_cache: Option<(Tensor, Tensor)>,
+    kv_cache: RotatingKvCache,
     use_flash_attn: bool,
 }
 
@@ -236,7 +239,7 @@ impl Attention {
             head_dim,
             hidden_size: hidden_sz,
             rotary_emb,
-            kv_cache: None,
+            kv_cache: RotatingKvCache::new(2, cfg.sliding_window),
             use_flash_attn: cfg.use_flash_attn,
         })
     }
@@ -279,15 +282,9 @@ impl Attention {
             self.rotary_emb
                 .apply_rotary_emb_qkv(&query_states, &key_states, seqlen_offset)?;
 
-        let (key_states, value_states) = match &self.kv_cache {
-            None => (key_states, value_states),
-            Some((prev_k, prev_v)) => {
-                let key_states = Tensor::cat(&[prev_k, &key_states], 2)?;
-                let value_states = Tensor::cat(&[prev_v, &value_states], 2)?;
-                (key_states, value_states)
-            }
-        };
-        self.kv_cache = Some((key_states.clone(), value_states.clone()));
+        let (key_states, value_states) = self.kv_cache.append(&key_states, &value_states)?;
+        let key_states = key_states.contiguous()?;
+        let value_states = value_states.contiguous()?;
 
         let key_states = self.repeat_kv(key_states)?;
         let value_states = self.repeat_kv(value_states)?;
@@ -317,7 +314,52 @@ impl Attention {
     }
 
     fn clear_kv_cache(&mut self) {
-        self.kv_cache = None
+        self.kv_cache.reset()
+    }
+
+    fn forward_paged(
+        &self,
+        xs: &Tensor,
+        seqs: &[SequenceInput],
+        cache: &PagedKvCache,
+        layer_idx: usize,
+        sliding_window: usize,
+    ) -> Result<Tensor> {
+        let (b_sz, total_len, _) = xs.dims3()?;
+        let query_states =
+            self.q_proj
+                .forward(xs)?
+                .reshape((b_sz, total_len, self.num_heads, self.head_dim))?;
+        let key_states = self.k_proj.forward(xs)?.reshape((
+            b_sz,
+            total_len,
+            self.num_kv_heads,
+            self.head_dim,
+        ))?;
+        let value_states = self.v_proj.forward(xs)?.reshape((
+            b_sz,
+            total_len,
+            self.num_kv_heads,
+            self.head_dim,
+        ))?;
+        let mut offset = 0;
+        let mut attn_outputs = Vec::with_capacity(seqs.len());
+        for seq in seqs.iter() {
+            let q_len = seq.tokens.len();
+            let q = query_states.narrow(1, offset, q_len)?.transpose(1, 2)?;
+            let k = key_states.narrow(1, offset, q_len)?.transpose(1, 2)?;
+            let v = value_states.narrow(1, offset, q_len)?.transpose(1, 2)?;
+            let (q, k) = self.rotary_emb.apply_rotary_emb_qkv(&q, &k, seq.position)?;
+            let attn_output =
+                paged_attention(&q, &k, &v, cache, layer_idx, seq, Some(sliding_window))?;
+            attn_outputs.push(attn_output.transpose(1, 2)?.reshape((
+                b_sz,
+                q_len,
+                self.hidden_size,
+            ))?);
+            offset += q_len;
+        }
+        Tensor::cat(&attn_outputs, 1)?.apply(&self.o_proj)
     }
 }
 
@@ -366,6 +408,25 @@ impl DecoderLayer {
     fn clear_kv_cache(&mut self) {
         self.self_attn.clear_kv_cache()
     }
+
+    fn forward_paged(
+        &self,
+        xs: &Tensor,
+        seqs: &[SequenceInput],
+        cache: &PagedKvCache,
+        layer_idx: usize,
+        sliding_window: usize,
+    ) -> Result<Tensor> {
+        let residual = xs;
+        let xs = self.input_layernorm.forward(xs)?;
+        let xs = self
+            .self_attn
+            .forward_paged(&xs, seqs, cache, layer_idx, sliding_window)?;
+        let xs = (xs + residual)?;
+        let residual = &xs;
+        let xs = xs.apply(&self.post_attention_layernorm)?.apply(&self.mlp)?;
+        residual + xs
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -410,11 +471,14 @@ impl Model {
         tgt_len: usize,
         seqlen_offset: usize,
     ) -> Result<Tensor> {
-        // Sliding window mask?
+        // Sliding window mask, the kv cache only retains the last sliding_window positions.
+        let sliding_window = self.sliding_window;
+        let kv_len = usize::min(seqlen_offset, sliding_window);
         let mask: Vec<_> = (0..tgt_len)
             .flat_map(|i| {
-                (0..tgt_len).map(move |j| {
-                    if i < j || j + self.sliding_window < i {
+                let i = i + kv_len;
+                (0..tgt_len + kv_len).map(move |j| {
+                    if i < j || j + sliding_window <= i {
                         f32::NEG_INFINITY
                     } else {
                         0.
@@ -422,14 +486,8 @@ impl Model {
                 })
             })
             .collect();
-        let mask = Tensor::from_slice(&mask, (tgt_len, tgt_len), &self.device)?;
-        let mask = if seqlen_offset > 0 {
-            let mask0 = Tensor::zeros((tgt_len, seqlen_offset), DType::F32, &self.device)?;
-            Tensor::cat(&[&mask0, &mask], D::Minus1)?
-        } else {
-            mask
-        };
-        mask.expand((b_size, 1, tgt_len, tgt_len + seqlen_offset))?
+        let mask = Tensor::from_slice(&mask, (tgt_len, tgt_len + kv_len), &self.device)?;
+        mask.expand((b_size, 1, tgt_len, tgt_len + kv_len))?
             .to_dtype(self.dtype)
     }
 
@@ -456,3 +514,36 @@ impl Model {
         }
     }
 }
+
+impl PagedModel for Model {
+    fn num_layers(&self) -> usize {
+        self.layers.len()
+    }
+
+    fn num_kv_heads(&self) -> usize {
+        self.layers[0].self_attn.num_kv_heads
+    }
+
+    fn head_dim(&self) -> usize {
+        self.layers[0].self_attn.head_dim
+    }
+
+    fn dtype(&self) -> DType {
+        self.dtype
+    }
+
+    fn device(&self) -> &Device {
+        &self.device
+    }
+
+    fn forward_paged(&self, seqs: &[SequenceInput], cache: &PagedKvCache) -> Result<Tensor> {
+        let input_ids = batch_tokens(seqs, &self.device)?;
+        let mut xs = self.embed_tokens.forward(&input_ids)?;
+        for (layer_idx, layer) in self.layers.iter().enumerate() {
+            xs = layer.forward_paged(&xs, seqs, cache, layer_idx, self.sliding_window)?
+        }
+        last_positions(&xs, seqs)?
+            .apply(&self.norm)?
+            .apply(&self.lm_head)
+    }
+}
@@ -3,7 +3,7 @@ use crate::models::with_tracing::{linear_no_bias, Linear};
 /// https://github.com/huggingface/transformers/blob/main/src/transformers/models/mixtral/modeling_mixtral.py
 /// https://mistral.ai/news/mixtral-of-experts/
 use candle::{DType, Device, Module, Result, Tensor, D};
-use candle_nn::{Activation, VarBuilder};
+use candle_nn::{kv_cache::RotatingKvCache, Activation, VarBuilder};
 use serde::Deserialize;
 use std::sync::Arc;
 
@@ -148,7 +148,7 @@ struct Attention {
     head_dim: usize,
     hidden_size: usize,
     rotary_emb: Arc<RotaryEmbedding>,
-    kv_cache: Option<(Tensor, Tensor)>,
+    kv_cache: RotatingKvCache,
     use_flash_attn: bool,
 }
 
@@ -174,7 +174,7 @@ impl Attention {
             head_dim,
             hidden_size: hidden_sz,
             rotary_emb,
-            kv_cache: None,
+            kv_cache: RotatingKvCache::new(2, cfg.sliding_window),
             use_flash_attn: cfg.use_flash_attn,
         })
     }
@@ -217,15 +217,9 @@ impl Attention {
             self.rotary_emb
                 .apply_rotary_emb_qkv(&query_states, &key_states, seqlen_offset)?;
 
-        let (key_states, value_states) = match &self.kv_cache {
-            None => (key_states, value_states),
-            Some((prev_k, prev_v)) => {
-                let key_states = Tensor::cat(&[prev_k, &key_states], 2)?;
-                let value_states = Tensor::cat(&[prev_v, &value_states], 2)?;
-                (key_states, value_states)
-            }
-        };
-        self.kv_cache = Some((key_states.clone(), value_states.clone()));
+        let (key_states, value_states) = self.kv_cache.append(&key_states, &value_states)?;
+        let key_states = key_states.contiguous()?;
+        let value_states = value_states.contiguous()?;
 
         let key_states = self.repeat_kv(key_states)?;
         let value_states = self.repeat_kv(value_states)?;
@@ -457,11 +451,14 @@ impl Model {
         tgt_len: usize,
         seqlen_offset: usize,
     ) -> Result<Tensor> {
-        // Sliding window mask?
+        // Sliding window mask, the kv cache only retains the last sliding_window positions.
+        let sliding_window = self.sliding_window;
+        let kv_len = usize::min(seqlen_offset, sliding_window);
         let mask: Vec<_> = (0..tgt_len)
             .flat_map(|i| {
-                (0..tgt_len).map(move |j| {
-                    if i < j || j + self.sliding_window < i {
+                let i = i + kv_len;
+                (0..tgt_len + kv_len).map(move |j| {
+                    if i < j || j + sliding_window <= i {
                         f32::NEG_INFINITY
                     } else {
                         0.
@@ -469,14 +466,8 @@ impl Model {
                 })
             })
             .collect();
-        let mask = Tensor::from_slice(&mask, (tgt_len, tgt_len), &self.device)?;
-        let mask = if seqlen_offset > 0 {
-            let mask0 = Tensor::zeros((tgt_len, seqlen_offset), DType::F32, &self.device)?;
-            Tensor::cat(&[&mask0, &mask], D::Minus1)?
-        } else {
-            mask
-        };
-        mask.expand((b_size, 1, tgt_len, tgt_len + seqlen_offset))?
+        let mask = Tensor::from_slice(&mask, (tgt_len, tgt_len + kv_len), &self.device)?;
+        mask.expand((b_size, 1, tgt_len, tgt_len + kv_len))?
             .to_dtype(self.dtype)
     }
 
@@ -3,8 +3,11 @@ use std::collections::HashMap;
 use candle::quantized::QTensor;
 use candle::quantized::{ggml_file, gguf_file};
 use candle::{DType, Device, IndexOp, Result, Tensor, D};
+use candle_nn::kv_cache::QuantizedKvCache;
 use candle_nn::{Embedding, Module};
 
+pub use candle_nn::kv_cache::QuantizedKvDType;
+
 pub const MAX_SEQ_LEN: usize = 4096;
 
 #[derive(Debug, Clone)]
@@ -143,6 +146,21 @@ impl Module for MlpOrMoe {
     }
 }
 
+#[derive(Debug, Clone)]
+enum KvCache {
+    Float(Option<(Tensor, Tensor)>),
+    Quantized(QuantizedKvCache),
+}
+
+impl KvCache {
+    fn new(dtype: Option<QuantizedKvDType>) -> Self {
+        match dtype {
+            None => Self::Float(None),
+            Some(dtype) => Self::Quantized(QuantizedKvCache::new(dtype)),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 struct LayerWeights {
     attention_wq: QMatMul,
@@ -157,7 +175,7 @@ struct LayerWeights {
     head_dim: usize,
     cos: Tensor,
     sin: Tensor,
-    kv_cache: Option<(Tensor, Tensor)>,
+    kv_cache: KvCache,
     span_attn: tracing::Span,
     span_rot: tracing::Span,
     span_mlp: tracing::Span,
@@ -220,7 +238,21 @@ impl LayerWeights {
         let q = self.apply_rotary_emb(&q, index_pos)?;
         let k = self.apply_rotary_emb(&k, index_pos)?;
 
-        let (k, v) = match &self.kv_cache {
+        let kv_cache = match &mut self.kv_cache {
+            KvCache::Float(kv_cache) => kv_cache,
+            KvCache::Quantized(kv_cache) => {
+                // The attention is computed directly against the quantized keys and values.
+                if index_pos == 0 {
+                    kv_cache.reset()
+                }
+                kv_cache.append(&k, &v)?;
+                let scale = 1. / (self.head_dim as f64).sqrt();
+                let y = kv_cache.attention(&q, scale, true)?;
+                let y = y.transpose(1, 2)?.reshape(&[b_sz, seq_len, n_embd])?;
+                return self.attention_wo.forward(&y);
+            }
+        };
+        let (k, v) = match &kv_cache {
             None => (k, v),
             Some((k_cache, v_cache)) => {
                 if index_pos == 0 {
@@ -232,7 +264,7 @@ impl LayerWeights {
                 }
             }
         };
-        self.kv_cache = Some((k.clone(), v.clone()));
+        *kv_cache = Some((k.clone(), v.clone()));
 
         // Support for MQA, useful for 70B models.
         let k = self.repeat_kv(k)?;
@@ -334,7 +366,7 @@ impl ModelWeights {
                 head_dim: (ct.hparams.n_embd / ct.hparams.n_head) as usize,
                 cos: cos.clone(),
                 sin: sin.clone(),
-                kv_cache: None,
+                kv_cache: KvCache::Float(None),
                 span_attn,
                 span_rot,
                 span_mlp,
@@ -453,7 +485,7 @@ impl ModelWeights {
                 head_dim: embedding_length / head_count,
                 cos: cos.clone(),
                 sin: sin.clone(),
-                kv_cache: None,
+                kv_cache: KvCache::Float(None),
                 span_attn,
                 span_rot,
                 span_mlp,
@@ -472,6 +504,16 @@ impl ModelWeights {
         })
     }
 
+    /// Sets the format used to store the keys and values of the attention layers, `None` uses
+    /// float tensors. With a quantized format the attention is computed directly against the
+    /// quantized entries, this is only supported on the cpu. Changing the format clears the
+    /// cache.
+    pub fn set_kv_cache_dtype(&mut self, dtype: Option<QuantizedKvDType>) {
+        for layer in self.layers.iter_mut() {
+            layer.kv_cache = KvCache::new(dtype)
+        }
+    }
+
     fn mask(&mut self, t: usize) -> Result<Tensor> {
         if let Some(mask) = self.masks.get(&t) {
             Ok(mask.clone())
@@ -494,6 +494,15 @@ impl T5Attention {
     fn clear_kv_cache(&mut self) {
         self.kv_cache = None
     }
+
+    fn reorder_kv_cache(&mut self, indices: &Tensor) -> Result<()> {
+        if let Some((k, v)) = &self.kv_cache {
+            let k = k.contiguous()?.index_select(indices, 0)?;
+            let v = v.contiguous()?.index_select(indices, 0)?;
+            self.kv_cache = Some((k, v))
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -533,6 +542,10 @@ impl T5LayerSelfAttention {
     fn clear_kv_cache(&mut self) {
         self.self_attention.clear_kv_cache()
     }
+
+    fn reorder_kv_cache(&mut self, indices: &Tensor) -> Result<()> {
+        self.self_attention.reorder_kv_cache(indices)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -646,6 +659,10 @@ impl T5Block {
         self.self_attn.clear_kv_cache();
         self.cross_attn.iter_mut().for_each(|c| c.clear_kv_cache());
     }
+
+    fn reorder_kv_cache(&mut self, indices: &Tensor) -> Result<()> {
+        self.self_attn.reorder_kv_cache(indices)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -696,6 +713,12 @@ impl T5Stack {
     fn clear_kv_cache(&mut self) {
         self.block.iter_mut().for_each(|b| b.clear_kv_cache())
     }
+
+    fn reorder_kv_cache(&mut self, indices: &Tensor) -> Result<()> {
+        self.block
+            .iter_mut()
+            .try_for_each(|b| b.reorder_kv_cache(indices))
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -744,6 +767,7 @@ pub struct T5ForConditionalGeneration {
     tie_word_embeddings: bool,
     lm_head: Option<Linear>,
     shared: Arc<Embedding>,
+    use_cache: bool,
     device: Device,
     span_decode: tracing::Span,
     span_decode_head: tracing::Span,
@@ -791,6 +815,7 @@ impl T5ForConditionalGeneration {
             tie_word_embeddings,
             lm_head,
             shared,
+            use_cache: cfg.use_cache,
             device: vb.device().clone(),
             span_decode: tracing::span!(tracing::Level::TRACE, "decode"),
             span_decode_head: tracing::span!(tracing::Level::TRACE, "decode-head"),
@@ -845,4 +870,33 @@ impl T5ForConditionalGeneration {
         self.encoder.clear_kv_cache();
         self.decoder.clear_kv_cache();
     }
+
+    /// Reorders the decoder kv cache along the batch dimension, see
+    /// [`crate::generation::beam_search::Decoder::reorder_kv_cache`].
+    pub fn reorder_kv_cache(&mut self, indices: &Tensor) -> Result<()> {
+        self.decoder.reorder_kv_cache(indices)
+    }
+}
+
+impl crate::generation::beam_search::Decoder for T5ForConditionalGeneration {
+    fn decode_step(
+        &mut self,
+        tokens: &Tensor,
+        encoder_output: &Tensor,
+        _past_len: usize,
+    ) -> Result<Tensor> {
+        self.decode(tokens, encoder_output)
+    }
+
+    fn reorder_kv_cache(&mut self, indices: &Tensor) -> Result<()> {
+        T5ForConditionalGeneration::reorder_kv_cache(self, indices)
+    }
+
+    fn reset_kv_cache(&mut self) {
+        self.clear_kv_cache()
+    }
+
+    fn use_kv_cache(&self) -> bool {
+        self.use_cache
+    }
 }
@@ -180,6 +180,15 @@ impl TrOCRAttention {
         self.kv_cache = None
     }
 
+    fn reorder_kv_cache(&mut self, indices: &Tensor) -> Result<()> {
+        if let Some((k, v)) = &self.kv_cache {
+            let k = k.contiguous()?.index_select(indices, 0)?;
+            let v = v.contiguous()?.index_select(indices, 0)?;
+            self.kv_cache = Some((k, v))
+        }
+        Ok(())
+    }
+
     fn _shape(&self, tensor: &Tensor, bsz: usize) -> Result<Tensor> {
         tensor
             .reshape((bsz, (), self.num_heads, self.head_dim))?
@@ -283,6 +292,10 @@ impl TrOCRDecoderLayer {
         self.self_attn.reset_kv_cache();
     }
 
+    fn reorder_kv_cache(&mut self, indices: &Tensor) -> Result<()> {
+        self.self_attn.reorder_kv_cache(indices)
+    }
+
     fn forward(
         &mut self,
         xs: &Tensor,
@@ -296,12 +309,11 @@ impl TrOCRDecoderLayer {
 
         if let Some(encoder_hidden_states) = &encoder_hidden_states {
             let residual = xs.clone();
-            let encoder_attention_mask = attention_mask.clone(); // TODO
-            xs = self.encoder_attn.forward(
-                &xs,
-                Some(encoder_hidden_states),
-                Some(&encoder_attention_mask),
-            )?;
+            // The cross-attention can attend to all the encoder positions, the causal mask only
+            // applies to the self-attention.
+            xs = self
+                .encoder_attn
+                .forward(&xs, Some(encoder_hidden_states), None)?;
             xs = (xs + residual)?;
             xs = self.encoder_attn_layer_norm.forward(&xs)?
         }
@@ -359,6 +371,12 @@ impl TrOCRDecoder {
         self.layers.iter_mut().for_each(|l| l.reset_kv_cache())
     }
 
+    fn reorder_kv_cache(&mut self, indices: &Tensor) -> Result<()> {
+        self.layers
+            .iter_mut()
+            .try_for_each(|l| l.reorder_kv_cache(indices))
+    }
+
     pub fn forward(
         &mut self,
         xs: &Tensor,
@@ -456,6 +474,10 @@ impl TrOCRForCausalLM {
     fn reset_kv_cache(&mut self) {
         self.decoder.reset_kv_cache();
     }
+
+    fn reorder_kv_cache(&mut self, indices: &Tensor) -> Result<()> {
+        self.decoder.reorder_kv_cache(indices)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -498,4 +520,31 @@ impl TrOCRModel {
     pub fn reset_kv_cache(&mut self) {
         self.decoder.reset_kv_cache();
     }
+
+    /// Reorders the decoder kv cache along the batch dimension, see
+    /// [`crate::generation::beam_search::Decoder::reorder_kv_cache`].
+    pub fn reorder_kv_cache(&mut self, indices: &Tensor) -> Result<()> {
+        self.decoder.reorder_kv_cache(indices)
+    }
+}
+
+impl crate::generation::beam_search::Decoder for TrOCRModel {
+    fn decode_step(
+        &mut self,
+        tokens: &Tensor,
+        encoder_output: &Tensor,
+        past_len: usize,
+    ) -> Result<Tensor> {
+        let logits = self.decode(tokens, encoder_output, past_len)?;
+        let seq_len = logits.dim(1)?;
+        logits.narrow(1, seq_len - 1, 1)?.squeeze(1)
+    }
+
+    fn reorder_kv_cache(&mut self, indices: &Tensor) -> Result<()> {
+        TrOCRModel::reorder_kv_cache(self, indices)
+    }
+
+    fn reset_kv_cache(&mut self) {
+        TrOCRModel::reset_kv_cache(self)
+    }
 }
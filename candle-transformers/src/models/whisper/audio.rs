@@ -2,6 +2,7 @@
 // https://github.com/ggerganov/whisper.cpp
 
 use candle::utils::get_num_threads;
+use candle::{Device, Result, Tensor};
 use std::sync::Arc;
 use std::thread;
 
@@ -87,13 +88,12 @@ fn dft<T: Float>(inp: &[T]) -> Vec<T> {
 
 #[allow(clippy::too_many_arguments)]
 // https://github.com/ggerganov/whisper.cpp/blob/4774d2feb01a772a15de81ffc34b34a1f294f020/whisper.cpp#L2414
+// The frames are already windowed, `frames` holds `n_len` frames of `fft_size` samples.
 fn log_mel_spectrogram_w<T: Float>(
     ith: usize,
-    hann: &[T],
-    samples: &[T],
+    frames: &[T],
     filters: &[T],
     fft_size: usize,
-    fft_step: usize,
     speed_up: bool,
     n_len: usize,
     n_mel: usize,
@@ -107,26 +107,13 @@ fn log_mel_spectrogram_w<T: Float>(
 
     let zero = T::zero();
     let half = T::from(0.5).unwrap();
-    let mut fft_in = vec![zero; fft_size];
     let mut mel = vec![zero; n_len * n_mel];
-    let n_samples = samples.len();
-    let end = std::cmp::min(n_samples / fft_step + 1, n_len);
-
-    for i in (ith..end).step_by(n_threads) {
-        let offset = i * fft_step;
 
-        // apply Hanning window
-        for j in 0..std::cmp::min(fft_size, n_samples - offset) {
-            fft_in[j] = hann[j] * samples[offset + j];
-        }
-
-        // fill the rest with zeros
-        if n_samples - offset < fft_size {
-            fft_in[n_samples - offset..].fill(zero);
-        }
+    for i in (ith..n_len).step_by(n_threads) {
+        let fft_in = &frames[i * fft_size..(i + 1) * fft_size];
 
         // FFT
-        let mut fft_out: Vec<T> = fft(&fft_in);
+        let mut fft_out: Vec<T> = fft(fft_in);
 
         // Calculate modulus^2 of complex numbers
         for j in 0..fft_size {
@@ -167,14 +154,14 @@ fn log_mel_spectrogram_w<T: Float>(
     mel
 }
 
-fn log_mel_spectrogram_<T: Float>(
+fn log_mel_spectrogram_<T: Float + candle::WithDType>(
     samples: &[T],
     filters: &[T],
     fft_size: usize,
     fft_step: usize,
     n_mel: usize,
     speed_up: bool,
-) -> Vec<T> {
+) -> Result<Vec<T>> {
     let zero = T::zero();
     let two_pi = T::PI() + T::PI();
     let half = T::from(0.5).unwrap();
@@ -202,11 +189,23 @@ fn log_mel_spectrogram_<T: Float>(
         samples_padded
     };
 
+    // The frames are views on the samples, the extra zeros pad the last frames which would
+    // otherwise go past the end of the samples. Only the windowed frames get materialized.
+    let n_samples = samples.len();
+    let samples =
+        Tensor::from_vec(samples, n_samples, &Device::Cpu)?.pad_with_zeros(0, 0, fft_size)?;
+    let hann = Tensor::from_vec(hann, fft_size, &Device::Cpu)?;
+    let frames = samples
+        .unfold(0, fft_size, fft_step)?
+        .narrow(0, 0, n_len)?
+        .broadcast_mul(&hann)?
+        .flatten_all()?
+        .to_vec1::<T>()?;
+
     // ensure that the number of threads is even and less than 12
     let n_threads = std::cmp::min(get_num_threads() - get_num_threads() % 2, 12);
 
-    let hann = Arc::new(hann);
-    let samples = Arc::new(samples);
+    let frames = Arc::new(frames);
     let filters = Arc::new(filters);
 
     // use scope to allow for non static references to be passed to the threads
@@ -215,14 +214,12 @@ fn log_mel_spectrogram_<T: Float>(
         (0..n_threads)
             // create threads and return their handles
             .map(|thread_id| {
-                let hann = Arc::clone(&hann);
-                let samples = Arc::clone(&samples);
+                let frames = Arc::clone(&frames);
                 let filters = Arc::clone(&filters);
                 // spawn new thread and start work
                 s.spawn(move || {
                     log_mel_spectrogram_w(
-                        thread_id, &hann, &samples, &filters, fft_size, fft_step, speed_up, n_len,
-                        n_mel, n_threads,
+                        thread_id, &frames, &filters, fft_size, speed_up, n_len, n_mel, n_threads,
                     )
                 })
             })
@@ -258,13 +255,17 @@ fn log_mel_spectrogram_<T: Float>(
         .unwrap_or(zero)
         - T::from(8).unwrap();
     for m in mel.iter_mut() {
-        let v = T::max(*m, mmax);
+        let v = num_traits::Float::max(*m, mmax);
         *m = v / four + one
     }
-    mel
+    Ok(mel)
 }
 
-pub fn pcm_to_mel<T: Float>(cfg: &super::Config, samples: &[T], filters: &[T]) -> Vec<T> {
+pub fn pcm_to_mel<T: Float + candle::WithDType>(
+    cfg: &super::Config,
+    samples: &[T],
+    filters: &[T],
+) -> Result<Vec<T>> {
     log_mel_spectrogram_(
         samples,
         filters,
@@ -321,7 +322,7 @@ mod tests {
     fn test_log_mel_spectrogram() {
         let samples = vec![0.0; 1000];
         let filters = vec![0.0; 1000];
-        let output = log_mel_spectrogram_(&samples, &filters, 100, 10, 10, false);
+        let output = log_mel_spectrogram_(&samples, &filters, 100, 10, 10, false).unwrap();
         assert_eq!(output.len(), 30_000);
     }
 
@@ -329,7 +330,7 @@ mod tests {
     fn test_tiny_log_mel_spectrogram() {
         let samples = vec![0.0; 100];
         let filters = vec![0.0; 100];
-        let output = log_mel_spectrogram_(&samples, &filters, 20, 2, 2, false);
+        let output = log_mel_spectrogram_(&samples, &filters, 20, 2, 2, false).unwrap();
         assert_eq!(output.len(), 6_000);
     }
 }
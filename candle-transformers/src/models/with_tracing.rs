@@ -33,28 +33,42 @@ impl Module for Embedding {
     }
 }
 
+/// A linear layer whose weights are either a float tensor or a quantized tensor, depending on
+/// the `VarBuilder` backend. GPTQ and AWQ weights are loaded via [`crate::quantized_int4`].
 #[derive(Debug, Clone)]
 pub struct Linear {
-    inner: candle_nn::Linear,
+    inner: candle_nn::QLinear,
     span: tracing::Span,
 }
 
 impl Linear {
     pub fn from_weights(weights: Tensor, bias: Option<Tensor>) -> Self {
-        let inner = candle_nn::Linear::new(weights, bias);
+        let weights = candle::quantized::QMatMul::Tensor(weights);
+        let inner = candle_nn::QLinear::new(weights, bias);
         let span = tracing::span!(tracing::Level::TRACE, "linear");
         Self { inner, span }
     }
 }
 
 pub fn linear(d1: usize, d2: usize, vb: VarBuilder) -> Result<Linear> {
-    let inner = candle_nn::linear(d1, d2, vb)?;
+    let inner = if crate::quantized_int4::is_int4(&vb) {
+        let bias = vb.get(d2, "bias")?;
+        let weight = crate::quantized_int4::qmatmul(d1, d2, vb)?;
+        candle_nn::QLinear::new(weight, Some(bias))
+    } else {
+        candle_nn::qlinear(d1, d2, vb)?
+    };
     let span = tracing::span!(tracing::Level::TRACE, "linear");
     Ok(Linear { inner, span })
 }
 
 pub fn linear_no_bias(d1: usize, d2: usize, vb: VarBuilder) -> Result<Linear> {
-    let inner = candle_nn::linear_no_bias(d1, d2, vb)?;
+    let inner = if crate::quantized_int4::is_int4(&vb) {
+        let weight = crate::quantized_int4::qmatmul(d1, d2, vb)?;
+        candle_nn::QLinear::new(weight, None)
+    } else {
+        candle_nn::qlinear_no_bias(d1, d2, vb)?
+    };
     let span = tracing::span!(tracing::Level::TRACE, "linear");
     Ok(Linear { inner, span })
 }
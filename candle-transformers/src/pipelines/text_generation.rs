@@ -1 +1,321 @@
+//! Text generation engine with a paged kv cache and continuous batching.
+//!
+//! The keys and values for all the sequences being generated are stored in a single pool of
+//! fixed size blocks. Each sequence owns a block table mapping its positions to blocks, so that
+//! memory is only reserved for the tokens that have actually been processed. At each step the
+//! scheduler picks the running sequences that need a new token as well as some waiting
+//! sequences to prefill, and runs all of them through the model in a single forward pass.
+//!
+//! The cache and the model interface are defined in [`crate::generation::paged`] and re-exported
+//! here.
+pub use crate::generation::paged::{
+    batch_tokens, last_positions, paged_attention, CacheConfig, PagedKvCache, PagedModel,
+    SequenceInput,
+};
 
+use crate::generation::LogitsProcessor;
+use candle::{DType, Result};
+use std::collections::VecDeque;
+
+/// Limits on the work done in a single step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchedulerConfig {
+    /// The maximum number of sequences processed in a single step.
+    pub max_num_seqs: usize,
+    /// The maximum number of tokens processed in a single step. A prompt that is longer than
+    /// this is still scheduled when no other sequence is running.
+    pub max_num_batched_tokens: usize,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            max_num_seqs: 64,
+            max_num_batched_tokens: 2048,
+        }
+    }
+}
+
+/// How the tokens are generated for a request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SamplingParams {
+    pub seed: u64,
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub max_new_tokens: usize,
+    pub eos_token_id: Option<u32>,
+}
+
+impl Default for SamplingParams {
+    fn default() -> Self {
+        Self {
+            seed: 299792458,
+            temperature: None,
+            top_p: None,
+            max_new_tokens: 100,
+            eos_token_id: None,
+        }
+    }
+}
+
+/// A token produced by [`TextGeneration::step`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepOutput {
+    pub request_id: usize,
+    pub token: u32,
+    pub finished: bool,
+}
+
+/// The result of a request once it has finished.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GenerationOutput {
+    pub request_id: usize,
+    pub prompt: Vec<u32>,
+    pub tokens: Vec<u32>,
+}
+
+struct Sequence {
+    request_id: usize,
+    tokens: Vec<u32>,
+    prompt_len: usize,
+    // The number of tokens for which the keys and values are in the cache.
+    num_computed: usize,
+    block_table: Vec<usize>,
+    logits_processor: LogitsProcessor,
+    max_new_tokens: usize,
+    eos_token_id: Option<u32>,
+}
+
+impl Sequence {
+    fn num_generated(&self) -> usize {
+        self.tokens.len() - self.prompt_len
+    }
+
+    fn into_output(self) -> GenerationOutput {
+        let mut prompt = self.tokens;
+        let tokens = prompt.split_off(self.prompt_len);
+        GenerationOutput {
+            request_id: self.request_id,
+            prompt,
+            tokens,
+        }
+    }
+}
+
+/// A generation engine running multiple requests concurrently with continuous batching:
+/// new requests join the running batch as soon as there is room for them in the cache, and
+/// finished requests leave it at the end of each step.
+///
+/// When the cache runs out of blocks, the most recently scheduled sequences are preempted and
+/// their cache entries get recomputed once enough blocks are available again.
+pub struct TextGeneration<M: PagedModel> {
+    model: M,
+    cache: PagedKvCache,
+    scheduler: SchedulerConfig,
+    waiting: VecDeque<Sequence>,
+    running: Vec<Sequence>,
+    finished: Vec<GenerationOutput>,
+    next_request_id: usize,
+}
+
+impl<M: PagedModel> TextGeneration<M> {
+    pub fn new(model: M, cache: &CacheConfig, scheduler: SchedulerConfig) -> Result<Self> {
+        if scheduler.max_num_seqs == 0 {
+            candle::bail!("invalid scheduler config {scheduler:?}")
+        }
+        let cache = PagedKvCache::new(
+            cache,
+            model.num_layers(),
+            model.num_kv_heads(),
+            model.head_dim(),
+            model.dtype(),
+            model.device(),
+        )?;
+        Ok(Self {
+            model,
+            cache,
+            scheduler,
+            waiting: VecDeque::new(),
+            running: vec![],
+            finished: vec![],
+            next_request_id: 0,
+        })
+    }
+
+    pub fn model(&self) -> &M {
+        &self.model
+    }
+
+    pub fn cache(&self) -> &PagedKvCache {
+        &self.cache
+    }
+
+    /// Queues a new request and returns its id.
+    pub fn add_request(&mut self, prompt: Vec<u32>, params: &SamplingParams) -> Result<usize> {
+        if prompt.is_empty() {
+            candle::bail!("empty prompt")
+        }
+        let max_len = prompt.len() + params.max_new_tokens;
+        if self.cache.blocks_for(max_len) > self.cache.num_blocks() {
+            candle::bail!(
+                "request needs up to {max_len} positions, more than the cache can hold ({})",
+                self.cache.num_blocks() * self.cache.block_size()
+            )
+        }
+        let request_id = self.next_request_id;
+        self.next_request_id += 1;
+        let seq = Sequence {
+            request_id,
+            prompt_len: prompt.len(),
+            tokens: prompt,
+            num_computed: 0,
+            block_table: vec![],
+            logits_processor: LogitsProcessor::new(params.seed, params.temperature, params.top_p),
+            max_new_tokens: params.max_new_tokens,
+            eos_token_id: params.eos_token_id,
+        };
+        if seq.max_new_tokens == 0 {
+            self.finished.push(seq.into_output())
+        } else {
+            self.waiting.push_back(seq);
+        }
+        Ok(request_id)
+    }
+
+    pub fn num_waiting(&self) -> usize {
+        self.waiting.len()
+    }
+
+    pub fn num_running(&self) -> usize {
+        self.running.len()
+    }
+
+    pub fn has_unfinished_requests(&self) -> bool {
+        !self.waiting.is_empty() || !self.running.is_empty()
+    }
+
+    /// Returns the requests that have finished since the last call.
+    pub fn take_finished(&mut self) -> Vec<GenerationOutput> {
+        std::mem::take(&mut self.finished)
+    }
+
+    fn blocks_needed(&self, seq: &Sequence) -> usize {
+        self.cache
+            .blocks_for(seq.tokens.len())
+            .saturating_sub(seq.block_table.len())
+    }
+
+    fn preempt(&mut self, seq: Sequence) {
+        let mut seq = seq;
+        self.cache.free(&seq.block_table);
+        seq.block_table.clear();
+        seq.num_computed = 0;
+        self.waiting.push_front(seq)
+    }
+
+    fn allocate(&mut self, seq: &mut Sequence) {
+        for _ in 0..self.blocks_needed(seq) {
+            // The callers have checked that enough blocks are available.
+            let block = self.cache.allocate().expect("no free blocks");
+            seq.block_table.push(block)
+        }
+    }
+
+    fn schedule(&mut self) {
+        // Make room for the running sequences first, preempting the most recent ones when
+        // the cache is full.
+        let mut running = std::mem::take(&mut self.running);
+        let mut preempted = false;
+        let mut scheduled = Vec::with_capacity(running.len());
+        while !running.is_empty() {
+            let needed: usize = running.iter().map(|s| self.blocks_needed(s)).sum();
+            if needed <= self.cache.num_free_blocks() {
+                break;
+            }
+            let seq = running.pop().unwrap();
+            self.preempt(seq);
+            preempted = true;
+        }
+        let mut num_tokens = 0;
+        for mut seq in running.into_iter() {
+            self.allocate(&mut seq);
+            num_tokens += seq.tokens.len() - seq.num_computed;
+            scheduled.push(seq)
+        }
+        // Then admit waiting sequences in arrival order.
+        while !preempted && scheduled.len() < self.scheduler.max_num_seqs {
+            let seq = match self.waiting.front() {
+                None => break,
+                Some(seq) => seq,
+            };
+            let seq_tokens = seq.tokens.len() - seq.num_computed;
+            if !scheduled.is_empty()
+                && num_tokens + seq_tokens > self.scheduler.max_num_batched_tokens
+            {
+                break;
+            }
+            if self.blocks_needed(seq) > self.cache.num_free_blocks() {
+                break;
+            }
+            let mut seq = self.waiting.pop_front().unwrap();
+            self.allocate(&mut seq);
+            num_tokens += seq_tokens;
+            scheduled.push(seq)
+        }
+        self.running = scheduled
+    }
+
+    /// Runs a single scheduling step and returns the tokens generated during this step.
+    pub fn step(&mut self) -> Result<Vec<StepOutput>> {
+        self.schedule();
+        if self.running.is_empty() {
+            return Ok(vec![]);
+        }
+        let logits = {
+            let inputs: Vec<_> = self
+                .running
+                .iter()
+                .map(|seq| SequenceInput {
+                    tokens: &seq.tokens[seq.num_computed..],
+                    position: seq.num_computed,
+                    block_table: &seq.block_table,
+                })
+                .collect();
+            self.model.forward_paged(&inputs, &self.cache)?
+        };
+        let logits = logits.to_dtype(DType::F32)?;
+        let mut outputs = Vec::with_capacity(self.running.len());
+        let mut still_running = Vec::with_capacity(self.running.len());
+        for (idx, mut seq) in std::mem::take(&mut self.running).into_iter().enumerate() {
+            let token = seq.logits_processor.sample(&logits.get(idx)?)?;
+            seq.num_computed = seq.tokens.len();
+            seq.tokens.push(token);
+            let finished =
+                seq.num_generated() >= seq.max_new_tokens || Some(token) == seq.eos_token_id;
+            outputs.push(StepOutput {
+                request_id: seq.request_id,
+                token,
+                finished,
+            });
+            if finished {
+                self.cache.free(&seq.block_table);
+                self.finished.push(seq.into_output())
+            } else {
+                still_running.push(seq)
+            }
+        }
+        self.running = still_running;
+        Ok(outputs)
+    }
+
+    /// Runs steps until all the queued requests have finished, the outputs are sorted by
+    /// request id.
+    pub fn run(&mut self) -> Result<Vec<GenerationOutput>> {
+        while self.has_unfinished_requests() {
+            self.step()?;
+        }
+        let mut outputs = self.take_finished();
+        outputs.sort_by_key(|o| o.request_id);
+        Ok(outputs)
+    }
+}
@@ -0,0 +1,41 @@
+//! Loading of the 4-bit GPTQ and AWQ safetensors checkpoints.
+//!
+//! In these checkpoints, the `weight` of the quantized linear layers is replaced by the packed
+//! `qweight`, `qzeros` and `scales` tensors, GPTQ also adds a `g_idx` tensor when the input
+//! features are quantized out of order. The weights are unpacked to a
+//! [`candle::quantized::int4::Int4Weight`] and used through a [`QMatMul`].
+use candle::quantized::{int4::Int4Weight, QMatMul};
+use candle::{DType, Result};
+use candle_nn::VarBuilder;
+
+/// Returns true if the linear layer at the current path of `vb` uses GPTQ or AWQ weights.
+pub fn is_int4(vb: &VarBuilder) -> bool {
+    vb.contains_tensor("qweight")
+}
+
+/// Loads the int4 weights of a linear layer. The format is detected from the shape of `qweight`,
+/// GPTQ packs the input features whereas AWQ packs the output features.
+pub fn qmatmul(in_dim: usize, out_dim: usize, vb: VarBuilder) -> Result<QMatMul> {
+    let qweight = vb.get_unchecked_dtype("qweight", DType::I64)?;
+    let qzeros = vb.get_unchecked_dtype("qzeros", DType::I64)?;
+    let scales = vb.get_unchecked_dtype("scales", DType::F32)?;
+    let weight = match qweight.dims2()? {
+        (d1, d2) if d1 * 8 == in_dim && d2 == out_dim => {
+            let g_idx = if vb.contains_tensor("g_idx") {
+                Some(vb.get_unchecked_dtype("g_idx", DType::I64)?)
+            } else {
+                None
+            };
+            Int4Weight::from_gptq(&qweight, &qzeros, &scales, g_idx.as_ref())?
+        }
+        (d1, d2) if d1 == in_dim && d2 * 8 == out_dim => {
+            Int4Weight::from_awq(&qweight, &qzeros, &scales)?
+        }
+        _ => candle::bail!(
+            "unexpected qweight shape {:?} for {}, in {in_dim}, out {out_dim}",
+            qweight.shape(),
+            vb.prefix()
+        ),
+    };
+    QMatMul::from_int4(weight)
+}
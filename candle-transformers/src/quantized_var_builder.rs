@@ -1,97 +1,73 @@
 use candle::quantized::QTensor;
-use candle::{Device, Result, Shape};
+use candle::{DType, Device, Result, Shape};
 use std::sync::Arc;
 
-// VarBuilder specialized for QTensors
+// VarBuilder specialized for QTensors, this wraps a `candle_nn::VarBuilder` holding the quantized
+// tensors so that the models using either of them share the same loading code.
+#[derive(Clone)]
 pub struct VarBuilder {
-    data: Arc<std::collections::HashMap<String, Arc<QTensor>>>,
-    path: Vec<String>,
-    device: Device,
+    vb: candle_nn::VarBuilder<'static>,
 }
 
 impl VarBuilder {
     pub fn from_gguf<P: AsRef<std::path::Path>>(p: P, device: &Device) -> Result<Self> {
-        let mut file = std::fs::File::open(p)?;
-        let content = candle::quantized::gguf_file::Content::read(&mut file)?;
-        let mut data = std::collections::HashMap::new();
-        for tensor_name in content.tensor_infos.keys() {
-            let tensor = content.tensor(&mut file, tensor_name, device)?;
-            data.insert(tensor_name.to_string(), Arc::new(tensor));
-        }
-        Ok(Self {
-            data: Arc::new(data),
-            path: Vec::new(),
-            device: device.clone(),
-        })
+        let vb = candle_nn::VarBuilder::from_gguf(p, DType::F32, device)?;
+        Ok(Self { vb })
+    }
+
+    /// Memory maps a GGUF file, on the cpu the tensors are not copied but borrow their data from
+    /// the mapping.
+    ///
+    /// # Safety
+    ///
+    /// The unsafe is inherited from [`candle::quantized::gguf_file::MmapedContent::new`].
+    pub unsafe fn from_gguf_mmaped<P: AsRef<std::path::Path>>(
+        p: P,
+        device: &Device,
+    ) -> Result<Self> {
+        let vb = candle_nn::VarBuilder::from_mmaped_gguf(p, DType::F32, device)?;
+        Ok(Self { vb })
     }
 
     pub fn from_gguf_buffer(buffer: &[u8], device: &Device) -> Result<Self> {
-        let mut cursor = std::io::Cursor::new(buffer);
-        let content = candle::quantized::gguf_file::Content::read(&mut cursor)?;
-        let mut data = std::collections::HashMap::new();
-        for tensor_name in content.tensor_infos.keys() {
-            let tensor = content.tensor(&mut cursor, tensor_name, device)?;
-            data.insert(tensor_name.to_string(), Arc::new(tensor));
-        }
-        Ok(Self {
-            data: Arc::new(data),
-            path: Vec::new(),
-            device: device.clone(),
-        })
+        let vb = candle_nn::VarBuilder::from_gguf_buffer(buffer, DType::F32, device)?;
+        Ok(Self { vb })
     }
 
-    pub fn pp<S: ToString>(&self, s: S) -> Self {
-        let mut path = self.path.clone();
-        path.push(s.to_string());
-        Self {
-            data: self.data.clone(),
-            path,
-            device: self.device.clone(),
-        }
+    /// The underlying [`candle_nn::VarBuilder`], the float tensors it returns are dequantized to
+    /// f32 and the layers created with [`candle_nn::linear::qlinear`] keep their weights quantized.
+    pub fn var_builder(&self) -> &candle_nn::VarBuilder<'static> {
+        &self.vb
     }
 
-    fn path(&self, tensor_name: &str) -> String {
-        if self.path.is_empty() {
-            tensor_name.to_string()
-        } else {
-            [&self.path.join("."), tensor_name].join(".")
-        }
+    pub fn pp<S: ToString>(&self, s: S) -> Self {
+        Self { vb: self.vb.pp(s) }
     }
 
     pub fn get<S: Into<Shape>>(&self, s: S, name: &str) -> Result<Arc<QTensor>> {
-        let path = self.path(name);
-        match self.data.get(&path) {
-            None => {
-                candle::bail!("cannot find tensor {name}")
-            }
-            Some(qtensor) => {
-                let shape = s.into();
-                if qtensor.shape() != &shape {
-                    candle::bail!(
-                        "shape mismatch for {name}, got {:?}, expected {shape:?}",
-                        qtensor.shape()
-                    )
-                }
-                Ok(qtensor.clone())
-            }
+        let qtensor = self.get_no_shape(name)?;
+        let shape = s.into();
+        if qtensor.shape() != &shape {
+            candle::bail!(
+                "shape mismatch for {name}, got {:?}, expected {shape:?}",
+                qtensor.shape()
+            )
         }
+        Ok(qtensor)
     }
 
     pub fn get_no_shape(&self, name: &str) -> Result<Arc<QTensor>> {
-        let path = self.path(name);
-        match self.data.get(&path) {
-            None => {
-                candle::bail!("cannot find tensor {name}")
-            }
-            Some(qtensor) => Ok(qtensor.clone()),
+        match self.vb.get_qtensor(name)? {
+            None => candle::bail!("cannot find tensor {name}"),
+            Some(qtensor) => Ok(qtensor),
         }
     }
 
     pub fn device(&self) -> &Device {
-        &self.device
+        self.vb.device()
     }
 
     pub fn contains_key(&self, key: &str) -> bool {
-        self.data.contains_key(key)
+        self.vb.root().contains_tensor(key)
     }
 }
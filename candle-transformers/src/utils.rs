@@ -1,18 +1,19 @@
-use candle::{Result, Tensor};
+use candle::{DType, Result, Tensor};
 
 pub fn apply_repeat_penalty(logits: &Tensor, penalty: f32, context: &[u32]) -> Result<Tensor> {
     let device = logits.device();
-    let mut logits = logits.to_vec1::<f32>()?;
-    let context: std::collections::HashSet<_> = context.iter().collect();
-    for (token_id, logit) in logits.iter_mut().enumerate() {
-        if context.contains(&(token_id as u32)) {
-            if *logit >= 0. {
-                *logit /= penalty
-            } else {
-                *logit *= penalty
-            }
+    let vocab_size = logits.dim(0)?;
+    let mut in_context = vec![0u8; vocab_size];
+    for &token_id in context.iter() {
+        if let Some(v) = in_context.get_mut(token_id as usize) {
+            *v = 1
         }
     }
-    let logits_len = logits.len();
-    Tensor::from_vec(logits, logits_len, device)
+    let in_context = Tensor::from_vec(in_context, vocab_size, device)?;
+    let logits = logits.to_dtype(DType::F32)?;
+    let penalty = penalty as f64;
+    let penalized = logits
+        .ge(0f64)?
+        .where_cond(&(&logits / penalty)?, &(&logits * penalty)?)?;
+    in_context.where_cond(&penalized, &logits)
 }
@@ -0,0 +1,366 @@
+use candle::{DType, Device, Result, Tensor, D};
+use candle_nn::{VarBuilder, VarMap};
+use candle_transformers::generation::beam_search::{BeamSearch, Decoder, Hypothesis};
+use candle_transformers::models::{marian, t5, trocr, vit};
+
+const EOS: u32 = 1;
+
+fn t5_model(vb: VarBuilder, use_cache: bool) -> Result<t5::T5ForConditionalGeneration> {
+    let cfg = format!(
+        r#"{{
+            "vocab_size": 48,
+            "d_model": 16,
+            "d_kv": 4,
+            "d_ff": 32,
+            "num_layers": 2,
+            "num_heads": 4,
+            "relative_attention_num_buckets": 8,
+            "dropout_rate": 0.0,
+            "layer_norm_epsilon": 1e-6,
+            "initializer_factor": 1.0,
+            "is_encoder_decoder": true,
+            "use_cache": {use_cache},
+            "pad_token_id": 0,
+            "eos_token_id": 1,
+            "decoder_start_token_id": 0
+        }}"#
+    );
+    let cfg: t5::Config = serde_json::from_str(&cfg).map_err(candle::Error::wrap)?;
+    t5::T5ForConditionalGeneration::load(vb, &cfg)
+}
+
+fn marian_config() -> marian::Config {
+    marian::Config {
+        vocab_size: 40,
+        decoder_vocab_size: None,
+        max_position_embeddings: 32,
+        encoder_layers: 1,
+        encoder_ffn_dim: 32,
+        encoder_attention_heads: 2,
+        decoder_layers: 2,
+        decoder_ffn_dim: 32,
+        decoder_attention_heads: 2,
+        use_cache: true,
+        is_encoder_decoder: true,
+        activation_function: candle_nn::Activation::Swish,
+        d_model: 16,
+        decoder_start_token_id: 39,
+        scale_embedding: true,
+        pad_token_id: 39,
+        eos_token_id: EOS,
+        forced_eos_token_id: EOS,
+        share_encoder_decoder_embeddings: true,
+    }
+}
+
+fn trocr_model(vb: VarBuilder) -> Result<trocr::TrOCRModel> {
+    let encoder_cfg = vit::Config {
+        hidden_size: 16,
+        num_hidden_layers: 1,
+        num_attention_heads: 2,
+        intermediate_size: 32,
+        hidden_act: candle_nn::Activation::Gelu,
+        layer_norm_eps: 1e-12,
+        image_size: 8,
+        patch_size: 4,
+        num_channels: 3,
+        qkv_bias: true,
+    };
+    let decoder_cfg = trocr::TrOCRConfig {
+        vocab_size: 36,
+        d_model: 16,
+        cross_attention_hidden_size: 16,
+        decoder_layers: 2,
+        decoder_attention_heads: 2,
+        decoder_ffn_dim: 32,
+        max_position_embeddings: 32,
+        decoder_vocab_size: Some(36),
+        eos_token_id: EOS,
+        ..Default::default()
+    };
+    trocr::TrOCRModel::new(&encoder_cfg, &decoder_cfg, vb)
+}
+
+// Some weights, e.g. the t5 layer norms, are initialized to zero which would result in uniform
+// logits, so all the variables are re-initialized randomly.
+fn randomize(varmap: &VarMap) -> Result<()> {
+    for var in varmap.all_vars() {
+        var.set(&Tensor::randn(0f32, 0.5, var.shape(), var.device())?)?
+    }
+    Ok(())
+}
+
+// Greedy decoding using the decoder kv cache.
+fn greedy<M: Decoder>(
+    model: &mut M,
+    encoder_output: &Tensor,
+    start: u32,
+    max_new_tokens: usize,
+) -> Result<Vec<u32>> {
+    model.reset_kv_cache();
+    let mut tokens = vec![start];
+    for _ in 0..max_new_tokens {
+        let input = Tensor::new(&tokens[tokens.len() - 1..], &Device::Cpu)?.unsqueeze(0)?;
+        let logits = model.decode_step(&input, encoder_output, tokens.len() - 1)?;
+        let token = logits.squeeze(0)?.argmax(D::Minus1)?.to_scalar::<u32>()?;
+        if token == EOS {
+            break;
+        }
+        tokens.push(token)
+    }
+    Ok(tokens[1..].to_vec())
+}
+
+// Recomputes the score of a hypothesis from the log-probabilities of the full sequence, without
+// using the kv cache. `logits_fn` returns the logits for all the positions of a sequence.
+fn check_scores(
+    hyps: &[Hypothesis],
+    bs: &BeamSearch,
+    mut logits_fn: impl FnMut(&[u32]) -> Result<Tensor>,
+) -> Result<()> {
+    for hyp in hyps.iter() {
+        let mut tokens = hyp.tokens.clone();
+        if tokens.len() < bs.max_new_tokens {
+            tokens.push(bs.eos_token_id)
+        }
+        let input = [&[bs.decoder_start_token_id], &tokens[..tokens.len() - 1]].concat();
+        let log_probs = candle_nn::ops::log_softmax(&logits_fn(&input)?, D::Minus1)?;
+        let log_probs = log_probs.to_vec2::<f32>()?;
+        let score: f64 = tokens
+            .iter()
+            .zip(log_probs.iter())
+            .map(|(&t, lp)| lp[t as usize] as f64)
+            .sum();
+        let score = score / (tokens.len() as f64).powf(bs.length_penalty);
+        assert!((score - hyp.score).abs() < 1e-4, "{score} {hyp:?}");
+    }
+    for w in hyps.windows(2) {
+        assert!(w[0].score >= w[1].score)
+    }
+    Ok(())
+}
+
+fn has_repeated_ngram(tokens: &[u32], n: usize) -> bool {
+    let ngrams: Vec<_> = tokens.windows(n).collect();
+    ngrams
+        .iter()
+        .enumerate()
+        .any(|(i, a)| ngrams[i + 1..].contains(a))
+}
+
+// A decoder where the next token distribution only depends on the last token.
+struct Bigram {
+    log_probs: Vec<Vec<f32>>,
+}
+
+impl Bigram {
+    fn new() -> Self {
+        let vocab_size = 5;
+        // Unlisted transitions have a zero probability.
+        let mut log_probs = vec![vec![0f32; vocab_size]; vocab_size];
+        log_probs[EOS as usize] = vec![1. / vocab_size as f32; vocab_size];
+        for (prev, next, p) in [
+            (0, 2, 0.6),
+            (0, 3, 0.4),
+            (2, EOS, 0.3),
+            (2, 4, 0.36),
+            (2, 2, 0.34),
+            (3, EOS, 0.9),
+            (3, 4, 0.1),
+            (4, EOS, 1.0),
+        ] {
+            log_probs[prev][next as usize] = p
+        }
+        for row in log_probs.iter_mut() {
+            row.iter_mut().for_each(|v| *v = v.ln())
+        }
+        Self { log_probs }
+    }
+}
+
+impl Decoder for Bigram {
+    fn decode_step(&mut self, tokens: &Tensor, _: &Tensor, _: usize) -> Result<Tensor> {
+        let (b_sz, seq_len) = tokens.dims2()?;
+        let last = tokens.narrow(1, seq_len - 1, 1)?.flatten_all()?;
+        let logits: Vec<f32> = last
+            .to_vec1::<u32>()?
+            .iter()
+            .flat_map(|&t| self.log_probs[t as usize].clone())
+            .collect();
+        Tensor::from_vec(logits, (b_sz, self.log_probs.len()), tokens.device())
+    }
+
+    fn reorder_kv_cache(&mut self, _: &Tensor) -> Result<()> {
+        unreachable!("the bigram decoder does not use a kv cache")
+    }
+
+    fn reset_kv_cache(&mut self) {}
+
+    fn use_kv_cache(&self) -> bool {
+        false
+    }
+}
+
+#[test]
+fn bigram_beam_search() -> Result<()> {
+    let mut model = Bigram::new();
+    let encoder_output = Tensor::zeros((1, 1, 1), DType::F32, &Device::Cpu)?;
+    let greedy_tokens = greedy(&mut model, &encoder_output, 0, 8)?;
+    assert_eq!(greedy_tokens, [2, 4]);
+
+    let mut bs = BeamSearch::new(2, 0, EOS);
+    bs.length_penalty = 0.;
+    bs.num_return_sequences = 2;
+    for early_stopping in [false, true] {
+        bs.early_stopping = early_stopping;
+        let hyps = bs.generate(&mut model, &encoder_output)?;
+        let tokens: Vec<_> = hyps.iter().map(|h| h.tokens.as_slice()).collect();
+        assert_eq!(tokens, [&[3][..], &[2, 4]]);
+        assert!((hyps[0].score - (0.4f64 * 0.9).ln()).abs() < 1e-5);
+        assert!((hyps[1].score - (0.6f64 * 0.36).ln()).abs() < 1e-5);
+    }
+
+    // A large length penalty favors the longer sequence.
+    bs.length_penalty = 2.;
+    let hyps = bs.generate(&mut model, &encoder_output)?;
+    assert_eq!(hyps[0].tokens, [2, 4]);
+    assert!((hyps[0].score - (0.6f64 * 0.36).ln() / 9.).abs() < 1e-5);
+
+    // The end of sequence token is banned for the first two steps.
+    bs.length_penalty = 0.;
+    bs.min_new_tokens = 2;
+    let hyps = bs.generate(&mut model, &encoder_output)?;
+    assert_eq!(hyps[0].tokens, [2, 4]);
+    assert!(hyps.iter().all(|h| h.tokens.len() >= 2));
+
+    // Beams that have not finished are returned when reaching the length limit.
+    bs.min_new_tokens = 0;
+    bs.max_new_tokens = 1;
+    let hyps = bs.generate(&mut model, &encoder_output)?;
+    let tokens: Vec<_> = hyps.iter().map(|h| h.tokens.as_slice()).collect();
+    assert_eq!(tokens, [[2], [3]]);
+    Ok(())
+}
+
+#[test]
+fn t5_single_beam_is_greedy() -> Result<()> {
+    let device = &Device::Cpu;
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, device);
+    let mut model = t5_model(vb.clone(), true)?;
+    randomize(&varmap)?;
+    let input_ids = Tensor::new(&[[5u32, 6, 7, 8, 9, 1]], device)?;
+    let encoder_output = model.encode(&input_ids)?;
+
+    let mut bs = BeamSearch::new(1, 0, EOS);
+    bs.max_new_tokens = 10;
+    let hyps = bs.generate(&mut model, &encoder_output)?;
+    assert_eq!(hyps.len(), 1);
+    let expected = greedy(&mut model, &encoder_output, 0, 10)?;
+    assert_eq!(hyps[0].tokens, expected);
+
+    // Running without the kv cache gives the same beams.
+    bs.num_beams = 3;
+    bs.num_return_sequences = 3;
+    let hyps = bs.generate(&mut model, &encoder_output)?;
+    let mut model_no_cache = t5_model(vb, false)?;
+    let hyps_no_cache = bs.generate(&mut model_no_cache, &encoder_output)?;
+    assert_eq!(hyps.len(), 3);
+    assert_eq!(hyps.len(), hyps_no_cache.len());
+    for (h1, h2) in hyps.iter().zip(hyps_no_cache.iter()) {
+        assert_eq!(h1.tokens, h2.tokens);
+        assert!((h1.score - h2.score).abs() < 1e-4);
+    }
+    Ok(())
+}
+
+#[test]
+fn t5_no_repeat_ngram() -> Result<()> {
+    let device = &Device::Cpu;
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, device);
+    let mut model = t5_model(vb, true)?;
+    randomize(&varmap)?;
+    let input_ids = Tensor::new(&[[5u32, 5, 5, 5, 1]], device)?;
+    let encoder_output = model.encode(&input_ids)?;
+    let mut bs = BeamSearch::new(4, 0, EOS);
+    bs.max_new_tokens = 16;
+    bs.min_new_tokens = 16;
+    bs.num_return_sequences = 4;
+    for n in [1, 2] {
+        bs.no_repeat_ngram_size = n;
+        let hyps = bs.generate(&mut model, &encoder_output)?;
+        assert_eq!(hyps.len(), 4);
+        for hyp in hyps.iter() {
+            assert_eq!(hyp.tokens.len(), 16);
+            let tokens = [&[0], hyp.tokens.as_slice()].concat();
+            assert!(!has_repeated_ngram(&tokens, n), "{n} {tokens:?}");
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn marian_n_best() -> Result<()> {
+    let device = &Device::Cpu;
+    let cfg = marian_config();
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, device);
+    let mut model = marian::MTModel::new(&cfg, vb)?;
+    randomize(&varmap)?;
+    let input_ids = Tensor::new(&[[3u32, 4, 5, 6, 1]], device)?;
+    let encoder_output = model.encoder().forward(&input_ids, 0)?;
+
+    for (length_penalty, early_stopping) in [(1.0, false), (0.5, true), (-1.0, false)] {
+        let mut bs = BeamSearch::new(4, cfg.decoder_start_token_id, EOS);
+        bs.max_new_tokens = 8;
+        bs.num_return_sequences = 3;
+        bs.length_penalty = length_penalty;
+        bs.early_stopping = early_stopping;
+        let hyps = bs.generate(&mut model, &encoder_output)?;
+        assert_eq!(hyps.len(), 3);
+        check_scores(&hyps, &bs, |tokens| {
+            model.reset_kv_cache();
+            let input = Tensor::new(tokens, device)?.unsqueeze(0)?;
+            model.decode(&input, &encoder_output, 0)?.squeeze(0)
+        })?;
+    }
+
+    let mut bs = BeamSearch::new(1, cfg.decoder_start_token_id, EOS);
+    bs.max_new_tokens = 8;
+    let hyps = bs.generate(&mut model, &encoder_output)?;
+    let expected = greedy(&mut model, &encoder_output, cfg.decoder_start_token_id, 8)?;
+    assert_eq!(hyps[0].tokens, expected);
+    Ok(())
+}
+
+#[test]
+fn trocr_n_best() -> Result<()> {
+    let device = &Device::Cpu;
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, device);
+    let mut model = trocr_model(vb)?;
+    randomize(&varmap)?;
+    let image = Tensor::randn(0f32, 1., (1, 3, 8, 8), device)?;
+    let encoder_output = model.encoder().forward(&image)?;
+
+    let mut bs = BeamSearch::new(3, 2, EOS);
+    bs.max_new_tokens = 6;
+    bs.num_return_sequences = 3;
+    bs.no_repeat_ngram_size = 2;
+    let hyps = bs.generate(&mut model, &encoder_output)?;
+    assert_eq!(hyps.len(), 3);
+    check_scores(&hyps, &bs, |tokens| {
+        model.reset_kv_cache();
+        let input = Tensor::new(tokens, device)?.unsqueeze(0)?;
+        model.decode(&input, &encoder_output, 0)?.squeeze(0)
+    })?;
+    for hyp in hyps.iter() {
+        let tokens = [&[2], hyp.tokens.as_slice()].concat();
+        assert!(!has_repeated_ngram(&tokens, 2), "{tokens:?}");
+    }
+
+    bs.num_return_sequences = 4;
+    assert!(bs.generate(&mut model, &encoder_output).is_err());
+    Ok(())
+}
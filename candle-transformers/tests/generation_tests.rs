@@ -27,3 +27,131 @@ fn sample_with_top_p() -> Result<()> {
     assert_eq!(token, 2);
     Ok(())
 }
+
+#[test]
+fn sample_with_top_k() -> Result<()> {
+    let logits = Tensor::new(&[0.1f32, 0.2, 0.3, 0.4], &Device::Cpu)?;
+    let mut logits_process = LogitsProcessor::builder(42)
+        .temperature(1.0)
+        .top_k(2)
+        .build();
+    for _ in 0..20 {
+        let token = logits_process.sample(&logits)?;
+        assert!(token == 2 || token == 3);
+    }
+    let mut logits_process = LogitsProcessor::builder(42)
+        .temperature(1.0)
+        .top_k(1)
+        .build();
+    assert_eq!(logits_process.sample(&logits)?, 3);
+    Ok(())
+}
+
+#[test]
+fn sample_with_min_p() -> Result<()> {
+    // Probabilities are roughly [0.09, 0.24, 0.67, 0.0].
+    let logits = Tensor::new(&[0f32, 1., 2., -10.], &Device::Cpu)?;
+    let mut logits_process = LogitsProcessor::builder(42)
+        .temperature(1.0)
+        .min_p(0.3)
+        .build();
+    for _ in 0..20 {
+        let token = logits_process.sample(&logits)?;
+        assert!(token == 1 || token == 2);
+    }
+    Ok(())
+}
+
+#[test]
+fn sample_with_typical_p() -> Result<()> {
+    let logits = Tensor::new(&[0f32, 1., 2., 6.], &Device::Cpu)?;
+    let mut logits_process = LogitsProcessor::builder(42)
+        .temperature(1.0)
+        .typical_p(0.5)
+        .build();
+    for _ in 0..20 {
+        assert_eq!(logits_process.sample(&logits)?, 3);
+    }
+    Ok(())
+}
+
+#[test]
+fn sample_with_tfs() -> Result<()> {
+    let logits = Tensor::new(&[0f32, 0.1, 0.2, 5., 5.1, 5.2], &Device::Cpu)?;
+    let mut logits_process = LogitsProcessor::builder(42)
+        .temperature(1.0)
+        .tfs_z(0.5)
+        .build();
+    for _ in 0..20 {
+        let token = logits_process.sample(&logits)?;
+        assert!(token >= 3);
+    }
+    Ok(())
+}
+
+#[test]
+fn sample_with_penalties() -> Result<()> {
+    let logits = Tensor::new(&[1f32, 2., 3., 2.9], &Device::Cpu)?;
+    let mut logits_process = LogitsProcessor::builder(42).repeat_penalty(1.5).build();
+    assert_eq!(logits_process.sample(&logits)?, 2);
+    assert_eq!(logits_process.sample_with_context(&logits, &[2])?, 3);
+    // Only the last token of the context is taken into account.
+    let mut logits_process = LogitsProcessor::builder(42)
+        .repeat_penalty(1.5)
+        .penalty_last_n(1)
+        .build();
+    assert_eq!(logits_process.sample_with_context(&logits, &[2, 0])?, 2);
+
+    let mut logits_process = LogitsProcessor::builder(42).frequency_penalty(0.4).build();
+    assert_eq!(logits_process.sample_with_context(&logits, &[2, 3])?, 2);
+    assert_eq!(logits_process.sample_with_context(&logits, &[2, 2, 3])?, 3);
+    assert_eq!(
+        logits_process.sample_with_context(&logits, &[2, 2, 3, 3, 1])?,
+        2
+    );
+
+    let mut logits_process = LogitsProcessor::builder(42).presence_penalty(2.).build();
+    assert_eq!(logits_process.sample_with_context(&logits, &[2, 3, 3])?, 1);
+    Ok(())
+}
+
+#[test]
+fn sample_with_bias_and_banned_tokens() -> Result<()> {
+    let logits = Tensor::new(&[1f32, 2., 3., 2.9], &Device::Cpu)?;
+    let mut logits_process = LogitsProcessor::builder(42).logit_bias(0, 2.5).build();
+    assert_eq!(logits_process.sample(&logits)?, 0);
+    let mut logits_process = LogitsProcessor::builder(42)
+        .temperature(2.0)
+        .banned_tokens(&[2, 3])
+        .build();
+    for _ in 0..20 {
+        let token = logits_process.sample(&logits)?;
+        assert!(token == 0 || token == 1);
+    }
+    Ok(())
+}
+
+#[test]
+fn sample_is_deterministic() -> Result<()> {
+    let logits = Tensor::new(&[0.1f32, 0.2, 0.3, 0.4, 0.5, 0.6], &Device::Cpu)?;
+    let sample = |seed: u64| -> Result<Vec<u32>> {
+        let mut logits_process = LogitsProcessor::builder(seed)
+            .temperature(1.5)
+            .top_k(5)
+            .top_p(0.95)
+            .min_p(0.05)
+            .build();
+        (0..32).map(|_| logits_process.sample(&logits)).collect()
+    };
+    assert_eq!(sample(1337)?, sample(1337)?);
+    assert_ne!(sample(1337)?, sample(42)?);
+    Ok(())
+}
+
+#[test]
+fn repeat_penalty() -> Result<()> {
+    let logits = Tensor::new(&[1f32, -2., 3., 4.], &Device::Cpu)?;
+    let logits = candle_transformers::utils::apply_repeat_penalty(&logits, 2., &[1, 2, 2, 7])?;
+    assert_eq!(logits.to_vec1::<f32>()?, [1., -4., 1.5, 4.]);
+    Ok(())
+}
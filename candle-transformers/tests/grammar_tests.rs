@@ -0,0 +1,233 @@
+use candle::{Device, Result, Tensor};
+use candle_transformers::generation::grammar::{Grammar, GrammarConstraint, Vocabulary};
+use candle_transformers::generation::{LogitsProcessor, TokenMask};
+use std::sync::Arc;
+
+fn vocab(tokens: &[&str]) -> Arc<Vocabulary> {
+    let tokens = tokens.iter().map(|t| t.as_bytes().to_vec()).collect();
+    Arc::new(Vocabulary::new(tokens, Some(0)))
+}
+
+fn token_strs(vocab: &Vocabulary, tokens: &[u32]) -> Vec<String> {
+    tokens
+        .iter()
+        .map(|&t| String::from_utf8_lossy(vocab.token(t).unwrap()).to_string())
+        .collect()
+}
+
+#[test]
+fn regex_matches() -> Result<()> {
+    let g = Grammar::from_regex(r"[0-9]{2,3}-[a-z]+(\.txt)?")?;
+    for text in ["12-a", "123-abc", "12-a.txt"] {
+        assert!(g.matches(text.as_bytes()), "{text}");
+    }
+    for text in ["1-a", "1234-a", "12-", "12-A", "12-a.tx", ""] {
+        assert!(!g.matches(text.as_bytes()), "{text}");
+    }
+
+    let g = Grammar::from_regex(r"^(cat|dog)s?\s\d+$")?;
+    assert!(g.matches(b"cats 12"));
+    assert!(g.matches(b"dog\t7"));
+    assert!(!g.matches(b"cow 1"));
+
+    let g = Grammar::from_regex(r"[^a-c]\w*")?;
+    assert!(g.matches(b"d_Z9"));
+    assert!(g.matches("é".as_bytes()));
+    assert!(!g.matches(b"a"));
+    assert!(!g.matches(b"d-"));
+
+    // Multi-byte characters and ranges spanning several encoded lengths.
+    let g = Grammar::from_regex(r"[é-ë]+")?;
+    assert!(g.matches("éêë".as_bytes()));
+    assert!(!g.matches("è".as_bytes()));
+    assert!(!g.matches("ì".as_bytes()));
+    let g = Grammar::from_regex(r"[\u{7F}-\u{10000}]")?;
+    for c in [
+        '\u{7F}',
+        '\u{80}',
+        '\u{7FF}',
+        '\u{800}',
+        '\u{FFFF}',
+        '\u{10000}',
+    ] {
+        assert!(g.matches(c.to_string().as_bytes()), "{c:?}");
+    }
+    assert!(!g.matches("\u{10001}".as_bytes()));
+    assert!(!g.matches(&[0xC3]));
+
+    assert!(Grammar::from_regex("a(b").is_err());
+    assert!(Grammar::from_regex("*a").is_err());
+    assert!(Grammar::from_regex("a{3,1}").is_err());
+    // Repeating a pattern that matches the empty string is not supported.
+    assert!(Grammar::from_regex("(a?)*").is_err());
+    Ok(())
+}
+
+#[test]
+fn gbnf_matches() -> Result<()> {
+    let g = Grammar::from_gbnf(
+        r#"
+# Arithmetic expressions.
+root ::= expr
+expr ::= term (("+" | "-") term)*
+term ::= factor
+       | factor "*" term
+factor ::= [0-9]+ | "(" ws expr ws ")"
+ws ::= " "?
+"#,
+    )?;
+    for text in ["1", "1+2*3", "(1+2)*3", "( 4-(5) )*6", "((1))"] {
+        assert!(g.matches(text.as_bytes()), "{text}");
+    }
+    for text in ["", "1+", "(1", "1**2", "(1+2))"] {
+        assert!(!g.matches(text.as_bytes()), "{text}");
+    }
+
+    let left_recursive = r#"
+root ::= expr
+expr ::= expr "+" [0-9] | [0-9]
+"#;
+    let err = Grammar::from_gbnf(left_recursive).unwrap_err();
+    assert!(err.to_string().contains("left recursive"), "{err}");
+    assert!(Grammar::from_gbnf("root ::= foo").is_err());
+    assert!(Grammar::from_gbnf("expr ::= \"a\"").is_err());
+    assert!(Grammar::from_gbnf("root ::= \"a").is_err());
+    Ok(())
+}
+
+#[test]
+fn json_grammar() -> Result<()> {
+    let g = Grammar::json()?;
+    for text in [
+        "{}",
+        r#"{"a": [1, -2.5e3, true, null], "b\n": {"c": "\u00e9"}}"#,
+        "[\n  1,\n  \"x\"\n]",
+        "\"é\"",
+    ] {
+        assert!(g.matches(text.as_bytes()), "{text}");
+    }
+    for text in ["{", "[1,]", "{'a': 1}", "01", "\"\\x\""] {
+        assert!(!g.matches(text.as_bytes()), "{text}");
+    }
+    Ok(())
+}
+
+#[test]
+fn token_mask() -> Result<()> {
+    let vocab = vocab(&["", "1", "2", "12", "123", "-", "1-", "a", "ab", "b", "é"]);
+    let g = Arc::new(Grammar::from_regex(r"[0-9]{2,3}-[a-z]+")?);
+    let mut c = GrammarConstraint::new(g, vocab.clone());
+    let allowed = token_strs(&vocab, &c.allowed_tokens());
+    assert_eq!(allowed, ["1", "2", "12", "123"]);
+
+    c.advance(1)?;
+    assert_eq!(
+        token_strs(&vocab, &c.allowed_tokens()),
+        ["1", "2", "12", "1-"]
+    );
+    // Tokens that do not match are rejected and do not change the state.
+    assert!(c.advance(4).is_err());
+    c.advance(6)?;
+    assert!(!c.is_accepting());
+    let mut mask = vec![true; 12];
+    c.mask(&mut mask)?;
+    let allowed: Vec<_> = (0..12).filter(|&i| mask[i]).collect();
+    assert_eq!(allowed, [7, 8, 9]);
+
+    c.advance(8)?;
+    assert!(c.is_accepting());
+    assert_eq!(c.allowed_tokens(), [0, 7, 8, 9]);
+    c.advance(0)?;
+    assert!(c.is_finished());
+    assert!(c.allowed_tokens().is_empty());
+
+    c.reset();
+    assert!(!c.is_finished());
+    assert!(c.advance(0).is_err());
+    Ok(())
+}
+
+#[test]
+fn token_mask_partial_utf8() -> Result<()> {
+    // "é" is encoded as [0xC3, 0xA9], the vocabulary only contains the individual bytes.
+    let tokens = vec![
+        vec![],
+        vec![0xC3],
+        vec![0xA9],
+        b"e".to_vec(),
+        vec![0xC3, 0xA8],
+    ];
+    let vocab = Arc::new(Vocabulary::new(tokens, Some(0)));
+    let g = Arc::new(Grammar::from_regex("é+")?);
+    let mut c = GrammarConstraint::new(g, vocab);
+    assert_eq!(c.allowed_tokens(), [1]);
+    c.advance(1)?;
+    assert_eq!(c.allowed_tokens(), [2]);
+    c.advance(2)?;
+    assert_eq!(c.allowed_tokens(), [0, 1]);
+    Ok(())
+}
+
+#[test]
+fn sample_json() -> Result<()> {
+    let strs = [
+        "<eos>",
+        "{",
+        "}",
+        "[",
+        "]",
+        "\"",
+        ":",
+        ",",
+        " ",
+        "\n",
+        "a",
+        "b",
+        "1",
+        "-",
+        ".",
+        "e",
+        "true",
+        "null",
+        "\"key\": ",
+        "{\"",
+        "\\",
+        "u",
+        "0",
+    ];
+    let vocab = vocab(&strs);
+    let grammar = Arc::new(Grammar::json()?);
+    let closing: Vec<usize> = ["}", "]", "\"", "<eos>"]
+        .iter()
+        .map(|c| strs.iter().position(|s| s == c).unwrap())
+        .collect();
+    for seed in 0..10 {
+        let mut constraint = GrammarConstraint::new(grammar.clone(), vocab.clone());
+        let mut processor = LogitsProcessor::new(seed, Some(1.0), None);
+        let mut tokens = vec![];
+        for step in 0..1000 {
+            if constraint.is_finished() {
+                break;
+            }
+            // Make the closing tokens more and more likely so that generation terminates.
+            let mut logits = vec![0f32; strs.len() + 3];
+            for &c in closing.iter() {
+                logits[c] = step as f32 / 20.
+            }
+            let logits = Tensor::new(logits, &Device::Cpu)?;
+            let token = processor.sample_masked(&logits, &tokens, &mut constraint)?;
+            tokens.push(token)
+        }
+        // Generation stops either on the end of sequence token or when the grammar cannot be
+        // extended anymore.
+        assert!(constraint.is_finished());
+        if tokens.last() == Some(&0) {
+            tokens.pop();
+        }
+        let text: String = tokens.iter().map(|&t| strs[t as usize]).collect();
+        if let Err(err) = serde_json::from_str::<serde_json::Value>(&text) {
+            panic!("{text}: {err}")
+        }
+    }
+    Ok(())
+}
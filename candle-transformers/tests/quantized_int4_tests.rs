@@ -0,0 +1,69 @@
+use candle::{DType, Device, Module, Result, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::models::with_tracing;
+use std::collections::HashMap;
+
+const IN_DIM: usize = 16;
+const OUT_DIM: usize = 8;
+
+fn pack(vs: impl Iterator<Item = u32>) -> i64 {
+    vs.enumerate()
+        .fold(0u32, |acc, (i, v)| acc | (v << (4 * i))) as i32 as i64
+}
+
+fn q(i: usize, o: usize) -> u32 {
+    ((3 * i + o) % 16) as u32
+}
+
+#[test]
+fn int4_linear() -> Result<()> {
+    let dev = &Device::Cpu;
+    // Two groups of 8 input features, all the zero points are 8 and the scales 0.5.
+    let scales = Tensor::full(0.5f32, (2, OUT_DIM), dev)?.to_dtype(DType::F16)?;
+    let bias = Tensor::arange(0f32, OUT_DIM as f32, dev)?;
+    let gptq_qweight = (0..IN_DIM / 8)
+        .map(|r| {
+            (0..OUT_DIM)
+                .map(|o| pack((0..8).map(|i| q(8 * r + i, o))))
+                .collect()
+        })
+        .collect::<Vec<Vec<i64>>>();
+    let gptq_qzeros = vec![vec![pack(std::iter::repeat(7).take(8))]; 2];
+    // The AWQ output features are interleaved in the packed values.
+    let awq_qweight = (0..IN_DIM)
+        .map(|i| vec![pack([0, 2, 4, 6, 1, 3, 5, 7].into_iter().map(|o| q(i, o)))])
+        .collect::<Vec<Vec<i64>>>();
+    let awq_qzeros = vec![vec![pack(std::iter::repeat(8).take(8))]; 2];
+    let mut ts = HashMap::new();
+    ts.insert("gptq.qweight".to_string(), Tensor::new(gptq_qweight, dev)?);
+    ts.insert("gptq.qzeros".to_string(), Tensor::new(gptq_qzeros, dev)?);
+    ts.insert("gptq.scales".to_string(), scales.clone());
+    ts.insert("gptq.bias".to_string(), bias.clone());
+    ts.insert("awq.qweight".to_string(), Tensor::new(awq_qweight, dev)?);
+    ts.insert("awq.qzeros".to_string(), Tensor::new(awq_qzeros, dev)?);
+    ts.insert("awq.scales".to_string(), scales);
+    ts.insert(
+        "dense.weight".to_string(),
+        Tensor::zeros((OUT_DIM, IN_DIM), DType::F32, dev)?,
+    );
+    let vb = VarBuilder::from_tensors(ts, DType::F32, dev);
+
+    let ws = (0..OUT_DIM)
+        .flat_map(|o| (0..IN_DIM).map(move |i| (q(i, o) as f32 - 8.) * 0.5))
+        .collect::<Vec<_>>();
+    let ws = Tensor::from_vec(ws, (OUT_DIM, IN_DIM), dev)?;
+    let xs = Tensor::randn(0f32, 1., (2, 3, IN_DIM), dev)?;
+    let expected = xs.broadcast_matmul(&ws.t()?)?;
+
+    let gptq = with_tracing::linear(IN_DIM, OUT_DIM, vb.pp("gptq"))?;
+    let diff = (gptq.forward(&xs)? - expected.broadcast_add(&bias)?)?.abs()?;
+    assert!(diff.flatten_all()?.max(0)?.to_scalar::<f32>()? < 1e-4);
+    let awq = with_tracing::linear_no_bias(IN_DIM, OUT_DIM, vb.pp("awq"))?;
+    let diff = (awq.forward(&xs)? - &expected)?.abs()?;
+    assert!(diff.flatten_all()?.max(0)?.to_scalar::<f32>()? < 1e-4);
+    // Layers without a qweight tensor are loaded as usual.
+    let dense = with_tracing::linear_no_bias(IN_DIM, OUT_DIM, vb.pp("dense"))?;
+    assert_eq!(dense.forward(&xs)?.dims(), [2, 3, OUT_DIM]);
+    assert!(with_tracing::linear_no_bias(IN_DIM, 2 * OUT_DIM, vb.pp("awq")).is_err());
+    Ok(())
+}
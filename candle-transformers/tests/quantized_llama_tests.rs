@@ -0,0 +1,104 @@
+use candle::quantized::{convert, gguf_file, GgmlDType};
+use candle::{DType, Device, Result, Tensor};
+use candle_transformers::models::llama;
+use candle_transformers::models::quantized_llama::ModelWeights;
+use std::collections::HashMap;
+
+const VOCAB_SIZE: usize = 8;
+const HIDDEN_SIZE: usize = 256;
+const INTERMEDIATE_SIZE: usize = 512;
+const NUM_HEADS: usize = 4;
+const NUM_KV_HEADS: usize = 2;
+const NUM_LAYERS: usize = 2;
+
+// Converts a Hugging Face llama checkpoint, i.e. using the Hugging Face tensor names and the
+// rotate-half RoPE layout, and checks that the GGUF model matches the float one.
+#[test]
+fn convert_and_load() -> Result<()> {
+    let dev = &Device::Cpu;
+    let (h, i) = (HIDDEN_SIZE, INTERMEDIATE_SIZE);
+    let kv = HIDDEN_SIZE / NUM_HEADS * NUM_KV_HEADS;
+    let mut shapes = vec![
+        ("model.embed_tokens.weight".to_string(), vec![VOCAB_SIZE, h]),
+        ("model.norm.weight".to_string(), vec![h]),
+        ("lm_head.weight".to_string(), vec![VOCAB_SIZE, h]),
+    ];
+    let layer_shapes = [
+        ("self_attn.q_proj", vec![h, h]),
+        ("self_attn.k_proj", vec![kv, h]),
+        ("self_attn.v_proj", vec![kv, h]),
+        ("self_attn.o_proj", vec![h, h]),
+        ("input_layernorm", vec![h]),
+        ("mlp.gate_proj", vec![i, h]),
+        ("mlp.up_proj", vec![i, h]),
+        ("mlp.down_proj", vec![h, i]),
+        ("post_attention_layernorm", vec![h]),
+    ];
+    for layer_idx in 0..NUM_LAYERS {
+        for (name, shape) in layer_shapes.iter() {
+            shapes.push((
+                format!("model.layers.{layer_idx}.{name}.weight"),
+                shape.clone(),
+            ))
+        }
+    }
+    let tensors = shapes
+        .into_iter()
+        .map(|(name, shape)| {
+            let tensor = if shape.len() == 1 {
+                Tensor::randn(1f32, 0.1, shape, dev)?
+            } else {
+                Tensor::randn(0f32, 0.1, shape, dev)?
+            };
+            Ok((name, tensor))
+        })
+        .collect::<Result<HashMap<_, _>>>()?;
+
+    let dir = std::env::temp_dir().join(format!("candle-quantized-llama-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let src = dir.join("model.safetensors");
+    let hf_config = dir.join("config.json");
+    let dst = dir.join("model.gguf");
+    candle::safetensors::save(&tensors, &src)?;
+    let hf_config_json = serde_json::json!({
+        "vocab_size": VOCAB_SIZE,
+        "hidden_size": HIDDEN_SIZE,
+        "intermediate_size": INTERMEDIATE_SIZE,
+        "num_attention_heads": NUM_HEADS,
+        "num_key_value_heads": NUM_KV_HEADS,
+        "num_hidden_layers": NUM_LAYERS,
+        "max_position_embeddings": 2048,
+        "rms_norm_eps": 1e-5,
+        "rope_theta": 10000.,
+    });
+    std::fs::write(&hf_config, hf_config_json.to_string())?;
+    // No quantization so that the logits can be compared with the float model.
+    let config = convert::Config::new(GgmlDType::F32)
+        .with_architecture("llama")
+        .with_hf_config(&hf_config)?;
+    convert::convert(&[&src], &dst, &config)?;
+
+    let mut file = std::fs::File::open(&dst)?;
+    let content = gguf_file::Content::read(&mut file)?;
+    let mut model = ModelWeights::from_gguf(content, &mut file, dev)?;
+    let tokens = Tensor::new(&[[1u32, 5, 3, 7, 2]], dev)?;
+    let logits = model.forward(&tokens, 0)?.to_dtype(DType::F32)?;
+    assert_eq!(logits.dims(), [1, VOCAB_SIZE]);
+
+    let llama_config: llama::LlamaConfig =
+        serde_json::from_value(hf_config_json).map_err(candle::Error::wrap)?;
+    let llama_config = llama_config.into_config(false);
+    let vb = candle_nn::VarBuilder::from_tensors(tensors, DType::F32, dev);
+    let cache = llama::Cache::new(false, DType::F32, &llama_config, dev)?;
+    let float_model = llama::Llama::load(vb, &cache, &llama_config)?;
+    let expected = float_model.forward(&tokens, 0)?;
+    let diff = (logits - &expected)?
+        .abs()?
+        .flatten_all()?
+        .max(0)?
+        .to_scalar::<f32>()?;
+    let norm = expected.abs()?.flatten_all()?.max(0)?.to_scalar::<f32>()?;
+    assert!(diff <= 1e-4 * norm.max(1.), "{diff} {norm}");
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,241 @@
+use candle::{DType, Device, Result, Tensor, D};
+use candle_nn::{Activation, VarBuilder, VarMap};
+use candle_transformers::models::{llama, mistral};
+use candle_transformers::pipelines::text_generation::{
+    CacheConfig, PagedKvCache, PagedModel, SamplingParams, SchedulerConfig, SequenceInput,
+    TextGeneration,
+};
+
+fn mistral_config(sliding_window: usize) -> mistral::Config {
+    mistral::Config {
+        vocab_size: 64,
+        hidden_size: 32,
+        intermediate_size: 48,
+        num_hidden_layers: 2,
+        num_attention_heads: 4,
+        num_key_value_heads: 2,
+        hidden_act: Activation::Silu,
+        max_position_embeddings: 128,
+        rms_norm_eps: 1e-5,
+        rope_theta: 10_000.,
+        sliding_window,
+        use_flash_attn: false,
+    }
+}
+
+fn llama_config() -> llama::Config {
+    llama::Config {
+        hidden_size: 32,
+        intermediate_size: 48,
+        vocab_size: 64,
+        num_hidden_layers: 2,
+        num_attention_heads: 4,
+        num_key_value_heads: 2,
+        use_flash_attn: false,
+        rms_norm_eps: 1e-5,
+        rope_theta: 10_000.,
+    }
+}
+
+fn prompts() -> Vec<Vec<u32>> {
+    vec![
+        vec![1, 5, 9, 13, 2, 7, 40],
+        vec![3, 60],
+        vec![11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22],
+        vec![42],
+    ]
+}
+
+// Greedy generation one sequence at a time using the non-paged kv cache.
+fn mistral_greedy(
+    cfg: &mistral::Config,
+    vb: VarBuilder,
+    prompt: &[u32],
+    n: usize,
+) -> Result<Vec<u32>> {
+    let mut model = mistral::Model::new(cfg, vb)?;
+    let mut tokens = prompt.to_vec();
+    let mut pos = 0;
+    for _ in 0..n {
+        let input = Tensor::new(&tokens[pos..], &Device::Cpu)?.unsqueeze(0)?;
+        let logits = model.forward(&input, pos)?.squeeze(0)?.squeeze(0)?;
+        pos = tokens.len();
+        tokens.push(logits.argmax(D::Minus1)?.to_scalar::<u32>()?);
+    }
+    Ok(tokens[prompt.len()..].to_vec())
+}
+
+#[test]
+fn paged_forward_matches_forward() -> Result<()> {
+    let device = &Device::Cpu;
+    let cfg = mistral_config(6);
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, device);
+    let paged_model = mistral::Model::new(&cfg, vb.clone())?;
+    let cache_cfg = CacheConfig {
+        block_size: 4,
+        num_blocks: 16,
+    };
+    let cache = PagedKvCache::new(&cache_cfg, 2, 2, 8, DType::F32, device)?;
+
+    let prompts = prompts();
+    let tables: Vec<Vec<usize>> = vec![(0..4).collect(), (4..8).collect(), (8..16).collect()];
+    // Prefill the first two prompts, the third one is only prefilled with the decode step.
+    let seqs = vec![
+        SequenceInput {
+            tokens: &prompts[0],
+            position: 0,
+            block_table: &tables[0],
+        },
+        SequenceInput {
+            tokens: &prompts[1],
+            position: 0,
+            block_table: &tables[1],
+        },
+    ];
+    let logits = paged_model.forward_paged(&seqs, &cache)?;
+    assert_eq!(logits.dims(), [2, 64]);
+    let seqs = vec![
+        SequenceInput {
+            tokens: &[4],
+            position: prompts[0].len(),
+            block_table: &tables[0],
+        },
+        SequenceInput {
+            tokens: &prompts[2],
+            position: 0,
+            block_table: &tables[2],
+        },
+        SequenceInput {
+            tokens: &[8, 9],
+            position: prompts[1].len(),
+            block_table: &tables[1],
+        },
+    ];
+    let logits = paged_model.forward_paged(&seqs, &cache)?;
+
+    let expected = |tokens: &[u32]| -> Result<Tensor> {
+        let mut model = mistral::Model::new(&cfg, vb.clone())?;
+        let input = Tensor::new(tokens, device)?.unsqueeze(0)?;
+        model.forward(&input, 0)?.squeeze(0)?.squeeze(0)
+    };
+    let exp = Tensor::stack(
+        &[
+            expected(&[prompts[0].as_slice(), &[4]].concat())?,
+            expected(&prompts[2])?,
+            expected(&[prompts[1].as_slice(), &[8, 9]].concat())?,
+        ],
+        0,
+    )?;
+    let diff = (logits - exp)?
+        .abs()?
+        .max_keepdim(1)?
+        .max(0)?
+        .to_vec1::<f32>()?;
+    assert!(diff[0] < 1e-4, "{diff:?}");
+    Ok(())
+}
+
+#[test]
+fn continuous_batching_mistral() -> Result<()> {
+    let device = &Device::Cpu;
+    let cfg = mistral_config(8);
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, device);
+    let model = mistral::Model::new(&cfg, vb.clone())?;
+    // Only 12 blocks of 4 positions, this is not enough for all the requests at once so some of
+    // them get preempted.
+    let cache = CacheConfig {
+        block_size: 4,
+        num_blocks: 12,
+    };
+    let scheduler = SchedulerConfig {
+        max_num_seqs: 3,
+        max_num_batched_tokens: 16,
+    };
+    let mut engine = TextGeneration::new(model, &cache, scheduler)?;
+    let params = SamplingParams {
+        max_new_tokens: 10,
+        ..Default::default()
+    };
+    for prompt in prompts() {
+        engine.add_request(prompt, &params)?;
+    }
+    let outputs = engine.run()?;
+    assert_eq!(outputs.len(), 4);
+    assert_eq!(engine.cache().num_free_blocks(), 12);
+    for (idx, (output, prompt)) in outputs.iter().zip(prompts()).enumerate() {
+        assert_eq!(output.request_id, idx);
+        assert_eq!(output.prompt, prompt);
+        let expected = mistral_greedy(&cfg, vb.clone(), &prompt, 10)?;
+        assert_eq!(output.tokens, expected);
+    }
+    Ok(())
+}
+
+#[test]
+fn continuous_batching_llama() -> Result<()> {
+    let device = &Device::Cpu;
+    let cfg = llama_config();
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, device);
+    let cache = llama::Cache::new(true, DType::F32, &cfg, device)?;
+    let model = llama::Llama::load(vb.clone(), &cache, &cfg)?;
+    let cache_cfg = CacheConfig {
+        block_size: 2,
+        num_blocks: 64,
+    };
+    let mut engine = TextGeneration::new(model, &cache_cfg, SchedulerConfig::default())?;
+    let params = SamplingParams {
+        max_new_tokens: 6,
+        ..Default::default()
+    };
+    let mut outputs = vec![];
+    for prompt in prompts() {
+        engine.add_request(prompt, &params)?;
+        // Interleave the arrival of new requests with the generation of the previous ones.
+        for step in engine.step()? {
+            assert!(step.request_id < 4)
+        }
+        outputs.extend(engine.take_finished())
+    }
+    outputs.extend(engine.run()?);
+    outputs.sort_by_key(|o| o.request_id);
+    assert_eq!(outputs.len(), 4);
+    for (output, prompt) in outputs.iter().zip(prompts()) {
+        let cache = llama::Cache::new(true, DType::F32, &cfg, device)?;
+        let model = llama::Llama::load(vb.clone(), &cache, &cfg)?;
+        let mut tokens = prompt.clone();
+        let mut pos = 0;
+        for _ in 0..6 {
+            let input = Tensor::new(&tokens[pos..], device)?.unsqueeze(0)?;
+            let logits = model.forward(&input, pos)?.squeeze(0)?;
+            pos = tokens.len();
+            tokens.push(logits.argmax(D::Minus1)?.to_scalar::<u32>()?);
+        }
+        assert_eq!(output.tokens, tokens[prompt.len()..]);
+    }
+    Ok(())
+}
+
+#[test]
+fn request_too_long() -> Result<()> {
+    let device = &Device::Cpu;
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, device);
+    let model = mistral::Model::new(&mistral_config(8), vb)?;
+    let cache = CacheConfig {
+        block_size: 4,
+        num_blocks: 2,
+    };
+    let mut engine = TextGeneration::new(model, &cache, SchedulerConfig::default())?;
+    let params = SamplingParams {
+        max_new_tokens: 4,
+        ..Default::default()
+    };
+    assert!(engine.add_request(vec![1, 2, 3, 4, 5], &params).is_err());
+    engine.add_request(vec![1, 2, 3, 4], &params)?;
+    let outputs = engine.run()?;
+    assert_eq!(outputs[0].tokens.len(), 4);
+    Ok(())
+}